@@ -3,6 +3,7 @@
 use bfffs::{
     common::{
         Error,
+        control,
         database::*,
         device_manager::DevManager,
     },
@@ -12,7 +13,8 @@ use futures::{Future, Stream, future};
 use std::{
     ffi::OsString,
     sync::Arc,
-    thread
+    thread,
+    time::Duration
 };
 use tokio_signal::unix::{Signal, SIGUSR1};
 
@@ -24,8 +26,28 @@ fn main() {
     env_logger::init();
     let app = clap::App::new("bfffsd")
         .version(crate_version!())
-        .arg(clap::Arg::with_name("option")
-             .help("Mount options")
+        .arg(clap::Arg::with_name("force")
+             .help("Import the pool even if it appears to be in use")
+             .long("force")
+             .short("f")
+        ).arg(clap::Arg::with_name("rewind")
+             .help("Roll the pool back to its newest recent transaction group \
+                    that passes a consistency check.  Newer changes are lost")
+             .long("rewind")
+             .conflicts_with("rewind_to_checkpoint")
+        ).arg(clap::Arg::with_name("rewind_to_checkpoint")
+             .help("Roll the pool back to its checkpoint.  Changes made since \
+                    the checkpoint are lost")
+             .long("rewind-to-checkpoint")
+        ).arg(clap::Arg::with_name("search_path")
+             .help("Directories to search for devices, besides /dev.  Comma \
+                    delimited")
+             .long("search-path")
+             .takes_value(true)
+             .multiple(true)
+             .require_delimiter(true)
+        ).arg(clap::Arg::with_name("option")
+             .help("Mount options.  \"ro\" imports the pool read-only")
              .short("o")
              .takes_value(true)
              .multiple(true)
@@ -36,15 +58,17 @@ fn main() {
          ).arg(clap::Arg::with_name("mountpoint")
              .required(true)
          ).arg(clap::Arg::with_name("devices")
-             .required(true)
+             .help("The pool's devices.  If omitted, search for them")
              .multiple(true)
          );
     let matches = app.get_matches();
 
     // Unconditionally disable the kernel's buffer cache since BFFFS has its own
     let mut opts = vec![OsString::from("-o"), OsString::from("direct_io")];
+    let mut readonly = false;
     if let Some(it) = matches.values_of("option") {
         for o in it {
+            readonly |= o == "ro";
             opts.push(OsString::from("-o"));
             opts.push(OsString::from(o));
         }
@@ -52,30 +76,55 @@ fn main() {
 
     let poolname = matches.value_of("name").unwrap().to_string();
     let mountpoint = matches.value_of("mountpoint").unwrap().to_string();
-    let devices = matches.values_of("devices").unwrap()
-        .map(str::to_string)
-        .collect::<Vec<_>>();
 
     let dev_manager = DevManager::default();
-    for dev in devices.iter() {
-        dev_manager.taste(dev);
+    dev_manager.set_force(matches.is_present("force"));
+    dev_manager.set_readonly(readonly);
+    dev_manager.set_rewind(matches.is_present("rewind"));
+    dev_manager.set_rewind_to_checkpoint(
+        matches.is_present("rewind_to_checkpoint"));
+    for dir in matches.values_of("search_path").into_iter().flatten() {
+        dev_manager.add_search_path(dir);
+    }
+    match matches.values_of("devices") {
+        Some(devices) => {
+            for dev in devices {
+                dev_manager.taste(dev);
+            }
+        },
+        None => dev_manager.scan()
     }
     let uuid = dev_manager.importable_pools().iter()
         .filter(|(name, _uuid)| {
             **name == poolname
-        }).nth(0).unwrap().1;
+        }).nth(0)
+        .unwrap_or_else(|| {
+            eprintln!("Error: pool {} not found", poolname);
+            std::process::exit(1);
+        }).1;
 
     let mut rt = tokio_io_pool::Runtime::new();
     let handle = rt.handle().clone();
     let handle2 = rt.handle().clone();
     let db = Arc::new(rt.block_on(future::lazy(move || {
         dev_manager.import_by_uuid(uuid, handle)
-    })).unwrap());
+    })).unwrap_or_else(|e| {
+        eprintln!("Error: cannot import pool: {:?}", e);
+        std::process::exit(1);
+    }));
+    let sockpath = control::socket_path(control::SOCKET_DIR, &poolname);
+    let server = control::Server::new(&sockpath, Arc::downgrade(&db),
+                                      rt.handle().clone())
+    .unwrap_or_else(|e| {
+        eprintln!("Error: cannot listen on {}: {}", sockpath.display(), e);
+        std::process::exit(1);
+    });
     // For now, hardcode tree_id to 0
     let tree_id = TreeID::Fs(0);
-    let db2 = db.clone();
+    let db2 = Arc::downgrade(&db);
+    let db3 = db.clone();
     let thr_handle = thread::spawn(move || {
-        let fs = FuseFs::new(db, handle2, tree_id);
+        let fs = FuseFs::new(db3, handle2, tree_id);
         // We need a separate vec of references :(
         // https://github.com/zargony/rust-fuse/issues/117
         let opt_refs = opts.iter().map(|o| o.as_ref()).collect::<Vec<_>>();
@@ -88,9 +137,32 @@ fn main() {
     rt.spawn(
         sigusr1.map_err(Error::unhandled_canceled)
         .for_each(move |_| {
-            db2.clean()
+            match db2.upgrade() {
+                Some(db) => future::Either::A(db.clean()),
+                // Already unmounted
+                None => future::Either::B(future::ok(()))
+            }
         }).map_err(Error::unhandled)
     ).unwrap();
 
-    thr_handle.join().unwrap()
+    thr_handle.join().unwrap();
+
+    // Unmounted.  Cleanly export the pool, once any outstanding requests have
+    // released it.
+    drop(server);
+    let mut arc = db;
+    let mut db = loop {
+        match Arc::try_unwrap(arc) {
+            Ok(db) => break db,
+            Err(a) => {
+                arc = a;
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    };
+    rt.block_on(db.export()).unwrap_or_else(|e| {
+        eprintln!("Error: cannot export pool: {:?}", e);
+        std::process::exit(1);
+    });
+    rt.shutdown_on_idle();
 }
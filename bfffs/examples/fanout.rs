@@ -145,7 +145,7 @@ impl DML for FakeDDML {
 
     /// Write a record to disk and cache.  Return its Direct Record Pointer.
     fn put<T: Cacheable>(&self, cacheable: T, compression: Compression,
-                             _txg: TxgT)
+                             _txg: TxgT, _priority: PriorityClass)
         -> Box<dyn Future<Item=Self::Addr, Error=Error> + Send>
     {
         let db = cacheable.make_ref().serialize();
@@ -261,7 +261,7 @@ impl DML for FakeIDML {
 
     /// Write a record to disk and cache.  Return its Direct Record Pointer.
     fn put<T: Cacheable>(&self, cacheable: T, compression: Compression,
-                             txg: TxgT)
+                             txg: TxgT, _priority: PriorityClass)
         -> Box<dyn Future<Item=Self::Addr, Error=Error> + Send>
     {
         let db = cacheable.make_ref().serialize();
@@ -1,4 +1,5 @@
 use bfffs::common::{
+    Error,
     database::TreeID,
     device_manager::DevManager,
     property::Property
@@ -6,7 +7,6 @@ use bfffs::common::{
 use clap::crate_version;
 use futures::future;
 use std::{
-    path::Path,
     process::exit,
     sync::Arc
 };
@@ -26,12 +26,10 @@ use super::*;
 // * Spacemaps match actual usage
 pub fn main(args: &clap::ArgMatches) {
     let poolname = args.value_of("name").unwrap().to_owned();
-    let disks = args.values_of("disks").unwrap();
     let dev_manager = DevManager::default();
-    for dev in disks.map(str::to_string)
-    {
-        dev_manager.taste(dev);
-    }
+    dev_manager.set_force(args.is_present("force"));
+    dev_manager.set_readonly(true);
+    discover(&dev_manager, args);
 
     let mut rt = tokio_io_pool::Runtime::new();
     let handle = rt.handle().clone();
@@ -54,11 +52,9 @@ mod debug {
 use super::*;
 use tokio::runtime::current_thread::Runtime;
 
-fn dump_fsm<P: AsRef<Path>, S: AsRef<str>>(poolname: S, disks: &[P]) {
+fn dump_fsm<S: AsRef<str>>(poolname: S, args: &clap::ArgMatches) {
     let dev_manager = DevManager::default();
-    for disk in disks {
-        dev_manager.taste(disk);
-    }
+    discover(&dev_manager, args);
     let uuid = dev_manager.importable_pools().iter()
         .filter(|(name, _uuid)| {
             *name == poolname.as_ref()
@@ -72,12 +68,12 @@ fn dump_fsm<P: AsRef<Path>, S: AsRef<str>>(poolname: S, disks: &[P]) {
     }
 }
 
-fn dump_tree<P: AsRef<Path>>(poolname: String, disks: &[P]) {
+fn dump_tree(poolname: String, args: &clap::ArgMatches) {
     let poolname2 = poolname.to_owned();
     let dev_manager = DevManager::default();
-    for disk in disks {
-        dev_manager.taste(disk);
-    }
+    dev_manager.set_force(args.is_present("force"));
+    dev_manager.set_readonly(true);
+    discover(&dev_manager, args);
     let mut rt = tokio_io_pool::Runtime::new();
     let handle = rt.handle().clone();
     let db = Arc::new(rt.block_on(future::lazy(move || {
@@ -96,12 +92,11 @@ pub fn main(args: &clap::ArgMatches) {
     match args.subcommand() {
         ("dump", Some(args)) => {
             let poolname = args.value_of("name").unwrap();
-            let disks = args.values_of("disks").unwrap().collect::<Vec<&str>>();
             if args.is_present("fsm") {
-                dump_fsm(&poolname, &disks[..]);
+                dump_fsm(&poolname, args);
             }
             if args.is_present("tree") {
-                dump_tree(poolname.to_string(), &disks[..]);
+                dump_tree(poolname.to_string(), args);
             }
         },
         _ => {
@@ -113,22 +108,117 @@ pub fn main(args: &clap::ArgMatches) {
 
 }
 
+mod labelclear {
+use super::*;
+
+pub fn main(args: &clap::ArgMatches) {
+    let device = args.value_of("device").unwrap();
+    let force = args.is_present("force");
+    DevManager::labelclear(device, force).unwrap_or_else(|e| {
+        if e == Error::EBUSY {
+            eprintln!("Error: {} belongs to an active pool.  Use -f to clear \
+                       it anyway", device);
+        } else {
+            eprintln!("Error: cannot clear labels: {:?}", e);
+        }
+        exit(1);
+    });
+}
+
+}
+
 mod pool {
-use bfffs::common::BYTES_PER_LBA;
+use bfffs::common::{BYTES_PER_LBA, LbaT, Uuid};
 use bfffs::common::cache::Cache;
+use bfffs::common::control;
 use bfffs::common::database::*;
 use bfffs::common::ddml::DDML;
 use bfffs::common::idml::IDML;
-use bfffs::common::pool::{ClusterProxy, Pool};
-use futures::Future;
+use bfffs::common::iostat::{Histogram, VdevStats};
+use bfffs::common::pool::{ClusterProxy, Pool, StorageClass};
+use bfffs::common::throttle::IoLimits;
+use bfffs::common::vdev_block::SchedulerPolicy;
+use futures::{Future, Stream, stream};
 use std::{
     convert::TryFrom,
     num::NonZeroU64,
+    path::PathBuf,
     str::FromStr,
-    sync::Mutex
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant}
 };
 use super::*;
 
+/// Connect to the `bfffsd` that has the named pool imported, if any
+fn connect(poolname: &str) -> Option<control::Client> {
+    let path = control::socket_path(control::SOCKET_DIR, poolname);
+    control::Client::connect(path).ok()
+}
+
+/// Import a pool for the duration of a single command.
+///
+/// Only for pools that aren't already imported by `bfffsd`.
+fn import(poolname: String, dev_manager: DevManager,
+          rt: &mut tokio_io_pool::Runtime) -> Arc<Database>
+{
+    let handle = rt.handle().clone();
+    Arc::new(rt.block_on(future::lazy(move || {
+        dev_manager.import_by_name(poolname, handle)
+        .unwrap_or_else(|_e| {
+            eprintln!("Error: pool not found");
+            exit(1);
+        })
+    })).unwrap_or_else(|e| {
+        eprintln!("Error: cannot import pool: {:?}", e);
+        exit(1);
+    }))
+}
+
+/// Export a pool that was imported by `import`, so it's left the way it was
+/// found.
+fn export_offline(rt: &mut tokio_io_pool::Runtime, mut arc: Arc<Database>) {
+    // The runtime's workers may not have dropped their references yet
+    let mut db = loop {
+        match Arc::try_unwrap(arc) {
+            Ok(db) => break db,
+            Err(a) => {
+                arc = a;
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    };
+    rt.block_on(db.export()).unwrap_or_else(|e| {
+        eprintln!("Error: cannot export pool: {:?}", e);
+        exit(1);
+    });
+}
+
+/// Add new clusters to an existing pool
+fn add(args: &clap::ArgMatches) {
+    let rt = Runtime::new().unwrap();
+    let name = args.value_of("name").unwrap().to_owned();
+    let zone_size = args.value_of("zone_size")
+        .map(|s| {
+            let lbas = u64::from_str(s)
+             .expect("zone_size must be a decimal integer")
+             * 1024 * 1024 / (BYTES_PER_LBA as u64);
+            NonZeroU64::new(lbas).expect("zone_size may not be zero")
+        });
+    let mut builder = Builder::new(name.clone(), Vec::new(),
+                                   SchedulerPolicy::default(), zone_size, rt);
+    builder.create_vdevs(args.values_of("vdev").unwrap());
+    match connect(&name) {
+        Some(client) => builder.add_to_daemon(client),
+        None => {
+            let dev_manager = DevManager::default();
+            dev_manager.set_force(args.is_present("force"));
+            discover(&dev_manager, args);
+            builder.add(dev_manager);
+        }
+    }
+}
+
 fn create(args: &clap::ArgMatches) {
     let rt = Runtime::new().unwrap();
     let name = args.value_of("name").unwrap().to_owned();
@@ -144,65 +234,36 @@ fn create(args: &clap::ArgMatches) {
     } else {
         Vec::new()
     };
+    let scheduler = args.value_of("scheduler")
+        .map(|s| {
+            SchedulerPolicy::try_from(s).unwrap_or_else(|_e| {
+                eprintln!("Invalid scheduler {}", s);
+                std::process::exit(2);
+            })
+        }).unwrap_or_default();
 
-    let mut builder = Builder::new(name, propstrings, zone_size, rt);
-    let mut vdev_tokens = args.values_of("vdev").unwrap();
-    let mut cluster_type = None;
-    let mut devs = vec![];
-    loop {
-        let next = vdev_tokens.next();
-        match next {
-            None => {
-                if !devs.is_empty() {
-                    match cluster_type {
-                        Some("mirror") =>
-                            builder.create_mirror(&devs[..]),
-                        Some("raid") => builder.create_raid(&devs[..]),
-                        None => assert!(devs.is_empty()),
-                        _ => unreachable!()
-                    }
-                }
-                break;
-            },
-            Some("mirror") => {
-                if !devs.is_empty() {
-                    builder.create_cluster(cluster_type.as_ref().unwrap(),
-                                           &devs[..]);
-                }
-                devs.clear();
-                cluster_type = Some("mirror")
-            },
-            Some("raid") => {
-                if !devs.is_empty() {
-                    builder.create_cluster(cluster_type.as_ref().unwrap(),
-                                           &devs[..]);
-                }
-                devs.clear();
-                cluster_type = Some("raid")
-            },
-            Some(ref dev) => {
-                if cluster_type == None {
-                    builder.create_single(dev);
-                } else {
-                    devs.push(dev);
-                }
-            }
-        }
-    }
+    let mut builder = Builder::new(name, propstrings, scheduler, zone_size,
+                                   rt);
+    builder.create_vdevs(args.values_of("vdev").unwrap());
     builder.format()
 }
 
 struct Builder {
-    clusters: Vec<ClusterProxy>,
+    /// Clusters to create
+    clusters: Vec<control::ClusterSpec>,
     name: String,
     properties: Vec<Property>,
     rt: Runtime,
+    scheduler: SchedulerPolicy,
+    /// Storage class for the next clusters to be created
+    storage_class: StorageClass,
     zone_size: Option<NonZeroU64>
 }
 
 impl Builder {
     pub fn new(name: String, propstrings: Vec<&str>,
-               zone_size: Option<NonZeroU64>, rt: Runtime)
+               scheduler: SchedulerPolicy, zone_size: Option<NonZeroU64>,
+               rt: Runtime)
         -> Self
     {
         let clusters = Vec::new();
@@ -214,7 +275,55 @@ impl Builder {
                 })
             })
             .collect::<Vec<_>>();
-        Builder{clusters, name, properties, rt, zone_size}
+        let storage_class = StorageClass::default();
+        Builder{clusters, name, properties, rt, scheduler, storage_class,
+                zone_size}
+    }
+
+    /// Add the `Builder`'s clusters to an existing pool, then export it
+    pub fn add(&mut self, dev_manager: DevManager) {
+        let name = self.name.clone();
+        // Import the pool before formatting anything, so a typo in its name
+        // won't destroy the new disks' contents
+        let db = Arc::new(self.rt.block_on(future::lazy(move || {
+            let task_executor = TaskExecutor::current();
+            dev_manager.import_by_name(name, task_executor)
+            .unwrap_or_else(|_e| {
+                eprintln!("Error: pool not found");
+                exit(1);
+            })
+        })).unwrap_or_else(|e| {
+            eprintln!("Error: cannot import pool: {:?}", e);
+            exit(1);
+        }));
+        let r = self.create_clusters()
+        .and_then(|clusters| {
+            let db2 = db.clone();
+            self.rt.block_on(future::lazy(move || {
+                stream::iter_ok(clusters)
+                .for_each(move |cluster| db2.add_cluster(cluster))
+            }))
+        });
+        let mut db = Arc::try_unwrap(db).ok()
+            .expect("Database still referenced after adding clusters");
+        self.rt.block_on(db.export()).unwrap_or_else(|e| {
+            eprintln!("Error: cannot export pool: {:?}", e);
+            exit(1);
+        });
+        r.unwrap_or_else(|e| {
+            eprintln!("Error: cannot add to pool: {:?}", e);
+            exit(1);
+        });
+    }
+
+    /// Add the `Builder`'s clusters to a pool imported by `bfffsd`
+    pub fn add_to_daemon(&mut self, mut client: control::Client) {
+        for spec in self.clusters.drain(..) {
+            client.add_cluster(spec).unwrap_or_else(|e| {
+                eprintln!("Error: cannot add to pool: {:?}", e);
+                exit(1);
+            });
+        }
     }
 
     pub fn create_cluster(&mut self, vtype: &str, devs: &[&str]) {
@@ -244,22 +353,105 @@ impl Builder {
         self.do_create_cluster(1, 0, &[&dev])
     }
 
+    /// Create clusters from a list of vdev tokens, like
+    /// `raid 3 1 da0 da1 da2 mirror da3 da4`
+    pub fn create_vdevs(&mut self, mut vdev_tokens: clap::Values) {
+        let mut cluster_type = None;
+        let mut devs = vec![];
+        loop {
+            let next = vdev_tokens.next();
+            match next {
+                None => {
+                    if !devs.is_empty() {
+                        match cluster_type {
+                            Some("mirror") =>
+                                self.create_mirror(&devs[..]),
+                            Some("raid") => self.create_raid(&devs[..]),
+                            None => assert!(devs.is_empty()),
+                            _ => unreachable!()
+                        }
+                    }
+                    break;
+                },
+                Some("mirror") => {
+                    if !devs.is_empty() {
+                        self.create_cluster(cluster_type.as_ref().unwrap(),
+                                            &devs[..]);
+                    }
+                    devs.clear();
+                    cluster_type = Some("mirror")
+                },
+                Some("raid") => {
+                    if !devs.is_empty() {
+                        self.create_cluster(cluster_type.as_ref().unwrap(),
+                                            &devs[..]);
+                    }
+                    devs.clear();
+                    cluster_type = Some("raid")
+                },
+                Some(class @ "fast") | Some(class @ "slow") => {
+                    // Applies to all following clusters
+                    if !devs.is_empty() {
+                        self.create_cluster(cluster_type.as_ref().unwrap(),
+                                            &devs[..]);
+                    }
+                    devs.clear();
+                    cluster_type = None;
+                    self.storage_class = if class == "fast" {
+                        StorageClass::Fast
+                    } else {
+                        StorageClass::Slow
+                    };
+                },
+                Some(ref dev) => {
+                    if cluster_type == None {
+                        self.create_single(dev);
+                    } else {
+                        devs.push(dev);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Actually format the disks of each cluster
+    fn create_clusters(&mut self) -> Result<Vec<ClusterProxy>, Error> {
+        let specs = self.clusters.drain(..).collect::<Vec<_>>();
+        specs.into_iter()
+        .map(|spec| {
+            let zone_size = spec.lbas_per_zone.and_then(NonZeroU64::new);
+            let mut c = self.rt.block_on(future::lazy(|| {
+                Pool::create_cluster(None, spec.disks_per_stripe, zone_size,
+                                     spec.redundancy, &spec.paths[..])
+            }))?;
+            c.set_storage_class(spec.storage_class);
+            Ok(c)
+        }).collect()
+    }
+
     fn do_create_cluster(&mut self, k: i16, f: i16, devs: &[&str])
     {
-        let zone_size = self.zone_size;
-        let c = self.rt.block_on(future::lazy(move || {
-            Pool::create_cluster(None, k, zone_size, f, devs)
-        })).unwrap();
-        self.clusters.push(c);
+        self.clusters.push(control::ClusterSpec {
+            disks_per_stripe: k,
+            redundancy: f,
+            lbas_per_zone: self.zone_size.map(NonZeroU64::get),
+            storage_class: self.storage_class,
+            paths: devs.iter().map(PathBuf::from).collect()
+        });
     }
 
-    /// Actually format the disks
+    /// Create a new pool from the `Builder`'s clusters
     pub fn format(&mut self) {
         let name = self.name.clone();
-        let clusters = self.clusters.drain(..).collect();
+        let clusters = self.create_clusters().unwrap_or_else(|e| {
+            eprintln!("Error: cannot create cluster: {:?}", e);
+            exit(1);
+        });
+        let scheduler = self.scheduler;
         let db = self.rt.block_on(future::lazy(|| {
             Pool::create(name, clusters)
             .map(|pool| {
+                pool.set_scheduler(scheduler);
                 let cache = Arc::new(Mutex::new(Cache::with_capacity(1000)));
                 let ddml = Arc::new(DDML::new(pool, cache.clone()));
                 let idml = Arc::new(IDML::create(ddml, cache));
@@ -275,9 +467,349 @@ impl Builder {
     }
 }
 
+/// Checkpoint a pool, or discard its checkpoint
+fn checkpoint(args: &clap::ArgMatches) {
+    let poolname = args.value_of("name").unwrap().to_owned();
+    let discard = args.is_present("discard");
+    if let Some(mut client) = connect(&poolname) {
+        let r = if discard {
+            client.discard_checkpoint()
+        } else {
+            client.checkpoint()
+        };
+        if let Err(e) = r {
+            eprintln!("Error: cannot update checkpoint: {:?}", e);
+            exit(1);
+        }
+        return;
+    }
+
+    let dev_manager = DevManager::default();
+    dev_manager.set_force(args.is_present("force"));
+    discover(&dev_manager, args);
+
+    let mut rt = tokio_io_pool::Runtime::new();
+    let db = import(poolname, dev_manager, &mut rt);
+    let db2 = db.clone();
+    rt.block_on(future::lazy(move || {
+        if discard {
+            future::Either::A(db2.discard_checkpoint())
+        } else {
+            future::Either::B(db2.checkpoint())
+        }
+    })).unwrap_or_else(|e| {
+        eprintln!("Error: cannot update checkpoint: {:?}", e);
+        exit(1);
+    });
+    export_offline(&mut rt, db);
+}
+
+/// Mark a pool as destroyed, so it can never be imported again
+fn destroy(args: &clap::ArgMatches) {
+    let poolname = args.value_of("name").unwrap().to_owned();
+    let dev_manager = DevManager::default();
+    dev_manager.set_force(args.is_present("force"));
+    discover(&dev_manager, args);
+
+    let mut rt = tokio_io_pool::Runtime::new();
+    let handle = rt.handle().clone();
+    let mut db = rt.block_on(future::lazy(move || {
+        dev_manager.import_by_name(poolname, handle)
+        .unwrap_or_else(|_e| {
+            eprintln!("Error: pool not found");
+            exit(1);
+        })
+    })).unwrap_or_else(|e| {
+        eprintln!("Error: cannot import pool: {:?}", e);
+        exit(1);
+    });
+    rt.block_on(db.destroy()).unwrap_or_else(|e| {
+        eprintln!("Error: cannot destroy pool: {:?}", e);
+        exit(1);
+    });
+    rt.shutdown_on_idle();
+}
+
+/// Cleanly export a pool, so it may be imported elsewhere
+fn export(args: &clap::ArgMatches) {
+    let poolname = args.value_of("name").unwrap().to_owned();
+    let dev_manager = DevManager::default();
+    dev_manager.set_force(args.is_present("force"));
+    discover(&dev_manager, args);
+
+    let mut rt = tokio_io_pool::Runtime::new();
+    let handle = rt.handle().clone();
+    let mut db = rt.block_on(future::lazy(move || {
+        dev_manager.import_by_name(poolname, handle)
+        .unwrap_or_else(|_e| {
+            eprintln!("Error: pool not found");
+            exit(1);
+        })
+    })).unwrap_or_else(|e| {
+        eprintln!("Error: cannot import pool: {:?}", e);
+        exit(1);
+    });
+    rt.block_on(db.export()).unwrap_or_else(|e| {
+        eprintln!("Error: cannot export pool: {:?}", e);
+        exit(1);
+    });
+    rt.shutdown_on_idle();
+}
+
+/// Format a rate with a unit suffix, like `zpool iostat` does
+fn human(mut x: f64) -> String {
+    const SUFFIXES: [&str; 5] = ["", "K", "M", "G", "T"];
+    let mut i = 0;
+    while x >= 1000.0 && i < SUFFIXES.len() - 1 {
+        x /= 1024.0;
+        i += 1;
+    }
+    if i == 0 {
+        format!("{:.0}", x)
+    } else {
+        format!("{:.1}{}", x, SUFFIXES[i])
+    }
+}
+
+fn human_latency(h: &Histogram) -> String {
+    match h.mean() {
+        None => "-".to_string(),
+        Some(d) if d < Duration::from_millis(1) =>
+            format!("{}us", d.as_micros()),
+        Some(d) if d < Duration::from_secs(1) =>
+            format!("{}ms", d.as_millis()),
+        Some(d) => format!("{}s", d.as_secs())
+    }
+}
+
+/// Print one line for `vs`, and if `verbose`, one more for each of its
+/// descendants.  `secs` is the length of the sampling period.
+fn print_iostat(name: &str, vs: &VdevStats, secs: f64, depth: usize,
+                verbose: bool)
+{
+    let s = &vs.stats;
+    let state = if vs.faulted {
+        "FAULTED"
+    } else if vs.degraded() {
+        "DEGRADED"
+    } else {
+        ""
+    };
+    println!("{:<40} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>5} {:>6} {}",
+        format!("{:width$}{}", "", name, width = 2 * depth),
+        human(s.read_ops as f64 / secs),
+        human(s.write_ops as f64 / secs),
+        human(s.read_bytes as f64 / secs),
+        human(s.write_bytes as f64 / secs),
+        human_latency(&s.read_latency),
+        human_latency(&s.write_latency),
+        s.queue_depth,
+        s.errors,
+        state);
+    if verbose {
+        for child in vs.children.iter() {
+            print_iostat(&child.uuid.to_string(), child, secs, depth + 1,
+                         verbose);
+        }
+    }
+}
+
+/// Display I/O statistics, like `zpool iostat`
+fn iostat(args: &clap::ArgMatches) {
+    let poolname = args.value_of("name").unwrap().to_owned();
+    let verbose = args.is_present("verbose");
+    let interval = args.value_of("interval").map(|s| {
+        let secs = u64::from_str(s)
+            .expect("interval must be a decimal integer");
+        Duration::from_secs(secs)
+    });
+    let count = args.value_of("count").map(|s| {
+        u64::from_str(s).expect("count must be a decimal integer")
+    });
+    let mut get_stats: Box<dyn FnMut() -> VdevStats> = match connect(&poolname)
+    {
+        Some(mut client) => Box::new(move || {
+            client.iostats().unwrap_or_else(|e| {
+                eprintln!("Error: cannot get statistics: {:?}", e);
+                exit(1);
+            })
+        }),
+        None => {
+            let dev_manager = DevManager::default();
+            dev_manager.set_force(args.is_present("force"));
+            // Read-only, so the import doesn't change the pool's labels
+            dev_manager.set_readonly(true);
+            discover(&dev_manager, args);
+
+            let mut rt = tokio_io_pool::Runtime::new();
+            let db = import(poolname.clone(), dev_manager, &mut rt);
+            Box::new(move || {
+                let db2 = db.clone();
+                rt.block_on(future::lazy(move || db2.iostats())).unwrap()
+            })
+        }
+    };
+
+    // Like zpool iostat, the first report covers everything since import
+    let mut prev: Option<VdevStats> = None;
+    let mut last = Instant::now();
+    let mut reports = 0;
+    loop {
+        let vs = get_stats();
+        let now = Instant::now();
+        let elapsed = now.duration_since(last).as_micros() as f64 / 1e6;
+        let secs = elapsed.max(1e-3);
+        let delta = match prev {
+            Some(ref p) => vs.since(p),
+            None => vs.clone()
+        };
+        println!("{:<40} {:>13} {:>13} {:>13} {:>5} {:>6}",
+                 "", "operations", "bandwidth", "latency", "queue", "");
+        println!("{:<40} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>5} {:>6}",
+                 "pool", "read", "write", "read", "write", "read", "write",
+                 "depth", "errors");
+        print_iostat(&poolname, &delta, secs, 0, verbose);
+        println!();
+        reports += 1;
+        match (interval, count) {
+            (None, _) => break,
+            (_, Some(c)) if reports >= c => break,
+            (Some(i), _) => {
+                prev = Some(vs);
+                last = now;
+                thread::sleep(i);
+            }
+        }
+    }
+}
+
+/// Evacuate a cluster and remove it from its pool
+fn remove(args: &clap::ArgMatches) {
+    let poolname = args.value_of("name").unwrap().to_owned();
+    let cluster = args.value_of("cluster").unwrap();
+    let uuid = Uuid::parse_str(cluster).unwrap_or_else(|_e| {
+        eprintln!("Error: invalid cluster UUID {}", cluster);
+        exit(2);
+    });
+    let print_progress = |progress: RemovalProgress| {
+        println!("Evacuated {} of {} zones", progress.zones_done,
+                 progress.zones_total);
+    };
+    let check_removing = |removing: Option<(Uuid, LbaT)>| {
+        match removing {
+            Some((u, _)) if u == uuid => {
+                println!("Resuming the removal of cluster {}", uuid);
+            },
+            Some((u, _)) => {
+                eprintln!("Error: cluster {} is already being removed", u);
+                exit(1);
+            },
+            None => ()
+        }
+    };
+
+    if let Some(mut client) = connect(&poolname) {
+        let removing = client.removing().unwrap_or_else(|e| {
+            eprintln!("Error: cannot remove cluster: {:?}", e);
+            exit(1);
+        });
+        check_removing(removing);
+        client.remove_cluster(uuid, print_progress).unwrap_or_else(|e| {
+            eprintln!("Error: cannot remove cluster: {:?}", e);
+            exit(1);
+        });
+        return;
+    }
+
+    let dev_manager = DevManager::default();
+    dev_manager.set_force(args.is_present("force"));
+    discover(&dev_manager, args);
+
+    let mut rt = tokio_io_pool::Runtime::new();
+    let db = import(poolname, dev_manager, &mut rt);
+    check_removing(db.removing());
+    let db2 = db.clone();
+    rt.block_on(future::lazy(move || {
+        db2.remove_cluster(uuid)
+        .for_each(move |progress| {
+            print_progress(progress);
+            Ok(())
+        })
+    })).unwrap_or_else(|e| {
+        eprintln!("Error: cannot remove cluster: {:?}", e);
+        exit(1);
+    });
+    export_offline(&mut rt, db);
+}
+
+/// Set pool properties, such as I/O rate limits
+fn set(args: &clap::ArgMatches) {
+    let poolname = args.value_of("name").unwrap().to_owned();
+    let props = args.values_of("property").unwrap()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    let apply = |mut limits: IoLimits| {
+        for prop in props.iter() {
+            limits.set(prop).unwrap_or_else(|_e| {
+                eprintln!("Error: invalid property {}", prop);
+                exit(2);
+            });
+        }
+        limits
+    };
+
+    if let Some(mut client) = connect(&poolname) {
+        let limits = client.limits()
+            .map(&apply)
+            .and_then(|limits| client.set_limits(limits));
+        if let Err(e) = limits {
+            eprintln!("Error: cannot set properties: {:?}", e);
+            exit(1);
+        }
+        return;
+    }
+
+    let dev_manager = DevManager::default();
+    dev_manager.set_force(args.is_present("force"));
+    discover(&dev_manager, args);
+
+    let mut rt = tokio_io_pool::Runtime::new();
+    let db = import(poolname, dev_manager, &mut rt);
+    let limits = apply(db.limits());
+    let db2 = db.clone();
+    rt.block_on(future::lazy(move || {
+        db2.set_limits(limits)
+    })).unwrap();
+    export_offline(&mut rt, db);
+}
+
+/// Enable every on-disk feature supported by this version of BFFFS
+fn upgrade(args: &clap::ArgMatches) {
+    let poolname = args.value_of("name").unwrap().to_owned();
+    let dev_manager = DevManager::default();
+    dev_manager.set_force(args.is_present("force"));
+    discover(&dev_manager, args);
+
+    let mut rt = tokio_io_pool::Runtime::new();
+    let db = import(poolname, dev_manager, &mut rt);
+    let db2 = db.clone();
+    rt.block_on(future::lazy(move || {
+        db2.upgrade()
+    })).unwrap();
+    export_offline(&mut rt, db);
+}
+
 pub fn main(args: &clap::ArgMatches) {
     match args.subcommand() {
+        ("add", Some(add_args)) => add(add_args),
+        ("checkpoint", Some(checkpoint_args)) => checkpoint(checkpoint_args),
         ("create", Some(create_args)) => create(create_args),
+        ("destroy", Some(destroy_args)) => destroy(destroy_args),
+        ("export", Some(export_args)) => export(export_args),
+        ("iostat", Some(iostat_args)) => iostat(iostat_args),
+        ("remove", Some(remove_args)) => remove(remove_args),
+        ("set", Some(set_args)) => set(set_args),
+        ("upgrade", Some(upgrade_args)) => upgrade(upgrade_args),
         _ => {
             println!("Error: subcommand required\n{}", args.usage());
             std::process::exit(2);
@@ -287,17 +819,46 @@ pub fn main(args: &clap::ArgMatches) {
 
 }
 
+/// Find the pool's disks, either from the command line or by searching for
+/// them.
+fn discover(dev_manager: &DevManager, args: &clap::ArgMatches) {
+    for dir in args.values_of("search_path").into_iter().flatten() {
+        dev_manager.add_search_path(dir);
+    }
+    match args.values_of("disks") {
+        Some(disks) => {
+            for disk in disks {
+                dev_manager.taste(disk);
+            }
+        },
+        None => dev_manager.scan()
+    }
+}
+
 fn main() {
     let app = clap::App::new("bfffs")
         .version(crate_version!())
+        .arg(clap::Arg::with_name("force")
+             .help("Use pools and disks even if they appear to be in use")
+             .long("force")
+             .global(true)
+        ).arg(clap::Arg::with_name("search_path")
+             .help("Directories to search for disks, besides /dev.  Comma \
+                    delimited")
+             .long("search-path")
+             .takes_value(true)
+             .multiple(true)
+             .require_delimiter(true)
+             .global(true)
+        )
         .subcommand(clap::SubCommand::with_name("check")
             .about("Consistency check")
             .arg(clap::Arg::with_name("name")
                  .help("Pool name")
                  .required(true)
             ).arg(clap::Arg::with_name("disks")
+                  .help("The pool's disks.  If omitted, search for them")
                   .multiple(true)
-                  .required(true)
             )
         ).subcommand(clap::SubCommand::with_name("debug")
             .about("Debugging tools")
@@ -315,18 +876,60 @@ fn main() {
                      .help("Pool name")
                      .required(true)
                 ).arg(clap::Arg::with_name("disks")
+                      .help("The pool's disks.  If omitted, search for them")
                       .multiple(true)
-                      .required(true)
                 )
             )
+        ).subcommand(clap::SubCommand::with_name("labelclear")
+            .about("Erase BFFFS labels from a device")
+            .arg(clap::Arg::with_name("device")
+                 .required(true)
+            )
         ).subcommand(clap::SubCommand::with_name("pool")
             .about("create, destroy, and modify storage pools")
-            .subcommand(clap::SubCommand::with_name("create")
+            .subcommand(clap::SubCommand::with_name("add")
+                .about("add clusters to an existing storage pool")
+                .arg(clap::Arg::with_name("zone_size")
+                     .help("Simulated Zone size in MB")
+                     .long("zone_size")
+                     .takes_value(true)
+                ).arg(clap::Arg::with_name("disks")
+                     .help("The pool's existing disks, comma delimited.  If \
+                            omitted, search for them")
+                     .short("d")
+                     .takes_value(true)
+                     .multiple(true)
+                     .require_delimiter(true)
+                ).arg(clap::Arg::with_name("name")
+                     .help("Pool name")
+                     .required(true)
+                ).arg(clap::Arg::with_name("vdev")
+                      .help("Clusters, each optionally after fast or slow")
+                      .multiple(true)
+                      .required(true)
+                )
+            ).subcommand(clap::SubCommand::with_name("checkpoint")
+                .about("preserve a storage pool's current state")
+                .arg(clap::Arg::with_name("discard")
+                     .help("Discard the existing checkpoint instead")
+                     .long("discard")
+                ).arg(clap::Arg::with_name("name")
+                     .help("Pool name")
+                     .required(true)
+                ).arg(clap::Arg::with_name("disks")
+                      .help("The pool's disks.  If omitted, search for them")
+                      .multiple(true)
+                )
+            ).subcommand(clap::SubCommand::with_name("create")
                 .about("create a new storage pool")
                 .arg(clap::Arg::with_name("zone_size")
                      .help("Simulated Zone size in MB")
                      .long("zone_size")
                      .takes_value(true)
+                ).arg(clap::Arg::with_name("scheduler")
+                     .help("I/O scheduler: clook, deadline, or priority")
+                     .long("scheduler")
+                     .takes_value(true)
                 ).arg(clap::Arg::with_name("property")
                      .help("Dataset properties, comma delimited")
                      .short("o")
@@ -337,15 +940,95 @@ fn main() {
                      .help("Pool name")
                      .required(true)
                 ).arg(clap::Arg::with_name("vdev")
+                      .help("Clusters, each optionally after fast or slow")
                       .multiple(true)
                       .required(true)
                 )
+            ).subcommand(clap::SubCommand::with_name("destroy")
+                .about("destroy a storage pool")
+                .arg(clap::Arg::with_name("name")
+                     .help("Pool name")
+                     .required(true)
+                ).arg(clap::Arg::with_name("disks")
+                      .help("The pool's disks.  If omitted, search for them")
+                      .multiple(true)
+                )
+            ).subcommand(clap::SubCommand::with_name("export")
+                .about("cleanly export a storage pool")
+                .arg(clap::Arg::with_name("name")
+                     .help("Pool name")
+                     .required(true)
+                ).arg(clap::Arg::with_name("disks")
+                      .help("The pool's disks.  If omitted, search for them")
+                      .multiple(true)
+                )
+            ).subcommand(clap::SubCommand::with_name("iostat")
+                .about("display I/O statistics")
+                .arg(clap::Arg::with_name("verbose")
+                     .help("Display statistics for every cluster and disk")
+                     .short("v")
+                ).arg(clap::Arg::with_name("interval")
+                     .help("Seconds between reports")
+                     .long("interval")
+                     .short("i")
+                     .takes_value(true)
+                ).arg(clap::Arg::with_name("count")
+                     .help("Number of reports to display")
+                     .long("count")
+                     .short("c")
+                     .takes_value(true)
+                     .requires("interval")
+                ).arg(clap::Arg::with_name("name")
+                     .help("Pool name")
+                     .required(true)
+                ).arg(clap::Arg::with_name("disks")
+                      .help("The pool's disks.  If omitted, search for them")
+                      .multiple(true)
+                )
+            ).subcommand(clap::SubCommand::with_name("remove")
+                .about("evacuate a cluster and remove it from its pool")
+                .arg(clap::Arg::with_name("name")
+                     .help("Pool name")
+                     .required(true)
+                ).arg(clap::Arg::with_name("cluster")
+                     .help("UUID of the cluster to remove")
+                     .required(true)
+                ).arg(clap::Arg::with_name("disks")
+                      .help("The pool's disks.  If omitted, search for them")
+                      .multiple(true)
+                )
+            ).subcommand(clap::SubCommand::with_name("set")
+                .about("set pool properties")
+                .arg(clap::Arg::with_name("property")
+                     .help("I/O limits, like read_bw=10M, comma delimited")
+                     .short("o")
+                     .takes_value(true)
+                     .multiple(true)
+                     .required(true)
+                     .require_delimiter(true)
+                ).arg(clap::Arg::with_name("name")
+                     .help("Pool name")
+                     .required(true)
+                ).arg(clap::Arg::with_name("disks")
+                      .help("The pool's disks.  If omitted, search for them")
+                      .multiple(true)
+                )
+            ).subcommand(clap::SubCommand::with_name("upgrade")
+                .about("enable all supported on-disk features")
+                .arg(clap::Arg::with_name("name")
+                     .help("Pool name")
+                     .required(true)
+                ).arg(clap::Arg::with_name("disks")
+                      .help("The pool's disks.  If omitted, search for them")
+                      .multiple(true)
+                )
             )
         );
     let matches = app.get_matches();
     match matches.subcommand() {
         ("check", Some(args)) => check::main(args),
         ("debug", Some(args)) => debug::main(args),
+        ("labelclear", Some(args)) => labelclear::main(args),
         ("pool", Some(args)) => pool::main(args),
         _ => {
             println!("Error: subcommand required\n{}", matches.usage());
@@ -46,6 +46,9 @@ pub trait Cacheable: Any + Debug + Send + Sync {
     /// As long as this handle is alive, the object will not be evicted from
     /// cache.
     fn make_ref(&self) -> Box<dyn CacheRef>;
+
+    /// What kind of record is this, for the purpose of choosing a zone?
+    fn write_class(&self) -> WriteClass;
 }
 
 downcast!(dyn Cacheable);
@@ -87,6 +90,10 @@ impl Cacheable for DivBufShared {
     fn make_ref(&self) -> Box<dyn CacheRef> {
         Box::new(self.try_const().unwrap())
     }
+
+    fn write_class(&self) -> WriteClass {
+        WriteClass::Data
+    }
 }
 
 impl CacheRef for DivBuf {
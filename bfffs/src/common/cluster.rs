@@ -5,12 +5,15 @@ use crate::{
     boxfut,
     common::{
         *,
+        iostat::VdevStats,
         label::*,
-        raid::VdevRaidApi
+        raid::VdevRaidApi,
+        throttle::IoLimits,
+        vdev::PriorityClass,
+        vdev_block::SchedulerPolicy
     }
 };
 #[cfg(test)] use crate::common::raid::MockVdevRaid;
-use fixedbitset::FixedBitSet;
 use futures::{ Future, IntoFuture, future};
 use metrohash::MetroHash64;
 #[cfg(test)] use mockall::automock;
@@ -21,6 +24,7 @@ use std::{
     convert::TryFrom,
     fmt::{self, Display, Formatter},
     hash::Hash,
+    mem,
     num::NonZeroU64,
     ops::Range,
     path::Path,
@@ -80,6 +84,8 @@ struct OpenZone {
     pub start: LbaT,
     /// Number of LBAs that have been allocated within this `Zone` so far.
     pub allocated_blocks: u32,
+    /// The only kind of record that may be allocated from this `Zone`
+    pub class: WriteClass,
 }
 
 impl OpenZone {
@@ -105,7 +111,7 @@ impl OpenZone {
 #[derive(Debug)]
 struct FreeSpaceMap {
     /// Which Zones have been modified since the last Cluster::flush?
-    dirty: FixedBitSet,
+    dirty: BTreeSet<ZoneT>,
 
     /// Stores the set of empty zones with id less than zones.len().  All zones
     /// with id greater than or equal to zones.len() are implicitly empty
@@ -164,53 +170,50 @@ impl<'a> FreeSpaceMap {
         self.dirty.clear();
     }
 
-    fn deserialize(vdev: Rc<dyn VdevRaidApi>, buf: DivBuf, zones: ZoneT)
-        -> bincode::Result<
-            impl Future<Item=(Self, Rc<dyn VdevRaidApi + 'static>),
-            Error=Error>
-        >
+    /// Rebuild a `FreeSpaceMap` from the state of each zone, as recorded in
+    /// the spacemap log.  Zones that aren't mentioned are empty.  Open zones
+    /// are reopened for the same class of records they held before.  If
+    /// `readonly`, they're only reopened in memory; the disks aren't touched.
+    fn deserialize(vdev: Rc<dyn VdevRaidApi>,
+                   zods: BTreeMap<ZoneT, ZoneOnDisk>, zones: ZoneT,
+                   readonly: bool)
+        -> impl Future<Item=(Self, Rc<dyn VdevRaidApi + 'static>), Error=Error>
     {
         let mut fsm = FreeSpaceMap::new(zones);
         let mut oz_futs = Vec::new();
-        let mut zid: ZoneT = 0;
-        for (i, db) in buf.into_chunks(BYTES_PER_LBA).enumerate() {
-            let sod = SpacemapOnDisk::deserialize(i as u64, &db).unwrap();
-            if sod.is_err() {
-                let fut = Err(sod.unwrap_err()).into_future();
-                return Ok(boxfut!(fut, _, _, 'static));
-            }
-            for zod in sod.unwrap().zones.into_iter() {
-                if zod.allocated_blocks > 0 {
-                    let zl = vdev.zone_limits(zid);
-                    fsm.open_zone(zid, zl.0, zl.1, 0, zod.txgs.start).unwrap();
-                    if zod.allocated_blocks == u32::max_value() {
-                        // Zone is closed
-                        fsm.finish_zone(zid, zod.txgs.end - 1);
-                    } else {
-                        // Zone is Open
-                        let allocated = LbaT::from(zod.allocated_blocks);
+        for (zid, zod) in zods.into_iter() {
+            assert!(zid < zones, "Spacemap describes a nonexistent zone");
+            if zod.allocated_blocks > 0 {
+                let class = zod.class;
+                let zl = vdev.zone_limits(zid);
+                fsm.open_zone(zid, zl.0, zl.1, 0, class, zod.txgs.start)
+                    .unwrap();
+                if zod.allocated_blocks == u32::max_value() {
+                    // Zone is closed
+                    fsm.finish_zone(zid, zod.txgs.end - 1);
+                } else {
+                    // Zone is Open
+                    let allocated = LbaT::from(zod.allocated_blocks);
+                    if !readonly {
                         oz_futs.push(vdev.reopen_zone(zid, allocated));
-                        let azid = fsm.try_allocate(allocated).0.unwrap().0;
-                        assert_eq!(azid, zid);
                     }
-                    fsm.zones[zid as usize].freed_blocks = zod.freed_blocks;
-                    fsm.zones[zid as usize].txgs = zod.txgs;
-                } else {
-                    // Zone is empty
+                    let azid = fsm.try_allocate(allocated, class)
+                        .0.unwrap().0;
+                    assert_eq!(azid, zid);
                 }
-                zid += 1;
+                fsm.zones[zid as usize].freed_blocks = zod.freed_blocks;
+                fsm.zones[zid as usize].txgs = zod.txgs;
+            } else {
+                // Zone is empty
             }
         }
-        assert_eq!(zid, zones);
         fsm.clear_dirty_zones();
-        let fut = future::join_all(oz_futs).map(|_| (fsm, vdev));
-        Ok(boxfut!(fut, _, _, 'static))
+        future::join_all(oz_futs).map(|_| (fsm, vdev))
     }
 
     /// Mark zone `zone_id` as dirty
     fn dirty_zone(&mut self, zone_id: ZoneT) {
-        let block = zone_id as usize / SPACEMAP_ZONES_PER_LBA;
-        self.dirty.insert(block);
+        self.dirty.insert(zone_id);
     }
 
     /// Return Zone `zone_id` to an Empty state
@@ -239,16 +242,23 @@ impl<'a> FreeSpaceMap {
         }
     }
 
-    /// Find the first Empty zone
-    fn find_empty(&self) -> Option<ZoneT> {
-        self.empty_zones.iter().nth(0).cloned()
-            .or_else(|| {
-                if (self.zones.len() as ZoneT) < self.total_zones {
-                    Some(self.zones.len() as ZoneT)
-                } else {
-                    None
-                }
-            })
+    /// How many zones are Empty, whether explicitly or implicitly?
+    fn empty_zone_count(&self) -> ZoneT {
+        let implicit = self.total_zones - self.zones.len() as ZoneT;
+        implicit + self.empty_zones.len() as ZoneT
+    }
+
+    /// Find the first Empty zone for which `fits` returns true.
+    ///
+    /// Zones need not all be the same size, so a zone that's too small for one
+    /// allocation may still be fine for the next.
+    fn find_empty<F>(&self, fits: F) -> Option<ZoneT>
+        where F: Fn(ZoneT) -> bool
+    {
+        let implicit = self.zones.len() as ZoneT .. self.total_zones;
+        self.empty_zones.iter().cloned()
+            .chain(implicit)
+            .find(|&zid| fits(zid))
     }
 
     /// Mark the Zone as closed.  `txg` is the current transaction group
@@ -337,13 +347,8 @@ impl<'a> FreeSpaceMap {
     }
 
     fn new(total_zones: ZoneT) -> Self {
-        let spacemap_blocks = spacemap_space(u64::from(total_zones));
-        let mut dirty = FixedBitSet::with_capacity(spacemap_blocks as usize);
-        // When newly created, all blocks are considered dirty.  This forces
-        // them to be written out when formatting a new disk.
-        dirty.insert_range(..);
         FreeSpaceMap{
-            dirty,
+            dirty: BTreeSet::new(),
             empty_zones: BTreeSet::new(),
             open_zones: BTreeMap::new(),
             total_zones,
@@ -363,6 +368,7 @@ impl<'a> FreeSpaceMap {
     /// - `start`:  First LBA inside of the zone
     /// - `end`:    First LBA beyond the zone
     /// - `lbas`:   If nonzero, immediately allocate this much space
+    /// - `class`:  The kind of records that may be allocated from this zone
     /// - `txg`:    Current transaction group
     ///
     /// # Returns
@@ -371,7 +377,9 @@ impl<'a> FreeSpaceMap {
     /// space.  If `lbas` was zero, return `None`.  If `lbas` was nonzero and
     /// the requested zone has insufficient space, return ENOSPC.
     fn open_zone(&mut self, id: ZoneT, start: LbaT, end: LbaT, lbas: LbaT,
-                 txg: TxgT) -> Result<Option<(ZoneT, LbaT)>, Error> {
+                 class: WriteClass, txg: TxgT)
+        -> Result<Option<(ZoneT, LbaT)>, Error>
+    {
         self.dirty_zone(id);
         let idx = id as usize;
         let space = end - start;
@@ -391,7 +399,7 @@ impl<'a> FreeSpaceMap {
         self.zones[idx].total_blocks = space as u32;
         self.zones[idx].freed_blocks = 0;
         self.zones[idx].txgs = txg..TxgT(u32::max_value());
-        let oz = OpenZone{start, allocated_blocks: lbas as u32};
+        let oz = OpenZone{start, allocated_blocks: lbas as u32, class};
         self.empty_zones.remove(&id);
         assert!(self.open_zones.insert(id, oz).is_none(),
             "Can only open empty zones");
@@ -402,79 +410,86 @@ impl<'a> FreeSpaceMap {
         }
     }
 
-    /// Open a FreeSpaceMap from an already-formatted `VdevRaid`.
-    fn open(vdev: Rc<dyn VdevRaidApi>)
-        -> impl Future<Item=(Self, Rc<dyn VdevRaidApi + 'static>), Error=Error>
+    /// Open a FreeSpaceMap from an already-formatted `VdevRaid`, by replaying
+    /// its spacemap log.  If `readonly`, nothing is written to the disks.
+    fn open(vdev: Rc<dyn VdevRaidApi>, root: SpacemapRoot, readonly: bool)
+        -> impl Future<Item=(Self, SpacemapLog, Rc<dyn VdevRaidApi + 'static>),
+                       Error=Error>
     {
         let total_zones = vdev.zones();
-        // NB: it would be slightly faster to created it with the correct
-        // capacity and uninitialized.
-        let blocks = div_roundup(total_zones as usize, SPACEMAP_ZONES_PER_LBA);
-        let dbs = DivBufShared::from(vec![0u8; blocks * BYTES_PER_LBA]);
-        let dbm = dbs.try_mut().unwrap();
-        vdev.read_spacemap(dbm, 0)
-        .and_then(move |_| {
-            FreeSpaceMap::deserialize(vdev, dbs.try_const().unwrap(),
-                                      total_zones)
-            .unwrap()
+        SpacemapLog::read(vdev.clone(), root.spacemap)
+        .and_then(move |records| {
+            // Replay from oldest to newest
+            let mut zods = BTreeMap::new();
+            for (_, record) in records.iter().rev() {
+                zods.extend(record.zones.iter().cloned());
+            }
+            if let Some(head) = root.spacemap {
+                // The gap after the newest record is only recorded in the
+                // label
+                let zid = vdev.lba2zone(head.lba).unwrap();
+                if let Some(zod) = zods.get_mut(&zid) {
+                    if zod.allocated_blocks != u32::max_value() {
+                        zod.allocated_blocks += root.gap as u32;
+                        zod.freed_blocks += root.gap as u32;
+                    }
+                }
+            }
+            let ptrs = records.iter().rev().map(|(ptr, _)| *ptr).collect();
+            let sm = SpacemapLog::new(ptrs, root.gap);
+            FreeSpaceMap::deserialize(vdev, zods, total_zones, readonly)
+            .map(move |(fsm, vdev)| (fsm, sm, vdev))
         })
     }
 
+    /// Return an iterator over the zone IDs of all nonempty zones.  These are
+    /// the zones recorded by a spacemap checkpoint.
+    fn nonempty_zone_ids(&'a self) -> impl Iterator<Item=ZoneT> + 'a {
+        (0..self.zones.len() as ZoneT).filter(move |&z| !self.is_empty(z))
+    }
+
     /// Return an iterator over the zone IDs of all open zones
     fn open_zone_ids(&self) -> Keys<ZoneT, OpenZone> {
         self.open_zones.keys()
     }
 
-    /// Serialize this `FreeSpaceMap` so it can be written to a disk's reserved
-    /// area
-    fn serialize(&'a self) -> impl Iterator<Item=(LbaT, DivBufShared)> + 'a {
-        self.dirty.ones()
-        .map(move |i| {
-            let block = i as ZoneT;
-            let szpl = SPACEMAP_ZONES_PER_LBA as ZoneT;
-            let start = block * szpl;
-            let end = cmp::min((block + 1) * szpl, self.total_zones);
-            let v = (start..end).map(|z| {
-                let allocated_blocks = if self.is_empty(z) {
-                    0
-                } else {
-                    match self.open_zones.get(&z) {
-                        Some(oz) => oz.allocated_blocks,
-                        None => u32::max_value()
-                    }
-                };
-                let freed_blocks = if self.is_empty(z) {
-                    0
-                } else {
-                    self.zones[z as usize].freed_blocks
-                };
-                let txgs = if self.is_empty(z) {
-                    TxgT::from(0)..TxgT::from(0)
-                } else {
-                    self.zones[z as usize].txgs.clone()
-                };
-                ZoneOnDisk{allocated_blocks, freed_blocks, txgs}
-            }).collect::<Vec<_>>();
-            let sod = SpacemapOnDisk::new(u64::from(block), v);
-            let dbs = DivBufShared::from(bincode::serialize(&sod).unwrap());
-            (LbaT::from(block), dbs)
-        })
+    /// Describe zone `zid` the way the spacemap stores it
+    fn zone_on_disk(&self, zid: ZoneT) -> ZoneOnDisk {
+        if self.is_empty(zid) {
+            let txgs = TxgT::from(0)..TxgT::from(0);
+            return ZoneOnDisk{allocated_blocks: 0, freed_blocks: 0, txgs,
+                              class: WriteClass::Data};
+        }
+        let (allocated_blocks, class) = match self.open_zones.get(&zid) {
+            Some(oz) => (oz.allocated_blocks, oz.class),
+            None => (u32::max_value(), WriteClass::Data)
+        };
+        let z = &self.zones[zid as usize];
+        ZoneOnDisk{
+            allocated_blocks,
+            freed_blocks: z.freed_blocks,
+            txgs: z.txgs.clone(),
+            class
+        }
     }
 
-    /// Try to allocate `space` worth of space in any open zone.  If no open
-    /// zones can satisfy the allocation, return `None` instead.
+    /// Try to allocate `space` worth of space in any open zone of the given
+    /// class.  If no such zones can satisfy the allocation, return `None`
+    /// instead.
     ///
     /// # Returns
     ///
     /// The Zone and LBA where the allocation happened, and a vector of Zone IDs
-    /// of Zones which have too little space.
-    fn try_allocate(&mut self, space: LbaT)
+    /// of Zones of the same class which have too little space.
+    fn try_allocate(&mut self, space: LbaT, class: WriteClass)
         -> (Option<(ZoneT, LbaT)>, Vec<ZoneT>)
     {
         let mut nearly_full_zones = Vec::with_capacity(1);
         let result = {
             let zones = &self.zones;
-            self.open_zones.iter_mut().find(|&(zone_id, ref oz)| {
+            self.open_zones.iter_mut()
+            .filter(|(_, oz)| oz.class == class)
+            .find(|&(zone_id, ref oz)| {
                 let zone = &zones[*zone_id as usize];
                 let avail_lbas = zone.total_blocks - oz.allocated_blocks;
                 // NB the next two lines can be replaced by
@@ -511,6 +526,35 @@ impl<'a> FreeSpaceMap {
             zid, space, oz.allocated_blocks,
             self.zones[zid as usize].total_blocks);
     }
+
+    /// Try to allocate `space` in whichever open zone has the most available
+    /// space, regardless of its class.
+    ///
+    /// Returns the Zone and LBA where the allocation happened, or `None` if no
+    /// open zone has enough space.
+    fn try_allocate_any(&mut self, space: LbaT) -> Option<(ZoneT, LbaT)> {
+        let zid = self.open_zone_ids().cloned()
+            .max_by_key(|&zid| self.available(zid))
+            .filter(|&zid| self.available(zid) >= space)?;
+        self.dirty_zone(zid);
+        let oz = self.open_zones.get_mut(&zid).unwrap();
+        let lba = oz.write_pointer();
+        oz.allocated_blocks += space as u32;
+        Some((zid, lba))
+    }
+
+    /// Choose which open zones to finish so that no more than `max_open`
+    /// remain open.
+    ///
+    /// Prefer the zones with the least available space, since finishing them
+    /// wastes the least.
+    fn zones_to_finish(&self, max_open: ZoneT) -> Vec<ZoneT> {
+        let mut zone_ids = self.open_zone_ids().cloned().collect::<Vec<_>>();
+        let excess = zone_ids.len().saturating_sub(max_open as usize);
+        zone_ids.sort_by_key(|&zid| self.available(zid));
+        zone_ids.truncate(excess);
+        zone_ids
+    }
 }
 
 impl Display for FreeSpaceMap {
@@ -600,8 +644,33 @@ impl Display for FreeSpaceMap {
     }
 }
 
-/// Persists the `FreeSpaceMap` in the reserved region of the disk
-#[derive(Serialize, Deserialize, Debug, Hash)]
+/// The `Cluster`'s portion of the label
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Label {
+    /// The spacemap log as of each recent transaction group
+    roots: TxgRing<SpacemapRoot>
+}
+
+impl Label {
+    /// The transaction group of the pool checkpoint, if there is one
+    pub fn checkpoint(&self) -> Option<TxgT> {
+        self.roots.checkpoint_txg()
+    }
+}
+
+/// Everything needed to read the spacemap log as of one transaction group
+#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+struct SpacemapRoot {
+    /// The newest record of the spacemap log, if any
+    spacemap: Option<SpacemapPtr>,
+
+    /// LBAs that were wasted after the newest record, to fill out its RAID
+    /// stripe.  The spacemap itself doesn't include them.
+    gap: LbaT
+}
+
+/// The state of a single zone, as recorded in the spacemap log
+#[derive(Clone, Serialize, Deserialize, Debug, Hash)]
 struct ZoneOnDisk {
     /// The number of blocks that have been allocated in each Zone.  If zero,
     /// then the zone is empty.  If `u32::max_value()`, then the zone is closed.
@@ -616,54 +685,179 @@ struct ZoneOnDisk {
     ///
     /// The end is invalid for open zones, and both start and end are invalid
     /// for empty zones.
-    txgs: Range<TxgT>
+    txgs: Range<TxgT>,
+
+    /// The kind of records that may be allocated from this zone.  Only
+    /// meaningful for open zones.
+    class: WriteClass
+}
+
+/// Location of one record of the spacemap log
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Eq, Hash, PartialEq)]
+struct SpacemapPtr {
+    /// First LBA of the record
+    lba: LbaT,
+    /// Length of the record, in LBAs
+    lbas: LbaT
 }
 
-/// Persists the `FreeSpaceMap` in the reserved region of the disk.  Each one of
-/// these structures stores the allocations of as many zones as can fit into
-/// 4KB.
+/// One record of the spacemap log.
+///
+/// The spacemap is stored in ordinary zones rather than in a region reserved
+/// at format time, so its size doesn't limit the number of zones.  Every
+/// `Cluster::flush` that changes the `FreeSpaceMap` appends a record of the
+/// zones that changed.  Every so often, a checkpoint record describes every
+/// nonempty zone instead, and the older records can be freed.
+///
+/// Nothing in the log depends on the size of the device.  If the device grows,
+/// then `Cluster::open` finds the new zones empty.
 #[derive(Serialize, Deserialize, Debug)]
-struct SpacemapOnDisk {
-    /// MetroHash64 self-checksum.  Includes the index of this `SpacemapOnDisk`
-    /// within the overall spacemap, to detect misdirected writes.
+struct SpacemapRecord {
+    /// MetroHash64 self-checksum.  Includes the LBA of this `SpacemapRecord`,
+    /// to detect misdirected writes.
     checksum: u64,
-    zones: Vec<ZoneOnDisk>
+    /// The previous record of the log, or `None` for a checkpoint
+    prev: Option<SpacemapPtr>,
+    zones: Vec<(ZoneT, ZoneOnDisk)>
 }
 
-impl SpacemapOnDisk {
-    fn deserialize(block: LbaT, buf: &DivBuf)
-        -> bincode::Result<Result<Self, Error>>
-    {
-        bincode::deserialize::<SpacemapOnDisk>(&buf[..])
-        .map(|sod| {
-            let mut hasher = MetroHash64::new();
-            hasher.write_u64(block);
-            sod.zones.hash(&mut hasher);
-            if hasher.finish() == sod.checksum {
-                Ok(sod)
-            } else {
-                Err(Error::ECKSUM)
-            }
-        })
+impl SpacemapRecord {
+    /// Serialized size of the fixed portion of a record
+    const HEADER_BYTES: usize = 33;
+    /// Serialized size of each zone's entry
+    const ZONE_BYTES: usize = 24;
+
+    fn deserialize(lba: LbaT, buf: &DivBuf) -> Result<Self, Error> {
+        let record = bincode::deserialize::<SpacemapRecord>(&buf[..])
+            .map_err(|_| Error::ECKSUM)?;
+        let mut hasher = MetroHash64::new();
+        hasher.write_u64(lba);
+        record.prev.hash(&mut hasher);
+        record.zones.hash(&mut hasher);
+        if hasher.finish() == record.checksum {
+            Ok(record)
+        } else {
+            Err(Error::ECKSUM)
+        }
+    }
+
+    /// How many LBAs are needed to store a record of `nzones` zones?
+    fn lbas(nzones: usize) -> LbaT {
+        let bytes = SpacemapRecord::HEADER_BYTES +
+            nzones * SpacemapRecord::ZONE_BYTES;
+        div_roundup(bytes, BYTES_PER_LBA) as LbaT
     }
 
-    fn new(i: u64, v: Vec<ZoneOnDisk>) -> Self {
-        debug_assert!(v.len() <= SPACEMAP_ZONES_PER_LBA);
+    fn new(lba: LbaT, prev: Option<SpacemapPtr>,
+           zones: Vec<(ZoneT, ZoneOnDisk)>) -> Self
+    {
         let mut hasher = MetroHash64::new();
-        hasher.write_u64(i);
-        v.hash(&mut hasher);
-        SpacemapOnDisk {
+        hasher.write_u64(lba);
+        prev.hash(&mut hasher);
+        zones.hash(&mut hasher);
+        SpacemapRecord {
             checksum: hasher.finish(),
-            zones: v
+            prev,
+            zones
         }
     }
 }
 
+/// In-core bookkeeping for the spacemap log
+#[derive(Debug)]
+struct SpacemapLog {
+    /// Every live record since, and including, the last checkpoint, oldest
+    /// first
+    records: Vec<SpacemapPtr>,
+
+    /// Records superseded by the last checkpoint.  They may not be freed until
+    /// the checkpoint's transaction group has been committed.
+    obsolete: Vec<SpacemapPtr>,
+
+    /// Transaction group of the last checkpoint
+    checkpoint_txg: TxgT,
+
+    /// LBAs wasted after the newest record
+    gap: LbaT
+}
+
+impl SpacemapLog {
+    /// The newest record, if any
+    fn head(&self) -> Option<SpacemapPtr> {
+        self.records.last().cloned()
+    }
+
+    /// Total size of the live records, in LBAs
+    fn lbas(&self) -> LbaT {
+        self.records.iter().map(|ptr| ptr.lbas).sum()
+    }
+
+    fn new(records: Vec<SpacemapPtr>, gap: LbaT) -> Self {
+        let checkpoint_txg = TxgT::from(0);
+        SpacemapLog{records, obsolete: Vec::new(), checkpoint_txg, gap}
+    }
+
+    /// Read every record of the log, beginning with `head`, newest first
+    fn read(vdev: Rc<dyn VdevRaidApi>, head: Option<SpacemapPtr>)
+        -> impl Future<Item=Vec<(SpacemapPtr, SpacemapRecord)>, Error=Error>
+    {
+        future::loop_fn((Vec::new(), head), move |(mut records, next)| {
+            let ptr = match next {
+                None => {
+                    let done = future::Loop::Break(records);
+                    return future::Either::A(future::ok(done));
+                },
+                Some(ptr) => ptr
+            };
+            let len = ptr.lbas as usize * BYTES_PER_LBA;
+            let dbs = DivBufShared::from(vec![0u8; len]);
+            let dbm = dbs.try_mut().unwrap();
+            let fut = vdev.read_at(dbm, ptr.lba, PriorityClass::Normal)
+            .and_then(move |_| {
+                let db = dbs.try_const().unwrap();
+                let record = SpacemapRecord::deserialize(ptr.lba, &db)?;
+                let prev = record.prev;
+                records.push((ptr, record));
+                Ok(future::Loop::Continue((records, prev)))
+            });
+            future::Either::B(fut)
+        })
+    }
+
+    /// Zones that contain any live or obsolete records
+    fn zones(&self, vdev: &dyn VdevRaidApi) -> BTreeSet<ZoneT> {
+        self.records.iter()
+            .chain(self.obsolete.iter())
+            .map(|ptr| vdev.lba2zone(ptr.lba).unwrap())
+            .collect()
+    }
+}
+
 /// A `Cluster` is BFFFS's equivalent of ZFS's top-level Vdev.  It is the
 /// highest level `Vdev` that has its own LBA space.
 pub struct Cluster {
     fsm: RefCell<FreeSpaceMap>,
 
+    /// The most zones that may be open at once, if the underlying devices have
+    /// such a limit
+    max_open_zones: Option<ZoneT>,
+
+    /// Closed zones that have been completely freed, but may not be erased
+    /// yet because the label's older roots may still reference them.  Each is
+    /// tagged with the transaction group in which it was freed, once that's
+    /// known.
+    pending_erase: RefCell<BTreeMap<ZoneT, Option<TxgT>>>,
+
+    /// Empty zones that ordinary writes may not open.  They're held back so
+    /// that the cleaner and the spacemap can always make progress.
+    reserved_zones: ZoneT,
+
+    /// Spacemap roots written to the label in recent transaction groups
+    roots: RefCell<TxgRing<SpacemapRoot>>,
+
+    /// Location of the on-disk spacemap
+    spacemap: RefCell<SpacemapLog>,
+
     /// Underlying vdev (which may or may not use RAID)
     // The Rc is necessary in order for some methods to return futures with
     // 'static lifetimes
@@ -686,17 +880,116 @@ macro_rules! close_zones{
 
 #[cfg_attr(test, automock)]
 impl Cluster {
+    /// Reserve 1/2^SLOP_SHIFT of each `Cluster`'s zones as slop space, like
+    /// ZFS's `spa_slop_shift`.
+    const SLOP_SHIFT: u32 = 5;
+
     /// How many blocks have been allocated, including blocks that have been
     /// freed but not erased?
     pub fn allocated(&self) -> LbaT {
         self.fsm.borrow().allocated()
     }   // LCOV_EXCL_LINE   kcov false negative
 
+    /// Allocate `space` LBAs for a record of the given `class`.
+    ///
+    /// # Returns
+    ///
+    /// The zone and LBA of the allocation, a `Future` that opens the zone if
+    /// necessary, and a `Future` that finishes any zones which became too full
+    /// along the way.  Returns `None` if there is no space.
+    fn allocate(&self, space: LbaT, class: WriteClass, txg: TxgT)
+        -> Option<(ZoneT, LbaT, Box<ClusterFut>, Box<ClusterFut>)>
+    {
+        // Outline:
+        // 1) Try allocating in an open zone of the same class
+        // 2) If that doesn't work and no more zones may be opened, try
+        //    allocating in an open zone of any class
+        // 3) If that doesn't work, try opening a new one, and allocating from
+        //    that
+        // 4) If that doesn't work, return None
+        let mut finish_futs = Vec::new();
+        if let Some(max) = self.max_open_zones {
+            // Zones reopened on import may exceed the limit, if the pool was
+            // last imported on devices that allowed more.
+            let excess = self.fsm.borrow().zones_to_finish(max);
+            finish_futs.extend(close_zones!(self, &excess, txg));
+        }
+        let (alloc_result, nearly_full_zones) =
+            self.fsm.borrow_mut().try_allocate(space, class);
+        finish_futs.extend(close_zones!(self, &nearly_full_zones, txg));
+        let vdev2 = self.vdev.clone();
+        alloc_result.or_else(|| {
+            let at_limit = self.max_open_zones.map_or(false, |max| {
+                self.fsm.borrow().open_zones.len() >= max as usize
+            });
+            if at_limit {
+                // Finishing a zone early would waste its remaining space, so
+                // share one with another class instead.
+                self.fsm.borrow_mut().try_allocate_any(space)
+            } else {
+                None
+            }
+        }).map(|(zone_id, lba)| {
+            let oz_fut: Box<ClusterFut> = Box::new(future::ok::<(),
+                                                            Error>(()));
+            (zone_id, lba, oz_fut)
+        }).or_else(|| {
+            if class == WriteClass::Data &&
+                self.fsm.borrow().empty_zone_count() <= self.reserved_zones
+            {
+                // Leave the slop space for whatever a transaction sync must
+                // write: tree nodes, including the cleaner's own, relocated
+                // records, and the spacemap.
+                return None;
+            }
+            let empty_zone = self.fsm.borrow().find_empty(|zid| {
+                let zl = vdev2.zone_limits(zid);
+                zl.1 - zl.0 >= space
+            });
+            empty_zone.and_then(|zone_id| {
+                let zl = vdev2.zone_limits(zone_id);
+                if let Some(max) = self.max_open_zones {
+                    // Make room for the new zone by finishing others
+                    let victims = self.fsm.borrow().zones_to_finish(max - 1);
+                    finish_futs.extend(close_zones!(self, &victims, txg));
+                }
+                let e = self.fsm.borrow_mut().open_zone(zone_id, zl.0, zl.1,
+                                                        space, class, txg);
+                match e {
+                    Ok(Some((zone_id, lba))) => {
+                        let fut = vdev2.open_zone(zone_id);
+                        Some((zone_id, lba, boxfut!(fut, _, _, 'static)))
+                    },
+                    Err(_) => None,
+                    Ok(None) => panic!("Tried a 0-length write?"),
+                }
+            })  // LCOV_EXCL_LINE   kcov false negative
+        }).map(move |(zone_id, lba, oz_fut)| {
+            let finish_fut = future::join_all(finish_futs).map(drop);
+            (zone_id, lba, oz_fut, Box::new(finish_fut) as Box<ClusterFut>)
+        })
+    }
+
     /// Assert that the given zone was clean as of the given transaction
     pub fn assert_clean_zone(&self, zone: ZoneT, txg: TxgT) {
         self.fsm.borrow().assert_clean_zone(zone, txg)
     }   // LCOV_EXCL_LINE   kcov false negative
 
+    /// The transaction group of the pool checkpoint, if there is one
+    pub fn checkpoint(&self) -> Option<TxgT> {
+        self.roots.borrow().checkpoint_txg()
+    }
+
+    /// How many freed blocks can't be reused until the pool checkpoint is
+    /// discarded, because their zones may hold checkpointed data?
+    pub fn checkpointed(&self) -> LbaT {
+        let fsm = self.fsm.borrow();
+        (0..fsm.zones.len() as ZoneT)
+            .filter(|&zid| !fsm.is_empty(zid) && self.in_checkpoint(&fsm, zid))
+            .map(|zid| LbaT::from(fsm.zones[zid as usize].freed_blocks))
+            .sum()
+    }
+
     /// Create a new `Cluster` from unused files or devices
     ///
     /// * `chunksize`:          RAID chunksize in LBAs, if specified.  This is
@@ -715,14 +1008,14 @@ impl Cluster {
     /// * `paths`:              Slice of pathnames of files and/or devices
     pub fn create<P>(chunksize: Option<NonZeroU64>, disks_per_stripe: i16,
         lbas_per_zone: Option<NonZeroU64>, redundancy: i16, paths: Vec<P>)
-        -> Self
+        -> Result<Self, Error>
         where P: AsRef<Path> + 'static
     {
         let vdev = raid::create(chunksize, disks_per_stripe, lbas_per_zone,
-                                redundancy, paths);
+                                redundancy, paths)?;
         let total_zones = vdev.zones();
         let fsm = FreeSpaceMap::new(total_zones);
-        Cluster::new((fsm, vdev))
+        Ok(Cluster::new((fsm, vdev)))
     }
 
     /// Dump the FreeSpaceMap in human-readable form, for debugging purposes
@@ -731,6 +1024,32 @@ impl Cluster {
         format!("{}", self.fsm.borrow())
     }
 
+    /// Erase every fully freed zone that no root in the label can reference
+    /// anymore.
+    ///
+    /// Zones freed since the last call are tagged as freed in `txg`.  Zones
+    /// that may hold checkpointed data stay pending until the checkpoint is
+    /// discarded.
+    fn erase_freed_zones(&self, txg: TxgT) -> Vec<Box<ClusterFut>> {
+        let mut pending_erase = self.pending_erase.borrow_mut();
+        for freed_txg in pending_erase.values_mut() {
+            freed_txg.get_or_insert(txg);
+        }
+        let fsm = self.fsm.borrow();
+        let erasable = pending_erase.iter()
+            .filter(|&(&zid, freed_txg)| {
+                freed_txg.unwrap() + REWIND_TXGS as u32 <= txg &&
+                    !self.in_checkpoint(&fsm, zid)
+            }).map(|(zid, _)| *zid)
+            .collect::<Vec<_>>();
+        drop(fsm);
+        erasable.into_iter()
+            .map(|zid| {
+                pending_erase.remove(&zid);
+                Box::new(self.erase_zone(zid)) as Box<ClusterFut>
+            }).collect()
+    }
+
     /// Delete the underlying storage for a Zone.
     fn erase_zone(&self, zone: ZoneT)
         -> impl Future<Item=(), Error=Error>
@@ -739,51 +1058,178 @@ impl Cluster {
         self.vdev.erase_zone(zone)
     }
 
+    /// How many blocks hold live data?  Blocks that have been freed don't
+    /// count, and neither does the spacemap log.
+    ///
+    /// Once a `Cluster` has been evacuated, this should be zero.
+    pub fn live_data(&self) -> LbaT {
+        let sm_zones = self.spacemap.borrow().zones(&*self.vdev);
+        let fsm = self.fsm.borrow();
+        (0..fsm.zones.len() as ZoneT)
+            .filter(|zid| !sm_zones.contains(zid))
+            .map(|zid| fsm.in_use(zid))
+            .sum()
+    }
+
     /// Find the first closed zone whose index is greater than or equal to `zid`
+    ///
+    /// Zones that hold the spacemap log are skipped; the `Cluster` frees them
+    /// itself.  So are zones that are already waiting to be erased, and zones
+    /// that may hold checkpointed data, since cleaning them would reclaim
+    /// nothing.
     pub fn find_closed_zone(&self, zid: ZoneT) -> Option<ClosedZone> {
-        self.fsm.borrow().find_closed_zone(zid)
-            .map(|mut zone| {
-                zone.start = self.vdev.zone_limits(zone.zid).0;
-                zone
-            })
+        let sm_zones = self.spacemap.borrow().zones(&*self.vdev);
+        let pending_erase = self.pending_erase.borrow();
+        let fsm = self.fsm.borrow();
+        let mut zid = zid;
+        loop {
+            match fsm.find_closed_zone(zid) {
+                Some(ref zone) if sm_zones.contains(&zone.zid) ||
+                    pending_erase.contains_key(&zone.zid) ||
+                    self.in_checkpoint(&fsm, zone.zid) =>
+                {
+                    zid = zone.zid + 1;
+                },
+                Some(mut zone) => {
+                    zone.start = self.vdev.zone_limits(zone.zid).0;
+                    return Some(zone);
+                },
+                None => return None
+            }
+        }
     }
 
-    /// Flush all data and metadata to disk, but don't sync yet.  This should
-    /// normally be called just before [`sync_all`](#method.sync_all).  `idx` is
-    /// the index of the label that is about to be written.
-    pub fn flush(&self, idx: u32) -> impl Future<Item=(), Error=Error>
+    /// Finish every open zone, so that all of the `Cluster`'s data can be
+    /// cleaned.
+    ///
+    /// Used when removing the `Cluster` from its `Pool`.  Once the `Pool`
+    /// stops choosing this `Cluster` for writes, only the spacemap log will
+    /// open any more zones.
+    pub fn finish_zones(&self, txg: TxgT)
+        -> impl Future<Item=(), Error=Error>
     {
-        let mut fsm = self.fsm.borrow_mut();
-        let vdev2 = self.vdev.clone();
-        let zone_ids = fsm.open_zone_ids().cloned().collect::<Vec<_>>();
-        let flush_futs = zone_ids.iter().map(|&zone_id| {
+        let zone_ids = self.fsm.borrow().open_zone_ids().cloned()
+            .collect::<Vec<_>>();
+        future::join_all(close_zones!(self, &zone_ids, txg)).map(drop)
+    }
+
+    /// Flush all data and metadata to disk, but don't sync yet.  This should
+    /// normally be called just before [`sync_all`](#method.sync_all).
+    ///
+    /// If the `FreeSpaceMap` has changed, appends a record to the spacemap
+    /// log.  Records that were superseded by a checkpoint in an earlier
+    /// transaction group get freed, and zones freed at least `REWIND_TXGS`
+    /// transaction groups ago get erased.
+    pub fn flush(&self, txg: TxgT) -> Box<ClusterFut> {
+        let mut futs: Vec<Box<ClusterFut>> = Vec::new();
+
+        let obsolete = {
+            let mut sm = self.spacemap.borrow_mut();
+            if txg > sm.checkpoint_txg {
+                mem::replace(&mut sm.obsolete, Vec::new())
+            } else {
+                Vec::new()
+            }
+        };
+        for ptr in obsolete.into_iter() {
+            futs.push(self.free(ptr.lba, ptr.lbas));
+        }
+        futs.extend(self.erase_freed_zones(txg));
+
+        let zone_ids = self.fsm.borrow().open_zone_ids().cloned()
+            .collect::<Vec<_>>();
+        for zone_id in zone_ids.into_iter() {
             let (gap, fut) = self.vdev.flush_zone(zone_id);
+            if gap > 0 {
+                self.fsm.borrow_mut().waste_space(zone_id, gap);
+            }
+            futs.push(fut);
+        }
+
+        if self.fsm.borrow().dirty.is_empty() {
+            return Box::new(future::join_all(futs).map(drop));
+        }
+
+        let mut sm = self.spacemap.borrow_mut();
+        if let Some(head) = sm.head() {
+            // The gap after the previous record is only recorded in the label,
+            // so this record must describe its zone.
+            let zid = self.vdev.lba2zone(head.lba).unwrap();
+            if !self.fsm.borrow().is_empty(zid) {
+                self.fsm.borrow_mut().dirty_zone(zid);
+            }
+        }
+
+        // Checkpoint once the log grows bigger than a checkpoint would be
+        let nonempty = self.fsm.borrow().nonempty_zone_ids().count();
+        let checkpoint = sm.records.is_empty() ||
+            sm.lbas() >= SpacemapRecord::lbas(nonempty);
+        // Allocating the record may dirty a few more zones: its own, and any
+        // that have to be finished to make room for it.
+        let slack = self.fsm.borrow().open_zone_ids().count() + 1;
+        let nzones = if checkpoint {
+            nonempty
+        } else {
+            self.fsm.borrow().dirty.len()
+        } + slack;
+        let lbas = SpacemapRecord::lbas(nzones);
+        let (zone_id, lba, oz_fut, finish_fut) =
+            match self.allocate(lbas, WriteClass::Spacemap, txg)
+        {
+            Some(x) => x,
+            None => {
+                let fut = future::join_all(futs)
+                    .and_then(|_| Err(Error::ENOSPC));
+                return Box::new(fut);
+            }
+        };
+        futs.push(finish_fut);
+
+        let fsm = self.fsm.borrow();
+        let zones = if checkpoint {
+            fsm.nonempty_zone_ids()
+                .map(|zid| (zid, fsm.zone_on_disk(zid)))
+                .collect::<Vec<_>>()
+        } else {
+            fsm.dirty.iter()
+                .map(|&zid| (zid, fsm.zone_on_disk(zid)))
+                .collect::<Vec<_>>()
+        };
+        drop(fsm);
+        let prev = if checkpoint { None } else { sm.head() };
+        let record = SpacemapRecord::new(lba, prev, zones);
+        let mut buf = bincode::serialize(&record).unwrap();
+        assert!(buf.len() <= lbas as usize * BYTES_PER_LBA,
+            "Spacemap record overflowed its allocation");
+        buf.resize(lbas as usize * BYTES_PER_LBA, 0);
+        let dbs = DivBufShared::from(buf);
+        let wfut = self.vdev.write_at(dbs.try_const().unwrap(), zone_id, lba,
+                                      PriorityClass::Normal);
+        futs.push(Box::new(oz_fut.and_then(move |_| wfut)));
+
+        let (gap, fut) = self.vdev.flush_zone(zone_id);
+        futs.push(fut);
+        let mut fsm = self.fsm.borrow_mut();
+        if gap > 0 {
             fsm.waste_space(zone_id, gap);
-            fut
-        }).collect::<Vec<_>>();
-        // Since FreeSpaceMap::waste_space is synchronous, we can serialize the
-        // FSM here; we don't need to copy it into a Future's continuation.
-        let sm_futs = fsm.serialize()
-        .map(|(block, dbs)| {
-            let db = dbs.try_const().unwrap();
-            let sglist = if db.len() % BYTES_PER_LBA != 0 {
-                // This can happen in the last blockof the spacemap.  Pad out.
-                let padlen = BYTES_PER_LBA - db.len() % BYTES_PER_LBA;
-                let pad = ZERO_REGION.try_const().unwrap().slice_to(padlen);
-                vec![db, pad]
-            } else {
-                vec![db]
-            };
-            vdev2.write_spacemap(&sglist, idx, block)
-        }).collect::<Vec<_>>();
-        let fut = future::join_all(flush_futs).join(future::join_all(sm_futs))
-        .map(drop);
+        }
         fsm.clear_dirty_zones();
-        fut
+
+        if checkpoint {
+            let records = mem::replace(&mut sm.records, Vec::new());
+            sm.obsolete.extend(records);
+            sm.checkpoint_txg = txg;
+        }
+        sm.records.push(SpacemapPtr{lba, lbas});
+        sm.gap = gap;
+        Box::new(future::join_all(futs).map(drop))
     }
 
-    /// Mark `length` LBAs beginning at LBA `lba` as unused, and possibly delete
-    /// them from the underlying storage.
+    /// Mark `length` LBAs beginning at LBA `lba` as unused.
+    ///
+    /// Once a closed zone is completely unused, it will be erased by a later
+    /// [`flush`](#method.flush).  But not right away, because the roots of the
+    /// last few transaction groups may still reference it.
     ///
     /// Deleting data in increments other than it was written is unsupported.
     /// In particular, it is not allowed to delete across zone boundaries.
@@ -801,32 +1247,75 @@ impl Cluster {
         }
         let mut fsm = self.fsm.borrow_mut();
         fsm.free(start_zone, length);
-        // Erase the zone if it is fully freed
         if fsm.is_closed(start_zone) && fsm.in_use(start_zone) == 0 {
-            drop(fsm);
-            Box::new(self.erase_zone(start_zone))
-        } else {
-            Box::new(Ok(()).into_future())
+            self.pending_erase.borrow_mut().insert(start_zone, None);
         }
+        Box::new(Ok(()).into_future())
     }
 
     /// Construct a new `Cluster` from an already constructed
     /// [`VdevRaidApi`](trait.VdevRaidApi.html)
     fn new(args: (FreeSpaceMap, Rc<dyn VdevRaidApi>)) -> Self {
         let (fsm, vdev) = args;
-        Cluster{fsm: RefCell::new(fsm), vdev}
+        let max_open_zones = vdev.max_open_zones();
+        // Small clusters still get one zone of slop, unless that's all they
+        // have.
+        let reserved_zones = cmp::min(
+            cmp::max(1, fsm.total_zones >> Cluster::SLOP_SHIFT),
+            fsm.total_zones.saturating_sub(1));
+        let spacemap = RefCell::new(SpacemapLog::new(Vec::new(), 0));
+        Cluster{fsm: RefCell::new(fsm), max_open_zones,
+                pending_erase: RefCell::new(BTreeMap::new()), reserved_zones,
+                roots: RefCell::new(TxgRing::default()), spacemap, vdev}
+    }
+
+    /// Might closed zone `zid` hold data referenced by the pool checkpoint?
+    fn in_checkpoint(&self, fsm: &FreeSpaceMap, zid: ZoneT) -> bool {
+        self.roots.borrow().checkpoint_txg()
+            .map(|ckpt| fsm.zones[zid as usize].txgs.start <= ckpt)
+            .unwrap_or(false)
+    }
+
+    /// Snapshot the I/O statistics of the `Cluster` and all of its disks
+    pub fn iostats(&self) -> VdevStats {
+        self.vdev.iostats()
     }
 
     /// Open a `Cluster` from an already opened
     /// [`VdevRaidApi`](trait.VdevRaidApi.html)
     ///
     /// Returns a new `Cluster` and a `LabelReader` that may be used to
-    /// construct other vdevs stacked on top.
-    pub fn open(vdev_raid: Rc<dyn VdevRaidApi>)
-        -> impl Future<Item=Self, Error=Error>
+    /// construct other vdevs stacked on top.  If the `LabelReader` has a rewind
+    /// target, the `Cluster` is opened as of that transaction group.  If
+    /// `readonly`, its open zones aren't reopened on disk, so the `Cluster`
+    /// must not be written.
+    pub fn open(vdev_raid: Rc<dyn VdevRaidApi>, mut label_reader: LabelReader,
+                readonly: bool)
+        -> impl Future<Item=(Self, LabelReader), Error=Error>
     {
-        FreeSpaceMap::open(vdev_raid)
-            .map(Cluster::new)
+        let mut label: Label = match label_reader.deserialize() {
+            Ok(l) => l,
+            Err(_) => return future::Either::A(future::err(Error::EINVAL))
+        };
+        let (txg, root) = match label.roots.select(label_reader.rewind()) {
+            Ok(x) => x,
+            Err(e) => return future::Either::A(future::err(e))
+        };
+        let fut = FreeSpaceMap::open(vdev_raid, root, readonly)
+        .map(move |(fsm, sm, vdev)| {
+            // Zones that were freed just before the label was written can't be
+            // erased until the roots that reference them age out.
+            let pending_erase = (0..fsm.zones.len() as ZoneT)
+                .filter(|&zid| fsm.is_closed(zid) && fsm.in_use(zid) == 0)
+                .map(|zid| (zid, Some(txg)))
+                .collect::<BTreeMap<_, _>>();
+            let cluster = Cluster::new((fsm, vdev));
+            *cluster.pending_erase.borrow_mut() = pending_erase;
+            *cluster.roots.borrow_mut() = label.roots;
+            *cluster.spacemap.borrow_mut() = sm;
+            (cluster, label_reader)
+        });
+        future::Either::B(fut)
     }
 
     /// Returns the "best" number of operations to queue to this `Cluster`.  A
@@ -838,10 +1327,54 @@ impl Cluster {
     }
 
     /// Asynchronously read from the cluster
-    pub fn read(&self, buf: IoVecMut, lba: LbaT)
+    pub fn read(&self, buf: IoVecMut, lba: LbaT, priority: PriorityClass)
+        -> impl Future<Item=(), Error=Error>
+    {
+        self.vdev.read_at(buf, lba, priority)
+    }
+
+    /// Approximately how much space is reserved as slop space, in LBAs?
+    ///
+    /// Ordinary data writes may not use it, but everything written by a
+    /// transaction sync may: tree nodes, the cleaner's relocations, and the
+    /// spacemap.
+    pub fn reserved(&self) -> LbaT {
+        let zones = LbaT::from(self.vdev.zones());
+        if zones == 0 {
+            0
+        } else {
+            self.vdev.size() * LbaT::from(self.reserved_zones) / zones
+        }
+    }
+
+    /// Make a `Cluster` that was opened as of an older transaction group safe
+    /// to write.
+    ///
+    /// Zones written after `txg` look empty or partly allocated in the
+    /// restored `FreeSpaceMap`, but the disks' write pointers have already
+    /// moved past them.  So erase every empty zone, and finish every open one.
+    pub fn reset_rewound_zones(&self, txg: TxgT)
         -> impl Future<Item=(), Error=Error>
     {
-        self.vdev.read_at(buf, lba)
+        let fsm = self.fsm.borrow();
+        let erase_futs = (0..self.vdev.zones())
+            .filter(|&zid| fsm.is_empty(zid))
+            .map(|zid| self.vdev.erase_zone(zid))
+            .collect::<Vec<_>>();
+        drop(fsm);
+        future::join_all(erase_futs)
+        .join(self.finish_zones(txg))
+        .map(drop)
+    }
+
+    /// Change the I/O rate limits of each of the `Cluster`'s disks
+    pub fn set_limits(&self, limits: IoLimits) {
+        self.vdev.set_limits(limits)
+    }
+
+    /// Change the I/O scheduling policy of all of the `Cluster`'s disks
+    pub fn set_scheduler(&self, policy: SchedulerPolicy) {
+        self.vdev.set_scheduler(policy)
     }
 
     /// Return approximately the usable space of the Cluster in LBAs.
@@ -862,60 +1395,41 @@ impl Cluster {
 
     /// Write a buffer to the cluster
     ///
+    /// The buffer will be placed in a zone that holds only records of the
+    /// same `class`.  `priority` is a hint to the disks' I/O throttles.
+    ///
     /// # Returns
     ///
     /// The LBA where the data will be written, and a
     /// `Future` for the operation in progress.
-    pub fn write(&self, buf: IoVec, txg: TxgT)
-        -> Result<(LbaT, Box<ClusterFut>), Error> {
-        // Outline:
-        // 1) Try allocating in an open zone
-        // 2) If that doesn't work, try opening a new one, and allocating from
-        //    that
-        // 3) If that doesn't work, return ENOSPC
-        // 4) write to the vdev
+    pub fn write(&self, buf: IoVec, class: WriteClass, txg: TxgT,
+                 priority: PriorityClass)
+        -> Result<(LbaT, Box<ClusterFut>), Error>
+    {
         let space = div_roundup(buf.len(), BYTES_PER_LBA) as LbaT;
-        let (alloc_result, nearly_full_zones) =
-            self.fsm.borrow_mut().try_allocate(space);
-        let finish_futs = close_zones!(self, &nearly_full_zones, txg);
-        let vdev2 = self.vdev.clone();
-        let vdev3 = self.vdev.clone();
-        alloc_result.map(|(zone_id, lba)| {
-            let oz_fut: Box<ClusterFut> = Box::new(future::ok::<(),
-                                                            Error>(()));
-            (zone_id, lba, oz_fut)
-        }).or_else(|| {
-            let empty_zone = self.fsm.borrow().find_empty();
-            empty_zone.and_then(|zone_id| {
-                let zl = vdev2.zone_limits(zone_id);
-                let e = self.fsm.borrow_mut().open_zone(zone_id, zl.0, zl.1,
-                                                        space, txg);
-                match e {
-                    Ok(Some((zone_id, lba))) => {
-                        let fut = vdev2.open_zone(zone_id);
-                        Some((zone_id, lba, boxfut!(fut, _, _, 'static)))
-                    },
-                    Err(_) => None,
-                    Ok(None) => panic!("Tried a 0-length write?"),
-                }
-            })  // LCOV_EXCL_LINE   kcov false negative
-        }).map(|(zone_id, lba, oz_fut)| {
-            let fut : Box<dyn Future<Item = (), Error = Error>>;
-            let wfut = vdev3.write_at(buf, zone_id, lba);
-            let owfut = oz_fut.and_then(move |_| {
-                wfut
-            }
-            );
-            fut = Box::new(future::join_all(finish_futs).join(owfut).map(drop));
-            (lba, fut)
+        self.allocate(space, class, txg)
+        .map(|(zone_id, lba, oz_fut, finish_fut)| {
+            let wfut = self.vdev.write_at(buf, zone_id, lba, priority);
+            let owfut = oz_fut.and_then(move |_| wfut);
+            let fut = Box::new(finish_fut.join(owfut).map(drop));
+            (lba, fut as Box<ClusterFut>)
         }).ok_or(Error::ENOSPC)
     }
 
     /// Asynchronously write this cluster's label to all component devices
     /// All data and spacemap should be written and synced first!
-    pub fn write_label(&self, labeller: LabelWriter)
+    pub fn write_label(&self, mut labeller: LabelWriter)
         -> impl Future<Item=(), Error=Error>
     {   // LCOV_EXCL_LINE   kcov false negative
+        let label = {
+            let sm = self.spacemap.borrow();
+            let root = SpacemapRoot{spacemap: sm.head(), gap: sm.gap};
+            let mut roots = self.roots.borrow_mut();
+            roots.push(labeller.txg(), root);
+            roots.update_checkpoint(labeller.checkpoint_op());
+            Label{roots: roots.clone()}
+        };
+        labeller.serialize(&label).unwrap();
         self.vdev.write_label(labeller)
     }
 }
@@ -930,7 +1444,8 @@ mod open_zone {
     // pet kcov
     #[test]
     fn debug() {
-        let oz = OpenZone{start: 0, allocated_blocks: 0};
+        let oz = OpenZone{start: 0, allocated_blocks: 0,
+                          class: WriteClass::Data};
         format!("{:?}", oz);
     }
 }
@@ -964,9 +1479,9 @@ mod cluster {
             .with(eq(0))
             .return_once(|_| Box::new( future::ok::<(), Error>(())));
         vr.expect_write_at()
-            .with(always(), eq(0), always())
+            .with(always(), eq(0), always(), always())
             .once()
-            .return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
+            .return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
         vr.expect_finish_zone()
             .once()
             .with(eq(0))
@@ -976,26 +1491,32 @@ mod cluster {
             .with(eq(1))
             .return_once(|_| Box::new( future::ok::<(), Error>(())));
         vr.expect_write_at()
-            .with(always(), eq(1), always())
+            .with(always(), eq(1), always(), always())
             .once()
-            .return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
+            .return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
         vr.expect_erase_zone()
             .once()
             .with(eq(0))
             .return_once(|_| Box::new( future::ok::<(), Error>(())));
 
         let fsm = FreeSpaceMap::new(vr.zones());
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
         let cluster = Cluster::new((fsm, Rc::new(vr)));
 
         let dbs = DivBufShared::from(vec![0u8; 4096]);
         let db0 = dbs.try_const().unwrap();
         let db1 = db0.clone();
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            let (lba, fut1) = cluster.write(db0, TxgT::from(0))
+            let (lba, fut1) = cluster.write(db0, WriteClass::Data,
+                                            TxgT::from(0),
+                                            PriorityClass::Normal)
                 .expect("write failed early");
             // Write a 2nd time so the first zone will get closed
             fut1.and_then(|_| {
-                let (_, fut2) = cluster.write(db1, TxgT::from(0))
+                let (_, fut2) = cluster.write(db1, WriteClass::Data,
+                                              TxgT::from(0),
+                                              PriorityClass::Normal)
                     .expect("write failed early");
                 fut2
             }).map(move|_| lba)
@@ -1003,6 +1524,12 @@ mod cluster {
                 cluster.free(lba, 1)
             })
         })).unwrap();
+        // The zone must not be erased until the label's older roots age out
+        assert!(cluster.erase_freed_zones(TxgT::from(1)).is_empty());
+        let txg = TxgT::from(1 + REWIND_TXGS as u32);
+        let futs = cluster.erase_freed_zones(txg);
+        future::join_all(futs).wait().unwrap();
+        assert!(cluster.pending_erase.borrow().is_empty());
     }
 
     #[test]
@@ -1024,9 +1551,9 @@ mod cluster {
             .with(eq(0))
             .return_once(|_| Box::new( future::ok::<(), Error>(())));
         vr.expect_write_at()
-            .with(always(), eq(0), always())
+            .with(always(), eq(0), always(), always())
             .once()
-            .return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
+            .return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
         vr.expect_finish_zone()
             .once()
             .with(eq(0))
@@ -1036,15 +1563,17 @@ mod cluster {
             .with(eq(1))
             .return_once(|_| Box::new( future::ok::<(), Error>(())));
         vr.expect_write_at()
-            .with(always(), eq(1), always())
+            .with(always(), eq(1), always(), always())
             .once()
-            .return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
+            .return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
         vr.expect_erase_zone()
             .once()
             .with(eq(0))
             .return_once(|_| Box::new( future::ok::<(), Error>(())));
 
         let fsm = FreeSpaceMap::new(vr.zones());
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
         let cluster = Cluster::new((fsm, Rc::new(vr)));
 
         let dbs0 = DivBufShared::from(vec![0u8; 4096]);
@@ -1052,11 +1581,15 @@ mod cluster {
         let db0 = dbs0.try_const().unwrap();
         let db1 = dbs1.try_const().unwrap();
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            let (lba, fut1) = cluster.write(db0, TxgT::from(0))
+            let (lba, fut1) = cluster.write(db0, WriteClass::Data,
+                                            TxgT::from(0),
+                                            PriorityClass::Normal)
                 .expect("write failed early");
             // Write a larger buffer so the first zone will get closed
             fut1.and_then(|_| {
-                let (_, fut2) = cluster.write(db1, TxgT::from(0))
+                let (_, fut2) = cluster.write(db1, WriteClass::Data,
+                                              TxgT::from(0),
+                                              PriorityClass::Normal)
                     .expect("write failed early");
                 fut2
             }).map(move|_| lba)
@@ -1064,6 +1597,91 @@ mod cluster {
                 cluster.free(lba, 1)
             })
         })).unwrap();
+        // The zone must not be erased until the label's older roots age out
+        assert!(cluster.erase_freed_zones(TxgT::from(1)).is_empty());
+        let txg = TxgT::from(1 + REWIND_TXGS as u32);
+        let futs = cluster.erase_freed_zones(txg);
+        future::join_all(futs).wait().unwrap();
+        assert!(cluster.pending_erase.borrow().is_empty());
+    }
+
+    // Space freed from zones that may hold checkpointed data is held by the
+    // checkpoint
+    #[test]
+    fn checkpointed() {
+        let mut vr = MockVdevRaid::default();
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
+        let mut fsm = FreeSpaceMap::new(10);
+        fsm.open_zone(0, 0, 10, 4, WriteClass::Data, TxgT::from(0)).unwrap();
+        fsm.free(0, 1);
+        fsm.open_zone(1, 10, 20, 4, WriteClass::Data, TxgT::from(2)).unwrap();
+        fsm.free(1, 2);
+        let cluster = Cluster::new((fsm, Rc::new(vr)));
+        assert_eq!(cluster.checkpointed(), 0);
+
+        let mut roots = cluster.roots.borrow_mut();
+        roots.push(TxgT::from(1), SpacemapRoot{spacemap: None, gap: 0});
+        roots.update_checkpoint(Some(CheckpointOp::Create));
+        drop(roots);
+        assert_eq!(cluster.checkpointed(), 1);
+
+        cluster.roots.borrow_mut()
+            .update_checkpoint(Some(CheckpointOp::Discard));
+        assert_eq!(cluster.checkpointed(), 0);
+    }
+
+    // Neither freed blocks nor the spacemap log count as live data
+    #[test]
+    fn live_data() {
+        let mut vr = MockVdevRaid::default();
+        vr.expect_lba2zone()
+            .with(eq(0))
+            .return_const(Some(0));
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
+        let mut fsm = FreeSpaceMap::new(10);
+        fsm.open_zone(0, 0, 10, 2, WriteClass::Spacemap, TxgT::from(0))
+            .unwrap();
+        fsm.open_zone(1, 10, 20, 4, WriteClass::Data, TxgT::from(0)).unwrap();
+        fsm.free(1, 1);
+        fsm.open_zone(2, 20, 30, 0, WriteClass::Data, TxgT::from(0)).unwrap();
+        fsm.finish_zone(2, TxgT::from(0));
+        let cluster = Cluster::new((fsm, Rc::new(vr)));
+        cluster.spacemap.borrow_mut().records.push(
+            SpacemapPtr{lba: 0, lbas: 2});
+        assert_eq!(cluster.live_data(), 3);
+    }
+
+    // Freed zones that may hold checkpointed data must not be erased until the
+    // checkpoint is discarded
+    #[test]
+    fn free_and_erase_checkpoint() {
+        let mut vr = MockVdevRaid::default();
+        vr.expect_erase_zone()
+            .once()
+            .with(eq(1))
+            .return_once(|_| Box::new( future::ok::<(), Error>(())));
+        let mut fsm = FreeSpaceMap::new(10);
+        fsm.open_zone(0, 0, 1, 0, WriteClass::Data, TxgT::from(0)).unwrap();
+        fsm.finish_zone(0, TxgT::from(0));
+        fsm.open_zone(1, 1, 2, 0, WriteClass::Data, TxgT::from(2)).unwrap();
+        fsm.finish_zone(1, TxgT::from(2));
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
+        let cluster = Cluster::new((fsm, Rc::new(vr)));
+        let mut roots = cluster.roots.borrow_mut();
+        roots.push(TxgT::from(1), SpacemapRoot{spacemap: None, gap: 0});
+        roots.update_checkpoint(Some(CheckpointOp::Create));
+        drop(roots);
+        cluster.pending_erase.borrow_mut().insert(0, Some(TxgT::from(2)));
+        cluster.pending_erase.borrow_mut().insert(1, Some(TxgT::from(2)));
+
+        let txg = TxgT::from(2 + REWIND_TXGS as u32);
+        let futs = cluster.erase_freed_zones(txg);
+        future::join_all(futs).wait().unwrap();
+        let pending_erase = cluster.pending_erase.borrow();
+        assert_eq!(pending_erase.keys().cloned().collect::<Vec<_>>(), vec![0]);
     }
 
     #[test]
@@ -1085,13 +1703,13 @@ mod cluster {
             .with(eq(0))
             .return_once(|_| Box::new( future::ok::<(), Error>(())));
         vr.expect_write_at()
-            .with(always(), eq(0), eq(1))
+            .with(always(), eq(0), eq(1), always())
             .once()
-            .return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
+            .return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
         vr.expect_write_at()
-            .with(always(), eq(0), eq(2))
+            .with(always(), eq(0), eq(2), always())
             .once()
-            .return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
+            .return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
 
         vr.expect_finish_zone()
             .once()
@@ -1102,11 +1720,13 @@ mod cluster {
             .with(eq(1))
             .return_once(|_| Box::new( future::ok::<(), Error>(())));
         vr.expect_write_at()
-            .with(always(), eq(1), always())
+            .with(always(), eq(1), always(), always())
             .once()
-            .return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
+            .return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
 
         let fsm = FreeSpaceMap::new(vr.zones());
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
         let cluster = Cluster::new((fsm, Rc::new(vr)));
 
         let dbs0 = DivBufShared::from(vec![0u8; 4096]);
@@ -1115,16 +1735,22 @@ mod cluster {
         let db1 = dbs0.try_const().unwrap();
         let db2 = dbs1.try_const().unwrap();
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            let (lba, fut1) = cluster.write(db0, TxgT::from(0))
+            let (lba, fut1) = cluster.write(db0, WriteClass::Data,
+                                            TxgT::from(0),
+                                            PriorityClass::Normal)
                 .expect("write failed early");
             fut1.and_then(|_| {
-                let (_, fut2) = cluster.write(db1, TxgT::from(0))
+                let (_, fut2) = cluster.write(db1, WriteClass::Data,
+                                              TxgT::from(0),
+                                              PriorityClass::Normal)
                     .expect("write failed early");
                 fut2
             })
             // Write a larger buffer so the first zone will get closed
             .and_then(|_| {
-                let (_, fut3) = cluster.write(db2, TxgT::from(0))
+                let (_, fut3) = cluster.write(db2, WriteClass::Data,
+                                              TxgT::from(0),
+                                              PriorityClass::Normal)
                     .expect("write failed early");
                 fut3
             }).map(move|_| lba)
@@ -1150,6 +1776,8 @@ mod cluster {
             .with(eq(0))
             .return_const((1, 1000));
         let fsm = FreeSpaceMap::new(vr.zones());
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
         let cluster = Cluster::new((fsm, Rc::new(vr)));
         let _ = cluster.free(900, 200);
     }
@@ -1167,6 +1795,8 @@ mod cluster {
             .with(eq(0))
             .return_const((1, 1000));
         let fsm = FreeSpaceMap::new(vr.zones());
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
         let cluster = Cluster::new((fsm, Rc::new(vr)));
         let _ = cluster.free(1000, 10);
     }
@@ -1177,66 +1807,84 @@ mod cluster {
     // An empty zone before the maximum open or full zone
     // An open zone with some freed blocks
     // A trailing empty zone
+    /// Serialize a `SpacemapRecord` into a single LBA
+    fn spacemap_record(lba: LbaT, prev: Option<SpacemapPtr>,
+                       zones: Vec<(ZoneT, ZoneOnDisk)>) -> Vec<u8>
+    {
+        let record = SpacemapRecord::new(lba, prev, zones);
+        let mut buf = bincode::serialize(&record).unwrap();
+        buf.resize(BYTES_PER_LBA, 0);
+        buf
+    }
+
+    fn zod(allocated_blocks: u32, freed_blocks: u32, start: u32, end: u32,
+           class: WriteClass) -> ZoneOnDisk
+    {
+        let txgs = TxgT::from(start)..TxgT::from(end);
+        ZoneOnDisk{allocated_blocks, freed_blocks, txgs, class}
+    }
+
+    // FreeSpaceMap::open should replay the spacemap log from oldest to newest
     #[test]
     fn freespacemap_open() {
-        // Serialized spacemap
-        const SPACEMAP: [u8; 96] = [
-            0xb2, 0x99, 0x6a, 0xb1, 0xa0, 0x6f, 0xb4, 0x9c, // Checksum
-            5, 0, 0, 0, 0, 0, 0, 0,         // 5 entries
-            255, 255, 255, 255,             // zone0: allocated_blocks
-            0, 0, 0, 0,                     // zone0: freed blocks
-            0, 0, 0, 0, 2, 0, 0, 0,         // zone0 txgs: 0..2
-            255, 255, 255, 255,             // zone1: allocated_blocks
-            22, 0, 0, 0,                    // zone1: freed blocks
-            1, 0, 0, 0, 3, 0, 0, 0,         // zone1 txgs: 1..3
-            0, 0, 0, 0,                     // zone2: allocated_blocks
-            0, 0, 0, 0,                     // zone2: freed blocks
-            0, 0, 0, 0, 0, 0, 0, 0,         // zone2 txgs: 0..0
-            77, 0, 0, 0,                    // zone3: allocated_blocks
-            33, 0, 0, 0,                    // zone3: freed blocks
-            2, 0, 0, 0, 255, 255, 255, 255, // zone3 txgs: 2..u32::MAX
-            0, 0, 0, 0,                     // zone4: allocated_blocks
-            0, 0, 0, 0,                     // zone4: freed blocks
-            0, 0, 0, 0, 0, 0, 0, 0,         // zone4 txgs: 0..0
-        ];
+        let closed = u32::max_value();
+        // Checkpoint
+        let sm0 = spacemap_record(504, None, vec![
+            (0, zod(closed, 0, 0, 2, WriteClass::Data)),
+            (1, zod(closed, 22, 1, 3, WriteClass::Data)),
+            (3, zod(77, 33, 2, u32::max_value(), WriteClass::Metadata)),
+            (5, zod(1, 0, 2, u32::max_value(), WriteClass::Spacemap)),
+        ]);
+        // Subsequent record
+        let ptr0 = SpacemapPtr{lba: 504, lbas: 1};
+        let sm1 = spacemap_record(505, Some(ptr0), vec![
+            (1, zod(closed, 30, 1, 3, WriteClass::Data)),
+            (5, zod(2, 0, 2, u32::max_value(), WriteClass::Spacemap)),
+        ]);
         let mut vr = MockVdevRaid::default();
         vr.expect_zones()
-            .return_const(5u32);
-        vr.expect_read_spacemap()
-            .with(always(), eq(0))
+            .return_const(6u32);
+        vr.expect_read_at()
+            .with(always(), eq(505), always())
             .once()
-            .returning(|mut dbm, _idx| {
-                 assert_eq!(dbm.len(), BYTES_PER_LBA);
-                 dbm[0..96].copy_from_slice(&SPACEMAP[..]);
-                 dbm[96..4096].iter_mut().set_from(iter::repeat(0));
+            .return_once(move |mut dbm, _, _| {
+                 dbm.copy_from_slice(&sm1[..]);
                  Box::new(future::ok::<(), Error>(()))
             });
-
+        vr.expect_read_at()
+            .with(always(), eq(504), always())
+            .once()
+            .return_once(move |mut dbm, _, _| {
+                 dbm.copy_from_slice(&sm0[..]);
+                 Box::new(future::ok::<(), Error>(()))
+            });
+        vr.expect_lba2zone()
+            .return_const(Some(5));
         vr.expect_reopen_zone()
             .once()
             .with(eq(3), eq(77))
             .return_once(|_, _| Box::new(Ok(()).into_future()));
+        // The gap after the newest record comes from the label
+        vr.expect_reopen_zone()
+            .once()
+            .with(eq(5), eq(5))
+            .return_once(|_, _| Box::new(Ok(()).into_future()));
         vr.expect_zone_limits()
-            .with(eq(0))
-            .return_const((4, 96));
-        vr.expect_zone_limits()
-            .with(eq(1))
-            .return_const((104, 196));
-        vr.expect_zone_limits()
-            .with(eq(2))
-            .return_const((204, 296));
-        vr.expect_zone_limits()
-            .with(eq(3))
-            .return_const((304, 396));
-        vr.expect_zone_limits()
-            .with(eq(4))
-            .return_const((404, 496));
-        let (fsm, _mock_vr) = FreeSpaceMap::open(Rc::new(vr)).wait().unwrap();
-        assert_eq!(fsm.zones.len(), 4);
+             .returning(|zid: ZoneT| {
+                 let i = LbaT::from(zid);
+                 (100 * i + 4, 100 * i + 96)
+             });
+        let root = SpacemapRoot{
+            spacemap: Some(SpacemapPtr{lba: 505, lbas: 1}),
+            gap: 3
+        };
+        let (fsm, sm, _mock_vr) = FreeSpaceMap::open(Rc::new(vr), root, false)
+            .wait().unwrap();
+        assert_eq!(fsm.zones.len(), 6);
         assert_eq!(fsm.zones[0].freed_blocks, 0);
         assert_eq!(fsm.zones[0].total_blocks, 92);
         assert_eq!(fsm.zones[0].txgs, TxgT::from(0)..TxgT::from(2));
-        assert_eq!(fsm.zones[1].freed_blocks, 22);
+        assert_eq!(fsm.zones[1].freed_blocks, 30);
         assert_eq!(fsm.zones[1].total_blocks, 92);
         assert_eq!(fsm.zones[1].txgs, TxgT::from(1)..TxgT::from(3));
         assert!(fsm.is_empty(2));
@@ -1246,85 +1894,85 @@ mod cluster {
         let oz = &fsm.open_zones[&3];
         assert_eq!(oz.start, 304);
         assert_eq!(oz.allocated_blocks, 77);
+        // Reopened zones keep their classes
+        assert_eq!(oz.class, WriteClass::Metadata);
         assert!(fsm.is_empty(4));
-        assert_eq!(0, fsm.dirty.count_ones(..));
-    }
-
-    // FreeSpaceMap::open with more zones that can fit into a single block
-    #[test]
-    fn freespacemap_open_300_zones() {
-        // Serialized spacemap
-        const SPACEMAP_B0: [u8; 32] = [
-            0x5e, 0xe9, 0x96, 0x17, 0xc2, 0xfe, 0xa0, 0x8e, // Checksum
-            255, 0, 0, 0, 0, 0, 0, 0,       // 255 entries
-            255, 255, 255, 255,             // zone0: allocated_blocks
-            0, 0, 0, 0,                     // zone0: freed blocks
-            0, 0, 0, 0, 2, 0, 0, 0,         // zone0 txgs: 0..2
-            // Rest is all 0, indicating empty zones
-        ];
-        const SPACEMAP_B1: [u8; 32] = [
-            0x07, 0x5e, 0x95, 0xf0, 0x93, 0x3f, 0xa7, 0xb0, // Checksum
-            45, 0, 0, 0, 0, 0, 0, 0,        // 45 entries
-            255, 255, 255, 255,             // zone255: allocated_blocks
-            1, 0, 0, 0,                     // zone255: freed blocks
-            3, 0, 0, 0, 4, 0, 0, 0,         // zone255 txgs: 3..4
-            // Rest is all 0, indicating empty zones
-        ];
+        assert_eq!(fsm.zones[5].freed_blocks, 3);
+        let oz = &fsm.open_zones[&5];
+        assert_eq!(oz.allocated_blocks, 5);
+        assert_eq!(oz.class, WriteClass::Spacemap);
+        assert!(fsm.dirty.is_empty());
+        assert_eq!(sm.records, vec![ptr0, SpacemapPtr{lba: 505, lbas: 1}]);
+        assert_eq!(sm.gap, 3);
+    }
+
+    // A readonly FreeSpaceMap::open should reopen zones in memory only, without
+    // any I/O to the disks
+    #[test]
+    fn freespacemap_open_readonly() {
+        let sm0 = spacemap_record(504, None, vec![
+            (3, zod(77, 33, 2, u32::max_value(), WriteClass::Metadata)),
+        ]);
         let mut vr = MockVdevRaid::default();
         vr.expect_zones()
-            .return_const(300u32);
-        vr.expect_read_spacemap()
-            .with(always(), eq(0))
+            .return_const(6u32);
+        vr.expect_read_at()
+            .with(always(), eq(504), always())
             .once()
-            .returning(|mut dbm, _idx| {
-                assert_eq!(dbm.len(), 8192);
-                dbm[0..32].copy_from_slice(&SPACEMAP_B0[..]);
-                dbm[32..4096].iter_mut().set_from(iter::repeat(0));
-                dbm[4096..4128].copy_from_slice(&SPACEMAP_B1[..]);
-                dbm[4192..8192].iter_mut().set_from(iter::repeat(0));
-                Box::new(future::ok::<(), Error>(()))
+            .return_once(move |mut dbm, _, _| {
+                 dbm.copy_from_slice(&sm0[..]);
+                 Box::new(future::ok::<(), Error>(()))
             });
-
+        vr.expect_lba2zone()
+            .return_const(Some(5));
+        vr.expect_reopen_zone()
+            .never();
         vr.expect_zone_limits()
              .returning(|zid: ZoneT| {
                  let i = LbaT::from(zid);
                  (100 * i + 4, 100 * i + 96)
              });
+        let root = SpacemapRoot{
+            spacemap: Some(SpacemapPtr{lba: 504, lbas: 1}),
+            gap: 0
+        };
+        let (fsm, _sm, _mock_vr) = FreeSpaceMap::open(Rc::new(vr), root, true)
+            .wait().unwrap();
+        let oz = &fsm.open_zones[&3];
+        assert_eq!(oz.allocated_blocks, 77);
+        assert_eq!(oz.class, WriteClass::Metadata);
+        assert_eq!(fsm.zones[3].freed_blocks, 33);
+    }
 
-        let (fsm, _mock_vr) = FreeSpaceMap::open(Rc::new(vr)).wait().unwrap();
-        assert_eq!(fsm.zones.len(), 256);
-        assert_eq!(fsm.zones[0].freed_blocks, 0);
-        assert_eq!(fsm.zones[0].total_blocks, 92);
-        assert_eq!(fsm.zones[0].txgs, TxgT::from(0)..TxgT::from(2));
-        assert!(fsm.is_empty(1));
-        assert!(fsm.is_empty(254));
-        assert_eq!(fsm.zones[255].freed_blocks, 1);
-        assert_eq!(fsm.zones[255].total_blocks, 92);
-        assert_eq!(fsm.zones[255].txgs, TxgT::from(3)..TxgT::from(4));
-        assert!(fsm.is_empty(256));
+    // A Cluster that was never flushed has no spacemap at all
+    #[test]
+    fn freespacemap_open_no_spacemap() {
+        let mut vr = MockVdevRaid::default();
+        vr.expect_zones()
+            .return_const(300u32);
+        let root = SpacemapRoot{spacemap: None, gap: 0};
+        let (fsm, sm, _mock_vr) = FreeSpaceMap::open(Rc::new(vr), root, false)
+            .wait().unwrap();
+        assert!(fsm.is_empty(0));
         assert!(fsm.is_empty(299));
+        assert!(sm.records.is_empty());
     }
 
     #[test]
     fn freespacemap_open_ecksum() {
-        // Serialized spacemap
-        const SPACEMAP: [u8; 16] = [
-            0, 0, 0, 0, 0, 0, 0, 0,         // Checksum
-            0, 0, 0, 0, 0, 0, 0, 0,         // 0 entries
-        ];
         let mut vr = MockVdevRaid::default();
         vr.expect_zones()
-            .return_const(0u32);
-        vr.expect_read_spacemap()
-            .with(always(), eq(0))
+            .return_const(10u32);
+        vr.expect_read_at()
+            .with(always(), eq(4), always())
             .once()
-            .returning(|mut dbm, _idx| {
-                dbm.try_truncate(0).unwrap();
-                dbm.extend(SPACEMAP.iter());
+            .return_once(|mut dbm, _, _| {
+                dbm.iter_mut().set_from(iter::repeat(0));
                 Box::new(future::ok::<(), Error>(()))
             });
-
-        let r = FreeSpaceMap::open(Rc::new(vr)).wait();
+        let root = SpacemapRoot{spacemap: Some(SpacemapPtr{lba: 4, lbas: 1}),
+                                gap: 0};
+        let r = FreeSpaceMap::open(Rc::new(vr), root, false).wait();
         assert_eq!(Error::ECKSUM, r.err().unwrap());
     }
 
@@ -1344,13 +1992,15 @@ mod cluster {
             .with(eq(4))
             .return_const((4, 5));
         let mut fsm = FreeSpaceMap::new(10);
-        fsm.open_zone(0, 0, 1, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(0, 0, 1, 0, WriteClass::Data, TxgT::from(0)).unwrap();
         fsm.finish_zone(0, TxgT::from(0));
-        fsm.open_zone(2, 2, 3, 0, TxgT::from(0)).unwrap();
-        fsm.open_zone(3, 3, 4, 0, TxgT::from(1)).unwrap();
+        fsm.open_zone(2, 2, 3, 0, WriteClass::Data, TxgT::from(0)).unwrap();
+        fsm.open_zone(3, 3, 4, 0, WriteClass::Data, TxgT::from(1)).unwrap();
         fsm.finish_zone(3, TxgT::from(3));
-        fsm.open_zone(4, 4, 5, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(4, 4, 5, 0, WriteClass::Data, TxgT::from(0)).unwrap();
         fsm.finish_zone(4, TxgT::from(0));
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
         let cluster = Cluster::new((fsm, Rc::new(vr)));
         assert_eq!(cluster.find_closed_zone(0).unwrap(),
             ClosedZone{zid: 0, start: 0, freed_blocks: 1, total_blocks: 1,
@@ -1364,6 +2014,124 @@ mod cluster {
         assert!(cluster.find_closed_zone(5).is_none());
     }
 
+    // Cluster::find_closed_zone should skip zones that may hold checkpointed
+    // data
+    #[test]
+    fn find_closed_zone_checkpoint() {
+        let mut vr = MockVdevRaid::default();
+        vr.expect_zone_limits()
+            .with(eq(1))
+            .return_const((1, 2));
+        let mut fsm = FreeSpaceMap::new(10);
+        fsm.open_zone(0, 0, 1, 0, WriteClass::Data, TxgT::from(0)).unwrap();
+        fsm.finish_zone(0, TxgT::from(0));
+        fsm.open_zone(1, 1, 2, 0, WriteClass::Data, TxgT::from(2)).unwrap();
+        fsm.finish_zone(1, TxgT::from(2));
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
+        let cluster = Cluster::new((fsm, Rc::new(vr)));
+        let mut roots = cluster.roots.borrow_mut();
+        roots.push(TxgT::from(1), SpacemapRoot{spacemap: None, gap: 0});
+        roots.update_checkpoint(Some(CheckpointOp::Create));
+        drop(roots);
+        assert_eq!(cluster.find_closed_zone(0).unwrap().zid, 1);
+    }
+
+    // Cluster::find_closed_zone should skip zones that hold the spacemap log
+    #[test]
+    fn find_closed_zone_spacemap() {
+        let mut vr = MockVdevRaid::default();
+        vr.expect_lba2zone()
+            .with(eq(0))
+            .return_const(Some(0));
+        vr.expect_zone_limits()
+            .with(eq(1))
+            .return_const((1, 2));
+        let mut fsm = FreeSpaceMap::new(10);
+        fsm.open_zone(0, 0, 1, 0, WriteClass::Spacemap, TxgT::from(0))
+            .unwrap();
+        fsm.finish_zone(0, TxgT::from(0));
+        fsm.open_zone(1, 1, 2, 0, WriteClass::Data, TxgT::from(0)).unwrap();
+        fsm.finish_zone(1, TxgT::from(0));
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
+        let cluster = Cluster::new((fsm, Rc::new(vr)));
+        cluster.spacemap.borrow_mut().records.push(
+            SpacemapPtr{lba: 0, lbas: 1});
+        assert_eq!(cluster.find_closed_zone(0).unwrap().zid, 1);
+    }
+
+    // Cluster::finish_zones should finish all open zones, of every class
+    #[test]
+    fn finish_zones() {
+        let mut vr = MockVdevRaid::default();
+        vr.expect_finish_zone()
+            .once()
+            .with(eq(1))
+            .return_once(|_| Box::new(future::ok::<(), Error>(())));
+        vr.expect_finish_zone()
+            .once()
+            .with(eq(2))
+            .return_once(|_| Box::new(future::ok::<(), Error>(())));
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
+        let mut fsm = FreeSpaceMap::new(10);
+        fsm.open_zone(0, 0, 1, 0, WriteClass::Data, TxgT::from(0)).unwrap();
+        fsm.finish_zone(0, TxgT::from(0));
+        fsm.open_zone(1, 1, 2, 0, WriteClass::Data, TxgT::from(0)).unwrap();
+        fsm.open_zone(2, 2, 3, 0, WriteClass::Metadata, TxgT::from(0))
+            .unwrap();
+        let cluster = Cluster::new((fsm, Rc::new(vr)));
+        cluster.finish_zones(TxgT::from(1)).wait().unwrap();
+        assert_eq!(cluster.fsm.borrow().open_zone_ids().count(), 0);
+    }
+
+    // After a rewind, Cluster::reset_rewound_zones should erase every empty
+    // zone and finish every open one, since they may have been written since
+    #[test]
+    fn reset_rewound_zones() {
+        let mut vr = MockVdevRaid::default();
+        vr.expect_zones()
+            .return_const(4u32);
+        vr.expect_erase_zone()
+            .once()
+            .with(eq(2))
+            .return_once(|_| Box::new(future::ok::<(), Error>(())));
+        vr.expect_erase_zone()
+            .once()
+            .with(eq(3))
+            .return_once(|_| Box::new(future::ok::<(), Error>(())));
+        vr.expect_finish_zone()
+            .once()
+            .with(eq(1))
+            .return_once(|_| Box::new(future::ok::<(), Error>(())));
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
+        let mut fsm = FreeSpaceMap::new(4);
+        fsm.open_zone(0, 0, 1, 0, WriteClass::Data, TxgT::from(0)).unwrap();
+        fsm.finish_zone(0, TxgT::from(0));
+        fsm.open_zone(1, 1, 2, 0, WriteClass::Data, TxgT::from(0)).unwrap();
+        let cluster = Cluster::new((fsm, Rc::new(vr)));
+        cluster.reset_rewound_zones(TxgT::from(1)).wait().unwrap();
+        assert_eq!(cluster.fsm.borrow().open_zone_ids().count(), 0);
+    }
+
+    // A label that can't be decoded should fail the import, not panic
+    #[test]
+    fn open_bad_label() {
+        let vr = MockVdevRaid::default();
+        let lw = LabelWriter::new(0, TxgT::from(0));
+        let v = lw.into_sglist().unwrap().iter()
+            .fold(Vec::new(), |mut acc, db| {
+                acc.extend_from_slice(&db[..]);
+                acc
+            });
+        let lr = LabelReader::from_dbs(DivBufShared::from(v)).unwrap();
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let r = rt.block_on(Cluster::open(Rc::new(vr), lr, false));
+        assert_eq!(r.err().unwrap(), Error::EINVAL);
+    }
+
     // VdevRaid::write_at must be called synchronously with Cluster::write, even
     // if opening a zone is slow.
     #[test]
@@ -1379,23 +2147,98 @@ mod cluster {
             .with(eq(0))
             .return_once(|_| Box::new( future::ok::<(), Error>(())));
         vr.expect_write_at()
-            .withf(|buf, zone, lba|
+            .withf(|buf, zone, lba, _|
                 buf.len() == BYTES_PER_LBA &&
                 *zone == 0 &&
                 *lba == 0
             ).once()
-            .return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
+            .return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
         let fsm = FreeSpaceMap::new(vr.zones());
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
         let cluster = Cluster::new((fsm, Rc::new(vr)));
 
         let dbs = DivBufShared::from(vec![0u8; 4096]);
         let db0 = dbs.try_const().unwrap();
-        let _ = cluster.write(db0, TxgT::from(0)).expect("write failed early");
+        let _ = cluster.write(db0, WriteClass::Data, TxgT::from(0),
+                              PriorityClass::Normal)
+            .expect("write failed early");
+    }
+
+    // Cluster::flush shouldn't write the spacemap if nothing changed
+    #[test]
+    fn flush_clean() {
+        let mut vr = MockVdevRaid::default();
+        vr.expect_zones()
+            .return_const(100u32);
+        vr.expect_flush_zone()
+            .once()
+            .with(eq(0))
+            .return_once(|_| (0, Box::new(future::ok::<(), Error>(()))));
+        let mut fsm = FreeSpaceMap::new(vr.zones());
+        fsm.open_zone(0, 0, 1000, 0, WriteClass::Data, TxgT::from(0)).unwrap();
+        fsm.clear_dirty_zones();
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
+        let cluster = Cluster::new((fsm, Rc::new(vr)));
+        cluster.flush(TxgT::from(0)).wait().unwrap();
+        assert!(cluster.spacemap.borrow().records.is_empty());
+    }
+
+    // Once a checkpoint's transaction is committed, Cluster::flush should free
+    // the records that it superseded
+    #[test]
+    fn flush_frees_obsolete() {
+        let mut vr = MockVdevRaid::default();
+        vr.expect_zones()
+            .return_const(100u32);
+        vr.expect_lba2zone()
+            .with(eq(1000))
+            .return_const(Some(1));
+        let mut fsm = FreeSpaceMap::new(vr.zones());
+        fsm.open_zone(1, 1000, 2000, 1, WriteClass::Spacemap, TxgT::from(0))
+            .unwrap();
+        fsm.finish_zone(1, TxgT::from(0));
+        fsm.clear_dirty_zones();
+        vr.expect_zone_limits()
+            .returning(|zid| {
+                let i = LbaT::from(zid);
+                (1000 * i, 1000 * i + 1000)
+            });
+        vr.expect_open_zone()
+            .once()
+            .return_once(|_| Box::new(future::ok::<(), Error>(())));
+        vr.expect_write_at()
+            .once()
+            .return_once(|_, _, _, _| Box::new(future::ok::<(), Error>(())));
+        vr.expect_flush_zone()
+            .once()
+            .return_once(|_| (0, Box::new(future::ok::<(), Error>(()))));
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
+        let cluster = Cluster::new((fsm, Rc::new(vr)));
+        {
+            let mut sm = cluster.spacemap.borrow_mut();
+            sm.obsolete.push(SpacemapPtr{lba: 1000, lbas: 1});
+            sm.checkpoint_txg = TxgT::from(1);
+        }
+        // Not yet; the checkpoint isn't committed
+        cluster.flush(TxgT::from(1)).wait().unwrap();
+        assert_eq!(cluster.spacemap.borrow().obsolete.len(), 1);
+
+        // Freeing the zone dirties it, so the flush must write a record, too.
+        // But the zone itself won't be erased for a few more transactions.
+        cluster.flush(TxgT::from(2)).wait().unwrap();
+        let sm = cluster.spacemap.borrow();
+        assert!(sm.obsolete.is_empty());
+        assert_eq!(sm.records.len(), 1);
+        assert_eq!(cluster.pending_erase.borrow().get(&1),
+                   Some(&Some(TxgT::from(2))));
     }
 
     // During transaction sync, Cluster::flush should flush all open VdevRaid
-    // zones and write the spacemap.  Then Cluster.sync_all should sync_all the
-    // VdevRaid
+    // zones and append a record to the spacemap log.  Then Cluster.sync_all
+    // should sync_all the VdevRaid
     #[test]
     fn txg_sync() {
         let mut seq = Sequence::new();
@@ -1405,6 +2248,9 @@ mod cluster {
         vr.expect_zone_limits()
             .with(eq(0))
             .return_const((0, 1000));
+        vr.expect_zone_limits()
+            .with(eq(1))
+            .return_const((1000, 2000));
         vr.expect_open_zone()
             .once()
             .in_sequence(&mut seq)
@@ -1413,44 +2259,69 @@ mod cluster {
         vr.expect_write_at()
             .once()
             .in_sequence(&mut seq)
-            .withf(|buf, zone, lba|
+            .withf(|buf, zone, lba, _|
                 buf.len() == BYTES_PER_LBA &&
                 *zone == 0 &&
                 *lba == 0
-            ).return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
+            ).return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
         vr.expect_flush_zone()
             .once()
             .in_sequence(&mut seq)
             .with(eq(0))
             .return_once(|_| (5, Box::new(future::ok::<(), Error>(()))));
-        vr.expect_write_spacemap()
+        vr.expect_open_zone()
+            .once()
+            .in_sequence(&mut seq)
+            .with(eq(1))
+            .return_once(|_| Box::new( future::ok::<(), Error>(())));
+        vr.expect_write_at()
+            .once()
+            .in_sequence(&mut seq)
+            .withf(|buf, zone, lba, _| {
+                let record = SpacemapRecord::deserialize(*lba, buf).unwrap();
+                buf.len() == BYTES_PER_LBA &&
+                *zone == 1 &&
+                *lba == 1000 &&
+                record.prev.is_none() &&
+                record.zones.len() == 2 &&
+                record.zones[0].0 == 0 &&
+                record.zones[0].1.allocated_blocks == 6 &&
+                record.zones[0].1.freed_blocks == 5 &&
+                record.zones[1].0 == 1 &&
+                record.zones[1].1.allocated_blocks == 1
+            }).return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
+        vr.expect_flush_zone()
             .once()
             .in_sequence(&mut seq)
-            .withf(|sglist, idx, block|
-                sglist.iter().map(DivBuf::len).sum::<usize>() == 4096 &&
-                *idx == 0 &&
-                *block == 0
-            ).return_once(|_, _, _| Box::new(future::ok::<(), Error>(())));
+            .with(eq(1))
+            .return_once(|_| (3, Box::new(future::ok::<(), Error>(()))));
         vr.expect_sync_all()
             .once()
             .in_sequence(&mut seq)
             .return_once(|| Box::new(future::ok::<(), Error>(())));
         let fsm = FreeSpaceMap::new(vr.zones());
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
         let cluster = Cluster::new((fsm, Rc::new(vr)));
-        cluster.fsm.borrow_mut().clear_dirty_zones();
 
         let dbs = DivBufShared::from(vec![0u8; 4096]);
         let db0 = dbs.try_const().unwrap();
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            let (_, fut) = cluster.write(db0, TxgT::from(0))
+            let (_, fut) = cluster.write(db0, WriteClass::Data, TxgT::from(0),
+                                         PriorityClass::Normal)
                 .expect("write failed early");
-            fut.and_then(|_| cluster.flush(0))
+            fut.and_then(|_| cluster.flush(TxgT::from(0)))
             .and_then(|_| cluster.sync_all())
         })).unwrap();
         let fsm = cluster.fsm.borrow();
         assert_eq!(fsm.open_zones[&0].write_pointer(), 6);
         assert_eq!(fsm.zones[0].freed_blocks, 5);
-        assert_eq!(0, fsm.dirty.count_ones(..));
+        assert_eq!(fsm.open_zones[&1].write_pointer(), 1004);
+        assert_eq!(fsm.zones[1].freed_blocks, 3);
+        assert!(fsm.dirty.is_empty());
+        let sm = cluster.spacemap.borrow();
+        assert_eq!(sm.records, vec![SpacemapPtr{lba: 1000, lbas: 1}]);
+        assert_eq!(sm.gap, 3);
     }
 
     #[test]
@@ -1459,15 +2330,17 @@ mod cluster {
         vr.expect_zones()
             .return_const(32768u32);
         vr.expect_zone_limits()
-            .with(eq(0))
             .return_const((0, 1));
         let fsm = FreeSpaceMap::new(vr.zones());
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
         let cluster = Cluster::new((fsm, Rc::new(vr)));
 
         let dbs = DivBufShared::from(vec![0u8; 8192]);
         let mut rt = current_thread::Runtime::new().unwrap();
         let result = rt.block_on(future::lazy(|| {
-            cluster.write(dbs.try_const().unwrap(), TxgT::from(0))
+            cluster.write(dbs.try_const().unwrap(), WriteClass::Data,
+                          TxgT::from(0), PriorityClass::Normal)
         }));
         assert_eq!(result.err().unwrap(), Error::ENOSPC);
     }
@@ -1482,13 +2355,115 @@ mod cluster {
             .with(eq(0))
             .return_const((0, 0));
         let fsm = FreeSpaceMap::new(vr.zones());
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
         let cluster = Cluster::new((fsm, Rc::new(vr)));
 
         let dbs = DivBufShared::from(vec![0u8; 4096]);
-        let result = cluster.write(dbs.try_const().unwrap(), TxgT::from(0));
+        let result = cluster.write(dbs.try_const().unwrap(), WriteClass::Data,
+                                   TxgT::from(0), PriorityClass::Normal);
         assert_eq!(result.err().unwrap(), Error::ENOSPC);
     }
 
+    // Clusters too small for a full share of slop should still reserve one zone
+    #[test]
+    fn reserved_small_cluster() {
+        let mut vr = MockVdevRaid::default();
+        vr.expect_zones()
+            .return_const(8u32);
+        vr.expect_size()
+            .return_const(8_000u64);
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
+        let fsm = FreeSpaceMap::new(vr.zones());
+        let cluster = Cluster::new((fsm, Rc::new(vr)));
+        assert_eq!(cluster.reserved(), 1000);
+    }
+
+    // Ordinary data writes may not open the last few empty zones, but tree
+    // nodes written by a transaction sync may
+    #[test]
+    fn write_slop() {
+        let mut vr = MockVdevRaid::default();
+        vr.expect_zones()
+            .return_const(32u32);
+        vr.expect_size()
+            .return_const(32_000u64);
+        vr.expect_zone_limits()
+            .with(eq(31))
+            .return_const((31_000, 32_000));
+        vr.expect_open_zone()
+            .once()
+            .with(eq(31))
+            .return_once(|_| Box::new(future::ok::<(), Error>(())));
+        vr.expect_write_at()
+            .once()
+            .with(always(), eq(31), eq(31_000), always())
+            .return_once(|_, _, _, _| Box::new(future::ok::<(), Error>(())));
+        let mut fsm = FreeSpaceMap::new(vr.zones());
+        for zid in 0..31 {
+            let start = 1000 * LbaT::from(zid);
+            fsm.open_zone(zid, start, start + 1000, 0, WriteClass::Data,
+                          TxgT::from(0)).unwrap();
+            fsm.finish_zone(zid, TxgT::from(0));
+        }
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
+        let cluster = Cluster::new((fsm, Rc::new(vr)));
+        assert_eq!(cluster.reserved(), 1000);
+
+        let dbs = DivBufShared::from(vec![0u8; 4096]);
+        let db0 = dbs.try_const().unwrap();
+        let db1 = dbs.try_const().unwrap();
+        let r = cluster.write(db0, WriteClass::Data, TxgT::from(0),
+                              PriorityClass::Normal);
+        assert_eq!(r.err().unwrap(), Error::ENOSPC);
+        let (lba, _) = cluster.write(db1, WriteClass::Metadata, TxgT::from(0),
+                                     PriorityClass::Normal).unwrap();
+        assert_eq!(lba, 31_000);
+    }
+
+    // Zones may differ in size.  A write that doesn't fit in the first empty
+    // zone should open a bigger one.
+    #[test]
+    fn write_first_empty_zone_too_small() {
+        let mut vr = MockVdevRaid::default();
+        vr.expect_zones()
+            .return_const(3u32);
+        vr.expect_zone_limits()
+            .with(eq(0))
+            .return_const((0, 1));
+        vr.expect_zone_limits()
+            .with(eq(1))
+            .return_const((1, 1001));
+        vr.expect_open_zone()
+            .once()
+            .with(eq(1))
+            .return_once(|_| Box::new( future::ok::<(), Error>(())));
+        vr.expect_write_at()
+            .withf(|buf, zone, lba, _|
+                buf.len() == 2 * BYTES_PER_LBA &&
+                *zone == 1 &&
+                *lba == 1
+            ).once()
+            .return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
+        let fsm = FreeSpaceMap::new(vr.zones());
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
+        let cluster = Cluster::new((fsm, Rc::new(vr)));
+
+        let dbs = DivBufShared::from(vec![0u8; 8192]);
+        let db0 = dbs.try_const().unwrap();
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let result = rt.block_on(future::lazy(|| {
+            let (lba, fut) = cluster.write(db0, WriteClass::Data, TxgT::from(0),
+                                           PriorityClass::Normal)
+                .expect("write failed early");
+            fut.map(move |_| lba)
+        }));
+        assert_eq!(result.unwrap(), 1);
+    }
+
     #[test]
     fn write_with_no_open_zones() {
         let mut vr = MockVdevRaid::default();
@@ -1502,20 +2477,23 @@ mod cluster {
             .with(eq(0))
             .return_once(|_| Box::new( future::ok::<(), Error>(())));
         vr.expect_write_at()
-            .withf(|buf, zone, lba|
+            .withf(|buf, zone, lba, _|
                 buf.len() == BYTES_PER_LBA &&
                 *zone == 0 &&
                 *lba == 0
             ).once()
-            .return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
+            .return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
         let fsm = FreeSpaceMap::new(vr.zones());
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
         let cluster = Cluster::new((fsm, Rc::new(vr)));
 
         let dbs = DivBufShared::from(vec![0u8; 4096]);
         let db0 = dbs.try_const().unwrap();
         let mut rt = current_thread::Runtime::new().unwrap();
         let result = rt.block_on(future::lazy(|| {
-            let (lba, fut) = cluster.write(db0, TxgT::from(0))
+            let (lba, fut) = cluster.write(db0, WriteClass::Data, TxgT::from(0),
+                                           PriorityClass::Normal)
                 .expect("write failed early");
             fut.map(move |_| lba)
         }));
@@ -1535,20 +2513,22 @@ mod cluster {
             .with(eq(0))
             .return_once(|_| Box::new( future::ok::<(), Error>(())));
         vr.expect_write_at()
-            .withf(|buf, zone, lba|
+            .withf(|buf, zone, lba, _|
                 buf.len() == BYTES_PER_LBA &&
                 *zone == 0 &&
                 *lba == 0
             ).once()
-            .return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
+            .return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
         vr.expect_write_at()
-            .withf(|buf, zone, lba|
+            .withf(|buf, zone, lba, _|
                 buf.len() == BYTES_PER_LBA &&
                 *zone == 0 &&
                 *lba == 1
             ).once()
-            .return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
+            .return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
         let fsm = FreeSpaceMap::new(vr.zones());
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
         let cluster = Cluster::new((fsm, Rc::new(vr)));
 
         let dbs = DivBufShared::from(vec![0u8; 4096]);
@@ -1556,10 +2536,13 @@ mod cluster {
         let db1 = dbs.try_const().unwrap();
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
             let cluster_ref = &cluster;
-            let (_, fut0) = cluster.write(db0, TxgT::from(0))
+            let (_, fut0) = cluster.write(db0, WriteClass::Data, TxgT::from(0),
+                                          PriorityClass::Normal)
                 .expect("Cluster::write");
             fut0.and_then(move |_| {
-                let (lba1, fut1) = cluster_ref.write(db1, TxgT::from(0))
+                let (lba1, fut1) = cluster_ref.write(db1, WriteClass::Data,
+                                                     TxgT::from(0),
+                                                     PriorityClass::Normal)
                     .expect("Cluster::write");
                 assert_eq!(lba1, 1);
                 fut1
@@ -1567,6 +2550,194 @@ mod cluster {
         })).expect("write failed");
     }
 
+    // Writes of different classes should go to different zones, even when the
+    // first zone still has room
+    #[test]
+    fn write_different_classes() {
+        let mut vr = MockVdevRaid::default();
+        vr.expect_zones()
+            .return_const(32768u32);
+        vr.expect_zone_limits()
+            .with(eq(0))
+            .return_const((0, 1000));
+        vr.expect_zone_limits()
+            .with(eq(1))
+            .return_const((1000, 2000));
+        vr.expect_open_zone()
+            .once()
+            .with(eq(0))
+            .return_once(|_| Box::new( future::ok::<(), Error>(())));
+        vr.expect_open_zone()
+            .once()
+            .with(eq(1))
+            .return_once(|_| Box::new( future::ok::<(), Error>(())));
+        vr.expect_write_at()
+            .withf(|buf, zone, lba, _|
+                buf.len() == BYTES_PER_LBA &&
+                *zone == 0 &&
+                *lba == 0
+            ).once()
+            .return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
+        vr.expect_write_at()
+            .withf(|buf, zone, lba, _|
+                buf.len() == BYTES_PER_LBA &&
+                *zone == 1 &&
+                *lba == 1000
+            ).once()
+            .return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
+        let fsm = FreeSpaceMap::new(vr.zones());
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
+        let cluster = Cluster::new((fsm, Rc::new(vr)));
+
+        let dbs = DivBufShared::from(vec![0u8; 4096]);
+        let db0 = dbs.try_const().unwrap();
+        let db1 = dbs.try_const().unwrap();
+        current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
+            let cluster_ref = &cluster;
+            let (lba0, fut0) = cluster.write(db0, WriteClass::Metadata,
+                                             TxgT::from(0),
+                                             PriorityClass::Normal)
+                .expect("Cluster::write");
+            assert_eq!(lba0, 0);
+            fut0.and_then(move |_| {
+                let (lba1, fut1) = cluster_ref.write(db1, WriteClass::Data,
+                                                     TxgT::from(0),
+                                                     PriorityClass::Normal)
+                    .expect("Cluster::write");
+                assert_eq!(lba1, 1000);
+                fut1
+            })
+        })).expect("write failed");
+    }
+
+    // Cluster should never open more zones than the vdev allows.  When there
+    // are more classes than open zones, some must share.
+    #[test]
+    fn write_max_open_zones() {
+        use std::sync::{Arc, atomic::{AtomicU32, Ordering}};
+
+        const MAX_OPEN_ZONES: u32 = 2;
+        let open_zones = Arc::new(AtomicU32::new(0));
+        let open_zones2 = open_zones.clone();
+        let open_zones3 = open_zones.clone();
+        let mut vr = MockVdevRaid::default();
+        vr.expect_zones()
+            .return_const(32768u32);
+        vr.expect_zone_limits()
+            .returning(|z| (LbaT::from(z) * 1000, LbaT::from(z + 1) * 1000));
+        vr.expect_max_open_zones()
+            .return_const(Some(MAX_OPEN_ZONES));
+        vr.expect_open_zone()
+            .times(2)
+            .returning(move |_| {
+                let n = open_zones2.fetch_add(1, Ordering::Relaxed) + 1;
+                assert!(n <= MAX_OPEN_ZONES, "Too many open zones");
+                Box::new( future::ok::<(), Error>(()))
+            });
+        // No zone should be finished while it still has room
+        vr.expect_finish_zone()
+            .times(0)
+            .returning(move |_| {
+                open_zones3.fetch_sub(1, Ordering::Relaxed);
+                Box::new( future::ok::<(), Error>(()))
+            });
+        vr.expect_write_at()
+            .times(6)
+            .returning(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
+        let fsm = FreeSpaceMap::new(vr.zones());
+        let cluster = Cluster::new((fsm, Rc::new(vr)));
+
+        let dbs = DivBufShared::from(vec![0u8; 4096]);
+        let classes = [WriteClass::Metadata, WriteClass::Data,
+                       WriteClass::Relocation];
+        current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
+            let futs = classes.iter().cycle().take(6).map(|&class| {
+                let db = dbs.try_const().unwrap();
+                cluster.write(db, class, TxgT::from(0), PriorityClass::Normal)
+                    .expect("Cluster::write")
+                    .1
+            }).collect::<Vec<_>>();
+            future::join_all(futs)
+        })).expect("write failed");
+        assert_eq!(open_zones.load(Ordering::Relaxed), MAX_OPEN_ZONES);
+    }
+
+    // When every open zone is too full and no more may be opened, Cluster
+    // should finish whichever open zone is least useful.
+    #[test]
+    fn write_max_open_zones_all_full() {
+        let mut vr = MockVdevRaid::default();
+        vr.expect_zones()
+            .return_const(32768u32);
+        vr.expect_zone_limits()
+            .returning(|z| (LbaT::from(z) * 2, LbaT::from(z + 1) * 2));
+        vr.expect_max_open_zones()
+            .return_const(Some(2));
+        vr.expect_open_zone()
+            .times(3)
+            .returning(|_| Box::new( future::ok::<(), Error>(())));
+        vr.expect_finish_zone()
+            .with(eq(0))
+            .once()
+            .returning(|_| Box::new( future::ok::<(), Error>(())));
+        vr.expect_write_at()
+            .times(3)
+            .returning(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
+        let fsm = FreeSpaceMap::new(vr.zones());
+        let cluster = Cluster::new((fsm, Rc::new(vr)));
+
+        let dbs = DivBufShared::from(vec![0u8; 8192]);
+        let classes = [WriteClass::Metadata, WriteClass::Data,
+                       WriteClass::Relocation];
+        let lbas = current_thread::Runtime::new().unwrap()
+        .block_on(future::lazy(|| {
+            let (lbas, futs): (Vec<_>, Vec<_>) = classes.iter().map(|&class| {
+                let db = dbs.try_const().unwrap();
+                cluster.write(db, class, TxgT::from(0), PriorityClass::Normal)
+                    .expect("Cluster::write")
+            }).unzip();
+            future::join_all(futs).map(move |_| lbas)
+        })).expect("write failed");
+        assert_eq!(lbas, vec![0, 2, 4]);
+        assert_eq!(cluster.fsm.borrow().open_zones.len(), 2);
+    }
+
+    // If more zones were open on import than the vdev allows, the excess
+    // should be finished before the next allocation.
+    #[test]
+    fn write_max_open_zones_reopened() {
+        let mut vr = MockVdevRaid::default();
+        vr.expect_zones()
+            .return_const(32768u32);
+        vr.expect_max_open_zones()
+            .return_const(Some(2));
+        vr.expect_finish_zone()
+            .with(eq(0))
+            .once()
+            .returning(|_| Box::new( future::ok::<(), Error>(())));
+        vr.expect_write_at()
+            .with(always(), eq(2), eq(2000), always())
+            .once()
+            .returning(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
+        let mut fsm = FreeSpaceMap::new(vr.zones());
+        let txg = TxgT::from(0);
+        fsm.open_zone(0, 0, 1000, 900, WriteClass::Metadata, txg).unwrap();
+        fsm.open_zone(1, 1000, 2000, 500, WriteClass::Relocation, txg)
+            .unwrap();
+        fsm.open_zone(2, 2000, 3000, 0, WriteClass::Data, txg).unwrap();
+        let cluster = Cluster::new((fsm, Rc::new(vr)));
+
+        let dbs = DivBufShared::from(vec![0u8; 4096]);
+        current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
+            cluster.write(dbs.try_const().unwrap(), WriteClass::Data,
+                          TxgT::from(0), PriorityClass::Normal)
+                .expect("Cluster::write")
+                .1
+        })).expect("write failed");
+        assert_eq!(cluster.fsm.borrow().open_zones.len(), 2);
+    }
+
     // When one zone is too full to satisfy an allocation, it should be closed
     // and a new zone opened.
     #[test]
@@ -1585,12 +2756,12 @@ mod cluster {
             .once()
             .return_once(|_| Box::new( future::ok::<(), Error>(())));
         vr.expect_write_at()
-            .withf(|buf, zone, lba|
+            .withf(|buf, zone, lba, _|
                 buf.len() == 2 * BYTES_PER_LBA &&
                 *zone == 0 &&
                 *lba == 0
             ).once()
-            .return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
+            .return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
         vr.expect_finish_zone()
             .with(eq(0))
             .once()
@@ -1600,13 +2771,15 @@ mod cluster {
             .once()
             .return_once(|_| Box::new( future::ok::<(), Error>(())));
         vr.expect_write_at()
-            .withf(|buf, zone, lba|
+            .withf(|buf, zone, lba, _|
                 buf.len() == 2 * BYTES_PER_LBA &&
                 *zone == 1 &&
                 *lba == 3
             ).once()
-            .return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
+            .return_once(|_, _, _, _| Box::new( future::ok::<(), Error>(())));
         let fsm = FreeSpaceMap::new(vr.zones());
+        vr.expect_max_open_zones()
+            .return_const(None::<ZoneT>);
         let cluster = Cluster::new((fsm, Rc::new(vr)));
 
         let dbs = DivBufShared::from(vec![0u8; 8192]);
@@ -1614,10 +2787,13 @@ mod cluster {
         let db1 = dbs.try_const().unwrap();
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
             let cluster_ref = &cluster;
-            let (_, fut0) = cluster.write(db0, TxgT::from(0))
+            let (_, fut0) = cluster.write(db0, WriteClass::Data, TxgT::from(0),
+                                          PriorityClass::Normal)
                 .expect("Cluster::write");
             fut0.and_then(move |_| {
-                let (lba1, fut1) = cluster_ref.write(db1, TxgT::from(0))
+                let (lba1, fut1) = cluster_ref.write(db1, WriteClass::Data,
+                                                     TxgT::from(0),
+                                                     PriorityClass::Normal)
                     .expect("Cluster::write");
                 assert_eq!(lba1, 3);
                 fut1
@@ -1646,7 +2822,8 @@ mod free_space_map {
     #[test]
     fn allocated_one_closed_zone() {
         let mut fsm = FreeSpaceMap::new(32768);
-        fsm.open_zone(0, 1000, 2000, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(0, 1000, 2000, 0, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
         fsm.finish_zone(0, TxgT::from(0));
         assert_eq!(1000, fsm.allocated());
     }
@@ -1654,19 +2831,24 @@ mod free_space_map {
     #[test]
     fn allocated_one_open_zone() {
         let mut fsm = FreeSpaceMap::new(32768);
-        fsm.open_zone(0, 1000, 2000, 200, TxgT::from(0)).unwrap();
+        fsm.open_zone(0, 1000, 2000, 200, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
         assert_eq!(200, fsm.allocated());
     }
 
     #[test]
     fn allocated_one_empty_two_closed_two_open_zones() {
         let mut fsm = FreeSpaceMap::new(32768);
-        fsm.open_zone(0, 1000, 2000, 200, TxgT::from(0)).unwrap();
+        fsm.open_zone(0, 1000, 2000, 200, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
         // Leave zone 1 empty
-        fsm.open_zone(2, 3000, 4000, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(2, 3000, 4000, 0, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
         fsm.finish_zone(2, TxgT::from(0));
-        fsm.open_zone(3, 4000, 5000, 500, TxgT::from(0)).unwrap();
-        fsm.open_zone(4, 5000, 7000, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(3, 4000, 5000, 500, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
+        fsm.open_zone(4, 5000, 7000, 0, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
         fsm.finish_zone(4, TxgT::from(0));
         assert_eq!(3700, fsm.allocated());
     }
@@ -1674,45 +2856,49 @@ mod free_space_map {
     #[test]
     fn dirty() {
         let mut fsm = FreeSpaceMap::new(4096);
-        // A freshly created FreeSpaceMap should be all dirty
-        assert_eq!(&[0b1_1111_1111_1111_1111], fsm.dirty.as_slice());
-
-        // clear_dirty_zones should clear it
-        fsm.clear_dirty_zones();
-        assert_eq!(0, fsm.dirty.count_ones(..));
+        // A freshly created FreeSpaceMap has nothing to record
+        assert!(fsm.dirty.is_empty());
 
         // open_zone should dirty a zone
-        fsm.open_zone(0, 100, 200, 20, TxgT::from(0)).unwrap();
-        assert_eq!(&[0b1], fsm.dirty.as_slice());
+        fsm.open_zone(0, 100, 200, 20, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
+        assert_eq!(vec![0], fsm.dirty.iter().cloned().collect::<Vec<_>>());
 
         // Allocating should dirty a zone, too
-        fsm.try_allocate(64);
-        assert_eq!(&[0b1], fsm.dirty.as_slice());
+        fsm.clear_dirty_zones();
+        fsm.try_allocate(64, WriteClass::Data);
+        assert_eq!(vec![0], fsm.dirty.iter().cloned().collect::<Vec<_>>());
 
         // Wasting space should dirty a zone, too
         fsm.clear_dirty_zones();
         fsm.waste_space(0, 10);
-        assert_eq!(&[0b1], fsm.dirty.as_slice());
+        assert_eq!(vec![0], fsm.dirty.iter().cloned().collect::<Vec<_>>());
 
         // Finishing a zone should also dirty it
         fsm.clear_dirty_zones();
         fsm.finish_zone(0, TxgT::from(0));
-        assert_eq!(&[0b1], fsm.dirty.as_slice());
+        assert_eq!(vec![0], fsm.dirty.iter().cloned().collect::<Vec<_>>());
 
         // As should freeing
         fsm.clear_dirty_zones();
         fsm.free(0, 10);
-        assert_eq!(&[0b1], fsm.dirty.as_slice());
+        assert_eq!(vec![0], fsm.dirty.iter().cloned().collect::<Vec<_>>());
 
         // Finally, so should erasing a zone
         fsm.clear_dirty_zones();
         fsm.erase_zone(0);
-        assert_eq!(&[0b1], fsm.dirty.as_slice());
+        assert_eq!(vec![0], fsm.dirty.iter().cloned().collect::<Vec<_>>());
 
-        // The dirty bitmap should also work for zones in other spacemap blocks
-        fsm.open_zone(512, 51200, 51300, 0, TxgT::from(0)).unwrap();
-        fsm.open_zone(2048, 204_000, 204_900, 0, TxgT::from(0)).unwrap();
-        assert_eq!(&[0b1_0000_0101], fsm.dirty.as_slice());
+        // clear_dirty_zones should clear it
+        fsm.clear_dirty_zones();
+        assert!(fsm.dirty.is_empty());
+
+        fsm.open_zone(512, 51200, 51300, 0, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
+        fsm.open_zone(2048, 204_000, 204_900, 0, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
+        assert_eq!(vec![512, 2048],
+                   fsm.dirty.iter().cloned().collect::<Vec<_>>());
     }
 
     // FreeSpaceMap::display with the following conditions:
@@ -1723,10 +2909,11 @@ mod free_space_map {
     #[test]
     fn display() {
         let mut fsm = FreeSpaceMap::new(1_000_004);
-        fsm.open_zone(0, 4, 96, 88, TxgT::from(1)).unwrap();
+        fsm.open_zone(0, 4, 96, 88, WriteClass::Data, TxgT::from(1)).unwrap();
         fsm.finish_zone(0, TxgT::from(2));
         fsm.free(0, 22);
-        fsm.open_zone(3, 204, 296, 77, TxgT::from(10)).unwrap();
+        fsm.open_zone(3, 204, 296, 77, WriteClass::Data,
+                      TxgT::from(10)).unwrap();
         fsm.free(3, 33);
         let expected =
 r#"FreeSpaceMap: 1000004 Zones: 1 Closed, 1000002 Empty, 1 Open
@@ -1744,7 +2931,7 @@ r#"FreeSpaceMap: 1000004 Zones: 1 Closed, 1000002 Empty, 1 Open
     #[test]
     fn display_no_closed_zones() {
         let mut fsm = FreeSpaceMap::new(2);
-        fsm.open_zone(0, 4, 96, 88, TxgT::from(0)).unwrap();
+        fsm.open_zone(0, 4, 96, 88, WriteClass::Data, TxgT::from(0)).unwrap();
         fsm.free(0, 22);
         let expected =
 r#"FreeSpaceMap: 2 Zones: 0 Closed, 1 Empty, 1 Open
@@ -1761,7 +2948,8 @@ r#"FreeSpaceMap: 2 Zones: 0 Closed, 1 Empty, 1 Open
     #[test]
     fn display_used_free_half_columns() {
         let mut fsm = FreeSpaceMap::new(1);
-        fsm.open_zone(0, 0, 2048, 1648, TxgT::from(0)).unwrap();
+        fsm.open_zone(0, 0, 2048, 1648, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
         fsm.finish_zone(0, TxgT::from(15));
         let expected =
 r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
@@ -1775,7 +2963,8 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
     #[test]
     fn erase_closed_zone() {
         let mut fsm = FreeSpaceMap::new(32768);
-        fsm.open_zone(1, 1000, 2000, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(1, 1000, 2000, 0, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
         fsm.erase_zone(0);
         assert!(fsm.is_empty(0));
         assert!(!fsm.is_empty(1));
@@ -1791,8 +2980,9 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
     #[test]
     fn erase_last_zone() {
         let mut fsm = FreeSpaceMap::new(32768);
-        fsm.open_zone(0, 0, 1000, 0, TxgT::from(0)).unwrap();
-        fsm.open_zone(1, 1000, 2000, 1, TxgT::from(0)).unwrap();
+        fsm.open_zone(0, 0, 1000, 0, WriteClass::Data, TxgT::from(0)).unwrap();
+        fsm.open_zone(1, 1000, 2000, 1, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
         fsm.finish_zone(1, TxgT::from(0));
         fsm.erase_zone(1);
         assert!(!fsm.is_empty(0));
@@ -1803,8 +2993,9 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
     #[test]
     fn erase_last_zone_with_empties_behind_it() {
         let mut fsm = FreeSpaceMap::new(32768);
-        fsm.open_zone(0, 0, 1000, 0, TxgT::from(0)).unwrap();
-        fsm.open_zone(2, 2000, 3000, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(0, 0, 1000, 0, WriteClass::Data, TxgT::from(0)).unwrap();
+        fsm.open_zone(2, 2000, 3000, 0, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
         fsm.finish_zone(2, TxgT::from(0));
         fsm.erase_zone(2);
         assert!(!fsm.is_empty(0));
@@ -1814,7 +3005,8 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
     #[test]
     fn erase_last_zone_with_all_other_zones_empty() {
         let mut fsm = FreeSpaceMap::new(32768);
-        fsm.open_zone(2, 2000, 3000, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(2, 2000, 3000, 0, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
         fsm.finish_zone(2, TxgT::from(0));
         fsm.erase_zone(2);
         assert_eq!(fsm.zones.len(), 0);
@@ -1824,7 +3016,7 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
     #[should_panic(expected = "Can't erase an open zone")]
     fn erase_open_zone() {
         let mut fsm = FreeSpaceMap::new(32768);
-        fsm.open_zone(0, 0, 1000, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(0, 0, 1000, 0, WriteClass::Data, TxgT::from(0)).unwrap();
         fsm.erase_zone(0);
     }
 
@@ -1839,12 +3031,12 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
     #[test]
     fn find_closed_zone() {
         let mut fsm = FreeSpaceMap::new(10);
-        fsm.open_zone(0, 0, 1, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(0, 0, 1, 0, WriteClass::Data, TxgT::from(0)).unwrap();
         fsm.finish_zone(0, TxgT::from(0));
-        fsm.open_zone(2, 2, 3, 0, TxgT::from(0)).unwrap();
-        fsm.open_zone(3, 3, 4, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(2, 2, 3, 0, WriteClass::Data, TxgT::from(0)).unwrap();
+        fsm.open_zone(3, 3, 4, 0, WriteClass::Data, TxgT::from(0)).unwrap();
         fsm.finish_zone(3, TxgT::from(0));
-        fsm.open_zone(4, 4, 5, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(4, 4, 5, 0, WriteClass::Data, TxgT::from(0)).unwrap();
         fsm.finish_zone(4, TxgT::from(0));
         assert_eq!(fsm.find_closed_zone(1).unwrap().zid, 3);
     }
@@ -1853,9 +3045,9 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
     #[test]
     fn find_closed_zone_no_closed_zones() {
         let mut fsm = FreeSpaceMap::new(10);
-        fsm.open_zone(0, 0, 1, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(0, 0, 1, 0, WriteClass::Data, TxgT::from(0)).unwrap();
         fsm.finish_zone(0, TxgT::from(0));
-        fsm.open_zone(2, 2, 3, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(2, 2, 3, 0, WriteClass::Data, TxgT::from(0)).unwrap();
         assert!(fsm.find_closed_zone(1).is_none());
     }
 
@@ -1864,7 +3056,7 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
     #[test]
     fn find_closed_zone_out_of_bounds() {
         let mut fsm = FreeSpaceMap::new(10);
-        fsm.open_zone(0, 0, 1, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(0, 0, 1, 0, WriteClass::Data, TxgT::from(0)).unwrap();
         fsm.finish_zone(0, TxgT::from(0));
         assert!(fsm.find_closed_zone(2).is_none());
     }
@@ -1872,27 +3064,44 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
     #[test]
     fn find_empty_enospc() {
         let mut fsm = FreeSpaceMap::new(2);
-        assert!(fsm.open_zone(0, 0, 1000, 0, TxgT::from(0)).unwrap().is_none());
+        assert!(fsm.open_zone(0, 0, 1000, 0, WriteClass::Data,
+                              TxgT::from(0)).unwrap().is_none());
         assert!(
-            fsm.open_zone(1, 1000, 2000, 0, TxgT::from(0)).unwrap().is_none());
+            fsm.open_zone(1, 1000, 2000, 0, WriteClass::Data,
+                          TxgT::from(0)).unwrap().is_none());
         fsm.finish_zone(1, TxgT::from(0));
-        assert_eq!(fsm.find_empty(), None);
+        assert_eq!(fsm.find_empty(|_| true), None);
+    }
+
+    #[test]
+    fn find_empty_too_small() {
+        let mut fsm = FreeSpaceMap::new(32768);
+        assert!(fsm.open_zone(0, 0, 1000, 0, WriteClass::Data,
+                              TxgT::from(0)).unwrap().is_none());
+        assert!(
+            fsm.open_zone(2, 2000, 3000, 0, WriteClass::Data,
+                          TxgT::from(0)).unwrap().is_none());
+        // Zone 1 is explicitly empty, but too small
+        assert_eq!(fsm.find_empty(|zid| zid != 1), Some(3));
     }
 
     #[test]
     fn find_empty_explicit() {
         let mut fsm = FreeSpaceMap::new(32768);
-        assert!(fsm.open_zone(0, 0, 1000, 0, TxgT::from(0)).unwrap().is_none());
+        assert!(fsm.open_zone(0, 0, 1000, 0, WriteClass::Data,
+                              TxgT::from(0)).unwrap().is_none());
         assert!(
-            fsm.open_zone(2, 2000, 3000, 0, TxgT::from(0)).unwrap().is_none());
-        assert_eq!(fsm.find_empty(), Some(1));
+            fsm.open_zone(2, 2000, 3000, 0, WriteClass::Data,
+                          TxgT::from(0)).unwrap().is_none());
+        assert_eq!(fsm.find_empty(|_| true), Some(1));
     }
 
     #[test]
     fn find_empty_implicit() {
         let mut fsm = FreeSpaceMap::new(32768);
-        assert!(fsm.open_zone(0, 0, 1000, 0, TxgT::from(0)).unwrap().is_none());
-        assert_eq!(fsm.find_empty(), Some(1));
+        assert!(fsm.open_zone(0, 0, 1000, 0, WriteClass::Data,
+                              TxgT::from(0)).unwrap().is_none());
+        assert_eq!(fsm.find_empty(|_| true), Some(1));
     }
 
     #[test]
@@ -1900,7 +3109,8 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
         let zid: ZoneT = 0;
         let mut fsm = FreeSpaceMap::new(32768);
         assert!(
-            fsm.open_zone(zid, 0, 1000, 0, TxgT::from(0)).unwrap().is_none());
+            fsm.open_zone(zid, 0, 1000, 0, WriteClass::Data,
+                          TxgT::from(0)).unwrap().is_none());
         fsm.finish_zone(zid, TxgT::from(1));
         assert!(!fsm.open_zones.contains_key(&zid));
         assert!(!fsm.is_empty(zid));
@@ -1914,7 +3124,8 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
         let mut fsm = FreeSpaceMap::new(32768);
         // First, open zone 1 so zone 0 will become explicitly empty
         let txg = TxgT::from(0);
-        assert!(fsm.open_zone(1, 1000, 2000, 0, txg).unwrap().is_none());
+        assert!(fsm.open_zone(1, 1000, 2000, 0, WriteClass::Data,
+                              txg).unwrap().is_none());
         fsm.finish_zone(zid, txg);
     }
 
@@ -1932,7 +3143,8 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
         let zid: ZoneT = 0;
         let space: LbaT = 10;
         let mut fsm = FreeSpaceMap::new(32768);
-        fsm.open_zone(zid, 0, 1000, space, TxgT::from(0)).unwrap();
+        fsm.open_zone(zid, 0, 1000, space, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
         fsm.finish_zone(zid, TxgT::from(0));
         fsm.free(zid, space);
         fsm.free(zid, space);
@@ -1944,7 +3156,8 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
         let zid: ZoneT = 0;
         let space: LbaT = 17;
         let mut fsm = FreeSpaceMap::new(32768);
-        fsm.open_zone(zid, 0, 1000, space, TxgT::from(0)).unwrap();
+        fsm.open_zone(zid, 0, 1000, space, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
         fsm.free(zid, space);
         fsm.free(zid, space);
     }
@@ -1955,7 +3168,8 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
         let space: LbaT = 1000;
         let used: LbaT = 17;
         let mut fsm = FreeSpaceMap::new(32768);
-        fsm.open_zone(zid, 0, 1000, used, TxgT::from(0)).unwrap();
+        fsm.open_zone(zid, 0, 1000, used, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
         fsm.finish_zone(zid, TxgT::from(0));
         assert_eq!(LbaT::from(fsm.zones[zid as usize].freed_blocks),
                    space - used);
@@ -1971,7 +3185,8 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
         let mut fsm = FreeSpaceMap::new(32768);
         // First, open zone 1 so zone 0 will become explicitly empty
         let txg = TxgT::from(0);
-        assert!(fsm.open_zone(1, 1000, 2000, 0, txg).unwrap().is_none());
+        assert!(fsm.open_zone(1, 1000, 2000, 0, WriteClass::Data,
+                              txg).unwrap().is_none());
         fsm.free(zid, space);
     }
 
@@ -1989,7 +3204,8 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
         let zid: ZoneT = 0;
         let space: LbaT = 17;
         let mut fsm = FreeSpaceMap::new(32768);
-        fsm.open_zone(zid, 0, 1000, space, TxgT::from(0)).unwrap();
+        fsm.open_zone(zid, 0, 1000, space, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
         fsm.free(zid, space);
         assert_eq!(LbaT::from(fsm.zones[zid as usize].freed_blocks), space);
     }
@@ -1999,7 +3215,8 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
         let zid: ZoneT = 0;
         let mut fsm = FreeSpaceMap::new(32768);
         let txg = TxgT::from(5);
-        assert!(fsm.open_zone(zid, 0, 1000, 0, txg).unwrap().is_none());
+        assert!(fsm.open_zone(zid, 0, 1000, 0, WriteClass::Data,
+                              txg).unwrap().is_none());
         assert_eq!(fsm.zones.len(), 1);
         assert_eq!(fsm.zones[zid as usize].total_blocks, 1000);
         assert_eq!(fsm.zones[zid as usize].freed_blocks, 0);
@@ -2013,7 +3230,8 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
         let zid: ZoneT = 0;
         let space: LbaT = 17;
         let mut fsm = FreeSpaceMap::new(32768);
-        assert_eq!(fsm.open_zone(zid, 0, 1000, space, TxgT::from(0)).unwrap(),
+        assert_eq!(fsm.open_zone(zid, 0, 1000, space, WriteClass::Data,
+                                 TxgT::from(0)).unwrap(),
                    Some((zid, 0)));
         assert_eq!(fsm.zones.len(), 1);
         assert_eq!(fsm.zones[zid as usize].total_blocks, 1000);
@@ -2029,7 +3247,8 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
         let space: LbaT = 2000;
         let mut fsm = FreeSpaceMap::new(32768);
         let txg = TxgT::from(0);
-        assert_eq!(fsm.open_zone(zid, 0, 1000, space, txg).unwrap_err(),
+        assert_eq!(fsm.open_zone(zid, 0, 1000, space, WriteClass::Data,
+                                 txg).unwrap_err(),
             Error::ENOSPC);
         assert_eq!(fsm.zones.len(), 0);
     }
@@ -2040,12 +3259,14 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
         let txg = TxgT::from(0);
 
         // First, open zone 1 so zone 0 will become explicitly empty
-        assert!(fsm.open_zone(1, 1000, 2000, 0, txg).unwrap().is_none());
+        assert!(fsm.open_zone(1, 1000, 2000, 0, WriteClass::Data,
+                              txg).unwrap().is_none());
         assert_eq!(fsm.zones.len(), 2);
 
         // Now try to open an explicitly empty zone
         let zid: ZoneT = 0;
-        assert!(fsm.open_zone(zid, 0, 1000, 0, txg).unwrap().is_none());
+        assert!(fsm.open_zone(zid, 0, 1000, 0, WriteClass::Data,
+                              txg).unwrap().is_none());
         assert_eq!(fsm.zones.len(), 2);
         assert_eq!(fsm.zones[zid as usize].total_blocks, 1000);
         assert_eq!(fsm.zones[zid as usize].freed_blocks, 0);
@@ -2058,7 +3279,8 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
         let zid: ZoneT = 1;
         let txg = TxgT::from(0);
         let mut fsm = FreeSpaceMap::new(32768);
-        assert!(fsm.open_zone(zid, 1000, 2000, 0, txg).unwrap().is_none());
+        assert!(fsm.open_zone(zid, 1000, 2000, 0, WriteClass::Data,
+                              txg).unwrap().is_none());
         assert_eq!(fsm.zones.len(), 2);
         assert_eq!(fsm.zones[zid as usize].total_blocks, 1000);
         assert_eq!(fsm.zones[zid as usize].freed_blocks, 0);
@@ -2072,8 +3294,10 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
     fn open_already_open() {
         let zid: ZoneT = 0;
         let mut fsm = FreeSpaceMap::new(32768);
-        fsm.open_zone(zid, 0, 1000, 0, TxgT::from(0)).unwrap();
-        fsm.open_zone(zid, 0, 1000, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(zid, 0, 1000, 0, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
+        fsm.open_zone(zid, 0, 1000, 0, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
     }
 
     #[test]
@@ -2081,48 +3305,39 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
     fn open_closed_zone() {
         let zid: ZoneT = 0;
         let mut fsm = FreeSpaceMap::new(32768);
-        fsm.open_zone(zid, 0, 1000, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(zid, 0, 1000, 0, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
         fsm.finish_zone(zid, TxgT::from(0));
-        fsm.open_zone(zid, 0, 1000, 0, TxgT::from(0)).unwrap();
+        fsm.open_zone(zid, 0, 1000, 0, WriteClass::Data,
+                      TxgT::from(0)).unwrap();
     }
 
-    // FreeSpaceMap::serialize with the following conditions:
+    // FreeSpaceMap::zone_on_disk with the following conditions:
     // A full zone with some freed blocks
-    // An empty zone before the maximum open or full zone
+    // An empty zone
     // An open zone with some freed blocks
-    // A trailing empty zone
     #[test]
-    fn serialize() {
-        const EXPECTED: [u8; 80] = [
-            18, 213, 216, 8, 231, 94, 198, 193, // Checksum
-            4, 0, 0, 0, 0, 0, 0, 0,         // 4 ZODs
-            255, 255, 255, 255,             // zone0: allocated_blocks
-            26, 0, 0, 0,                    // zone0: freed blocks
-            1, 0, 0, 0, 3, 0, 0, 0,         // zone0 txgs: 1..3
-            0, 0, 0, 0,                     // zone1: allocated_blocks
-            0, 0, 0, 0,                     // zone1: freed blocks
-            0, 0, 0, 0, 0, 0, 0, 0,         // zone1 txgs: DON'T CARE
-            77, 0, 0, 0,                    // zone2: allocated_blocks
-            33, 0, 0, 0,                    // zone2: freed blocks
-            2, 0, 0, 0, 255, 255, 255, 255, // zone2 txgs: 2..DON'T CARE
-            0, 0, 0, 0,                     // zone3: allocated_blocks
-            0, 0, 0, 0,                     // zone3: freed blocks
-            0, 0, 0, 0, 0, 0, 0, 0,         // zone3 txgs: DON'T CARE
-        ];
+    fn zone_on_disk() {
         let mut fsm = FreeSpaceMap::new(4);
-        fsm.open_zone(0, 4, 96, 88, TxgT::from(1)).unwrap();
+        fsm.open_zone(0, 4, 96, 88, WriteClass::Data, TxgT::from(1)).unwrap();
         fsm.finish_zone(0, TxgT::from(2));
         fsm.free(0, 22);
-        fsm.open_zone(2, 204, 296, 77, TxgT::from(2)).unwrap();
+        fsm.open_zone(2, 204, 296, 77, WriteClass::Metadata,
+                      TxgT::from(2)).unwrap();
         fsm.free(2, 33);
 
-        let mut fsm_iter = fsm.serialize();
-        let (block, dbs) = fsm_iter.next().unwrap();
-        assert_eq!(0, block);
-        let db = dbs.try_const().unwrap();
-        assert_eq!(&EXPECTED[..], &db[..80]);
-        assert!(&db[80..].iter().all(|&x| x == 0));
-        assert!(fsm_iter.next().is_none());
+        let zod0 = fsm.zone_on_disk(0);
+        assert_eq!(zod0.allocated_blocks, u32::max_value());
+        assert_eq!(zod0.freed_blocks, 26);
+        assert_eq!(zod0.txgs, TxgT::from(1)..TxgT::from(3));
+        let zod1 = fsm.zone_on_disk(1);
+        assert_eq!(zod1.allocated_blocks, 0);
+        let zod2 = fsm.zone_on_disk(2);
+        assert_eq!(zod2.allocated_blocks, 77);
+        assert_eq!(zod2.freed_blocks, 33);
+        assert_eq!(zod2.txgs.start, TxgT::from(2));
+        assert_eq!(zod2.class, WriteClass::Metadata);
+        assert_eq!(vec![0, 2], fsm.nonempty_zone_ids().collect::<Vec<_>>());
     }
 
     #[test]
@@ -2130,8 +3345,9 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
         let zid: ZoneT = 0;
         let mut fsm = FreeSpaceMap::new(32768);
         let txg = TxgT::from(0);
-        assert!(fsm.open_zone(zid, 0, 1000, 0, txg).unwrap().is_none());
-        let (res, full_zones) = fsm.try_allocate(64);
+        assert!(fsm.open_zone(zid, 0, 1000, 0, WriteClass::Data,
+                              txg).unwrap().is_none());
+        let (res, full_zones) = fsm.try_allocate(64, WriteClass::Data);
         assert_eq!(res, Some((zid, 0)));
         assert!(full_zones.is_empty());
         assert_eq!(fsm.open_zones[&zid].write_pointer(), 64);
@@ -2143,8 +3359,9 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
         let zid: ZoneT = 0;
         let txg = TxgT::from(0);
         let mut fsm = FreeSpaceMap::new(32768);
-        assert!(fsm.open_zone(zid, 0, 1000, 0, txg).unwrap().is_none());
-        assert!(fsm.try_allocate(2000).0.is_none());
+        assert!(fsm.open_zone(zid, 0, 1000, 0, WriteClass::Data,
+                              txg).unwrap().is_none());
+        assert!(fsm.try_allocate(2000, WriteClass::Data).0.is_none());
     }
 
     #[test]
@@ -2153,29 +3370,134 @@ r#"FreeSpaceMap: 1 Zones: 1 Closed, 0 Empty, 0 Open
         let mut fsm = FreeSpaceMap::new(32768);
         let txg = TxgT::from(0);
         // Pretend that zone 0 is too small for our allocation, but zone 1 isn't
-        assert!(fsm.open_zone(0, 0, 10, 0, txg).unwrap().is_none());
-        assert!(fsm.open_zone(zid, 10, 1000, 0, txg).unwrap().is_none());
-        let (res, full_zones) = fsm.try_allocate(64);
+        assert!(fsm.open_zone(0, 0, 10, 0, WriteClass::Data,
+                              txg).unwrap().is_none());
+        assert!(fsm.open_zone(zid, 10, 1000, 0, WriteClass::Data,
+                              txg).unwrap().is_none());
+        let (res, full_zones) = fsm.try_allocate(64, WriteClass::Data);
         assert_eq!(res, Some((zid, 10)));
         assert_eq!(full_zones, vec![0]);
         assert_eq!(fsm.open_zones[&0].write_pointer(), 0);
         assert_eq!(fsm.open_zones[&zid].write_pointer(), 74);
     }
 
+    // Records of one class should never be allocated from another class's zone,
+    // nor should they cause that zone to be closed.
+    #[test]
+    fn try_allocate_other_class() {
+        let mut fsm = FreeSpaceMap::new(32768);
+        let txg = TxgT::from(0);
+        assert!(fsm.open_zone(0, 0, 10, 0, WriteClass::Metadata,
+                              txg).unwrap().is_none());
+        assert!(fsm.open_zone(1, 10, 1000, 0, WriteClass::Metadata,
+                              txg).unwrap().is_none());
+        assert!(fsm.open_zone(2, 1000, 2000, 0, WriteClass::Data,
+                              txg).unwrap().is_none());
+        let (res, full_zones) = fsm.try_allocate(64, WriteClass::Data);
+        assert_eq!(res, Some((2, 1000)));
+        assert!(full_zones.is_empty());
+        let (res, full_zones) = fsm.try_allocate(64, WriteClass::Relocation);
+        assert!(res.is_none());
+        assert!(full_zones.is_empty());
+        let (res, full_zones) = fsm.try_allocate(64, WriteClass::Metadata);
+        assert_eq!(res, Some((1, 10)));
+        assert_eq!(full_zones, vec![0]);
+    }
+
     #[test]
     fn try_allocate_only_closed_zones() {
         let zid: ZoneT = 0;
         let mut fsm = FreeSpaceMap::new(32768);
         assert!(
-            fsm.open_zone(zid, 0, 1000, 0, TxgT::from(0)).unwrap().is_none());
+            fsm.open_zone(zid, 0, 1000, 0, WriteClass::Data,
+                          TxgT::from(0)).unwrap().is_none());
         fsm.finish_zone(zid, TxgT::from(0));
-        assert!(fsm.try_allocate(64).0.is_none());
+        assert!(fsm.try_allocate(64, WriteClass::Data).0.is_none());
     }
 
     #[test]
     fn try_allocate_only_empty_zones() {
         let mut fsm = FreeSpaceMap::new(32768);
-        assert!(fsm.try_allocate(64).0.is_none());
+        assert!(fsm.try_allocate(64, WriteClass::Data).0.is_none());
+    }
+
+    // try_allocate_any should use the emptiest open zone of any class
+    #[test]
+    fn try_allocate_any() {
+        let mut fsm = FreeSpaceMap::new(32768);
+        let txg = TxgT::from(0);
+        fsm.open_zone(0, 0, 1000, 900, WriteClass::Metadata, txg).unwrap();
+        fsm.open_zone(1, 1000, 2000, 500, WriteClass::Relocation, txg)
+            .unwrap();
+        fsm.clear_dirty_zones();
+        assert_eq!(fsm.try_allocate_any(64), Some((1, 1500)));
+        assert_eq!(fsm.open_zones[&1].class, WriteClass::Relocation);
+        assert!(fsm.dirty.contains(&1));
+        assert_eq!(fsm.try_allocate_any(500), None);
+    }
+
+    #[test]
+    fn zones_to_finish() {
+        let mut fsm = FreeSpaceMap::new(32768);
+        let txg = TxgT::from(0);
+        fsm.open_zone(0, 0, 1000, 100, WriteClass::Data, txg).unwrap();
+        fsm.open_zone(1, 1000, 2000, 900, WriteClass::Metadata, txg).unwrap();
+        fsm.open_zone(2, 2000, 3000, 500, WriteClass::Relocation, txg)
+            .unwrap();
+        assert!(fsm.zones_to_finish(3).is_empty());
+        // Finish the fullest zones first
+        assert_eq!(fsm.zones_to_finish(2), vec![1]);
+        assert_eq!(fsm.zones_to_finish(1), vec![1, 2]);
+        assert_eq!(fsm.zones_to_finish(0), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn zones_to_finish_no_open_zones() {
+        let fsm = FreeSpaceMap::new(32768);
+        assert!(fsm.zones_to_finish(0).is_empty());
+    }
+}
+mod spacemap_record {
+    use divbuf::DivBufShared;
+    use pretty_assertions::assert_eq;
+    use super::super::*;
+
+    fn zones() -> Vec<(ZoneT, ZoneOnDisk)> {
+        (0..4).map(|zid| {
+            let txgs = TxgT::from(1)..TxgT::from(3);
+            let class = WriteClass::Data;
+            (zid, ZoneOnDisk{allocated_blocks: 77, freed_blocks: 33, txgs,
+                             class})
+        }).collect()
+    }
+
+    // A serialized record should never exceed the size predicted by lbas()
+    #[test]
+    fn serialize() {
+        let prev = Some(SpacemapPtr{lba: 1000, lbas: 1});
+        let record = SpacemapRecord::new(2000, prev, zones());
+        let buf = bincode::serialize(&record).unwrap();
+        assert_eq!(buf.len(), SpacemapRecord::HEADER_BYTES +
+                   4 * SpacemapRecord::ZONE_BYTES);
+        assert_eq!(SpacemapRecord::lbas(4), 1);
+        assert_eq!(SpacemapRecord::lbas(169), 1);
+        assert_eq!(SpacemapRecord::lbas(170), 2);
+
+        let dbs = DivBufShared::from(buf);
+        let db = dbs.try_const().unwrap();
+        let record2 = SpacemapRecord::deserialize(2000, &db).unwrap();
+        assert_eq!(record2.prev, prev);
+        assert_eq!(record2.zones.len(), 4);
+    }
+
+    // A record read from the wrong location should fail its checksum
+    #[test]
+    fn misdirected() {
+        let record = SpacemapRecord::new(2000, None, zones());
+        let buf = bincode::serialize(&record).unwrap();
+        let db = DivBufShared::from(buf);
+        let r = SpacemapRecord::deserialize(3000, &db.try_const().unwrap());
+        assert_eq!(Error::ECKSUM, r.unwrap_err());
     }
 }
 }
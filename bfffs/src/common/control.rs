@@ -0,0 +1,339 @@
+// vim: tw=80
+//! Control channel for an imported pool
+//!
+//! A pool can only be imported by one process at a time.  So the daemon that
+//! has it imported listens on a Unix-domain socket for administrative
+//! requests, and the `bfffs` command sends them there instead of importing the
+//! pool itself.  Each message is a bincode-encoded `Request` or `Response`.
+
+use crate::common::{
+    Error,
+    LbaT,
+    Uuid,
+    database::RemovalProgress,
+    iostat::VdevStats,
+    pool::StorageClass,
+    throttle::IoLimits
+};
+#[cfg(not(test))]
+use crate::common::{
+    database::Database,
+    pool::Pool
+};
+#[cfg(not(test))]
+use futures::{Future, Stream, future, sync::oneshot};
+use serde_derive::*;
+use std::{
+    io,
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf}
+};
+#[cfg(not(test))]
+use std::{
+    fs,
+    num::NonZeroU64,
+    os::unix::net::UnixListener,
+    sync::{Arc, Weak, mpsc},
+    thread
+};
+
+/// Default directory for the pools' control sockets
+pub const SOCKET_DIR: &str = "/var/run/bfffs";
+
+/// Location of the control socket for the pool named `name`
+pub fn socket_path<P: AsRef<Path>>(dir: P, name: &str) -> PathBuf {
+    dir.as_ref().join(format!("{}.sock", name))
+}
+
+/// Describes a new `Cluster` to create and add to the pool
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ClusterSpec {
+    pub disks_per_stripe: i16,
+    pub redundancy: i16,
+    /// Simulated zone size, for devices that don't have zones
+    pub lbas_per_zone: Option<LbaT>,
+    pub storage_class: StorageClass,
+    pub paths: Vec<PathBuf>
+}
+
+/// Requests from the `bfffs` command to the pool's owner
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Request {
+    AddCluster(ClusterSpec),
+    Checkpoint,
+    DiscardCheckpoint,
+    IoStats,
+    Limits,
+    /// Answered by one `Progress` for each evacuated zone, then `Ok`
+    RemoveCluster(Uuid),
+    Removing,
+    SetLimits(IoLimits),
+}
+
+/// Replies to `Request`s
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Response {
+    Err(Error),
+    IoStats(VdevStats),
+    Limits(IoLimits),
+    Ok,
+    Progress(RemovalProgress),
+    Removing(Option<(Uuid, LbaT)>),
+}
+
+fn recv<T>(stream: &mut UnixStream) -> Result<T, Error>
+    where T: serde::de::DeserializeOwned
+{
+    bincode::deserialize_from(stream).map_err(|_| Error::EPIPE)
+}
+
+fn send<T: serde::Serialize>(stream: &mut UnixStream, msg: &T)
+    -> Result<(), Error>
+{
+    bincode::serialize_into(stream, msg).map_err(|_| Error::EPIPE)
+}
+
+/// Client side of a pool's control channel
+pub struct Client {
+    stream: UnixStream
+}
+
+impl Client {
+    /// Add a new `Cluster` to the pool.  Its disks will be formatted.
+    pub fn add_cluster(&mut self, spec: ClusterSpec) -> Result<(), Error> {
+        self.call_ok(Request::AddCluster(spec))
+    }
+
+    fn call(&mut self, req: Request) -> Result<Response, Error> {
+        send(&mut self.stream, &req)?;
+        match recv(&mut self.stream)? {
+            Response::Err(e) => Err(e),
+            r => Ok(r)
+        }
+    }
+
+    fn call_ok(&mut self, req: Request) -> Result<(), Error> {
+        match self.call(req)? {
+            Response::Ok => Ok(()),
+            _ => Err(Error::EPROTO)
+        }
+    }
+
+    /// Checkpoint the pool
+    pub fn checkpoint(&mut self) -> Result<(), Error> {
+        self.call_ok(Request::Checkpoint)
+    }
+
+    /// Connect to the control socket at `path`.
+    ///
+    /// Fails if nothing is listening there, which usually means that the pool
+    /// isn't imported.
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        UnixStream::connect(path).map(|stream| Client{stream})
+    }
+
+    /// Discard the pool's checkpoint
+    pub fn discard_checkpoint(&mut self) -> Result<(), Error> {
+        self.call_ok(Request::DiscardCheckpoint)
+    }
+
+    /// Snapshot the I/O statistics of the pool and all of its disks
+    pub fn iostats(&mut self) -> Result<VdevStats, Error> {
+        match self.call(Request::IoStats)? {
+            Response::IoStats(vs) => Ok(vs),
+            _ => Err(Error::EPROTO)
+        }
+    }
+
+    /// The I/O rate limits applied to each of the pool's disks
+    pub fn limits(&mut self) -> Result<IoLimits, Error> {
+        match self.call(Request::Limits)? {
+            Response::Limits(limits) => Ok(limits),
+            _ => Err(Error::EPROTO)
+        }
+    }
+
+    /// Evacuate and remove a `Cluster`, calling `f` after each zone.
+    ///
+    /// The removal carries on in the background even if the client goes away.
+    pub fn remove_cluster<F>(&mut self, uuid: Uuid, mut f: F)
+        -> Result<(), Error>
+        where F: FnMut(RemovalProgress)
+    {
+        send(&mut self.stream, &Request::RemoveCluster(uuid))?;
+        loop {
+            match recv(&mut self.stream)? {
+                Response::Progress(progress) => f(progress),
+                Response::Ok => return Ok(()),
+                Response::Err(e) => return Err(e),
+                _ => return Err(Error::EPROTO)
+            }
+        }
+    }
+
+    /// Return the UUID and remaining allocated space of the `Cluster` that is
+    /// being removed, if any.
+    pub fn removing(&mut self) -> Result<Option<(Uuid, LbaT)>, Error> {
+        match self.call(Request::Removing)? {
+            Response::Removing(r) => Ok(r),
+            _ => Err(Error::EPROTO)
+        }
+    }
+
+    /// Change the I/O rate limits of each of the pool's disks
+    pub fn set_limits(&mut self, limits: IoLimits) -> Result<(), Error> {
+        self.call_ok(Request::SetLimits(limits))
+    }
+}
+
+/// Server side of a pool's control channel
+///
+/// Serves each connection in its own thread.  It only holds a weak reference
+/// to the `Database`, so the pool can still be exported.  Requests that arrive
+/// after that fail with `ENXIO`.  Dropping the `Server` removes its socket.
+#[cfg(not(test))]
+pub struct Server {
+    path: PathBuf
+}
+
+#[cfg(not(test))]
+impl Server {
+    fn handle_conn(mut stream: UnixStream, db: Weak<Database>,
+                   handle: tokio_io_pool::Handle)
+    {
+        // A failed recv means the client hung up
+        while let Ok(req) = recv::<Request>(&mut stream) {
+            let r = match db.upgrade() {
+                Some(db) => Server::handle_req(&mut stream, db, &handle, req),
+                None => send(&mut stream, &Response::Err(Error::ENXIO))
+            };
+            if r.is_err() {
+                break;
+            }
+        }
+    }
+
+    fn handle_req(stream: &mut UnixStream, db: Arc<Database>,
+                  handle: &tokio_io_pool::Handle, req: Request)
+        -> Result<(), Error>
+    {
+        let resp = match req {
+            Request::AddCluster(spec) => {
+                let lbas_per_zone = spec.lbas_per_zone
+                    .and_then(NonZeroU64::new);
+                let class = spec.storage_class;
+                Server::run(handle, future::lazy(move || {
+                    Pool::create_cluster(None, spec.disks_per_stripe,
+                                         lbas_per_zone, spec.redundancy,
+                                         &spec.paths[..])
+                    .and_then(move |mut cluster| {
+                        cluster.set_storage_class(class);
+                        db.add_cluster(cluster)
+                    })
+                })).map(|_| Response::Ok)
+            },
+            Request::Checkpoint => {
+                Server::run(handle, future::lazy(move || db.checkpoint()))
+                    .map(|_| Response::Ok)
+            },
+            Request::DiscardCheckpoint => {
+                Server::run(handle,
+                            future::lazy(move || db.discard_checkpoint()))
+                    .map(|_| Response::Ok)
+            },
+            Request::IoStats => {
+                Server::run(handle, future::lazy(move || db.iostats()))
+                    .map(Response::IoStats)
+            },
+            Request::Limits => Ok(Response::Limits(db.limits())),
+            Request::RemoveCluster(uuid) => {
+                return Server::remove_cluster(stream, db, handle, uuid);
+            },
+            Request::Removing => Ok(Response::Removing(db.removing())),
+            Request::SetLimits(limits) => {
+                Server::run(handle, future::lazy(move || db.set_limits(limits)))
+                    .map(|_| Response::Ok)
+            },
+        };
+        send(stream, &resp.unwrap_or_else(Response::Err))
+    }
+
+    /// Listen for requests concerning `db` on a Unix-domain socket at `path`.
+    ///
+    /// Fails with `EADDRINUSE` if another `Server` is already listening there.
+    /// A stale socket left over from a crash is replaced.
+    pub fn new<P>(path: P, db: Weak<Database>, handle: tokio_io_pool::Handle)
+        -> io::Result<Self>
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref().to_owned();
+        if UnixStream::connect(&path).is_ok() {
+            return Err(io::Error::from_raw_os_error(libc::EADDRINUSE));
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        if let Err(e) = fs::remove_file(&path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(e);
+            }
+        }
+        let listener = UnixListener::bind(&path)?;
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let db = db.clone();
+                let handle = handle.clone();
+                thread::spawn(move || Server::handle_conn(stream, db, handle));
+            }
+        });
+        Ok(Server{path})
+    }
+
+    fn remove_cluster(stream: &mut UnixStream, db: Arc<Database>,
+                      handle: &tokio_io_pool::Handle, uuid: Uuid)
+        -> Result<(), Error>
+    {
+        let (tx, rx) = mpsc::channel();
+        let tx2 = tx.clone();
+        handle.spawn(future::lazy(move || {
+            db.remove_cluster(uuid)
+            .for_each(move |progress| {
+                // The client may have gone away, but the removal continues
+                let _ = tx.send(Response::Progress(progress));
+                Ok(())
+            }).then(move |r| {
+                let resp = match r {
+                    Ok(()) => Response::Ok,
+                    Err(e) => Response::Err(e)
+                };
+                let _ = tx2.send(resp);
+                Ok(())
+            })
+        })).unwrap();
+        for resp in rx {
+            send(stream, &resp)?;
+        }
+        Ok(())
+    }
+
+    /// Run `fut` on the pool's runtime, and wait for it to finish
+    fn run<F>(handle: &tokio_io_pool::Handle, fut: F)
+        -> Result<F::Item, Error>
+        where F: Future<Error=Error> + Send + 'static,
+              F::Item: Send
+    {
+        let (tx, rx) = oneshot::channel();
+        handle.spawn(fut.then(|r| {
+            tx.send(r).unwrap();
+            Ok(())
+        })).unwrap();
+        rx.wait().unwrap()
+    }
+}
+
+#[cfg(not(test))]
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
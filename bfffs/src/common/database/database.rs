@@ -9,8 +9,11 @@ use crate::{
         dml::DML,
         fs_tree::*,
         idml::*,
+        iostat::VdevStats,
         label::*,
+        pool::{ClusterProxy, PoolState},
         property::*,
+        throttle::IoLimits,
         tree::{Tree, TreeOnDisk}
     }
 };
@@ -173,10 +176,13 @@ impl Syncer {
 
 #[derive(Serialize, Deserialize, Debug)]
 struct Label {
-    forest: TreeOnDisk<RID>
+    /// The forest's root as of each recent transaction group, newest first
+    forests: TxgRing<TreeOnDisk<RID>>
 }
 
 struct Inner {
+    /// Change to the pool checkpoint to be made by the next transaction sync
+    checkpoint_op: std::sync::Mutex<Option<CheckpointOp>>,
     /// Has any part of the database been modified since the last transaction
     /// sync?
     // NB: This is likely to be highly contended and very slow.  Better to
@@ -186,15 +192,23 @@ struct Inner {
     forest: ITree<TreeID, TreeOnDisk<RID>>,
     idml: Arc<IDML>,
     propcache: Mutex<BTreeMap<PropCacheKey, (Property, PropertySource)>>,
+    /// Was the pool imported read-only?
+    readonly: bool,
+    /// Forest roots written to the label in recent transaction groups
+    roots: std::sync::Mutex<TxgRing<TreeOnDisk<RID>>>,
 }
 
 impl Inner {
-    fn new(idml: Arc<IDML>, forest: ITree<TreeID, TreeOnDisk<RID>>) -> Self
+    fn new(idml: Arc<IDML>, forest: ITree<TreeID, TreeOnDisk<RID>>,
+           readonly: bool) -> Self
     {
         let dirty = AtomicBool::new(true);
         let fs_trees = Mutex::new(BTreeMap::new());
         let propcache = Mutex::new(BTreeMap::new());
-        Inner{dirty, fs_trees, idml, forest, propcache}
+        let checkpoint_op = std::sync::Mutex::new(None);
+        let roots = std::sync::Mutex::new(TxgRing::default());
+        Inner{checkpoint_op, dirty, fs_trees, idml, forest, propcache, readonly,
+              roots}
     }
 
     // Must be called from within a Tokio executor context
@@ -232,16 +246,24 @@ impl Inner {
         where F: FnOnce(ReadWriteFilesystem) -> B,
               B: Future<Item = R, Error = Error>,
     {
-        inner.dirty.store(true, Ordering::Relaxed);
-        inner.idml.txg()
-        .map_err(|_| Error::EPIPE)
-        .and_then(move |txg| {
-            Inner::rw_filesystem(&inner, tree_id, *txg)
-                .and_then(|ds| f(ds).into_future())
-                .map(move |r| {
-                    drop(txg);
-                    r
-                })
+        let writable = if inner.readonly {
+            Err(Error::EROFS)
+        } else {
+            Ok(())
+        };
+        writable.into_future()
+        .and_then(move |_| {
+            inner.dirty.store(true, Ordering::Relaxed);
+            inner.idml.txg()
+            .map_err(|_| Error::EPIPE)
+            .and_then(move |txg| {
+                Inner::rw_filesystem(&inner, tree_id, *txg)
+                    .and_then(|ds| f(ds).into_future())
+                    .map(move |r| {
+                        drop(txg);
+                        r
+                    })
+            })
         })
     }
 
@@ -252,19 +274,24 @@ impl Inner {
     /// - `label`:      A Database::Label to serialize
     /// - `label_idx`:  0-based index of the label to write
     /// - `txg`:        The current transaction group of the database
-    fn write_label(&self, label: &Label, label_idx: u32, txg: TxgT)
+    /// - `op`:         Change to make to the pool checkpoint, if any
+    fn write_label(&self, label: &Label, label_idx: u32, txg: TxgT,
+                   op: Option<CheckpointOp>)
         -> impl Future<Item=(), Error=Error>
     {
-        let mut labeller = LabelWriter::new(label_idx);
+        let mut labeller = LabelWriter::new(label_idx, txg);
+        labeller.set_checkpoint_op(op);
         labeller.serialize(label).unwrap();
         self.idml.write_label(labeller, txg)
     }
 }
 
 pub struct Database {
-    cleaner: Cleaner,
+    /// Background zone cleaner.  `None` for read-only pools.
+    cleaner: Option<Cleaner>,
     inner: Arc<Inner>,
-    syncer: Syncer
+    /// Background transaction syncer.  `None` for read-only pools.
+    syncer: Option<Syncer>
 }
 
 // Some of these methods have no unit tests.  Their test coverage is provided
@@ -272,6 +299,22 @@ pub struct Database {
 #[cfg_attr(test, allow(unused))]
 #[cfg_attr(test, automock)]
 impl Database {
+    /// Add a newly created `Cluster` to the pool.
+    ///
+    /// The new `Cluster` will be usable immediately, and will become a
+    /// permanent part of the pool once the returned future completes.
+    pub fn add_cluster(&self, cluster: ClusterProxy)
+        -> impl Future<Item=(), Error=Error> + Send
+    {
+        if self.inner.readonly {
+            return future::Either::A(future::err(Error::EROFS));
+        }
+        let fut = self.inner.idml.add_cluster(cluster);
+        // Rewrite the labels to include the new Cluster
+        self.inner.dirty.store(true, Ordering::Relaxed);
+        future::Either::B(fut.join(self.sync_transaction()).map(drop))
+    }
+
     /// Foreground consistency check.  Prints any irregularities to stderr
     ///
     /// # Returns
@@ -313,14 +356,35 @@ impl Database {
             })
     }
 
+    /// Checkpoint the pool.
+    ///
+    /// Its current state is preserved by a transaction sync.  Until the
+    /// checkpoint is discarded, no space in use as of then will be reclaimed,
+    /// and the pool may be rewound to it on import.  Fails with `EEXIST` if the
+    /// pool already has a checkpoint, or `EBUSY` if a `Cluster` is being
+    /// removed.
+    pub fn checkpoint(&self) -> impl Future<Item=(), Error=Error> + Send {
+        self.update_checkpoint(CheckpointOp::Create)
+    }
+
     /// Clean zones immediately.  Does not wait for the result to be polled!
     ///
     /// The returned `Receiver` will deliver notification when cleaning is
     /// complete.  However, there is no requirement to poll it.  The client may
     /// drop it, and cleaning will continue in the background.
     pub fn clean(&self) -> oneshot::Receiver<()> {
-        self.inner.dirty.store(true, Ordering::Relaxed);
-        self.cleaner.clean()
+        match &self.cleaner {
+            Some(cleaner) => {
+                self.inner.dirty.store(true, Ordering::Relaxed);
+                cleaner.clean()
+            },
+            None => {
+                // Read-only pools never get cleaned
+                let (tx, rx) = oneshot::channel();
+                tx.send(()).unwrap();
+                rx
+            }
+        }
     }
 
     /// Construct a new `Database` from its `IDML`.
@@ -332,6 +396,40 @@ impl Database {
         Database::new(idml, forest, handle)
     }
 
+    /// Destroy the pool.
+    ///
+    /// Its labels will be marked as destroyed, so it can't be imported again,
+    /// and all background tasks will be shut down.
+    pub fn destroy(&mut self) -> impl Future<Item=(), Error=Error> + Send {
+        if self.inner.readonly {
+            return future::Either::A(future::err(Error::EROFS));
+        }
+        future::Either::B(self.close(PoolState::Destroyed))
+    }
+
+    /// Discard the pool checkpoint, so the space that it holds may be
+    /// reclaimed.  Fails with `ENOENT` if the pool has no checkpoint.
+    pub fn discard_checkpoint(&self)
+        -> impl Future<Item=(), Error=Error> + Send
+    {
+        self.update_checkpoint(CheckpointOp::Discard)
+    }
+
+    /// Record `state` in the labels, then shut down.
+    ///
+    /// A read-only pool's labels can't be changed, so it just shuts down.
+    fn close(&mut self, state: PoolState)
+        -> impl Future<Item=(), Error=Error> + Send
+    {
+        if !self.inner.readonly {
+            self.inner.idml.set_state(state);
+            self.inner.dirty.store(true, Ordering::Relaxed);
+        }
+        let shutdown_fut = self.shutdown();
+        self.sync_transaction()
+        .and_then(|_| shutdown_fut.map_err(|_| Error::EPIPE))
+    }
+
     /// Dump a YAMLized representation of the given Tree to a plain
     /// `std::fs::File`.
     ///
@@ -351,6 +449,15 @@ impl Database {
         })
     }
 
+    /// Export the pool.
+    ///
+    /// The final transaction sync marks the labels as cleanly exported, then
+    /// all background tasks are shut down.  Once the returned future completes
+    /// the disks are closed, so the pool may be imported again right away.
+    pub fn export(&mut self) -> impl Future<Item=(), Error=Error> + Send {
+        self.close(PoolState::Exported)
+    }
+
     /// Get the value of the `name` property for the dataset identified by
     /// `tree`.
     pub fn get_prop(&self, tree_id: TreeID, name: PropertyName)
@@ -405,16 +512,31 @@ impl Database {
         .map(drop)
     }
 
+    /// Snapshot the I/O statistics of the pool and all of its disks
+    pub fn iostats(&self)
+        -> impl Future<Item=VdevStats, Error=Error> + Send
+    {
+        self.inner.idml.iostats()
+    }
+
+    /// The I/O rate limits applied to each of the pool's disks
+    pub fn limits(&self) -> IoLimits {
+        self.inner.idml.limits()
+    }
+
     /// Create a new, blank filesystem
     ///
     /// Must be called from the tokio domain.
     pub fn new_fs(&self, props: Vec<Property>)
         -> impl Future<Item=TreeID, Error=Error> + Send
     {
+        if self.inner.readonly {
+            return future::Either::A(future::err(Error::EROFS));
+        }
         self.inner.dirty.store(true, Ordering::Relaxed);
         let idml2 = self.inner.idml.clone();
         let inner2 = self.inner.clone();
-        self.inner.fs_trees.with(move |mut guard| {
+        let fut = self.inner.fs_trees.with(move |mut guard| {
             let k = (0..=u32::max_value()).filter(|i| {
                 !guard.contains_key(&TreeID::Fs(*i))
             }).nth(0).expect("Maximum number of filesystems reached");
@@ -478,7 +600,8 @@ impl Database {
                            dataset.insert(dotdot_key, dotdot_value),
                            props_fut)
             }).map(move |_| tree_id)
-        }).unwrap()
+        }).unwrap();
+        future::Either::B(fut)
     }
 
     /// Perform a read-only operation on a Filesystem
@@ -507,9 +630,9 @@ impl Database {
         where E: Clone + Executor + 'static
     {
         let cleaner = Cleaner::new(handle.clone(), idml.clone(), None);
-        let inner = Arc::new(Inner::new(idml, forest));
+        let inner = Arc::new(Inner::new(idml, forest, false));
         let syncer = Syncer::new(handle, inner.clone());
-        Database{cleaner, inner, syncer}
+        Database{cleaner: Some(cleaner), inner, syncer: Some(syncer)}
     }
 
     /// Open an existing `Database`
@@ -518,15 +641,36 @@ impl Database {
     ///
     /// * `idml`:           An already-opened `IDML`
     /// * `label_reader`:   A `LabelReader` that has already consumed all labels
-    ///                     prior to this layer.
+    ///                     prior to this layer.  If it has a rewind target,
+    ///                     the `Database` is opened as of that transaction
+    ///                     group.
+    ///
+    /// If `idml` was opened read-only, then so is the `Database`.  No
+    /// background tasks will be started, and all modifications will fail with
+    /// `EROFS`.
     pub fn open<E>(idml: Arc<IDML>, handle: E, mut label_reader: LabelReader)
         -> Self
         where E: Clone + Executor + 'static
     {
-        let l: Label = label_reader.deserialize().unwrap();
+        let mut l: Label = label_reader.deserialize().unwrap();
+        // The Clusters already checked that the rewind target exists
+        let (_txg, root) = l.forests.select(label_reader.rewind())
+            .expect("Database label lacks the rewind target");
         let forest = Tree::<RID, IDML, TreeID, TreeOnDisk<RID>>::open(
-            idml.clone(), true, l.forest);
-        Database::new(idml, forest, handle)
+            idml.clone(), true, root);
+        let db = if idml.readonly() {
+            let inner = Arc::new(Inner::new(idml, forest, true));
+            Database{cleaner: None, inner, syncer: None}
+        } else {
+            Database::new(idml, forest, handle)
+        };
+        *db.inner.roots.lock().unwrap() = l.forests;
+        db
+    }
+
+    /// Was the pool imported read-only?
+    pub fn readonly(&self) -> bool {
+        self.inner.readonly
     }
 
     fn ro_filesystem(&self, tree_id: TreeID)
@@ -540,6 +684,101 @@ impl Database {
         })
     }
 
+    /// Change the I/O rate limits of each of the pool's disks.
+    ///
+    /// The new limits take effect immediately, and are recorded in the label
+    /// by a transaction sync.
+    pub fn set_limits(&self, limits: IoLimits)
+        -> impl Future<Item=(), Error=Error> + Send
+    {
+        if self.inner.readonly {
+            return future::Either::A(future::err(Error::EROFS));
+        }
+        self.inner.idml.set_limits(limits);
+        self.inner.dirty.store(true, Ordering::Relaxed);
+        future::Either::B(self.sync_transaction())
+    }
+
+    /// Evacuate all data from the `Cluster` with the given UUID, then remove
+    /// it from the pool.
+    ///
+    /// The returned `Stream` reports progress after each zone is evacuated,
+    /// and ends once the `Cluster` is gone from the labels.  If interrupted,
+    /// the removal can be resumed by calling this method again, even after
+    /// reimporting the pool.
+    pub fn remove_cluster(&self, uuid: Uuid)
+        -> impl Stream<Item=RemovalProgress, Error=Error> + Send
+    {
+        // Outline:
+        // 1) Stop allocating from the Cluster, and record that in the label
+        // 2) Finish its open zones, so they can be cleaned
+        // 3) Clean each of its closed zones
+        // 4) Sync, so nothing on disk refers to the Cluster anymore
+        // 5) Drop the Cluster, and rewrite the labels without it
+        let inner2 = self.inner.clone();
+        let inner3 = self.inner.clone();
+        let inner4 = self.inner.clone();
+        let inner5 = self.inner.clone();
+        let start = if self.inner.readonly {
+            Err(Error::EROFS)
+        } else {
+            self.inner.idml.start_removal(uuid)
+        };
+        start.into_future()
+        .and_then(move |cluster| {
+            inner2.dirty.store(true, Ordering::Relaxed);
+            Database::sync_transaction_priv(&inner2)
+            .and_then(move |_| {
+                let idml2 = inner2.idml.clone();
+                inner2.idml.txg()
+                    .map_err(|_| Error::EPIPE)
+                    .and_then(move |txg_guard| {
+                        idml2.finish_zones(cluster, *txg_guard)
+                    }).map(move |_| inner2)
+            }).and_then(move |inner2| {
+                inner2.dirty.store(true, Ordering::Relaxed);
+                Database::sync_transaction_priv(&inner2)
+            }).map(move |_| cluster)
+        }).and_then(move |cluster| {
+            inner3.idml.list_closed_zones()
+            .filter(move |zone| zone.pba.cluster == cluster)
+            .collect()
+            .map(move |zones| (cluster, zones))
+        }).map(move |(cluster, zones)| {
+            let zones_total = zones.len();
+            let cleaning = stream::iter_ok(zones.into_iter().enumerate())
+            .and_then(move |(i, zone)| {
+                let idml2 = inner4.idml.clone();
+                inner4.idml.txg()
+                    .map_err(|_| Error::EPIPE)
+                    .and_then(move |txg_guard| {
+                        idml2.clean_zone(zone, *txg_guard)
+                    }).map(move |_| {
+                        RemovalProgress{zones_done: i + 1, zones_total}
+                    })
+            });
+            let finishing = future::lazy(move || {
+                inner5.dirty.store(true, Ordering::Relaxed);
+                Database::sync_transaction_priv(&inner5)
+                .and_then(move |_| {
+                    inner5.idml.finish_removal(cluster).into_future()
+                    .and_then(move |_| {
+                        inner5.dirty.store(true, Ordering::Relaxed);
+                        Database::sync_transaction_priv(&inner5)
+                    })
+                })
+            }).into_stream()
+            .filter_map(|_| None);
+            cleaning.chain(finishing)
+        }).flatten_stream()
+    }
+
+    /// Return the UUID and remaining allocated space of the `Cluster` that is
+    /// being removed, if any.
+    pub fn removing(&self) -> Option<(Uuid, LbaT)> {
+        self.inner.idml.removing()
+    }
+
     // TODO: Make prop an Option.  A None value will signify that the property
     // should be inherited.
     pub fn set_prop(&self, tree_id: TreeID, prop: Property)
@@ -551,8 +790,11 @@ impl Database {
         //    tell which datasets might be inherited from this one, just
         //    invalidate all cached values for this property.
         // 3) Insert the new value into the propcache.
+        if self.inner.readonly {
+            return future::Either::A(future::err(Error::EROFS));
+        }
         let inner2 = self.inner.clone();
-        self.inner.propcache.with(move |mut guard| {
+        let fut = self.inner.propcache.with(move |mut guard| {
             let name = prop.name();
             let prop2 = prop.clone();
             Inner::fswrite(inner2, tree_id, move |dataset| {
@@ -571,22 +813,39 @@ impl Database {
                 guard.insert(key, (prop2, PropertySource::Local));
                 r
             })
-        }).unwrap()
+        }).unwrap();
+        future::Either::B(fut)
     }
 
     /// Shutdown all background tasks
     pub fn shutdown(&mut self) -> impl Future<Item=(), Error=()> + Send
     {
         let idml2 = self.inner.idml.clone();
-        self.syncer.shutdown()
-        .join(self.cleaner.shutdown())
-        .map(move |_| idml2.shutdown())
+        let syncer_fut = match &self.syncer {
+            Some(syncer) => future::Either::A(syncer.shutdown()),
+            None => future::Either::B(future::ok(()))
+        };
+        let cleaner_fut = match &mut self.cleaner {
+            Some(cleaner) => future::Either::A(cleaner.shutdown()),
+            None => future::Either::B(future::ok(()))
+        };
+        syncer_fut.join(cleaner_fut)
+        .and_then(move |_| idml2.shutdown().map_err(drop))
     }
 
     /// Finish the current transaction group and start a new one.
+    ///
+    /// A no-op for read-only pools.
     pub fn sync_transaction(&self) -> impl Future<Item=(), Error=Error> + Send {
-        self.syncer.kick().join(Database::sync_transaction_priv(&self.inner))
-            .map(drop)
+        match &self.syncer {
+            Some(syncer) => {
+                let fut = syncer.kick()
+                    .join(Database::sync_transaction_priv(&self.inner))
+                    .map(drop);
+                future::Either::A(fut)
+            },
+            None => future::Either::B(future::ok(()))
+        }
     }
 
     fn sync_transaction_priv(inner: &Arc<Inner>)
@@ -628,31 +887,79 @@ impl Database {
                 })
             }).and_then(move |inner3| {
                 Tree::flush(&inner3.forest, txg)
-            }).and_then(move |_| idml2.flush(0, txg).map(move |_| idml2))
+            }).and_then(move |_| idml2.flush(txg).map(move |_| idml2))
             .and_then(move |idml2| idml2.sync_all(txg).map(move |_| idml2))
             .and_then(move |idml2| {
                 let forest = inner2.forest.serialize().unwrap();
-                let label = Label {forest};
-                inner2.write_label(&label, 0, txg)
-                .map(|_| (idml2, label))
-            }).and_then(move |(idml2, label)| {
-                idml2.flush(1, txg).map(move |_| (idml2, label))
-            }).and_then(move |(idml2, label)| {
+                let op = inner2.checkpoint_op.lock().unwrap().take();
+                let label = {
+                    let mut roots = inner2.roots.lock().unwrap();
+                    roots.push(txg, forest);
+                    roots.update_checkpoint(op);
+                    Label {forests: roots.clone()}
+                };
+                inner2.write_label(&label, 0, txg, op)
+                .map(move |_| (idml2, label, op))
+            }).and_then(move |(idml2, label, op)| {
+                idml2.flush(txg).map(move |_| (idml2, label, op))
+            }).and_then(move |(idml2, label, op)| {
                 // The only time we need to read the second label is if we lose
                 // power while writing the first.  The fact that we reached this
                 // point means that that won't happen, at least not until the
                 // _next_ transaction sync.  So we don't need an additional
-                // sync_all between idml2.flush(1, ...) and idml2.sync_all(...).
+                // sync_all between idml2.flush(...) and idml2.sync_all(...).
                 idml2.sync_all(txg)
-                .map(move |_| (idml2, label))
-            }).and_then(move |(idml2, label)| {
-                inner5.write_label(&label, 1, txg)
+                .map(move |_| (idml2, label, op))
+            }).and_then(move |(idml2, label, op)| {
+                inner5.write_label(&label, 1, txg, op)
                 .map(move |_| idml2)
             }).and_then(move |idml2| idml2.sync_all(txg))
         });
         boxfut!(fut)
     }
 
+    /// Create or discard the checkpoint, recording it with a transaction sync
+    fn update_checkpoint(&self, op: CheckpointOp)
+        -> impl Future<Item=(), Error=Error> + Send
+    {
+        if self.inner.readonly {
+            return future::Either::A(future::err(Error::EROFS));
+        }
+        let exists = self.inner.roots.lock().unwrap().checkpoint_txg()
+            .is_some();
+        match op {
+            CheckpointOp::Create if exists => {
+                return future::Either::A(future::err(Error::EEXIST));
+            },
+            // Cleaning couldn't evacuate the checkpointed zones
+            CheckpointOp::Create if self.inner.idml.removing().is_some() => {
+                return future::Either::A(future::err(Error::EBUSY));
+            },
+            CheckpointOp::Discard if !exists => {
+                return future::Either::A(future::err(Error::ENOENT));
+            },
+            _ => ()
+        }
+        *self.inner.checkpoint_op.lock().unwrap() = Some(op);
+        self.inner.dirty.store(true, Ordering::Relaxed);
+        future::Either::B(self.sync_transaction())
+    }
+
+    /// Enable every on-disk feature supported by this version of BFFFS.
+    ///
+    /// The new features are recorded in the label by a transaction sync.  Once
+    /// that completes, older versions of BFFFS may be unable to import the
+    /// pool.
+    pub fn upgrade(&self) -> impl Future<Item=(), Error=Error> + Send {
+        if self.inner.readonly {
+            return future::Either::A(future::err(Error::EROFS));
+        }
+        if self.inner.idml.upgrade() {
+            self.inner.dirty.store(true, Ordering::Relaxed);
+        }
+        future::Either::B(self.sync_transaction())
+    }
+
     /// Perform a read-write operation on a Filesystem
     ///
     /// All operations conducted by the supplied closure will be completed
@@ -732,16 +1039,93 @@ mod database {
     // pet kcov
     #[test]
     fn debug() {
-        let label = Label{forest: TreeOnDisk::default()};
+        let mut forests = TxgRing::default();
+        forests.push(TxgT::from(0), TreeOnDisk::default());
+        let label = Label{forests};
         format!("{:?}", label);
     }
 
+    /// Exporting should record the new state in a final sync, then shut down
+    #[test]
+    fn export() {
+        let mut seq = Sequence::new();
+        let mut idml = IDML::default();
+        let mut forest = Tree::default();
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+
+        idml.expect_set_state()
+            .once()
+            .in_sequence(&mut seq)
+            .with(eq(PoolState::Exported))
+            .return_const(());
+        idml.expect_advance_transaction_inner()
+            .once()
+            .returning(|| TxgT::from(0));
+        forest.expect_flush()
+            .once()
+            .in_sequence(&mut seq)
+            .with(eq(TxgT::from(0)))
+            .returning(|_| {
+                Box::new(future::ok::<(), Error>(()))
+            });
+        idml.expect_flush()
+            .once()
+            .in_sequence(&mut seq)
+            .with(eq(TxgT::from(0)))
+            .returning(|_| Box::new(future::ok::<(), Error>(())));
+        idml.expect_sync_all()
+            .once()
+            .in_sequence(&mut seq)
+            .with(eq(TxgT::from(0)))
+            .returning(|_| Box::new(future::ok::<(), Error>(())));
+        forest.expect_serialize()
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|| {
+                Ok(TreeOnDisk::default())
+            });
+        idml.expect_write_label()
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|_, _| Box::new(future::ok::<(), Error>(())));
+        idml.expect_flush()
+            .once()
+            .in_sequence(&mut seq)
+            .with(eq(TxgT::from(0)))
+            .returning(|_| Box::new(future::ok::<(), Error>(())));
+        idml.expect_sync_all()
+            .once()
+            .in_sequence(&mut seq)
+            .with(eq(TxgT::from(0)))
+            .returning(|_| Box::new(future::ok::<(), Error>(())));
+        idml.expect_write_label()
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|_, _| Box::new(future::ok::<(), Error>(())));
+        idml.expect_sync_all()
+            .once()
+            .in_sequence(&mut seq)
+            .with(eq(TxgT::from(0)))
+            .returning(|_| Box::new(future::ok::<(), Error>(())));
+        idml.expect_shutdown()
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|| Box::new(future::ok::<(), Error>(())));
+
+        rt.block_on(future::lazy(|| {
+            let task_executor = TaskExecutor::current();
+            let mut db = Database::new(Arc::new(idml), forest, task_executor);
+            db.export()
+        })).unwrap();
+    }
+
     #[test]
     fn shutdown() {
         let mut idml = IDML::default();
         idml.expect_shutdown()
             .once()
-            .return_const(());
+            .returning(|| Box::new(future::ok::<(), Error>(())));
         let forest = Tree::default();
 
         let mut rt = current_thread::Runtime::new().unwrap();
@@ -759,7 +1143,7 @@ mod database {
         let mut idml = IDML::default();
         idml.expect_shutdown()
             .times(2)
-            .return_const(());
+            .returning(|| Box::new(future::ok::<(), Error>(())));
         let forest = Tree::default();
 
         let mut rt = current_thread::Runtime::new().unwrap();
@@ -794,8 +1178,8 @@ mod database {
         idml.expect_flush()
             .once()
             .in_sequence(&mut seq)
-            .with(eq(0), eq(TxgT::from(0)))
-            .returning(|_, _| Box::new(future::ok::<(), Error>(())));
+            .with(eq(TxgT::from(0)))
+            .returning(|_| Box::new(future::ok::<(), Error>(())));
         idml.expect_sync_all()
             .once()
             .in_sequence(&mut seq)
@@ -815,8 +1199,8 @@ mod database {
         idml.expect_flush()
             .once()
             .in_sequence(&mut seq)
-            .with(eq(1), eq(TxgT::from(0)))
-            .returning(|_, _| Box::new(future::ok::<(), Error>(())));
+            .with(eq(TxgT::from(0)))
+            .returning(|_| Box::new(future::ok::<(), Error>(())));
         idml.expect_sync_all()
             .once()
             .in_sequence(&mut seq)
@@ -860,6 +1244,26 @@ mod database {
             db.sync_transaction()
         })).unwrap();
     }
+
+    /// Upgrading a pool that already has every supported feature should not
+    /// sync a transaction
+    #[test]
+    fn upgrade_nothing_new() {
+        let mut idml = IDML::default();
+        idml.expect_upgrade()
+            .once()
+            .return_const(false);
+        let forest = Tree::default();
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+
+        rt.block_on(future::lazy(|| {
+            let task_executor = TaskExecutor::current();
+            let db = Database::new(Arc::new(idml), forest, task_executor);
+            db.inner.dirty.store(false, Ordering::Relaxed);
+            db.upgrade()
+        })).unwrap();
+    }
 }
 
 mod syncer_msg {
@@ -24,6 +24,15 @@ cfg_if! {
 pub use self::database::ReadOnlyFilesystem;
 pub use self::database::ReadWriteFilesystem;
 
+/// Progress of an ongoing `Cluster` removal
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct RemovalProgress {
+    /// Number of the `Cluster`'s zones that have been evacuated so far
+    pub zones_done: usize,
+    /// Total number of zones that must be evacuated
+    pub zones_total: usize,
+}
+
 /// Keys into the Forest
 #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, PartialOrd, Ord,
          Serialize)]
@@ -4,7 +4,7 @@ use crate::{
     boxfut,
     common::{
         *,
-        dml::{Compression, DML},
+        dml::{Compression, DML, PriorityClass},
         idml::IDML,
         tree::{Key, Value}
     }
@@ -29,6 +29,10 @@ impl<K: Key, V: Value> Dataset<K, V> {
         self.idml.allocated()
     }
 
+    fn checkpointed(&self) -> LbaT {
+        self.idml.checkpointed()
+    }
+
     fn delete_blob(&self, rid: RID, txg: TxgT)
         -> impl Future<Item=(), Error=Error>
     {
@@ -44,7 +48,8 @@ impl<K: Key, V: Value> Dataset<K, V> {
     fn get_blob(&self, rid: RID)
         -> Box<dyn Future<Item=Box<DivBuf>, Error=Error> + Send>
     {
-        self.idml.get::<DivBufShared, DivBuf>(&rid)
+        self.idml.get::<DivBufShared, DivBuf>(&rid,
+            PriorityClass::Interactive)
     }
 
     fn insert(&self, txg: TxgT, k: K, v: V)
@@ -66,7 +71,7 @@ impl<K: Key, V: Value> Dataset<K, V> {
     fn put_blob(&self, dbs: DivBufShared, compression: Compression, txg: TxgT)
         -> impl Future<Item=RID, Error=Error> + Send
     {
-        self.idml.put(dbs, compression, txg)
+        self.idml.put(dbs, compression, txg, PriorityClass::Normal)
     }
 
     #[cfg(not(test))]
@@ -106,7 +111,12 @@ impl<K: Key, V: Value> Dataset<K, V> {
     fn remove_blob(&self, rid: RID, txg: TxgT)
         -> impl Future<Item=Box<DivBufShared>, Error=Error> + Send
     {
-        self.idml.pop::<DivBufShared, DivBuf>(&rid, txg)
+        self.idml.pop::<DivBufShared, DivBuf>(&rid, txg,
+            PriorityClass::Normal)
+    }
+
+    fn reserved(&self) -> LbaT {
+        self.idml.reserved()
     }
 
     fn size(&self) -> LbaT {
@@ -124,6 +134,10 @@ impl<K: Key, V: Value> ReadOnlyDataset<K, V> {
         self.dataset.allocated()
     }
 
+    pub fn checkpointed(&self) -> LbaT {
+        self.dataset.checkpointed()
+    }
+
     pub fn last_key(&self) -> impl Future<Item=Option<K>, Error=Error> + Send
     {
         self.dataset.last_key()
@@ -133,6 +147,10 @@ impl<K: Key, V: Value> ReadOnlyDataset<K, V> {
         ReadOnlyDataset{dataset: Dataset::new(idml, tree)}
     }
 
+    pub fn reserved(&self) -> LbaT {
+        self.dataset.reserved()
+    }
+
     pub fn size(&self) -> LbaT {
         self.dataset.size()
     }
@@ -19,10 +19,12 @@ use super::*;
 mock! {
     pub ReadOnlyDataset<K: Key, V: Value> {
         fn allocated(&self) -> LbaT;
+        fn checkpointed(&self) -> LbaT;
         fn last_key(&self)
             -> Box<dyn Future<Item=Option<K>, Error=Error> + Send>;
         fn new(idml: Arc<IDML>, tree: Arc<ITree<K, V>>)
             -> ReadOnlyDataset<K, V>;
+        fn reserved(&self) -> LbaT;
         fn size(&self) -> LbaT;
     }
     trait ReadDataset<K: Key, V: Value> {
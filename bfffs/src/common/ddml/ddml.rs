@@ -4,7 +4,10 @@ use crate::{
     common::{
         *,
         cache::{Cache, Cacheable, CacheRef, Key},
+        iostat::VdevStats,
         label::*,
+        pool::{ClusterProxy, PoolState},
+        throttle::IoLimits,
     }
 };
 use futures::{Future, Stream, future, stream};
@@ -35,6 +38,13 @@ pub struct DDML {
 // instead by integration tests.
 #[cfg_attr(test, allow(unused))]
 impl DDML {
+    /// Add a newly created `Cluster` to the `Pool`.
+    pub fn add_cluster(&self, cluster: ClusterProxy)
+        -> impl Future<Item=(), Error=Error> + Send
+    {
+        self.pool.add_cluster(cluster)
+    }
+
     /// How many blocks have been allocated, including blocks that have been
     /// freed but not erased?
     pub fn allocated(&self) -> LbaT {
@@ -47,6 +57,12 @@ impl DDML {
         self.pool.assert_clean_zone(cluster, zone, txg)
     }
 
+    /// Return the amount of freed space, in LBAs, that can't be reused until
+    /// the pool checkpoint is discarded.
+    pub fn checkpointed(&self) -> LbaT {
+        self.pool.checkpointed()
+    }
+
     /// Free a record's storage, ignoring the Cache
     pub fn delete_direct(&self, drp: &DRP, _txg: TxgT)
         -> impl Future<Item=(), Error=Error> + Send
@@ -54,8 +70,22 @@ impl DDML {
         self.pool.free(drp.pba, drp.asize())
     }
 
-    pub fn flush(&self, idx: u32) -> Box<dyn Future<Item=(), Error=Error> + Send> {
-        Box::new(self.pool.flush(idx))
+    /// Drop the `Cluster` that is being removed from the `Pool`.
+    pub fn finish_removal(&self, cluster: ClusterT) -> Result<(), Error> {
+        self.pool.finish_removal(cluster)
+    }
+
+    /// Finish all of the given `Cluster`'s open zones.
+    pub fn finish_zones(&self, cluster: ClusterT, txg: TxgT)
+        -> impl Future<Item=(), Error=Error> + Send
+    {
+        self.pool.finish_zones(cluster, txg)
+    }
+
+    pub fn flush(&self, txg: TxgT)
+        -> Box<dyn Future<Item=(), Error=Error> + Send>
+    {
+        Box::new(self.pool.flush(txg))
     }
 
     pub fn new(pool: Pool, cache: Arc<Mutex<Cache>>) -> Self {
@@ -63,14 +93,26 @@ impl DDML {
     }
 
     /// Get directly from disk, bypassing cache
-    pub fn get_direct<T: Cacheable>(&self, drp: &DRP)
+    pub fn get_direct<T: Cacheable>(&self, drp: &DRP, priority: PriorityClass)
         -> impl Future<Item=Box<T>, Error=Error> + Send
     {
-        self.read(*drp).map(move |dbs| {
+        self.read(*drp, priority).map(move |dbs| {
             Box::new(T::deserialize(dbs))
         })
     }
 
+    /// Snapshot the I/O statistics of the underlying `Pool`
+    pub fn iostats(&self)
+        -> impl Future<Item=VdevStats, Error=Error> + Send
+    {
+        self.pool.iostats()
+    }
+
+    /// The I/O rate limits applied to each of the pool's disks
+    pub fn limits(&self) -> IoLimits {
+        self.pool.limits()
+    }
+
     /// List all closed zones in the `DDML` in no particular order
     pub fn list_closed_zones(&self)
         -> impl Stream<Item=ClosedZone, Error=Error> + Send
@@ -107,7 +149,7 @@ impl DDML {
     }
 
     /// Read a record from disk
-    fn read(&self, drp: DRP)
+    fn read(&self, drp: DRP, priority: PriorityClass)
         -> impl Future<Item=DivBufShared, Error=Error> + Send
     {
         // Outline
@@ -119,7 +161,8 @@ impl DDML {
         let dbs = DivBufShared::uninitialized(len);
         Box::new(
             // Read
-            self.pool.read(dbs.try_mut().unwrap(), drp.pba).and_then(move |_| {
+            self.pool.read(dbs.try_mut().unwrap(), drp.pba, priority)
+            .and_then(move |_| {
                 //Truncate
                 let mut dbm = dbs.try_mut().unwrap();
                 dbm.try_truncate(drp.csize as usize).unwrap();
@@ -155,37 +198,29 @@ impl DDML {
     }
 
     /// Read a record and return ownership of it, bypassing Cache
-    pub fn pop_direct<T: Cacheable>(&self, drp: &DRP)
+    pub fn pop_direct<T: Cacheable>(&self, drp: &DRP, priority: PriorityClass)
         -> impl Future<Item=Box<T>, Error=Error> + Send
     {
         let lbas = drp.asize();
         let pba = drp.pba;
         let pool2 = self.pool.clone();
-        self.read(*drp)
+        self.read(*drp, priority)
             .and_then(move |dbs|
                 pool2.free(pba, lbas)
                 .map(move |_| Box::new(T::deserialize(dbs)))
             )
     }
 
-    /// Does most of the work of DDML::put
-    fn put_common<T>(&self, cacheref: &T, compression: Compression,
-                     txg: TxgT)
-        -> impl Future<Item=DRP, Error=Error> + Send
+    /// Serialize, compress, and checksum a record in preparation for writing
+    /// it.  The returned `DRP` lacks only the `PBA`.
+    fn encode<T>(cacheref: &T, compression: Compression) -> (IoVec, DRP)
         where T: borrow::Borrow<dyn CacheRef>
     {
-        // Outline:
-        // 1) Serialize
-        // 2) Compress
-        // 3) Checksum
-        // 4) Write
-        // 5) Cache
-
         // Serialize
         let serialized = cacheref.borrow().serialize();
         assert!(serialized.len() < u32::max_value() as usize,
             "Record exceeds maximum allowable length");
-        let lsize = serialized.len();
+        let lsize = serialized.len() as u32;
 
         // Compress
         let (compressed_db, compression) = compression.compress(serialized);
@@ -197,24 +232,81 @@ impl DDML {
         checksum_iovec(&compressed_db, &mut hasher);
         let checksum = hasher.finish();
 
-        // Write
-        self.pool.write(compressed_db, txg)
-        .map(move |pba| {
-            DRP { pba, compressed, lsize: lsize as u32, csize, checksum }
-        })
+        let drp = DRP{pba: PBA::default(), compressed, lsize, csize, checksum};
+        (compressed_db, drp)
+    }
+
+    /// Does most of the work of DDML::put
+    fn put_common<T>(&self, cacheref: &T, compression: Compression,
+                     class: WriteClass, txg: TxgT, priority: PriorityClass)
+        -> impl Future<Item=DRP, Error=Error> + Send
+        where T: borrow::Borrow<dyn CacheRef>
+    {
+        // Outline:
+        // 1) Serialize
+        // 2) Compress
+        // 3) Checksum
+        // 4) Write
+        // 5) Cache
+        let (compressed_db, drp) = DDML::encode(cacheref, compression);
+        self.pool.write(compressed_db, class, txg, priority)
+        .map(move |pba| DRP{pba, ..drp})
     }
 
     /// Write a buffer bypassing cache.  Return the same buffer
     pub fn put_direct<T>(&self, cacheref: &T, compression: Compression,
-                         txg: TxgT)
+                         class: WriteClass, txg: TxgT, priority: PriorityClass)
+        -> impl Future<Item=DRP, Error=Error> + Send
+        where T: borrow::Borrow<dyn CacheRef>
+    {
+        self.put_common(cacheref, compression, class, txg, priority)
+    }
+
+    /// Rewrite a record that the cleaner is moving from `old`, bypassing
+    /// cache.
+    ///
+    /// The new copy will be stored on the same tier as the old.
+    pub fn relocate_direct<T>(&self, cacheref: &T, compression: Compression,
+                              old: &DRP, txg: TxgT)
         -> impl Future<Item=DRP, Error=Error> + Send
         where T: borrow::Borrow<dyn CacheRef>
     {
-        self.put_common(cacheref, compression, txg)
+        let (compressed_db, drp) = DDML::encode(cacheref, compression);
+        self.pool.relocate(compressed_db, old.pba.cluster, txg)
+        .map(move |pba| DRP{pba, ..drp})
+    }
+
+    /// Change the I/O rate limits of each of the pool's disks.
+    pub fn set_limits(&self, limits: IoLimits) {
+        self.pool.set_limits(limits)
+    }
+
+    /// Was the pool imported read-only?
+    pub fn readonly(&self) -> bool {
+        self.pool.readonly()
+    }
+
+    /// Return the amount of space, in LBAs, that is held back from ordinary
+    /// data writes.
+    pub fn reserved(&self) -> LbaT {
+        self.pool.reserved()
+    }
+
+    /// Return the UUID and remaining allocated space of the `Cluster` that is
+    /// being removed, if any.
+    pub fn removing(&self) -> Option<(Uuid, LbaT)> {
+        self.pool.removing()
+    }
+
+    /// Change the state that will be recorded in the pool's labels.
+    pub fn set_state(&self, state: PoolState) {
+        self.pool.set_state(state)
     }
 
     /// Shutdown all background tasks.
-    pub fn shutdown(&self) {
+    ///
+    /// The returned future completes once the disks have been closed.
+    pub fn shutdown(&self) -> impl Future<Item=(), Error=Error> + Send {
         self.pool.shutdown()
     }
 
@@ -223,6 +315,19 @@ impl DDML {
         self.pool.size()
     }
 
+    /// Stop allocating from the `Cluster` with the given UUID, in preparation
+    /// for removing it from the `Pool`.
+    pub fn start_removal(&self, uuid: Uuid) -> Result<ClusterT, Error> {
+        self.pool.start_removal(uuid)
+    }
+
+    /// Enable every on-disk feature supported by this version of BFFFS.
+    ///
+    /// Returns `true` if any new features were enabled.
+    pub fn upgrade(&self) -> bool {
+        self.pool.upgrade()
+    }
+
     pub fn write_label(&self, labeller: LabelWriter)
         -> impl Future<Item=(), Error=Error> + Send
     {
@@ -244,7 +349,8 @@ impl DML for DDML {
         self.cache.lock().unwrap().remove(&Key::PBA(drp.pba));
     }
 
-    fn get<T: Cacheable, R: CacheRef>(&self, drp: &DRP)
+    fn get<T: Cacheable, R: CacheRef>(&self, drp: &DRP,
+                                      priority: PriorityClass)
         -> Box<dyn Future<Item=Box<R>, Error=Error> + Send>
     {
         // Outline:
@@ -256,7 +362,8 @@ impl DML for DDML {
         }).unwrap_or_else(|| {
             let cache2 = self.cache.clone();
             Box::new(
-                self.get_direct(drp).map(move |cacheable: Box<T>| {
+                self.get_direct(drp, priority)
+                .map(move |cacheable: Box<T>| {
                     let r = cacheable.make_ref();
                     cache2.lock().unwrap().insert(Key::PBA(pba), cacheable);
                     r.downcast::<R>().unwrap()
@@ -265,7 +372,8 @@ impl DML for DDML {
         })
     }
 
-    fn pop<T: Cacheable, R: CacheRef>(&self, drp: &DRP, _txg: TxgT)
+    fn pop<T: Cacheable, R: CacheRef>(&self, drp: &DRP, _txg: TxgT,
+                                      priority: PriorityClass)
         -> Box<dyn Future<Item=Box<T>, Error=Error> + Send>
     {
         let lbas = drp.asize();
@@ -274,17 +382,18 @@ impl DML for DDML {
             let t = cacheable.downcast::<T>().unwrap();
             boxfut!(self.pool.free(pba, lbas).map(|_| t))
         }).unwrap_or_else(|| {
-            boxfut!( self.pop_direct::<T>(drp))
+            boxfut!( self.pop_direct::<T>(drp, priority))
         })
     }
 
     fn put<T: Cacheable>(&self, cacheable: T, compression: Compression,
-                             txg: TxgT)
+                             txg: TxgT, priority: PriorityClass)
         -> Box<dyn Future<Item=DRP, Error=Error> + Send>
     {
         let cache2 = self.cache.clone();
         let db = cacheable.make_ref();
-        let fut = self.put_common(&db, compression, txg)
+        let class = cacheable.write_class();
+        let fut = self.put_common(&db, compression, class, txg, priority)
             .map(move |drp|{
                 let pba = drp.pba();
                 cache2.lock().unwrap()
@@ -305,26 +414,47 @@ impl DML for DDML {
 #[cfg(test)]
 mock! {
     pub DDML {
+        fn add_cluster(&self, cluster: ClusterProxy)
+            -> Box<dyn Future<Item=(), Error=Error> + Send>;
         fn allocated(&self) -> LbaT;
         fn assert_clean_zone(&self, cluster: ClusterT, zone: ZoneT, txg: TxgT);
+        fn checkpointed(&self) -> LbaT;
         fn delete_direct(&self, drp: &DRP, txg: TxgT)
             -> Box<dyn Future<Item=(), Error=Error> + Send>;
-        fn flush(&self, idx: u32)
+        fn finish_removal(&self, cluster: ClusterT) -> Result<(), Error>;
+        fn finish_zones(&self, cluster: ClusterT, txg: TxgT)
+            -> Box<dyn Future<Item=(), Error=Error> + Send>;
+        fn flush(&self, txg: TxgT)
             -> Box<dyn Future<Item=(), Error=Error> + Send>;
         fn new(pool: Pool, cache: Arc<Mutex<Cache>>) -> Self;
-        fn get_direct<T: Cacheable>(&self, drp: &DRP)
+        fn get_direct<T: Cacheable>(&self, drp: &DRP, priority: PriorityClass)
             -> Box<dyn Future<Item=Box<T>, Error=Error> + Send>;
+        fn iostats(&self)
+            -> Box<dyn Future<Item=VdevStats, Error=Error> + Send>;
+        fn limits(&self) -> IoLimits;
         fn list_closed_zones(&self)
             -> Box<dyn Stream<Item=ClosedZone, Error=Error> + Send>;
         fn open(pool: Pool, cache: Arc<Mutex<Cache>>) -> Self;
-        fn pop_direct<T: Cacheable>(&self, drp: &DRP)
+        fn pop_direct<T: Cacheable>(&self, drp: &DRP, priority: PriorityClass)
             -> Box<dyn Future<Item=Box<T>, Error=Error> + Send>;
         fn put_direct<T: 'static>(&self, cacheref: &T, compression: Compression,
-                         txg: TxgT)
+                         class: WriteClass, txg: TxgT, priority: PriorityClass)
+            -> Box<dyn Future<Item=DRP, Error=Error> + Send>
+            where T: borrow::Borrow<dyn CacheRef>;
+        fn relocate_direct<T: 'static>(&self, cacheref: &T,
+                                       compression: Compression, old: &DRP,
+                                       txg: TxgT)
             -> Box<dyn Future<Item=DRP, Error=Error> + Send>
             where T: borrow::Borrow<dyn CacheRef>;
-        fn shutdown(&self);
+        fn set_limits(&self, limits: IoLimits);
+        fn readonly(&self) -> bool;
+        fn reserved(&self) -> LbaT;
+        fn removing(&self) -> Option<(Uuid, LbaT)>;
+        fn set_state(&self, state: PoolState);
+        fn shutdown(&self) -> Box<dyn Future<Item=(), Error=Error> + Send>;
         fn size(&self) -> LbaT;
+        fn start_removal(&self, uuid: Uuid) -> Result<ClusterT, Error>;
+        fn upgrade(&self) -> bool;
         fn write_label(&self, labeller: LabelWriter)
             -> Box<dyn Future<Item=(), Error=Error> + Send>;
     }
@@ -334,12 +464,14 @@ mock! {
         fn delete(&self, addr: &DRP, txg: TxgT)
             -> Box<dyn Future<Item=(), Error=Error> + Send>;
         fn evict(&self, addr: &DRP);
-        fn get<T: Cacheable, R: CacheRef>(&self, addr: &DRP)
+        fn get<T: Cacheable, R: CacheRef>(&self, addr: &DRP,
+                                          priority: PriorityClass)
             -> Box<dyn Future<Item=Box<R>, Error=Error> + Send>;
-        fn pop<T: Cacheable, R: CacheRef>(&self, rid: &DRP, txg: TxgT)
+        fn pop<T: Cacheable, R: CacheRef>(&self, rid: &DRP, txg: TxgT,
+                                          priority: PriorityClass)
             -> Box<dyn Future<Item=Box<T>, Error=Error> + Send>;
         fn put<T: Cacheable>(&self, cacheable: T, compression: Compression,
-                                 txg: TxgT)
+                                 txg: TxgT, priority: PriorityClass)
             -> Box<dyn Future<Item=DRP, Error=Error> + Send>;
         fn sync_all(&self, txg: TxgT)
             -> Box<dyn Future<Item=(), Error=Error> + Send>;
@@ -444,8 +576,10 @@ mod ddml {
         let cache = Cache::default();
         let mut pool = Pool::default();
         pool.expect_read()
-            .withf(|dbm, pba| dbm.len() == 4096 && *pba == PBA::default())
-            .returning(|mut dbm, _pba| {
+            .withf(|dbm, pba, priority| dbm.len() == 4096 &&
+                   *pba == PBA::default() &&
+                   *priority == PriorityClass::Background)
+            .returning(|mut dbm, _pba, _priority| {
                 for x in dbm.iter_mut() {
                     *x = 0;
                 }
@@ -453,7 +587,8 @@ mod ddml {
             });
 
         let ddml = DDML::new(pool, Arc::new(Mutex::new(cache)));
-        ddml.get_direct::<DivBufShared>(&drp).wait().unwrap();
+        ddml.get_direct::<DivBufShared>(&drp, PriorityClass::Background)
+            .wait().unwrap();
     }
 
     #[test]
@@ -472,7 +607,8 @@ mod ddml {
             });
 
         let ddml = DDML::new(pool, Arc::new(Mutex::new(cache)));
-        ddml.get::<DivBufShared, DivBuf>(&drp).wait().unwrap();
+        ddml.get::<DivBufShared, DivBuf>(&drp, PriorityClass::Normal).wait()
+            .unwrap();
     }
 
     #[test]
@@ -493,10 +629,12 @@ mod ddml {
             .with(eq(Key::PBA(pba)))
             .return_const(None);
         pool.expect_read()
-            .withf(|dbm, pba| dbm.len() == 4096 && *pba == PBA::default())
+            .withf(|dbm, pba, priority| dbm.len() == 4096 &&
+                   *pba == PBA::default() &&
+                   *priority == PriorityClass::Interactive)
             .once()
             .in_sequence(&mut seq)
-            .returning(|mut dbm, _pba| {
+            .returning(|mut dbm, _pba, _priority| {
                 for x in dbm.iter_mut() {
                     *x = 0;
                 }
@@ -511,7 +649,8 @@ mod ddml {
             });
 
         let ddml = DDML::new(pool, Arc::new(Mutex::new(cache)));
-        ddml.get::<DivBufShared, DivBuf>(&drp).wait().unwrap();
+        ddml.get::<DivBufShared, DivBuf>(&drp, PriorityClass::Interactive)
+            .wait().unwrap();
     }
 
     #[test]
@@ -526,11 +665,13 @@ mod ddml {
             .with(eq(Key::PBA(pba)))
             .return_const(None);
         pool.expect_read()
-            .withf(|dbm, pba| dbm.len() == 4096 && *pba == PBA::default())
-            .return_once(|_, _| Box::new(future::ok::<(), Error>(())));
+            .withf(|dbm, pba, _priority| dbm.len() == 4096 &&
+                   *pba == PBA::default())
+            .return_once(|_, _, _| Box::new(future::ok::<(), Error>(())));
 
         let ddml = DDML::new(pool, Arc::new(Mutex::new(cache)));
-        let err = ddml.get::<DivBufShared, DivBuf>(&drp).wait().unwrap_err();
+        let err = ddml.get::<DivBufShared, DivBuf>(&drp, PriorityClass::Normal)
+            .wait().unwrap_err();
         assert_eq!(err, Error::ECKSUM);
     }
 
@@ -607,7 +748,9 @@ mod ddml {
             .return_once(|_, _| Box::new(Ok(()).into_future()));
 
         let ddml = DDML::new(pool, Arc::new(Mutex::new(cache)));
-        ddml.pop::<DivBufShared, DivBuf>(&drp, TxgT::from(0)).wait().unwrap();
+        ddml.pop::<DivBufShared, DivBuf>(&drp, TxgT::from(0),
+                                         PriorityClass::Normal)
+            .wait().unwrap();
     }
 
     #[test]
@@ -623,10 +766,10 @@ mod ddml {
             .with(eq(Key::PBA(pba)))
             .return_once(|_| None);
         pool.expect_read()
-            .with(always(), eq(pba))
+            .with(always(), eq(pba), eq(PriorityClass::Normal))
             .once()
             .in_sequence(&mut seq)
-            .returning(|mut dbm, _pba| {
+            .returning(|mut dbm, _pba, _priority| {
                 for x in dbm.iter_mut() {
                     *x = 0;
                 }
@@ -639,7 +782,9 @@ mod ddml {
             .return_once(|_, _| Box::new(Ok(()).into_future()));
 
         let ddml = DDML::new(pool, Arc::new(Mutex::new(cache)));
-        ddml.pop::<DivBufShared, DivBuf>(&drp, TxgT::from(0)).wait().unwrap();
+        ddml.pop::<DivBufShared, DivBuf>(&drp, TxgT::from(0),
+                                         PriorityClass::Normal)
+            .wait().unwrap();
     }
 
     #[test]
@@ -654,12 +799,13 @@ mod ddml {
             .with(eq(Key::PBA(pba)))
             .return_once(|_| None);
         pool.expect_read()
-            .with(always(), eq(pba))
-            .return_once(|_, _| Box::new(future::ok::<(), Error>(())));
+            .with(always(), eq(pba), always())
+            .return_once(|_, _, _| Box::new(future::ok::<(), Error>(())));
 
         let ddml = DDML::new(pool, Arc::new(Mutex::new(cache)));
-        let err = ddml.pop::<DivBufShared, DivBuf>(&drp, TxgT::from(0)).wait()
-            .unwrap_err();
+        let err = ddml.pop::<DivBufShared, DivBuf>(&drp, TxgT::from(0),
+                                                   PriorityClass::Normal)
+            .wait().unwrap_err();
         assert_eq!(err, Error::ECKSUM);
     }
 
@@ -672,10 +818,10 @@ mod ddml {
         let cache = Cache::default();
         let mut pool = Pool::default();
         pool.expect_read()
-            .with(always(), eq(pba))
+            .with(always(), eq(pba), eq(PriorityClass::Normal))
             .once()
             .in_sequence(&mut seq)
-            .returning(|mut dbm, _pba| {
+            .returning(|mut dbm, _pba, _priority| {
                 for x in dbm.iter_mut() {
                     *x = 0;
                 }
@@ -688,7 +834,8 @@ mod ddml {
             .return_once(|_, _| Box::new(Ok(()).into_future()));
 
         let ddml = DDML::new(pool, Arc::new(Mutex::new(cache)));
-        ddml.pop_direct::<DivBufShared>(&drp).wait().unwrap();
+        ddml.pop_direct::<DivBufShared>(&drp, PriorityClass::Normal).wait()
+            .unwrap();
     }
 
     #[test]
@@ -701,12 +848,16 @@ mod ddml {
             .return_const(());
         let mut pool = Pool::default();
         pool.expect_write()
-            .with(always(), eq(TxgT::from(42)))
-            .return_once(move |_, _| Box::new(future::ok::<PBA, Error>(pba)));
+            .with(always(), eq(WriteClass::Data), eq(TxgT::from(42)),
+                  eq(PriorityClass::Normal))
+            .return_once(move |_, _, _, _|
+                Box::new(future::ok::<PBA, Error>(pba))
+            );
 
         let ddml = DDML::new(pool, Arc::new(Mutex::new(cache)));
         let dbs = DivBufShared::from(vec![42u8; 4096]);
-        let drp = ddml.put(dbs, Compression::None, TxgT::from(42))
+        let drp = ddml.put(dbs, Compression::None, TxgT::from(42),
+                           PriorityClass::Normal)
             .wait()
             .unwrap();
         assert!(!drp.is_compressed());
@@ -726,12 +877,16 @@ mod ddml {
             .return_const(());
         let mut pool = Pool::default();
         pool.expect_write()
-            .with(always(), eq(TxgT::from(42)))
-            .return_once(move |_, _| Box::new(future::ok::<PBA, Error>(pba)));
+            .with(always(), eq(WriteClass::Data), eq(TxgT::from(42)),
+                  eq(PriorityClass::Normal))
+            .return_once(move |_, _, _, _|
+                Box::new(future::ok::<PBA, Error>(pba))
+            );
 
         let ddml = DDML::new(pool, Arc::new(Mutex::new(cache)));
         let dbs = DivBufShared::from(vec![42u8; 8192]);
-        let drp = ddml.put(dbs, Compression::Zstd(None), TxgT::from(42))
+        let drp = ddml.put(dbs, Compression::Zstd(None), TxgT::from(42),
+                           PriorityClass::Normal)
             .wait()
             .unwrap();
         assert!(drp.is_compressed());
@@ -752,15 +907,19 @@ mod ddml {
             .return_const(());
         let mut pool = Pool::default();
         pool.expect_write()
-            .with(always(), eq(TxgT::from(42)))
-            .return_once(move |_, _| Box::new(future::ok::<PBA, Error>(pba)));
+            .with(always(), eq(WriteClass::Data), eq(TxgT::from(42)),
+                  eq(PriorityClass::Normal))
+            .return_once(move |_, _, _, _|
+                Box::new(future::ok::<PBA, Error>(pba))
+            );
 
         let ddml = DDML::new(pool, Arc::new(Mutex::new(cache)));
         let mut rng = XorShiftRng::seed_from_u64(12345);
         let mut v = vec![0u8; 8192];
         rng.fill_bytes(&mut v[..]);
         let dbs = DivBufShared::from(v);
-        let drp = ddml.put(dbs, Compression::Zstd(None), TxgT::from(42))
+        let drp = ddml.put(dbs, Compression::Zstd(None), TxgT::from(42),
+                           PriorityClass::Normal)
             .wait()
             .unwrap();
         assert!(!drp.is_compressed());
@@ -779,12 +938,16 @@ mod ddml {
             .return_const(());
         let mut pool = Pool::default();
         pool.expect_write()
-            .with(always(), eq(TxgT::from(42)))
-            .return_once(move |_, _| Box::new(future::ok::<PBA, Error>(pba)));
+            .with(always(), eq(WriteClass::Data), eq(TxgT::from(42)),
+                  eq(PriorityClass::Normal))
+            .return_once(move |_, _, _, _|
+                Box::new(future::ok::<PBA, Error>(pba))
+            );
 
         let ddml = DDML::new(pool, Arc::new(Mutex::new(cache)));
         let dbs = DivBufShared::from(vec![42u8; 1024]);
-        let drp = ddml.put(dbs, Compression::None, TxgT::from(42))
+        let drp = ddml.put(dbs, Compression::None, TxgT::from(42),
+                           PriorityClass::Normal)
             .wait()
             .unwrap();
         assert_eq!(drp.pba, pba);
@@ -799,13 +962,44 @@ mod ddml {
         let mut pool = Pool::default();
         let txg = TxgT::from(42);
         pool.expect_write()
-            .with(always(), eq(txg))
-            .return_once(move |_, _| Box::new(future::ok::<PBA, Error>(pba)));
+            .with(always(), eq(WriteClass::Relocation), eq(txg),
+                  eq(PriorityClass::Background))
+            .return_once(move |_, _, _, _|
+                Box::new(future::ok::<PBA, Error>(pba))
+            );
 
         let ddml = DDML::new(pool, Arc::new(Mutex::new(cache)));
         let dbs = DivBufShared::from(vec![42u8; 4096]);
         let db = Box::new(dbs.try_const().unwrap()) as Box<dyn CacheRef>;
-        let drp = ddml.put_direct(&db, Compression::None, txg).wait().unwrap();
+        let class = WriteClass::Relocation;
+        let drp = ddml.put_direct(&db, Compression::None, class, txg,
+                                  PriorityClass::Background)
+            .wait()
+            .unwrap();
+        assert_eq!(drp.pba, pba);
+        assert_eq!(drp.csize, 4096);
+        assert_eq!(drp.lsize, 4096);
+    }
+
+    #[test]
+    fn relocate_direct() {
+        let cache = Cache::default();
+        let old = DRP{pba: PBA::new(1, 100), ..DRP::default()};
+        let pba = PBA::new(1, 200);
+        let mut pool = Pool::default();
+        let txg = TxgT::from(42);
+        pool.expect_relocate()
+            .with(always(), eq(1), eq(txg))
+            .return_once(move |_, _, _|
+                Box::new(future::ok::<PBA, Error>(pba))
+            );
+
+        let ddml = DDML::new(pool, Arc::new(Mutex::new(cache)));
+        let dbs = DivBufShared::from(vec![42u8; 4096]);
+        let db = Box::new(dbs.try_const().unwrap()) as Box<dyn CacheRef>;
+        let drp = ddml.relocate_direct(&db, Compression::None, &old, txg)
+            .wait()
+            .unwrap();
         assert_eq!(drp.pba, pba);
         assert_eq!(drp.csize, 4096);
         assert_eq!(drp.lsize, 4096);
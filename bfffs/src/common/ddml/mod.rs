@@ -9,7 +9,7 @@ use cfg_if::cfg_if;
 use crate::common::*;
 #[cfg(test)] use rand::{self, Rng};
 
-pub use crate::common::dml::{Compression, DML};
+pub use crate::common::dml::{Compression, DML, PriorityClass};
 pub use crate::common::pool::ClosedZone;
 
 mod ddml;
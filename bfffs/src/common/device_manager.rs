@@ -2,8 +2,8 @@
 
 #[cfg(not(test))]
 use crate::common::vdev::Vdev;
-use crate::common::{Error, Uuid, cache, database, ddml, idml, label, pool,
-                    raid};
+use crate::common::{Error, TxgT, Uuid, cache, cluster, database, ddml, idml,
+                    label::{self, REWIND_TXGS}, pool, raid};
 use futures::{
     Future,
     Stream,
@@ -13,13 +13,15 @@ use futures::{
 };
 use std::{
     collections::BTreeMap,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex, MutexGuard}
 };
 #[cfg(not(test))]
 use std::{
     borrow::ToOwned,
-    path::Path
+    fs::{self, OpenOptions},
+    io::{Seek, SeekFrom},
+    os::unix::fs::{FileTypeExt, OpenOptionsExt}
 };
 #[cfg(not(test))]
 use tokio::runtime::current_thread;
@@ -39,9 +41,28 @@ use tokio::executor::{self, DefaultExecutor, Executor};
 
 #[derive(Default)]
 struct Inner {
+    /// Transaction group of each pool's checkpoint, for pools that have one
+    checkpoints: BTreeMap<Uuid, TxgT>,
     leaves: BTreeMap<Uuid, PathBuf>,
     raids: BTreeMap<Uuid, raid::Label>,
+    /// Transaction group of the newest label seen for each raid.  A disk that
+    /// missed some transaction groups still carries its old labels.
+    raid_txgs: BTreeMap<Uuid, TxgT>,
     pools: BTreeMap<Uuid, pool::Label>,
+    /// Transaction group of the newest label seen for each pool.  Disks that
+    /// were removed from a pool still carry its old labels.
+    pool_txgs: BTreeMap<Uuid, TxgT>,
+    /// Import pools even if they appear to be in use by another host
+    force: bool,
+    /// Import pools read-only
+    readonly: bool,
+    /// Import pools as of the newest recent transaction group that passes a
+    /// consistency check
+    rewind: bool,
+    /// Import pools as of their checkpoints
+    rewind_to_checkpoint: bool,
+    /// Directories that `scan` searches in addition to `/dev`
+    search_path: Vec<PathBuf>,
 }
 
 #[derive(Default)]
@@ -50,6 +71,13 @@ pub struct DevManager {
 }
 
 impl DevManager {
+    /// Search `dir` for devices whenever `scan` is called, in addition to
+    /// `/dev`.
+    pub fn add_search_path<P: AsRef<Path>>(&self, dir: P) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.search_path.push(dir.as_ref().to_owned());
+    }
+
     /// Import a pool by its pool name
     // It would be nice to return a Future instead of a Result<Future>.  But if
     // we do that, then we can't automatically infer whether the result should
@@ -87,23 +115,167 @@ impl DevManager {
     }
 
     /// Import a pool that is already known to exist
+    ///
+    /// Fails with `EBUSY` if the pool is still active on a different host,
+    /// unless forced.  An active pool last imported by this host is assumed to
+    /// be left over from a crash.  Fails with `ENXIO` if any cluster is missing
+    /// more disks than its redundancy can cover.
+    ///
+    /// A pool imported read-write also locks its disks, so a second import on
+    /// this host fails with `EBUSY` too, even if forced.  A pool imported
+    /// read-only is never written, so it isn't claimed or locked.
+    ///
+    /// When rewinding, each of the last few transaction groups is imported
+    /// read-only and checked, newest first.  The pool is then imported as of
+    /// the first one that passes, discarding anything newer, and any zones
+    /// written since are reset.  Fails with `ECKSUM` if none do.
+    ///
+    /// When rewinding to the checkpoint, everything written since the
+    /// checkpoint is discarded.  The checkpoint itself is kept.  Fails with
+    /// `ENOENT` if the pool has no checkpoint.
     fn import<E>(&self, uuid: Uuid, handle: E, inner: MutexGuard<Inner>)
         -> impl Future<Item = database::Database, Error = Error>
         where E: Clone + Executor + 'static
     {
+        let label = &inner.pools[&uuid];
+        // Pools without the MULTIHOST feature don't record their host
+        if label.state == pool::PoolState::Active &&
+            label.hostid != 0 &&
+            label.hostid != pool::hostid() &&
+            !inner.force
+        {
+            return future::Either::A(future::err(Error::EBUSY));
+        }
+        let available = label.children.iter()
+            .filter(|child_uuid| !child_uuid.is_nil())
+            .all(|child_uuid| match inner.raids.get(child_uuid) {
+                Some(rl) => {
+                    let missing = rl.iter_children()
+                        .filter(|leaf| !inner.leaves.contains_key(leaf) ||
+                                rl.is_faulted(leaf))
+                        .count();
+                    missing <= rl.redundancy() as usize
+                },
+                None => false
+            });
+        if !available {
+            return future::Either::A(future::err(Error::ENXIO));
+        }
+        let checkpoint = inner.checkpoints.get(&uuid).cloned();
+        if inner.rewind_to_checkpoint && checkpoint.is_none() {
+            return future::Either::A(future::err(Error::ENOENT));
+        }
+        let readonly = inner.readonly;
+        let rewind = inner.rewind;
+        let rewind_to_checkpoint = inner.rewind_to_checkpoint;
+        let newest_txg = inner.pool_txgs[&uuid];
         let (_pool, raids, mut leaves) = self.open_labels(uuid, inner);
-        let proxies = raids.into_iter().map(move |raid| {
-            let leaf_paths: Vec<PathBuf> = leaves.remove(&raid.uuid()).unwrap();
+        let clusters = raids.into_iter()
+            .map(|raid| (raid.uuid(), leaves.remove(&raid.uuid()).unwrap()))
+            .collect::<Vec<_>>();
+        let fut = if rewind_to_checkpoint {
+            let fut = DevManager::open_pool(uuid, clusters, readonly,
+                                            checkpoint, handle);
+            future::Either::A(future::Either::A(fut))
+        } else if rewind {
+            let clusters2 = clusters.clone();
+            let handle2 = handle.clone();
+            let fut = DevManager::find_rewind_txg(uuid, clusters2, newest_txg,
+                                                  handle2)
+            .and_then(move |txg| {
+                DevManager::open_pool(uuid, clusters, readonly, Some(txg),
+                                      handle)
+            });
+            future::Either::A(future::Either::B(fut))
+        } else {
+            let fut = DevManager::open_pool(uuid, clusters, readonly, None,
+                                            handle);
+            future::Either::B(fut)
+        }.and_then(|db| {
+            // Claim the pool right away, so other hosts won't import it.
+            // This is a no-op for read-only imports.
+            db.sync_transaction().map(move |_| db)
+        });
+        future::Either::B(fut)
+    }
+
+    /// Import a pool read-only and run a consistency check on it, then shut it
+    /// down.  Returns `false` if the pool can't be imported at all.
+    fn check_pool<E>(uuid: Uuid, clusters: Vec<(Uuid, Vec<PathBuf>)>,
+                     txg: TxgT, handle: E)
+        -> impl Future<Item=bool, Error=Error>
+        where E: Clone + Executor + 'static
+    {
+        DevManager::open_pool(uuid, clusters, true, Some(txg), handle)
+        .and_then(|mut db| {
+            let (tx, rx) = oneshot::channel();
+            // Database::check's Future isn't Send, so run it in its own task
+            DefaultExecutor::current().spawn(Box::new(future::lazy(move || {
+                let fut = db.check()
+                .then(move |r| {
+                    db.shutdown().map(move |_| {
+                        tx.send(r.unwrap_or(false))
+                            .ok().expect("channel dropped too soon");
+                    })
+                });
+                executor::current_thread::TaskExecutor::current().spawn_local(
+                    Box::new(fut)
+                ).map_err(Error::unhandled)
+            }))
+            ).expect("DefaultExecutor::spawn failed");
+            rx.map_err(|_| Error::EPIPE)
+        }).or_else(|_| Ok::<bool, Error>(false))
+    }
+
+    /// Find the newest of the pool's last few transaction groups that passes
+    /// a consistency check.
+    fn find_rewind_txg<E>(uuid: Uuid, clusters: Vec<(Uuid, Vec<PathBuf>)>,
+                          newest_txg: TxgT, handle: E)
+        -> impl Future<Item=TxgT, Error=Error>
+        where E: Clone + Executor + 'static
+    {
+        let newest: u32 = newest_txg.into();
+        let candidates = (0..REWIND_TXGS as u32)
+            .filter_map(move |i| newest.checked_sub(i))
+            .map(TxgT::from)
+            .collect::<Vec<_>>();
+        stream::iter_ok(candidates)
+        .and_then(move |txg| {
+            DevManager::check_pool(uuid, clusters.clone(), txg, handle.clone())
+            .map(move |passed| (txg, passed))
+        }).filter(|(_txg, passed)| *passed)
+        .into_future()
+        .map_err(|(e, _)| e)
+        .and_then(|(first, _)| {
+            first.map(|(txg, _)| txg).ok_or(Error::ECKSUM)
+        })
+    }
+
+    /// Open all of a pool's clusters, and then the rest of the pool on top.
+    ///
+    /// Each element of `clusters` is a cluster's UUID and the paths of its
+    /// leaf devices.  If `rewind` is set, the pool is opened as of that
+    /// transaction group.
+    fn open_pool<E>(uuid: Uuid, clusters: Vec<(Uuid, Vec<PathBuf>)>,
+                    readonly: bool, rewind: Option<TxgT>, handle: E)
+        -> impl Future<Item = database::Database, Error = Error>
+        where E: Clone + Executor + 'static
+    {
+        let proxies = clusters.into_iter().map(move |(cuuid, leaf_paths)| {
             let (tx, rx) = oneshot::channel();
             // The top-level Executor spawn puts each Cluster onto a different
             // thread, when using tokio-io-pool
             DefaultExecutor::current().spawn(Box::new(future::lazy(move || {
-                let fut = DevManager::open_cluster(leaf_paths, raid.uuid())
-                .map(move |(cluster, reader)| {
-                    let proxy = pool::ClusterProxy::new(cluster);
-                    tx.send((proxy, reader))
-                        .ok().expect("channel dropped too soon");
-                }).map_err(Error::unhandled);
+                // Send back errors too.  The rewind target may be missing.
+                let fut = DevManager::open_cluster(leaf_paths, cuuid,
+                                                   readonly, rewind)
+                .then(move |r| {
+                    let r = r.map(|(cluster, reader)| {
+                        (pool::ClusterProxy::new(cluster), reader)
+                    });
+                    tx.send(r).ok().expect("channel dropped too soon");
+                    Ok::<(), ()>(())
+                });
                 executor::current_thread::TaskExecutor::current().spawn_local(
                     Box::new(fut)
                 ).map_err(Error::unhandled)
@@ -112,8 +284,9 @@ impl DevManager {
             rx
         });
         future::join_all(proxies).map_err(|_| Error::EPIPE)
+            .and_then(|results| results.into_iter().collect::<Result<_, _>>())
             .and_then(move |proxies| {
-                Pool::open(Some(uuid), proxies)
+                Pool::open(Some(uuid), proxies, readonly)
             }).map(|(pool, label_reader)| {
                 let cache = cache::Cache::with_capacity(1_000_000_000);
                 let arc_cache = Arc::new(Mutex::new(cache));
@@ -133,7 +306,7 @@ impl DevManager {
         let (_pool, raids, mut leaves) = self.open_labels(uuid, inner);
         let cfuts = raids.into_iter().map(move |raid| {
             let leaf_paths = leaves.remove(&raid.uuid()).unwrap();
-            DevManager::open_cluster(leaf_paths, raid.uuid())
+            DevManager::open_cluster(leaf_paths, raid.uuid(), true, None)
             .map(|(cluster, _reader)| cluster)
         });
         future::join_all(cfuts)
@@ -148,14 +321,54 @@ impl DevManager {
             }).collect::<Vec<_>>()
     }
 
-    fn open_cluster(leaf_paths: Vec<PathBuf>, uuid: Uuid)
+    /// Erase all BFFFS labels from the device at `p`.
+    ///
+    /// Refuses with `EBUSY` if the device belongs to an active pool, unless
+    /// `force` is set.
+    // Disable in test mode because MockVdevFile::Open requires P: 'static
+    #[cfg(not(test))]
+    pub fn labelclear<P: AsRef<Path>>(p: P, force: bool) -> Result<(), Error> {
+        let mut rt = current_thread::Runtime::new().unwrap();
+        rt.block_on(future::lazy(move || {
+            VdevFile::open(p)
+                .and_then(move |(vdev_file, mut reader)| {
+                    let active = raid::Label::read(&mut reader).is_ok()
+                        && reader.deserialize::<cluster::Label>().is_ok()
+                        && pool::Label::read(&mut reader)
+                            .map(|pl| pl.state == pool::PoolState::Active)
+                            .unwrap_or(false);
+                    if active && !force {
+                        future::Either::A(future::err(Error::EBUSY))
+                    } else {
+                        let fut = vdev_file.clear_labels();
+                        future::Either::B(fut.map(move |_| drop(vdev_file)))
+                    }
+                })
+        }))
+    }
+
+    /// Open one of a pool's clusters.  Unless `readonly`, its leaf devices
+    /// are locked against other imports.
+    fn open_cluster(leaf_paths: Vec<PathBuf>, uuid: Uuid, readonly: bool,
+                    rewind: Option<TxgT>)
         -> impl Future<Item=(Cluster, label::LabelReader), Error=Error>
     {
-        DevManager::open_vdev_blocks(leaf_paths)
+        DevManager::open_vdev_blocks(leaf_paths, !readonly)
         .and_then(move |vdev_blocks| {
-            let (vdev_raid_api, reader) = raid::open(Some(uuid), vdev_blocks);
-            Cluster::open(vdev_raid_api)
-            .map(move |cluster| (cluster, reader))
+            let (vdev_raid_api, mut reader) = raid::open(Some(uuid),
+                                                         vdev_blocks);
+            reader.set_rewind(rewind);
+            Cluster::open(vdev_raid_api, reader, readonly)
+        }).and_then(move |(cluster, reader)| {
+            match rewind {
+                // The disks may hold data newer than the rewind target
+                Some(txg) if !readonly => {
+                    let fut = cluster.reset_rewound_zones(txg)
+                        .map(move |_| (cluster, reader));
+                    future::Either::A(fut)
+                },
+                _ => future::Either::B(future::ok((cluster, reader)))
+            }
         })
     }
 
@@ -163,30 +376,166 @@ impl DevManager {
         -> (pool::Label, Vec<raid::Label>, BTreeMap<Uuid, Vec<PathBuf>>)
     {
         let pool = inner.pools.remove(&uuid).unwrap();
+        inner.pool_txgs.remove(&uuid);
+        inner.checkpoints.remove(&uuid);
         let raids = pool.children.iter()
-            .map(|child_uuid| inner.raids.remove(child_uuid).unwrap())
-            .collect::<Vec<_>>();
-        let leaves = raids.iter().map(|raid| {
-            let leaves = raid.iter_children().map(|uuid| {
-                inner.leaves.remove(&uuid).unwrap()
+            .filter(|child_uuid| !child_uuid.is_nil())
+            .map(|child_uuid| {
+                inner.raid_txgs.remove(child_uuid);
+                inner.raids.remove(child_uuid).unwrap()
             }).collect::<Vec<_>>();
+        let leaves = raids.iter().map(|raid| {
+            // Some leaves may be missing, if the raid is degraded.  Faulted
+            // ones hold stale data, so they're left closed.
+            let leaves = raid.iter_children()
+                .filter_map(|uuid| {
+                    inner.leaves.remove(&uuid)
+                        .filter(|_| !raid.is_faulted(uuid))
+                })
+                .collect::<Vec<_>>();
             (raid.uuid(), leaves)
         }).collect::<BTreeMap<_, _>>();
         // Drop the self.inner mutex
         (pool, raids, leaves)
     }
 
-    fn open_vdev_blocks(leaf_paths: Vec<PathBuf>)
+    fn open_vdev_blocks(leaf_paths: Vec<PathBuf>, lock: bool)
         -> impl Future<Item=Vec<(VdevBlock, label::LabelReader)>,
                        Error=Error>
     {
         stream::iter_ok(leaf_paths.into_iter())
         .and_then(VdevFile::open)
-        .map(|(leaf, reader)| {
-            (VdevBlock::new(leaf), reader)
+        .and_then(move |(leaf, reader)| {
+            if lock {
+                leaf.lock()?;
+            }
+            Ok((VdevBlock::new(leaf), reader))
         }).collect()
     }
 
+    /// Could the file at `path` hold a BFFFS label?
+    ///
+    /// Only regular files and disks qualify.  Other devices, like terminals,
+    /// either can't seek or have no size.
+    #[cfg(not(test))]
+    fn probeable(path: &Path) -> bool {
+        let ft = match fs::metadata(path) {
+            Ok(md) => md.file_type(),
+            Err(_) => return false
+        };
+        // FreeBSD has no block devices; its disks are character devices
+        let is_disk = if cfg!(target_os = "freebsd") {
+            ft.is_char_device()
+        } else {
+            ft.is_block_device()
+        };
+        if !ft.is_file() && !is_disk {
+            return false;
+        }
+        OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+            .and_then(|mut f| f.seek(SeekFrom::End(0)))
+            .map(|len| len > 0)
+            .unwrap_or(false)
+    }
+
+    /// Record the labels of a newly tasted leaf device
+    ///
+    /// Fails with `EINVAL`, recording nothing, if the labels above the
+    /// `VdevFile`'s can't be decoded.
+    #[cfg(not(test))]
+    fn record_labels(&self, path: PathBuf, uuid: Uuid,
+                     mut reader: label::LabelReader) -> Result<(), Error>
+    {
+        let rl = raid::Label::read(&mut reader)
+            .map_err(|_| Error::EINVAL)?;
+        let cl: cluster::Label = reader.deserialize()
+            .map_err(|_| Error::EINVAL)?;
+        let pl = pool::Label::read(&mut reader)
+            .map_err(|_| Error::EINVAL)?;
+        let mut inner = self.inner.lock().unwrap();
+        inner.leaves.insert(uuid, path);
+        let txg = reader.txg();
+        let newest_raid = inner.raid_txgs.get(&rl.uuid())
+            .map_or(true, |&t| txg >= t);
+        if newest_raid {
+            inner.raid_txgs.insert(rl.uuid(), txg);
+            inner.raids.insert(rl.uuid(), rl);
+        }
+        let newest = inner.pool_txgs.get(&pl.uuid)
+            .map_or(true, |&t| txg >= t);
+        if newest {
+            inner.pool_txgs.insert(pl.uuid, txg);
+            match cl.checkpoint() {
+                Some(ckpt) => inner.checkpoints.insert(pl.uuid, ckpt),
+                None => inner.checkpoints.remove(&pl.uuid)
+            };
+            if pl.state == pool::PoolState::Destroyed {
+                inner.pools.remove(&pl.uuid);
+            } else {
+                inner.pools.insert(pl.uuid, pl);
+            }
+        }
+        Ok(())
+    }
+
+    /// Search `/dev` and the search path for devices with BFFFS labels, and
+    /// retain them all as by `taste`.
+    ///
+    /// The devices are probed in parallel.  Unlike `taste`, anything that
+    /// can't be opened or lacks a valid label is silently skipped.  Devices
+    /// are identified by their labels, so it doesn't matter if they've been
+    /// renumbered.
+    #[cfg(not(test))]
+    pub fn scan(&self) {
+        let dirs = {
+            let inner = self.inner.lock().unwrap();
+            let mut dirs = vec![PathBuf::from("/dev")];
+            dirs.extend(inner.search_path.iter().cloned());
+            dirs
+        };
+        let paths = dirs.iter()
+            .filter_map(|dir| fs::read_dir(dir).ok())
+            .flat_map(|entries| entries.filter_map(Result::ok))
+            .map(|entry| entry.path())
+            .filter(|path| DevManager::probeable(path))
+            .collect::<Vec<_>>();
+        let mut rt = current_thread::Runtime::new().unwrap();
+        rt.block_on(future::lazy(move || {
+            let futs = paths.into_iter().map(|path| {
+                VdevFile::open(path.clone())
+                    .and_then(move |(vdev_file, reader)| {
+                        self.record_labels(path, vdev_file.uuid(), reader)
+                    }).or_else(|_| Ok::<(), Error>(()))
+            }).collect::<Vec<_>>();
+            future::join_all(futs)
+        })).unwrap();
+    }
+
+    /// Import pools even if they appear to be in use by another host.
+    pub fn set_force(&self, force: bool) {
+        self.inner.lock().unwrap().force = force;
+    }
+
+    /// Import pools read-only.  Nothing will be written to their disks.
+    pub fn set_readonly(&self, readonly: bool) {
+        self.inner.lock().unwrap().readonly = readonly;
+    }
+
+    /// Import pools as of the newest of their last few transaction groups that
+    /// passes a consistency check.  Anything written after that will be lost.
+    pub fn set_rewind(&self, rewind: bool) {
+        self.inner.lock().unwrap().rewind = rewind;
+    }
+
+    /// Import pools as of their checkpoints.  Anything written since the
+    /// checkpoint will be lost.
+    pub fn set_rewind_to_checkpoint(&self, rewind: bool) {
+        self.inner.lock().unwrap().rewind_to_checkpoint = rewind;
+    }
+
     /// Taste the device identified by `p` for an BFFFS label.
     ///
     /// If present, retain the device in the `DevManager` for use as a spare or
@@ -200,14 +549,48 @@ impl DevManager {
         rt.block_on(future::lazy(move || {
             let pathbuf = p.as_ref().to_owned();
             VdevFile::open(p)
-                .map(move |(vdev_file, mut reader)| {
-                    let mut inner = self.inner.lock().unwrap();
-                    inner.leaves.insert(vdev_file.uuid(), pathbuf);
-                    let rl: raid::Label = reader.deserialize().unwrap();
-                    inner.raids.insert(rl.uuid(), rl);
-                    let pl: pool::Label = reader.deserialize().unwrap();
-                    inner.pools.insert(pl.uuid, pl);
+                .and_then(move |(vdev_file, reader)| {
+                    self.record_labels(pathbuf, vdev_file.uuid(), reader)
                 })
         })).unwrap();
     }
 }
+
+// LCOV_EXCL_START
+#[cfg(test)]
+mod t {
+    use crate::common::{
+        throttle::IoLimits,
+        vdev_block::SchedulerPolicy
+    };
+    use pretty_assertions::assert_eq;
+    use super::*;
+
+    // A pool that's still active on a different host must not be imported
+    // unless forced
+    #[test]
+    fn import_active_elsewhere() {
+        let uuid = Uuid::new_v4();
+        let label = pool::Label {
+            name: "foo".to_owned(),
+            uuid,
+            children: vec![],
+            scheduler: SchedulerPolicy::default(),
+            limits: IoLimits::default(),
+            removing: None,
+            classes: vec![],
+            state: pool::PoolState::Active,
+            hostid: pool::hostid().wrapping_add(1),
+            import_time: 0
+        };
+        let dm = DevManager::default();
+        {
+            let mut inner = dm.inner.lock().unwrap();
+            inner.pools.insert(uuid, label);
+            inner.pool_txgs.insert(uuid, TxgT::from(0));
+        }
+        let r = dm.import_by_uuid(uuid, DefaultExecutor::current()).wait();
+        assert_eq!(r.err(), Some(Error::EBUSY));
+    }
+}
+// LCOV_EXCL_STOP
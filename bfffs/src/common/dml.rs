@@ -7,6 +7,7 @@ use futures::Future;
 use std::num::NonZeroU8;
 
 pub use crate::common::cache::{Cacheable, CacheRef};
+pub use crate::common::vdev::PriorityClass;
 
 /// Compression mode in use
 #[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, Hash, Ord, PartialEq, PartialOrd)]
@@ -100,17 +101,26 @@ pub trait DML: Send + Sync {
     /// If the given record is present in the cache, evict it.
     fn evict(&self, addr: &Self::Addr);
 
-    /// Read a record and return a shared reference
-    fn get<T: Cacheable, R: CacheRef>(&self, addr: &Self::Addr)
+    /// Read a record and return a shared reference.
+    ///
+    /// `priority` tells the I/O scheduler how urgently the record is needed.
+    /// It's ignored if the record is already cached.
+    fn get<T: Cacheable, R: CacheRef>(&self, addr: &Self::Addr,
+                                      priority: PriorityClass)
         -> Box<dyn Future<Item=Box<R>, Error=Error> + Send>;
 
     /// Read a record and return ownership of it.
-    fn pop<T: Cacheable, R: CacheRef>(&self, rid: &Self::Addr, txg: TxgT)
+    fn pop<T: Cacheable, R: CacheRef>(&self, rid: &Self::Addr, txg: TxgT,
+                                      priority: PriorityClass)
         -> Box<dyn Future<Item=Box<T>, Error=Error> + Send>;
 
     /// Write a record to disk and cache.  Return its Direct Record Pointer.
+    ///
+    /// `priority` selects which I/O rate limits apply.  Zoned devices require
+    /// writes to be issued in order, so the I/O scheduler never reorders them
+    /// by priority.
     fn put<T: Cacheable>(&self, cacheable: T, compression: Compression,
-                             txg: TxgT)
+                             txg: TxgT, priority: PriorityClass)
         -> Box<dyn Future<Item=<Self as DML>::Addr, Error=Error> + Send>;
 
     /// Sync all records written so far to stable storage.
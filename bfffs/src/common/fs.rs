@@ -845,7 +845,8 @@ impl Fs {
         ).unwrap();
         let (last_key, (atimep, _), (recsizep, _)) = rx.wait().unwrap();
         let next_object = AtomicU64::new(last_key.unwrap().object() + 1);
-        let atime = atimep.as_bool();
+        // A read-only pool can't record atimes
+        let atime = atimep.as_bool() && !database.readonly();
         let record_size = recsizep.as_u8();
         Fs{
             db: database,
@@ -1863,9 +1864,12 @@ impl Fs {
             self.db.fsread(self.tree, move |dataset| {
                 let blocks = dataset.size();
                 let allocated = dataset.allocated();
+                let reserved = dataset.reserved();
+                // Freed space held by the checkpoint isn't free yet
+                let held = allocated + dataset.checkpointed();
                 let r = libc::statvfs {
-                    f_bavail: blocks - allocated,
-                    f_bfree: blocks - allocated,
+                    f_bavail: blocks.saturating_sub(held + reserved),
+                    f_bfree: blocks.saturating_sub(held),
                     f_blocks: blocks,
                     f_favail: u64::max_value(),
                     f_ffree: u64::max_value(),
@@ -2127,6 +2131,7 @@ fn setup() -> (tokio_io_pool::Runtime, Database, TreeID) {
             let source = PropertySource::Default;
             Box::new(Ok((prop, source)).into_future())
         });
+    db.expect_readonly().return_const(false);
     let tree_id = rt.block_on(db.new_fs(Vec::new())).unwrap();
     (rt, db, tree_id)
 }
@@ -336,7 +336,8 @@ impl InlineExtAttr {
             let namespace = self.namespace;
             let name = self.name;
             let dbs = Arc::try_unwrap(self.extent.buf).unwrap();
-            let fut = dml.put(dbs, Compression::None, txg)
+            let fut = dml.put(dbs, Compression::None, txg,
+                              PriorityClass::Normal)
             .map(move |rid: D::Addr| {
                 debug_assert_eq!(mem::size_of::<D::Addr>(),
                                  mem::size_of::<A>());
@@ -588,7 +589,8 @@ impl InlineExtent {
         let lsize = self.len();
         if lsize > BLOB_THRESHOLD {
             let dbs = Arc::try_unwrap(self.buf).unwrap();
-            let fut = dml.put(dbs, Compression::None, txg)
+            let fut = dml.put(dbs, Compression::None, txg,
+                              PriorityClass::Normal)
             .map(move |rid: D::Addr| {
                 debug_assert_eq!(mem::size_of::<D::Addr>(),
                                  mem::size_of::<A>());
@@ -814,9 +816,9 @@ fn fsvalue_flush_inline_extattr_long() {
     let mut idml = IDML::default();
     idml.expect_put()
         .once()
-        .withf(|cacheable: &DivBufShared, _compression, _txg| {
+        .withf(|cacheable: &DivBufShared, _compression, _txg, _| {
             cacheable.len() == BYTES_PER_LBA
-        }).returning(move |_, _, _| boxfut!(Ok(rid).into_future()));
+        }).returning(move |_, _, _, _| boxfut!(Ok(rid).into_future()));
     let txg = TxgT(0);
 
     let namespace = ExtAttrNamespace::User;
@@ -860,9 +862,9 @@ fn fsvalue_flush_inline_extent_long() {
     let mut idml = IDML::default();
     idml.expect_put()
         .once()
-        .withf(|cacheable: &DivBufShared, _compression, _txg| {
+        .withf(|cacheable: &DivBufShared, _compression, _txg, _| {
             cacheable.len() == BYTES_PER_LBA
-        }).returning(move |_, _, _| boxfut!(Ok(rid).into_future()));
+        }).returning(move |_, _, _, _| boxfut!(Ok(rid).into_future()));
     let txg = TxgT(0);
 
     let data = Arc::new(DivBufShared::from(vec![42u8; BYTES_PER_LBA]));
@@ -7,7 +7,10 @@ use crate::{
         dml::*,
         ddml::*,
         cache::{Cache, Cacheable, CacheRef, Key},
+        iostat::VdevStats,
         label::*,
+        pool::{ClusterProxy, PoolState},
+        throttle::IoLimits,
         tree::TreeOnDisk
     }
 };
@@ -45,6 +48,12 @@ pub struct IDML {
     /// Holds the next RID to allocate.  They are never reused.
     next_rid: AtomicU64,
 
+    /// Was the pool imported read-only?  If so, reject all modifications.
+    readonly: bool,
+
+    /// Roots written to the label in recent transaction groups
+    roots: Mutex<TxgRing<Root>>,
+
     /// Current transaction group
     transaction: RwLock<TxgT>,
 
@@ -57,12 +66,25 @@ pub struct IDML {
 // instead by integration tests.
 #[cfg_attr(test, allow(unused))]
 impl<'a> IDML {
+    /// Add a newly created `Cluster` to the `Pool`.
+    pub fn add_cluster(&self, cluster: ClusterProxy)
+        -> impl Future<Item=(), Error=Error> + Send
+    {
+        self.ddml.add_cluster(cluster)
+    }
+
     /// How many blocks have been allocated, including blocks that have been
     /// freed but not erased?
     pub fn allocated(&self) -> LbaT {
         self.ddml.allocated()
     }
 
+    /// Return the amount of freed space, in LBAs, that can't be reused until
+    /// the pool checkpoint is discarded.
+    pub fn checkpointed(&self) -> LbaT {
+        self.ddml.checkpointed()
+    }
+
     /// Foreground RIDT/AllocT consistency check.
     ///
     /// Checks that the RIDT and AllocT are exact inverses of each other.
@@ -194,7 +216,8 @@ impl<'a> IDML {
             3.73);
         let transaction = RwLock::new(TxgT::from(0));
         let trees = Arc::new(Trees{alloct, ridt});
-        IDML{cache, ddml, next_rid, transaction, trees}
+        let roots = Mutex::new(TxgRing::default());
+        IDML{cache, ddml, next_rid, readonly: false, roots, transaction, trees}
     }
 
     pub fn dump_trees(&self, f: &mut dyn io::Write) -> Result<(), Error>
@@ -203,13 +226,40 @@ impl<'a> IDML {
         self.trees.alloct.dump(f)
     }
 
-    pub fn flush(&self, idx: u32, txg: TxgT)
+    /// Drop the `Cluster` that is being removed from the `Pool`.
+    ///
+    /// All of its zones must already have been cleaned.  Fails with `EBUSY` if
+    /// it still holds any data.
+    pub fn finish_removal(&self, cluster: ClusterT) -> Result<(), Error> {
+        self.ddml.finish_removal(cluster)
+    }
+
+    /// Finish all of the given `Cluster`'s open zones.
+    pub fn finish_zones(&self, cluster: ClusterT, txg: TxgT)
+        -> impl Future<Item=(), Error=Error> + Send
+    {
+        self.ddml.finish_zones(cluster, txg)
+    }
+
+    pub fn flush(&self, txg: TxgT)
         -> impl Future<Item=(), Error=Error> + Send
     {
         let ddml2 = self.ddml.clone();
         self.trees.alloct.flush(txg)
         .join(self.trees.ridt.flush(txg))
-        .and_then(move |_| ddml2.flush(idx))
+        .and_then(move |_| ddml2.flush(txg))
+    }
+
+    /// Snapshot the I/O statistics of the underlying `Pool`
+    pub fn iostats(&self)
+        -> impl Future<Item=VdevStats, Error=Error> + Send
+    {
+        self.ddml.iostats()
+    }
+
+    /// The I/O rate limits applied to each of the pool's disks
+    pub fn limits(&self) -> IoLimits {
+        self.ddml.limits()
     }
 
     pub fn list_closed_zones(&self)
@@ -239,17 +289,24 @@ impl<'a> IDML {
     /// * `ddml`:           An already-opened `DDML`
     /// * `cache`:          An already-constrcuted `Cache`
     /// * `label_reader`:   A `LabelReader` that has already consumed all labels
-    ///                     prior to this layer.
+    ///                     prior to this layer.  If it has a rewind target,
+    ///                     the `IDML` is opened as of that transaction group.
     pub fn open(ddml: Arc<DDML>, cache: Arc<Mutex<Cache>>,
                  mut label_reader: LabelReader) -> (Self, LabelReader)
     {
-        let l: Label = label_reader.deserialize().unwrap();
-        let alloct = DTree::open(ddml.clone(), true, l.alloct);
-        let ridt = DTree::open(ddml.clone(), true, l.ridt);
-        let transaction = RwLock::new(l.txg);
-        let next_rid = AtomicU64::new(l.next_rid);
+        let mut l: Label = label_reader.deserialize().unwrap();
+        // The Clusters already checked that the rewind target exists
+        let (txg, root) = l.roots.select(label_reader.rewind())
+            .expect("IDML label lacks the rewind target");
+        let alloct = DTree::open(ddml.clone(), true, root.alloct);
+        let ridt = DTree::open(ddml.clone(), true, root.ridt);
+        let transaction = RwLock::new(txg);
+        let next_rid = AtomicU64::new(root.next_rid);
+        let readonly = ddml.readonly();
+        let roots = Mutex::new(l.roots);
         let trees = Arc::new(Trees{alloct, ridt});
-        let idml = IDML{cache, ddml, next_rid, transaction, trees};
+        let idml = IDML{cache, ddml, next_rid, readonly, roots, transaction,
+                        trees};
         (idml, label_reader)
     }
 
@@ -282,13 +339,17 @@ impl<'a> IDML {
                     // works perfectly fine with put_direct.
                     //
                     // Read the record as though it were uncompressed, to avoid
-                    // the CPU cost of decompression/compression.
+                    // the CPU cost of decompression/compression.  Moving
+                    // records is background work; it shouldn't delay the
+                    // user's reads.
                     let drp_uc = entry.drp.as_uncompressed();
                     let ddml4 = ddml2.clone();
-                    let fut = ddml2.get_direct::<DivBufShared>(&drp_uc)
+                    let fut = ddml2.get_direct::<DivBufShared>(&drp_uc,
+                        PriorityClass::Background)
                     .and_then(move |dbs| {
                         let db = dbs.try_const().unwrap();
-                        ddml4.put_direct(&db, Compression::None, txg)
+                        ddml4.relocate_direct(&db, Compression::None,
+                                              &entry.drp, txg)
                         .and_then(move |drp| {
                             ddml4.delete_direct(&entry.drp, txg)
                             .map(move |_| drp.into_compressed(&entry.drp))
@@ -308,7 +369,8 @@ impl<'a> IDML {
                         // NB: if BFFFS ever implements deferred zone erase,
                         // then we can write and delete in parallel.
                         let db = t.serialize();
-                        let fut = ddml2.put_direct(&db, Compression::None, txg)
+                        let fut = ddml2.relocate_direct(&db,
+                            Compression::None, &entry.drp, txg)
                         .and_then(move |drp| {
                             ddml3.delete_direct(&entry.drp, txg)
                             .map(move |_| drp)
@@ -330,8 +392,37 @@ impl<'a> IDML {
             })  // LCOV_EXCL_LINE   kcov false negative
     }
 
+    /// Change the I/O rate limits of each of the pool's disks.
+    pub fn set_limits(&self, limits: IoLimits) {
+        self.ddml.set_limits(limits)
+    }
+
+    /// Was the pool imported read-only?
+    pub fn readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// Return the amount of space, in LBAs, that is held back from ordinary
+    /// data writes.
+    pub fn reserved(&self) -> LbaT {
+        self.ddml.reserved()
+    }
+
+    /// Return the UUID and remaining allocated space of the `Cluster` that is
+    /// being removed, if any.
+    pub fn removing(&self) -> Option<(Uuid, LbaT)> {
+        self.ddml.removing()
+    }
+
+    /// Change the state that will be recorded in the pool's labels.
+    pub fn set_state(&self, state: PoolState) {
+        self.ddml.set_state(state)
+    }
+
     /// Shutdown all background tasks.
-    pub fn shutdown(&self) {
+    ///
+    /// The returned future completes once the disks have been closed.
+    pub fn shutdown(&self) -> impl Future<Item=(), Error=Error> + Send {
         self.ddml.shutdown()
     }
 
@@ -340,6 +431,12 @@ impl<'a> IDML {
         self.ddml.size()
     }
 
+    /// Stop allocating from the `Cluster` with the given UUID, in preparation
+    /// for removing it from the `Pool`.
+    pub fn start_removal(&self, uuid: Uuid) -> Result<ClusterT, Error> {
+        self.ddml.start_removal(uuid)
+    }
+
     /// Get a reference to the current transaction group.
     ///
     /// The reference will prevent the current transaction group from syncing,
@@ -355,19 +452,33 @@ impl<'a> IDML {
               B: IntoFuture<Item = (), Error = Error> + Send + 'a,
               <B as IntoFuture>::Future: Send
     {
-        self.transaction.write()
+        if self.readonly {
+            return future::Either::A(future::err(Error::EROFS));
+        }
+        let fut = self.transaction.write()
             .map_err(|_| Error::EPIPE)
             .and_then(move |mut txg_guard| {
                 let txg = *txg_guard;
                 f(txg).into_future()
                 .map(move |_| *txg_guard += 1)
-            })
+            });
+        future::Either::B(fut)
+    }
+
+    /// Enable every on-disk feature supported by this version of BFFFS.
+    ///
+    /// Returns `true` if any new features were enabled.
+    pub fn upgrade(&self) -> bool {
+        self.ddml.upgrade()
     }
 
     /// Asynchronously write this `IDML`'s label to its `Pool`
     pub fn write_label(&self, mut labeller: LabelWriter, txg: TxgT)
         -> impl Future<Item=(), Error=Error> + Send
     {
+        if self.readonly {
+            return future::Either::A(future::err(Error::EROFS));
+        }
         // The txg lock must be held when calling write_label.  Otherwise,
         // next_rid may be out-of-date by the time we serialize the label.
         debug_assert!(self.transaction.try_read().is_err(),
@@ -375,14 +486,14 @@ impl<'a> IDML {
         let next_rid = self.next_rid.load(Ordering::Relaxed);
         let alloct = self.trees.alloct.serialize().unwrap();
         let ridt = self.trees.ridt.serialize().unwrap();
-        let label = Label {
-            alloct,
-            next_rid,
-            ridt,
-            txg,
+        let label = {
+            let mut roots = self.roots.lock().unwrap();
+            roots.push(txg, Root{alloct, next_rid, ridt});
+            roots.update_checkpoint(labeller.checkpoint_op());
+            Label{roots: roots.clone()}
         };
         labeller.serialize(&label).unwrap();
-        self.ddml.write_label(labeller)
+        future::Either::B(self.ddml.write_label(labeller))
     }
 }
 
@@ -402,6 +513,9 @@ impl DML for IDML {
     fn delete(&self, ridp: &Self::Addr, txg: TxgT)
         -> Box<dyn Future<Item=(), Error=Error> + Send>
     {
+        if self.readonly {
+            return boxfut!(future::err::<(), Error>(Error::EROFS));
+        }
         let cache2 = self.cache.clone();
         let ddml2 = self.ddml.clone();
         let trees2 = self.trees.clone();
@@ -434,7 +548,8 @@ impl DML for IDML {
         self.cache.lock().unwrap().remove(&Key::Rid(*rid));
     }
 
-    fn get<T: Cacheable, R: CacheRef>(&self, ridp: &Self::Addr)
+    fn get<T: Cacheable, R: CacheRef>(&self, ridp: &Self::Addr,
+                                      priority: PriorityClass)
         -> Box<dyn Future<Item=Box<R>, Error=Error> + Send>
     {
         let rid = *ridp;
@@ -446,7 +561,7 @@ impl DML for IDML {
             let fut = self.trees.ridt.get(rid)
                 .and_then(unwrap_or_enoent)
                 .and_then(move |entry| {
-                    ddml2.get_direct(&entry.drp)
+                    ddml2.get_direct(&entry.drp, priority)
                 }).map(move |cacheable: Box<T>| {
                     let r = cacheable.make_ref();
                     let key = Key::Rid(rid);
@@ -457,9 +572,13 @@ impl DML for IDML {
         })
     }
 
-    fn pop<T: Cacheable, R: CacheRef>(&self, ridp: &Self::Addr, txg: TxgT)
+    fn pop<T: Cacheable, R: CacheRef>(&self, ridp: &Self::Addr, txg: TxgT,
+                                      priority: PriorityClass)
         -> Box<dyn Future<Item=Box<T>, Error=Error> + Send>
     {
+        if self.readonly {
+            return boxfut!(future::err::<Box<T>, Error>(Error::EROFS));
+        }
         let rid = *ridp;
         let cache2 = self.cache.clone();
         let ddml2 = self.ddml.clone();
@@ -479,7 +598,8 @@ impl DML for IDML {
                                               .map(move |_| t)
                             )
                         }).unwrap_or_else(||{
-                            boxfut!(ddml3.pop_direct::<T>(&entry.drp))
+                            boxfut!(ddml3.pop_direct::<T>(&entry.drp,
+                                                          priority))
                         });
                     let alloct_fut = trees2.alloct.remove(entry.drp.pba(), txg);
                     let ridt_fut = trees2.ridt.remove(rid, txg);
@@ -497,7 +617,7 @@ impl DML for IDML {
                         let t = cacheref.to_owned().downcast::<T>().unwrap();
                         boxfut!(future::ok(t))
                     }).unwrap_or_else(|| {
-                        Box::new(ddml2.get_direct::<T>(&entry.drp))
+                        Box::new(ddml2.get_direct::<T>(&entry.drp, priority))
                     });
                     let ridt_fut = trees2.ridt.insert(rid, entry, txg);
                     boxfut!(
@@ -511,7 +631,8 @@ impl DML for IDML {
         Box::new(fut)
     }
 
-    fn put<T>(&self, cacheable: T, compression: Compression, txg: TxgT)
+    fn put<T>(&self, cacheable: T, compression: Compression, txg: TxgT,
+              priority: PriorityClass)
         -> Box<dyn Future<Item=Self::Addr, Error=Error> + Send>
         where T: Cacheable
     {
@@ -521,11 +642,16 @@ impl DML for IDML {
         // 2) Cache
         // 3) Add entry to the RIDT
         // 4) Add reverse entry to the AllocT
+        if self.readonly {
+            return boxfut!(future::err::<RID, Error>(Error::EROFS));
+        }
         let cache2 = self.cache.clone();
         let trees2 = self.trees.clone();
         let rid = RID(self.next_rid.fetch_add(1, Ordering::Relaxed));
 
-        let fut = self.ddml.put_direct(&cacheable.make_ref(), compression, txg)
+        let class = cacheable.write_class();
+        let fut = self.ddml.put_direct(&cacheable.make_ref(), compression,
+                                       class, txg, priority)
         .and_then(move|drp| {
             let alloct_fut = trees2.alloct.insert(drp.pba(), rid, txg);
             let rid_entry = RidtEntry::new(drp);
@@ -553,32 +679,52 @@ impl DML for IDML {
 
 #[derive(Serialize, Deserialize, Debug)]
 struct Label {
+    /// The IDML's roots as of each recent transaction group, newest first
+    roots:              TxgRing<Root>,
+}
+
+/// Everything needed to open the `IDML` as of one transaction group
+#[derive(Clone, Serialize, Deserialize, Debug)]
+struct Root {
     alloct:             TreeOnDisk<DRP>,
     next_rid:           u64,
     ridt:               TreeOnDisk<DRP>,
-    /// Last transaction group synced before the label was written
-    txg:                TxgT,
 }
 
 // LCOV_EXCL_START
 #[cfg(test)]
 mock!{
     pub IDML {
+        fn add_cluster(&self, cluster: ClusterProxy)
+            -> Box<dyn Future<Item=(), Error=Error> + Send>;
         fn allocated(&self) -> LbaT;
         fn check(&self) -> Box<dyn Future<Item=bool, Error=Error>>;
+        fn checkpointed(&self) -> LbaT;
         fn clean_zone(&self, zone: ClosedZone, txg: TxgT)
             -> Box<dyn Future<Item=(), Error=Error> + Send>;
         fn create(ddml: Arc<DDML>, cache: Arc<Mutex<Cache>>) -> Self;
         fn dump_trees(&self, f: &mut (dyn io::Write + 'static))
             -> Result<(), Error>;
-        fn flush(&self, idx: u32, txg: TxgT)
+        fn finish_removal(&self, cluster: ClusterT) -> Result<(), Error>;
+        fn finish_zones(&self, cluster: ClusterT, txg: TxgT)
             -> Box<dyn Future<Item=(), Error=Error> + Send>;
+        fn flush(&self, txg: TxgT)
+            -> Box<dyn Future<Item=(), Error=Error> + Send>;
+        fn iostats(&self)
+            -> Box<dyn Future<Item=VdevStats, Error=Error> + Send>;
+        fn limits(&self) -> IoLimits;
         fn list_closed_zones(&self)
             -> Box<dyn Stream<Item=ClosedZone, Error=Error> + Send>;
         fn open(ddml: Arc<DDML>, cache: Arc<Mutex<Cache>>,
                      mut label_reader: LabelReader) -> (Self, LabelReader);
-        fn shutdown(&self);
+        fn set_limits(&self, limits: IoLimits);
+        fn readonly(&self) -> bool;
+        fn reserved(&self) -> LbaT;
+        fn removing(&self) -> Option<(Uuid, LbaT)>;
+        fn set_state(&self, state: PoolState);
+        fn shutdown(&self) -> Box<dyn Future<Item=(), Error=Error> + Send>;
         fn size(&self) -> LbaT;
+        fn start_removal(&self, uuid: Uuid) -> Result<ClusterT, Error>;
         // Return a static reference instead of a RwLockReadFut because it makes
         // the expectations easier to write
         fn txg(&self)
@@ -588,6 +734,7 @@ mock!{
         // Instead, we'll use special logic in advance_transaction and only mock
         // the txg used.
         fn advance_transaction_inner(&self) -> TxgT;
+        fn upgrade(&self) -> bool;
         fn write_label(&self, mut labeller: LabelWriter, txg: TxgT)
             -> Box<dyn Future<Item=(), Error=Error> + Send>;
     }
@@ -596,12 +743,14 @@ mock!{
         fn delete(&self, addr: &RID, txg: TxgT)
             -> Box<dyn Future<Item=(), Error=Error> + Send>;
         fn evict(&self, addr: &RID);
-        fn get<T: Cacheable, R: CacheRef>(&self, addr: &RID)
+        fn get<T: Cacheable, R: CacheRef>(&self, addr: &RID,
+                                          priority: PriorityClass)
             -> Box<dyn Future<Item=Box<R>, Error=Error> + Send>;
-        fn pop<T: Cacheable, R: CacheRef>(&self, rid: &RID, txg: TxgT)
+        fn pop<T: Cacheable, R: CacheRef>(&self, rid: &RID, txg: TxgT,
+                                          priority: PriorityClass)
             -> Box<dyn Future<Item=Box<T>, Error=Error> + Send>;
         fn put<T: Cacheable>(&self, cacheable: T, compression: Compression,
-                                 txg: TxgT)
+                                 txg: TxgT, priority: PriorityClass)
             -> Box<dyn Future<Item=RID, Error=Error> + Send>;
         fn sync_all(&self, txg: TxgT)
             -> Box<dyn Future<Item=(), Error=Error> + Send>;
@@ -646,12 +795,13 @@ mod t {
         let ridt_entry = RidtEntry::new(drp);
         format!("{:?}", ridt_entry);
 
-        let label = Label{
+        let mut roots = TxgRing::default();
+        roots.push(TxgT(0), Root{
             alloct:     TreeOnDisk::default(),
             next_rid:   0,
             ridt:       TreeOnDisk::default(),
-            txg:        TxgT(0)
-        };
+        });
+        let label = Label{roots};
         format!("{:?}", label);
     }
 
@@ -802,7 +952,8 @@ mod t {
         let arc_ddml = Arc::new(ddml);
         let idml = IDML::create(arc_ddml, Arc::new(Mutex::new(cache)));
 
-        idml.get::<DivBufShared, DivBuf>(&rid).wait().unwrap();
+        idml.get::<DivBufShared, DivBuf>(&rid, PriorityClass::Normal).wait()
+            .unwrap();
     }
 
     #[test]
@@ -830,8 +981,8 @@ mod t {
         let mut ddml = DDML::default();
         ddml.expect_get_direct::<DivBufShared>()
             .once()
-            .with(eq(drp))
-            .returning(move |_| {
+            .with(eq(drp), eq(PriorityClass::Interactive))
+            .returning(move |_, _| {
                 let dbs = Box::new(DivBufShared::from(vec![0u8; 4096]));
                 Box::new(future::ok::<Box<DivBufShared>, Error>(dbs))
             });
@@ -839,7 +990,8 @@ mod t {
         let idml = IDML::create(arc_ddml, Arc::new(Mutex::new(cache)));
         inject_record(&idml, rid, &drp, 1);
 
-        idml.get::<DivBufShared, DivBuf>(&rid).wait().unwrap();
+        idml.get::<DivBufShared, DivBuf>(&rid, PriorityClass::Interactive)
+            .wait().unwrap();
     }
 
     #[test]
@@ -897,17 +1049,18 @@ mod t {
         ddml.expect_get_direct()
             .once()
             .in_sequence(&mut seq)
-            .withf(move |key| key.pba() == drp0.pba() &&
-                   !key.is_compressed())
-            .returning(move |_| {
+            .withf(move |key, priority| key.pba() == drp0.pba() &&
+                   !key.is_compressed() &&
+                   *priority == PriorityClass::Background)
+            .returning(move |_, _| {
                 let r = DivBufShared::from(&dbs.try_const().unwrap()[..]);
                 Box::new(future::ok::<Box<DivBufShared>, Error>(Box::new(r)))
             });
-        ddml.expect_put_direct::<DivBuf>()
+        ddml.expect_relocate_direct::<DivBuf>()
             .once()
             .in_sequence(&mut seq)
-            .with(always(), eq(Compression::None), always())
-            .returning(move |_, _, _|
+            .with(always(), eq(Compression::None), eq(drp0), always())
+            .returning(move |_, _, _, _|
                 Box::new(Ok(drp1).into_future())
             );
         ddml.expect_delete_direct()
@@ -949,17 +1102,18 @@ mod t {
         ddml.expect_get_direct()
             .once()
             .in_sequence(&mut seq)
-            .withf(move |key| key.pba() == drp0.pba() &&
-                   !key.is_compressed())
-            .returning(move |_| {
+            .withf(move |key, priority| key.pba() == drp0.pba() &&
+                   !key.is_compressed() &&
+                   *priority == PriorityClass::Background)
+            .returning(move |_, _| {
                 let r = DivBufShared::from(&dbs.try_const().unwrap()[..]);
                 Box::new(future::ok(Box::new(r)))
             });
-        ddml.expect_put_direct::<DivBuf>()
+        ddml.expect_relocate_direct::<DivBuf>()
             .once()
             .in_sequence(&mut seq)
-            .with(always(), eq(Compression::None), always())
-            .returning(move |_, _, _| Box::new(Ok(drp1).into_future()));
+            .with(always(), eq(Compression::None), eq(drp0), always())
+            .returning(move |_, _, _, _| Box::new(Ok(drp1).into_future()));
         ddml.expect_delete_direct()
             .once()
             .in_sequence(&mut seq)
@@ -999,10 +1153,10 @@ mod t {
             .returning(move |_| {
                 Some(Box::new(dbs.try_const().unwrap()))
             });
-        ddml.expect_put_direct::<DivBuf>()
+        ddml.expect_relocate_direct::<DivBuf>()
             .once()
             .in_sequence(&mut seq)
-            .returning(move |_, _, _|
+            .returning(move |_, _, _, _|
                        Box::new(Ok(drp1).into_future())
             );
         ddml.expect_delete_direct()
@@ -1046,7 +1200,9 @@ mod t {
         let idml = IDML::create(arc_ddml, Arc::new(Mutex::new(cache)));
         inject_record(&idml, rid, &drp, 1);
 
-        idml.pop::<DivBufShared, DivBuf>(&rid, TxgT::from(42)).wait().unwrap();
+        idml.pop::<DivBufShared, DivBuf>(&rid, TxgT::from(42),
+                                         PriorityClass::Normal)
+            .wait().unwrap();
         // Now verify the contents of the RIDT and AllocT
         assert!(idml.trees.ridt.get(rid).wait().unwrap().is_none());
         let alloc_rec = idml.trees.alloct.get(drp.pba()).wait().unwrap();
@@ -1070,7 +1226,9 @@ mod t {
         let idml = IDML::create(arc_ddml, Arc::new(Mutex::new(cache)));
         inject_record(&idml, rid, &drp, 2);
 
-        idml.pop::<DivBufShared, DivBuf>(&rid, TxgT::from(0)).wait().unwrap();
+        idml.pop::<DivBufShared, DivBuf>(&rid, TxgT::from(0),
+                                         PriorityClass::Normal)
+            .wait().unwrap();
         // Now verify the contents of the RIDT and AllocT
         let entry2 = idml.trees.ridt.get(rid).wait().unwrap().unwrap();
         assert_eq!(entry2.drp, drp);
@@ -1091,8 +1249,8 @@ mod t {
             .returning(|_| None);
         ddml.expect_pop_direct::<DivBufShared>()
             .once()
-            .with(eq(drp))
-            .returning(|_| {
+            .with(eq(drp), eq(PriorityClass::Normal))
+            .returning(|_, _| {
                 let dbs = DivBufShared::from(vec![42u8; 4096]);
                 Box::new(future::ok::<Box<DivBufShared>, Error>(
                         Box::new(dbs))
@@ -1102,7 +1260,9 @@ mod t {
         let idml = IDML::create(arc_ddml, Arc::new(Mutex::new(cache)));
         inject_record(&idml, rid, &drp, 1);
 
-        idml.pop::<DivBufShared, DivBuf>(&rid, TxgT::from(0)).wait().unwrap();
+        idml.pop::<DivBufShared, DivBuf>(&rid, TxgT::from(0),
+                                         PriorityClass::Normal)
+            .wait().unwrap();
         // Now verify the contents of the RIDT and AllocT
         assert!(idml.trees.ridt.get(rid).wait().unwrap().is_none());
         let alloc_rec = idml.trees.alloct.get(drp.pba()).wait().unwrap();
@@ -1121,8 +1281,8 @@ mod t {
             .returning(|_| None);
         ddml.expect_get_direct()
             .once()
-            .with(eq(drp))
-            .returning(move |_| {
+            .with(eq(drp), eq(PriorityClass::Normal))
+            .returning(move |_, _| {
                 let dbs = Box::new(DivBufShared::from(vec![42u8; 4096]));
                 Box::new(future::ok::<Box<DivBufShared>, Error>(dbs))
             });
@@ -1130,7 +1290,9 @@ mod t {
         let idml = IDML::create(arc_ddml, Arc::new(Mutex::new(cache)));
         inject_record(&idml, rid, &drp, 2);
 
-        idml.pop::<DivBufShared, DivBuf>(&rid, TxgT::from(0)).wait().unwrap();
+        idml.pop::<DivBufShared, DivBuf>(&rid, TxgT::from(0),
+                                         PriorityClass::Normal)
+            .wait().unwrap();
         // Now verify the contents of the RIDT and AllocT
         let entry2 = idml.trees.ridt.get(rid).wait().unwrap().unwrap();
         assert_eq!(entry2.drp, drp);
@@ -1152,14 +1314,17 @@ mod t {
             .return_const(());
         ddml.expect_put_direct::<Box<dyn CacheRef>>()
             .once()
-            .returning(move |_, _, _|
+            .with(always(), always(), eq(WriteClass::Data), always(),
+                  eq(PriorityClass::Normal))
+            .returning(move |_, _, _, _, _|
                        Box::new(Ok(drp).into_future())
             );
         let arc_ddml = Arc::new(ddml);
         let idml = IDML::create(arc_ddml, Arc::new(Mutex::new(cache)));
 
         let dbs = DivBufShared::from(vec![42u8; 4096]);
-        let actual_rid = idml.put(dbs, Compression::None, TxgT::from(0))
+        let actual_rid = idml.put(dbs, Compression::None, TxgT::from(0),
+                                  PriorityClass::Normal)
             .wait().unwrap();
         assert_eq!(rid, actual_rid);
 
@@ -1180,14 +1345,16 @@ mod t {
         let drp = DRP::new(PBA::new(0, 0), Compression::None, 40000, 40000,
                            0xdead_beef);
         ddml.expect_put::<Arc<tree::Node<DRP, RID, RidtEntry>>>()
-            .with(always(), always(), eq(TxgT::from(42)))
-            .returning(move |_, _, _| {
+            .with(always(), always(), eq(TxgT::from(42)),
+                  eq(PriorityClass::Normal))
+            .returning(move |_, _, _, _| {
                 let drp = DRP::random(Compression::None, 4096);
                  Box::new(Ok(drp).into_future())
             });
         ddml.expect_put::<Arc<tree::Node<DRP, PBA, RID>>>()
-            .with(always(), always(), eq(TxgT::from(42)))
-            .returning(move |_, _, _| {
+            .with(always(), always(), eq(TxgT::from(42)),
+                  eq(PriorityClass::Normal))
+            .returning(move |_, _, _, _| {
                 let drp = DRP::random(Compression::None, 4096);
                  Box::new(Ok(drp).into_future())
             });
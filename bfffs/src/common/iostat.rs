@@ -0,0 +1,256 @@
+// vim: tw=80
+//! I/O statistics
+//!
+//! Each `VdevBlock` counts the operations that it completes.  Upper layers
+//! aggregate their children's counters into a tree of `VdevStats`.
+
+use crate::common::Uuid;
+use serde_derive::*;
+use std::time::Duration;
+
+/// Number of buckets in a `Histogram`.  The last one catches everything
+/// slower than about half an hour.
+const BUCKETS: usize = 32;
+
+/// A latency histogram with power-of-two buckets.
+///
+/// Bucket `i` counts operations that took less than `2^i` microseconds, but
+/// not less than `2^(i-1)`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+    /// Sum of all recorded latencies, in microseconds
+    total_us: u64,
+}
+
+impl Histogram {
+    /// The per-bucket counts, fastest first
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets[..]
+    }
+
+    /// How many latencies have been recorded?
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        for (b, o) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *b += *o;
+        }
+        self.total_us += other.total_us;
+    }
+
+    /// Mean latency, or `None` if nothing has been recorded
+    pub fn mean(&self) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            None
+        } else {
+            Some(Duration::from_micros(self.total_us / count))
+        }
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let us = latency.as_micros() as u64;
+        let bucket = (64 - u64::leading_zeros(us)) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)] += 1;
+        self.total_us += us;
+    }
+
+    /// Everything recorded since `earlier`, an older copy of this histogram
+    fn since(&self, earlier: &Histogram) -> Histogram {
+        let mut h = *self;
+        for (b, e) in h.buckets.iter_mut().zip(earlier.buckets.iter()) {
+            *b = b.saturating_sub(*e);
+        }
+        h.total_us = h.total_us.saturating_sub(earlier.total_us);
+        h
+    }
+}
+
+/// Cumulative I/O counters for a single vdev
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct IoStats {
+    /// Completed reads
+    pub read_ops: u64,
+    /// Completed writes, including label writes
+    pub write_ops: u64,
+    /// Completed zone management and sync operations
+    pub other_ops: u64,
+    /// Operations that failed, after exhausting any retries
+    pub errors: u64,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    /// Operations that are currently outstanding, whether issued to the disk
+    /// or still waiting in the scheduler.  Unlike the other fields, this is
+    /// not cumulative.
+    pub queue_depth: u32,
+    pub read_latency: Histogram,
+    pub write_latency: Histogram,
+}
+
+impl IoStats {
+    /// Add another vdev's counters to these
+    pub fn merge(&mut self, other: &IoStats) {
+        self.read_ops += other.read_ops;
+        self.write_ops += other.write_ops;
+        self.other_ops += other.other_ops;
+        self.errors += other.errors;
+        self.read_bytes += other.read_bytes;
+        self.write_bytes += other.write_bytes;
+        self.queue_depth += other.queue_depth;
+        self.read_latency.merge(&other.read_latency);
+        self.write_latency.merge(&other.write_latency);
+    }
+
+    /// Activity since `earlier`, an older snapshot of the same vdev
+    pub fn since(&self, earlier: &IoStats) -> IoStats {
+        IoStats {
+            read_ops: self.read_ops.saturating_sub(earlier.read_ops),
+            write_ops: self.write_ops.saturating_sub(earlier.write_ops),
+            other_ops: self.other_ops.saturating_sub(earlier.other_ops),
+            errors: self.errors.saturating_sub(earlier.errors),
+            read_bytes: self.read_bytes.saturating_sub(earlier.read_bytes),
+            write_bytes: self.write_bytes.saturating_sub(earlier.write_bytes),
+            queue_depth: self.queue_depth,
+            read_latency: self.read_latency.since(&earlier.read_latency),
+            write_latency: self.write_latency.since(&earlier.write_latency),
+        }
+    }
+}
+
+/// I/O statistics for a vdev and everything beneath it
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct VdevStats {
+    pub uuid: Uuid,
+    /// This vdev's own counters.  For anything but a leaf, they're the sum of
+    /// its children's.
+    pub stats: IoStats,
+    pub children: Vec<VdevStats>,
+    /// The vdev is missing, and its data must be reconstructed from its peers
+    pub faulted: bool
+}
+
+impl VdevStats {
+    /// Summarize a vdev that has no I/O of its own
+    pub fn aggregate(uuid: Uuid, children: Vec<VdevStats>) -> Self {
+        let mut stats = IoStats::default();
+        for child in children.iter() {
+            stats.merge(&child.stats);
+        }
+        VdevStats{uuid, stats, children, faulted: false}
+    }
+
+    /// Is this vdev, or anything beneath it, faulted?
+    pub fn degraded(&self) -> bool {
+        self.faulted || self.children.iter().any(VdevStats::degraded)
+    }
+
+    /// A vdev that could not be opened
+    pub fn faulted(uuid: Uuid) -> Self {
+        VdevStats{uuid, stats: IoStats::default(), children: Vec::new(),
+                  faulted: true}
+    }
+
+    pub fn leaf(uuid: Uuid, stats: IoStats) -> Self {
+        VdevStats{uuid, stats, children: Vec::new(), faulted: false}
+    }
+
+    /// Activity since `earlier`, an older snapshot of the same vdev tree
+    pub fn since(&self, earlier: &VdevStats) -> VdevStats {
+        let children = self.children.iter()
+            .zip(earlier.children.iter())
+            .map(|(c, e)| c.since(e))
+            .collect();
+        VdevStats {
+            uuid: self.uuid,
+            stats: self.stats.since(&earlier.stats),
+            children,
+            faulted: self.faulted
+        }
+    }
+}
+
+#[cfg(test)]
+mod t {
+use super::*;
+
+#[test]
+fn aggregate() {
+    let mut s0 = IoStats::default();
+    s0.read_ops = 1;
+    s0.read_bytes = 4096;
+    s0.queue_depth = 2;
+    s0.read_latency.record(Duration::from_micros(100));
+    let mut s1 = IoStats::default();
+    s1.write_ops = 3;
+    s1.write_bytes = 12288;
+    s1.queue_depth = 1;
+    s1.write_latency.record(Duration::from_micros(300));
+    let c0 = VdevStats::leaf(Uuid::new_v4(), s0);
+    let c1 = VdevStats::leaf(Uuid::new_v4(), s1);
+    let vs = VdevStats::aggregate(Uuid::new_v4(), vec![c0, c1]);
+    assert_eq!(vs.stats.read_ops, 1);
+    assert_eq!(vs.stats.write_ops, 3);
+    assert_eq!(vs.stats.read_bytes, 4096);
+    assert_eq!(vs.stats.write_bytes, 12288);
+    assert_eq!(vs.stats.queue_depth, 3);
+    assert_eq!(vs.stats.read_latency.count(), 1);
+    assert_eq!(vs.stats.write_latency.count(), 1);
+    assert_eq!(vs.children.len(), 2);
+}
+
+#[test]
+fn degraded() {
+    let c0 = VdevStats::leaf(Uuid::new_v4(), IoStats::default());
+    let c1 = VdevStats::faulted(Uuid::new_v4());
+    let healthy = VdevStats::aggregate(Uuid::new_v4(), vec![c0.clone()]);
+    let vs = VdevStats::aggregate(Uuid::new_v4(), vec![c0, c1]);
+    assert!(!healthy.degraded());
+    assert!(vs.degraded());
+    assert!(!vs.faulted);
+    assert!(vs.children[1].faulted);
+}
+
+#[test]
+fn histogram_record() {
+    let mut h = Histogram::default();
+    h.record(Duration::from_micros(0));
+    h.record(Duration::from_micros(1));
+    h.record(Duration::from_micros(5));
+    h.record(Duration::from_micros(7));
+    h.record(Duration::from_secs(1 << 20));
+    assert_eq!(h.buckets()[0], 1);
+    assert_eq!(h.buckets()[1], 1);
+    assert_eq!(h.buckets()[3], 2);
+    assert_eq!(h.buckets()[BUCKETS - 1], 1);
+    assert_eq!(h.count(), 5);
+}
+
+#[test]
+fn histogram_mean() {
+    let mut h = Histogram::default();
+    assert_eq!(h.mean(), None);
+    h.record(Duration::from_micros(100));
+    h.record(Duration::from_micros(300));
+    assert_eq!(h.mean(), Some(Duration::from_micros(200)));
+}
+
+#[test]
+fn since() {
+    let mut earlier = IoStats::default();
+    earlier.read_ops = 5;
+    earlier.read_latency.record(Duration::from_micros(10));
+    let mut later = earlier;
+    later.read_ops = 8;
+    later.queue_depth = 4;
+    later.read_latency.record(Duration::from_micros(20));
+    let delta = later.since(&earlier);
+    assert_eq!(delta.read_ops, 3);
+    assert_eq!(delta.queue_depth, 4);
+    assert_eq!(delta.read_latency.count(), 1);
+    assert_eq!(delta.read_latency.mean(), Some(Duration::from_micros(20)));
+}
+
+}
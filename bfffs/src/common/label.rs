@@ -6,51 +6,179 @@ use crate::common::*;
 use divbuf::DivBufShared;
 use metrohash::MetroHash64;
 use serde::{de::DeserializeOwned, Serialize};
-use std::{hash::{Hash, Hasher}, io::{self, Seek, SeekFrom}};
+use serde_derive::*;
+use std::{
+    collections::VecDeque,
+    hash::{Hash, Hasher},
+    io::{self, Seek, SeekFrom}
+};
 
 /*
  * On-disk Label Format:
  *
  * Magic:       16 bytes
- * Checksum:    8 bytes     MetroHash64.  Covers all of Length and Contents.
+ * Checksum:    8 bytes     MetroHash64.  Covers everything from Length
+ *                          through Contents.
  * Length:      8 bytes     Length of Contents in bytes
+ * Txg:         4 bytes     Transaction group in which the label was written
+ * Version:     4 bytes     Version of the label format.  Future versions
+ *                          must not move the Magic, Checksum, Length, Txg, or
+ *                          Version fields.
+ * Compat:      8 bytes     Bitmap of compatible features
+ * RoCompat:    8 bytes     Bitmap of read-only compatible features
+ * Incompat:    8 bytes     Bitmap of incompatible features
  * VdevFile:    variable    bincode-encoded VdevFile::Label
  * VdevRaid:    variable    bincode-encoded VdevRaid::Label
+ * Cluster:     variable    bincode-encoded Cluster::Label
  * Pool:        variable    bincode-encoded Pool::Label
  * IDML:        variable    bincode-encoded IDML::Label
  * Database:    variable    bincode-encoded Database::Label
- * Pad:         variable    0-padding fills the remainder, up to 4 LBAs
+ * Pad:         variable    0-padding fills the remainder, up to 4 LBAs.  A
+ *                          label that doesn't fit can't be written.
  *
  * On-disk Reserved Region Format:
  *
  * Label 0      4 LBAs
  * Label 1      4 LBAs
- * Spacemap0    variable    bincode-encoded spacemap.  Size is determined at
- *                          format-time.
- * Spacemap1    variable
+ *
+ * A backup copy of the reserved region is stored at the end of the device, for
+ * simulated zones, or at the end of the last leading conventional zone, for
+ * native zones.
+ *
+ * The spacemap is not stored in the reserved region.  Instead, each Cluster
+ * logs it to ordinary zones, and records the location of the newest log entry
+ * in its label.
+ *
+ * The Cluster, IDML, and Database labels each record their roots for the most
+ * recent REWIND_TXGS transaction groups, newest first.  Space freed in a
+ * transaction group is not erased until that many more have been synced, so
+ * any of those roots may be imported.  Each label may also record the roots of
+ * one older transaction group, the pool checkpoint.  While it exists, no zone
+ * that was in use at the checkpoint is erased.
  */
 /// The file magic is "BFFFS Vdev\0\0\0\0\0\0"
 const MAGIC: &[u8; MAGIC_LEN] = b"BFFFS Vdev\0\0\0\0\0\0";
 const MAGIC_LEN: usize = 16;
 const CHECKSUM_LEN: usize = 8;
 const LENGTH_LEN: usize = 8;
+const TXG_LEN: usize = 4;
+const VERSION_LEN: usize = 4;
+const FEATURES_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC_LEN + CHECKSUM_LEN + LENGTH_LEN + TXG_LEN +
+    VERSION_LEN + FEATURES_LEN;
+/// Current version of the label format.  Labels with any other version cannot
+/// be read.
+///
+/// Version 2 added variable zone layouts, the spacemap log, storage classes,
+/// pool states, and the `TxgRing`s with their checkpoints.  Changes like those,
+/// which every pool must have, bump the version.  Optional changes get a
+/// feature bit instead.
+pub const LABEL_VERSION: u32 = 2;
 pub const LABEL_COUNT: LbaT = 2;
-// Actual label size is about 17 bytes for each RAID member plus 17 bytes for
-// each Cluster, plus a couple hundred bytes more.
+// Actual label size is about 17 bytes for each RAID member plus 600 bytes for
+// each Cluster, plus about two kilobytes more.  `LabelWriter::into_sglist`
+// fails if it grows too big.
 pub const LABEL_LBAS: LbaT = 4;
 pub const LABEL_SIZE: usize = LABEL_LBAS as usize * BYTES_PER_LBA;
-/// Space allocated for storing the spacemap.  This the number of zones whose
-/// information can be recorded in one LBA of storage.
-pub const SPACEMAP_ZONES_PER_LBA: usize = 255;
+/// Number of transaction groups whose roots are kept in the label, and which
+/// may be imported with `--rewind`.
+pub const REWIND_TXGS: usize = 4;
+
+/// A change to the pool checkpoint, to be made when writing a label
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CheckpointOp {
+    /// Preserve the roots of the label's transaction group as the checkpoint
+    Create,
+    /// Forget the checkpoint, so its space may be reclaimed
+    Discard
+}
+
+/// On-disk format features, in the style of ext4 and ZFS.
+///
+/// Each field is a bitmap.  A pool may be imported by any software that
+/// understands all of its `incompat` features.  It may be imported read-write
+/// only if the software also understands all of its `ro_compat` features.
+/// `compat` features may always be ignored.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Features {
+    /// Features that older software may safely ignore
+    pub compat: u64,
+    /// Features that older software may read, but must not write
+    pub ro_compat: u64,
+    /// Features that older software must not touch at all
+    pub incompat: u64,
+}
+
+impl Features {
+    /// Incompatible feature: the `Pool` label records which host last imported
+    /// the pool, and when, so another host won't import it at the same time.
+    /// Older pools may enable it with `upgrade`.
+    pub const MULTIHOST: u64 = 1 << 0;
+
+    /// Incompatible feature: the `Pool` label also records the rate limits for
+    /// background writes.  Older pools may enable it with `upgrade`.
+    pub const BG_WRITE_LIMITS: u64 = 1 << 1;
+
+    /// Incompatible feature: each `VdevRaid` label records which children
+    /// were missing when it was written, and the zone map computed from all
+    /// children.  Faulted children must be reconstructed until they're
+    /// resilvered.  Older pools may enable it with `upgrade`.
+    pub const RAID_FAULTS: u64 = 1 << 2;
+
+    /// Every feature understood by this version of BFFFS.  Newly created pools
+    /// enable all of them.
+    pub const SUPPORTED: Features = Features {
+        compat: 0,
+        ro_compat: 0,
+        incompat: Features::MULTIHOST | Features::BG_WRITE_LIMITS |
+            Features::RAID_FAULTS
+    };
+
+    /// Can a pool with these features be imported read-write?
+    ///
+    /// Returns `ENOTSUP` if the pool has unknown incompatible features, or
+    /// `EROFS` if it has unknown read-only compatible features.
+    pub fn check(self) -> Result<(), Error> {
+        if self.incompat & !Features::SUPPORTED.incompat != 0 {
+            Err(Error::ENOTSUP)
+        } else if self.ro_compat & !Features::SUPPORTED.ro_compat != 0 {
+            Err(Error::EROFS)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read(buf: &[u8]) -> Self {
+        Features {
+            compat: BigEndian::read_u64(&buf[0..8]),
+            ro_compat: BigEndian::read_u64(&buf[8..16]),
+            incompat: BigEndian::read_u64(&buf[16..24])
+        }
+    }
 
-/// How many LBAs should be reserved for each spacemap?
-pub fn spacemap_space(nzones: u64) -> LbaT {
-    div_roundup(nzones, SPACEMAP_ZONES_PER_LBA as u64)
+    /// Return the union of these features and `other`
+    pub fn union(self, other: Features) -> Self {
+        Features {
+            compat: self.compat | other.compat,
+            ro_compat: self.ro_compat | other.ro_compat,
+            incompat: self.incompat | other.incompat
+        }
+    }
+
+    fn write(&self, buf: &mut [u8]) {
+        BigEndian::write_u64(&mut buf[0..8], self.compat);
+        BigEndian::write_u64(&mut buf[8..16], self.ro_compat);
+        BigEndian::write_u64(&mut buf[16..24], self.incompat);
+    }
 }
 
 /// Used to read successive structs out of the label
+#[derive(Clone)]
 pub struct LabelReader {
-    cursor: io::Cursor<DivBuf>
+    cursor: io::Cursor<DivBuf>,
+    features: Features,
+    rewind: Option<TxgT>,
+    txg: TxgT
 }
 
 impl<'de> LabelReader {
@@ -64,7 +192,7 @@ impl<'de> LabelReader {
     /// Construct a `LabelReader` using the raw buffer read from disk
     pub fn from_dbs(buffer: DivBufShared) -> Result<Self, Error> {
         let db = buffer.try_const().unwrap();
-        if db.len() < MAGIC_LEN + CHECKSUM_LEN + LENGTH_LEN {
+        if db.len() < HEADER_LEN {
             return Err(Error::EINVAL);
         }
         if MAGIC[..] != db[0..MAGIC_LEN] {
@@ -74,25 +202,50 @@ impl<'de> LabelReader {
         let checksum = BigEndian::read_u64(
             &db[MAGIC_LEN..MAGIC_LEN + CHECKSUM_LEN]);
         let length_start = MAGIC_LEN + CHECKSUM_LEN;
-        let contents_start = length_start + LENGTH_LEN;
+        let txg_start = length_start + LENGTH_LEN;
+        let version_start = txg_start + TXG_LEN;
+        let features_start = version_start + VERSION_LEN;
+        let contents_start = HEADER_LEN;
+        // Check the version before the checksum, because other versions might
+        // compute the checksum differently.
+        let version = BigEndian::read_u32(&db[version_start..features_start]);
+        if version == 0 {
+            return Err(Error::EINVAL);
+        } else if version != LABEL_VERSION {
+            // Either a newer format, or one that predates a change that we
+            // can't read without
+            return Err(Error::ENOTSUP);
+        }
         let contents_len = BigEndian::read_u64(
-            &db[length_start .. contents_start]);
+            &db[length_start .. txg_start]);
+        if contents_len > (db.len() - contents_start) as u64 {
+            return Err(Error::EINVAL);
+        }
+        let txg = TxgT::from(BigEndian::read_u32(
+            &db[txg_start .. version_start]));
         let mut hasher = MetroHash64::new();
         {
             let contents = &db[contents_start ..
                                contents_start + contents_len as usize];
             contents_len.to_be().hash(&mut hasher);
+            hasher.write(&db[txg_start .. contents_start]);
             hasher.write(contents);
         }
         if checksum != hasher.finish() {
             return Err(Error::ECKSUM);
         }
+        let features = Features::read(&db[features_start .. contents_start]);
 
         let mut cursor = io::Cursor::new(db);
         // Seek past header
         cursor.seek(SeekFrom::Start(contents_start as u64))
             .expect("IoVec too short");
-        Ok(LabelReader { cursor })
+        Ok(LabelReader { cursor, features, rewind: None, txg })
+    }
+
+    /// The on-disk features recorded in this label
+    pub fn features(&self) -> Features {
+        self.features
     }
 
     /// Get the offset of the `label`th label.
@@ -100,16 +253,72 @@ impl<'de> LabelReader {
         assert!(LbaT::from(label) < LABEL_COUNT);
         LbaT::from(label) * LABEL_LBAS
     }
+
+    /// Choose the newest of several copies of a label.
+    ///
+    /// Invalid copies are ignored.  Among the valid copies, the one with the
+    /// highest transaction group wins, with ties going to the earliest copy.
+    /// Returns the index of the winning copy, or the first copy's error if none
+    /// are valid.
+    pub fn newest<I>(copies: I) -> Result<(usize, Self), Error>
+        where I: IntoIterator<Item=Result<Self, Error>>
+    {
+        let mut first_error = None;
+        let mut best: Option<(usize, Self)> = None;
+        for (i, copy) in copies.into_iter().enumerate() {
+            match copy {
+                Ok(lr) => {
+                    if best.as_ref().map(|b| lr.txg > b.1.txg).unwrap_or(true)
+                    {
+                        best = Some((i, lr));
+                    }
+                },
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        best.ok_or_else(|| first_error.unwrap_or(Error::EINVAL))
+    }
+
+    /// The transaction group to import, if not the newest one.
+    pub fn rewind(&self) -> Option<TxgT> {
+        self.rewind
+    }
+
+    /// Import the pool as of transaction group `txg`, rather than the newest
+    /// one.  `txg` must be one of the last `REWIND_TXGS` in the label.
+    pub fn set_rewind(&mut self, txg: Option<TxgT>) {
+        self.rewind = txg;
+    }
+
+    /// The transaction group in which this label was written
+    pub fn txg(&self) -> TxgT {
+        self.txg
+    }
 }
 
 /// Successively writes serialized structs into the label
 #[derive(Clone, Debug)]
 pub struct LabelWriter {
     buffers: SGList,
+    checkpoint_op: Option<CheckpointOp>,
+    features: Features,
     label: u32,
+    txg: TxgT
 }
 
 impl LabelWriter {
+    /// Any change to the pool checkpoint that this label should record
+    pub fn checkpoint_op(&self) -> Option<CheckpointOp> {
+        self.checkpoint_op
+    }
+
+    /// The on-disk features to record in the label's header
+    pub fn features(&self) -> Features {
+        self.features
+    }
+
     /// Which label are we writing?
     pub fn idx(&self) -> u32 {
         self.label
@@ -120,10 +329,29 @@ impl LabelWriter {
         LbaT::from(self.label) * LABEL_LBAS
     }
 
-    /// Create a new label in the `label`th position.
-    pub fn new(label: u32) -> Self {
+    /// Create a new label in the `label`th position, for transaction group
+    /// `txg`.  It will record every supported feature, unless changed with
+    /// `set_features`.
+    pub fn new(label: u32, txg: TxgT) -> Self {
         assert!(LbaT::from(label) < LABEL_COUNT);
-        LabelWriter{buffers: SGList::default(), label}
+        let features = Features::SUPPORTED;
+        LabelWriter{buffers: SGList::default(), checkpoint_op: None, features,
+                    label, txg}
+    }
+
+    /// The transaction group for which this label is being written
+    pub fn txg(&self) -> TxgT {
+        self.txg
+    }
+
+    /// Create or discard the pool checkpoint along with this label
+    pub fn set_checkpoint_op(&mut self, op: Option<CheckpointOp>) {
+        self.checkpoint_op = op;
+    }
+
+    /// Record the pool's on-disk features in the label's header
+    pub fn set_features(&mut self, features: Features) {
+        self.features = features;
     }
 
     /// Write a `T` into the label.
@@ -140,27 +368,335 @@ impl LabelWriter {
 
     /// Consume the `LabelWriter` and return an `SGList` suitable for writing to
     /// the first sector of a disk.
-    pub fn into_sglist(self) -> SGList {
+    ///
+    /// Fails with `EFBIG` if the serialized structs don't fit in `LABEL_SIZE`.
+    pub fn into_sglist(self) -> Result<SGList, Error> {
         let mut sglist: SGList = Vec::with_capacity(self.buffers.len() + 2);
-        let header_len = MAGIC_LEN + CHECKSUM_LEN + LENGTH_LEN;
-        let header_dbs = DivBufShared::with_capacity(header_len);
+        let header_dbs = DivBufShared::with_capacity(HEADER_LEN);
         let mut header = header_dbs.try_mut().unwrap();
         header.extend(&MAGIC[..]);
         let contents = self.buffers.into_iter().rev().collect::<Vec<_>>();
         let contents_len: usize = contents.iter().map(DivBuf::len).sum();
+        let len = HEADER_LEN + contents_len;
+        if len > LABEL_SIZE {
+            return Err(Error::EFBIG);
+        }
+        // Txg, Version, and Features
+        let mut tail = [0u8; TXG_LEN + VERSION_LEN + FEATURES_LEN];
+        BigEndian::write_u32(&mut tail[0..TXG_LEN], self.txg.0);
+        BigEndian::write_u32(&mut tail[TXG_LEN..TXG_LEN + VERSION_LEN],
+                             LABEL_VERSION);
+        self.features.write(&mut tail[TXG_LEN + VERSION_LEN..]);
         let mut hasher = MetroHash64::new();
         (contents_len as u64).to_be().hash(&mut hasher);
+        hasher.write(&tail[..]);
         checksum_sglist(&contents, &mut hasher);
         header.try_resize(MAGIC_LEN + CHECKSUM_LEN, 0).unwrap();
         BigEndian::write_u64(&mut header[MAGIC_LEN..], hasher.finish());
         header.try_resize(MAGIC_LEN + CHECKSUM_LEN + LENGTH_LEN, 0).unwrap();
         let length_start = MAGIC_LEN + CHECKSUM_LEN;
         BigEndian::write_u64(&mut header[length_start..], contents_len as u64);
+        header.extend(&tail[..]);
         sglist.push(header.freeze());
         sglist.extend(contents);
-        let len = MAGIC_LEN + CHECKSUM_LEN + LENGTH_LEN + contents_len;
         let padlen = LABEL_SIZE - len;
-        sglist.append(&mut zero_sglist(padlen));
-        sglist
+        if padlen > 0 {
+            sglist.append(&mut zero_sglist(padlen));
+        }
+        Ok(sglist)
+    }
+}
+
+/// The roots of some on-disk structure for the last few transaction groups,
+/// newest first, plus those of the pool checkpoint, if any.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TxgRing<T> {
+    roots: VecDeque<(TxgT, T)>,
+    checkpoint: Option<(TxgT, T)>
+}
+
+impl<T: Clone> TxgRing<T> {
+    /// The transaction group of the pool checkpoint, if there is one
+    pub fn checkpoint_txg(&self) -> Option<TxgT> {
+        self.checkpoint.as_ref().map(|(t, _)| *t)
+    }
+
+    /// Record `root` as the root for transaction group `txg`.
+    ///
+    /// Replaces any root already recorded for `txg`, and forgets the oldest
+    /// root if there are more than `REWIND_TXGS`.
+    pub fn push(&mut self, txg: TxgT, root: T) {
+        if self.roots.front().map(|(t, _)| *t == txg).unwrap_or(false) {
+            self.roots.pop_front();
+        }
+        self.roots.push_front((txg, root));
+        self.roots.truncate(REWIND_TXGS);
+    }
+
+    /// Choose the root to import.
+    ///
+    /// Normally that's the newest one.  But if `rewind` is set, then forget all
+    /// roots newer than `rewind` and return `rewind`'s.  `rewind` may also be
+    /// the checkpoint's transaction group, which is kept.  Returns `ENOENT` if
+    /// the ring has no root for the requested transaction group.
+    pub fn select(&mut self, rewind: Option<TxgT>) -> Result<(TxgT, T), Error>
+    {
+        if let Some(txg) = rewind {
+            while self.roots.front().map(|(t, _)| *t > txg).unwrap_or(false) {
+                self.roots.pop_front();
+            }
+            if self.roots.front().map(|(t, _)| *t != txg).unwrap_or(true) {
+                match self.checkpoint.clone() {
+                    Some((t, root)) if t == txg => {
+                        self.roots.clear();
+                        self.roots.push_front((t, root));
+                    },
+                    _ => return Err(Error::ENOENT)
+                }
+            }
+        }
+        self.roots.front().cloned().ok_or(Error::ENOENT)
+    }
+
+    /// The transaction groups whose roots are recorded, newest first.  The
+    /// checkpoint's is not included.
+    pub fn txgs(&self) -> impl Iterator<Item=TxgT> + '_ {
+        self.roots.iter().map(|(t, _)| *t)
     }
+
+    /// Apply a change to the checkpoint.  A new checkpoint preserves the
+    /// newest root.
+    pub fn update_checkpoint(&mut self, op: Option<CheckpointOp>) {
+        match op {
+            Some(CheckpointOp::Create) => {
+                self.checkpoint = self.roots.front().cloned();
+            },
+            Some(CheckpointOp::Discard) => {
+                self.checkpoint = None;
+            },
+            None => ()
+        }
+    }
+}
+
+impl<T> Default for TxgRing<T> {
+    fn default() -> Self {
+        TxgRing {
+            roots: VecDeque::with_capacity(REWIND_TXGS),
+            checkpoint: None
+        }
+    }
+}
+
+// LCOV_EXCL_START
+#[cfg(test)]
+mod t {
+
+mod features {
+    use pretty_assertions::assert_eq;
+    use super::super::*;
+
+    #[test]
+    fn check_supported() {
+        assert!(Features::default().check().is_ok());
+        assert!(Features::SUPPORTED.check().is_ok());
+    }
+
+    #[test]
+    fn check_unknown_compat() {
+        let features = Features{compat: !0, .. Features::default()};
+        assert!(features.check().is_ok());
+    }
+
+    #[test]
+    fn check_unknown_incompat() {
+        let features = Features{incompat: !0, ro_compat: !0, compat: 0};
+        assert_eq!(features.check(), Err(Error::ENOTSUP));
+    }
+
+    #[test]
+    fn check_unknown_ro_compat() {
+        let features = Features{ro_compat: !0, .. Features::default()};
+        assert_eq!(features.check(), Err(Error::EROFS));
+    }
+
+    #[test]
+    fn union() {
+        let a = Features{compat: 1, ro_compat: 2, incompat: 4};
+        let b = Features{compat: 2, ro_compat: 2, incompat: 8};
+        let expected = Features{compat: 3, ro_compat: 2, incompat: 12};
+        assert_eq!(a.union(b), expected);
+    }
+}
+
+mod label_reader {
+    use pretty_assertions::assert_eq;
+    use super::super::*;
+
+    fn roundtrip(features: Features) -> DivBufShared {
+        let mut lw = LabelWriter::new(0, TxgT::from(3));
+        lw.set_features(features);
+        lw.serialize(&42u32).unwrap();
+        let v = lw.into_sglist().unwrap().iter()
+            .fold(Vec::new(), |mut acc, db| {
+                acc.extend_from_slice(&db[..]);
+                acc
+            });
+        DivBufShared::from(v)
+    }
+
+    #[test]
+    fn features() {
+        let features = Features{compat: 1, ro_compat: 2,
+                                incompat: Features::SUPPORTED.incompat};
+        let mut lr = LabelReader::from_dbs(roundtrip(features)).unwrap();
+        assert_eq!(lr.features(), features);
+        assert_eq!(lr.txg(), TxgT::from(3));
+        assert_eq!(lr.deserialize::<u32>().unwrap(), 42);
+    }
+
+    #[test]
+    fn future_version() {
+        let dbs = roundtrip(Features::SUPPORTED);
+        {
+            let mut db = dbs.try_mut().unwrap();
+            let version_start = MAGIC_LEN + CHECKSUM_LEN + LENGTH_LEN + TXG_LEN;
+            BigEndian::write_u32(&mut db[version_start..], LABEL_VERSION + 1);
+        }
+        assert_eq!(LabelReader::from_dbs(dbs).err(), Some(Error::ENOTSUP));
+    }
+
+    // Labels from before a required format change can't be decoded
+    #[test]
+    fn old_version() {
+        let dbs = roundtrip(Features::SUPPORTED);
+        {
+            let mut db = dbs.try_mut().unwrap();
+            let version_start = MAGIC_LEN + CHECKSUM_LEN + LENGTH_LEN + TXG_LEN;
+            BigEndian::write_u32(&mut db[version_start..], LABEL_VERSION - 1);
+        }
+        assert_eq!(LabelReader::from_dbs(dbs).err(), Some(Error::ENOTSUP));
+    }
+
+    #[test]
+    fn optional_missing() {
+        let features = Features::default();
+        let lr = LabelReader::from_dbs(roundtrip(features)).unwrap();
+        assert_eq!(lr.features(), features);
+    }
+}
+
+mod label_writer {
+    use pretty_assertions::assert_eq;
+    use super::super::*;
+
+    #[test]
+    fn fits() {
+        let mut lw = LabelWriter::new(0, TxgT::from(0));
+        lw.serialize(&vec![0u8; LABEL_SIZE - HEADER_LEN - 8]).unwrap();
+        let sglist = lw.into_sglist().unwrap();
+        let len = sglist.iter().map(DivBuf::len).sum::<usize>();
+        assert_eq!(len, LABEL_SIZE);
+    }
+
+    // A label that doesn't fit should fail to write, rather than overwrite
+    // whatever follows it
+    #[test]
+    fn too_big() {
+        let mut lw = LabelWriter::new(0, TxgT::from(0));
+        lw.serialize(&vec![0u8; LABEL_SIZE - HEADER_LEN - 7]).unwrap();
+        assert_eq!(lw.into_sglist().err(), Some(Error::EFBIG));
+    }
+}
+
+mod txg_ring {
+    use pretty_assertions::assert_eq;
+    use super::super::*;
+
+    fn ring(txgs: std::ops::Range<u32>) -> TxgRing<u32> {
+        let mut ring = TxgRing::default();
+        for t in txgs {
+            ring.push(TxgT::from(t), t * 10);
+        }
+        ring
+    }
+
+    #[test]
+    fn checkpoint_create() {
+        let mut ring = ring(0..3);
+        ring.update_checkpoint(Some(CheckpointOp::Create));
+        assert_eq!(ring.checkpoint_txg(), Some(TxgT::from(2)));
+        // Pushing more roots mustn't disturb the checkpoint
+        for t in 3..10 {
+            ring.push(TxgT::from(t), t * 10);
+        }
+        ring.update_checkpoint(None);
+        assert_eq!(ring.checkpoint_txg(), Some(TxgT::from(2)));
+    }
+
+    #[test]
+    fn checkpoint_discard() {
+        let mut ring = ring(0..3);
+        ring.update_checkpoint(Some(CheckpointOp::Create));
+        ring.update_checkpoint(Some(CheckpointOp::Discard));
+        assert_eq!(ring.checkpoint_txg(), None);
+        assert_eq!(ring.select(Some(TxgT::from(2))), Ok((TxgT::from(2), 20)));
+    }
+
+    #[test]
+    fn push_overflow() {
+        let ring = ring(0..10);
+        let expected = (6..10).rev().map(TxgT::from).collect::<Vec<_>>();
+        assert_eq!(ring.txgs().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn push_same_txg() {
+        let mut ring = ring(0..2);
+        ring.push(TxgT::from(1), 11);
+        let expected = vec![TxgT::from(1), TxgT::from(0)];
+        assert_eq!(ring.txgs().collect::<Vec<_>>(), expected);
+        assert_eq!(ring.select(None), Ok((TxgT::from(1), 11)));
+    }
+
+    #[test]
+    fn select_empty() {
+        let mut ring = TxgRing::<u32>::default();
+        assert_eq!(ring.select(None), Err(Error::ENOENT));
+    }
+
+    #[test]
+    fn select_newest() {
+        let mut ring = ring(0..3);
+        assert_eq!(ring.select(None), Ok((TxgT::from(2), 20)));
+        assert_eq!(ring.txgs().count(), 3);
+    }
+
+    #[test]
+    fn select_rewind() {
+        let mut ring = ring(0..3);
+        assert_eq!(ring.select(Some(TxgT::from(1))), Ok((TxgT::from(1), 10)));
+        let expected = vec![TxgT::from(1), TxgT::from(0)];
+        assert_eq!(ring.txgs().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn select_rewind_to_checkpoint() {
+        let mut ring = ring(0..3);
+        ring.update_checkpoint(Some(CheckpointOp::Create));
+        for t in 3..10 {
+            ring.push(TxgT::from(t), t * 10);
+        }
+        assert_eq!(ring.select(Some(TxgT::from(2))), Ok((TxgT::from(2), 20)));
+        assert_eq!(ring.txgs().collect::<Vec<_>>(), vec![TxgT::from(2)]);
+        assert_eq!(ring.checkpoint_txg(), Some(TxgT::from(2)));
+    }
+
+    #[test]
+    fn select_rewind_too_far() {
+        let mut ring = ring(3..9);
+        assert_eq!(ring.select(Some(TxgT::from(4))), Err(Error::ENOENT));
+    }
+}
 }
+// LCOV_EXCL_STOP
@@ -18,6 +18,7 @@ use serde_derive::*;
 use std::{
     fmt::{self, Display, Formatter},
     hash::Hasher,
+    io,
     ops::{Add, AddAssign, Div, Sub},
 };
 use uuid;
@@ -25,6 +26,7 @@ use uuid;
 pub mod cache;
 pub mod cleaner;
 pub mod cluster;
+pub mod control;
 pub mod database;
 pub mod dataset;
 pub mod ddml;
@@ -33,10 +35,12 @@ pub mod dml;
 pub mod fs;
 pub mod fs_tree;
 pub mod idml;
+pub mod iostat;
 pub mod label;
 pub mod pool;
 pub mod property;
 pub mod raid;
+pub mod throttle;
 pub mod tree;
 pub mod vdev;
 pub mod vdev_block;
@@ -74,7 +78,7 @@ pub type IoVecMut = DivBufMut;
 pub type LbaT = u64;
 
 /// BFFFS's error type.  Basically just an errno
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Primitive)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Primitive, Serialize)]
 pub enum Error {
     // Standard errnos
     EPERM           = libc::EPERM as isize,
@@ -205,6 +209,14 @@ impl From<nix::Error> for Error {
     }
 }
 
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        e.raw_os_error()
+            .and_then(Error::from_i32)
+            .unwrap_or(Error::EUNKNOWN)
+    }
+}
+
 impl Into<i32> for Error {
     fn into(self) -> i32 {
         match self {
@@ -305,10 +317,18 @@ impl TypicalSize for RID {
 pub struct Uuid(uuid::Uuid);
 
 impl Uuid {
+    pub fn is_nil(&self) -> bool {
+        self.0.is_nil()
+    }
+
     pub fn new_v4() -> Self {
         Uuid(uuid::Uuid::new_v4())
     }
 
+    pub fn nil() -> Self {
+        Uuid(uuid::Uuid::nil())
+    }
+
     pub fn parse_str(input: &str) -> Result<Uuid, uuid::parser::ParseError> {
         uuid::Uuid::parse_str(input).map(Uuid)
     }
@@ -370,6 +390,27 @@ impl TypicalSize for f32 {
     const TYPICAL_SIZE: usize = 4;
 }
 
+/// What kind of record is being written.
+///
+/// Records of different classes have very different lifetimes.  Each `Cluster`
+/// writes each class into its own open zone, so records in the same zone tend
+/// to die together, leaving less live data for the cleaner to move.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd,
+         Serialize)]
+pub enum WriteClass {
+    /// Tree nodes.  They are short-lived, because every modification to a
+    /// tree rewrites its nodes.
+    Metadata,
+    /// File data, extended attributes, and other blobs
+    Data,
+    /// Records moved by the cleaner.  Having already outlived the rest of
+    /// their zone, they will probably live a long time yet.
+    Relocation,
+    /// The `Cluster`'s own spacemap log.  It gets freed all at once, at each
+    /// checkpoint.
+    Spacemap,
+}
+
 /// Our scatter-gather list.  A slice of reference-counted `IoVec`s.
 pub type SGList = Vec<IoVec>;
 
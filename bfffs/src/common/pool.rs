@@ -2,7 +2,14 @@
 
 use crate::{
     boxfut,
-    common::{*, label::*}
+    common::{
+        *,
+        iostat::VdevStats,
+        label::*,
+        throttle::IoLimits,
+        vdev::PriorityClass,
+        vdev_block::SchedulerPolicy
+    }
 };
 use futures::{
     Future,
@@ -16,14 +23,18 @@ use std::{
     ops::Range,
     rc::Rc,
     sync::{
-        atomic::{AtomicU32, AtomicU64, Ordering},
-        Arc
-    }
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc,
+        Mutex,
+        RwLock
+    },
+    time::{SystemTime, UNIX_EPOCH}
 };
 #[cfg(not(test))] use std::{
     num::NonZeroU64,
     path::{Path, PathBuf},
 };
+#[cfg(not(test))] use crate::common::vdev_file::VdevFile;
 use std::collections::BTreeMap;
 use tokio::executor;
 #[cfg(not(test))] use tokio::executor::{DefaultExecutor, Executor};
@@ -40,19 +51,53 @@ pub type PoolFut = dyn Future<Item = (), Error = Error>;
 enum Rpc {
     Allocated(oneshot::Sender<LbaT>),
     FindClosedZone(ZoneT, oneshot::Sender<Option<cluster::ClosedZone>>),
-    Flush(u32, oneshot::Sender<Result<(), Error>>),
+    FinishZones(TxgT, oneshot::Sender<Result<(), Error>>),
+    Flush(TxgT, oneshot::Sender<Result<(), Error>>),
     Free(LbaT, LbaT, oneshot::Sender<Result<(), Error>>),
-    OptimumQueueDepth(oneshot::Sender<u32>),
-    Read(IoVecMut, LbaT, oneshot::Sender<Result<(), Error>>),
-    Shutdown(),
+    IoStats(oneshot::Sender<VdevStats>),
+    Read(IoVecMut, LbaT, PriorityClass, oneshot::Sender<Result<(), Error>>),
+    Reserved(oneshot::Sender<LbaT>),
+    SetLimits(IoLimits),
+    SetScheduler(SchedulerPolicy),
+    Shutdown(oneshot::Sender<()>),
     Size(oneshot::Sender<LbaT>),
     SyncAll(oneshot::Sender<Result<(), Error>>),
-    Write(IoVec, TxgT, oneshot::Sender<Result<LbaT, Error>>),
+    Write(IoVec, WriteClass, TxgT, PriorityClass,
+          oneshot::Sender<Result<LbaT, Error>>),
     WriteLabel(LabelWriter, oneshot::Sender<Result<(), Error>>),
     #[cfg(debug_assertions)]
     AssertCleanZone(ZoneT, TxgT),
 }
 
+/// Figures that a `Cluster` reports to its `ClusterProxy`.  Refreshed after
+/// every RPC.
+#[derive(Debug)]
+struct ClusterStatus {
+    /// Does the `Cluster` have a pool checkpoint?
+    checkpoint: AtomicBool,
+    /// Space held by the checkpoint
+    checkpointed: AtomicU64,
+    /// Space used by anything but the spacemap log
+    live_data: AtomicU64
+}
+
+impl ClusterStatus {
+    fn new(cluster: &Cluster) -> Self {
+        ClusterStatus {
+            checkpoint: AtomicBool::new(cluster.checkpoint().is_some()),
+            checkpointed: AtomicU64::new(cluster.checkpointed()),
+            live_data: AtomicU64::new(cluster.live_data())
+        }
+    }
+
+    fn refresh(&self, cluster: &Cluster) {
+        self.checkpoint.store(cluster.checkpoint().is_some(),
+                              Ordering::Relaxed);
+        self.checkpointed.store(cluster.checkpointed(), Ordering::Relaxed);
+        self.live_data.store(cluster.live_data(), Ordering::Relaxed);
+    }
+}
+
 /// RPC server for `Cluster` objects
 ///
 /// As `Cluster` is neither `Send` nor `Sync` it cannot be directly accessed
@@ -60,44 +105,71 @@ enum Rpc {
 /// thread, it owns a `Cluster` and serves RPC requests from its own and other
 /// threads.
 struct ClusterServer {
-    cluster: Cluster
+    cluster: Cluster,
+    /// Shared with the `ClusterProxy`.  Since the `Cluster` tunes its queue
+    /// depth at runtime, we refresh this on every RPC.
+    optimum_queue_depth: Arc<AtomicU32>,
+    /// Shared with the `ClusterProxy`
+    status: Arc<ClusterStatus>
 }
 
 impl ClusterServer {
-    fn new(cluster: Cluster) -> Self {
-        ClusterServer{cluster}
+    fn new(cluster: Cluster, optimum_queue_depth: Arc<AtomicU32>,
+           status: Arc<ClusterStatus>) -> Self
+    {
+        ClusterServer{cluster, optimum_queue_depth, status}
     }
 
     /// Start the `ClusterServer` in the background, in the current thread
     fn run(cs: Rc<ClusterServer>, rx: mpsc::UnboundedReceiver<Rpc>) {
         let fut = future::lazy(move || {
             // In Futures 0.2, use for_each_concurrent instead
-            rx.for_each(move |rpc| cs.dispatch(rpc))
+            rx.map_err(|_| None)
+            .for_each(move |rpc| cs.dispatch(rpc))
             // If we get here, the ClusterProxy was dropped
+        }).or_else(|tx: Option<oneshot::Sender<()>>| {
+            // By now the loop, and the Cluster with it, has been dropped.  That
+            // closed its disks and released their locks.
+            if let Some(tx) = tx {
+                // The caller may have stopped waiting
+                let _ = tx.send(());
+            }
+            Ok(())
         });
         executor::current_thread::TaskExecutor::current().spawn_local(
             Box::new(fut)
         ).unwrap();
     }
 
-    fn dispatch(&self, rpc: Rpc) -> impl Future<Item=(), Error=()>
+    fn dispatch(&self, rpc: Rpc)
+        -> impl Future<Item=(), Error=Option<oneshot::Sender<()>>>
     {
-        match rpc {
+        self.optimum_queue_depth.store(self.cluster.optimum_queue_depth(),
+                                       Ordering::Relaxed);
+        let fut = match rpc {
             #[cfg(debug_assertions)]
             Rpc::AssertCleanZone(zone, txg) => {
                 self.cluster.assert_clean_zone(zone, txg);
-                boxfut!(future::ok::<(), ()>(()), _, _, 'static)
+                boxfut!(future::ok(()), _, _, 'static)
             },
             Rpc::Allocated(tx) => {
                 tx.send(self.cluster.allocated()).unwrap();
-                boxfut!(future::ok::<(), ()>(()), _, _, 'static)
+                boxfut!(future::ok(()), _, _, 'static)
             },
             Rpc::FindClosedZone(zid, tx) => {
                 tx.send(self.cluster.find_closed_zone(zid)).unwrap();
-                boxfut!(future::ok::<(), ()>(()), _, _, 'static)
+                boxfut!(future::ok(()), _, _, 'static)
+            },
+            Rpc::FinishZones(txg, tx) => {
+                let fut = self.cluster.finish_zones(txg)
+                .then(|r| {
+                    tx.send(r).unwrap();
+                    Ok(())
+                });
+                boxfut!(fut, _, _, 'static)
             },
-            Rpc::Flush(idx, tx) => {
-                let fut = self.cluster.flush(idx)
+            Rpc::Flush(txg, tx) => {
+                let fut = self.cluster.flush(txg)
                 .then(|r| {
                     tx.send(r).unwrap();
                     Ok(())
@@ -112,25 +184,37 @@ impl ClusterServer {
                 });
                 boxfut!(fut, _, _, 'static)
             }
-            Rpc::OptimumQueueDepth(tx) => {
-                tx.send(self.cluster.optimum_queue_depth()).unwrap();
-                boxfut!(future::ok::<(), ()>(()), _, _, 'static)
+            Rpc::IoStats(tx) => {
+                tx.send(self.cluster.iostats()).unwrap();
+                boxfut!(future::ok(()), _, _, 'static)
             },
-            Rpc::Read(buf, lba, tx) => {
-                let fut = self.cluster.read(buf, lba)
+            Rpc::Read(buf, lba, priority, tx) => {
+                let fut = self.cluster.read(buf, lba, priority)
                 .then(|r| {
                     tx.send(r).unwrap();
                     Ok(())
                 });
                 boxfut!(fut, _, _, 'static)
             },
-            Rpc::Shutdown() => {
+            Rpc::SetLimits(limits) => {
+                self.cluster.set_limits(limits);
+                boxfut!(future::ok(()), _, _, 'static)
+            },
+            Rpc::Reserved(tx) => {
+                tx.send(self.cluster.reserved()).unwrap();
+                boxfut!(future::ok(()), _, _, 'static)
+            },
+            Rpc::SetScheduler(policy) => {
+                self.cluster.set_scheduler(policy);
+                boxfut!(future::ok(()), _, _, 'static)
+            },
+            Rpc::Shutdown(tx) => {
                 // Returning an error will cause the service loop to shut down
-                Box::new(future::err::<(), ()>(()))
+                boxfut!(future::err(Some(tx)), _, _, 'static)
             },
             Rpc::Size(tx) => {
                 tx.send(self.cluster.size()).unwrap();
-                boxfut!(future::ok::<(), ()>(()), _, _, 'static)
+                boxfut!(future::ok(()), _, _, 'static)
             },
             Rpc::SyncAll(tx) => {
                 let fut = self.cluster.sync_all()
@@ -140,8 +224,8 @@ impl ClusterServer {
                 });
                 boxfut!(fut, _, _, 'static)
             },
-            Rpc::Write(buf, txg, tx) => {
-                match self.cluster.write(buf, txg) {
+            Rpc::Write(buf, class, txg, priority, tx) => {
+                match self.cluster.write(buf, class, txg, priority) {
                     Ok((lba, wfut)) => {
                         let txfut = wfut
                             .then(move |r| {
@@ -167,7 +251,10 @@ impl ClusterServer {
                 });
                 boxfut!(fut, _, _, 'static)
             },
-        }
+        };
+        // Frees, flushes, and label writes all change it synchronously
+        self.status.refresh(&self.cluster);
+        fut
     }
 }
 
@@ -196,9 +283,15 @@ impl Future for ClusterProxyWrite
 }
 
 /// `Send`able, `Clone`able handle to a `ClusterServer`
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ClusterProxy {
+    /// The `Cluster`'s most recently reported optimum queue depth
+    optimum_queue_depth: Arc<AtomicU32>,
+    /// The `Cluster`'s most recently reported status
+    status: Arc<ClusterStatus>,
     server: mpsc::UnboundedSender<Rpc>,
+    /// Storage class that the `Pool` will assign to this `Cluster`
+    storage_class: StorageClass,
     // Copy of the underlying Cluster's uuid
     uuid: Uuid
 }
@@ -217,9 +310,18 @@ impl ClusterProxy {
         self.server.unbounded_send(rpc).unwrap();
     }
 
-    fn flush(&self, idx: u32) -> impl Future<Item=(), Error=Error> + Send {
+    fn finish_zones(&self, txg: TxgT)
+        -> impl Future<Item=(), Error=Error> + Send
+    {
+        let (tx, rx) = oneshot::channel::<Result<(), Error>>();
+        let rpc = Rpc::FinishZones(txg, tx);
+        self.server.unbounded_send(rpc).unwrap();
+        ClusterProxy::rx_unit_result(rx)
+    }
+
+    fn flush(&self, txg: TxgT) -> impl Future<Item=(), Error=Error> + Send {
         let (tx, rx) = oneshot::channel::<Result<(), Error>>();
-        let rpc = Rpc::Flush(idx, tx);
+        let rpc = Rpc::Flush(txg, tx);
         self.server.unbounded_send(rpc).unwrap();
         ClusterProxy::rx_unit_result(rx)
     }
@@ -242,9 +344,9 @@ impl ClusterProxy {
         rx.map_err(|_| Error::EPIPE)
     }
 
-    fn optimum_queue_depth(&self) -> impl Future<Item=u32, Error=Error> {
-        let (tx, rx) = oneshot::channel::<u32>();
-        let rpc = Rpc::OptimumQueueDepth(tx);
+    fn iostats(&self) -> impl Future<Item=VdevStats, Error=Error> {
+        let (tx, rx) = oneshot::channel::<VdevStats>();
+        let rpc = Rpc::IoStats(tx);
         self.server.unbounded_send(rpc).unwrap();
         rx.map_err(|_| Error::EPIPE)
     }
@@ -254,16 +356,21 @@ impl ClusterProxy {
     pub fn new(cluster: Cluster) -> Self {
         let (tx, rx) = mpsc::unbounded();
         let uuid = cluster.uuid();
-        let cs = Rc::new(ClusterServer::new(cluster));
+        let oqd = Arc::new(AtomicU32::new(cluster.optimum_queue_depth()));
+        let status = Arc::new(ClusterStatus::new(&cluster));
+        let cs = Rc::new(ClusterServer::new(cluster, oqd.clone(),
+                                            status.clone()));
         ClusterServer::run(cs, rx);
-        ClusterProxy{server: tx, uuid}
+        let storage_class = StorageClass::default();
+        ClusterProxy{optimum_queue_depth: oqd, status, server: tx,
+                     storage_class, uuid}
     }
 
-    fn read(&self, buf: IoVecMut, lba: LbaT)
+    fn read(&self, buf: IoVecMut, lba: LbaT, priority: PriorityClass)
         -> impl Future<Item=(), Error=Error>
     {
         let (tx, rx) = oneshot::channel::<Result<(), Error>>();
-        let rpc = Rpc::Read(buf, lba, tx);
+        let rpc = Rpc::Read(buf, lba, priority, tx);
         self.server.unbounded_send(rpc).unwrap();
         ClusterProxy::rx_unit_result(rx)
     }
@@ -276,11 +383,39 @@ impl ClusterProxy {
             .and_then(|result| result.into_future())
     }
 
-    fn shutdown(&self) {
-        let rpc = Rpc::Shutdown();
+    fn set_limits(&self, limits: IoLimits) {
+        let rpc = Rpc::SetLimits(limits);
+        self.server.unbounded_send(rpc).unwrap();
+    }
+
+    fn reserved(&self) -> impl Future<Item = LbaT, Error = Error> {
+        let (tx, rx) = oneshot::channel::<LbaT>();
+        let rpc = Rpc::Reserved(tx);
+        self.server.unbounded_send(rpc).unwrap();
+        rx.map_err(|_| Error::EPIPE)
+    }
+
+    fn set_scheduler(&self, policy: SchedulerPolicy) {
+        let rpc = Rpc::SetScheduler(policy);
+        self.server.unbounded_send(rpc).unwrap();
+    }
+
+    /// Set the storage class of a `Cluster` that hasn't yet joined a `Pool`.
+    pub fn set_storage_class(&mut self, class: StorageClass) {
+        self.storage_class = class;
+    }
+
+    /// Shut down the `ClusterServer`.
+    ///
+    /// The returned future completes once the `Cluster` has been dropped.
+    fn shutdown(&self) -> impl Future<Item = (), Error = Error> {
+        let (tx, rx) = oneshot::channel::<()>();
+        let rpc = Rpc::Shutdown(tx);
         // Ignore errors.  An error indicates that the ClusterServer is already
         // shut down.
         let _ = self.server.unbounded_send(rpc);
+        // Likewise, a canceled reply means the ClusterServer was already gone
+        rx.then(|_| Ok(()))
     }
 
     fn size(&self) -> impl Future<Item = LbaT, Error = Error> {
@@ -301,10 +436,11 @@ impl ClusterProxy {
         self.uuid
     }
 
-    fn write(&self, buf: IoVec, txg: TxgT) -> ClusterProxyWrite
+    fn write(&self, buf: IoVec, class: WriteClass, txg: TxgT,
+             priority: PriorityClass) -> ClusterProxyWrite
     {
         let (tx, rx) = oneshot::channel::<Result<LbaT, Error>>();
-        let rpc = Rpc::Write(buf, txg, tx);
+        let rpc = Rpc::Write(buf, class, txg, priority, tx);
         self.server.unbounded_send(rpc).unwrap();
         ClusterProxyWrite{rx}
     }
@@ -346,27 +482,210 @@ pub struct Label {
     /// Pool UUID, fixed at format time
     pub uuid:               Uuid,
 
-    /// `UUID`s of all component `VdevRaid`s
+    /// `UUID`s of all component `VdevRaid`s.  Removed `VdevRaid`s leave
+    /// behind a nil `UUID`, so the others keep their indices.
     pub children:           Vec<Uuid>,
+
+    /// I/O scheduling policy for all of the pool's disks
+    pub scheduler:          SchedulerPolicy,
+
+    /// I/O rate limits for each of the pool's disks.  The background write
+    /// limits are only recorded with the `BG_WRITE_LIMITS` feature.
+    #[serde(with = "label_limits")]
+    pub limits:             IoLimits,
+
+    /// `UUID` of the `VdevRaid` being removed, if any
+    pub removing:           Option<Uuid>,
+
+    /// Storage class of each child, in the same order as `children`
+    pub classes:            Vec<StorageClass>,
+
+    /// Whether the pool is in use, exported, or destroyed
+    pub state:              PoolState,
+
+    /// `hostid` of the system that last imported the pool, or 0 if the pool
+    /// lacks the `MULTIHOST` feature
+    #[serde(skip)]
+    pub hostid:             u32,
+
+    /// When the pool was last imported, in seconds since the epoch, or 0 if
+    /// the pool lacks the `MULTIHOST` feature
+    #[serde(skip)]
+    pub import_time:        u64,
+}
+
+impl Label {
+    /// Read a `Label` out of `reader`, in whichever layout its features call
+    /// for.
+    pub fn read(reader: &mut LabelReader) -> bincode::Result<Self> {
+        let mut label: Label = reader.deserialize()?;
+        if reader.features().incompat & Features::MULTIHOST != 0 {
+            let host: HostLabel = reader.deserialize()?;
+            label.hostid = host.hostid;
+            label.import_time = host.import_time;
+        }
+        if reader.features().incompat & Features::BG_WRITE_LIMITS != 0 {
+            let bg: BgWriteLabel = reader.deserialize()?;
+            label.limits.bg_write_bw = bg.bg_write_bw;
+            label.limits.bg_write_iops = bg.bg_write_iops;
+        }
+        Ok(label)
+    }
+
+    /// Write this `Label` into `labeller`, in whichever layout its features
+    /// call for.
+    pub fn write(&self, labeller: &mut LabelWriter) -> bincode::Result<()> {
+        // serialize is LIFO, so the optional parts go first, in reverse order
+        if labeller.features().incompat & Features::BG_WRITE_LIMITS != 0 {
+            let bg = BgWriteLabel {
+                bg_write_bw: self.limits.bg_write_bw,
+                bg_write_iops: self.limits.bg_write_iops
+            };
+            labeller.serialize(&bg)?;
+        }
+        if labeller.features().incompat & Features::MULTIHOST != 0 {
+            let host = HostLabel {
+                hostid: self.hostid,
+                import_time: self.import_time
+            };
+            labeller.serialize(&host)?;
+        }
+        labeller.serialize(self)
+    }
+}
+
+/// The part of a `Pool`'s label that is only present with the `MULTIHOST`
+/// feature.  On disk, it immediately follows the rest of the `Label`.
+#[derive(Serialize, Deserialize, Debug)]
+struct HostLabel {
+    hostid:             u32,
+    import_time:        u64,
+}
+
+/// The part of a `Pool`'s label that is only present with the
+/// `BG_WRITE_LIMITS` feature.  On disk, it follows the `HostLabel`, if any.
+#[derive(Serialize, Deserialize, Debug)]
+struct BgWriteLabel {
+    bg_write_bw:        Option<u64>,
+    bg_write_iops:      Option<u64>,
+}
+
+/// Encodes `Label::limits` in its original layout, which lacks the background
+/// write limits.  Those go in the `BgWriteLabel` instead.
+mod label_limits {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use super::IoLimits;
+
+    type Limits = (Option<u64>, Option<u64>, Option<u64>, Option<u64>,
+                   Option<u64>, Option<u64>);
+
+    pub fn serialize<S>(limits: &IoLimits, serializer: S)
+        -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        (limits.read_bw, limits.read_iops, limits.write_bw, limits.write_iops,
+         limits.bg_read_bw, limits.bg_read_iops).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<IoLimits, D::Error>
+        where D: Deserializer<'de>
+    {
+        let (read_bw, read_iops, write_bw, write_iops, bg_read_bw, bg_read_iops)
+            = Limits::deserialize(deserializer)?;
+        Ok(IoLimits {
+            read_bw, read_iops, write_bw, write_iops, bg_read_bw, bg_read_iops,
+            .. IoLimits::default()
+        })
+    }
+}
+
+/// Lifecycle state of a pool, as recorded in its label
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum PoolState {
+    /// Imported, or was imported when the system went down
+    Active,
+    /// Cleanly exported.  It may be imported again.
+    Exported,
+    /// Destroyed.  It can't be imported, and its disks may be reused.
+    Destroyed,
+}
+
+/// Identifier of the current host, as recorded in the labels of the pools that
+/// it imports
+pub fn hostid() -> u32 {
+    unsafe { libc::gethostid() as u32 }
+}
+
+/// The kind of storage backing a `Cluster`, used for tiering
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum StorageClass {
+    /// Low-latency devices, like SSDs.  Preferred for tree nodes and small
+    /// records.
+    Fast,
+    /// High-capacity devices, like HDDs.  Preferred for bulk file data.
+    Slow,
+}
+
+impl Default for StorageClass {
+    fn default() -> Self {
+        StorageClass::Slow
+    }
+}
+
+impl StorageClass {
+    /// Records this small or smaller are stored on `Fast` `Cluster`s, if
+    /// possible.
+    pub const SMALL_RECORD: usize = 8192;
+
+    /// Which storage class would best hold a record of the given size and
+    /// write class?
+    fn preferred(len: usize, class: WriteClass) -> Self {
+        if class == WriteClass::Metadata || len <= StorageClass::SMALL_RECORD {
+            StorageClass::Fast
+        } else {
+            StorageClass::Slow
+        }
+    }
 }
 
 struct Stats {
+    /// The storage class of each `Cluster`
+    classes: Vec<StorageClass>,
+
     /// The queue depth of each `Cluster`, including both commands that have
     /// been sent to the disks, and commands that are pending in `VdevBlock`
     queue_depth: Vec<AtomicU32>,
 
-    /// "Best" number of commands to queue to each VdevRaid
-    optimum_queue_depth: Vec<f64>,
+    /// "Best" number of commands to queue to each VdevRaid, shared with the
+    /// `ClusterProxy`s
+    optimum_queue_depth: Vec<Arc<AtomicU32>>,
 
     /// The total size of each `Cluster`
     size: Vec<LbaT>,
 
+    /// Space in each `Cluster` that is held back from ordinary data writes
+    reserved: Vec<LbaT>,
+
     /// The total amount of allocated space in each `Cluster`, excluding
     /// space that has already been freed but not erased.
     allocated_space: Vec<AtomicU64>,
 }
 
 impl Stats {
+    /// Begin tracking a newly added `Cluster`.
+    ///
+    /// Until its size is known, the new `Cluster` won't be chosen for writes.
+    fn add_cluster(&mut self, optimum_queue_depth: Arc<AtomicU32>,
+                   class: StorageClass)
+    {
+        self.allocated_space.push(AtomicU64::new(0));
+        self.classes.push(class);
+        self.optimum_queue_depth.push(optimum_queue_depth);
+        self.queue_depth.push(AtomicU32::new(0));
+        self.reserved.push(0);
+        self.size.push(0);
+    }
+
     /// How many blocks have been allocated, including blocks that have been
     /// freed but not erased?
     fn allocated(&self) -> LbaT {
@@ -378,21 +697,33 @@ impl Stats {
     /// Choose the best Cluster for the next write
     ///
     /// This decision is subjective, but should strive to:
-    /// 1) Balance capacity utilization amongst all Clusters
-    /// 2) Balance IOPs amongst all Clusters
-    /// 3) Run quickly
-    fn choose_cluster(&self) -> ClusterT {
+    /// 1) Use Clusters of the preferred storage class, until they fill up
+    /// 2) Balance capacity utilization amongst all Clusters
+    /// 3) Balance IOPs amongst all Clusters
+    /// 4) Run quickly
+    fn choose_cluster(&self, preferred: StorageClass) -> ClusterT {
         // This simple implementation weighs both capacity utilization and IOPs,
         // though above 95% utilization it switches to weighing by capacity
         // utilization only.  It's slow because it iterates through all clusters
         // on every write.  A better implementation would perform the full
         // calculation only occasionally, to update coefficients, and perform a
         // quick calculation on each write.
-        (0..self.size.len()).map(|i| {
-            let alloc = self.allocated_space[i].load(Ordering::Relaxed) as f64;
-            let space_util = alloc / (self.size[i] as f64);
+        // Ordinary writes can't use the reserve, so the preferred class is
+        // full once its unreserved space is.
+        let tiered = (0..self.size.len()).any(|i| {
+            self.size[i] > 0 &&
+            self.classes[i] == preferred &&
+            self.usable_util(i) < 0.95
+        });
+        (0..self.size.len())
+        .filter(|&i| self.size[i] > 0)
+        // Fall back to the other class once the preferred one is full
+        .filter(|&i| !tiered || self.classes[i] == preferred)
+        .map(|i| {
+            let space_util = self.space_util(i);
             let qdepth = self.queue_depth[i].load(Ordering::Relaxed) as f64;
-            let queue_fraction = qdepth / self.optimum_queue_depth[i];
+            let oqd = self.optimum_queue_depth[i].load(Ordering::Relaxed);
+            let queue_fraction = qdepth / f64::from(oqd);
             let q_coeff = if 0.95 > space_util {0.95 - space_util} else {0.0};
             let weight = q_coeff * queue_fraction + space_util;
             (i, weight)
@@ -402,23 +733,88 @@ impl Stats {
         .unwrap() as ClusterT
     }
 
+    /// Choose a `Cluster` to retry a write that didn't fit in `cidx`.
+    ///
+    /// It will be the least utilized `Cluster` of the other storage class, if
+    /// there are any.
+    fn choose_fallback(&self, cidx: usize) -> Option<ClusterT> {
+        (0..self.size.len())
+        .filter(|&i| self.size[i] > 0 && self.classes[i] != self.classes[cidx])
+        .map(|i| (i, self.usable_util(i)))
+        .min_by(|&(_, x), &(_, y)| x.partial_cmp(&y).unwrap())
+        .map(|(i, _)| i as ClusterT)
+    }
+
+    /// Space held back for the cleaner and for txg sync
+    fn reserved(&self) -> LbaT {
+        self.reserved.iter().sum()
+    }
+
+    /// Could the other `Cluster`s hold all of the data from this one?
+    fn can_evacuate(&self, cidx: usize) -> bool {
+        let needed = self.allocated_space[cidx].load(Ordering::Relaxed);
+        let others = (0..self.size.len())
+            .filter(|&i| i != cidx && self.size[i] > 0)
+            .collect::<Vec<_>>();
+        let available = others.iter()
+            .map(|&i| {
+                let alloc = self.allocated_space[i].load(Ordering::Relaxed);
+                self.size[i].saturating_sub(self.reserved[i] + alloc)
+            }).sum::<LbaT>();
+        !others.is_empty() && needed <= available
+    }
+
+    /// Record the size and usage of a `Cluster` previously passed to
+    /// `add_cluster`, making it eligible for writes.
+    fn set_cluster_space(&mut self, cidx: usize, size: LbaT, reserved: LbaT,
+                         allocated: LbaT)
+    {
+        self.allocated_space[cidx].store(allocated, Ordering::Relaxed);
+        self.reserved[cidx] = reserved;
+        self.size[cidx] = size;
+    }
+
     /// The approximate usable size of the Pool
     fn size(&self) -> LbaT {
         self.size.iter().sum()
     }
+
+    /// Fraction of a `Cluster`'s total space that is allocated
+    fn space_util(&self, cidx: usize) -> f64 {
+        let alloc = self.allocated_space[cidx].load(Ordering::Relaxed) as f64;
+        alloc / (self.size[cidx] as f64)
+    }
+
+    /// Fraction of a `Cluster`'s unreserved space that is allocated
+    fn usable_util(&self, cidx: usize) -> f64 {
+        let alloc = self.allocated_space[cidx].load(Ordering::Relaxed) as f64;
+        let usable = self.size[cidx].saturating_sub(self.reserved[cidx]);
+        if usable == 0 {
+            1.0
+        } else {
+            alloc / (usable as f64)
+        }
+    }
+
+    /// Stop choosing the given `Cluster` for writes, and stop counting its
+    /// capacity.
+    fn stop_allocating(&mut self, cidx: usize) {
+        self.reserved[cidx] = 0;
+        self.size[cidx] = 0;
+    }
 }
 
 /// Return type of `Pool::write`
 struct Write {
     cpfut: ClusterProxyWrite,
-    stats: Arc<Stats>,
+    stats: Arc<RwLock<Stats>>,
     cidx: usize,
     space: LbaT,
     cluster: ClusterT
 }
 
 impl Write {
-    fn new(cpfut: ClusterProxyWrite, stats: Arc<Stats>,
+    fn new(cpfut: ClusterProxyWrite, stats: Arc<RwLock<Stats>>,
            cidx: usize, space: LbaT, cluster: ClusterT) -> Self
     {
         Write{cpfut, stats, cidx, space, cluster}
@@ -434,15 +830,15 @@ impl Future for Write
         match self.cpfut.poll() {
             Ok(futures::Async::NotReady) => Ok(futures::Async::NotReady),
             Ok(futures::Async::Ready(lba)) => {
-                self.stats.queue_depth[self.cidx]
-                    .fetch_sub(1, Ordering::Relaxed);
-                self.stats.allocated_space[self.cidx]
+                let stats = self.stats.read().unwrap();
+                stats.queue_depth[self.cidx].fetch_sub(1, Ordering::Relaxed);
+                stats.allocated_space[self.cidx]
                     .fetch_add(self.space, Ordering::Relaxed);
                 let pba = PBA::new(self.cluster, lba);
                 Ok(futures::Async::Ready(pba))
             },
             Err(e) => {
-                self.stats.queue_depth[self.cidx]
+                self.stats.read().unwrap().queue_depth[self.cidx]
                     .fetch_sub(1, Ordering::Relaxed);
                 Err(Error::from(e))
             }
@@ -453,28 +849,92 @@ impl Future for Write
 
 /// An BFFFS storage pool
 pub struct Pool {
-    clusters: Vec<ClusterProxy>,
+    /// The `Pool`'s `Cluster`s.  More may be added while the `Pool` is live.
+    /// Removed `Cluster`s leave behind a `None`, so the others keep their
+    /// indices.
+    clusters: RwLock<Vec<Option<ClusterProxy>>>,
+
+    /// On-disk format features enabled for this pool
+    features: Mutex<Features>,
+
+    /// I/O rate limits for each of the pool's disks
+    limits: Mutex<IoLimits>,
+
+    /// `hostid` of this system, recorded in every label
+    hostid: u32,
+
+    /// When this `Pool` was imported or created, in seconds since the epoch
+    import_time: u64,
 
     /// Human-readable pool name.  Must be unique on any one system.
     name: String,
 
-    stats: Arc<Stats>,
+    /// Was the `Pool` imported read-only?  If so, it must not be written.
+    readonly: bool,
+
+    /// Index of the `Cluster` being removed, if any
+    removing: Mutex<Option<ClusterT>>,
+
+    /// I/O scheduling policy for all of the pool's disks
+    scheduler: Mutex<SchedulerPolicy>,
+
+    /// State to record in the next label
+    state: Mutex<PoolState>,
+
+    stats: Arc<RwLock<Stats>>,
 
     uuid: Uuid,
 }
 
 #[cfg_attr(test, automock)]
 impl Pool {
+    /// Add a newly created `Cluster` to a live `Pool`.
+    ///
+    /// The new `Cluster` will be used for writes as soon as the returned
+    /// future completes, but it won't become a permanent part of the `Pool`
+    /// until the next label is written.
+    pub fn add_cluster(&self, cluster: ClusterProxy)
+        -> impl Future<Item=(), Error=Error> + Send
+    {
+        cluster.set_limits(self.limits());
+        cluster.set_scheduler(self.scheduler());
+        let size_fut = cluster.size();
+        let reserved_fut = cluster.reserved();
+        let allocated_fut = cluster.allocated();
+        let mut clusters = self.clusters.write().unwrap();
+        let cidx = clusters.len();
+        self.stats.write().unwrap()
+            .add_cluster(cluster.optimum_queue_depth.clone(),
+                         cluster.storage_class);
+        clusters.push(Some(cluster));
+        let stats = self.stats.clone();
+        size_fut.join3(reserved_fut, allocated_fut)
+        .map(move |(size, reserved, allocated)| {
+            stats.write().unwrap()
+                .set_cluster_space(cidx, size, reserved, allocated);
+        })
+    }
+
     /// How many blocks have been allocated, including blocks that have been
     /// freed but not erased?
     pub fn allocated(&self) -> LbaT {
-        self.stats.allocated()
+        self.stats.read().unwrap().allocated()
     }
 
     /// Assert that the given zone was clean as of the given transaction
     #[cfg(debug_assertions)]
     pub fn assert_clean_zone(&self, cluster: ClusterT, zone: ZoneT, txg: TxgT) {
-        self.clusters[cluster as usize].assert_clean_zone(zone, txg)
+        if let Some(c) = &self.clusters.read().unwrap()[cluster as usize] {
+            c.assert_clean_zone(zone, txg)
+        }
+    }
+
+    /// Return the amount of freed space, in LBAs, that can't be reused until
+    /// the pool checkpoint is discarded.
+    pub fn checkpointed(&self) -> LbaT {
+        self.clusters.read().unwrap().iter().flatten()
+            .map(|cp| cp.status.checkpointed.load(Ordering::Relaxed))
+            .sum()
     }
 
     /// Create a new `Cluster` from unused files or devices.
@@ -497,26 +957,43 @@ impl Pool {
     ///                         disks may fail before the array becomes
     ///                         inoperable.
     /// * `paths`:              Slice of pathnames of files and/or devices
+    ///
+    /// Fails with `EEXIST` if any of the devices already has a label, and
+    /// with `EINVAL` if they can't be arranged as requested.
     #[cfg(not(test))]
     pub fn create_cluster<P: AsRef<Path> + Sync>(chunksize: Option<NonZeroU64>,
                                disks_per_stripe: i16,
                                lbas_per_zone: Option<NonZeroU64>,
                                redundancy: i16,
                                paths: &[P])
-        -> impl Future<Item=ClusterProxy, Error=()>
+        -> impl Future<Item=ClusterProxy, Error=Error>
     {
-        let (tx, rx) = oneshot::channel();
         // DefaultExecutor needs 'static futures; we must copy the Paths
         let owned_paths = paths.iter()
             .map(|p| p.as_ref().to_owned())
             .collect::<Vec<PathBuf>>();
-        DefaultExecutor::current().spawn(Box::new(future::lazy(move || {
-            let c = Cluster::create(chunksize, disks_per_stripe,
-                    lbas_per_zone, redundancy, owned_paths);
-            tx.send(ClusterProxy::new(c)).unwrap();
-            Ok(())
-        }))).unwrap();
-        rx.map_err(|_| panic!("Closed Runtime while creating Cluster?"))
+        // Never format a device that may belong to some pool, even an exported
+        // one.  Devices that can't be opened will fail below.
+        let probes = owned_paths.iter()
+            .map(|p| {
+                VdevFile::open(p.clone())
+                .then(|r| match r {
+                    Ok(_) => Err(Error::EEXIST),
+                    Err(_) => Ok(())
+                })
+            }).collect::<Vec<_>>();
+        future::join_all(probes)
+        .and_then(move |_| {
+            let (tx, rx) = oneshot::channel();
+            DefaultExecutor::current().spawn(Box::new(future::lazy(move || {
+                let r = Cluster::create(chunksize, disks_per_stripe,
+                        lbas_per_zone, redundancy, owned_paths)
+                    .map(ClusterProxy::new);
+                tx.send(r).unwrap();
+                Ok(())
+            }))).unwrap();
+            rx.then(|r| r.expect("Closed Runtime while creating Cluster?"))
+        })
     }
 
     /// Create a new `Pool` from some freshly created `Cluster`s.
@@ -525,13 +1002,61 @@ impl Pool {
     pub fn create(name: String, clusters: Vec<ClusterProxy>)
         -> impl Future<Item=Self, Error=Error>
     {
+        let clusters = clusters.into_iter().map(Some).collect();
         Pool::new(name, Uuid::new_v4(), clusters)
     }
 
-    pub fn flush(&self, idx: u32) -> impl Future<Item=(), Error=Error> + Send {
+    /// The on-disk format features enabled for this pool
+    pub fn features(&self) -> Features {
+        *self.features.lock().unwrap()
+    }
+
+    /// Finish all of the given `Cluster`'s open zones, so they can be
+    /// cleaned.
+    pub fn finish_zones(&self, cluster: ClusterT, txg: TxgT)
+        -> impl Future<Item=(), Error=Error> + Send
+    {
+        match &self.clusters.read().unwrap()[cluster as usize] {
+            Some(c) => future::Either::A(c.finish_zones(txg)),
+            None => future::Either::B(future::err(Error::ENXIO))
+        }
+    }
+
+    /// Drop the `Cluster` that is being removed from the `Pool`.
+    ///
+    /// All of its data must already have been relocated.  The change won't be
+    /// persisted until the next label is written.  Fails with `EBUSY`, leaving
+    /// the `Cluster` in place, if it still holds any data besides its own
+    /// spacemap log.
+    pub fn finish_removal(&self, cluster: ClusterT) -> Result<(), Error> {
+        let mut removing = self.removing.lock().unwrap();
+        assert_eq!(*removing, Some(cluster),
+                   "Cluster {} isn't being removed", cluster);
+        let cidx = cluster as usize;
+        let mut clusters = self.clusters.write().unwrap();
+        let live = clusters[cidx].as_ref().unwrap()
+            .status.live_data.load(Ordering::Relaxed);
+        if live > 0 {
+            return Err(Error::EBUSY);
+        }
+        let cp = clusters[cidx].take().unwrap();
+        // No need to wait for it.  The removed Cluster's disks aren't part of
+        // the pool anymore.
+        drop(cp.shutdown());
+        // Whatever's left is the Cluster's own spacemap, which went away with
+        // it
+        self.stats.read().unwrap().allocated_space[cidx]
+            .store(0, Ordering::Relaxed);
+        *removing = None;
+        Ok(())
+    }
+
+    pub fn flush(&self, txg: TxgT)
+        -> impl Future<Item=(), Error=Error> + Send
+    {
         future::join_all(
-            self.clusters.iter()
-            .map(|cp| cp.flush(idx))
+            self.clusters.read().unwrap().iter().flatten()
+            .map(|cp| cp.flush(txg))
             .collect::<Vec<_>>()
         ).map(drop)
     }
@@ -548,44 +1073,82 @@ impl Pool {
         -> impl Future<Item=(), Error=Error> + Send
     {
         let idx = pba.cluster as usize;
-        self.stats.allocated_space[idx].fetch_sub(length, Ordering::Relaxed);
-        self.clusters[pba.cluster as usize].free(pba.lba, length)
+        match &self.clusters.read().unwrap()[idx] {
+            Some(c) => {
+                self.stats.read().unwrap().allocated_space[idx]
+                    .fetch_sub(length, Ordering::Relaxed);
+                future::Either::A(c.free(pba.lba, length))
+            },
+            // The storage went away along with the Cluster
+            None => future::Either::B(future::ok(()))
+        }
     }
 
     /// Construct a new `Pool` from some already constructed
     /// [`Cluster`](struct.Cluster.html)s.
     ///
     /// Must be called from within the context of a Tokio Runtime.
+    ///
+    /// Removed `Cluster`s are represented by `None`.
     #[allow(clippy::new_ret_no_self)]
-    fn new(name: String, uuid: Uuid, clusters: Vec<ClusterProxy>)
+    fn new(name: String, uuid: Uuid, clusters: Vec<Option<ClusterProxy>>)
         -> impl Future<Item=Self, Error=Error>
     {
+        // Removed Clusters have no space at all
         let size_fut = future::join_all(clusters.iter()
-            .map(ClusterProxy::size)
-            .collect::<Vec<_>>()
+            .map(|cluster| match cluster {
+                Some(c) => future::Either::A(c.size()),
+                None => future::Either::B(future::ok(0))
+            }).collect::<Vec<_>>()
         );
-        let allocated_fut = future::join_all(clusters.iter()
-            .map(|cluster| cluster.allocated()
-                .map(AtomicU64::new)
-            ).collect::<Vec<_>>()
+        let reserved_fut = future::join_all(clusters.iter()
+            .map(|cluster| match cluster {
+                Some(c) => future::Either::A(c.reserved()),
+                None => future::Either::B(future::ok(0))
+            }).collect::<Vec<_>>()
         );
-        let oqd_fut = future::join_all(clusters.iter()
-            .map(|cluster| cluster.optimum_queue_depth()
-                .map(f64::from)
+        let allocated_fut = future::join_all(clusters.iter()
+            .map(|cluster| match cluster {
+                Some(c) => future::Either::A(c.allocated()),
+                None => future::Either::B(future::ok(0))
+            }.map(AtomicU64::new)
             ).collect::<Vec<_>>()
         );
+        let optimum_queue_depth = clusters.iter()
+            .map(|cluster| match cluster {
+                Some(c) => c.optimum_queue_depth.clone(),
+                None => Arc::new(AtomicU32::new(1))
+            }).collect();
         let queue_depth: Vec<_> = clusters.iter()
             .map(|_| AtomicU32::new(0))
             .collect();
-        size_fut.join3(allocated_fut, oqd_fut)
-        .map(move |(size, allocated_space, optimum_queue_depth)| {
-            let stats = Arc::new(Stats{
+        let classes = clusters.iter()
+            .map(|cluster| match cluster {
+                Some(c) => c.storage_class,
+                None => StorageClass::default()
+            }).collect();
+        size_fut.join3(reserved_fut, allocated_fut)
+        .map(move |(size, reserved, allocated_space)| {
+            let stats = Arc::new(RwLock::new(Stats{
                 allocated_space,
+                classes,
                 optimum_queue_depth,
                 queue_depth,
+                reserved,
                 size
-            });
-            Pool{name, clusters, stats, uuid}
+            }));
+            let clusters = RwLock::new(clusters);
+            let features = Mutex::new(Features::SUPPORTED);
+            let limits = Mutex::new(IoLimits::default());
+            let removing = Mutex::new(None);
+            let scheduler = Mutex::new(SchedulerPolicy::default());
+            let state = Mutex::new(PoolState::Active);
+            let import_time = SystemTime::now().duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            Pool{name, clusters, features, hostid: hostid(), import_time,
+                 limits, readonly: false, removing, scheduler, state, stats,
+                 uuid}
         })
     }
 
@@ -605,29 +1168,43 @@ impl Pool {
         -> impl Future<Item=(Option<ClosedZone>, Option<(ClusterT, ZoneT)>),
                        Error=Error> + Send
     {
-        let nclusters = self.clusters.len() as ClusterT;
-        self.clusters[clust as usize].find_closed_zone(zid)
-            .map(move |r| {
-                if let Some(cclz) = r {
-                    // convert cluster::ClosedZone to pool::ClosedZone
-                    let pclz = ClosedZone {
-                        freed_blocks: cclz.freed_blocks,
-                        pba: PBA::new(clust, cclz.start),
-                        total_blocks: cclz.total_blocks,
-                        txgs: cclz.txgs,
-                        zid: cclz.zid};
-                    (Some(pclz), Some((clust, cclz.zid + 1)))
+        let clusters = self.clusters.read().unwrap();
+        let nclusters = clusters.len() as ClusterT;
+        let fut = match &clusters[clust as usize] {
+            Some(c) => future::Either::A(c.find_closed_zone(zid)),
+            None => future::Either::B(future::ok(None))
+        };
+        fut.map(move |r| {
+            if let Some(cclz) = r {
+                // convert cluster::ClosedZone to pool::ClosedZone
+                let pclz = ClosedZone {
+                    freed_blocks: cclz.freed_blocks,
+                    pba: PBA::new(clust, cclz.start),
+                    total_blocks: cclz.total_blocks,
+                    txgs: cclz.txgs,
+                    zid: cclz.zid};
+                (Some(pclz), Some((clust, cclz.zid + 1)))
+            } else {
+                // No more closed zones on this cluster
+                if clust < nclusters - 1 {
+                    // Try the next cluster
+                    (None, Some((clust + 1, 0)))
                 } else {
-                    // No more closed zones on this cluster
-                    if clust < nclusters - 1 {
-                        // Try the next cluster
-                        (None, Some((clust + 1, 0)))
-                    } else {
-                        // No more clusters
-                        (None, None)
-                    }
+                    // No more clusters
+                    (None, None)
                 }
-            })
+            }
+        })
+    }
+
+    /// Snapshot the I/O statistics of every `Cluster` and disk in the pool
+    pub fn iostats(&self) -> impl Future<Item=VdevStats, Error=Error> + Send {
+        let uuid = self.uuid;
+        future::join_all(
+            self.clusters.read().unwrap().iter().flatten()
+            .map(ClusterProxy::iostats)
+            .collect::<Vec<_>>()
+        ).map(move |children| VdevStats::aggregate(uuid, children))
     }
 
     /// Return the `Pool`'s name.
@@ -645,13 +1222,20 @@ impl Pool {
     ///                 it will not be verified.
     /// * `combined`:   An array of pairs of `ClusterProxy`s and their
     ///                 associated `LabelReader`.  The labels of each will be verified.
-    pub fn open(uuid: Option<Uuid>, combined: Vec<(ClusterProxy, LabelReader)>)
+    /// * `readonly`:   Open the `Pool` read-only.  All writes will fail with
+    ///                 `EROFS`.
+    ///
+    /// Fails with `ENOTSUP` if the pool uses incompatible features unknown to
+    /// this version of BFFFS, or `EROFS` if it uses unknown read-only
+    /// compatible features and `readonly` is false.
+    pub fn open(uuid: Option<Uuid>, combined: Vec<(ClusterProxy, LabelReader)>,
+                readonly: bool)
         -> impl Future<Item = (Self, LabelReader), Error = Error>
     {
         let mut label_pair = None;
         let mut all_clusters = combined.into_iter()
             .map(|(cluster_proxy, mut label_reader)| {
-            let label: Label = label_reader.deserialize().unwrap();
+            let label = Label::read(&mut label_reader).unwrap();
             if let Some(u) = uuid {
                 assert_eq!(u, label.uuid, "Opening cluster from wrong pool");
             }
@@ -661,46 +1245,199 @@ impl Pool {
             (cluster_proxy.uuid(), cluster_proxy)
         }).collect::<BTreeMap<Uuid, ClusterProxy>>();
         let (label, label_reader) = label_pair.unwrap();
-        assert_eq!(all_clusters.len(), label.children.len(),
-            "Missing clusters");
-        let children = label.children.iter().map(|uuid| {
-            all_clusters.remove(&uuid).unwrap()
+        let nlive = label.children.iter().filter(|u| !u.is_nil()).count();
+        assert_eq!(all_clusters.len(), nlive, "Missing clusters");
+        let children = label.children.iter().zip(label.classes.iter())
+        .map(|(uuid, &class)| {
+            if uuid.is_nil() {
+                None
+            } else {
+                let mut cluster = all_clusters.remove(&uuid).unwrap();
+                cluster.set_storage_class(class);
+                Some(cluster)
+            }
         }).collect::<Vec<_>>();
-        Pool::new(label.name, label.uuid, children)
-            .map(|pool| (pool, label_reader))
+        let features = label_reader.features();
+        let scheduler = label.scheduler;
+        let limits = label.limits;
+        let removing = label.removing.map(|uuid| {
+            label.children.iter().position(|u| *u == uuid)
+                .expect("Removing a Cluster that isn't in the Pool")
+        });
+        let check = if label.classes.len() != label.children.len() {
+            // Every child must have a storage class
+            Err(Error::EINVAL)
+        } else {
+            match features.check() {
+                // Unknown read-only compatible features are fine if we won't
+                // write
+                Err(Error::EROFS) if readonly => Ok(()),
+                r => r
+            }
+        };
+        check.into_future()
+        .and_then(move |_| Pool::new(label.name, label.uuid, children))
+        .map(move |mut pool| {
+            pool.features = Mutex::new(features);
+            pool.readonly = readonly;
+            pool.set_scheduler(scheduler);
+            pool.set_limits(limits);
+            if let Some(cidx) = removing {
+                // Resume an interrupted removal
+                pool.stats.write().unwrap().stop_allocating(cidx);
+                pool.removing = Mutex::new(Some(cidx as ClusterT));
+            }
+            (pool, label_reader)
+        })
     }
 
     /// Asynchronously read from the pool
-    pub fn read(&self, buf: IoVecMut, pba: PBA)
+    ///
+    /// `priority` is a hint to the disks' I/O schedulers.
+    pub fn read(&self, buf: IoVecMut, pba: PBA, priority: PriorityClass)
         -> impl Future<Item=(), Error=Error> + Send
     {
         let cidx = pba.cluster as usize;
-        self.stats.queue_depth[cidx].fetch_add(1, Ordering::Relaxed);
+        self.stats.read().unwrap().queue_depth[cidx]
+            .fetch_add(1, Ordering::Relaxed);
         let stats2 = self.stats.clone();
-        self.clusters[pba.cluster as usize].read(buf, pba.lba)
-            .then(move |r| {
-                stats2.queue_depth[cidx].fetch_sub(1, Ordering::Relaxed);
-                r
-            })
+        let fut = match &self.clusters.read().unwrap()[cidx] {
+            Some(c) => future::Either::A(c.read(buf, pba.lba, priority)),
+            None => future::Either::B(future::err(Error::ENXIO))
+        };
+        fut.then(move |r| {
+            stats2.read().unwrap().queue_depth[cidx]
+                .fetch_sub(1, Ordering::Relaxed);
+            r
+        })
     }
 
-    /// Shutdown all background tasks.
-    pub fn shutdown(&self) {
-        for c in self.clusters.iter() {
-            c.shutdown();
+    /// The I/O rate limits applied to each of the pool's disks
+    pub fn limits(&self) -> IoLimits {
+        *self.limits.lock().unwrap()
+    }
+
+    /// Was this `Pool` imported read-only?
+    pub fn readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// The I/O scheduling policy used by all of the pool's disks
+    pub fn scheduler(&self) -> SchedulerPolicy {
+        *self.scheduler.lock().unwrap()
+    }
+
+    /// Change the I/O rate limits of each of the pool's disks.
+    ///
+    /// The new limits take effect immediately, but won't be persisted until
+    /// the next label is written.
+    pub fn set_limits(&self, limits: IoLimits) {
+        *self.limits.lock().unwrap() = limits;
+        for c in self.clusters.read().unwrap().iter().flatten() {
+            c.set_limits(limits);
+        }
+    }
+
+    /// Change the I/O scheduling policy of all of the pool's disks.
+    ///
+    /// The new policy takes effect immediately, but won't be persisted until
+    /// the next label is written.
+    pub fn set_scheduler(&self, policy: SchedulerPolicy) {
+        *self.scheduler.lock().unwrap() = policy;
+        for c in self.clusters.read().unwrap().iter().flatten() {
+            c.set_scheduler(policy);
         }
     }
 
+    /// Change the state that will be recorded in the pool's labels.
+    ///
+    /// The new state won't be persisted until the next label is written.
+    pub fn set_state(&self, state: PoolState) {
+        *self.state.lock().unwrap() = state;
+    }
+
+    /// Shutdown all background tasks.
+    ///
+    /// The returned future completes once every `Cluster` has closed its disks
+    /// and released their locks.
+    pub fn shutdown(&self) -> impl Future<Item=(), Error=Error> + Send {
+        let futs = self.clusters.read().unwrap().iter().flatten()
+            .map(ClusterProxy::shutdown)
+            .collect::<Vec<_>>();
+        future::join_all(futs).map(drop)
+    }
+
+    /// Return the amount of space, in LBAs, that is held back from ordinary
+    /// data writes.
+    pub fn reserved(&self) -> LbaT {
+        self.stats.read().unwrap().reserved()
+    }
+
+    /// Return the UUID and remaining allocated space of the `Cluster` that is
+    /// being removed, if any.
+    pub fn removing(&self) -> Option<(Uuid, LbaT)> {
+        self.removing.lock().unwrap().map(|cluster| {
+            let cidx = cluster as usize;
+            // finish_removal empties the slot and clears self.removing
+            // together, under the same lock.
+            let uuid = self.clusters.read().unwrap()[cidx].as_ref()
+                .expect("The Cluster being removed is already gone")
+                .uuid();
+            let allocated = self.stats.read().unwrap().allocated_space[cidx]
+                .load(Ordering::Relaxed);
+            (uuid, allocated)
+        })
+    }
+
     /// Return approximately the Pool's usable storage space in LBAs.
     pub fn size(&self) -> LbaT {
-        self.stats.size()
+        self.stats.read().unwrap().size()
+    }
+
+    /// Stop allocating from the `Cluster` with the given UUID, in preparation
+    /// for removing it from the `Pool`.
+    ///
+    /// Returns the `Cluster`'s index.  It's harmless to call this again for
+    /// the `Cluster` that is already being removed.  The change won't be
+    /// persisted until the next label is written.
+    ///
+    /// Fails with `ENOENT` if there is no such `Cluster`, `EBUSY` if a
+    /// different `Cluster` is already being removed or the pool has a
+    /// checkpoint, or `ENOSPC` if the remaining `Cluster`s couldn't hold its
+    /// data.  Zones that hold checkpointed data can't be cleaned, so the
+    /// checkpoint must be discarded first.
+    pub fn start_removal(&self, uuid: Uuid) -> Result<ClusterT, Error> {
+        let clusters = self.clusters.read().unwrap();
+        let cidx = clusters.iter()
+            .position(|c| c.as_ref().map(ClusterProxy::uuid) == Some(uuid))
+            .ok_or(Error::ENOENT)?;
+        let checkpoint = clusters.iter().flatten()
+            .any(|cp| cp.status.checkpoint.load(Ordering::Relaxed));
+        drop(clusters);
+        let cluster = cidx as ClusterT;
+        let mut removing = self.removing.lock().unwrap();
+        match *removing {
+            Some(c) if c == cluster => return Ok(cluster),
+            Some(_) => return Err(Error::EBUSY),
+            None => ()
+        }
+        if checkpoint {
+            return Err(Error::EBUSY);
+        }
+        let mut stats = self.stats.write().unwrap();
+        if !stats.can_evacuate(cidx) {
+            return Err(Error::ENOSPC);
+        }
+        stats.stop_allocating(cidx);
+        *removing = Some(cluster);
+        Ok(cluster)
     }
 
     /// Sync the `Pool`, ensuring that all data written so far reaches stable
     /// storage.
     pub fn sync_all(&self) -> impl Future<Item=(), Error=Error> + Send {
         future::join_all(
-            self.clusters.iter()
+            self.clusters.read().unwrap().iter().flatten()
             .map(ClusterProxy::sync_all)
             .collect::<Vec<_>>()
         ).map(drop)
@@ -713,37 +1450,129 @@ impl Pool {
 
     /// Write a buffer to the pool
     ///
+    /// `class` describes the kind of record being written, so it can be kept
+    /// apart from records of other kinds.  `priority` is a hint to the disks'
+    /// I/O throttles.
+    ///
+    /// # Returns
+    ///
+    /// The `PBA` where the data was written
+    pub fn write(&self, buf: IoVec, class: WriteClass, txg: TxgT,
+                 priority: PriorityClass)
+        -> impl Future<Item = PBA, Error=Error> + Send
+    {
+        let preferred = StorageClass::preferred(buf.len(), class);
+        self.write_to(buf, class, preferred, txg, priority)
+    }
+
+    /// Write a record that the cleaner is moving out of cluster `from`.
+    ///
+    /// The record stays on the same tier as it was before.  The cleaner can't
+    /// tell a tree node from file data, but its original placement already
+    /// took that into account.  Relocation is background work, so it's
+    /// always written with `Background` priority.
+    ///
     /// # Returns
     ///
     /// The `PBA` where the data was written
-    pub fn write(&self, buf: IoVec, txg: TxgT)
+    pub fn relocate(&self, buf: IoVec, from: ClusterT, txg: TxgT)
+        -> impl Future<Item = PBA, Error=Error> + Send
+    {
+        let preferred = self.stats.read().unwrap().classes[from as usize];
+        self.write_to(buf, WriteClass::Relocation, preferred, txg,
+                      PriorityClass::Background)
+    }
+
+    fn write_to(&self, buf: IoVec, class: WriteClass, preferred: StorageClass,
+                txg: TxgT, priority: PriorityClass)
         -> impl Future<Item = PBA, Error=Error> + Send
     {
-        let cluster = self.stats.choose_cluster();
+        if self.readonly {
+            return future::Either::A(future::err(Error::EROFS));
+        }
+        let (cluster, fallback) = {
+            let stats = self.stats.read().unwrap();
+            let cluster = stats.choose_cluster(preferred);
+            (cluster, stats.choose_fallback(cluster as usize))
+        };
         let cidx = cluster as usize;
-        self.stats.queue_depth[cidx].fetch_add(1, Ordering::Relaxed);
         let space = div_roundup(buf.len(), BYTES_PER_LBA) as LbaT;
+        let clusters = self.clusters.read().unwrap();
+        // If the chosen Cluster turns out to be full, retry on the other tier.
+        // The retry needs its own reference to the buffer, and a proxy that
+        // doesn't borrow self.
+        let retry = fallback.and_then(|fb| {
+            clusters[fb as usize].as_ref()
+                .map(|cp| (fb, cp.clone(), buf.clone()))
+        });
+        let cpfut = match &clusters[cidx] {
+            Some(cp) => cp.write(buf, class, txg, priority),
+            // choose_cluster shouldn't pick a removed Cluster, because its
+            // size is zero.
+            None => return future::Either::A(future::err(Error::ENXIO))
+        };
+        self.stats.read().unwrap().queue_depth[cidx]
+            .fetch_add(1, Ordering::Relaxed);
         let stats2 = self.stats.clone();
-        let cpfut = self.clusters[cidx].write(buf, txg);
-        Write::new(cpfut, stats2, cidx, space, cluster)
+        let fut = Write::new(cpfut, stats2.clone(), cidx, space, cluster)
+        .or_else(move |e| {
+            match (e, retry) {
+                (Error::ENOSPC, Some((fb, cp, buf))) => {
+                    let fbidx = fb as usize;
+                    let cpfut = cp.write(buf, class, txg, priority);
+                    stats2.read().unwrap().queue_depth[fbidx]
+                        .fetch_add(1, Ordering::Relaxed);
+                    future::Either::A(
+                        Write::new(cpfut, stats2, fbidx, space, fb))
+                },
+                (e, _) => future::Either::B(future::err(e))
+            }
+        });
+        future::Either::B(fut)
+    }
+
+    /// Enable every on-disk feature supported by this version of BFFFS.
+    ///
+    /// The new features won't be recorded until the next label is written.
+    /// Returns `true` if any new features were enabled.
+    pub fn upgrade(&self) -> bool {
+        let mut guard = self.features.lock().unwrap();
+        let old = *guard;
+        *guard = old.union(Features::SUPPORTED);
+        *guard != old
     }
 
     /// Asynchronously write this `Pool`'s label to all component devices
     pub fn write_label(&self, mut labeller: LabelWriter)
         -> impl Future<Item=(), Error=Error> + Send
     {
-        let cluster_uuids = self.clusters.iter().map(ClusterProxy::uuid)
+        if self.readonly {
+            return future::Either::A(future::err(Error::EROFS));
+        }
+        labeller.set_features(self.features());
+        let removing = self.removing().map(|(uuid, _)| uuid);
+        let classes = self.stats.read().unwrap().classes.clone();
+        let clusters = self.clusters.read().unwrap();
+        let cluster_uuids = clusters.iter()
+            .map(|c| c.as_ref().map_or_else(Uuid::nil, ClusterProxy::uuid))
             .collect::<Vec<_>>();
         let label = Label {
             name: self.name.clone(),
             uuid: self.uuid,
             children: cluster_uuids,
+            scheduler: self.scheduler(),
+            limits: self.limits(),
+            removing,
+            classes,
+            state: *self.state.lock().unwrap(),
+            hostid: self.hostid,
+            import_time: self.import_time
         };
-        labeller.serialize(&label).unwrap();
-        let futs = self.clusters.iter().map(|cluster| {
+        label.write(&mut labeller).unwrap();
+        let futs = clusters.iter().flatten().map(|cluster| {
             cluster.write_label(labeller.clone())
         }).collect::<Vec<_>>();
-        future::join_all(futs).map(drop)
+        future::Either::B(future::join_all(futs).map(drop))
     }
 }
 
@@ -753,36 +1582,170 @@ mod t {
 
 mod label {
     use super::super::*;
+    use divbuf::DivBufShared;
 
     // pet kcov
     #[test]
     fn debug() {
         let label = Label{name: "Foo".to_owned(),
             uuid: Uuid::new_v4(),
-            children: vec![]
+            children: vec![],
+            scheduler: SchedulerPolicy::default(),
+            limits: IoLimits::default(),
+            removing: None,
+            classes: vec![StorageClass::Slow],
+            state: PoolState::Active,
+            hostid: 0,
+            import_time: 0
         };
         format!("{:?}", label);
     }
-}
 
-mod pool {
-    use super::super::*;
-    use divbuf::DivBufShared;
-    use futures::{IntoFuture, future};
-    use mockall::predicate::*;
-    use pretty_assertions::assert_eq;
-    use tokio::runtime::current_thread;
+    fn roundtrip(label: &Label, features: Features) -> Label {
+        let mut lw = LabelWriter::new(0, TxgT::from(0));
+        lw.set_features(features);
+        label.write(&mut lw).unwrap();
+        let v = lw.into_sglist().unwrap().iter()
+            .fold(Vec::new(), |mut acc, db| {
+                acc.extend_from_slice(&db[..]);
+                acc
+            });
+        let mut lr = LabelReader::from_dbs(DivBufShared::from(v)).unwrap();
+        Label::read(&mut lr).unwrap()
+    }
 
-    // pet kcov
+    fn host_label() -> Label {
+        Label{name: "Foo".to_owned(),
+            uuid: Uuid::new_v4(),
+            children: vec![],
+            scheduler: SchedulerPolicy::default(),
+            limits: IoLimits::default(),
+            removing: None,
+            classes: vec![],
+            state: PoolState::Active,
+            hostid: 42,
+            import_time: 12345
+        }
+    }
+
+    // Pools without the MULTIHOST feature don't record their host
     #[test]
-    fn debug() {
-        let mut c = Cluster::default();
+    fn read_write_legacy() {
+        let features = Features{
+            incompat: Features::SUPPORTED.incompat & !Features::MULTIHOST,
+            .. Features::default()
+        };
+        let label = roundtrip(&host_label(), features);
+        assert_eq!(label.hostid, 0);
+        assert_eq!(label.import_time, 0);
+    }
+
+    #[test]
+    fn read_write_multihost() {
+        let label = roundtrip(&host_label(), Features::SUPPORTED);
+        assert_eq!(label.hostid, 42);
+        assert_eq!(label.import_time, 12345);
+    }
+
+    // Pools without the BG_WRITE_LIMITS feature don't record background write
+    // limits, but their other limits are unaffected
+    #[test]
+    fn read_write_bg_write_limits() {
+        let mut label = host_label();
+        label.limits.read_bw = Some(1 << 20);
+        label.limits.bg_write_iops = Some(100);
+        let features = Features{
+            incompat: Features::SUPPORTED.incompat & !Features::BG_WRITE_LIMITS,
+            .. Features::default()
+        };
+        let legacy = roundtrip(&label, features);
+        assert_eq!(legacy.limits.read_bw, Some(1 << 20));
+        assert_eq!(legacy.limits.bg_write_iops, None);
+        assert_eq!(legacy.hostid, 42);
+        let label = roundtrip(&label, Features::SUPPORTED);
+        assert_eq!(label.limits.read_bw, Some(1 << 20));
+        assert_eq!(label.limits.bg_write_iops, Some(100));
+        assert_eq!(label.hostid, 42);
+    }
+}
+
+mod pool {
+    use super::super::*;
+    use divbuf::DivBufShared;
+    use futures::{IntoFuture, future};
+    use mockall::predicate::*;
+    use pretty_assertions::assert_eq;
+    use tokio::runtime::current_thread;
+
+    // A newly added cluster should immediately be used for writes, and it
+    // should inherit the pool's I/O settings
+    #[test]
+    fn add_cluster() {
+        let mut c0 = Cluster::default();
+        c0.expect_allocated().return_const(900u64);
+        c0.expect_optimum_queue_depth().return_const(10u32);
+        c0.expect_checkpointed().return_const(0u64);
+        c0.expect_checkpoint().return_const(None);
+        c0.expect_live_data().return_const(0u64);
+        c0.expect_size().return_const(1000u64);
+        c0.expect_reserved().return_const(0u64);
+        c0.expect_uuid().return_const(Uuid::new_v4());
+        let mut c1 = Cluster::default();
+        c1.expect_allocated().return_const(0u64);
+        c1.expect_optimum_queue_depth().return_const(10u32);
+        c1.expect_checkpointed().return_const(0u64);
+        c1.expect_checkpoint().return_const(None);
+        c1.expect_live_data().return_const(0u64);
+        c1.expect_size().return_const(1000u64);
+        c1.expect_reserved().return_const(0u64);
+        c1.expect_uuid().return_const(Uuid::new_v4());
+        c1.expect_set_limits()
+            .with(eq(IoLimits::default()))
+            .once()
+            .return_const(());
+        c1.expect_set_scheduler()
+            .with(eq(SchedulerPolicy::default()))
+            .once()
+            .return_const(());
+        c1.expect_write()
+            .once()
+            .return_once(|_, _, _, _|
+                Ok((0, Box::new(future::ok::<(), Error>(()))))
+            );
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let pool = rt.block_on(future::lazy(|| {
+            Pool::new("foo".to_string(), Uuid::new_v4(),
+                      vec![Some(ClusterProxy::new(c0))])
+        })).unwrap();
+        rt.block_on(future::lazy(|| {
+            pool.add_cluster(ClusterProxy::new(c1))
+        })).unwrap();
+        assert_eq!(pool.allocated(), 900);
+        assert_eq!(pool.size(), 2000);
+
+        let dbs = DivBufShared::from(vec![0u8; 4096]);
+        let db0 = dbs.try_const().unwrap();
+        let result = rt.block_on(pool.write(db0, WriteClass::Data,
+                                            TxgT::from(42),
+                                            PriorityClass::Normal));
+        assert_eq!(result.unwrap(), PBA::new(1, 0));
+    }
+
+    // pet kcov
+    #[test]
+    fn debug() {
+        let mut c = Cluster::default();
+        c.expect_optimum_queue_depth().return_const(10u32);
+        c.expect_checkpointed().return_const(0u64);
+        c.expect_checkpoint().return_const(None);
+        c.expect_live_data().return_const(0u64);
         c.expect_uuid().return_const(Uuid::new_v4());
         let mut rt = current_thread::Runtime::new().unwrap();
         rt.block_on(future::lazy(|| {
             let cluster_proxy = ClusterProxy::new(c);
             format!("{:?}", cluster_proxy);
-            future::ok::<(), ()>(())
+            future::ok(())
         })).unwrap();
     }
 
@@ -792,6 +1755,9 @@ mod pool {
             let mut c = Cluster::default();
             c.expect_allocated().return_const(0u64);
             c.expect_optimum_queue_depth().return_const(10u32);
+            c.expect_checkpointed().return_const(0u64);
+            c.expect_checkpoint().return_const(None);
+            c.expect_live_data().return_const(0u64);
             c.expect_find_closed_zone()
                 .with(eq(0))
                 .return_const(Some(cluster::ClosedZone {
@@ -814,14 +1780,15 @@ mod pool {
                 .with(eq(4))
                 .return_const(None);
             c.expect_size().return_const(32_768_000u64);
+            c.expect_reserved().return_const(0u64);
             c.expect_uuid().return_const(Uuid::new_v4());
             c
         };
         let mut rt = current_thread::Runtime::new().unwrap();
         let pool = rt.block_on(future::lazy(|| {
             let clusters = vec![
-                ClusterProxy::new(cluster()),
-                ClusterProxy::new(cluster())
+                Some(ClusterProxy::new(cluster())),
+                Some(ClusterProxy::new(cluster()))
             ];
             Pool::new("foo".to_string(), Uuid::new_v4(), clusters)
         })).unwrap();
@@ -860,7 +1827,11 @@ mod pool {
             let mut c = Cluster::default();
             c.expect_allocated().return_const(0u64);
             c.expect_optimum_queue_depth().return_const(10u32);
+            c.expect_checkpointed().return_const(0u64);
+            c.expect_checkpoint().return_const(None);
+            c.expect_live_data().return_const(0u64);
             c.expect_size().return_const(32_768_000u64);
+            c.expect_reserved().return_const(0u64);
             c.expect_uuid().return_const(Uuid::new_v4());
             c
         };
@@ -874,8 +1845,8 @@ mod pool {
         let mut rt = current_thread::Runtime::new().unwrap();
         let pool = rt.block_on(future::lazy(|| {
             let clusters = vec![
-                ClusterProxy::new(c0),
-                ClusterProxy::new(c1)
+                Some(ClusterProxy::new(c0)),
+                Some(ClusterProxy::new(c1))
             ];
             Pool::new("foo".to_string(), Uuid::new_v4(), clusters)
         })).unwrap();
@@ -883,16 +1854,57 @@ mod pool {
         assert!(rt.block_on(pool.free(PBA::new(1, 12345), 16)).is_ok());
     }
 
-    // optimum_queue_depth is always a smallish integer, so exact equality
-    // testing is ok.
-    #[allow(clippy::float_cmp)]
+    #[test]
+    fn iostats() {
+        let cluster = |write_ops| {
+            let mut c = Cluster::default();
+            c.expect_allocated().return_const(0u64);
+            c.expect_optimum_queue_depth().return_const(10u32);
+            c.expect_checkpointed().return_const(0u64);
+            c.expect_checkpoint().return_const(None);
+            c.expect_live_data().return_const(0u64);
+            c.expect_size().return_const(32_768_000u64);
+            c.expect_reserved().return_const(0u64);
+            c.expect_uuid().return_const(Uuid::new_v4());
+            c.expect_iostats()
+                .once()
+                .returning(move || {
+                    let mut stats = iostat::IoStats::default();
+                    stats.write_ops = write_ops;
+                    VdevStats::leaf(Uuid::new_v4(), stats)
+                });
+            c
+        };
+
+        let uuid = Uuid::new_v4();
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let pool = rt.block_on(future::lazy(|| {
+            let clusters = vec![
+                Some(ClusterProxy::new(cluster(5))),
+                Some(ClusterProxy::new(cluster(7)))
+            ];
+            Pool::new("foo".to_string(), uuid, clusters)
+        })).unwrap();
+
+        let vs = rt.block_on(pool.iostats()).unwrap();
+        assert_eq!(vs.uuid, uuid);
+        assert_eq!(vs.stats.write_ops, 12);
+        assert_eq!(vs.children.len(), 2);
+        assert_eq!(vs.children[0].stats.write_ops, 5);
+        assert_eq!(vs.children[1].stats.write_ops, 7);
+    }
+
     #[test]
     fn new() {
         let cluster = || {
             let mut c = Cluster::default();
             c.expect_optimum_queue_depth().return_const(10u32);
+            c.expect_checkpointed().return_const(0u64);
+            c.expect_checkpoint().return_const(None);
+            c.expect_live_data().return_const(0u64);
             c.expect_allocated().return_const(500u64);
             c.expect_size().return_const(1000u64);
+            c.expect_reserved().return_const(0u64);
             c.expect_uuid().return_const(Uuid::new_v4());
             c
         };
@@ -900,30 +1912,336 @@ mod pool {
         let mut rt = current_thread::Runtime::new().unwrap();
         let pool = rt.block_on(future::lazy(|| {
             let clusters = vec![
-                ClusterProxy::new(cluster()),
-                ClusterProxy::new(cluster())
+                Some(ClusterProxy::new(cluster())),
+                Some(ClusterProxy::new(cluster()))
             ];
             Pool::new("foo".to_string(), Uuid::new_v4(), clusters)
         })).unwrap();
-        assert_eq!(pool.stats.allocated_space[0].load(Ordering::Relaxed), 500);
-        assert_eq!(pool.stats.allocated_space[1].load(Ordering::Relaxed), 500);
-        assert_eq!(pool.stats.optimum_queue_depth[0], 10.0);
-        assert_eq!(pool.stats.optimum_queue_depth[1], 10.0);
+        {
+            let stats = pool.stats.read().unwrap();
+            assert_eq!(stats.allocated_space[0].load(Ordering::Relaxed), 500);
+            assert_eq!(stats.allocated_space[1].load(Ordering::Relaxed), 500);
+            let oqd0 = stats.optimum_queue_depth[0].load(Ordering::Relaxed);
+            let oqd1 = stats.optimum_queue_depth[1].load(Ordering::Relaxed);
+            assert_eq!(oqd0, 10);
+            assert_eq!(oqd1, 10);
+        }
         assert_eq!(pool.allocated(), 1000);
         assert_eq!(pool.size(), 2000);
     }
 
+    /// Build a `LabelReader` for a single-cluster pool, as if it had been read
+    /// from disk.
+    fn label_reader(cluster_uuid: Uuid, features: Features,
+                    scheduler: SchedulerPolicy, limits: IoLimits)
+        -> LabelReader
+    {
+        let label = Label {
+            name: "foo".to_owned(),
+            uuid: Uuid::new_v4(),
+            children: vec![cluster_uuid],
+            scheduler,
+            limits,
+            removing: None,
+            classes: vec![StorageClass::default()],
+            state: PoolState::Exported,
+            hostid: 0,
+            import_time: 0
+        };
+        let mut lw = LabelWriter::new(0, TxgT::from(0));
+        lw.set_features(features);
+        label.write(&mut lw).unwrap();
+        let v = lw.into_sglist().unwrap().iter()
+            .fold(Vec::new(), |mut acc, db| {
+                acc.extend_from_slice(&db[..]);
+                acc
+            });
+        LabelReader::from_dbs(DivBufShared::from(v)).unwrap()
+    }
+
+    #[test]
+    fn open_unknown_incompat() {
+        let cluster_uuid = Uuid::new_v4();
+        let mut cluster = Cluster::default();
+        cluster.expect_optimum_queue_depth().return_const(10u32);
+        cluster.expect_checkpointed().return_const(0u64);
+        cluster.expect_checkpoint().return_const(None);
+        cluster.expect_live_data().return_const(0u64);
+        cluster.expect_uuid().return_const(cluster_uuid);
+        let unknown = Features{incompat: 1 << 63, .. Features::default()};
+        let features = Features::SUPPORTED.union(unknown);
+        let lr = label_reader(cluster_uuid, features,
+                              SchedulerPolicy::default(), IoLimits::default());
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let r = rt.block_on(future::lazy(move || {
+            let combined = vec![(ClusterProxy::new(cluster), lr)];
+            Pool::open(None, combined, false)
+        }));
+        assert_eq!(r.err(), Some(Error::ENOTSUP));
+    }
+
+    // A label with fewer storage classes than children is corrupt
+    #[test]
+    fn open_missing_classes() {
+        let cluster_uuid = Uuid::new_v4();
+        let mut cluster = Cluster::default();
+        cluster.expect_optimum_queue_depth().return_const(10u32);
+        cluster.expect_checkpointed().return_const(0u64);
+        cluster.expect_checkpoint().return_const(None);
+        cluster.expect_live_data().return_const(0u64);
+        cluster.expect_uuid().return_const(cluster_uuid);
+        let label = Label {
+            name: "foo".to_owned(),
+            uuid: Uuid::new_v4(),
+            children: vec![cluster_uuid],
+            scheduler: SchedulerPolicy::default(),
+            limits: IoLimits::default(),
+            removing: None,
+            classes: Vec::new(),
+            state: PoolState::Exported,
+            hostid: 0,
+            import_time: 0
+        };
+        let mut lw = LabelWriter::new(0, TxgT::from(0));
+        label.write(&mut lw).unwrap();
+        let v = lw.into_sglist().unwrap().iter()
+            .fold(Vec::new(), |mut acc, db| {
+                acc.extend_from_slice(&db[..]);
+                acc
+            });
+        let lr = LabelReader::from_dbs(DivBufShared::from(v)).unwrap();
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let r = rt.block_on(future::lazy(move || {
+            let combined = vec![(ClusterProxy::new(cluster), lr)];
+            Pool::open(None, combined, false)
+        }));
+        assert_eq!(r.err(), Some(Error::EINVAL));
+    }
+
+    // A read-only import may ignore unknown ro_compat features, but must
+    // refuse to write
+    #[test]
+    fn open_readonly() {
+        let cluster_uuid = Uuid::new_v4();
+        let mut cluster = Cluster::default();
+        cluster.expect_allocated().return_const(0u64);
+        cluster.expect_optimum_queue_depth().return_const(10u32);
+        cluster.expect_checkpointed().return_const(0u64);
+        cluster.expect_checkpoint().return_const(None);
+        cluster.expect_live_data().return_const(0u64);
+        cluster.expect_size().return_const(32_768_000u64);
+        cluster.expect_reserved().return_const(0u64);
+        cluster.expect_set_limits().return_const(());
+        cluster.expect_set_scheduler().return_const(());
+        cluster.expect_uuid().return_const(cluster_uuid);
+        cluster.expect_write().never();
+        cluster.expect_write_label().never();
+        let unknown = Features{ro_compat: 1 << 63, .. Features::default()};
+        let features = Features::SUPPORTED.union(unknown);
+        let lr = label_reader(cluster_uuid, features,
+                              SchedulerPolicy::default(), IoLimits::default());
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let (pool, _lr) = rt.block_on(future::lazy(move || {
+            let combined = vec![(ClusterProxy::new(cluster), lr)];
+            Pool::open(None, combined, true)
+        })).unwrap();
+        assert!(pool.readonly());
+
+        let dbs = DivBufShared::from(vec![0u8; 4096]);
+        let db0 = dbs.try_const().unwrap();
+        let r = rt.block_on(pool.write(db0, WriteClass::Data, TxgT::from(42),
+                                       PriorityClass::Normal));
+        assert_eq!(r.unwrap_err(), Error::EROFS);
+        let label_writer = LabelWriter::new(0, TxgT::from(0));
+        let r = rt.block_on(pool.write_label(label_writer));
+        assert_eq!(r.unwrap_err(), Error::EROFS);
+    }
+
+    // Opening a pool should skip removed Clusters and resume any interrupted
+    // removal
+    #[test]
+    fn open_removing() {
+        let uuids = [Uuid::new_v4(), Uuid::new_v4()];
+        let cluster = |uuid| {
+            let mut c = Cluster::default();
+            c.expect_allocated().return_const(100u64);
+            c.expect_optimum_queue_depth().return_const(10u32);
+            c.expect_checkpointed().return_const(0u64);
+            c.expect_checkpoint().return_const(None);
+            c.expect_live_data().return_const(0u64);
+            c.expect_size().return_const(1000u64);
+            c.expect_reserved().return_const(0u64);
+            c.expect_uuid().return_const(uuid);
+            c.expect_set_limits().return_const(());
+            c.expect_set_scheduler().return_const(());
+            c
+        };
+        let label = Label {
+            name: "foo".to_owned(),
+            uuid: Uuid::new_v4(),
+            children: vec![Uuid::nil(), uuids[0], uuids[1]],
+            scheduler: SchedulerPolicy::default(),
+            limits: IoLimits::default(),
+            removing: Some(uuids[1]),
+            classes: vec![StorageClass::default(); 3],
+            state: PoolState::Active,
+            hostid: 0,
+            import_time: 0
+        };
+        let label_reader = || {
+            let mut lw = LabelWriter::new(0, TxgT::from(0));
+            label.write(&mut lw).unwrap();
+            let v = lw.into_sglist().unwrap().iter()
+                .fold(Vec::new(), |mut acc, db| {
+                    acc.extend_from_slice(&db[..]);
+                    acc
+                });
+            LabelReader::from_dbs(DivBufShared::from(v)).unwrap()
+        };
+        let combined = vec![
+            (cluster(uuids[0]), label_reader()),
+            (cluster(uuids[1]), label_reader())
+        ];
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let (pool, _lr) = rt.block_on(future::lazy(move || {
+            let combined = combined.into_iter()
+                .map(|(c, lr)| (ClusterProxy::new(c), lr))
+                .collect::<Vec<_>>();
+            Pool::open(None, combined, false)
+        })).unwrap();
+        assert_eq!(pool.removing(), Some((uuids[1], 100)));
+        assert_eq!(pool.size(), 1000);
+        assert_eq!(pool.allocated(), 200);
+    }
+
+    // Opening a pool should restore its scheduling policy
+    #[test]
+    fn open_scheduler() {
+        let cluster_uuid = Uuid::new_v4();
+        let mut cluster = Cluster::default();
+        cluster.expect_allocated().return_const(0u64);
+        cluster.expect_optimum_queue_depth().return_const(10u32);
+        cluster.expect_checkpointed().return_const(0u64);
+        cluster.expect_checkpoint().return_const(None);
+        cluster.expect_live_data().return_const(0u64);
+        cluster.expect_size().return_const(32_768_000u64);
+        cluster.expect_reserved().return_const(0u64);
+        cluster.expect_uuid().return_const(cluster_uuid);
+        cluster.expect_set_limits().return_const(());
+        cluster.expect_set_scheduler()
+            .with(eq(SchedulerPolicy::Deadline))
+            .once()
+            .return_const(());
+        cluster.expect_sync_all()
+            .once()
+            .return_once(|| Box::new(future::ok::<(), Error>(())));
+        let lr = label_reader(cluster_uuid, Features::SUPPORTED,
+                              SchedulerPolicy::Deadline, IoLimits::default());
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let (pool, _lr) = rt.block_on(future::lazy(move || {
+            let combined = vec![(ClusterProxy::new(cluster), lr)];
+            Pool::open(None, combined, false)
+        })).unwrap();
+        assert_eq!(pool.scheduler(), SchedulerPolicy::Deadline);
+        // The ClusterServer handles RPCs in order, so once sync_all completes
+        // set_scheduler must have been delivered, too.
+        rt.block_on(pool.sync_all()).unwrap();
+    }
+
+    // Opening a pool should restore its I/O rate limits
+    #[test]
+    fn open_limits() {
+        let cluster_uuid = Uuid::new_v4();
+        let mut limits = IoLimits::default();
+        limits.read_bw = Some(1 << 20);
+        limits.bg_read_iops = Some(100);
+        let mut cluster = Cluster::default();
+        cluster.expect_allocated().return_const(0u64);
+        cluster.expect_optimum_queue_depth().return_const(10u32);
+        cluster.expect_checkpointed().return_const(0u64);
+        cluster.expect_checkpoint().return_const(None);
+        cluster.expect_live_data().return_const(0u64);
+        cluster.expect_size().return_const(32_768_000u64);
+        cluster.expect_reserved().return_const(0u64);
+        cluster.expect_uuid().return_const(cluster_uuid);
+        cluster.expect_set_limits()
+            .with(eq(limits))
+            .once()
+            .return_const(());
+        cluster.expect_set_scheduler().return_const(());
+        cluster.expect_sync_all()
+            .once()
+            .return_once(|| Box::new(future::ok::<(), Error>(())));
+        let lr = label_reader(cluster_uuid, Features::SUPPORTED,
+                              SchedulerPolicy::default(), limits);
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let (pool, _lr) = rt.block_on(future::lazy(move || {
+            let combined = vec![(ClusterProxy::new(cluster), lr)];
+            Pool::open(None, combined, false)
+        })).unwrap();
+        assert_eq!(pool.limits(), limits);
+        // The ClusterServer handles RPCs in order, so once sync_all completes
+        // set_limits must have been delivered, too.
+        rt.block_on(pool.sync_all()).unwrap();
+    }
+
+    #[test]
+    fn upgrade() {
+        let cluster_uuid = Uuid::new_v4();
+        let mut cluster = Cluster::default();
+        cluster.expect_allocated().return_const(0u64);
+        cluster.expect_optimum_queue_depth().return_const(10u32);
+        cluster.expect_checkpointed().return_const(0u64);
+        cluster.expect_checkpoint().return_const(None);
+        cluster.expect_live_data().return_const(0u64);
+        cluster.expect_size().return_const(32_768_000u64);
+        cluster.expect_reserved().return_const(0u64);
+        cluster.expect_set_limits().return_const(());
+        cluster.expect_set_scheduler().return_const(());
+        cluster.expect_uuid().return_const(cluster_uuid);
+        // A pool that predates MULTIHOST, with an unknown compatible feature
+        let features = Features{
+            compat: 1 << 63,
+            ro_compat: 0,
+            incompat: 0
+        };
+        let lr = label_reader(cluster_uuid, features,
+                              SchedulerPolicy::default(), IoLimits::default());
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let (pool, _lr) = rt.block_on(future::lazy(move || {
+            let combined = vec![(ClusterProxy::new(cluster), lr)];
+            Pool::open(None, combined, false)
+        })).unwrap();
+        // Unknown compatible features are preserved
+        assert_eq!(pool.features(), features);
+        assert!(pool.upgrade());
+        assert_eq!(pool.features(), features.union(Features::SUPPORTED));
+        // Upgrading twice is a no-op
+        assert!(!pool.upgrade());
+    }
+
     #[test]
     fn read() {
         let mut cluster = Cluster::default();
         cluster.expect_allocated().return_const(0u64);
         cluster.expect_optimum_queue_depth().return_const(10u32);
+        cluster.expect_checkpointed().return_const(0u64);
+        cluster.expect_checkpoint().return_const(None);
+        cluster.expect_live_data().return_const(0u64);
         cluster.expect_size().return_const(32_768_000u64);
+        cluster.expect_reserved().return_const(0u64);
         cluster.expect_uuid().return_const(Uuid::new_v4());
         cluster.expect_read()
-            .with(always(), eq(10))
+            .with(always(), eq(10), eq(PriorityClass::Interactive))
             .once()
-            .returning(|mut iovec, _lba| {
+            .returning(|mut iovec, _lba, _priority| {
                 iovec.copy_from_slice(&vec![99; 4096][..]);
                 Box::new( future::ok::<(), Error>(()))
             });
@@ -931,7 +2249,7 @@ mod pool {
         let mut rt = current_thread::Runtime::new().unwrap();
         let pool = rt.block_on(future::lazy(move || {
             let clusters = vec![
-                ClusterProxy::new(cluster),
+                Some(ClusterProxy::new(cluster)),
             ];
             Pool::new("foo".to_string(), Uuid::new_v4(), clusters)
         })).unwrap();
@@ -939,7 +2257,8 @@ mod pool {
         let dbs = DivBufShared::from(vec![0u8; 4096]);
         let dbm0 = dbs.try_mut().unwrap();
         let pba = PBA::new(0, 10);
-        let result = rt.block_on(pool.read(dbm0, pba));
+        let result = rt.block_on(pool.read(dbm0, pba,
+                                           PriorityClass::Interactive));
         assert!(result.is_ok());
         let db0 = dbs.try_const().unwrap();
         assert_eq!(&db0[..], &vec![99u8; 4096][..]);
@@ -951,16 +2270,20 @@ mod pool {
         let mut cluster = Cluster::default();
         cluster.expect_allocated().return_const(0u64);
         cluster.expect_optimum_queue_depth().return_const(10u32);
+        cluster.expect_checkpointed().return_const(0u64);
+        cluster.expect_checkpoint().return_const(None);
+        cluster.expect_live_data().return_const(0u64);
         cluster.expect_size().return_const(32_768_000u64);
+        cluster.expect_reserved().return_const(0u64);
         cluster.expect_uuid().return_const(Uuid::new_v4());
         cluster.expect_read()
             .once()
-            .return_once(move |_, _| Box::new(Err(e).into_future()));
+            .return_once(move |_, _, _| Box::new(Err(e).into_future()));
 
         let mut rt = current_thread::Runtime::new().unwrap();
         let pool = rt.block_on(future::lazy(move || {
             let clusters = vec![
-                ClusterProxy::new(cluster),
+                Some(ClusterProxy::new(cluster)),
             ];
             Pool::new("foo".to_string(), Uuid::new_v4(), clusters)
         })).unwrap();
@@ -968,17 +2291,163 @@ mod pool {
         let dbs = DivBufShared::from(vec![0u8; 4096]);
         let dbm0 = dbs.try_mut().unwrap();
         let pba = PBA::new(0, 10);
-        let result = rt.block_on(pool.read(dbm0, pba));
+        let result = rt.block_on(pool.read(dbm0, pba, PriorityClass::Normal));
         assert_eq!(result.unwrap_err(), e);
     }
 
+    // Reading from a removed Cluster should fail cleanly
+    #[test]
+    fn read_removed() {
+        let mut c = Cluster::default();
+        c.expect_allocated().return_const(0u64);
+        c.expect_optimum_queue_depth().return_const(10u32);
+        c.expect_checkpointed().return_const(0u64);
+        c.expect_checkpoint().return_const(None);
+        c.expect_live_data().return_const(0u64);
+        c.expect_size().return_const(32_768_000u64);
+        c.expect_reserved().return_const(0u64);
+        c.expect_uuid().return_const(Uuid::new_v4());
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let pool = rt.block_on(future::lazy(|| {
+            let clusters = vec![None, Some(ClusterProxy::new(c))];
+            Pool::new("foo".to_string(), Uuid::new_v4(), clusters)
+        })).unwrap();
+        assert_eq!(pool.size(), 32_768_000);
+
+        let dbs = DivBufShared::from(vec![0u8; 4096]);
+        let dbm0 = dbs.try_mut().unwrap();
+        let r = rt.block_on(pool.read(dbm0, PBA::new(0, 10),
+                                      PriorityClass::Interactive));
+        assert_eq!(r.err(), Some(Error::ENXIO));
+    }
+
+    #[test]
+    fn start_removal() {
+        let uuids = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        let cluster = |uuid, allocated| {
+            let mut c = Cluster::default();
+            c.expect_allocated().return_const(allocated);
+            c.expect_optimum_queue_depth().return_const(10u32);
+            c.expect_checkpointed().return_const(0u64);
+            c.expect_checkpoint().return_const(None);
+            c.expect_live_data().return_const(0u64);
+            c.expect_size().return_const(1000u64);
+            c.expect_reserved().return_const(0u64);
+            c.expect_uuid().return_const(uuid);
+            c
+        };
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let pool = rt.block_on(future::lazy(|| {
+            let clusters = vec![
+                Some(ClusterProxy::new(cluster(uuids[0], 100u64))),
+                Some(ClusterProxy::new(cluster(uuids[1], 900u64))),
+                Some(ClusterProxy::new(cluster(uuids[2], 300u64))),
+            ];
+            Pool::new("foo".to_string(), Uuid::new_v4(), clusters)
+        })).unwrap();
+
+        assert_eq!(pool.start_removal(Uuid::new_v4()), Err(Error::ENOENT));
+        // The other Clusters have only 800 LBAs free
+        assert_eq!(pool.start_removal(uuids[1]), Err(Error::ENOSPC));
+        assert!(pool.removing().is_none());
+        assert_eq!(pool.start_removal(uuids[2]), Ok(2));
+        assert_eq!(pool.removing(), Some((uuids[2], 300)));
+        assert_eq!(pool.size(), 2000);
+        // Starting the same removal again is harmless
+        assert_eq!(pool.start_removal(uuids[2]), Ok(2));
+        assert_eq!(pool.start_removal(uuids[0]), Err(Error::EBUSY));
+    }
+
+    // Zones that may hold checkpointed data can't be cleaned, so a Cluster
+    // can't be removed while the pool has a checkpoint
+    #[test]
+    fn start_removal_checkpoint() {
+        let uuids = [Uuid::new_v4(), Uuid::new_v4()];
+        let cluster = |uuid| {
+            let mut c = Cluster::default();
+            c.expect_allocated().return_const(100u64);
+            c.expect_optimum_queue_depth().return_const(10u32);
+            c.expect_checkpointed().return_const(0u64);
+            c.expect_checkpoint().return_const(Some(TxgT::from(1)));
+            c.expect_live_data().return_const(100u64);
+            c.expect_size().return_const(1000u64);
+            c.expect_reserved().return_const(0u64);
+            c.expect_uuid().return_const(uuid);
+            c
+        };
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let pool = rt.block_on(future::lazy(|| {
+            let clusters = vec![
+                Some(ClusterProxy::new(cluster(uuids[0]))),
+                Some(ClusterProxy::new(cluster(uuids[1]))),
+            ];
+            Pool::new("foo".to_string(), Uuid::new_v4(), clusters)
+        })).unwrap();
+
+        assert_eq!(pool.start_removal(uuids[1]), Err(Error::EBUSY));
+        assert!(pool.removing().is_none());
+    }
+
+    fn finish_removal_pool(live_data: LbaT) -> Pool {
+        let cluster = |live_data| {
+            let mut c = Cluster::default();
+            c.expect_allocated().return_const(100u64);
+            c.expect_optimum_queue_depth().return_const(10u32);
+            c.expect_checkpointed().return_const(0u64);
+            c.expect_checkpoint().return_const(None);
+            c.expect_live_data().return_const(live_data);
+            c.expect_size().return_const(1000u64);
+            c.expect_reserved().return_const(0u64);
+            c.expect_uuid().return_const(Uuid::new_v4());
+            c
+        };
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+        rt.block_on(future::lazy(|| {
+            let clusters = vec![
+                Some(ClusterProxy::new(cluster(live_data))),
+                Some(ClusterProxy::new(cluster(100))),
+            ];
+            Pool::new("foo".to_string(), Uuid::new_v4(), clusters)
+        })).unwrap()
+    }
+
+    // A Cluster can only be dropped once it holds nothing but its spacemap
+    #[test]
+    fn finish_removal() {
+        let pool = finish_removal_pool(0);
+        let uuid = pool.clusters.read().unwrap()[0].as_ref().unwrap().uuid();
+        assert_eq!(pool.start_removal(uuid), Ok(0));
+        assert_eq!(pool.finish_removal(0), Ok(()));
+        assert!(pool.removing().is_none());
+        assert_eq!(pool.allocated(), 100);
+    }
+
+    // Removal must not finish while the Cluster still holds live data
+    #[test]
+    fn finish_removal_busy() {
+        let pool = finish_removal_pool(10);
+        let uuid = pool.clusters.read().unwrap()[0].as_ref().unwrap().uuid();
+        assert_eq!(pool.start_removal(uuid), Ok(0));
+        assert_eq!(pool.finish_removal(0), Err(Error::EBUSY));
+        assert_eq!(pool.removing(), Some((uuid, 100)));
+        assert_eq!(pool.allocated(), 200);
+    }
+
     #[test]
     fn sync_all() {
         let cluster = || {
             let mut c = Cluster::default();
             c.expect_allocated().return_const(0u64);
             c.expect_optimum_queue_depth().return_const(10u32);
+            c.expect_checkpointed().return_const(0u64);
+            c.expect_checkpoint().return_const(None);
+            c.expect_live_data().return_const(0u64);
             c.expect_size().return_const(32_768_000u64);
+            c.expect_reserved().return_const(0u64);
             c.expect_uuid().return_const(Uuid::new_v4());
             c.expect_sync_all()
                 .once()
@@ -989,8 +2458,8 @@ mod pool {
         let mut rt = current_thread::Runtime::new().unwrap();
         let pool = rt.block_on(future::lazy(|| {
             let clusters = vec![
-                ClusterProxy::new(cluster()),
-                ClusterProxy::new(cluster())
+                Some(ClusterProxy::new(cluster())),
+                Some(ClusterProxy::new(cluster()))
             ];
             Pool::new("foo".to_string(), Uuid::new_v4(), clusters)
         })).unwrap();
@@ -998,28 +2467,83 @@ mod pool {
         assert!(rt.block_on(pool.sync_all()).is_ok());
     }
 
+    // Relocated records stay on the same tier as their original cluster, even
+    // if they're large
+    #[test]
+    fn relocate() {
+        let mock = || {
+            let mut c = Cluster::default();
+            c.expect_allocated().return_const(0u64);
+            c.expect_optimum_queue_depth().return_const(10u32);
+            c.expect_checkpointed().return_const(0u64);
+            c.expect_checkpoint().return_const(None);
+            c.expect_live_data().return_const(0u64);
+            c.expect_size().return_const(32_768_000u64);
+            c.expect_reserved().return_const(0u64);
+            c.expect_uuid().return_const(Uuid::new_v4());
+            c
+        };
+        let mut fast = mock();
+        fast.expect_write()
+            .withf(|buf, class, txg, priority| {
+                buf.len() == 65536 &&
+                *class == WriteClass::Relocation &&
+                *txg == TxgT::from(42) &&
+                *priority == PriorityClass::Background
+            }).once()
+            .return_once(|_, _, _, _|
+                Ok((0, Box::new(future::ok::<(), Error>(()))))
+            );
+        let slow = mock();
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let pool = rt.block_on(future::lazy(|| {
+            let mut cp0 = ClusterProxy::new(fast);
+            cp0.set_storage_class(StorageClass::Fast);
+            let mut cp1 = ClusterProxy::new(slow);
+            cp1.set_storage_class(StorageClass::Slow);
+            Pool::new("foo".to_string(), Uuid::new_v4(),
+                      vec![Some(cp0), Some(cp1)])
+        })).unwrap();
+
+        let dbs = DivBufShared::from(vec![0u8; 65536]);
+        let db0 = dbs.try_const().unwrap();
+        let result = rt.block_on(pool.relocate(db0, 0, TxgT::from(42)));
+        assert_eq!(result.unwrap(), PBA::new(0, 0));
+    }
+
     #[test]
     fn write() {
         let mut cluster = Cluster::default();
             cluster.expect_allocated().return_const(0u64);
             cluster.expect_optimum_queue_depth().return_const(10u32);
+            cluster.expect_checkpointed().return_const(0u64);
+            cluster.expect_checkpoint().return_const(None);
+            cluster.expect_live_data().return_const(0u64);
             cluster.expect_size().return_const(32_768_000u64);
+            cluster.expect_reserved().return_const(0u64);
             cluster.expect_uuid().return_const(Uuid::new_v4());
             cluster.expect_write()
-                .withf(|buf, txg| {
-                    buf.len() == BYTES_PER_LBA && *txg == TxgT::from(42)
+                .withf(|buf, class, txg, _| {
+                    buf.len() == BYTES_PER_LBA &&
+                    *class == WriteClass::Data &&
+                    *txg == TxgT::from(42)
                 }).once()
-                .return_once(|_, _| Ok((0, Box::new(future::ok::<(), Error>(())))));
+                .return_once(|_, _, _, _|
+                    Ok((0, Box::new(future::ok::<(), Error>(()))))
+                );
 
         let mut rt = current_thread::Runtime::new().unwrap();
         let pool = rt.block_on(future::lazy(|| {
             Pool::new("foo".to_string(), Uuid::new_v4(),
-                      vec![ClusterProxy::new(cluster)])
+                      vec![Some(ClusterProxy::new(cluster))])
         })).unwrap();
 
         let dbs = DivBufShared::from(vec![0u8; 4096]);
         let db0 = dbs.try_const().unwrap();
-        let result = rt.block_on( pool.write(db0, TxgT::from(42)));
+        let result = rt.block_on(pool.write(db0, WriteClass::Data,
+                                            TxgT::from(42),
+                                            PriorityClass::Normal));
         assert_eq!(result.unwrap(), PBA::new(0, 0));
     }
 
@@ -1029,21 +2553,29 @@ mod pool {
         let mut cluster = Cluster::default();
             cluster.expect_allocated().return_const(0u64);
             cluster.expect_optimum_queue_depth().return_const(10u32);
+            cluster.expect_checkpointed().return_const(0u64);
+            cluster.expect_checkpoint().return_const(None);
+            cluster.expect_live_data().return_const(0u64);
             cluster.expect_size().return_const(32_768_000u64);
+            cluster.expect_reserved().return_const(0u64);
             cluster.expect_uuid().return_const(Uuid::new_v4());
             cluster.expect_write()
                 .once()
-                .return_once(move |_, _| Ok((0, Box::new(Err(e).into_future()))));
+                .return_once(move |_, _, _, _|
+                    Ok((0, Box::new(Err(e).into_future())))
+                );
 
         let mut rt = current_thread::Runtime::new().unwrap();
         let pool = rt.block_on(future::lazy(|| {
             Pool::new("foo".to_string(), Uuid::new_v4(),
-                      vec![ClusterProxy::new(cluster)])
+                      vec![Some(ClusterProxy::new(cluster))])
         })).unwrap();
 
         let dbs = DivBufShared::from(vec![0u8; 4096]);
         let db0 = dbs.try_const().unwrap();
-        let result = rt.block_on( pool.write(db0, TxgT::from(42)));
+        let result = rt.block_on(pool.write(db0, WriteClass::Data,
+                                            TxgT::from(42),
+                                            PriorityClass::Normal));
         assert_eq!(result.unwrap_err(), e);
     }
 
@@ -1053,35 +2585,100 @@ mod pool {
         let mut cluster = Cluster::default();
             cluster.expect_allocated().return_const(0u64);
             cluster.expect_optimum_queue_depth().return_const(10u32);
+            cluster.expect_checkpointed().return_const(0u64);
+            cluster.expect_checkpoint().return_const(None);
+            cluster.expect_live_data().return_const(0u64);
             cluster.expect_size().return_const(32_768_000u64);
+            cluster.expect_reserved().return_const(0u64);
             cluster.expect_uuid().return_const(Uuid::new_v4());
             cluster.expect_write()
                 .once()
-                .return_once(move |_, _| Err(e));
+                .return_once(move |_, _, _, _| Err(e));
 
         let mut rt = current_thread::Runtime::new().unwrap();
         let pool = rt.block_on(future::lazy(|| {
             Pool::new("foo".to_string(), Uuid::new_v4(),
-                      vec![ClusterProxy::new(cluster)])
+                      vec![Some(ClusterProxy::new(cluster))])
         })).unwrap();
 
         let dbs = DivBufShared::from(vec![0u8; 4096]);
         let db0 = dbs.try_const().unwrap();
-        let result = rt.block_on( pool.write(db0, TxgT::from(42)));
+        let result = rt.block_on(pool.write(db0, WriteClass::Data,
+                                            TxgT::from(42),
+                                            PriorityClass::Normal));
         assert_eq!(result.unwrap_err(), e);
     }
 
+    // A write that doesn't fit in the preferred tier should go to the other
+    #[test]
+    fn write_enospc_retry() {
+        let mock = || {
+            let mut c = Cluster::default();
+            c.expect_allocated().return_const(0u64);
+            c.expect_optimum_queue_depth().return_const(10u32);
+            c.expect_checkpointed().return_const(0u64);
+            c.expect_checkpoint().return_const(None);
+            c.expect_live_data().return_const(0u64);
+            c.expect_size().return_const(32_768_000u64);
+            c.expect_reserved().return_const(0u64);
+            c.expect_uuid().return_const(Uuid::new_v4());
+            c
+        };
+        let mut fast = mock();
+        fast.expect_write()
+            .once()
+            .return_once(|_, _, _, _| Err(Error::ENOSPC));
+        let mut slow = mock();
+        slow.expect_write()
+            .withf(|buf, class, txg, _| {
+                buf.len() == BYTES_PER_LBA &&
+                *class == WriteClass::Data &&
+                *txg == TxgT::from(42)
+            }).once()
+            .return_once(|_, _, _, _|
+                Ok((7, Box::new(future::ok::<(), Error>(()))))
+            );
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let pool = rt.block_on(future::lazy(|| {
+            let mut cp0 = ClusterProxy::new(fast);
+            cp0.set_storage_class(StorageClass::Fast);
+            let mut cp1 = ClusterProxy::new(slow);
+            cp1.set_storage_class(StorageClass::Slow);
+            Pool::new("foo".to_string(), Uuid::new_v4(),
+                      vec![Some(cp0), Some(cp1)])
+        })).unwrap();
+
+        let dbs = DivBufShared::from(vec![0u8; 4096]);
+        let db0 = dbs.try_const().unwrap();
+        let result = rt.block_on(pool.write(db0, WriteClass::Data,
+                                            TxgT::from(42),
+                                            PriorityClass::Normal));
+        assert_eq!(result.unwrap(), PBA::new(1, 7));
+        let stats = pool.stats.read().unwrap();
+        assert_eq!(stats.queue_depth[0].load(Ordering::Relaxed), 0);
+        assert_eq!(stats.queue_depth[1].load(Ordering::Relaxed), 0);
+        assert_eq!(stats.allocated_space[0].load(Ordering::Relaxed), 0);
+        assert_eq!(stats.allocated_space[1].load(Ordering::Relaxed), 1);
+    }
+
     // Make sure allocated space accounting is symmetric
     #[test]
     fn write_and_free() {
         let mut cluster = Cluster::default();
         cluster.expect_allocated().return_const(0u64);
         cluster.expect_optimum_queue_depth().return_const(10u32);
+        cluster.expect_checkpointed().return_const(0u64);
+        cluster.expect_checkpoint().return_const(None);
+        cluster.expect_live_data().return_const(0u64);
         cluster.expect_size().return_const(32_768_000u64);
+        cluster.expect_reserved().return_const(0u64);
         cluster.expect_uuid().return_const(Uuid::new_v4());
         cluster.expect_write()
             .once()
-            .return_once(|_, _| Ok((0, Box::new(future::ok::<(), Error>(())))));
+            .return_once(|_, _, _, _|
+                Ok((0, Box::new(future::ok::<(), Error>(()))))
+            );
         cluster.expect_free()
             .once()
             .return_once(|_, _| Box::new(Ok(()).into_future()));
@@ -1089,15 +2686,21 @@ mod pool {
         let mut rt = current_thread::Runtime::new().unwrap();
         let pool = rt.block_on(future::lazy(|| {
             Pool::new("foo".to_string(), Uuid::new_v4(),
-                      vec![ClusterProxy::new(cluster)])
+                      vec![Some(ClusterProxy::new(cluster))])
         })).unwrap();
 
         let dbs = DivBufShared::from(vec![0u8; 1024]);
         let db0 = dbs.try_const().unwrap();
-        let drp = rt.block_on( pool.write(db0, TxgT::from(42))).unwrap();
-        assert!(pool.stats.allocated_space[0].load(Ordering::Relaxed) > 0);
+        let drp = rt.block_on(pool.write(db0, WriteClass::Data,
+                                         TxgT::from(42),
+                                         PriorityClass::Normal)).unwrap();
+        let allocated = |pool: &Pool| {
+            pool.stats.read().unwrap().allocated_space[0]
+                .load(Ordering::Relaxed)
+        };
+        assert!(allocated(&pool) > 0);
         rt.block_on( pool.free(drp, 1)).unwrap();
-        assert_eq!(pool.stats.allocated_space[0].load(Ordering::Relaxed), 0);
+        assert_eq!(allocated(&pool), 0);
     }
 }
 
@@ -1108,69 +2711,174 @@ mod rpc {
     #[test]
     fn debug() {
         let dbs = DivBufShared::from(Vec::new());
-        let lw = LabelWriter::new(0);
+        let lw = LabelWriter::new(0, TxgT::from(0));
         format!("{:?}", Rpc::Allocated(oneshot::channel().0));
         format!("{:?}", Rpc::FindClosedZone(0, oneshot::channel().0));
-        format!("{:?}", Rpc::Flush(0, oneshot::channel().0));
+        format!("{:?}", Rpc::FinishZones(TxgT::from(0), oneshot::channel().0));
+        format!("{:?}", Rpc::Flush(TxgT::from(0), oneshot::channel().0));
         format!("{:?}", Rpc::Free(0, 0, oneshot::channel().0));
-        format!("{:?}", Rpc::OptimumQueueDepth(oneshot::channel().0));
+        format!("{:?}", Rpc::IoStats(oneshot::channel().0));
         format!("{:?}", Rpc::Read(dbs.try_mut().unwrap(), 0,
-            oneshot::channel().0));
+            PriorityClass::Normal, oneshot::channel().0));
+        format!("{:?}", Rpc::Reserved(oneshot::channel().0));
+        format!("{:?}", Rpc::SetLimits(IoLimits::default()));
+        format!("{:?}", Rpc::SetScheduler(SchedulerPolicy::default()));
         format!("{:?}", Rpc::Size(oneshot::channel().0));
         format!("{:?}", Rpc::SyncAll(oneshot::channel().0));
-        format!("{:?}", Rpc::Write(dbs.try_const().unwrap(), TxgT(0),
-            oneshot::channel().0));
+        format!("{:?}", Rpc::Write(dbs.try_const().unwrap(), WriteClass::Data,
+            TxgT(0), PriorityClass::Normal, oneshot::channel().0));
         format!("{:?}", Rpc::WriteLabel(lw, oneshot::channel().0));
         #[cfg(debug_assertions)]
         format!("{:?}", Rpc::AssertCleanZone(0, TxgT(0)));
     }
 }
 
+mod storage_class {
+    use pretty_assertions::assert_eq;
+    use super::super::*;
+
+    #[test]
+    fn preferred() {
+        let small = StorageClass::SMALL_RECORD;
+        assert_eq!(StorageClass::preferred(65536, WriteClass::Metadata),
+                   StorageClass::Fast);
+        assert_eq!(StorageClass::preferred(small, WriteClass::Data),
+                   StorageClass::Fast);
+        assert_eq!(StorageClass::preferred(small + 1, WriteClass::Data),
+                   StorageClass::Slow);
+        assert_eq!(StorageClass::preferred(65536, WriteClass::Relocation),
+                   StorageClass::Slow);
+    }
+}
+
 mod stats {
     use pretty_assertions::assert_eq;
     use super::super::*;
 
+    fn oqd(depths: &[u32]) -> Vec<Arc<AtomicU32>> {
+        depths.iter().map(|d| Arc::new(AtomicU32::new(*d))).collect()
+    }
+
+    // A newly added cluster isn't eligible for writes until its size is known
+    #[test]
+    fn add_cluster() {
+        let mut stats = Stats {
+            optimum_queue_depth: oqd(&[10]),
+            queue_depth: vec![AtomicU32::new(0)],
+            reserved: vec![0],
+            size: vec![1000],
+            allocated_space: vec![AtomicU64::new(900)],
+            classes: vec![StorageClass::Slow; 1]
+        };
+        stats.add_cluster(Arc::new(AtomicU32::new(10)), StorageClass::Slow);
+        assert_eq!(stats.choose_cluster(StorageClass::Slow), 0);
+        assert_eq!(stats.size(), 1000);
+
+        stats.set_cluster_space(1, 1000, 10, 0);
+        assert_eq!(stats.choose_cluster(StorageClass::Slow), 1);
+        assert_eq!(stats.reserved(), 10);
+        assert_eq!(stats.size(), 2000);
+    }
+
     #[test]
     fn allocated() {
         let stats = Stats {
-            optimum_queue_depth: vec![10.0, 10.0],
+            optimum_queue_depth: oqd(&[10, 10]),
             queue_depth: vec![AtomicU32::new(0), AtomicU32::new(0)],
+            reserved: vec![0, 0],
             size: vec![1000, 1000],
-            allocated_space: vec![AtomicU64::new(10), AtomicU64::new(900)]
+            allocated_space: vec![AtomicU64::new(10), AtomicU64::new(900)],
+            classes: vec![StorageClass::Slow; 2]
         };
         assert_eq!(stats.allocated(), 910);
     }
 
+    // A Cluster can be evacuated only if the others have room for its data
+    #[test]
+    fn can_evacuate() {
+        let stats = Stats {
+            optimum_queue_depth: oqd(&[10, 10, 10]),
+            queue_depth: vec![AtomicU32::new(0), AtomicU32::new(0),
+                              AtomicU32::new(0)],
+            reserved: vec![10, 10, 10],
+            size: vec![1000, 1000, 1000],
+            allocated_space: vec![AtomicU64::new(500), AtomicU64::new(700),
+                                  AtomicU64::new(800)],
+            classes: vec![StorageClass::Slow; 3]
+        };
+        assert!(stats.can_evacuate(0));
+        assert!(stats.can_evacuate(1));
+        assert!(!stats.can_evacuate(2));
+    }
+
+    // The last live Cluster can never be evacuated
+    #[test]
+    fn can_evacuate_last() {
+        let mut stats = Stats {
+            optimum_queue_depth: oqd(&[10, 10]),
+            queue_depth: vec![AtomicU32::new(0), AtomicU32::new(0)],
+            reserved: vec![0, 0],
+            size: vec![1000, 1000],
+            allocated_space: vec![AtomicU64::new(0), AtomicU64::new(0)],
+            classes: vec![StorageClass::Slow; 2]
+        };
+        stats.stop_allocating(1);
+        assert!(!stats.can_evacuate(0));
+    }
+
     #[test]
     fn choose_cluster_empty() {
         // Two clusters, one full and one empty.  Choose the empty one
         let mut stats = Stats {
-            optimum_queue_depth: vec![10.0, 10.0],
+            optimum_queue_depth: oqd(&[10, 10]),
             queue_depth: vec![AtomicU32::new(0), AtomicU32::new(0)],
+            reserved: vec![0, 0],
             size: vec![1000, 1000],
-            allocated_space: vec![AtomicU64::new(0), AtomicU64::new(1000)]
+            allocated_space: vec![AtomicU64::new(0), AtomicU64::new(1000)],
+            classes: vec![StorageClass::Slow; 2]
         };
-        assert_eq!(stats.choose_cluster(), 0);
+        assert_eq!(stats.choose_cluster(StorageClass::Slow), 0);
 
         // Try the reverse, too
         stats.allocated_space = vec![AtomicU64::new(1000), AtomicU64::new(0)];
-        assert_eq!(stats.choose_cluster(), 1);
+        assert_eq!(stats.choose_cluster(StorageClass::Slow), 1);
     }
 
     #[test]
     fn choose_cluster_queue_depth() {
         // Two clusters, one busy and one idle.  Choose the idle one
         let mut stats = Stats {
-            optimum_queue_depth: vec![10.0, 10.0],
+            optimum_queue_depth: oqd(&[10, 10]),
             queue_depth: vec![AtomicU32::new(0), AtomicU32::new(10)],
+            reserved: vec![0, 0],
             size: vec![1000, 1000],
-            allocated_space: vec![AtomicU64::new(0), AtomicU64::new(0)]
+            allocated_space: vec![AtomicU64::new(0), AtomicU64::new(0)],
+            classes: vec![StorageClass::Slow; 2]
         };
-        assert_eq!(stats.choose_cluster(), 0);
+        assert_eq!(stats.choose_cluster(StorageClass::Slow), 0);
 
         // Try the reverse, too
         stats.queue_depth = vec![AtomicU32::new(10), AtomicU32::new(0)];
-        assert_eq!(stats.choose_cluster(), 1);
+        assert_eq!(stats.choose_cluster(StorageClass::Slow), 1);
+    }
+
+    #[test]
+    fn choose_cluster_optimum_queue_depth() {
+        // Two equally busy clusters, but one can handle a deeper queue.
+        // Choose that one.
+        let stats = Stats {
+            optimum_queue_depth: oqd(&[10, 20]),
+            queue_depth: vec![AtomicU32::new(5), AtomicU32::new(5)],
+            reserved: vec![0, 0],
+            size: vec![1000, 1000],
+            allocated_space: vec![AtomicU64::new(0), AtomicU64::new(0)],
+            classes: vec![StorageClass::Slow; 2]
+        };
+        assert_eq!(stats.choose_cluster(StorageClass::Slow), 1);
+
+        // Now the first cluster's optimum queue depth grows
+        stats.optimum_queue_depth[0].store(40, Ordering::Relaxed);
+        assert_eq!(stats.choose_cluster(StorageClass::Slow), 0);
     }
 
     #[test]
@@ -1178,17 +2886,108 @@ mod stats {
         // Two clusters, one nearly full and idle, the other busy but not very
         // full.  Choose the not very full one.
         let mut stats = Stats {
-            optimum_queue_depth: vec![10.0, 10.0],
+            optimum_queue_depth: oqd(&[10, 10]),
             queue_depth: vec![AtomicU32::new(0), AtomicU32::new(10)],
+            reserved: vec![0, 0],
             size: vec![1000, 1000],
-            allocated_space: vec![AtomicU64::new(960), AtomicU64::new(50)]
+            allocated_space: vec![AtomicU64::new(960), AtomicU64::new(50)],
+            classes: vec![StorageClass::Slow; 2]
         };
-        assert_eq!(stats.choose_cluster(), 1);
+        assert_eq!(stats.choose_cluster(StorageClass::Slow), 1);
 
         // Try the reverse, too
         stats.queue_depth = vec![AtomicU32::new(10), AtomicU32::new(0)];
         stats.allocated_space = vec![AtomicU64::new(50), AtomicU64::new(960)];
-        assert_eq!(stats.choose_cluster(), 0);
+        assert_eq!(stats.choose_cluster(StorageClass::Slow), 0);
+    }
+
+    // Writes should go to the preferred storage class until it's nearly full
+    #[test]
+    fn choose_cluster_tiered() {
+        let stats = Stats {
+            optimum_queue_depth: oqd(&[10, 10]),
+            queue_depth: vec![AtomicU32::new(10), AtomicU32::new(0)],
+            reserved: vec![0, 0],
+            size: vec![1000, 1000],
+            allocated_space: vec![AtomicU64::new(900), AtomicU64::new(0)],
+            classes: vec![StorageClass::Fast, StorageClass::Slow]
+        };
+        assert_eq!(stats.choose_cluster(StorageClass::Fast), 0);
+        assert_eq!(stats.choose_cluster(StorageClass::Slow), 1);
+
+        // Once the fast cluster fills up, fall back to the slow one
+        stats.allocated_space[0].store(960, Ordering::Relaxed);
+        assert_eq!(stats.choose_cluster(StorageClass::Fast), 1);
+    }
+
+    // The preferred storage class is full once its unreserved space is
+    #[test]
+    fn choose_cluster_tiered_reserved() {
+        let stats = Stats {
+            optimum_queue_depth: oqd(&[10, 10]),
+            queue_depth: vec![AtomicU32::new(0), AtomicU32::new(0)],
+            reserved: vec![100, 100],
+            size: vec![1000, 1000],
+            allocated_space: vec![AtomicU64::new(850), AtomicU64::new(0)],
+            classes: vec![StorageClass::Fast, StorageClass::Slow]
+        };
+        assert_eq!(stats.choose_cluster(StorageClass::Fast), 0);
+
+        // 86% of the total, but more than 95% of the unreserved space
+        stats.allocated_space[0].store(860, Ordering::Relaxed);
+        assert_eq!(stats.choose_cluster(StorageClass::Fast), 1);
+    }
+
+    // Writes that don't fit should be retried on the least utilized Cluster
+    // of the other storage class
+    #[test]
+    fn choose_fallback() {
+        let stats = Stats {
+            optimum_queue_depth: oqd(&[10, 10, 10, 10]),
+            queue_depth: (0..4).map(|_| AtomicU32::new(0)).collect(),
+            reserved: vec![0, 0, 0, 0],
+            size: vec![1000, 1000, 1000, 0],
+            allocated_space: vec![AtomicU64::new(900), AtomicU64::new(500),
+                                  AtomicU64::new(100), AtomicU64::new(0)],
+            classes: vec![StorageClass::Fast, StorageClass::Slow,
+                          StorageClass::Slow, StorageClass::Fast]
+        };
+        assert_eq!(stats.choose_fallback(0), Some(2));
+        // Cluster 3 is being removed, so it can't take the retry
+        assert_eq!(stats.choose_fallback(1), Some(0));
+    }
+
+    // With only one storage class, there's nowhere to retry
+    #[test]
+    fn choose_fallback_none() {
+        let stats = Stats {
+            optimum_queue_depth: oqd(&[10, 10]),
+            queue_depth: vec![AtomicU32::new(0), AtomicU32::new(0)],
+            reserved: vec![0, 0],
+            size: vec![1000, 1000],
+            allocated_space: vec![AtomicU64::new(900), AtomicU64::new(0)],
+            classes: vec![StorageClass::Slow; 2]
+        };
+        assert_eq!(stats.choose_fallback(0), None);
+    }
+
+    // A Cluster that's being removed should get no more writes, nor count
+    // toward the Pool's size
+    #[test]
+    fn stop_allocating() {
+        let mut stats = Stats {
+            optimum_queue_depth: oqd(&[10, 10]),
+            queue_depth: vec![AtomicU32::new(0), AtomicU32::new(10)],
+            reserved: vec![10, 10],
+            size: vec![1000, 1000],
+            allocated_space: vec![AtomicU64::new(0), AtomicU64::new(500)],
+            classes: vec![StorageClass::Slow; 2]
+        };
+        stats.stop_allocating(0);
+        assert_eq!(stats.choose_cluster(StorageClass::Slow), 1);
+        assert_eq!(stats.reserved(), 10);
+        assert_eq!(stats.size(), 1000);
+        assert_eq!(stats.allocated(), 500);
     }
 }
 }
@@ -10,10 +10,16 @@ use crate::common::{
     label::*,
     vdev::*,
 };
+#[cfg(test)]
+use crate::common::{
+    iostat::VdevStats,
+    throttle::IoLimits,
+    vdev_block::SchedulerPolicy
+};
 #[cfg(test)] use futures::Future;
 #[cfg(test)] use mockall::*;
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     iter::once,
     num::NonZeroU64,
     path::Path,
@@ -45,6 +51,15 @@ pub enum Label {
 }
 
 impl<'a> Label {
+    /// Is `child` faulted?  Its data is stale, because labels were written
+    /// while it was missing.  So it must not be used until it's resilvered.
+    pub fn is_faulted(&self, child: &Uuid) -> bool {
+        match self {
+            Label::Raid(l) => l.faulted.contains(child),
+            Label::OneDisk(_) => false
+        }
+    }
+
     pub fn iter_children(&'a self) -> Box<dyn Iterator<Item=&Uuid> + 'a> {
         match self {
             Label::Raid(l) => Box::new(l.children.iter()),
@@ -52,12 +67,58 @@ impl<'a> Label {
         }
     }
 
+    /// Read a `Label` out of `reader`, in whichever layout its features call
+    /// for.
+    pub fn read(reader: &mut LabelReader) -> bincode::Result<Self> {
+        let mut label: Label = reader.deserialize()?;
+        if reader.features().incompat & Features::RAID_FAULTS != 0 {
+            let degraded: DegradedLabel = reader.deserialize()?;
+            if let Label::Raid(l) = &mut label {
+                l.faulted = degraded.faulted;
+                l.zones = degraded.zones;
+            }
+        }
+        Ok(label)
+    }
+
+    /// How many children may be missing without losing data?
+    pub fn redundancy(&self) -> i16 {
+        match self {
+            Label::Raid(l) => l.redundancy,
+            Label::OneDisk(_) => 0
+        }
+    }
+
     pub fn uuid(&self) -> Uuid {
         match self {
             Label::Raid(l) => l.uuid,
             Label::OneDisk(l) => l.uuid
         }
     }
+
+    /// Write this `Label` into `labeller`, in whichever layout its features
+    /// call for.
+    pub fn write(&self, labeller: &mut LabelWriter) -> bincode::Result<()> {
+        if labeller.features().incompat & Features::RAID_FAULTS != 0 {
+            // serialize is LIFO, so the DegradedLabel goes first
+            let (faulted, zones) = match self {
+                Label::Raid(l) => (l.faulted.clone(), l.zones.clone()),
+                Label::OneDisk(_) => (Vec::new(), Vec::new())
+            };
+            labeller.serialize(&DegradedLabel{faulted, zones})?;
+        }
+        labeller.serialize(self)
+    }
+}
+
+/// The part of a RAID `Label` that is only present with the `RAID_FAULTS`
+/// feature.  It holds what's needed to import the RAID while some children
+/// are missing.  On disk, it immediately follows the rest of the `Label`.
+#[derive(Serialize, Deserialize, Debug)]
+struct DegradedLabel {
+    faulted:            Vec<Uuid>,
+    /// Run-length encoded zone map.  See `VdevRaid::encode_zone_map`.
+    zones:              Vec<(LbaT, LbaT, ZoneT)>,
 }
 
 /// Create a raid-like `Vdev` from its components.
@@ -77,18 +138,30 @@ impl<'a> Label {
 ///                         disks may fail before the array becomes
 ///                         inoperable.
 /// * `paths`:              Slice of pathnames of files and/or devices
+///
+/// Fails with `EINVAL` if the layout is impossible or a device is listed
+/// twice.
 pub fn create<P>(chunksize: Option<NonZeroU64>, disks_per_stripe: i16,
     lbas_per_zone: Option<NonZeroU64>, redundancy: i16,
-    mut paths: Vec<P>) -> Rc<dyn VdevRaidApi>
+    mut paths: Vec<P>) -> Result<Rc<dyn VdevRaidApi>, Error>
     where P: AsRef<Path> + 'static
 {
+    let unique = paths.iter()
+        .map(|p| p.as_ref())
+        .collect::<BTreeSet<_>>();
+    if unique.len() < paths.len() {
+        return Err(Error::EINVAL);
+    }
     if paths.len() == 1 {
-        assert_eq!(disks_per_stripe, 1);
-        assert_eq!(redundancy, 0);
-        Rc::new(VdevOneDisk::create(lbas_per_zone, paths.pop().unwrap()))
+        if disks_per_stripe != 1 || redundancy != 0 {
+            return Err(Error::EINVAL);
+        }
+        let vdev = VdevOneDisk::create(lbas_per_zone, paths.pop().unwrap())?;
+        Ok(Rc::new(vdev))
     } else {
-        Rc::new(VdevRaid::create(chunksize, disks_per_stripe, lbas_per_zone,
-                                 redundancy, paths))
+        let vdev = VdevRaid::create(chunksize, disks_per_stripe,
+                                    lbas_per_zone, redundancy, paths)?;
+        Ok(Rc::new(vdev))
     }
 }
 
@@ -100,18 +173,23 @@ pub fn create<P>(chunksize: Option<NonZeroU64>, disks_per_stripe: i16,
 ///                 then it will not be verified.
 /// * `combined`:   An array of pairs of `VdevBlock`s and their
 ///                 associated `LabelReader`.  The labels of each will be
-///                 verified.
+///                 verified.  A RAID `Vdev` may be missing up to its
+///                 redundancy's worth of children, and will operate degraded.
 pub fn open(uuid: Option<Uuid>, combined: Vec<(VdevBlock, LabelReader)>)
     -> (Rc<dyn VdevRaidApi>, LabelReader)
 {
-    let mut label_pair = None;
+    let mut label_pair: Option<(Label, LabelReader)> = None;
     let all_blockdevs = combined.into_iter()
         .map(|(vdev_block, mut label_reader)| {
-        let label: Label = label_reader.deserialize().unwrap();
+        let label = Label::read(&mut label_reader).unwrap();
         if let Some(u) = uuid {
             assert_eq!(u, label.uuid(), "Opening disk from wrong cluster");
         }
-        if label_pair.is_none() {
+        // A child that came back after missing some transaction groups has
+        // older labels than the rest
+        let newer = label_pair.as_ref()
+            .map_or(true, |(_, lr)| label_reader.txg() > lr.txg());
+        if newer {
             label_pair = Some((label, label_reader));
         }
         (vdev_block.uuid(), vdev_block)
@@ -133,6 +211,7 @@ mock!{
     pub VdevRaid {}
     trait Vdev {
         fn lba2zone(&self, lba: LbaT) -> Option<ZoneT>;
+        fn max_open_zones(&self) -> Option<ZoneT>;
         fn optimum_queue_depth(&self) -> u32;
         fn size(&self) -> LbaT;
         fn sync_all(&self) -> Box<dyn Future<Item = (), Error = Error>>;
@@ -144,14 +223,15 @@ mock!{
         fn erase_zone(&self, zone: ZoneT) -> BoxVdevFut;
         fn finish_zone(&self, zone: ZoneT) -> BoxVdevFut;
         fn flush_zone(&self, zone: ZoneT) -> (LbaT, BoxVdevFut);
+        fn iostats(&self) -> VdevStats;
         fn open_zone(&self, zone: ZoneT) -> BoxVdevFut;
-        fn read_at(&self, buf: IoVecMut, lba: LbaT) -> BoxVdevFut;
-        fn read_spacemap(&self, buf: IoVecMut, idx: u32) -> BoxVdevFut;
+        fn read_at(&self, buf: IoVecMut, lba: LbaT, priority: PriorityClass)
+            -> BoxVdevFut;
         fn reopen_zone(&self, zone: ZoneT, allocated: LbaT) -> BoxVdevFut;
-        fn write_at(&self, buf: IoVec, zone: ZoneT,
-                    lba: LbaT) -> BoxVdevFut;
+        fn set_limits(&self, limits: IoLimits);
+        fn set_scheduler(&self, policy: SchedulerPolicy);
+        fn write_at(&self, buf: IoVec, zone: ZoneT, lba: LbaT,
+                    priority: PriorityClass) -> BoxVdevFut;
         fn write_label(&self, labeller: LabelWriter) -> BoxVdevFut;
-        fn write_spacemap(&self, sglist: &SGList, idx: u32, block: LbaT)
-            -> BoxVdevFut;
     }
 }
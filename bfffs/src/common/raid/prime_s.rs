@@ -156,6 +156,18 @@ pub struct PrimeS {
 }
 
 impl PrimeS {
+    /// Can a PrimeS Locator be built with these parameters?
+    pub fn is_valid(num_disks: i16, disks_per_stripe: i16, redundancy: i16)
+        -> bool
+    {
+        is_prime(num_disks) &&
+            // Limit disk count so we don't need to use 64-bit math, which is
+            // slower.
+            num_disks <= 215 &&
+            disks_per_stripe > 1 && disks_per_stripe <= num_disks &&
+            redundancy > 0 && redundancy < disks_per_stripe
+    }
+
     /// Create a new PrimeS Locator
     ///
     /// # Parameters
@@ -165,12 +177,8 @@ impl PrimeS {
     /// `redundancy`:       Redundancy level of the RAID array.  This many disks
     ///                     may fail before the data becomes irrecoverable.
     pub fn new(num_disks: i16, disks_per_stripe: i16, redundancy: i16) -> Self {
-        assert!(is_prime(num_disks));
-        // Limit disk count so we don't need to use 64-bit math, which is
-        // slower.
-        assert!(num_disks <= 215);
-        assert!(disks_per_stripe > 1 && disks_per_stripe <= num_disks);
-        assert!(redundancy > 0 && redundancy < disks_per_stripe);
+        assert!(PrimeS::is_valid(num_disks, disks_per_stripe, redundancy),
+            "Invalid PrimeS layout");
         let depth = disks_per_stripe * (num_disks - 1);
         let disks_per_stripe = disks_per_stripe as u8;
         let redundancy = redundancy as u8;
@@ -5,7 +5,10 @@ use crate::{
     common::{
         *,
         label::*,
+        iostat::VdevStats,
+        throttle::IoLimits,
         vdev::*,
+        vdev_block::SchedulerPolicy,
     }
 };
 use futures::{Future, IntoFuture};
@@ -52,12 +55,13 @@ impl VdevOneDisk {
     // Hide from docs.  The public API should just be raid::create, but this
     // function technically needs to be public for testing purposes.
     #[doc(hidden)]
-    pub fn create<P>(lbas_per_zone: Option<NonZeroU64>, path: P) -> Self
+    pub fn create<P>(lbas_per_zone: Option<NonZeroU64>, path: P)
+        -> Result<Self, Error>
         where P: AsRef<Path> + 'static
     {
         let uuid = Uuid::new_v4();
-        let blockdev = VdevBlock::create(path, lbas_per_zone).unwrap();
-        VdevOneDisk{uuid, blockdev}
+        let blockdev = VdevBlock::create(path, lbas_per_zone)?;
+        Ok(VdevOneDisk{uuid, blockdev})
     }
 
     /// Open an existing `VdevOneDisk`
@@ -81,6 +85,10 @@ impl Vdev for VdevOneDisk {
         self.blockdev.lba2zone(lba)
     }
 
+    fn max_open_zones(&self) -> Option<ZoneT> {
+        self.blockdev.max_open_zones()
+    }
+
     fn optimum_queue_depth(&self) -> u32 {
         self.blockdev.optimum_queue_depth()
     }
@@ -122,35 +130,48 @@ impl VdevRaidApi for VdevOneDisk {
         (0, boxfut!(Ok(()).into_future(), _, _, 'static))
     }
 
+    fn iostats(&self) -> VdevStats {
+        VdevStats::aggregate(self.uuid, vec![self.blockdev.iostats()])
+    }
+
     fn open_zone(&self, zone: ZoneT) -> BoxVdevFut {
         let limits = self.blockdev.zone_limits(zone);
         boxfut!(self.blockdev.open_zone(limits.0), _, _, 'static)
     }
 
-    fn read_at(&self, buf: IoVecMut, lba: LbaT) -> BoxVdevFut {
-        boxfut!(self.blockdev.read_at(buf, lba), _, _, 'static)
-    }
-
-    fn read_spacemap(&self, buf: IoVecMut, idx: u32) -> BoxVdevFut {
-        boxfut!(self.blockdev.read_spacemap(buf, idx), _, _, 'static)
+    fn read_at(&self, buf: IoVecMut, lba: LbaT, priority: PriorityClass)
+        -> BoxVdevFut
+    {
+        boxfut!(self.blockdev.read_at(buf, lba, priority), _, _, 'static)
     }
 
     fn reopen_zone(&self, _zone: ZoneT, _allocated: LbaT) -> BoxVdevFut {
         boxfut!(Ok(()).into_future(), _, _, 'static)
     }
 
-    fn write_at(&self, buf: IoVec, _zone: ZoneT, lba: LbaT) -> BoxVdevFut {
+    fn set_limits(&self, limits: IoLimits) {
+        self.blockdev.set_limits(limits)
+    }
+
+    fn set_scheduler(&self, policy: SchedulerPolicy) {
+        self.blockdev.set_scheduler(policy)
+    }
+
+    fn write_at(&self, buf: IoVec, _zone: ZoneT, lba: LbaT,
+                priority: PriorityClass) -> BoxVdevFut
+    {
         // Pad up to a whole number of LBAs.  Upper layers don't do this because
         // VdevRaidApi doesn't have a writev_at method.  But VdevBlock does, so
         // the raid layer is the most efficient place to pad.
         let partial = buf.len() % BYTES_PER_LBA;
         if partial == 0 {
-            boxfut!(self.blockdev.write_at(buf, lba), _, _, 'static)
+            boxfut!(self.blockdev.write_at(buf, lba, priority), _, _, 'static)
         } else {
             let remainder = BYTES_PER_LBA - partial;
             let zbuf = ZERO_REGION.try_const().unwrap().slice_to(remainder);
             let sglist = vec![buf, zbuf];
-            boxfut!(self.blockdev.writev_at(sglist, lba), _, _, 'static)
+            let fut = self.blockdev.writev_at(sglist, lba, priority);
+            boxfut!(fut, _, _, 'static)
         }
     }
 
@@ -160,19 +181,9 @@ impl VdevRaidApi for VdevOneDisk {
             child: self.blockdev.uuid()
         };
         let label = super::Label::OneDisk(onedisk_label);
-        labeller.serialize(&label).unwrap();
+        label.write(&mut labeller).unwrap();
         boxfut!(self.blockdev.write_label(labeller), _, _, 'static)
     }
-
-    // Allow &Vec arguments so we can clone them.
-    // TODO: pass by value instead of reference, to eliminate the clone
-    #[allow(clippy::ptr_arg)]
-    fn write_spacemap(&self, sglist: &SGList, idx: u32, block: LbaT)
-        -> Box<VdevFut>
-    {
-        let fut = self.blockdev.write_spacemap(sglist.clone(), idx, block);
-        boxfut!(fut, _, _, 'static)
-    }
 }
 
 #[cfg(test)]
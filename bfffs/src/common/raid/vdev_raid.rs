@@ -4,11 +4,15 @@ use crate::{
     boxfut,
     common::{
         *,
+        iostat::VdevStats,
         label::*,
+        throttle::IoLimits,
         vdev::*,
+        vdev_block::SchedulerPolicy,
     }
 };
 use divbuf::DivBufShared;
+use fixedbitset::FixedBitSet;
 use futures::{Future, future};
 use itertools::multizip;
 use std::{
@@ -18,7 +22,8 @@ use std::{
     mem,
     num::NonZeroU64,
     path::Path,
-    ptr
+    ptr,
+    rc::Rc
 };
 use super::{
     codec::*,
@@ -160,9 +165,51 @@ pub struct Label {
     pub uuid:           Uuid,
     chunksize:          LbaT,
     disks_per_stripe:   i16,
-    redundancy:         i16,
+    pub redundancy:     i16,
     layout_algorithm:   LayoutAlgorithm,
-    pub children:       Vec<Uuid>
+    pub children:       Vec<Uuid>,
+    /// Children that were missing when this label was written.  Stored
+    /// separately, with the `RAID_FAULTS` feature.
+    #[serde(skip)]
+    pub faulted:        Vec<Uuid>,
+    /// The disk LBAs of each RAID zone, as computed from all children at
+    /// creation, run-length encoded by `VdevRaid::encode_zone_map`.  Stored
+    /// separately, with the `RAID_FAULTS` feature.  Empty if the label
+    /// predates it.
+    #[serde(skip)]
+    pub(super) zones:   Vec<(LbaT, LbaT, ZoneT)>
+}
+
+/// One child of a `VdevRaid`
+enum Child {
+    Present(VdevBlock),
+    /// The child could not be found when the pool was imported, or it has
+    /// missed some transaction groups.  Its data will be reconstructed from
+    /// the other children until it's resilvered.
+    Missing(Uuid)
+}
+
+impl Child {
+    fn as_present(&self) -> Option<&VdevBlock> {
+        match self {
+            Child::Present(bd) => Some(bd),
+            Child::Missing(_) => None
+        }
+    }
+
+    fn is_missing(&self) -> bool {
+        match self {
+            Child::Present(_) => false,
+            Child::Missing(_) => true
+        }
+    }
+
+    fn uuid(&self) -> Uuid {
+        match self {
+            Child::Present(bd) => bd.uuid(),
+            Child::Missing(uuid) => *uuid
+        }
+    }
 }
 
 /// `VdevRaid`: Virtual Device for the RAID transform
@@ -178,20 +225,17 @@ pub struct VdevRaid {
     chunksize: LbaT,
 
     /// RAID codec
-    codec: Codec,
+    codec: Rc<Codec>,
 
     /// Locator, declustering or otherwise
     locator: Box<dyn Locator>,
 
     /// Underlying block devices.  Order is important!
-    blockdevs: Box<[VdevBlock]>,
+    children: Box<[Child]>,
 
     /// RAID placement algorithm.
     layout_algorithm: LayoutAlgorithm,
 
-    /// Best number of queued commands for the whole `VdevRaid`
-    optimum_queue_depth: u32,
-
     /// In memory cache of data that has not yet been flushed to the block
     /// devices.
     ///
@@ -200,6 +244,10 @@ pub struct VdevRaid {
     stripe_buffers: RefCell<BTreeMap<ZoneT, StripeBuffer>>,
 
     uuid: Uuid,
+
+    /// The range of disk LBAs used by each RAID zone, the same on every disk.
+    /// The end is exclusive.
+    zones: Box<[(LbaT, LbaT)]>,
 }
 
 /// Convenience macro for `VdevRaid` I/O methods
@@ -211,8 +259,11 @@ pub struct VdevRaid {
 /// let lba = 0;
 /// let fut = issue_1stripe_ops!(self, v, lba, false, write_at)
 /// ```
+///
+/// Any additional arguments are passed through to `$func`.
 macro_rules! issue_1stripe_ops {
-    ( $self:ident, $buf:expr, $lba:expr, $parity:expr, $func:ident) => {
+    ( $self:ident, $buf:expr, $lba:expr, $parity:expr, $func:ident
+      $(, $arg:expr)* ) => {
         {
             let (start, end) = if $parity {
                 let m = $self.codec.stripesize() - $self.codec.protection();
@@ -236,7 +287,12 @@ macro_rules! issue_1stripe_ops {
                 } else {
                     loc.offset * $self.chunksize
                 };
-                $self.blockdevs[loc.disk as usize].$func(d, disk_lba)
+                match &$self.children[loc.disk as usize] {
+                    Child::Present(bd) =>
+                        future::Either::A(bd.$func(d, disk_lba $(, $arg)*)),
+                    // Parity will cover for the missing disk
+                    Child::Missing(_) => future::Either::B(future::ok(()))
+                }
             })
             .collect();
             future::join_all(futs)
@@ -247,6 +303,27 @@ macro_rules! issue_1stripe_ops {
 impl VdevRaid {
     const DEFAULT_CHUNKSIZE: LbaT = 16;
 
+    /// The `VdevBlock` of child `disk`, which must not be missing
+    fn blockdev(&self, disk: usize) -> &VdevBlock {
+        self.children[disk].as_present()
+            .expect("Can't issue I/O to a missing disk")
+    }
+
+    /// Iterate through all children that aren't missing
+    fn blockdevs(&self) -> impl Iterator<Item=&VdevBlock> {
+        self.children.iter().filter_map(Child::as_present)
+    }
+
+    /// Return the limits of each of `blockdev`'s zones that lie within the
+    /// disk LBA range `limits`.
+    fn child_zones(blockdev: &VdevBlock, limits: (LbaT, LbaT))
+        -> impl Iterator<Item=(LbaT, LbaT)> + '_
+    {
+        let first = blockdev.lba2zone(limits.0).unwrap();
+        let last = blockdev.lba2zone(limits.1 - 1).unwrap();
+        (first..=last).map(move |z| blockdev.zone_limits(z))
+    }
+
     /// Choose the best declustering layout for the requirements given.
     fn choose_layout(num_disks: i16, _disks_per_stripe: i16, _redundancy: i16,
                      chunksize: Option<NonZeroU64>)
@@ -274,23 +351,72 @@ impl VdevRaid {
     ///                         disks may fail before the array becomes
     ///                         inoperable.
     /// * `paths`:              Slice of pathnames of files and/or devices
+    ///
+    /// Fails with `EINVAL` if the layout is impossible, or if the disks
+    /// aren't alike enough to be combined.
     // Hide from docs.  The public API should just be raid::create, but this
     // function technically needs to be public for testing purposes.
     #[doc(hidden)]
     pub fn create<P>(chunksize: Option<NonZeroU64>, disks_per_stripe: i16,
         lbas_per_zone: Option<NonZeroU64>, redundancy: i16, paths: Vec<P>)
-        -> Self
+        -> Result<Self, Error>
         where P: AsRef<Path> + 'static
     {
+        if paths.len() > i16::max_value() as usize {
+            return Err(Error::EINVAL);
+        }
         let num_disks = paths.len() as i16;
+        if !PrimeS::is_valid(num_disks, disks_per_stripe, redundancy) {
+            return Err(Error::EINVAL);
+        }
         let (layout, chunksize) = VdevRaid::choose_layout(num_disks,
             disks_per_stripe, redundancy, chunksize);
         let uuid = Uuid::new_v4();
         let blockdevs = paths.into_iter().map(|path| {
-            VdevBlock::create(path, lbas_per_zone).unwrap()
-        }).collect::<Vec<_>>();
-        VdevRaid::new(chunksize, disks_per_stripe, redundancy, uuid,
-                      layout, blockdevs.into_boxed_slice())
+            VdevBlock::create(path, lbas_per_zone).map_err(Error::from)
+        }).collect::<Result<Vec<_>, _>>()?;
+        if blockdevs.iter().any(|bd| bd.size() != blockdevs[0].size()) {
+            return Err(Error::EINVAL);
+        }
+        VdevRaid::zone_map(&blockdevs.iter().collect::<Vec<_>>(), chunksize)?;
+        Ok(VdevRaid::new(chunksize, disks_per_stripe, redundancy, uuid,
+                         layout, blockdevs.into_boxed_slice()))
+    }
+
+    /// Expand a zone map that was encoded by `encode_zone_map`
+    fn decode_zone_map(runs: &[(LbaT, LbaT, ZoneT)]) -> Vec<(LbaT, LbaT)> {
+        runs.iter()
+            .flat_map(|&(start, lbas, count)| {
+                (0..LbaT::from(count)).map(move |i| {
+                    (start + i * lbas, start + (i + 1) * lbas)
+                })
+            }).collect()
+    }
+
+    /// Run-length encode a zone map, for the label.  Each run is `(start, lbas,
+    /// count)`: `count` adjacent zones of `lbas` LBAs each, beginning at disk
+    /// LBA `start`.  Most disks need only a few runs.
+    fn encode_zone_map(zones: &[(LbaT, LbaT)]) -> Vec<(LbaT, LbaT, ZoneT)> {
+        let mut runs: Vec<(LbaT, LbaT, ZoneT)> = Vec::new();
+        for &(start, end) in zones {
+            match runs.last_mut() {
+                Some((s, lbas, count)) if *lbas == end - start &&
+                    *s + *lbas * LbaT::from(*count) == start => *count += 1,
+                _ => runs.push((start, end - start, 1))
+            }
+        }
+        runs
+    }
+
+    /// First LBA of `blockdev`'s next zone after `zone`, or the end of the disk
+    fn next_zone_start(blockdev: &VdevBlock, zones: ZoneT, zone: ZoneT)
+        -> LbaT
+    {
+        if zone + 1 < zones {
+            blockdev.zone_limits(zone + 1).0
+        } else {
+            blockdev.size()
+        }
     }
 
     fn new(chunksize: LbaT,
@@ -300,33 +426,62 @@ impl VdevRaid {
            layout_algorithm: LayoutAlgorithm,
            blockdevs: Box<[VdevBlock]>) -> Self
     {
-        let num_disks = blockdevs.len() as i16;
-        let codec = Codec::new(disks_per_stripe as u32, redundancy as u32);
+        let children = blockdevs.into_vec().into_iter()
+            .map(Child::Present)
+            .collect::<Vec<_>>();
+        VdevRaid::with_children(chunksize, disks_per_stripe, redundancy, uuid,
+            layout_algorithm, children.into_boxed_slice(), None)
+    }
+
+    /// Like `new`, but some of the children may be missing.
+    ///
+    /// `zones` is the zone map recorded in the label, if any.  Otherwise it's
+    /// computed from the children that are present, which is only correct if
+    /// the missing ones had the same zone boundaries.  The children may also
+    /// have grown since the label was written, or be of different sizes if
+    /// they haven't all grown yet.  The RAID uses only as much space as the
+    /// smallest one.
+    fn with_children(chunksize: LbaT,
+                     disks_per_stripe: i16,
+                     redundancy: i16,
+                     uuid: Uuid,
+                     layout_algorithm: LayoutAlgorithm,
+                     children: Box<[Child]>,
+                     zones: Option<Vec<(LbaT, LbaT)>>) -> Self
+    {
+        let num_disks = children.len() as i16;
+        let codec = Rc::new(Codec::new(disks_per_stripe as u32,
+                                       redundancy as u32));
         let locator: Box<dyn Locator> = match layout_algorithm {
             LayoutAlgorithm::PrimeS => Box::new(
                 PrimeS::new(num_disks, disks_per_stripe, redundancy))
         };
-        for i in 1..blockdevs.len() {
-            // All blockdevs must be the same size
-            assert_eq!(blockdevs[0].size(), blockdevs[i].size());
-
-            // All blockdevs must have the same zone boundaries
-            // XXX this check assumes fixed-size zones
-            assert_eq!(blockdevs[0].zone_limits(0),
-                       blockdevs[i].zone_limits(0));
-        }
-
-        // NB: the optimum queue depth should actually be a little higher for
-        // healthy reads than for writes or degraded reads.  This calculation
-        // computes the optimum for writes and degraded reads.
-        let optimum_queue_depth = blockdevs.iter()
-        .map(|bd| bd.optimum_queue_depth())
-        .sum::<u32>() / (codec.stripesize() as u32);
+        let blockdevs = children.iter()
+            .filter_map(Child::as_present)
+            .collect::<Vec<_>>();
+        let computed = VdevRaid::zone_map(&blockdevs, chunksize);
+        let zones = match zones {
+            None => computed.expect("Children's zones don't fit a RAID layout"),
+            Some(mut zones) => {
+                // If the children have grown, then the last recorded zone
+                // grows with them, and new zones follow it.
+                let last = *zones.last().unwrap();
+                if let Ok(computed) = computed {
+                    let i = computed.iter().position(|z| z.0 == last.0);
+                    if let Some(i) = i {
+                        if computed.last().unwrap().1 > last.1 {
+                            zones.pop();
+                            zones.extend_from_slice(&computed[i..]);
+                        }
+                    }
+                }
+                zones
+            }
+        }.into_boxed_slice();
 
-        VdevRaid { chunksize, codec, locator, blockdevs, layout_algorithm,
-                   optimum_queue_depth,
+        VdevRaid { chunksize, codec, locator, children, layout_algorithm,
                    stripe_buffers: RefCell::new(BTreeMap::new()),
-                   uuid}   // LCOV_EXCL_LINE   kcov false negative
+                   uuid, zones}   // LCOV_EXCL_LINE   kcov false negative
     }
 
     /// Open an existing `VdevRaid` from its component devices
@@ -334,21 +489,99 @@ impl VdevRaid {
     /// # Parameters
     ///
     /// * `label`:      The `VdevRaid`'s label, taken from any child.
-    /// * `blocks`:     A map of all the children `VdevBlock`s, indexed by UUID.
+    /// * `blocks`:     A map of the children `VdevBlock`s, indexed by UUID.
+    ///                 Up to `redundancy` of them may be missing, in which
+    ///                 case the `VdevRaid` will operate in degraded mode.
+    ///                 Any that the label lists as faulted will be treated as
+    ///                 missing, too.
     pub(super) fn open(label: Label, mut blocks: BTreeMap<Uuid, VdevBlock>)
         -> Self
     {
-        assert_eq!(blocks.len(), label.children.len(),
-            "Missing block devices");
         let children = label.children.iter().map(|uuid| {
-            blocks.remove(&uuid).unwrap()
+            match blocks.remove(&uuid) {
+                Some(bd) if !label.faulted.contains(uuid) =>
+                    Child::Present(bd),
+                _ => Child::Missing(*uuid)
+            }
         }).collect::<Vec<_>>();
-        VdevRaid::new(label.chunksize,
-                      label.disks_per_stripe,
-                      label.redundancy,
-                      label.uuid,
-                      label.layout_algorithm,
-                      children.into_boxed_slice())
+        let missing = children.iter().filter(|c| c.is_missing()).count();
+        assert!(missing <= label.redundancy as usize,
+            "Too many missing block devices");
+        // Missing children may have had different zone boundaries than the
+        // present ones, so use the zone map from when they were all present.
+        let zones = if label.zones.is_empty() {
+            None
+        } else {
+            Some(VdevRaid::decode_zone_map(&label.zones))
+        };
+        VdevRaid::with_children(label.chunksize,
+                                label.disks_per_stripe,
+                                label.redundancy,
+                                label.uuid,
+                                label.layout_algorithm,
+                                children.into_boxed_slice(),
+                                zones)
+    }
+
+    /// Map each RAID zone onto a range of disk LBAs.
+    ///
+    /// The disks' zones needn't be the same size, nor even share boundaries.
+    /// A RAID zone spans as many of each disk's zones as necessary to end at a
+    /// boundary common to every disk, and includes only the disk LBAs that are
+    /// usable on all of them.  Within a RAID zone, each disk's zones must be
+    /// contiguous and their boundaries must be chunk-aligned, so that no chunk
+    /// will straddle two of them.  Fails with `EINVAL` otherwise.
+    fn zone_map(blockdevs: &[&VdevBlock], chunksize: LbaT)
+        -> Result<Vec<(LbaT, LbaT)>, Error>
+    {
+        let n = blockdevs.len();
+        let nzones = blockdevs.iter()
+            .map(|bd| bd.zones())
+            .collect::<Vec<_>>();
+        // Each disk's current zone
+        let mut cur = vec![0; n];
+        let mut zones = Vec::new();
+        while (0..n).all(|i| cur[i] < nzones[i]) {
+            let mut limits = (0..n)
+                .map(|i| blockdevs[i].zone_limits(cur[i]))
+                .collect::<Vec<_>>();
+            let mut nexts = (0..n)
+                .map(|i| VdevRaid::next_zone_start(blockdevs[i], nzones[i],
+                                                    cur[i]))
+                .collect::<Vec<_>>();
+            loop {
+                let boundary = *nexts.iter().max().unwrap();
+                let mut merged = false;
+                for i in 0..n {
+                    if nexts[i] < boundary && cur[i] + 1 < nzones[i] {
+                        // This disk's zone ends too soon.  Extend the RAID zone
+                        // into its next one.
+                        if limits[i].1 != nexts[i] ||
+                            nexts[i] % chunksize != 0
+                        {
+                            // Disk zones must be contiguous and chunk-aligned
+                            // within a RAID zone
+                            return Err(Error::EINVAL);
+                        }
+                        cur[i] += 1;
+                        limits[i].1 = blockdevs[i].zone_limits(cur[i]).1;
+                        nexts[i] = VdevRaid::next_zone_start(blockdevs[i],
+                            nzones[i], cur[i]);
+                        merged = true;
+                    }
+                }
+                if !merged {
+                    break;
+                }
+            }
+            let start = limits.iter().map(|l| l.0).max().unwrap();
+            let end = limits.iter().map(|l| l.1).min().unwrap();
+            zones.push((start, end));
+            for c in cur.iter_mut() {
+                *c += 1;
+            }
+        }
+        Ok(zones)
     }
 
     /// Asynchronously open a zone on a RAID device
@@ -367,10 +600,12 @@ impl VdevRaid {
         let sb = StripeBuffer::new(start_lba + already_allocated, stripe_lbas);
         assert!(self.stripe_buffers.borrow_mut().insert(zone, sb).is_none());
 
-        let (first_disk_lba, _) = self.blockdevs[0].zone_limits(zone);
+        let disk_limits = self.zones[zone as usize];
+        let first_disk_lba = disk_limits.0;
         let start_disk_chunk = div_roundup(first_disk_lba, self.chunksize);
-        let futs: Vec<_> = self.blockdevs.iter()
+        let futs: Vec<_> = self.children.iter()
             .enumerate()
+            .filter_map(|(idx, child)| child.as_present().map(|bd| (idx, bd)))
             .map(|(idx, blockdev)| {
                 // Find the first LBA of this disk that's within our zone
                 let mut first_usable_disk_lba = 0;
@@ -383,27 +618,41 @@ impl VdevRaid {
                         break;
                     }
                 }
-                let zero_fut = if first_usable_disk_lba > first_disk_lba {
+                let child_zones = VdevRaid::child_zones(blockdev, disk_limits)
+                    .collect::<Vec<_>>();
+                // This disk's zone may begin before the RAID zone does
+                let disk_zone_start = child_zones[0].0;
+                let zero_fut = if already_allocated == 0 &&
+                    first_usable_disk_lba > disk_zone_start
+                {
                     // Zero-fill leading wasted space so as not to cause a
-                    // write pointer violation on SMR disks.
-                    let zero_lbas = first_usable_disk_lba - first_disk_lba;
+                    // write pointer violation on SMR disks.  A reopened zone
+                    // was already zero-filled when it was first opened, and
+                    // its write pointer has since moved past that space.
+                    let zero_lbas = first_usable_disk_lba - disk_zone_start;
                     let zero_len = zero_lbas as usize * BYTES_PER_LBA;
                     let sglist = zero_sglist(zero_len);
-                    Box::new(blockdev.writev_at(sglist, first_disk_lba))
+                    Box::new(blockdev.writev_at(sglist, disk_zone_start,
+                                                PriorityClass::Normal))
                 } else {
                     boxfut!(future::ok::<(), Error>(()), _, _, 'static)
                 };
+                let open_futs = child_zones.iter()
+                    .map(|zl| blockdev.open_zone(zl.0))
+                    .collect::<Vec<_>>();
 
-                blockdev.open_zone(first_disk_lba).join(zero_fut)
+                future::join_all(open_futs).join(zero_fut)
             }).collect();
 
         Box::new(future::join_all(futs).map(drop))
     }
 
     /// Read more than one whole stripe
-    fn read_at_multi(&self, mut buf: IoVecMut, lba: LbaT) -> Box<VdevFut> {
+    fn read_at_multi(&self, mut buf: IoVecMut, lba: LbaT,
+                     priority: PriorityClass) -> Box<VdevFut>
+    {
         let col_len = self.chunksize as usize * BYTES_PER_LBA;
-        let n = self.blockdevs.len();
+        let n = self.children.len();
         debug_assert_eq!(buf.len() % BYTES_PER_LBA, 0);
         let lbas = (buf.len() / BYTES_PER_LBA) as LbaT;
         let chunks = div_roundup(buf.len(), col_len);
@@ -451,7 +700,8 @@ impl VdevRaid {
                 let old = mem::replace(&mut sglists[disk], new);
                 let lba = start_lbas[disk];
                 futs.push(boxfut!(
-                    self.blockdevs[disk].readv_at(old, lba), _, _, 'static
+                    self.blockdev(disk).readv_at(old, lba, priority),
+                    _, _, 'static
                 ));
                 start_lbas[disk] = disk_lba;
             }
@@ -459,12 +709,13 @@ impl VdevRaid {
             next_lbas[disk as usize] = disk_lba + self.chunksize;
         }
 
-        futs.extend(multizip((self.blockdevs.iter(),
+        futs.extend(multizip((0..n,
                               sglists.into_iter(),
                               start_lbas.into_iter()))  // LCOV_EXCL_LINE   kcov false neg
             .filter(|&(_, _, lba)| lba != SENTINEL)
-            .map(|(blockdev, sglist, lba)| {
-                boxfut!(blockdev.readv_at(sglist, lba), _, _, 'static)
+            .map(|(disk, sglist, lba)| {
+                boxfut!(self.blockdev(disk).readv_at(sglist, lba, priority),
+                        _, _, 'static)
             })
         );
         let fut = future::join_all(futs);
@@ -477,7 +728,9 @@ impl VdevRaid {
     }
 
     /// Read a (possibly improper) subset of one stripe
-    fn read_at_one(&self, mut buf: IoVecMut, lba: LbaT) -> Box<VdevFut> {
+    fn read_at_one(&self, mut buf: IoVecMut, lba: LbaT,
+                   priority: PriorityClass) -> Box<VdevFut>
+    {
         let col_len = self.chunksize as usize * BYTES_PER_LBA;
         let f = self.codec.protection() as usize;
         let m = self.codec.stripesize() as usize - f as usize;
@@ -495,7 +748,8 @@ impl VdevRaid {
         };
         debug_assert!(data.len() <= m);
 
-        let fut = issue_1stripe_ops!(self, data, lba, false, read_at);
+        let fut = issue_1stripe_ops!(self, data, lba, false, read_at,
+                                     priority);
         // TODO: on error, some futures get cancelled.  Figure out how to clean
         // them up.
         // TODO: on error, record error statistics, possibly fault a drive,
@@ -504,14 +758,108 @@ impl VdevRaid {
         Box::new(fut.map(drop ))
     }
 
+    /// Read from a degraded array, reconstructing any data that lies on
+    /// missing disks.
+    ///
+    /// Each stripe is read in full, even if `buf` only covers part of it.
+    fn read_at_reconstruct(&self, mut buf: IoVecMut, lba: LbaT,
+                           priority: PriorityClass) -> Box<VdevFut>
+    {
+        let col_len = self.chunksize as usize * BYTES_PER_LBA;
+        let f = self.codec.protection() as usize;
+        let k = self.codec.stripesize() as usize;
+        let m = k - f;
+        let stripe_lbas = m as LbaT * self.chunksize;
+        let end_lba = lba + (buf.len() / BYTES_PER_LBA) as LbaT;
+        let stripes = lba / stripe_lbas..div_roundup(end_lba, stripe_lbas);
+
+        let futs = stripes.map(|stripe| {
+            // Byte offset of the requested data within this stripe
+            let offset = lba.saturating_sub(stripe * stripe_lbas) as usize *
+                BYTES_PER_LBA;
+            let len = cmp::min(buf.len(), m * col_len - offset);
+            let mut dest = buf.split_to(len);
+            let first_chunk = stripe * m as LbaT;
+            let locs = (0..k).map(|c| {
+                let id = if c < m {
+                    ChunkId::Data(first_chunk + c as LbaT)
+                } else {
+                    ChunkId::Parity(first_chunk, (c - m) as i16)
+                };
+                self.locator.id2loc(id)
+            }).collect::<Vec<_>>();
+            let mut erasures = FixedBitSet::with_capacity(k);
+            for (c, loc) in locs.iter().enumerate() {
+                if self.children[loc.disk as usize].is_missing() {
+                    erasures.insert(c);
+                }
+            }
+            let cols = (0..k).map(|_| DivBufShared::from(vec![0u8; col_len]))
+                .collect::<Vec<_>>();
+            // Read the lowest m surviving columns
+            let reads = (0..k).filter(|c| !erasures.contains(*c))
+                .take(m)
+                .map(|c| {
+                    let disk_lba = locs[c].offset * self.chunksize;
+                    self.blockdev(locs[c].disk as usize)
+                        .read_at(cols[c].try_mut().unwrap(), disk_lba, priority)
+                }).collect::<Vec<_>>();
+            let codec = self.codec.clone();
+            future::join_all(reads).map(move |_| {
+                if erasures.count_ones(..m) > 0 {
+                    let surviving = (0..k).filter(|c| !erasures.contains(*c))
+                        .take(m)
+                        .map(|c| cols[c].try_const().unwrap())
+                        .collect::<Vec<_>>();
+                    let mut missing = (0..m).filter(|c| erasures.contains(*c))
+                        .map(|c| cols[c].try_mut().unwrap())
+                        .collect::<Vec<_>>();
+                    let srefs = surviving.iter()
+                        .map(|col| col.as_ptr())
+                        .collect::<Vec<_>>();
+                    let mrefs = missing.iter_mut()
+                        .map(|col| col.as_mut_ptr())
+                        .collect::<Vec<_>>();
+                    codec.decode(col_len, &srefs, &mrefs, &erasures);
+                }
+                // Copy the requested portion of the data columns out
+                let mut skip = offset;
+                for col in cols.iter().take(m) {
+                    if skip >= col_len {
+                        skip -= col_len;
+                        continue;
+                    }
+                    let db = col.try_const().unwrap();
+                    let n = cmp::min(col_len - skip, dest.len());
+                    dest.split_to(n)[..].copy_from_slice(&db[skip..skip + n]);
+                    skip = 0;
+                }
+            })
+        }).collect::<Vec<_>>();
+        Box::new(future::join_all(futs).map(drop))
+    }
+
+    /// Does the inclusive LBA range include any data on a missing disk?
+    fn reads_missing(&self, lba: LbaT, end_lba: LbaT) -> bool {
+        if !self.children.iter().any(Child::is_missing) {
+            return false;
+        }
+        let start = ChunkId::Data(lba / self.chunksize);
+        let end = ChunkId::Data(end_lba / self.chunksize + 1);
+        self.locator.iter_data(start, end)
+            .any(|(_, loc)| self.children[loc.disk as usize].is_missing())
+    }
+
     /// Write two or more whole stripes
     #[allow(clippy::needless_range_loop)]
-    fn write_at_multi(&self, mut buf: IoVec, lba: LbaT) -> Box<VdevFut> {
+    fn write_at_multi(&self, mut buf: IoVec, lba: LbaT,
+                      priority: PriorityClass) -> Box<VdevFut>
+    {
         let col_len = self.chunksize as usize * BYTES_PER_LBA;
         let f = self.codec.protection() as usize;
         let k = self.codec.stripesize() as usize;
         let m = k - f as usize;
-        let n = self.blockdevs.len();
+        let n = self.children.len();
         let chunks = buf.len() / col_len;
         let stripes = chunks / m;
 
@@ -573,13 +921,15 @@ impl VdevRaid {
             sglists[loc.disk as usize].push(col);
         }
 
-        let bi = self.blockdevs.iter();
+        let ci = self.children.iter();
         let sgi = sglists.into_iter();
         let li = start_lbas.into_iter();
-        let futs = multizip((bi, sgi, li))
+        let futs = multizip((ci, sgi, li))
             .filter(|&(_, _, lba)| lba != SENTINEL)
-            .map(|(blockdev, sglist, lba)| blockdev.writev_at(sglist, lba))
-            .collect::<Vec<_>>();
+            .filter_map(|(child, sglist, lba)| {
+                child.as_present()
+                    .map(|bd| bd.writev_at(sglist, lba, priority))
+            }).collect::<Vec<_>>();
         let fut = future::join_all(futs);
         // TODO: on error, some futures get cancelled.  Figure out how to clean
         // them up.
@@ -590,7 +940,9 @@ impl VdevRaid {
     }
 
     /// Write exactly one stripe
-    fn write_at_one(&self, buf: IoVec, lba: LbaT) -> Box<VdevFut> {
+    fn write_at_one(&self, buf: IoVec, lba: LbaT, priority: PriorityClass)
+        -> Box<VdevFut>
+    {
         let col_len = self.chunksize as usize * BYTES_PER_LBA;
         let f = self.codec.protection() as usize;
         let m = self.codec.stripesize() as usize - f as usize;
@@ -614,8 +966,10 @@ impl VdevRaid {
         self.codec.encode(col_len, &drefs, &prefs);
         let pw = parity.into_iter().map(DivBufMut::freeze);
 
-        let data_fut = issue_1stripe_ops!(self, dcols, lba, false, write_at);
-        let parity_fut = issue_1stripe_ops!(self, pw, lba, true, write_at);
+        let data_fut = issue_1stripe_ops!(self, dcols, lba, false, write_at,
+                                          priority);
+        let parity_fut = issue_1stripe_ops!(self, pw, lba, true, write_at,
+                                            priority);
         // TODO: on error, some futures get cancelled.  Figure out how to clean
         // them up.
         // TODO: on error, record error statistics, and possibly fault a drive.
@@ -627,7 +981,8 @@ impl VdevRaid {
     /// This is mostly useful internally, for writing from the stripe buffer.
     /// It should not be used publicly.
     #[doc(hidden)]
-    pub fn writev_at_one(&self, buf: &[IoVec], lba: LbaT)
+    pub fn writev_at_one(&self, buf: &[IoVec], lba: LbaT,
+                         priority: PriorityClass)
         -> impl Future<Item = (), Error = Error>
     {
         let col_len = self.chunksize as usize * BYTES_PER_LBA;
@@ -663,8 +1018,10 @@ impl VdevRaid {
         self.codec.encodev(col_len, &dcols, &mut pcols);
         let pw = pcols.into_iter().map(DivBufMut::freeze);
 
-        let data_fut = issue_1stripe_ops!(self, dcols, lba, false, writev_at);
-        let parity_fut = issue_1stripe_ops!(self, pw, lba, true, write_at);
+        let data_fut = issue_1stripe_ops!(self, dcols, lba, false, writev_at,
+                                          priority);
+        let parity_fut = issue_1stripe_ops!(self, pw, lba, true, write_at,
+                                            priority);
         // TODO: on error, some futures get cancelled.  Figure out how to clean
         // them up.
         // TODO: on error, record error statistics, and possibly fault a drive.
@@ -689,23 +1046,53 @@ impl Vdev for VdevRaid {
     fn lba2zone(&self, lba: LbaT) -> Option<ZoneT> {
         let loc = self.locator.id2loc(ChunkId::Data(lba / self.chunksize));
         let disk_lba = loc.offset * self.chunksize;
-        let tentative = self.blockdevs[loc.disk as usize].lba2zone(disk_lba);
-        tentative?;
+        let tentative = self.zones.binary_search_by(|&(start, end)| {
+            if end <= disk_lba {
+                cmp::Ordering::Less
+            } else if start > disk_lba {
+                cmp::Ordering::Greater
+            } else {
+                cmp::Ordering::Equal
+            }
+        }).ok()? as ZoneT;
         // NB: this call to zone_limits is slow, but unfortunately necessary.
-        let limits = self.zone_limits(tentative.unwrap());
+        let limits = self.zone_limits(tentative);
         if lba >= limits.0 && lba < limits.1 {
-            tentative
+            Some(tentative)
         } else {
             None
         }
     }
 
+    /// Each open RAID zone keeps open at least one zone on every disk, and
+    /// more on disks whose zones are smaller than the RAID zones.
+    fn max_open_zones(&self) -> Option<ZoneT> {
+        self.blockdevs().filter_map(|blockdev| {
+            blockdev.max_open_zones().map(|max| {
+                let span = self.zones.iter()
+                    .map(|&limits| {
+                        VdevRaid::child_zones(blockdev, limits).count()
+                    }).max()
+                    .unwrap_or(1) as ZoneT;
+                cmp::max(1, max / span)
+            })
+        }).min()
+    }
+
+    /// Best number of queued commands for the whole `VdevRaid`.  Since each
+    /// `VdevBlock` tunes its own queue depth, this may change over time.
     fn optimum_queue_depth(&self) -> u32 {
-        self.optimum_queue_depth
+        // NB: the optimum queue depth should actually be a little higher for
+        // healthy reads than for writes or degraded reads.  This calculation
+        // computes the optimum for writes and degraded reads.
+        self.blockdevs()
+        .map(|bd| bd.optimum_queue_depth())
+        .sum::<u32>() / (self.codec.stripesize() as u32)
     }
 
     fn size(&self) -> LbaT {
-        let disk_size_in_chunks = self.blockdevs[0].size() / self.chunksize;
+        let disk_size = self.blockdevs().map(|bd| bd.size()).min().unwrap();
+        let disk_size_in_chunks = disk_size / self.chunksize;
         disk_size_in_chunks * self.locator.datachunks() *
             self.chunksize / LbaT::from(self.locator.depth())
     }
@@ -721,7 +1108,7 @@ impl Vdev for VdevRaid {
         }
         Box::new(
             future::join_all(
-                self.blockdevs.iter()
+                self.blockdevs()
                 .map(|bd| bd.sync_all())
                 .collect::<Vec<_>>()
             ).map(drop)   // LCOV_EXCL_LINE kcov false negative
@@ -744,7 +1131,7 @@ impl Vdev for VdevRaid {
     //
     // Outline:
     // 1) Determine the disks' zone limits.  This will be the same for all
-    //    disks.
+    //    disks, as computed by zone_map.
     // 2) Find both the lowest and the highest stripe that are fully contained
     //    within those limits, on any disk.
     // 3) Determine whether any of those stripes also include a chunk from
@@ -755,15 +1142,14 @@ impl Vdev for VdevRaid {
     fn zone_limits(&self, zone: ZoneT) -> (LbaT, LbaT) {
         let m = (self.codec.stripesize() - self.codec.protection()) as LbaT;
 
-        // 1) All blockdevs must have the same zone map, so we only need to do
-        //    the zone_limits call once.
-        let (disk_lba_b, disk_lba_e) = self.blockdevs[0].zone_limits(zone);
+        // 1) The RAID zone uses the same range of LBAs on every disk
+        let (disk_lba_b, disk_lba_e) = self.zones[zone as usize];
         let disk_chunk_b = div_roundup(disk_lba_b, self.chunksize);
         let disk_chunk_e = disk_lba_e / self.chunksize - 1; //inclusive endpoint
 
         let endpoint_lba = |boundary_chunk, is_highend| {
             // 2) Find the lowest and highest stripe
-            let stripes = (0..self.blockdevs.len()).map(|i| {
+            let stripes = (0..self.children.len()).map(|i| {
                 let cid = self.locator.loc2id(Chunkloc::new(i as i16,
                                                             boundary_chunk));
                 cid.address() / m
@@ -806,9 +1192,10 @@ impl Vdev for VdevRaid {
     }
 
     // The RAID transform does not increase the number of zones; it just makes
-    // them bigger
+    // them bigger.  It may decrease the number, if the disks' zones don't
+    // share boundaries.
     fn zones(&self) -> ZoneT {
-        self.blockdevs[0].zones()
+        self.zones.len() as ZoneT
     }
 }
 
@@ -816,9 +1203,10 @@ impl VdevRaidApi for VdevRaid {
     fn erase_zone(&self, zone: ZoneT) -> BoxVdevFut {
         assert!(!self.stripe_buffers.borrow().contains_key(&zone),
             "Tried to erase an open zone");
-        let (start, end) = self.blockdevs[0].zone_limits(zone);
-        let futs : Vec<_> = self.blockdevs.iter().map(|blockdev| {
-            blockdev.erase_zone(start, end - 1)
+        let disk_limits = self.zones[zone as usize];
+        let futs : Vec<_> = self.blockdevs().flat_map(|blockdev| {
+            VdevRaid::child_zones(blockdev, disk_limits)
+            .map(move |(start, end)| blockdev.erase_zone(start, end - 1))
         }).collect();
         Box::new(future::join_all(futs).map(drop))
     }
@@ -827,7 +1215,7 @@ impl VdevRaidApi for VdevRaid {
     // StripeBuffer.
     fn finish_zone(&self, zone: ZoneT) -> BoxVdevFut {
         let mut sbs = self.stripe_buffers.borrow_mut();
-        let nfuts = self.blockdevs.len() + 1;
+        let nfuts = self.children.len() + 1;
         let mut futs: Vec<_> = Vec::with_capacity(nfuts);
         let sbfut = {
             let sb = sbs.get_mut(&zone).expect("Can't finish a closed zone");
@@ -835,15 +1223,18 @@ impl VdevRaidApi for VdevRaid {
                 sb.pad();
                 let lba = sb.lba();
                 let sgl = sb.pop();
-                Box::new(self.writev_at_one(&sgl, lba))
+                Box::new(self.writev_at_one(&sgl, lba, PriorityClass::Normal))
             } else {    // LCOV_EXCL_LINE kcov false negative
                 boxfut!(future::ok::<(), Error>(()), _, _, 'static)
             }
         };
-        let (start, end) = self.blockdevs[0].zone_limits(zone);
+        let disk_limits = self.zones[zone as usize];
         futs.extend(
-            self.blockdevs.iter()
-            .map(|blockdev| blockdev.finish_zone(start, end - 1))
+            self.blockdevs()
+            .flat_map(|blockdev| {
+                VdevRaid::child_zones(blockdev, disk_limits)
+                .map(move |(start, end)| blockdev.finish_zone(start, end - 1))
+            })
         );
 
         assert!(sbs.remove(&zone).is_some());
@@ -869,17 +1260,30 @@ impl VdevRaidApi for VdevRaid {
                     let pad_lbas = sb.pad();
                     let lba = sb.lba();
                     let sgl = sb.pop();
-                    (pad_lbas, Box::new(self.writev_at_one(&sgl, lba)))
+                    let fut = self.writev_at_one(&sgl, lba,
+                                                 PriorityClass::Normal);
+                    (pad_lbas, Box::new(fut))
                 }
             }
         }
     }
 
+    fn iostats(&self) -> VdevStats {
+        let children = self.children.iter()
+            .map(|child| match child {
+                Child::Present(bd) => bd.iostats(),
+                Child::Missing(uuid) => VdevStats::faulted(*uuid)
+            }).collect();
+        VdevStats::aggregate(self.uuid, children)
+    }
+
     fn open_zone(&self, zone: ZoneT) -> BoxVdevFut {
         self.open_zone_priv(zone, 0)
     }
 
-    fn read_at(&self, mut buf: IoVecMut, lba: LbaT) -> Box<VdevFut> {
+    fn read_at(&self, mut buf: IoVecMut, lba: LbaT, priority: PriorityClass)
+        -> Box<VdevFut>
+    {
         let f = self.codec.protection();
         let m = (self.codec.stripesize() - f) as LbaT;
         assert_eq!(buf.len() % BYTES_PER_LBA, 0, "reads must be LBA-aligned");
@@ -940,22 +1344,34 @@ impl VdevRaidApi for VdevRaid {
         };
         let start_stripe = lba / (self.chunksize * m as LbaT);
         let end_stripe = end_lba / (self.chunksize * m);
-        if start_stripe == end_stripe {
-            self.read_at_one(buf2, lba)
+        if self.reads_missing(lba, end_lba) {
+            self.read_at_reconstruct(buf2, lba, priority)
+        } else if start_stripe == end_stripe {
+            self.read_at_one(buf2, lba, priority)
         } else {
-            self.read_at_multi(buf2, lba)
+            self.read_at_multi(buf2, lba, priority)
         }
     }
 
-    fn read_spacemap(&self, buf: IoVecMut, idx: u32) -> BoxVdevFut {
-        Box::new(self.blockdevs[0].read_spacemap(buf, idx))
-    }
-
     fn reopen_zone(&self, zone: ZoneT, allocated: LbaT) -> BoxVdevFut {
         self.open_zone_priv(zone, allocated)
     }
 
-    fn write_at(&self, buf: IoVec, zone: ZoneT, mut lba: LbaT) -> BoxVdevFut {
+    fn set_limits(&self, limits: IoLimits) {
+        for blockdev in self.blockdevs() {
+            blockdev.set_limits(limits);
+        }
+    }
+
+    fn set_scheduler(&self, policy: SchedulerPolicy) {
+        for blockdev in self.blockdevs() {
+            blockdev.set_scheduler(policy);
+        }
+    }
+
+    fn write_at(&self, buf: IoVec, zone: ZoneT, mut lba: LbaT,
+                priority: PriorityClass) -> BoxVdevFut
+    {
         let col_len = self.chunksize as usize * BYTES_PER_LBA;
         let f = self.codec.protection() as usize;
         let m = self.codec.stripesize() as usize - f as usize;
@@ -980,7 +1396,8 @@ impl VdevRaidApi for VdevRaid {
                 let stripe_lba = stripe_buffer.lba();
                 let sglist = stripe_buffer.pop();
                 lba += ((buflen - buf2.len()) / BYTES_PER_LBA) as LbaT;
-                futs.push(Box::new(self.writev_at_one(&sglist, stripe_lba)));
+                futs.push(Box::new(self.writev_at_one(&sglist, stripe_lba,
+                                                      priority)));
             }
             buf2
         } else {  // LCOV_EXCL_LINE kcov false negative
@@ -998,22 +1415,27 @@ impl VdevRaidApi for VdevRaid {
                     // completing a stripe
                     let slba = stripe_buffer.lba();
                     let sglist = stripe_buffer.pop();
-                    futs.push(Box::new(self.writev_at_one(&sglist, slba)));
+                    futs.push(Box::new(self.writev_at_one(&sglist, slba,
+                                                          priority)));
                 }
                 debug_assert!(!stripe_buffer.is_full());
                 debug_assert!(buf4.is_empty());
             }
             futs.push(if nstripes == 1 {
-                Box::new(self.write_at_one(writable_buf, lba))
+                Box::new(self.write_at_one(writable_buf, lba, priority))
             } else {
-                Box::new(self.write_at_multi(writable_buf, lba))
+                Box::new(self.write_at_multi(writable_buf, lba, priority))
             });
         }
         Box::new(future::join_all(futs).map(drop))
     }
 
     fn write_label(&self, mut labeller: LabelWriter) -> BoxVdevFut {
-        let children_uuids = self.blockdevs.iter().map(|bd| bd.uuid())
+        let children_uuids = self.children.iter().map(Child::uuid)
+            .collect::<Vec<_>>();
+        let faulted = self.children.iter()
+            .filter(|c| c.is_missing())
+            .map(Child::uuid)
             .collect::<Vec<_>>();
         let raid_label = Label {
             uuid: self.uuid,
@@ -1021,27 +1443,17 @@ impl VdevRaidApi for VdevRaid {
             disks_per_stripe: self.locator.stripesize(),
             redundancy: self.locator.protection(),
             layout_algorithm: self.layout_algorithm,
-            children: children_uuids
+            children: children_uuids,
+            faulted,
+            zones: VdevRaid::encode_zone_map(&self.zones)
         };
         let label = super::Label::Raid(raid_label);
-        labeller.serialize(&label).unwrap();
-        let futs = self.blockdevs.iter().map(|bd| {
+        label.write(&mut labeller).unwrap();
+        let futs = self.blockdevs().map(|bd| {
             bd.write_label(labeller.clone())
         }).collect::<Vec<_>>();
         Box::new(future::join_all(futs).map(drop))
     }
-
-    // Allow &Vec arguments so we can clone them.
-    #[allow(clippy::ptr_arg)]
-    fn write_spacemap(&self, sglist: &SGList, idx: u32, block: LbaT)
-        -> BoxVdevFut
-    {
-        let futs = self.blockdevs.iter().map(|bd| {
-            bd.write_spacemap(sglist.clone(), idx, block)
-        }).collect::<Vec<_>>();
-        Box::new(future::join_all(futs).map(drop))
-    }
-
 }
 
 // LCOV_EXCL_START
@@ -1211,6 +1623,7 @@ use super::*;
 use futures::future;
 use galvanic_test::*;
 use mockall::predicate::*;
+use std::sync::{Arc, Mutex};
 
 // pet kcov
 #[test]
@@ -1221,7 +1634,9 @@ fn debug() {
         disks_per_stripe: 2,
         redundancy: 1,
         layout_algorithm: LayoutAlgorithm::PrimeS,
-        children: vec![Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()]
+        children: vec![Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()],
+        faulted: Vec::new(),
+        zones: Vec::new()
     };
     format!("{:?}", label);
 }
@@ -1242,6 +1657,8 @@ test_suite! {
                 let mut mock = VdevBlock::default();
                 mock.expect_size()
                     .return_const(262_144u64);
+                mock.expect_zones()
+                    .return_const(2u32);
                 mock.expect_lba2zone()
                     .with(eq(0))
                     .return_const(None);
@@ -1381,6 +1798,14 @@ fn read_at_one_stripe() {
     let mut m0 = VdevBlock::default();
     m0.expect_size()
         .return_const(262_144u64);
+    m0.expect_zones()
+        .return_const(2u32);
+    m0.expect_lba2zone()
+        .with(eq(65_536))
+        .return_const(Some(1));
+    m0.expect_lba2zone()
+        .with(eq(131_071))
+        .return_const(Some(1));
     m0.expect_open_zone()
         .once()
         .with(eq(65536))
@@ -1398,6 +1823,14 @@ fn read_at_one_stripe() {
     let mut m1 = VdevBlock::default();
     m1.expect_size()
         .return_const(262_144u64);
+    m1.expect_zones()
+        .return_const(2u32);
+    m1.expect_lba2zone()
+        .with(eq(65_536))
+        .return_const(Some(1));
+    m1.expect_lba2zone()
+        .with(eq(131_071))
+        .return_const(Some(1));
     m1.expect_open_zone()
         .once()
         .with(eq(65536))
@@ -1412,15 +1845,24 @@ fn read_at_one_stripe() {
         .return_const((65536, 131_072));
     m1.expect_read_at()
         .once()
-        .withf(|buf, lba| {
+        .withf(|buf, lba, priority| {
             buf.len() == CHUNKSIZE as usize * BYTES_PER_LBA
                 && *lba == 65536
-        }).return_once(|_, _|  Box::new( future::ok::<(), Error>(())));
+                && *priority == PriorityClass::Background
+        }).return_once(|_, _, _|  Box::new( future::ok::<(), Error>(())));
     blockdevs.push(m1);
 
     let mut m2 = VdevBlock::default();
     m2.expect_size()
         .return_const(262_144u64);
+    m2.expect_zones()
+        .return_const(2u32);
+    m2.expect_lba2zone()
+        .with(eq(65_536))
+        .return_const(Some(1));
+    m2.expect_lba2zone()
+        .with(eq(131_071))
+        .return_const(Some(1));
     m2.expect_open_zone()
         .once()
         .with(eq(65536))
@@ -1435,10 +1877,11 @@ fn read_at_one_stripe() {
         .return_const((65536, 131_072));
     m2.expect_read_at()
         .once()
-        .withf(|buf, lba| {
+        .withf(|buf, lba, priority| {
             buf.len() == CHUNKSIZE as usize * BYTES_PER_LBA
                 && *lba == 65536
-        }).return_once(|_, _|  Box::new( future::ok::<(), Error>(())));
+                && *priority == PriorityClass::Background
+        }).return_once(|_, _, _|  Box::new( future::ok::<(), Error>(())));
     blockdevs.push(m2);
 
     let vdev_raid = VdevRaid::new(CHUNKSIZE, k, f,
@@ -1448,7 +1891,212 @@ fn read_at_one_stripe() {
     let dbs = DivBufShared::from(vec![0u8; 16384]);
     let rbuf = dbs.try_mut().unwrap();
     vdev_raid.open_zone(1).wait().unwrap();
-    vdev_raid.read_at(rbuf, 131_072).wait().unwrap();
+    vdev_raid.read_at(rbuf, 131_072, PriorityClass::Background).wait()
+        .unwrap();
+}
+
+// Reading from a degraded array should reconstruct the missing disk's data
+// from the surviving data and parity
+#[test]
+fn read_at_reconstruct() {
+    let k = 3;
+    let f = 1;
+    const CHUNKSIZE: LbaT = 2;
+    let col_len = CHUNKSIZE as usize * BYTES_PER_LBA;
+    // Read the whole of the 10th stripe
+    let stripe = 10;
+    let lba = stripe * 2 * CHUNKSIZE;
+
+    let d0 = vec![1u8; col_len];
+    let d1 = vec![2u8; col_len];
+    let mut p = vec![0u8; col_len];
+    let codec = Codec::new(k as u32, f as u32);
+    codec.encode(col_len, &[d0.as_ptr(), d1.as_ptr()], &[p.as_mut_ptr()]);
+    let locator = PrimeS::new(3, k, f);
+    let loc0 = locator.id2loc(ChunkId::Data(stripe * 2));
+    let loc1 = locator.id2loc(ChunkId::Data(stripe * 2 + 1));
+    let locp = locator.id2loc(ChunkId::Parity(stripe * 2, 0));
+
+    let bd = |contents: Vec<u8>, disk_lba: LbaT| {
+        let mut bd = VdevBlock::default();
+        bd.expect_size().return_const(262_144u64);
+        bd.expect_zones()
+            .return_const(1u32);
+        bd.expect_zone_limits()
+            .with(eq(0))
+            .return_const((1, 60_000));
+        bd.expect_read_at()
+            .withf(move |buf, lba, _| {
+                buf.len() == col_len && *lba == disk_lba
+            }).returning(move |mut buf, _, _| {
+                buf[..].copy_from_slice(&contents[..]);
+                Box::new(future::ok::<(), Error>(()))
+            });
+        bd
+    };
+    let mut children = vec![None, None, None];
+    children[loc0.disk as usize] = Some(Child::Missing(Uuid::new_v4()));
+    children[loc1.disk as usize] = Some(Child::Present(
+            bd(d1.clone(), loc1.offset * CHUNKSIZE)));
+    children[locp.disk as usize] = Some(Child::Present(
+            bd(p, locp.offset * CHUNKSIZE)));
+    let children = children.into_iter()
+        .map(Option::unwrap)
+        .collect::<Vec<_>>();
+
+    let vdev_raid = VdevRaid::with_children(CHUNKSIZE, k, f, Uuid::new_v4(),
+                                            LayoutAlgorithm::PrimeS,
+                                            children.into_boxed_slice(), None);
+    let dbs = DivBufShared::from(vec![0u8; 2 * col_len]);
+    let rbuf = dbs.try_mut().unwrap();
+    vdev_raid.read_at(rbuf, lba, PriorityClass::Normal).wait().unwrap();
+    let db = dbs.try_const().unwrap();
+    assert_eq!(&db[..col_len], &d0[..]);
+    assert_eq!(&db[col_len..], &d1[..]);
+
+    // A read that begins mid-chunk should get just the part it asked for
+    let dbs = DivBufShared::from(vec![0u8; col_len]);
+    let rbuf = dbs.try_mut().unwrap();
+    vdev_raid.read_at(rbuf, lba + 1, PriorityClass::Normal).wait()
+        .unwrap();
+    let db = dbs.try_const().unwrap();
+    assert_eq!(&db[..BYTES_PER_LBA], &d0[..BYTES_PER_LBA]);
+    assert_eq!(&db[BYTES_PER_LBA..], &d1[..BYTES_PER_LBA]);
+}
+
+// VdevRaid's statistics should be the sum of its children's
+#[test]
+fn iostats() {
+    let k = 3;
+    let f = 1;
+    const CHUNKSIZE: LbaT = 2;
+    let zl0 = (1, 60_000);
+    let uuid = Uuid::new_v4();
+
+    let mut blockdevs = Vec::<VdevBlock>::default();
+
+    let bd = |read_ops| {
+        let mut bd = VdevBlock::default();
+        bd.expect_size().return_const(262_144u64);
+        bd.expect_zones()
+            .return_const(1u32);
+        bd.expect_zone_limits()
+            .with(eq(0))
+            .return_const(zl0);
+        bd.expect_optimum_queue_depth()
+            .return_const(10u32);
+        bd.expect_iostats()
+            .returning(move || {
+                let mut stats = crate::common::iostat::IoStats::default();
+                stats.read_ops = read_ops;
+                VdevStats::leaf(Uuid::new_v4(), stats)
+            });
+        bd
+    };
+
+    blockdevs.push(bd(1));
+    blockdevs.push(bd(2));
+    blockdevs.push(bd(3));
+
+    let vdev_raid = VdevRaid::new(CHUNKSIZE, k, f, uuid,
+                                  LayoutAlgorithm::PrimeS,
+                                  blockdevs.into_boxed_slice());
+    let vs = vdev_raid.iostats();
+    assert_eq!(vs.uuid, uuid);
+    assert_eq!(vs.stats.read_ops, 6);
+    assert_eq!(vs.children.len(), 3);
+    assert_eq!(vs.children[1].stats.read_ops, 2);
+}
+
+// A child that was faulted when the label was written must stay faulted, even
+// if it comes back
+#[test]
+fn open_faulted() {
+    let zl0 = (1, 60_000);
+    let uuids = vec![Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+    let label = Label {
+        uuid: Uuid::new_v4(),
+        chunksize: 2,
+        disks_per_stripe: 3,
+        redundancy: 1,
+        layout_algorithm: LayoutAlgorithm::PrimeS,
+        children: uuids.clone(),
+        faulted: vec![uuids[1]],
+        zones: Vec::new()
+    };
+    let blocks = uuids.iter().map(|&uuid| {
+        let mut bd = VdevBlock::default();
+        bd.expect_size().return_const(262_144u64);
+        bd.expect_zones()
+            .return_const(1u32);
+        bd.expect_zone_limits()
+            .with(eq(0))
+            .return_const(zl0);
+        bd.expect_iostats()
+            .returning(move || VdevStats::leaf(uuid, Default::default()));
+        (uuid, bd)
+    }).collect::<BTreeMap<_, _>>();
+
+    let vdev_raid = VdevRaid::open(label, blocks);
+    let vs = vdev_raid.iostats();
+    assert!(!vs.children[0].faulted);
+    assert!(vs.children[1].faulted);
+    assert_eq!(vs.children[1].uuid, uuids[1]);
+    assert!(!vs.children[2].faulted);
+}
+
+// The label should list every missing child as faulted
+#[test]
+fn write_label_faulted() {
+    let k = 3;
+    let f = 1;
+    const CHUNKSIZE: LbaT = 2;
+    let zl0 = (1, 60_000);
+    let missing = Uuid::new_v4();
+    let present = Uuid::new_v4();
+    let labels = Arc::new(Mutex::new(Vec::new()));
+
+    let bd = |uuid| {
+        let labels2 = labels.clone();
+        let mut bd = VdevBlock::default();
+        bd.expect_size().return_const(262_144u64);
+        bd.expect_zones()
+            .return_const(1u32);
+        bd.expect_zone_limits()
+            .with(eq(0))
+            .return_const(zl0);
+        bd.expect_uuid().return_const(uuid);
+        bd.expect_write_label()
+            .times(1)
+            .returning(move |labeller| {
+                let v = labeller.into_sglist().unwrap().iter()
+                    .fold(Vec::new(), |mut acc, db| {
+                        acc.extend_from_slice(&db[..]);
+                        acc
+                    });
+                labels2.lock().unwrap().push(v);
+                Box::new(future::ok::<(), Error>(()))
+            });
+        bd
+    };
+    let children = vec![
+        Child::Present(bd(present)),
+        Child::Missing(missing),
+        Child::Present(bd(Uuid::new_v4())),
+    ];
+    let vdev_raid = VdevRaid::with_children(CHUNKSIZE, k, f, Uuid::new_v4(),
+                                            LayoutAlgorithm::PrimeS,
+                                            children.into_boxed_slice(), None);
+    let labeller = LabelWriter::new(0, TxgT::from(0));
+    vdev_raid.write_label(labeller).wait().unwrap();
+
+    let mut labels = labels.lock().unwrap();
+    assert_eq!(labels.len(), 2);
+    let dbs = DivBufShared::from(labels.pop().unwrap());
+    let mut reader = LabelReader::from_dbs(dbs).unwrap();
+    let label = super::super::Label::read(&mut reader).unwrap();
+    assert!(label.is_faulted(&missing));
+    assert!(!label.is_faulted(&present));
 }
 
 #[test]
@@ -1463,6 +2111,8 @@ fn sync_all() {
     let bd = || {
         let mut bd = VdevBlock::default();
         bd.expect_size().return_const(262_144u64);
+        bd.expect_zones()
+            .return_const(1u32);
         bd.expect_zone_limits()
             .with(eq(0))
             .return_const(zl0);
@@ -1508,6 +2158,11 @@ fn sync_all_unflushed() {
             .return_const(Some(1));
         bd.expect_size()
             .return_const(262_144u64);
+        bd.expect_zones()
+            .return_const(2u32);
+        bd.expect_lba2zone()
+            .with(eq(119_999))
+            .return_const(Some(1));
         bd.expect_zone_limits()
             .with(eq(0))
             .return_const(zl0);
@@ -1542,7 +2197,7 @@ fn sync_all_unflushed() {
     let dbs = DivBufShared::from(vec![1u8; 4096]);
     let wbuf = dbs.try_const().unwrap();
     vdev_raid.open_zone(1).wait().unwrap();
-    vdev_raid.write_at(wbuf, 1, 120_000).wait().unwrap();
+    vdev_raid.write_at(wbuf, 1, 120_000, PriorityClass::Normal).wait().unwrap();
     // Don't flush zone 1 before syncing.  Syncing should panic
     vdev_raid.sync_all().wait().unwrap();
 }
@@ -1560,6 +2215,11 @@ fn write_at_one_stripe() {
     let mut m0 = VdevBlock::default();
     m0.expect_size()
         .return_const(262_144u64);
+    m0.expect_zones()
+        .return_const(2u32);
+    m0.expect_lba2zone()
+        .with(eq(131_071))
+        .return_const(Some(1));
     m0.expect_lba2zone()
         .with(eq(65536))
         .return_const(Some(1));
@@ -1577,15 +2237,21 @@ fn write_at_one_stripe() {
         .return_const((65536, 131_072));
     m0.expect_write_at()
         .once()
-        .withf(|buf, lba|
+        .withf(|buf, lba, priority|
                buf.len() == CHUNKSIZE as usize * BYTES_PER_LBA
                && *lba == 65536
-        ).return_once(|_, _| Box::new( future::ok::<(), Error>(())));
+               && *priority == PriorityClass::Background
+        ).return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
 
     blockdevs.push(m0);
     let mut m1 = VdevBlock::default();
     m1.expect_size()
         .return_const(262_144u64);
+    m1.expect_zones()
+        .return_const(2u32);
+    m1.expect_lba2zone()
+        .with(eq(131_071))
+        .return_const(Some(1));
     m1.expect_lba2zone()
         .with(eq(65536))
         .return_const(Some(1));
@@ -1603,15 +2269,21 @@ fn write_at_one_stripe() {
         .return_const((65536, 131_072));
     m1.expect_write_at()
         .once()
-        .withf(|buf, lba|
+        .withf(|buf, lba, priority|
             buf.len() == CHUNKSIZE as usize * BYTES_PER_LBA
             && *lba == 65536
-        ).return_once(|_, _| Box::new( future::ok::<(), Error>(())));
+            && *priority == PriorityClass::Background
+        ).return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
 
     blockdevs.push(m1);
     let mut m2 = VdevBlock::default();
     m2.expect_size()
         .return_const(262_144u64);
+    m2.expect_zones()
+        .return_const(2u32);
+    m2.expect_lba2zone()
+        .with(eq(131_071))
+        .return_const(Some(1));
     m2.expect_lba2zone()
         .with(eq(65536))
         .return_const(Some(1));
@@ -1629,10 +2301,11 @@ fn write_at_one_stripe() {
         .return_const((65536, 131_072));
     m2.expect_write_at()
         .once()
-        .withf(|buf, lba|
+        .withf(|buf, lba, priority|
             buf.len() == CHUNKSIZE as usize * BYTES_PER_LBA
             && *lba == 65536
-        ).return_once(|_, _| Box::new( future::ok::<(), Error>(())));
+            && *priority == PriorityClass::Background
+        ).return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
     blockdevs.push(m2);
 
     let vdev_raid = VdevRaid::new(CHUNKSIZE, k, f,
@@ -1642,7 +2315,8 @@ fn write_at_one_stripe() {
     let dbs = DivBufShared::from(vec![0u8; 16384]);
     let wbuf = dbs.try_const().unwrap();
     vdev_raid.open_zone(1).wait().unwrap();
-    vdev_raid.write_at(wbuf, 1, 131_072).wait().unwrap();
+    vdev_raid.write_at(wbuf, 1, 131_072, PriorityClass::Background).wait()
+        .unwrap();
 }
 
 // Partially written stripes should be flushed by flush_zone
@@ -1660,6 +2334,11 @@ fn write_at_and_flush_zone() {
         let mut bd = VdevBlock::default();
         bd.expect_size()
             .return_const(262_144u64);
+        bd.expect_zones()
+            .return_const(2u32);
+        bd.expect_lba2zone()
+            .with(eq(119_999))
+            .return_const(Some(1));
         bd.expect_lba2zone()
             .with(eq(60_000))
             .return_const(Some(1));
@@ -1681,34 +2360,34 @@ fn write_at_and_flush_zone() {
     let mut bd0 = bd();
     bd0.expect_writev_at()
         .once()
-        .withf(|buf, lba|
+        .withf(|buf, lba, _|
             // The first segment is user data
             buf[0][..] == vec![1u8; BYTES_PER_LBA][..] &&
             // Later segments are zero-fill from flush_zone
             buf[1][..] == vec![0u8; BYTES_PER_LBA][..] &&
             *lba == 60_000
-        ).return_once(|_, _| Box::new( future::ok::<(), Error>(())));
+        ).return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
 
     let mut bd1 = bd();
     // This write is from the zero-fill
     bd1.expect_writev_at()
         .once()
-        .withf(|buf, lba|
+        .withf(|buf, lba, _|
             buf.len() == 1 &&
             buf[0][..] == vec![0u8; 2 * BYTES_PER_LBA][..] &&
             *lba == 60_000
-    ).return_once(|_, _| Box::new( future::ok::<(), Error>(())));
+    ).return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
 
     // This write is generated parity
     let mut bd2 = bd();
     bd2.expect_write_at()
         .once()
-        .withf(|buf, lba|
+        .withf(|buf, lba, _|
             // single disk parity is a simple XOR
             buf[0..4096] == vec![1u8; BYTES_PER_LBA][..] &&
             buf[4096..8192] == vec![0u8; BYTES_PER_LBA][..] &&
             *lba == 60_000
-    ).return_once(|_, _| Box::new( future::ok::<(), Error>(())));
+    ).return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
 
     blockdevs.push(bd0);
     blockdevs.push(bd1);
@@ -1721,7 +2400,7 @@ fn write_at_and_flush_zone() {
     let dbs = DivBufShared::from(vec![1u8; 4096]);
     let wbuf = dbs.try_const().unwrap();
     vdev_raid.open_zone(1).wait().unwrap();
-    vdev_raid.write_at(wbuf, 1, 120_000).wait().unwrap();
+    vdev_raid.write_at(wbuf, 1, 120_000, PriorityClass::Normal).wait().unwrap();
     vdev_raid.flush_zone(1).1.wait().unwrap();
 }
 
@@ -1742,6 +2421,14 @@ fn erase_zone() {
         let mut bd = VdevBlock::default();
         bd.expect_size()
             .return_const(262_144u64);
+        bd.expect_zones()
+            .return_const(2u32);
+        bd.expect_lba2zone()
+            .with(eq(1))
+            .return_const(Some(0));
+        bd.expect_lba2zone()
+            .with(eq(59_999))
+            .return_const(Some(0));
         bd.expect_lba2zone()
             .with(eq(60_000))
             .return_const(Some(1));
@@ -1774,6 +2461,189 @@ fn erase_zone() {
     vdev_raid.erase_zone(0).wait().unwrap();
 }
 
+// Disks whose zones have different sizes.  Each RAID zone should span as many
+// of each disk's zones as necessary to reach a boundary common to all disks.
+#[test]
+fn mismatched_zones() {
+    use mockall::PredicateBooleanExt;
+
+    let k = 3;
+    let f = 1;
+    const CHUNKSIZE: LbaT = 2;
+
+    let bd = |zls: Vec<(LbaT, LbaT)>, max_open_zones: Option<ZoneT>| {
+        let mut bd = VdevBlock::default();
+        bd.expect_size()
+            .return_const(131_072u64);
+        bd.expect_max_open_zones()
+            .return_const(max_open_zones);
+        bd.expect_zones()
+            .return_const(zls.len() as ZoneT);
+        for (i, &zl) in zls.iter().enumerate() {
+            bd.expect_zone_limits()
+                .with(eq(i as ZoneT))
+                .return_const(zl);
+            bd.expect_lba2zone()
+                .with(ge(zl.0).and(lt(zl.1)))
+                .return_const(Some(i as ZoneT));
+            if zl.1 <= 65536 {
+                // Erasing RAID zone 0 should erase all of these disk zones
+                bd.expect_erase_zone()
+                    .with(eq(zl.0), eq(zl.1 - 1))
+                    .once()
+                    .return_once(|_, _| Box::new(future::ok::<(), Error>(())));
+            }
+        }
+        bd.expect_optimum_queue_depth()
+            .return_const(10u32);
+        bd
+    };
+
+    let mut blockdevs = Vec::<VdevBlock>::new();
+    blockdevs.push(bd(vec![(1, 32768), (32768, 65536), (65536, 131_072)],
+                      Some(8)));
+    blockdevs.push(bd(vec![(1, 65536), (65536, 98304), (98304, 131_072)],
+                      None));
+    blockdevs.push(bd(vec![(1, 65536), (65536, 131_072)], Some(3)));
+
+    let vdev_raid = VdevRaid::new(CHUNKSIZE, k, f,
+                                  Uuid::new_v4(),
+                                  LayoutAlgorithm::PrimeS,
+                                  blockdevs.into_boxed_slice());
+    assert_eq!(vdev_raid.zones(), 2);
+    let zl0 = vdev_raid.zone_limits(0);
+    let zl1 = vdev_raid.zone_limits(1);
+    assert!(zl0.1 <= zl1.0);
+    assert_eq!(vdev_raid.lba2zone(zl0.1 - 1), Some(0));
+    assert_eq!(vdev_raid.lba2zone(zl1.0), Some(1));
+    assert_eq!(vdev_raid.lba2zone(zl1.1 - 1), Some(1));
+    // The first disk allows 8 open zones, but each RAID zone may hold two of
+    // them open.  The third disk allows only 3.
+    assert_eq!(vdev_raid.max_open_zones(), Some(3));
+    vdev_raid.erase_zone(0).wait().unwrap();
+}
+
+// Opening a RAID degraded must use the zone map from the label, not one
+// computed from the children that happen to be present
+#[test]
+fn mismatched_zones_degraded() {
+    let zls = vec![(1, 32768), (32768, 65536), (65536, 131_072)];
+    let uuids = vec![Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+    let label = Label {
+        uuid: Uuid::new_v4(),
+        chunksize: 2,
+        disks_per_stripe: 3,
+        redundancy: 1,
+        layout_algorithm: LayoutAlgorithm::PrimeS,
+        children: uuids.clone(),
+        faulted: Vec::new(),
+        // The missing child had zones (1, 65536) and (65536, 131072)
+        zones: vec![(1, 65535, 1), (65536, 65536, 1)]
+    };
+    let blocks = uuids[0..2].iter().map(|&uuid| {
+        let mut bd = VdevBlock::default();
+        bd.expect_size().return_const(131_072u64);
+        bd.expect_zones()
+            .return_const(zls.len() as ZoneT);
+        for (i, &zl) in zls.iter().enumerate() {
+            bd.expect_zone_limits()
+                .with(eq(i as ZoneT))
+                .return_const(zl);
+        }
+        bd.expect_optimum_queue_depth()
+            .return_const(10u32);
+        (uuid, bd)
+    }).collect::<BTreeMap<_, _>>();
+
+    let vdev_raid = VdevRaid::open(label, blocks);
+    // The present children alone would yield three zones
+    assert_eq!(vdev_raid.zones(), 2);
+    let zl0 = vdev_raid.zone_limits(0);
+    let zl1 = vdev_raid.zone_limits(1);
+    assert!(zl0.1 <= zl1.0);
+    assert_eq!(vdev_raid.lba2zone(zl0.1 - 1), Some(0));
+    assert_eq!(vdev_raid.lba2zone(zl1.0), Some(1));
+}
+
+// If the children have grown since the label was written, then the RAID
+// should grow with them
+#[test]
+fn open_grown() {
+    let zls = vec![(1, 65536), (65536, 131_072), (131_072, 196_608)];
+    let uuids = vec![Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+    let label = Label {
+        uuid: Uuid::new_v4(),
+        chunksize: 2,
+        disks_per_stripe: 3,
+        redundancy: 1,
+        layout_algorithm: LayoutAlgorithm::PrimeS,
+        children: uuids.clone(),
+        faulted: Vec::new(),
+        // The last zone used to end at the old end of the disks
+        zones: vec![(1, 65535, 1), (65536, 32768, 1)]
+    };
+    let blocks = uuids.iter().map(|&uuid| {
+        let mut bd = VdevBlock::default();
+        bd.expect_size().return_const(196_608u64);
+        bd.expect_zones()
+            .return_const(zls.len() as ZoneT);
+        for (i, &zl) in zls.iter().enumerate() {
+            bd.expect_zone_limits()
+                .with(eq(i as ZoneT))
+                .return_const(zl);
+        }
+        (uuid, bd)
+    }).collect::<BTreeMap<_, _>>();
+
+    let vdev_raid = VdevRaid::open(label, blocks);
+    assert_eq!(vdev_raid.zones(), 3);
+    let zl1 = vdev_raid.zone_limits(1);
+    let zl2 = vdev_raid.zone_limits(2);
+    assert!(zl1.1 <= zl2.0);
+    assert_eq!(vdev_raid.lba2zone(zl1.1 - 1), Some(1));
+    assert_eq!(vdev_raid.lba2zone(zl2.0), Some(2));
+}
+
+#[test]
+fn zone_map_encode_decode() {
+    let zones = vec![(1, 65536), (65536, 98304), (98304, 131_072),
+                     (131_072, 140_000)];
+    let runs = VdevRaid::encode_zone_map(&zones);
+    assert_eq!(runs, vec![(1, 65535, 1), (65536, 32768, 2),
+                          (131_072, 8928, 1)]);
+    assert_eq!(VdevRaid::decode_zone_map(&runs), zones);
+}
+
+// A RAID zone can't include a disk's inter-zone gap
+#[test]
+fn mismatched_zones_gap() {
+    const CHUNKSIZE: LbaT = 2;
+
+    let bd = |zls: Vec<(LbaT, LbaT)>| {
+        let mut bd = VdevBlock::default();
+        bd.expect_size()
+            .return_const(65536u64);
+        bd.expect_zones()
+            .return_const(zls.len() as ZoneT);
+        for (i, &zl) in zls.iter().enumerate() {
+            bd.expect_zone_limits()
+                .with(eq(i as ZoneT))
+                .return_const(zl);
+        }
+        bd.expect_optimum_queue_depth()
+            .return_const(10u32);
+        bd
+    };
+
+    let blockdevs = vec![
+        bd(vec![(1, 30000), (32768, 65536)]),
+        bd(vec![(1, 65536)])
+    ];
+    let refs = blockdevs.iter().collect::<Vec<_>>();
+    let r = VdevRaid::zone_map(&refs, CHUNKSIZE);
+    assert_eq!(r.err(), Some(Error::EINVAL));
+}
+
 // Flushing a closed zone is a no-op
 #[test]
 fn flush_zone_closed() {
@@ -1788,6 +2658,8 @@ fn flush_zone_closed() {
         let mut bd = VdevBlock::default();
         bd.expect_size()
             .return_const(262_144u64);
+        bd.expect_zones()
+            .return_const(1u32);
         bd.expect_lba2zone()
             .with(eq(60_000))
             .return_const(Some(1));
@@ -1828,6 +2700,11 @@ fn flush_zone_empty_stripe_buffer() {
         let mut bd = VdevBlock::default();
         bd.expect_size()
             .return_const(262_144u64);
+        bd.expect_zones()
+            .return_const(2u32);
+        bd.expect_lba2zone()
+            .with(eq(119_999))
+            .return_const(Some(1));
         bd.expect_lba2zone()
             .with(eq(60_000))
             .return_const(Some(1));
@@ -1878,6 +2755,14 @@ fn open_zone_reopen() {
         let mut bd = VdevBlock::default();
         bd.expect_size()
             .return_const(262_144u64);
+        bd.expect_zones()
+            .return_const(2u32);
+        bd.expect_lba2zone()
+            .with(eq(4096))
+            .return_const(Some(1));
+        bd.expect_lba2zone()
+            .with(eq(8191))
+            .return_const(Some(1));
         bd.expect_lba2zone()
             .with(eq(1))
             .return_const(Some(0));
@@ -1897,9 +2782,9 @@ fn open_zone_reopen() {
         bd.expect_optimum_queue_depth()
             .return_const(10u32);
         bd.expect_write_at()
-            .with(always(), eq(4196))
+            .with(always(), eq(4196), eq(PriorityClass::Normal))
             .once()
-            .return_once(|_, _| Box::new( future::ok::<(), Error>(())));
+            .return_once(|_, _, _| Box::new( future::ok::<(), Error>(())));
         bd
     };
     blockdevs.push(bd());    //disk 0
@@ -1912,7 +2797,7 @@ fn open_zone_reopen() {
     let dbs = DivBufShared::from(vec![0u8; 4096]);
     let wbuf = dbs.try_const().unwrap();
     vdev_raid.reopen_zone(1, 100).wait().unwrap();
-    vdev_raid.write_at(wbuf, 1, 4196).wait().unwrap();
+    vdev_raid.write_at(wbuf, 1, 4196, PriorityClass::Normal).wait().unwrap();
 }
 
 // Open a zone that has wasted leading space due to a chunksize misaligned with
@@ -1932,6 +2817,14 @@ fn open_zone_zero_fill_wasted_chunks() {
         let mut bd = VdevBlock::default();
         bd.expect_size()
             .return_const(262_144u64);
+        bd.expect_zones()
+            .return_const(2u32);
+        bd.expect_lba2zone()
+            .with(eq(32))
+            .return_const(Some(1));
+        bd.expect_lba2zone()
+            .with(eq(63))
+            .return_const(Some(1));
         bd.expect_lba2zone()
             .with(eq(1))
             .return_const(Some(0));
@@ -1949,7 +2842,7 @@ fn open_zone_zero_fill_wasted_chunks() {
             .return_const(10u32);
         bd.expect_writev_at()
             .once()
-            .withf(|sglist, lba| {
+            .withf(|sglist, lba, _| {
                 let len = sglist.iter().map(|b| b.len()).sum::<usize>();
                 len == 3 * BYTES_PER_LBA && *lba == 32
             }).return_once(|_, _| Box::new( future::ok::<(), Error>(())));
@@ -1969,6 +2862,63 @@ fn open_zone_zero_fill_wasted_chunks() {
     vdev_raid.open_zone(1).wait().unwrap();
 }
 
+// Reopening a zone with wasted leading space must not zero-fill it again.
+// That space lies behind the disks' write pointers.
+#[test]
+fn reopen_zone_wasted_chunks() {
+    let k = 5;
+    let f = 1;
+    const CHUNKSIZE : LbaT = 5;
+    let zl0 = (1, 32);
+    let zl1 = (32, 64);
+
+    let mut blockdevs = Vec::<VdevBlock>::new();
+
+    let bd = || {
+        let mut bd = VdevBlock::default();
+        bd.expect_size()
+            .return_const(262_144u64);
+        bd.expect_zones()
+            .return_const(2u32);
+        bd.expect_lba2zone()
+            .with(eq(32))
+            .return_const(Some(1));
+        bd.expect_lba2zone()
+            .with(eq(63))
+            .return_const(Some(1));
+        bd.expect_lba2zone()
+            .with(eq(1))
+            .return_const(Some(0));
+        bd.expect_zone_limits()
+            .with(eq(0))
+            .return_const(zl0);
+        bd.expect_zone_limits()
+            .with(eq(1))
+            .return_const(zl1);
+        bd.expect_open_zone()
+            .once()
+            .with(eq(32))
+            .return_once(|_| Box::new(future::ok::<(), Error>(())));
+        bd.expect_optimum_queue_depth()
+            .return_const(10u32);
+        bd.expect_writev_at()
+            .never();
+        bd
+    };
+
+    blockdevs.push(bd());    //disk 0
+    blockdevs.push(bd());    //disk 1
+    blockdevs.push(bd());    //disk 2
+    blockdevs.push(bd());    //disk 3
+    blockdevs.push(bd());    //disk 4
+
+    let vdev_raid = VdevRaid::new(CHUNKSIZE, k, f,
+                                  Uuid::new_v4(),
+                                  LayoutAlgorithm::PrimeS,
+                                  blockdevs.into_boxed_slice());
+    vdev_raid.reopen_zone(1, 20).wait().unwrap();
+}
+
 // Open a zone that has some leading wasted space.  Use mock VdevBlock objects
 // to verify that the leading wasted space gets zero-filled.
 // Use highly unrealistic disks with 32 LBAs per zone
@@ -1986,6 +2936,14 @@ fn open_zone_zero_fill_wasted_stripes() {
         let mut bd = VdevBlock::default();
         bd.expect_size()
             .return_const(262_144u64);
+        bd.expect_zones()
+            .return_const(2u32);
+        bd.expect_lba2zone()
+            .with(eq(32))
+            .return_const(Some(1));
+        bd.expect_lba2zone()
+            .with(eq(63))
+            .return_const(Some(1));
         bd.expect_lba2zone()
             .with(eq(1))
             .return_const(Some(0));
@@ -2004,7 +2962,7 @@ fn open_zone_zero_fill_wasted_stripes() {
         if gap_chunks > 0 {
             bd.expect_writev_at()
                 .once()
-                .withf(move |sglist, lba| {
+                .withf(move |sglist, lba, _| {
                     let gap_lbas = gap_chunks * CHUNKSIZE; 
                     let len = sglist.iter().map(|b| b.len()).sum::<usize>();
                     len == gap_lbas as usize * BYTES_PER_LBA && *lba == 32
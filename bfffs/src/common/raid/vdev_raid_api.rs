@@ -1,5 +1,12 @@
 // vim: tw=80
-use crate::common::{*, label::*, vdev::*};
+use crate::common::{
+    *,
+    iostat::VdevStats,
+    label::*,
+    throttle::IoLimits,
+    vdev::*,
+    vdev_block::SchedulerPolicy
+};
 
 /// The public interface for all RAID Vdevs.  All Vdevs that slot beneath a
 /// cluster must implement this API.
@@ -24,6 +31,9 @@ pub trait VdevRaidApi : Vdev + 'static {
     /// complete when the zone's contents are fully written
     fn flush_zone(&self, zone: ZoneT) -> (LbaT, BoxVdevFut);
 
+    /// Snapshot the I/O statistics of this device and all of its children
+    fn iostats(&self) -> VdevStats;
+
     /// Asynchronously open a zone on a RAID device
     ///
     /// # Parameters
@@ -32,17 +42,10 @@ pub trait VdevRaidApi : Vdev + 'static {
 
     /// Asynchronously read a contiguous portion of the vdev.
     ///
+    /// `priority` is a hint to the underlying devices' I/O schedulers.
     /// Returns `()` on success, or an error on failure
-    fn read_at(&self, buf: IoVecMut, lba: LbaT) -> BoxVdevFut;
-
-    /// Read one of the spacemaps from disk.
-    ///
-    /// # Parameters
-    /// - `buf`:        Place the still-serialized spacemap here.  `buf` will be
-    ///                 resized as needed.
-    /// - `idx`:        Index of the spacemap to read.  It should be the same as
-    ///                 whichever label is being used.
-    fn read_spacemap(&self, buf: IoVecMut, idx: u32) -> BoxVdevFut;
+    fn read_at(&self, buf: IoVecMut, lba: LbaT, priority: PriorityClass)
+        -> BoxVdevFut;
 
     /// Asynchronously reopen a zone on a RAID device
     ///
@@ -55,28 +58,24 @@ pub trait VdevRaidApi : Vdev + 'static {
     ///                        in this zone.
     fn reopen_zone(&self, zone: ZoneT, allocated: LbaT) -> BoxVdevFut;
 
+    /// Change the I/O rate limits of every underlying block device
+    fn set_limits(&self, limits: IoLimits);
+
+    /// Change the I/O scheduling policy of every underlying block device
+    fn set_scheduler(&self, policy: SchedulerPolicy);
+
     /// Asynchronously write a contiguous portion of the vdev.
     ///
-    /// Returns `()` on success, or an error on failure
-    fn write_at(&self, buf: IoVec, zone: ZoneT, lba: LbaT) -> BoxVdevFut;
+    /// `priority` is a hint to the underlying devices' I/O throttles.  When
+    /// writes are aggregated into a stripe, the stripe takes the priority of
+    /// the write that completed it.  Returns `()` on success, or an error on
+    /// failure
+    fn write_at(&self, buf: IoVec, zone: ZoneT, lba: LbaT,
+                priority: PriorityClass) -> BoxVdevFut;
 
     /// Asynchronously write this Vdev's label.
     ///
     /// `label_writer` should already contain the serialized labels of every
     /// vdev stacked on top of this one.
     fn write_label(&self, labeller: LabelWriter) -> BoxVdevFut;
-
-    /// Asynchronously write to the Vdev's spacemap area.
-    ///
-    /// # Parameters
-    ///
-    /// - `sglist`:     Buffers of data to write
-    /// - `idx`:        Index of the spacemap area to write: there are more than
-    ///                 one.  It should be the same as whichever label is being
-    ///                 written.
-    /// - `block`:      LBA-based offset from the start of the spacemap area
-    // Allow &Vec arguments so we can clone them.
-    #[allow(clippy::ptr_arg)]
-    fn write_spacemap(&self, sglist: &SGList, idx: u32, block: LbaT)
-        -> Box<VdevFut>;
 }
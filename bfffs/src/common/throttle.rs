@@ -0,0 +1,310 @@
+// vim: tw=80
+//! I/O rate limiting
+//!
+//! Each `VdevBlock` owns a `Throttle`, which holds token buckets for read and
+//! write bandwidth and IOPS.  Background reads and writes, such as those issued
+//! by the cleaner, additionally draw from their own buckets.
+
+use crate::common::Error;
+use serde_derive::*;
+use std::{
+    str::FromStr,
+    time::{Duration, Instant}
+};
+
+/// Which way data flows
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    Read,
+    Write
+}
+
+/// Per-disk I/O rate limits.  `None` means unlimited.
+///
+/// Bandwidth limits are in bytes per second.  The `bg_` limits apply only to
+/// operations of `PriorityClass::Background`, which also count against the
+/// overall limits.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct IoLimits {
+    pub read_bw: Option<u64>,
+    pub read_iops: Option<u64>,
+    pub write_bw: Option<u64>,
+    pub write_iops: Option<u64>,
+    pub bg_read_bw: Option<u64>,
+    pub bg_read_iops: Option<u64>,
+    pub bg_write_bw: Option<u64>,
+    pub bg_write_iops: Option<u64>,
+}
+
+impl IoLimits {
+    /// Set one limit from a property string, like `read_bw=10M`.
+    ///
+    /// Values may have a `K`, `M`, `G`, or `T` suffix.  `none` removes the
+    /// limit.
+    pub fn set(&mut self, prop: &str) -> Result<(), Error> {
+        let mut words = prop.splitn(2, '=');
+        let name = words.next().unwrap();
+        let value = words.next().ok_or(Error::EINVAL)?;
+        let limit = if value == "none" {
+            None
+        } else {
+            let (digits, shift) = match value.chars().last() {
+                Some('K') | Some('k') => (&value[..value.len() - 1], 10),
+                Some('M') | Some('m') => (&value[..value.len() - 1], 20),
+                Some('G') | Some('g') => (&value[..value.len() - 1], 30),
+                Some('T') | Some('t') => (&value[..value.len() - 1], 40),
+                _ => (value, 0)
+            };
+            let n = u64::from_str(digits).map_err(|_| Error::EINVAL)?;
+            if n == 0 || n.leading_zeros() < shift {
+                return Err(Error::EINVAL);
+            }
+            Some(n << shift)
+        };
+        let field = match name {
+            "read_bw" => &mut self.read_bw,
+            "read_iops" => &mut self.read_iops,
+            "write_bw" => &mut self.write_bw,
+            "write_iops" => &mut self.write_iops,
+            "bg_read_bw" => &mut self.bg_read_bw,
+            "bg_read_iops" => &mut self.bg_read_iops,
+            "bg_write_bw" => &mut self.bg_write_bw,
+            "bg_write_iops" => &mut self.bg_write_iops,
+            _ => return Err(Error::EINVAL)
+        };
+        *field = limit;
+        Ok(())
+    }
+}
+
+/// A token bucket that holds up to one second's worth of tokens
+#[derive(Debug)]
+struct TokenBucket {
+    /// Last time that tokens were added
+    last: Instant,
+    /// Tokens added per second
+    rate: f64,
+    /// May be negative, if an operation cost more than was available
+    tokens: f64,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, now: Instant) -> Self {
+        let rate = rate as f64;
+        TokenBucket{last: now, rate, tokens: rate}
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now > self.last {
+            let elapsed = now.duration_since(self.last).as_micros() as f64;
+            self.tokens = (self.tokens + elapsed * self.rate / 1e6)
+                .min(self.rate);
+            self.last = now;
+        }
+    }
+
+    fn take(&mut self, cost: u64) {
+        self.tokens -= cost as f64;
+    }
+
+    /// How long until the bucket holds at least one token?
+    fn wait(&mut self, now: Instant) -> Option<Duration> {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            None
+        } else {
+            let us = ((1.0 - self.tokens) * 1e6 / self.rate).ceil() as u64 + 1;
+            Some(Duration::from_micros(us))
+        }
+    }
+}
+
+/// Enforces `IoLimits` for a single disk.
+///
+/// An operation is admitted whenever each of its buckets holds at least one
+/// token, even if it costs more tokens than they hold.  The resulting debt
+/// delays later operations instead.  That way an operation larger than one
+/// second's worth of bandwidth can still be issued.
+#[derive(Debug, Default)]
+pub struct Throttle {
+    limits: IoLimits,
+    read_bw: Option<TokenBucket>,
+    read_iops: Option<TokenBucket>,
+    write_bw: Option<TokenBucket>,
+    write_iops: Option<TokenBucket>,
+    bg_read_bw: Option<TokenBucket>,
+    bg_read_iops: Option<TokenBucket>,
+    bg_write_bw: Option<TokenBucket>,
+    bg_write_iops: Option<TokenBucket>,
+}
+
+impl Throttle {
+    /// Try to admit an operation.
+    ///
+    /// On success, charges the operation to its buckets.  On failure, returns
+    /// how long to wait before trying again.
+    pub fn admit(&mut self, dir: Direction, background: bool, bytes: usize,
+                 now: Instant) -> Result<(), Duration>
+    {
+        let buckets = match dir {
+            Direction::Read if background => vec![
+                (&mut self.read_bw, bytes as u64),
+                (&mut self.read_iops, 1),
+                (&mut self.bg_read_bw, bytes as u64),
+                (&mut self.bg_read_iops, 1),
+            ],
+            Direction::Read => vec![
+                (&mut self.read_bw, bytes as u64),
+                (&mut self.read_iops, 1),
+            ],
+            Direction::Write if background => vec![
+                (&mut self.write_bw, bytes as u64),
+                (&mut self.write_iops, 1),
+                (&mut self.bg_write_bw, bytes as u64),
+                (&mut self.bg_write_iops, 1),
+            ],
+            Direction::Write => vec![
+                (&mut self.write_bw, bytes as u64),
+                (&mut self.write_iops, 1),
+            ]
+        };
+        let mut buckets = buckets.into_iter()
+            .filter_map(|(b, cost)| match b {
+                Some(b) => Some((b, cost)),
+                None => None
+            })
+            .collect::<Vec<_>>();
+        let wait = buckets.iter_mut()
+            .filter_map(|(b, _)| b.wait(now))
+            .max();
+        if let Some(w) = wait {
+            return Err(w);
+        }
+        for (b, cost) in buckets.iter_mut() {
+            b.take(*cost);
+        }
+        Ok(())
+    }
+
+    pub fn limits(&self) -> IoLimits {
+        self.limits
+    }
+
+    /// Change the limits.  Any accumulated debt is forgiven.
+    pub fn set_limits(&mut self, limits: IoLimits) {
+        let now = Instant::now();
+        let bucket = |rate: Option<u64>| rate.map(|r| TokenBucket::new(r, now));
+        self.limits = limits;
+        self.read_bw = bucket(limits.read_bw);
+        self.read_iops = bucket(limits.read_iops);
+        self.write_bw = bucket(limits.write_bw);
+        self.write_iops = bucket(limits.write_iops);
+        self.bg_read_bw = bucket(limits.bg_read_bw);
+        self.bg_read_iops = bucket(limits.bg_read_iops);
+        self.bg_write_bw = bucket(limits.bg_write_bw);
+        self.bg_write_iops = bucket(limits.bg_write_iops);
+    }
+}
+
+#[cfg(test)]
+mod t {
+use super::*;
+
+#[test]
+fn admit_unlimited() {
+    let mut throttle = Throttle::default();
+    let now = Instant::now();
+    for _ in 0..1000 {
+        assert!(throttle.admit(Direction::Read, true, 1 << 20, now).is_ok());
+        assert!(throttle.admit(Direction::Write, false, 1 << 20, now).is_ok());
+    }
+}
+
+// Exhausting the bucket delays the next operation until it refills
+#[test]
+fn admit_iops() {
+    let mut throttle = Throttle::default();
+    let mut limits = IoLimits::default();
+    limits.write_iops = Some(10);
+    throttle.set_limits(limits);
+    let now = Instant::now();
+    for _ in 0..10 {
+        assert!(throttle.admit(Direction::Write, false, 4096, now).is_ok());
+    }
+    let wait = throttle.admit(Direction::Write, false, 4096, now).unwrap_err();
+    assert!(wait > Duration::from_millis(99));
+    assert!(wait < Duration::from_millis(101));
+    // Reads aren't limited
+    assert!(throttle.admit(Direction::Read, false, 4096, now).is_ok());
+    let later = now + wait;
+    assert!(throttle.admit(Direction::Write, false, 4096, later).is_ok());
+}
+
+// A large operation is admitted, but leaves the bucket in debt
+#[test]
+fn admit_debt() {
+    let mut throttle = Throttle::default();
+    let mut limits = IoLimits::default();
+    limits.read_bw = Some(1 << 20);
+    throttle.set_limits(limits);
+    let now = Instant::now();
+    assert!(throttle.admit(Direction::Read, false, 2 << 20, now).is_ok());
+    let wait = throttle.admit(Direction::Read, false, 4096, now).unwrap_err();
+    assert!(wait > Duration::from_millis(999));
+    assert!(wait < Duration::from_millis(1001));
+}
+
+// Background reads are limited by both their own limits and the overall limits
+#[test]
+fn admit_background() {
+    let mut throttle = Throttle::default();
+    let mut limits = IoLimits::default();
+    limits.bg_read_iops = Some(1);
+    limits.read_iops = Some(3);
+    throttle.set_limits(limits);
+    let now = Instant::now();
+    assert!(throttle.admit(Direction::Read, true, 4096, now).is_ok());
+    assert!(throttle.admit(Direction::Read, true, 4096, now).is_err());
+    assert!(throttle.admit(Direction::Read, false, 4096, now).is_ok());
+    assert!(throttle.admit(Direction::Read, false, 4096, now).is_ok());
+    assert!(throttle.admit(Direction::Read, false, 4096, now).is_err());
+}
+
+// Background writes are limited by both their own limits and the overall
+// limits
+#[test]
+fn admit_background_write() {
+    let mut throttle = Throttle::default();
+    let mut limits = IoLimits::default();
+    limits.bg_write_bw = Some(8192);
+    limits.write_bw = Some(16384);
+    throttle.set_limits(limits);
+    let now = Instant::now();
+    assert!(throttle.admit(Direction::Write, true, 8192, now).is_ok());
+    assert!(throttle.admit(Direction::Write, true, 4096, now).is_err());
+    assert!(throttle.admit(Direction::Write, false, 8192, now).is_ok());
+    assert!(throttle.admit(Direction::Write, false, 4096, now).is_err());
+    // Background reads aren't affected
+    assert!(throttle.admit(Direction::Read, true, 4096, now).is_ok());
+}
+
+#[test]
+fn set() {
+    let mut limits = IoLimits::default();
+    limits.set("read_bw=10M").unwrap();
+    limits.set("write_iops=500").unwrap();
+    limits.set("bg_read_bw=64k").unwrap();
+    limits.set("bg_write_iops=100").unwrap();
+    assert_eq!(limits.read_bw, Some(10 << 20));
+    assert_eq!(limits.write_iops, Some(500));
+    assert_eq!(limits.bg_read_bw, Some(64 << 10));
+    assert_eq!(limits.bg_write_iops, Some(100));
+    limits.set("read_bw=none").unwrap();
+    assert_eq!(limits.read_bw, None);
+    assert_eq!(limits.set("read_bw"), Err(Error::EINVAL));
+    assert_eq!(limits.set("read_bw=0"), Err(Error::EINVAL));
+    assert_eq!(limits.set("read_bw=ten"), Err(Error::EINVAL));
+    assert_eq!(limits.set("foo=10"), Err(Error::EINVAL));
+}
+
+}
@@ -414,7 +414,7 @@ impl<A: Addr, K: Key, V: Value> TreeWriteGuard<A, K, V> {
                             .as_addr();
                 Box::new(
                     dml.pop::<Arc<Node<A, K, V>>, Arc<Node<A, K, V>>>(&addr,
-                                                                      txg)
+                        txg, PriorityClass::Normal)
                        .map(move |arc|
                     {
                         let child_node = Box::new(Arc::try_unwrap(*arc)
@@ -468,7 +468,8 @@ impl<A: Addr, K: Key, V: Value> TreeWriteGuard<A, K, V> {
         } else {
             let addr = *self.as_int().children[child_idx].ptr.as_addr();
             Box::new(
-                dml.pop::<Arc<Node<A, K, V>>, Arc<Node<A, K, V>>>(&addr, txg)
+                dml.pop::<Arc<Node<A, K, V>>, Arc<Node<A, K, V>>>(&addr, txg,
+                    PriorityClass::Normal)
                .map(move |arc| {
                     let child_node = Box::new(Arc::try_unwrap(*arc)
                         .expect("We should be the Node's only owner"));
@@ -515,7 +516,7 @@ impl<A: Addr, K: Key, V: Value> TreeWriteGuard<A, K, V> {
             } else {
                 let addr = *elem.ptr.as_addr();
                 let fut = dml.pop::<Arc<Node<A, K, V>>, Arc<Node<A, K, V>>>(
-                    &addr, txg)
+                    &addr, txg, PriorityClass::Normal)
                 .map(move |arc| {
                     let child_node = Box::new(Arc::try_unwrap(*arc)
                         .expect("We should be the Node's only owner"));
@@ -600,7 +601,8 @@ impl<A: Addr, K: Key, V: Value> IntElem<A, K, V> {
             },
             TreePtr::Addr(ref addr) => {
                 Box::new(
-                    dml.get::<Arc<Node<A, K, V>>, Arc<Node<A, K, V>>>(addr)
+                    dml.get::<Arc<Node<A, K, V>>, Arc<Node<A, K, V>>>(addr,
+                        PriorityClass::Normal)
                     .and_then(|node| {
                         node.0.read()
                             .map(move |guard| {
@@ -997,6 +999,10 @@ impl<A: Addr, K: Key, V: Value> Cacheable for Arc<Node<A, K, V>> {
     fn make_ref(&self) -> Box<dyn CacheRef> {
         Box::new(self.clone())
     }
+
+    fn write_class(&self) -> WriteClass {
+        WriteClass::Metadata
+    }
 }
 
 impl<A: Addr, K: Key, V: Value> CacheRef for Arc<Node<A, K, V>> {
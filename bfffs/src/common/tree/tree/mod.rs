@@ -1865,7 +1865,7 @@ impl<A, D, K, V> Tree<A, D, K, V>
         .and_then(move |leaf_data| {
             let node = Node::new(NodeData::Leaf(leaf_data));
             let arc: Arc<Node<A, K, V>> = Arc::new(node);
-            dml.put(arc, compressor, txg)
+            dml.put(arc, compressor, txg, PriorityClass::Normal)
         })
     }
 
@@ -1922,7 +1922,7 @@ impl<A, D, K, V> Tree<A, D, K, V>
                 ndata.as_int_mut().children = elems;
                 drop(ndata);
                 let arc: Arc<Node<A, K, V>> = Arc::new(node);
-                dml2.put(arc, int_compressor, txg)
+                dml2.put(arc, int_compressor, txg, PriorityClass::Normal)
                     .map(move |addr| (addr, start_txg..txg + 1))
             })
         )
@@ -2025,7 +2025,7 @@ impl<A, D, K, V> Tree<A, D, K, V>
             let addr = *guard.ptr.as_addr();
             Box::new(
                 dml.pop::<Arc<Node<A, K, V>>, Arc<Node<A, K, V>>>(
-                    &addr, txg).map(move |arc|
+                    &addr, txg, PriorityClass::Normal).map(move |arc|
                 {
                     let child_node = Box::new(Arc::try_unwrap(*arc)
                         .expect("We should be the Node's only owner"));
@@ -2202,9 +2202,11 @@ impl<D, K, V> Tree<ddml::DRP, D, K, V>
                 }
                 let fut = dml2.pop::<Arc<Node<ddml::DRP, K, V>>,
                                      Arc<Node<ddml::DRP, K, V>>>(
-                                        guard.ptr.as_addr(), txg)
+                                        guard.ptr.as_addr(), txg,
+                                        PriorityClass::Background)
                     .and_then(move |arc| {
-                        dml2.put(*arc, Compression::None, txg)
+                        dml2.put(*arc, Compression::None, txg,
+                                 PriorityClass::Background)
                     }).map(move |addr| {
                         let new = TreePtr::Addr(addr);
                         guard.ptr = new;
@@ -2236,7 +2238,8 @@ impl<D, K, V> Tree<ddml::DRP, D, K, V>
             let dml2 = dml.clone();
             let fut = dml.pop::<Arc<Node<ddml::DRP, K, V>>,
                                 Arc<Node<ddml::DRP, K, V>>>(
-                        guard.as_int().children[child_idx].ptr.as_addr(), txg)
+                        guard.as_int().children[child_idx].ptr.as_addr(), txg,
+                        PriorityClass::Background)
                 .and_then(move |arc| {
                     #[cfg(debug_assertions)]
                     {
@@ -2244,7 +2247,8 @@ impl<D, K, V> Tree<ddml::DRP, D, K, V>
                             assert!(node.key <= *guard.key());
                         }
                     }
-                    dml2.put(*arc, Compression::None, txg)
+                    dml2.put(*arc, Compression::None, txg,
+                             PriorityClass::Background)
                 }).map(move |addr| {
                     let new = TreePtr::Addr(addr);
                     guard.as_int_mut().children[child_idx].ptr = new;
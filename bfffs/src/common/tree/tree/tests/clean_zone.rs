@@ -64,32 +64,33 @@ fn basic() {
     let mut mock = DDML::default();
     type T = Arc<Node<DRP, u32, f32>>;
     mock.expect_get::<T, T>()
-        .with(eq(drpi1))
-        .returning(move |_| Box::new(future::ok(Box::new(in1.clone()))));
+        .with(eq(drpi1), always())
+        .returning(move |_, _| Box::new(future::ok(Box::new(in1.clone()))));
     mock.expect_get::<T, T>()
-        .with(eq(drpi2))
-        .returning(move |_| Box::new(future::ok(Box::new(in2.clone()))));
+        .with(eq(drpi2), always())
+        .returning(move |_, _| Box::new(future::ok(Box::new(in2.clone()))));
     mock.expect_pop::<T, T>()
         .once()
-        .with(eq(drpl3), always())
-        .return_once(move |_, _| Box::new(future::ok(Box::new(ln3))));
+        .with(eq(drpl3), always(), always())
+        .return_once(move |_, _, _| Box::new(future::ok(Box::new(ln3))));
     mock.expect_pop::<T, T>()
         .once()
-        .with(eq(drpi1), always())
-        .return_once(move |_, _| Box::new(future::ok(Box::new(in1_c))));
+        .with(eq(drpi1), always(), always())
+        .return_once(move |_, _, _| Box::new(future::ok(Box::new(in1_c))));
     mock.expect_pop::<T, T>()
         .once()
-        .with(eq(drpi2), always())
-        .return_once(move |_, _| Box::new(future::ok(Box::new(in2_c))));
+        .with(eq(drpi2), always(), always())
+        .return_once(move |_, _, _| Box::new(future::ok(Box::new(in2_c))));
     mock.expect_pop::<T, T>()
         .once()
-        .with(eq(drpl8), always())
-        .return_once(move |_, _| Box::new(future::ok(Box::new(ln8))));
+        .with(eq(drpl8), always(), always())
+        .return_once(move |_, _, _| Box::new(future::ok(Box::new(ln8))));
     let next_lba = AtomicU64::new(0);
     mock.expect_put::<Arc<Node<DRP, u32, f32>>>()
         .times(3)
-        .with(always(), always(), eq(TxgT::from(42)))
-        .returning(move |_cacheable, compression, _txg| {
+        .with(always(), always(), eq(TxgT::from(42)),
+              eq(PriorityClass::Background))
+        .returning(move |_cacheable, compression, _txg, _| {
             let lba = next_lba.fetch_add(1, Ordering::Relaxed);
             let drp = DRP::new(PBA{cluster: 1, lba}, compression, 0, 0, 0);
             Box::new(Ok(drp).into_future())
@@ -352,20 +353,22 @@ fn dirty_root() {
     let mut mock = DDML::default();
     type T = Arc<Node<DRP, u32, f32>>;
     mock.expect_get::<T, T>()
-        .with(eq(drpir))
-        .returning(move |_| {
+        .with(eq(drpir), always())
+        .returning(move |_, _| {
             Box::new(future::ok(Box::new(inr.clone())))
         });
     mock.expect_pop::<T, T>()
         .once()
-        .with(eq(drpir), eq(TxgT::from(42)))
-        .return_once(move |_, _| {
+        .with(eq(drpir), eq(TxgT::from(42)),
+              eq(PriorityClass::Background))
+        .return_once(move |_, _, _| {
             Box::new(future::ok(Box::new(inr_c)))
         });
     mock.expect_put::<T>()
         .once()
-        .with(always(), always(), eq(TxgT::from(42)))
-        .returning(move |_cacheable, _compression, _txg| {
+        .with(always(), always(), eq(TxgT::from(42)),
+              eq(PriorityClass::Background))
+        .returning(move |_cacheable, _compression, _txg, _| {
             let drp = DRP::random(Compression::None, 1024);
             Box::new(Ok(drp).into_future())
         });
@@ -36,8 +36,8 @@ fn expect_get(mock: &mut MockDML, addr: u32, node: NodeT)
 {
     mock.expect_get::<NodeT, NodeT>()
         .once()
-        .with(eq(addr))
-        .return_once(move |_| Box::new(future::ok(Box::new(node))));
+        .with(eq(addr), eq(PriorityClass::Normal))
+        .return_once(move |_, _| Box::new(future::ok(Box::new(node))));
 }
 
 /// Helper method for setting MockDML::delete expectations
@@ -53,8 +53,8 @@ fn expect_pop(mock: &mut MockDML, addr: u32, node: NodeT)
 {
     mock.expect_pop::<NodeT, NodeT>()
         .once()
-        .with(eq(addr), always())
-        .return_once(move |_, _| Box::new(future::ok(Box::new(node))));
+        .with(eq(addr), always(), eq(PriorityClass::Normal))
+        .return_once(move |_, _, _| Box::new(future::ok(Box::new(node))));
 }
 
 #[test]
@@ -408,8 +408,8 @@ fn insert_below_root() {
     let addrl = 0;
     mock.expect_pop::<Arc<Node<u32, u32, u32>>, Arc<Node<u32, u32, u32>>>()
         .once()
-        .with(eq(addrl), eq(TxgT::from(42)))
-        .return_once(move |_, _| {
+        .with(eq(addrl), eq(TxgT::from(42)), eq(PriorityClass::Normal))
+        .return_once(move |_, _, _| {
             Box::new(future::ok(Box::new(node)))
         });
     let dml = Arc::new(mock);
@@ -490,8 +490,8 @@ fn insert_root() {
     let addrl = 0;
     mock.expect_pop::<Arc<Node<u32, u32, u32>>, Arc<Node<u32, u32, u32>>>()
         .once()
-        .with(eq(addrl), eq(TxgT::from(42)))
-        .return_once(move |_, _| {
+        .with(eq(addrl), eq(TxgT::from(42)), eq(PriorityClass::Normal))
+        .return_once(move |_, _, _| {
             Box::new(future::ok(Box::new(node)))
         });
     let dml = Arc::new(mock);
@@ -543,8 +543,8 @@ fn is_dirty() {
     let addrl = 0;
     mock.expect_pop::<Arc<Node<u32, u32, u32>>, Arc<Node<u32, u32, u32>>>()
         .once()
-        .with(eq(addrl), eq(TxgT::from(42)))
-        .return_once(move |_, _| {
+        .with(eq(addrl), eq(TxgT::from(42)), eq(PriorityClass::Normal))
+        .return_once(move |_, _, _| {
             Box::new(future::ok(Box::new(node)))
         });
     let dml = Arc::new(mock);
@@ -1015,7 +1015,7 @@ fn range_leaf() {
     let node1 = Arc::new(Node::new(NodeData::Leaf(ld1)));
     mock.expect_get::<NodeT, NodeT>()
         .once()
-        .returning(move |_| {
+        .returning(move |_, _| {
             let mut seq = Sequence::new();
             let mut fut = MockFuture::new();
             let node2 = node1.clone();
@@ -1070,8 +1070,8 @@ fn read_int() {
     let mut mock = MockDML::new();
     mock.expect_get::<Arc<Node<u32, u32, u32>>, Arc<Node<u32, u32, u32>>>()
         .once()
-        .with(eq(addrl))
-        .return_once(move |_| Box::new(future::ok(Box::new(node))));
+        .with(eq(addrl), eq(PriorityClass::Normal))
+        .return_once(move |_, _| Box::new(future::ok(Box::new(node))));
     let dml = Arc::new(mock);
     let tree: Tree<u32, MockDML, u32, u32> =
         Tree::from_str(dml.clone(), false, r#"
@@ -1115,7 +1115,7 @@ fn read_leaf() {
     let node = Arc::new(Node::new(NodeData::Leaf(ld)));
     mock.expect_get::<Arc<Node<u32, u32, u32>>, Arc<Node<u32, u32, u32>>>()
         .once()
-        .return_once(move |_| Box::new(future::ok(Box::new(node))));
+        .return_once(move |_, _| Box::new(future::ok(Box::new(node))));
     let dml = Arc::new(mock);
     let tree: Tree<u32, MockDML, u32, u32> = Tree::from_str(dml, false, r#"
 ---
@@ -1151,8 +1151,8 @@ fn remove_and_merge_down() {
     let addrl: u32 = 0;
     mock.expect_pop::<NodeT, NodeT>()
         .once()
-        .with(eq(addrl), always())
-        .return_once(move |_, _| Box::new(future::ok(Box::new(leafnode))));
+        .with(eq(addrl), always(), always())
+        .return_once(move |_, _, _| Box::new(future::ok(Box::new(leafnode))));
 
     let dml = Arc::new(mock);
     let tree: Tree<u32, MockDML, u32, f32> = Tree::from_str(dml, false, r#"
@@ -1351,7 +1351,7 @@ fn write_deep() {
     mock.expect_put::<Arc<Node<u32, u32, u32>>>()
         .once()
         .in_sequence(&mut seq)
-        .withf(move |cacheable, _compression, txg| {
+        .withf(move |cacheable, _compression, txg, _| {
             let node_data = cacheable.0.try_read().unwrap();
             match node_data.deref() {
                 NodeData::Leaf(leaf_data) => {
@@ -1361,11 +1361,11 @@ fn write_deep() {
                 },
                 _ => false
             }
-        }).return_once(move |_, _, _| Box::new(Ok(addr).into_future()));
+        }).return_once(move |_, _, _, _| Box::new(Ok(addr).into_future()));
     mock.expect_put::<Arc<Node<u32, u32, u32>>>()
         .once()
         .in_sequence(&mut seq)
-        .withf(move |cacheable, _compression, txg| {
+        .withf(move |cacheable, _compression, txg, _| {
             let node_data = cacheable.0.try_read().unwrap();
             let int_data = node_data.as_int();
             int_data.children[0].key == 0 &&
@@ -1373,7 +1373,7 @@ fn write_deep() {
             int_data.children[1].key == 256 &&
             int_data.children[1].ptr.is_addr() &&
             *txg == TxgT::from(42)
-        }).return_once(move |_, _, _| Box::new(Ok(addr).into_future()));
+        }).return_once(move |_, _, _, _| Box::new(Ok(addr).into_future()));
     let dml = Arc::new(mock);
     let mut tree: Tree<u32, MockDML, u32, u32> = Tree::from_str(dml, false, r#"
 ---
@@ -1425,7 +1425,7 @@ fn write_int() {
     let addr = 9999;
     mock.expect_put::<Arc<Node<u32, u32, u32>>>()
         .once()
-        .withf(move |cacheable, _compression, txg| {
+        .withf(move |cacheable, _compression, txg, _| {
             let node_data = cacheable.0.try_read().unwrap();
             let int_data = node_data.as_int();
             int_data.children[0].key == 0 &&
@@ -1433,7 +1433,7 @@ fn write_int() {
             int_data.children[1].key == 256 &&
             !int_data.children[1].ptr.is_mem() &&
             *txg == TxgT::from(42)
-        }).returning(move |_, _, _| Box::new(Ok(addr).into_future()));
+        }).returning(move |_, _, _, _| Box::new(Ok(addr).into_future()));
     let dml = Arc::new(mock);
     let mut tree: Tree<u32, MockDML, u32, u32> = Tree::from_str(dml, false, r#"
 ---
@@ -1481,13 +1481,13 @@ fn write_leaf() {
     let addr = 9999;
     mock.expect_put::<Arc<Node<u32, u32, u32>>>()
         .once()
-        .withf(move |cacheable, _compression, txg| {
+        .withf(move |cacheable, _compression, txg, _| {
             let node_data = cacheable.0.try_read().unwrap();
             let leaf_data = node_data.as_leaf();
             leaf_data.get(&0) == Some(100) &&
             leaf_data.get(&1) == Some(200) &&
             *txg == TxgT::from(42)
-        }).returning(move |_, _, _| Box::new(Ok(addr).into_future()));
+        }).returning(move |_, _, _, _| Box::new(Ok(addr).into_future()));
     let dml = Arc::new(mock);
     let mut tree: Tree<u32, MockDML, u32, u32> = Tree::from_str(dml, false, r#"
 ---
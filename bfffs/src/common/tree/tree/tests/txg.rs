@@ -495,14 +495,14 @@ fn flush() {
     let addr = 9999;
     mock.expect_put::<Arc<Node<u32, u32, u32>>>()
         .once()
-        .withf(move |cacheable, _compression, txg| {
+        .withf(move |cacheable, _compression, txg, _| {
             let node_data = cacheable.0.try_read().unwrap();
             node_data.is_leaf() || *txg == TxgT::from(42)
         })
-        .return_once(move |_, _, _| Box::new(Ok(addr).into_future()));
+        .return_once(move |_, _, _, _| Box::new(Ok(addr).into_future()));
     mock.expect_put::<Arc<Node<u32, u32, u32>>>()
         .once()
-        .withf(move |cacheable, _compression, txg| {
+        .withf(move |cacheable, _compression, txg, _| {
             let node_data = cacheable.0.try_read().unwrap();
             let int_data = node_data.as_int();
             int_data.children[0].key == 0 &&
@@ -511,7 +511,7 @@ fn flush() {
             int_data.children[1].txgs == (TxgT::from(41)..TxgT::from(42)) &&
             *txg == TxgT::from(42)
         })
-        .returning(move |_, _, _| Box::new(Ok(addr).into_future()));
+        .returning(move |_, _, _, _| Box::new(Ok(addr).into_future()));
     let dml = Arc::new(mock);
     let mut tree: Tree<u32, MockDML, u32, u32> = Tree::from_str(dml, false, r#"
 ---
@@ -568,8 +568,8 @@ fn merge() {
     let ln1 = Arc::new(Node::new(NodeData::Leaf(ld1)));
     mock.expect_pop::<Arc<Node<u32, u32, f32>>, Arc<Node<u32, u32, f32>>>()
         .once()
-        .with(eq(addrl1), eq(TxgT::from(42)))
-        .return_once(move |_, _| {
+        .with(eq(addrl1), eq(TxgT::from(42)), eq(PriorityClass::Normal))
+        .return_once(move |_, _, _| {
             Box::new(future::ok(Box::new(ln1)))
         });
     let dml = Arc::new(mock);
@@ -725,8 +725,8 @@ fn split() {
     let node = Arc::new(Node::new(NodeData::Leaf(ld)));
     mock.expect_pop::<Arc<Node<u32, u32, f32>>, Arc<Node<u32, u32, f32>>>()
         .once()
-        .with(eq(addrl), eq(TxgT::from(42)))
-        .return_once(move |_, _| {
+        .with(eq(addrl), eq(TxgT::from(42)), eq(PriorityClass::Normal))
+        .return_once(move |_, _, _| {
             Box::new(future::ok(Box::new(node)))
         });
     let dml = Arc::new(mock);
@@ -9,6 +9,25 @@ pub type VdevFut = dyn futures::Future<Item = (), Error = Error>;
 /// Boxed `VdevFut`
 pub type BoxVdevFut = Box<dyn futures::Future<Item = (), Error = Error>>;
 
+/// Relative importance of an I/O operation.
+///
+/// Only some schedulers pay attention to it.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum PriorityClass {
+    /// Work that nobody is waiting on, like cleaning zones
+    Background,
+    /// Most operations
+    Normal,
+    /// Operations that a user is actively waiting on
+    Interactive,
+}
+
+impl Default for PriorityClass {
+    fn default() -> Self {
+        PriorityClass::Normal
+    }
+}
+
 /// Vdev: Virtual Device
 ///
 /// This is directly analogous to ZFS Vdevs.  A vdev is a virtual block device
@@ -27,10 +46,17 @@ pub trait Vdev {
     /// `None` indicates that the LBA is unused.
     fn lba2zone(&self, lba: LbaT) -> Option<ZoneT>;
 
+    /// Return the most zones that may be open at any one time, or `None` if
+    /// there is no limit.
+    fn max_open_zones(&self) -> Option<ZoneT>;
+
     /// Returns the "best" number of operations to queue to this `Vdev`.  A
     /// smaller number may result in inefficient use of resources, or even
     /// starvation.  A larger number won't hurt, but won't accrue any economies
     /// of scale, either.
+    ///
+    /// For leaf vdevs this is a static guess.  Higher layers may adjust it at
+    /// runtime.
     fn optimum_queue_depth(&self) -> u32;
 
     /// Return approximately the usable space of the Vdev in LBAs.
@@ -1,13 +1,18 @@
 // vim: tw=80
 
-use futures::{Async, Future, Poll, unsync::oneshot};
+use futures::{
+    Async,
+    Future,
+    Poll,
+    future::{self, Either},
+    unsync::oneshot
+};
 use std::{
     cell::RefCell,
     cmp::{Ord, Ordering, PartialOrd},
-    collections::BinaryHeap,
-    collections::VecDeque,
+    collections::{BTreeMap, VecDeque},
+    convert::TryFrom,
     io,
-    mem,
     num::NonZeroU64,
     path::Path,
     rc::{Rc, Weak},
@@ -18,7 +23,15 @@ use std::{
 use tokio_current_thread;
 use tokio::timer;
 
-use crate::common::{*, label::*, vdev::*, vdev_leaf::*, vdev_file::*};
+use crate::common::{
+    *,
+    iostat::*,
+    label::*,
+    throttle::*,
+    vdev::*,
+    vdev_leaf::*,
+    vdev_file::*
+};
 
 #[cfg(test)]
 pub type VdevLeaf = MockVdevFile;
@@ -29,7 +42,6 @@ pub type VdevLeaf = VdevFile;
 enum Cmd {
     OpenZone,
     ReadAt(IoVecMut),
-    ReadSpacemap(IoVecMut, u32),
     ReadvAt(SGListMut),
     WriteAt(IoVec),
     WritevAt(SGList),
@@ -38,7 +50,6 @@ enum Cmd {
     // The extra LBA is the zone's starting LBA
     FinishZone(LbaT),
     WriteLabel(LabelWriter),
-    WriteSpacemap(SGList, u32, LbaT),
     SyncAll,
 }
 
@@ -49,17 +60,98 @@ impl Cmd {
         match *self {
             Cmd::OpenZone => 0,
             Cmd::ReadAt(_) => 1,
-            Cmd::ReadSpacemap(_, _) => 2,
-            Cmd::ReadvAt(_) => 3,
-            Cmd::WriteAt(_) => 4,
-            Cmd::WritevAt(_) => 5,
-            Cmd::EraseZone(_) => 6,
-            Cmd::FinishZone(_) => 7,
-            Cmd::WriteLabel(_) => 8,
-            Cmd::WriteSpacemap(_, _, _) => 9,
-            Cmd::SyncAll => 10,
+            Cmd::ReadvAt(_) => 2,
+            Cmd::WriteAt(_) => 3,
+            Cmd::WritevAt(_) => 4,
+            Cmd::EraseZone(_) => 5,
+            Cmd::FinishZone(_) => 6,
+            Cmd::WriteLabel(_) => 7,
+            Cmd::SyncAll => 8,
         }
     }   // LCOV_EXCL_LINE   kcov false negative
+
+    /// Duplicate a `Cmd` so it can be reissued after a failure.
+    ///
+    /// Returns `None` for reads, because their buffers can't be shared, and
+    /// for label writes, which upper layers must redo themselves.
+    fn try_clone(&self) -> Option<Cmd> {
+        match *self {
+            Cmd::OpenZone => Some(Cmd::OpenZone),
+            Cmd::WriteAt(ref iovec) => Some(Cmd::WriteAt(iovec.clone())),
+            Cmd::WritevAt(ref sglist) => Some(Cmd::WritevAt(sglist.clone())),
+            Cmd::EraseZone(start) => Some(Cmd::EraseZone(start)),
+            Cmd::FinishZone(start) => Some(Cmd::FinishZone(start)),
+            Cmd::SyncAll => Some(Cmd::SyncAll),
+            _ => None
+        }
+    }
+
+    /// Append another command's buffers to this one's, producing a single
+    /// vectored command.  Both commands must have the same `merge_class`.
+    fn append(self, other: Cmd) -> Cmd {
+        match self {
+            Cmd::WriteAt(_) | Cmd::WritevAt(_) => {
+                let mut sglist = self.into_sglist();
+                sglist.extend(other.into_sglist());
+                Cmd::WritevAt(sglist)
+            },
+            _ => {
+                let mut sglist = self.into_sglist_mut();
+                sglist.extend(other.into_sglist_mut());
+                Cmd::ReadvAt(sglist)
+            }
+        }
+    }
+
+    fn into_sglist(self) -> SGList {
+        match self {
+            Cmd::WriteAt(iovec) => vec![iovec],
+            Cmd::WritevAt(sglist) => sglist,
+            _ => panic!("Not a data write")
+        }
+    }
+
+    fn into_sglist_mut(self) -> SGListMut {
+        match self {
+            Cmd::ReadAt(iovec_mut) => vec![iovec_mut],
+            Cmd::ReadvAt(sglist_mut) => sglist_mut,
+            _ => panic!("Not a data read")
+        }
+    }
+
+    fn is_read(&self) -> bool {
+        match *self {
+            Cmd::ReadAt(_) | Cmd::ReadvAt(_) => true,
+            _ => false
+        }
+    }
+
+    /// Which other commands may this one be merged with?  Only ordinary data
+    /// reads and writes may be merged, and only in the same direction.
+    fn merge_class(&self) -> Option<StatClass> {
+        match *self {
+            Cmd::ReadAt(_) | Cmd::ReadvAt(_) => Some(StatClass::Read),
+            Cmd::WriteAt(_) | Cmd::WritevAt(_) => Some(StatClass::Write),
+            _ => None
+        }
+    }
+
+    fn stat_class(&self) -> StatClass {
+        match *self {
+            Cmd::WriteAt(_) | Cmd::WritevAt(_) | Cmd::WriteLabel(_) =>
+                StatClass::Write,
+            _ if self.is_read() => StatClass::Read,
+            _ => StatClass::Other
+        }
+    }
+}
+
+/// How an operation is counted in `IoStats`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum StatClass {
+    Read,
+    Write,
+    Other
 }
 
 impl Eq for Cmd {
@@ -86,6 +178,8 @@ impl PartialOrd for Cmd {
     }
 }
 
+/// Delivers the result of a `BlockOp` back to its `VdevBlockFut`
+type OpSender = oneshot::Sender<Result<(), Error>>;
 
 /// A single read or write command that is queued at the `VdevBlock` layer
 struct BlockOp {
@@ -93,50 +187,23 @@ struct BlockOp {
     /// actual LBA
     pub lba: LbaT,
     pub cmd: Cmd,
+    /// Used by some schedulers to choose which operations to issue first
+    pub priority: PriorityClass,
+    /// When the operation was created.  Used for latency statistics.
+    pub start: time::Instant,
     /// Used by the `VdevLeaf` to complete this future
-    pub sender: oneshot::Sender<()>
-}
-
-impl Eq for BlockOp {
-}
-
-impl Ord for BlockOp {
-    /// Compare `BlockOp`s by LBA.
-    ///
-    /// This comparison is only correct for `BlockOp`s that are both on the same
-    /// side of the scheduler.  Comparing `BlockOp`s on different sides of the
-    /// scheduler would require knowledge of `Inner::last_lba`, and the `Ord`
-    /// trait doesn't allow that.
-    fn cmp(&self, other: &BlockOp) -> Ordering {
-        // Reverse the usual ordering, because we want to issue the minimum LBA
-        // first, and Rust provides a max heap but not a min heap.
-        other.lba.cmp(&self.lba).then_with(|| {
-            other.cmd.cmp(&self.cmd)
-        })
-    }
-}
-
-impl PartialEq for BlockOp {
-    fn eq(&self, other: &BlockOp) -> bool {
-        self.lba == other.lba && self.cmd == other.cmd
-    }
-}
-
-impl PartialOrd for BlockOp {
-    fn partial_cmp(&self, other: &BlockOp) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
+    pub sender: OpSender
 }
 
 impl BlockOp {
     pub fn erase_zone(start: LbaT, end: LbaT,
-                      sender: oneshot::Sender<()>) -> BlockOp {
-        BlockOp { lba: end, cmd: Cmd::EraseZone(start), sender }
+                      sender: OpSender) -> BlockOp {
+        BlockOp::new(end, Cmd::EraseZone(start), sender)
     }
 
     pub fn finish_zone(start: LbaT, end: LbaT,
-                       sender: oneshot::Sender<()>) -> BlockOp {
-        BlockOp { lba: end, cmd: Cmd::FinishZone(start), sender }
+                       sender: OpSender) -> BlockOp {
+        BlockOp::new(end, Cmd::FinishZone(start), sender)
     }
 
     pub fn len(&self) -> usize {
@@ -153,59 +220,441 @@ impl BlockOp {
         }
     }
 
-    pub fn open_zone(lba: LbaT, sender: oneshot::Sender<()>) -> BlockOp {
-        BlockOp { lba, cmd: Cmd::OpenZone, sender }
+    fn new(lba: LbaT, cmd: Cmd, sender: OpSender) -> BlockOp {
+        BlockOp {
+            lba,
+            cmd,
+            priority: PriorityClass::default(),
+            start: time::Instant::now(),
+            sender
+        }
     }
 
-    pub fn read_at(buf: IoVecMut, lba: LbaT,
-                   sender: oneshot::Sender<()>) -> BlockOp {
-        BlockOp { lba, cmd: Cmd::ReadAt(buf), sender}
+    pub fn open_zone(lba: LbaT, sender: OpSender) -> BlockOp {
+        BlockOp::new(lba, Cmd::OpenZone, sender)
     }
 
-    pub fn read_spacemap(buf: IoVecMut, lba: LbaT, idx: u32,
-                         sender: oneshot::Sender<()>) -> BlockOp
-    {
-        BlockOp { lba, cmd: Cmd::ReadSpacemap(buf, idx), sender}
+    pub fn read_at(buf: IoVecMut, lba: LbaT,
+                   sender: OpSender) -> BlockOp {
+        BlockOp::new(lba, Cmd::ReadAt(buf), sender)
     }
 
     pub fn readv_at(bufs: SGListMut, lba: LbaT,
-                    sender: oneshot::Sender<()>) -> BlockOp {
-        BlockOp { lba, cmd: Cmd::ReadvAt(bufs), sender}
+                    sender: OpSender) -> BlockOp {
+        BlockOp::new(lba, Cmd::ReadvAt(bufs), sender)
     }
 
-    pub fn sync_all(sender: oneshot::Sender<()>) -> BlockOp {
-        BlockOp { lba: 0, cmd: Cmd::SyncAll, sender}
+    pub fn sync_all(sender: OpSender) -> BlockOp {
+        BlockOp::new(0, Cmd::SyncAll, sender)
     }
 
     pub fn write_at(buf: IoVec, lba: LbaT,
-                    sender: oneshot::Sender<()>) -> BlockOp {
-        BlockOp { lba, cmd: Cmd::WriteAt(buf), sender}
+                    sender: OpSender) -> BlockOp {
+        BlockOp::new(lba, Cmd::WriteAt(buf), sender)
     }
 
     pub fn write_label(labeller: LabelWriter,
-                       sender: oneshot::Sender<()>) -> BlockOp {
-        BlockOp { lba: 0, cmd: Cmd::WriteLabel(labeller), sender}
+                       sender: OpSender) -> BlockOp {
+        BlockOp::new(0, Cmd::WriteLabel(labeller), sender)
+    }
+
+    pub fn writev_at(bufs: SGList, lba: LbaT,
+                     sender: OpSender) -> BlockOp {
+        BlockOp::new(lba, Cmd::WritevAt(bufs), sender)
     }
+}
+
+/// How long a read may wait under the `Deadline` scheduler before it gets
+/// issued out of order
+const READ_EXPIRY: time::Duration = time::Duration::from_millis(500);
+
+/// How many times a failed operation will be reissued, by default
+const DEFAULT_RETRIES: u32 = 2;
+
+/// The adaptive queue depth may grow to at most this multiple of the leaf's
+/// nominal optimum
+const MAX_QUEUE_DEPTH_FACTOR: u32 = 4;
 
-    pub fn write_spacemap(sglist: SGList, lba: LbaT, idx: u32, block: LbaT,
-                          sender: oneshot::Sender<()>) -> BlockOp
+/// If a window's mean service latency exceeds the baseline by more than this
+/// factor, then the device is saturated.
+const LATENCY_THRESHOLD: u64 = 2;
+
+/// Largest operation that will be created by merging adjacent operations, in
+/// bytes
+const MAX_MERGE: usize = 1 << 20;
+
+/// Sort key for pending operations.  Sorts by LBA, then by command type, then
+/// by arrival order.
+type OpKey = (LbaT, i32, u64);
+
+/// An I/O scheduling policy.
+///
+/// A `Scheduler` decides the order in which pending operations are issued to
+/// the `VdevLeaf`.  It never sees `sync_all` operations, nor anything that
+/// must strictly follow one; `Inner` handles those.  Whatever its policy, a
+/// `Scheduler` must not reorder writes or zone management operations relative
+/// to each other, because zoned devices require sequential writes.
+trait Scheduler {
+    /// How many operations are pending?
+    fn len(&self) -> usize;
+
+    /// Remove and return the next operation to issue, if any
+    fn pop(&mut self) -> Option<BlockOp>;
+
+    /// Remove and return the first pending operation at exactly `lba`, but
+    /// only if it satisfies `pred`.  Used to merge adjacent operations.
+    fn pop_at(&mut self, lba: LbaT, pred: &dyn Fn(&BlockOp) -> bool)
+        -> Option<BlockOp>;
+
+    /// Add a new pending operation
+    fn push(&mut self, op: BlockOp);
+}
+
+/// The C-LOOK scheduling algorithm.
+///
+/// Issues operations from the lowest LBA to the highest, then starts over at
+/// the lowest.  It guarantees that writes scheduled in LBA order will also be
+/// issued in LBA order.
+#[derive(Default)]
+struct CLook {
+    /// The last LBA issued an operation
+    last_lba: LbaT,
+
+    /// Breaks ties between operations with the same LBA and command type
+    next_seq: u64,
+
+    // Pending operations are stored in a BTreeMap rather than a pair of
+    // priority queues, because other schedulers need to remove operations out
+    // of order.
+    ops: BTreeMap<OpKey, BlockOp>,
+}
+
+impl CLook {
+    fn contains(&self, key: &OpKey) -> bool {
+        self.ops.contains_key(key)
+    }
+
+    /// Schedule `op`, and return the key by which it may later be removed
+    fn insert(&mut self, op: BlockOp) -> OpKey {
+        let key = (op.lba, op.cmd.discriminant(), self.next_seq);
+        self.next_seq += 1;
+        self.ops.insert(key, op);
+        key
+    }
+
+    /// Remove a specific operation, out of C-LOOK order.
+    ///
+    /// Does not change the scheduler's position.
+    fn remove(&mut self, key: &OpKey) -> Option<BlockOp> {
+        self.ops.remove(key)
+    }
+}
+
+impl Scheduler for CLook {
+    fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    fn pop(&mut self) -> Option<BlockOp> {
+        let key = self.ops.range((self.last_lba, i32::min_value(), 0)..)
+            .next()
+            // Ran out of operations ahead of the scheduler.  Go back to the
+            // beginning
+            .or_else(|| self.ops.iter().next())
+            .map(|(k, _)| *k)?;
+        self.last_lba = key.0;
+        self.ops.remove(&key)
+    }
+
+    fn pop_at(&mut self, lba: LbaT, pred: &dyn Fn(&BlockOp) -> bool)
+        -> Option<BlockOp>
     {
-        BlockOp { lba, cmd: Cmd::WriteSpacemap(sglist, idx, block), sender}
+        let key = self.ops.range((lba, i32::min_value(), 0)..)
+            .next()
+            .filter(|(k, op)| k.0 == lba && pred(op))
+            .map(|(k, _)| *k)?;
+        self.last_lba = lba;
+        self.ops.remove(&key)
     }
 
-    pub fn writev_at(bufs: SGList, lba: LbaT,
-                     sender: oneshot::Sender<()>) -> BlockOp {
-        BlockOp { lba, cmd: Cmd::WritevAt(bufs), sender}
+    fn push(&mut self, op: BlockOp) {
+        self.insert(op);
     }
 }
 
-struct Inner {
-    /// A VdevLeaf future that got delayed by an EAGAIN error.  We hold the
-    /// future around instead of spawning it into the reactor.
-    delayed: Option<(oneshot::Sender<()>, Box<VdevFut>)>,
+/// C-LOOK, except that reads which have waited too long are issued
+/// immediately, so they can't starve behind a long sweep of writes.
+///
+/// Only reads have deadlines.  Expediting a read doesn't change the
+/// scheduler's position, so everything else is still issued in C-LOOK order.
+struct Deadline {
+    clook: CLook,
+
+    /// How long a read may wait before it gets issued out of order
+    expiry: time::Duration,
+
+    /// Pending reads in arrival order, with their deadlines.  Reads that have
+    /// already been issued in C-LOOK order are lazily discarded.
+    reads: VecDeque<(time::Instant, OpKey)>,
+}
+
+impl Deadline {
+    fn new(expiry: time::Duration) -> Self {
+        Deadline {
+            clook: CLook::default(),
+            expiry,
+            reads: VecDeque::new()
+        }
+    }
+}
+
+impl Scheduler for Deadline {
+    fn len(&self) -> usize {
+        self.clook.len()
+    }
+
+    fn pop(&mut self) -> Option<BlockOp> {
+        let now = time::Instant::now();
+        while let Some(&(deadline, key)) = self.reads.front() {
+            if !self.clook.contains(&key) {
+                // Already issued
+                self.reads.pop_front();
+            } else if deadline <= now {
+                self.reads.pop_front();
+                return self.clook.remove(&key);
+            } else {
+                break;
+            }
+        }
+        self.clook.pop()
+    }
+
+    fn pop_at(&mut self, lba: LbaT, pred: &dyn Fn(&BlockOp) -> bool)
+        -> Option<BlockOp>
+    {
+        // Expedited reads that get merged will be lazily discarded by pop
+        self.clook.pop_at(lba, pred)
+    }
+
+    fn push(&mut self, op: BlockOp) {
+        let is_read = op.cmd.is_read();
+        let key = self.clook.insert(op);
+        if is_read {
+            self.reads.push_back((time::Instant::now() + self.expiry, key));
+        }
+    }
+}
+
+/// Issues reads in order of their `PriorityClass`, and in C-LOOK order within
+/// each class.
+///
+/// Only reads are prioritized.  Everything else is scheduled alongside
+/// `Normal` reads, so writes and zone management operations are never
+/// reordered relative to each other.  As a consequence, a `Background` read may
+/// be issued after an `erase_zone` that was scheduled later.  Callers must not
+/// erase a zone while reads from it are still pending.
+#[derive(Default)]
+struct Priority {
+    queues: BTreeMap<PriorityClass, CLook>
+}
+
+impl Scheduler for Priority {
+    fn len(&self) -> usize {
+        self.queues.values().map(CLook::len).sum()
+    }
+
+    fn pop(&mut self) -> Option<BlockOp> {
+        self.queues.values_mut()
+            .rev()
+            .filter_map(CLook::pop)
+            .next()
+    }
+
+    fn pop_at(&mut self, lba: LbaT, pred: &dyn Fn(&BlockOp) -> bool)
+        -> Option<BlockOp>
+    {
+        self.queues.values_mut()
+            .rev()
+            .filter_map(|q| q.pop_at(lba, pred))
+            .next()
+    }
+
+    fn push(&mut self, op: BlockOp) {
+        let class = if op.cmd.is_read() {
+            op.priority
+        } else {
+            PriorityClass::Normal
+        };
+        self.queues.entry(class).or_default().push(op);
+    }
+}
+
+/// The I/O scheduling policies available to a `VdevBlock`
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SchedulerPolicy {
+    /// Issue operations from lowest LBA to highest, then start over at the
+    /// lowest.  Gives the best throughput.
+    CLook,
+    /// Like `CLook`, but reads that have waited too long get issued
+    /// immediately
+    Deadline,
+    /// Issue reads in order of their `PriorityClass`, and in C-LOOK order
+    /// within each class
+    Priority,
+}
 
-    /// Max commands that will be simultaneously queued to the VdevLeaf
-    optimum_queue_depth: u32,
+impl SchedulerPolicy {
+    fn scheduler(self) -> Box<dyn Scheduler> {
+        match self {
+            SchedulerPolicy::CLook => Box::new(CLook::default()),
+            SchedulerPolicy::Deadline => Box::new(Deadline::new(READ_EXPIRY)),
+            SchedulerPolicy::Priority => Box::new(Priority::default()),
+        }
+    }
+}
+
+impl Default for SchedulerPolicy {
+    fn default() -> Self {
+        SchedulerPolicy::CLook
+    }
+}
+
+impl TryFrom<&str> for SchedulerPolicy {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self, Error> {
+        match s {
+            "clook" => Ok(SchedulerPolicy::CLook),
+            "deadline" => Ok(SchedulerPolicy::Deadline),
+            "priority" => Ok(SchedulerPolicy::Priority),
+            _ => Err(Error::EINVAL)
+        }
+    }
+}
+
+/// Adjusts the queue depth at runtime, using additive increase and
+/// multiplicative decrease.
+///
+/// Completions are grouped into windows of one queue's worth.  At the end of
+/// each window, if the mean service latency has grown well beyond the lowest
+/// recently observed, then the device is saturated and the queue depth gets
+/// cut.  Otherwise, if the queue ever filled up during the window, then the
+/// device might benefit from more concurrency, so the queue depth grows by one.
+#[derive(Debug)]
+struct QueueDepthTuner {
+    /// Lowest mean service latency of any recent window, in microseconds
+    baseline: Option<u64>,
+    /// Operations completed during the current window
+    completions: u32,
+    /// Current queue depth limit
+    depth: u32,
+    /// Upper bound for `depth`
+    max: u32,
+    /// Did the queue ever fill up during the current window?
+    saturated: bool,
+    /// Total service latency of the current window, in microseconds
+    total_us: u64,
+}
+
+impl QueueDepthTuner {
+    /// Account for one successfully completed operation
+    ///
+    /// # Parameters
+    ///
+    /// - `latency`:    Time since the operation was issued to the leaf
+    /// - `full`:       Was the queue full when the operation completed?
+    fn complete(&mut self, latency: time::Duration, full: bool) {
+        self.completions += 1;
+        self.total_us += latency.as_micros() as u64;
+        self.saturated |= full;
+        if self.completions < self.depth {
+            return;
+        }
+        let mean = self.total_us / u64::from(self.completions);
+        // Let the baseline drift upwards, so it can follow changes in the
+        // workload
+        let baseline = self.baseline
+            .map(|b| (b + b / 16 + 1).min(mean))
+            .unwrap_or(mean);
+        self.baseline = Some(baseline);
+        if mean > baseline * LATENCY_THRESHOLD {
+            self.congested();
+        } else {
+            if self.saturated && self.depth < self.max {
+                self.depth += 1;
+            }
+            self.reset();
+        }
+    }
+
+    /// Back off, because the device is overloaded
+    fn congested(&mut self) {
+        self.depth = (self.depth * 3 / 4).max(1);
+        self.reset();
+    }
+
+    fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// # Parameters
+    ///
+    /// - `nominal`:    The leaf's static idea of its optimum queue depth, used
+    ///                 as a starting point.
+    fn new(nominal: u32) -> Self {
+        let depth = nominal.max(1);
+        QueueDepthTuner {
+            baseline: None,
+            completions: 0,
+            depth,
+            max: depth * MAX_QUEUE_DEPTH_FACTOR,
+            saturated: false,
+            total_us: 0
+        }
+    }
+
+    /// Start a new window
+    fn reset(&mut self) {
+        self.completions = 0;
+        self.saturated = false;
+        self.total_us = 0;
+    }
+}
+
+/// Bookkeeping for an operation that has been issued to the `VdevLeaf`
+struct IssuedOp {
+    /// A copy of the operation's command, if it may be reissued on failure
+    cmd: Option<Cmd>,
+    class: StatClass,
+    /// When the operation was most recently issued to the `VdevLeaf`
+    issued: time::Instant,
+    lba: LbaT,
+    /// Data bytes transferred by the operation
+    len: usize,
+    /// How many more times the operation may be reissued
+    retries: u32,
+    /// Every caller waiting on this operation, more than one if adjacent
+    /// operations were merged.  Each comes with the time when its operation
+    /// was submitted to the `VdevBlock`.
+    senders: Vec<(OpSender, time::Instant)>
+}
+
+impl IssuedOp {
+    /// Deliver the operation's result to every caller
+    fn send(self, result: Result<(), Error>) {
+        for (sender, _) in self.senders {
+            // Ignore send errors.  They just mean that the caller dropped its
+            // future.
+            let _ = sender.send(result);
+        }
+    }
+}
+
+struct Inner {
+    /// VdevLeaf futures that got delayed by an EAGAIN error, or that must be
+    /// retried.  We hold the futures around instead of spawning them into the
+    /// reactor.
+    delayed: VecDeque<(IssuedOp, Box<VdevFut>)>,
 
     /// Current queue depth
     queue_depth: u32,
@@ -213,66 +662,92 @@ struct Inner {
     /// Underlying device
     pub leaf: VdevLeaf,
 
-    /// The last LBA issued an operation
-    last_lba: LbaT,
+    /// How many times a failed operation will be reissued before its error is
+    /// returned to the caller.  Reads are never reissued, nor are writes to
+    /// sequential zones.
+    retries: u32,
 
     /// If true, then we are preparing to issue sync_all to the underlying
     /// storage
     syncing: bool,
 
-    // Pending operations are stored in a pair of priority queues.  They _could_
-    // be stored in a single queue, _if_ the priority queue's comparison
-    // function were allowed to be stateful, as in C++'s STL.  However, Rust's
-    // standard library does not have any way to create a priority queueful with
-    // a stateful comparison function.
-    /// Pending operations ahead of the scheduler's current LBA.
-    ahead: BinaryHeap<BlockOp>,
+    /// Pending operations that may be freely reordered
+    scheduler: Box<dyn Scheduler>,
 
-    /// Pending operations behind the scheduler's current LBA.
-    behind: BinaryHeap<BlockOp>,
+    /// Counters for completed operations
+    stats: IoStats,
+
+    /// Enforces I/O rate limits
+    throttle: Throttle,
+
+    /// Operations that were popped from the scheduler, but must wait for the
+    /// throttle, along with the senders of any operations merged into them
+    throttled: VecDeque<(BlockOp, Vec<(OpSender, time::Instant)>)>,
+
+    /// Decides the max commands that will be simultaneously queued to the
+    /// VdevLeaf
+    tuner: QueueDepthTuner,
 
     /// Pending operations that should strictly follow a sync_all (possibly
     /// including other sync_all operations).  We store these in a FIFO because
     /// we can't correctly schedule them until the sync_all is complete.
     after_sync: VecDeque<BlockOp>,
 
+    /// Operations that take longer than this will fail with `ETIMEDOUT`
+    timeout: Option<time::Duration>,
+
+    /// When a timer is already scheduled to call `issue_all`, if any
+    wakeup: Option<time::Instant>,
+
     /// A `Weak` pointer back to `self`.  Used for closures that require a
     /// reference to `self`, but also require `'static` lifetime.
     weakself: Weak<RefCell<Inner>>
 }
 
 impl Inner {
+    /// Finish an issued operation, reissuing it if it failed and is allowed
+    /// to retry.
+    fn complete(&mut self, mut op: IssuedOp, result: Result<(), Error>) {
+        if result.is_err() && op.retries > 0 {
+            if let Some(cmd) = op.cmd.take() {
+                op.retries -= 1;
+                op.cmd = cmd.try_clone();
+                op.issued = time::Instant::now();
+                let fut = self.leaf_fut(op.lba, cmd);
+                if let Some(d) = self.issue_fut(op, fut) {
+                    self.delayed.push_back(d);
+                }
+                return;
+            }
+        }
+        if result.is_ok() {
+            let full = self.queue_depth >= self.tuner.depth();
+            self.tuner.complete(op.issued.elapsed(), full);
+        }
+        self.queue_depth -= 1;
+        self.record(&op, result.is_ok());
+        op.send(result);
+    }
+
     /// Issue as many scheduled operations as possible
-    // Use the C-LOOK scheduling algorithm.  It guarantees that writes scheduled
-    // in LBA order will also be issued in LBA order.
     fn issue_all(&mut self) {
-        while self.queue_depth < self.optimum_queue_depth {
-            let delayed = self.delayed.take();
-            let (sender, fut) = if let Some((sender, fut)) = delayed {
-                (sender, fut)
-            } else if let Some(op) = self.pop_op() {
-                self.make_fut(op)
+        while self.queue_depth < self.tuner.depth() {
+            let (op, fut) = if let Some(d) = self.delayed.pop_front() {
+                d
+            } else if let Some((op, merged)) = self.next_admitted() {
+                self.make_fut(op, merged)
             } else {
-                // Ran out of pending operations
+                // Ran out of pending operations, or they're all throttled
                 break;
             };
-            if let Some(d) = self.issue_fut(sender, fut) {
-                self.delayed = Some(d);
+            if let Some(d) = self.issue_fut(op, fut) {
+                self.delayed.push_front(d);
                 if self.queue_depth == 1 {
                     // Can't issue any I/O at all!  This means that other
                     // processes outside of bfffs's control are using too many
                     // disk resources.  In this case, the only thing we can do
                     // is sleep and try again later.
-                    let weakself = self.weakself.clone();
-                    let duration = time::Duration::from_millis(10);
-                    let wakeup_time = time::Instant::now() + duration;
-                    let delay_fut = timer::Delay::new(wakeup_time)
-                    .map(move |_| {
-                        let inner = weakself.upgrade().expect(
-                            "VdevBlock dropped with outstanding I/O");
-                        inner.borrow_mut().issue_all();
-                    }).map_err(Error::unhandled);
-                    tokio_current_thread::spawn(delay_fut);
+                    self.issue_later(time::Duration::from_millis(10));
                 }
                 break;
             }
@@ -282,15 +757,34 @@ impl Inner {
         // again.
     }
 
+    /// Call `issue_all` again after `duration`
+    fn issue_later(&mut self, duration: time::Duration) {
+        let wakeup_time = time::Instant::now() + duration;
+        if self.wakeup.map(|w| w <= wakeup_time).unwrap_or(false) {
+            // An earlier timer will do
+            return;
+        }
+        self.wakeup = Some(wakeup_time);
+        let weakself = self.weakself.clone();
+        let delay_fut = timer::Delay::new(wakeup_time)
+        .map(move |_| {
+            let inner = weakself.upgrade().expect(
+                "VdevBlock dropped with outstanding I/O");
+            let mut guard = inner.borrow_mut();
+            if guard.wakeup == Some(wakeup_time) {
+                guard.wakeup = None;
+            }
+            guard.issue_all();
+        }).map_err(Error::unhandled);
+        tokio_current_thread::spawn(delay_fut);
+    }
+
     /// Immediately issue one I/O future.
     ///
     /// Returns a delayed operation, if there were insufficient resources to
     /// immediately issue the future.
-    fn issue_fut(&mut self, sender: oneshot::Sender<()>, mut fut: Box<VdevFut>)
-        -> Option<(oneshot::Sender<()>, Box<VdevFut>)> {
-
-        let inner = self.weakself.upgrade().expect(
-            "VdevBlock dropped with outstanding I/O");
+    fn issue_fut(&mut self, op: IssuedOp, mut fut: Box<VdevFut>)
+        -> Option<(IssuedOp, Box<VdevFut>)> {
 
         // Certain errors, like EAGAIN, happen synchronously.  If the future is
         // going to fail synchronously, then we want to handle the error
@@ -298,75 +792,212 @@ impl Inner {
         // reactor.
         match fut.poll() {
             Err(Error::EAGAIN) => {
-                // Out of resources to issue this future.  Delay it
-                return Some((sender, fut));
+                // Out of resources to issue this future.  Delay it, and
+                // don't try so hard in the future.
+                self.tuner.congested();
+                return Some((op, fut));
             },
-            Err(e) => panic!("Unhandled error {:?}", e),
-            Ok(r) => {
-                match r {
-                    Async::NotReady => {
-                        tokio_current_thread::spawn(
-                            fut.and_then(move |_| {
-                                sender.send(()).unwrap();
-                                inner.borrow_mut().queue_depth -= 1;
-                                inner.borrow_mut().issue_all();
-                                Ok(())
-                            })
-                            .map_err(|e| {
-                                panic!("Unhandled error {:?}", e);
-                            })
-                        );
-                    },
-                    Async::Ready(_) => {
-                        // This normally doesn't happen, but it can happen on a
-                        // heavily laden system or one with very fast storage.
-                        sender.send(()).unwrap();
-                        self.queue_depth -= 1;
-                    }
-                }
+            Err(e) => self.complete(op, Err(e)),
+            Ok(Async::NotReady) => {
+                let inner = self.weakself.upgrade().expect(
+                    "VdevBlock dropped with outstanding I/O");
+                let watcher = Inner::watch(inner, op, fut, self.timeout);
+                tokio_current_thread::spawn(watcher);
+            },
+            Ok(Async::Ready(_)) => {
+                // This normally doesn't happen, but it can happen on a
+                // heavily laden system or one with very fast storage.
+                self.complete(op, Ok(()));
             }
         }
         None
     }
 
-    /// Create a future from a BlockOp, but don't spawn it yet
-    fn make_fut(&mut self, block_op: BlockOp)
-        -> (oneshot::Sender<()>, Box<VdevFut>) {
-
-        self.queue_depth += 1;
-        let lba = block_op.lba;
-
-        // In the context where this is called, we can't return a future.  So we
-        // have to spawn it into the event loop manually
-        let fut = match block_op.cmd {
+    /// Create a `VdevLeaf` future for a single command
+    fn leaf_fut(&self, lba: LbaT, cmd: Cmd) -> Box<VdevFut> {
+        match cmd {
             Cmd::WriteAt(iovec) => self.leaf.write_at(iovec, lba),
             Cmd::ReadAt(iovec_mut) => self.leaf.read_at(iovec_mut, lba),
             Cmd::WritevAt(sglist) => self.leaf.writev_at(sglist, lba),
-            Cmd::ReadSpacemap(iovec_mut, idx) =>
-                self.leaf.read_spacemap(iovec_mut, idx),
             Cmd::ReadvAt(sglist_mut) => self.leaf.readv_at(sglist_mut, lba),
             Cmd::EraseZone(start) => self.leaf.erase_zone(start),
             Cmd::FinishZone(start) => self.leaf.finish_zone(start),
             Cmd::OpenZone => self.leaf.open_zone(lba),
             Cmd::WriteLabel(labeller) => self.leaf.write_label(labeller),
-            Cmd::WriteSpacemap(sglist, idx, block) =>
-                self.leaf.write_spacemap(sglist, idx, block),
             Cmd::SyncAll => self.leaf.sync_all(),
+        }
+    }
+
+    /// Create a future from a BlockOp, but don't spawn it yet
+    ///
+    /// `merged` holds the senders of any other operations that were merged
+    /// into `block_op`.
+    fn make_fut(&mut self, block_op: BlockOp,
+                mut merged: Vec<(OpSender, time::Instant)>)
+        -> (IssuedOp, Box<VdevFut>)
+    {
+        self.queue_depth += 1;
+        let lba = block_op.lba;
+        // A write to a sequential zone can't be retried, because later writes
+        // to the same zone may already have been issued.  Reissuing it would
+        // land behind the write pointer.
+        let retryable = match block_op.cmd {
+            Cmd::WriteAt(_) | Cmd::WritevAt(_) =>
+                self.leaf.is_conventional(lba),
+            _ => true
+        };
+        let cmd = if self.retries > 0 && retryable {
+            block_op.cmd.try_clone()
+        } else {
+            None
+        };
+        let mut op = IssuedOp {
+            cmd,
+            class: block_op.cmd.stat_class(),
+            issued: time::Instant::now(),
+            lba,
+            len: block_op.len(),
+            retries: self.retries,
+            senders: Vec::with_capacity(merged.len() + 1)
+        };
+        op.senders.push((block_op.sender, block_op.start));
+        op.senders.append(&mut merged);
+
+        // In the context where this is called, we can't return a future.  So we
+        // have to spawn it into the event loop manually
+        let fut = self.leaf_fut(lba, block_op.cmd);
+        (op, fut)
+    }
+
+    /// Merge any pending operations that are contiguous with `block_op` and
+    /// go in the same direction, so they can be issued as a single vectored
+    /// command.
+    ///
+    /// Returns the merged operation, and the senders of the operations that
+    /// were absorbed into it.
+    fn merge(&mut self, mut block_op: BlockOp)
+        -> (BlockOp, Vec<(OpSender, time::Instant)>)
+    {
+        let mut merged = Vec::new();
+        let class = match block_op.cmd.merge_class() {
+            Some(class) => class,
+            None => return (block_op, merged)
+        };
+        let leaf = &self.leaf;
+        let lba = block_op.lba;
+        let priority = block_op.priority;
+        let mut len = block_op.len();
+        while len % BYTES_PER_LBA == 0 {
+            let next_lba = lba + (len / BYTES_PER_LBA) as LbaT;
+            let pred = |op: &BlockOp| {
+                op.cmd.merge_class() == Some(class) &&
+                    op.priority == priority &&
+                    len + op.len() <= MAX_MERGE &&
+                    // Zoned devices can't handle I/O across a zone boundary
+                    leaf.lba2zone(next_lba) == leaf.lba2zone(lba)
+            };
+            let next = match self.scheduler.pop_at(next_lba, &pred) {
+                Some(op) => op,
+                None => break
+            };
+            len += next.len();
+            block_op.cmd = block_op.cmd.append(next.cmd);
+            merged.push((next.sender, next.start));
+        }
+        (block_op, merged)
+    }
+
+    /// Get the next operation that the throttle will admit, merged with any
+    /// adjacent operations.
+    ///
+    /// Operations that must wait are set aside.  A background read can wait
+    /// without holding up anything else.  But if any other operation must
+    /// wait, then nothing more may be issued, lest writes get reordered.
+    fn next_admitted(&mut self)
+        -> Option<(BlockOp, Vec<(OpSender, time::Instant)>)>
+    {
+        let now = time::Instant::now();
+        let mut i = 0;
+        while i < self.throttled.len() {
+            let op = &self.throttled[i].0;
+            match Inner::admit(&mut self.throttle, op, now) {
+                Ok(()) => return self.throttled.remove(i),
+                Err(wait) => {
+                    let background = Inner::may_wait(op);
+                    self.issue_later(wait);
+                    if !background {
+                        return None;
+                    }
+                    i += 1;
+                }
+            }
+        }
+        while let Some(op) = self.pop_op() {
+            let (op, merged) = self.merge(op);
+            match Inner::admit(&mut self.throttle, &op, now) {
+                Ok(()) => return Some((op, merged)),
+                Err(wait) => {
+                    let background = Inner::may_wait(&op);
+                    self.throttled.push_back((op, merged));
+                    self.issue_later(wait);
+                    if !background {
+                        return None;
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// May this operation be set aside while later ones get issued?  Only
+    /// background reads may.  Writes must be issued in order, whatever their
+    /// priority.
+    fn may_wait(op: &BlockOp) -> bool {
+        op.priority == PriorityClass::Background && op.cmd.is_read()
+    }
+
+    /// Charge an operation to the throttle, if it's subject to rate limits
+    fn admit(throttle: &mut Throttle, op: &BlockOp, now: time::Instant)
+        -> Result<(), time::Duration>
+    {
+        let dir = match op.cmd.merge_class() {
+            Some(StatClass::Read) => Direction::Read,
+            Some(StatClass::Write) => Direction::Write,
+            // Only ordinary data reads and writes are rate limited
+            _ => return Ok(())
         };
-        (block_op.sender, fut)
+        let background = op.priority == PriorityClass::Background;
+        throttle.admit(dir, background, op.len(), now)
+    }
+
+    /// Update the statistics for a finished operation
+    fn record(&mut self, op: &IssuedOp, success: bool) {
+        let nops = op.senders.len() as u64;
+        if !success {
+            self.stats.errors += nops;
+            return;
+        }
+        let latencies = op.senders.iter().map(|(_, start)| start.elapsed());
+        match op.class {
+            StatClass::Read => {
+                self.stats.read_ops += nops;
+                self.stats.read_bytes += op.len as u64;
+                latencies.for_each(|l| self.stats.read_latency.record(l));
+            },
+            StatClass::Write => {
+                self.stats.write_ops += nops;
+                self.stats.write_bytes += op.len as u64;
+                latencies.for_each(|l| self.stats.write_latency.record(l));
+            },
+            StatClass::Other => {
+                self.stats.other_ops += nops;
+            }
+        }
     }
 
     /// Get the next pending operation, if any
     fn pop_op(&mut self) -> Option<BlockOp> {
-        if let Some(op) = self.ahead.pop() {
-            self.last_lba = op.lba;
-            Some(op)
-        } else if !self.behind.is_empty() {
-            // Ran out of operations ahead of the scheduler.  Go back to the
-            // beginning
-            mem::swap(&mut self.behind, &mut self.ahead);
-            let op = self.ahead.pop().unwrap();
-            self.last_lba = op.lba;
+        if let Some(op) = self.scheduler.pop() {
             Some(op)
         } else if self.syncing {
             debug_assert!(!self.after_sync.is_empty());
@@ -400,10 +1031,8 @@ impl Inner {
         if block_op.cmd == Cmd::SyncAll || self.syncing {
             self.syncing = true;
             self.after_sync.push_back(block_op);
-        } else if block_op.lba >= self.last_lba {
-            self.ahead.push(block_op);
         } else {
-            self.behind.push(block_op);
+            self.scheduler.push(block_op);
         }
     }
 
@@ -412,12 +1041,66 @@ impl Inner {
         self.sched(block_op);
         self.issue_all();
     }
+
+    /// Drive an issued future to completion, enforcing the timeout if any.
+    ///
+    /// An operation that times out can't be cancelled, because the kernel may
+    /// still be using its buffers.  Instead, we report `ETIMEDOUT` right away
+    /// and keep the operation in the queue depth until it really finishes.
+    fn watch(inner: Rc<RefCell<Inner>>, op: IssuedOp, fut: Box<VdevFut>,
+             timeout: Option<time::Duration>)
+        -> Box<dyn Future<Item=(), Error=()>>
+    {
+        let timeout = match timeout {
+            None => {
+                return Box::new(fut.then(move |r| {
+                    let mut guard = inner.borrow_mut();
+                    guard.complete(op, r);
+                    guard.issue_all();
+                    Ok::<(), ()>(())
+                }));
+            },
+            Some(t) => t
+        };
+        let delay = timer::Delay::new(time::Instant::now() + timeout);
+        Box::new(fut.select2(delay).then(move |r| {
+            let fut = match r {
+                Ok(Either::A(_)) => {
+                    let mut guard = inner.borrow_mut();
+                    guard.complete(op, Ok(()));
+                    guard.issue_all();
+                    return Either::A(future::ok(()));
+                },
+                Err(Either::A((e, _))) => {
+                    let mut guard = inner.borrow_mut();
+                    guard.complete(op, Err(e));
+                    guard.issue_all();
+                    return Either::A(future::ok(()));
+                },
+                // A timer error means that the timer is gone, so we can't
+                // enforce the timeout anymore.  Treat it like an expiration.
+                Ok(Either::B((_, fut))) | Err(Either::B((_, fut))) => fut
+            };
+            {
+                let mut guard = inner.borrow_mut();
+                guard.record(&op, false);
+                guard.tuner.congested();
+            }
+            op.send(Err(Error::ETIMEDOUT));
+            Either::B(fut.then(move |_| {
+                let mut guard = inner.borrow_mut();
+                guard.queue_depth -= 1;
+                guard.issue_all();
+                Ok::<(), ()>(())
+            }))
+        }))
+    }
 }
 
 struct VdevBlockFut {
     block_op: Option<BlockOp>,
     inner: Rc<RefCell<Inner>>,
-    receiver: oneshot::Receiver<()>,
+    receiver: oneshot::Receiver<Result<(), Error>>,
 }
 
 impl Future for VdevBlockFut {
@@ -429,7 +1112,11 @@ impl Future for VdevBlockFut {
             let block_op = self.block_op.take().unwrap();
             self.inner.borrow_mut().sched_and_issue(block_op);
         }
-        self.receiver.poll().or(Err(Error::EPIPE))
+        match self.receiver.poll() {
+            Ok(Async::Ready(r)) => r.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => Err(Error::EPIPE)
+        }
     }
 }
 
@@ -443,9 +1130,6 @@ pub struct VdevBlock {
 
     /// Usable size of the vdev, in LBAs
     size:   LbaT,
-
-    /// Size of a single spacemap as stored in the leaf vdev
-    spacemap_space:  LbaT
 }
 
 impl VdevBlock {
@@ -490,7 +1174,7 @@ impl VdevBlock {
     {
         // The zone must already be closed, but VdevBlock doesn't keep enough
         // information to assert that
-        let (sender, receiver) = oneshot::channel::<()>();
+        let (sender, receiver) = oneshot::channel();
         let block_op = BlockOp::erase_zone(start, end, sender);
 
         // Sanity check LBAs
@@ -515,7 +1199,7 @@ impl VdevBlock {
     pub fn finish_zone(&self, start: LbaT, end: LbaT)
         -> impl Future<Item=(), Error=Error>
     {
-        let (sender, receiver) = oneshot::channel::<()>();
+        let (sender, receiver) = oneshot::channel();
         let block_op = BlockOp::finish_zone(start, end, sender);
 
         // Sanity check LBAs
@@ -534,7 +1218,9 @@ impl VdevBlock {
     }
 
     fn new_fut(&self, block_op: BlockOp,
-               receiver: oneshot::Receiver<()>) -> VdevBlockFut {
+               receiver: oneshot::Receiver<Result<(), Error>>)
+        -> VdevBlockFut
+    {
         VdevBlockFut {
             block_op: Some(block_op),
             inner: self.inner.clone(),
@@ -549,7 +1235,7 @@ impl VdevBlock {
     pub fn open_zone(&self, start: LbaT)
         -> impl Future<Item=(), Error=Error>
     {
-        let (sender, receiver) = oneshot::channel::<()>();
+        let (sender, receiver) = oneshot::channel();
         let block_op = BlockOp::open_zone(start, sender);
 
         // Sanity check LBA
@@ -565,29 +1251,44 @@ impl VdevBlock {
         self.new_fut(block_op, receiver)
     }
 
+    /// Snapshot this device's I/O statistics
+    pub fn iostats(&self) -> VdevStats {
+        let inner = self.inner.borrow();
+        let mut stats = inner.stats;
+        let throttled = inner.throttled.iter()
+            .map(|(_, merged)| 1 + merged.len() as u32)
+            .sum::<u32>();
+        stats.queue_depth = inner.queue_depth + inner.scheduler.len() as u32 +
+            inner.after_sync.len() as u32 + throttled;
+        VdevStats::leaf(inner.leaf.uuid(), stats)
+    }
+
     /// Instantiate a new VdevBlock from an existing VdevLeaf
     ///
     /// * `leaf`    An already-open underlying VdevLeaf 
     pub fn new(leaf: VdevLeaf) -> Self {
         let size = leaf.size();
-        let spacemap_space = leaf.spacemap_space();
+        let tuner = QueueDepthTuner::new(leaf.optimum_queue_depth());
         let inner = Rc::new(RefCell::new(Inner {
-            delayed: None,
-            optimum_queue_depth: leaf.optimum_queue_depth(),
+            delayed: VecDeque::new(),
             queue_depth: 0,
             leaf,
-            last_lba: 0,
+            retries: DEFAULT_RETRIES,
             syncing: false,
+            scheduler: SchedulerPolicy::default().scheduler(),
+            stats: IoStats::default(),
+            throttle: Throttle::default(),
+            throttled: VecDeque::new(),
+            tuner,
             after_sync: VecDeque::new(),
-            ahead: BinaryHeap::new(),
-            behind: BinaryHeap::new(),
+            timeout: None,
+            wakeup: None,
             weakself: Weak::new()
         }));    // LCOV_EXCL_LINE   kcov false negative
         inner.borrow_mut().weakself = Rc::downgrade(&inner);
         VdevBlock {
             inner,
-            size,
-            spacemap_space
+            size
         }
     }
 
@@ -614,27 +1315,15 @@ impl VdevBlock {
 
     /// Asynchronously read a contiguous portion of the vdev.
     ///
-    /// Return the number of bytes actually read.
-    pub fn read_at(&self, buf: IoVecMut, lba: LbaT)
+    /// Return the number of bytes actually read.  `priority` is a hint to the
+    /// I/O scheduler.
+    pub fn read_at(&self, buf: IoVecMut, lba: LbaT, priority: PriorityClass)
         -> impl Future<Item=(), Error=Error>
     {
         self.check_iovec_bounds(lba, &buf);
-        let (sender, receiver) = oneshot::channel::<()>();
-        let block_op = BlockOp::read_at(buf, lba, sender);
-        self.new_fut(block_op, receiver)
-    }
-
-    /// Read the entire serialized spacemap.  `idx` selects which spacemap to
-    /// read, and should match whichever label is being read concurrently.
-    pub fn read_spacemap(&self, buf: IoVecMut, idx: u32)
-        -> impl Future<Item=(), Error=Error>
-    {
-        let (sender, receiver) = oneshot::channel::<()>();
-        // lba is for sorting purposes only.  It should sort before any other
-        // write operation, and different read_spacemap operations should sort
-        // in the same order as their true LBA order.
-        let lba = 1 + self.spacemap_space * LbaT::from(idx);
-        let block_op = BlockOp::read_spacemap(buf, lba, idx, sender);
+        let (sender, receiver) = oneshot::channel();
+        let mut block_op = BlockOp::read_at(buf, lba, sender);
+        block_op.priority = priority;
         self.new_fut(block_op, receiver)
     }
 
@@ -646,24 +1335,64 @@ impl VdevBlock {
     ///
     /// * `bufs`	Scatter-gather list of buffers to receive data
     /// * `lba`     LBA from which to read
-    pub fn readv_at(&self, bufs: SGListMut, lba: LbaT)
+    /// * `priority`    Hint to the I/O scheduler
+    pub fn readv_at(&self, bufs: SGListMut, lba: LbaT,
+                    priority: PriorityClass)
         -> impl Future<Item=(), Error=Error>
     {
         self.check_sglist_bounds(lba, &bufs);
-        let (sender, receiver) = oneshot::channel::<()>();
-        let block_op = BlockOp::readv_at(bufs, lba, sender);
+        let (sender, receiver) = oneshot::channel();
+        let mut block_op = BlockOp::readv_at(bufs, lba, sender);
+        block_op.priority = priority;
         self.new_fut(block_op, receiver)
     }
 
+    /// The I/O rate limits currently in effect
+    pub fn limits(&self) -> IoLimits {
+        self.inner.borrow().throttle.limits()
+    }
+
+    /// Change the I/O rate limits.  They take effect immediately.
+    pub fn set_limits(&self, limits: IoLimits) {
+        let mut inner = self.inner.borrow_mut();
+        inner.throttle.set_limits(limits);
+        inner.issue_all();
+    }
+
+    /// Set how many times a failed write or zone operation will be reissued
+    /// before its error is returned.  Failed reads are never reissued; the
+    /// RAID layer can reconstruct their data instead.
+    pub fn set_retries(&self, retries: u32) {
+        self.inner.borrow_mut().retries = retries;
+    }
+
+    /// Change the I/O scheduling policy.  Pending operations are preserved.
+    pub fn set_scheduler(&self, policy: SchedulerPolicy) {
+        let mut inner = self.inner.borrow_mut();
+        let mut scheduler = policy.scheduler();
+        while let Some(op) = inner.scheduler.pop() {
+            scheduler.push(op);
+        }
+        inner.scheduler = scheduler;
+    }
+
+    /// Set the per-operation timeout, or disable it with `None`.  Operations
+    /// that exceed it will fail with `ETIMEDOUT`, and will not be retried.
+    pub fn set_timeout(&self, timeout: Option<time::Duration>) {
+        self.inner.borrow_mut().timeout = timeout;
+    }
+
     /// Asynchronously write a contiguous portion of the vdev.
     ///
-    /// Returns nothing on success, and on error on failure
-    pub fn write_at(&self, buf: IoVec, lba: LbaT)
+    /// Returns nothing on success, and on error on failure.  `priority` is a
+    /// hint to the I/O throttle.  Writes are never reordered by priority.
+    pub fn write_at(&self, buf: IoVec, lba: LbaT, priority: PriorityClass)
         -> impl Future<Item=(), Error=Error>
     {
         self.check_iovec_bounds(lba, &buf);
-        let (sender, receiver) = oneshot::channel::<()>();
-        let block_op = BlockOp::write_at(buf, lba, sender);
+        let (sender, receiver) = oneshot::channel();
+        let mut block_op = BlockOp::write_at(buf, lba, sender);
+        block_op.priority = priority;
         assert_eq!(block_op.len() % BYTES_PER_LBA, 0,
             "VdevBlock does not support fragmentary writes");
         self.new_fut(block_op, receiver)
@@ -672,23 +1401,11 @@ impl VdevBlock {
     pub fn write_label(&self, labeller: LabelWriter)
         -> impl Future<Item=(), Error=Error>
     {
-        let (sender, receiver) = oneshot::channel::<()>();
+        let (sender, receiver) = oneshot::channel();
         let block_op = BlockOp::write_label(labeller, sender);
         self.new_fut(block_op, receiver)
     }
 
-    pub fn write_spacemap(&self, sglist: SGList, idx: u32, block: LbaT)
-        ->  impl Future<Item=(), Error=Error>
-    {
-        let (sender, receiver) = oneshot::channel::<()>();
-        // lba is for sorting purposes only.  It should sort after write_label,
-        // but before any other write operation, and different write_spacemap
-        // operations should sort in the same order as their true LBA order.
-        let lba = 1 + self.spacemap_space * LbaT::from(idx) + block;
-        let block_op = BlockOp::write_spacemap(sglist, lba, idx, block, sender);
-        self.new_fut(block_op, receiver)
-    }
-
     /// The asynchronous scatter/gather write function.
     ///
     /// Returns nothing on success, or an error on failure
@@ -697,12 +1414,15 @@ impl VdevBlock {
     ///
     /// * `bufs`	Scatter-gather list of buffers to receive data
     /// * `lba`     LBA at which to write
-    pub fn writev_at(&self, bufs: SGList, lba: LbaT)
+    /// * `priority`    Hint to the I/O throttle
+    pub fn writev_at(&self, bufs: SGList, lba: LbaT,
+                     priority: PriorityClass)
         -> impl Future<Item=(), Error=Error>
     {
         self.check_sglist_bounds(lba, &bufs);
-        let (sender, receiver) = oneshot::channel::<()>();
-        let block_op = BlockOp::writev_at(bufs, lba, sender);
+        let (sender, receiver) = oneshot::channel();
+        let mut block_op = BlockOp::writev_at(bufs, lba, sender);
+        block_op.priority = priority;
         assert_eq!(block_op.len() % BYTES_PER_LBA, 0,
             "VdevBlock does not support fragmentary writes");
         self.new_fut(block_op, receiver)
@@ -714,12 +1434,19 @@ impl Vdev for VdevBlock {
         self.inner.borrow().leaf.lba2zone(lba)
     }   // LCOV_EXCL_LINE   kcov false negative
 
+    fn max_open_zones(&self) -> Option<ZoneT> {
+        self.inner.borrow().leaf.max_open_zones()
+    }   // LCOV_EXCL_LINE   kcov false negative
+
     /// Returns the "best" number of operations to queue to this `VdevBlock`.  A
     /// smaller number may result in inefficient use of resources, or even
     /// starvation.  A larger number won't hurt, but won't accrue any economies
     /// of scale, either.
+    ///
+    /// The value is adjusted at runtime based on the device's observed
+    /// latency.
     fn optimum_queue_depth(&self) -> u32 {
-        self.inner.borrow().optimum_queue_depth
+        self.inner.borrow().tuner.depth()
     }
 
     fn size(&self) -> LbaT {
@@ -729,7 +1456,7 @@ impl Vdev for VdevBlock {
     /// Asynchronously sync the underlying device, ensuring that all data
     /// reaches stable storage
     fn sync_all(&self) -> Box<VdevFut> {
-        let (sender, receiver) = oneshot::channel::<()>();
+        let (sender, receiver) = oneshot::channel();
         let block_op = BlockOp::sync_all(sender);
         Box::new(self.new_fut(block_op, receiver))
     }
@@ -756,21 +1483,29 @@ mock! {
             where P: AsRef<Path> + 'static;
         fn erase_zone(&self, start: LbaT, end: LbaT) -> Box<VdevFut>;
         fn finish_zone(&self, start: LbaT, end: LbaT) -> Box<VdevFut>;
+        fn iostats(&self) -> VdevStats;
+        fn limits(&self) -> IoLimits;
         fn new(leaf: VdevLeaf) -> Self;
         fn open<P: AsRef<Path> + 'static>(path: P)
             -> Box<dyn Future<Item=(Self, LabelReader), Error=Error>>;
         fn open_zone(&self, lba: LbaT) -> Box<VdevFut>;
-        fn read_at(&self, buf: IoVecMut, lba: LbaT) -> Box<VdevFut>;
-        fn read_spacemap(&self, buf: IoVecMut, idx: u32) -> Box<VdevFut>;
-        fn readv_at(&self, buf: SGListMut, lba: LbaT) -> Box<VdevFut>;
-        fn write_at(&self, buf: IoVec, lba: LbaT) -> Box<VdevFut>;
+        fn read_at(&self, buf: IoVecMut, lba: LbaT, priority: PriorityClass)
+            -> Box<VdevFut>;
+        fn readv_at(&self, buf: SGListMut, lba: LbaT, priority: PriorityClass)
+            -> Box<VdevFut>;
+        fn set_limits(&self, limits: IoLimits);
+        fn set_retries(&self, retries: u32);
+        fn set_scheduler(&self, policy: SchedulerPolicy);
+        fn set_timeout(&self, timeout: Option<time::Duration>);
+        fn write_at(&self, buf: IoVec, lba: LbaT, priority: PriorityClass)
+            -> Box<VdevFut>;
         fn write_label(&self, labeller: LabelWriter) -> Box<VdevFut>;
-        fn write_spacemap(&self, sglist: SGList, idx: u32, block: LbaT)
+        fn writev_at(&self, buf: SGList, lba: LbaT, priority: PriorityClass)
             -> Box<VdevFut>;
-        fn writev_at(&self, buf: SGList, lba: LbaT) -> Box<VdevFut>;
     }
     trait Vdev {
         fn lba2zone(&self, lba: LbaT) -> Option<ZoneT>;
+        fn max_open_zones(&self) -> Option<ZoneT>;
         fn optimum_queue_depth(&self) -> u32;
         fn size(&self) -> LbaT;
         fn sync_all(&self) -> Box<VdevFut>;
@@ -798,21 +1533,15 @@ fn debug_cmd() {
         let readv_at = Cmd::ReadvAt(vec![dbs.try_mut().unwrap()]);
         format!("{:?}", readv_at);
     }
-    {
-        let read_spacemap = Cmd::ReadSpacemap(dbs.try_mut().unwrap(), 0);
-        format!("{:?}", read_spacemap);
-    }
     let write_at = Cmd::WriteAt(dbs.try_const().unwrap());
     let writev_at = Cmd::WritevAt(vec![dbs.try_const().unwrap()]);
     let erase_zone = Cmd::EraseZone(0);
     let finish_zone = Cmd::FinishZone(0);
     let sync_all = Cmd::SyncAll;
-    let label_writer = LabelWriter::new(0);
+    let label_writer = LabelWriter::new(0, TxgT::from(0));
     let write_label = Cmd::WriteLabel(label_writer);
-    let write_spacemap = Cmd::WriteSpacemap(vec![dbs.try_const().unwrap()],
-                                            0, 0);
-    format!("{:?} {:?} {:?} {:?} {:?} {:?} {:?}", write_at, writev_at,
-            erase_zone, finish_zone, sync_all, write_label, write_spacemap);
+    format!("{:?} {:?} {:?} {:?} {:?} {:?}", write_at, writev_at,
+            erase_zone, finish_zone, sync_all, write_label);
 }
 
 // pet kcov
@@ -825,17 +1554,73 @@ fn cmd_partial_cmp() {
 
 // pet kcov
 #[test]
-#[allow(clippy::eq_op)]
-fn blockop_partial_eq() {
-    let (tx0, _rx) = oneshot::channel();
-    let (tx1, _rx) = oneshot::channel();
-    let (tx2, _rx) = oneshot::channel();
-    let bo0 = BlockOp::erase_zone(0, 100, tx0);
-    let bo1 = BlockOp::erase_zone(100, 200, tx1);
-    let bo2 = BlockOp::finish_zone(100, 200, tx2);
-    assert!(bo1 == bo1);
-    assert!(bo0 != bo1);
-    assert!(bo2 != bo1);
+fn debug_scheduler_policy() {
+    format!("{:?}", SchedulerPolicy::default());
+}
+
+#[test]
+fn scheduler_policy_try_from() {
+    assert_eq!(SchedulerPolicy::try_from("clook"), Ok(SchedulerPolicy::CLook));
+    assert_eq!(SchedulerPolicy::try_from("deadline"),
+               Ok(SchedulerPolicy::Deadline));
+    assert_eq!(SchedulerPolicy::try_from("priority"),
+               Ok(SchedulerPolicy::Priority));
+    assert_eq!(SchedulerPolicy::try_from("noop"), Err(Error::EINVAL));
+}
+
+// Errors and timeouts cut the queue depth, but never below 1
+#[test]
+fn tuner_congested() {
+    let mut tuner = QueueDepthTuner::new(4);
+    tuner.congested();
+    assert_eq!(tuner.depth(), 3);
+    tuner.congested();
+    assert_eq!(tuner.depth(), 2);
+    tuner.congested();
+    assert_eq!(tuner.depth(), 1);
+    tuner.congested();
+    assert_eq!(tuner.depth(), 1);
+}
+
+// A full queue with steady latency grows the queue depth by one per window,
+// up to the limit
+#[test]
+fn tuner_increase() {
+    let latency = time::Duration::from_micros(100);
+    let mut tuner = QueueDepthTuner::new(1);
+    for _ in 0..3 {
+        tuner.complete(latency, true);
+    }
+    assert_eq!(tuner.depth(), 3);
+    for _ in 0..100 {
+        tuner.complete(latency, true);
+    }
+    assert_eq!(tuner.depth(), MAX_QUEUE_DEPTH_FACTOR);
+}
+
+// If latency balloons, then the device is saturated
+#[test]
+fn tuner_latency_spike() {
+    let mut tuner = QueueDepthTuner::new(8);
+    for _ in 0..8 {
+        tuner.complete(time::Duration::from_micros(100), true);
+    }
+    assert_eq!(tuner.depth(), 9);
+    for _ in 0..9 {
+        tuner.complete(time::Duration::from_micros(1000), true);
+    }
+    assert_eq!(tuner.depth(), 6);
+}
+
+// If the queue never filled up, then there's no evidence that a deeper queue
+// would help
+#[test]
+fn tuner_unsaturated() {
+    let mut tuner = QueueDepthTuner::new(2);
+    for _ in 0..10 {
+        tuner.complete(time::Duration::from_micros(100), false);
+    }
+    assert_eq!(tuner.depth(), 2);
 }
 
 test_suite! {
@@ -849,6 +1634,7 @@ test_suite! {
     use mockall::PredicateBooleanExt;
     use permutohedron;
     use pretty_assertions::assert_eq;
+    use std::mem;
     use super::*;
     use tokio::runtime::current_thread;
 
@@ -872,8 +1658,12 @@ test_suite! {
                 .return_const(Some(0));
             leaf.expect_optimum_queue_depth()
                 .return_const(10u32);
-            leaf.expect_spacemap_space()
-                .return_const(1u64);
+            leaf.expect_is_conventional()
+                .with(lt(1 << 16))
+                .return_const(true);
+            leaf.expect_is_conventional()
+                .with(ge(1 << 16))
+                .return_const(false);
             leaf.expect_zone_limits()
                 .with(eq(0))
                 .return_const((1, 1 << 16));
@@ -936,10 +1726,12 @@ test_suite! {
         let rbuf1 = dbs1.try_mut().unwrap();
         let vdev = VdevBlock::new(leaf);
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            let f0 = vdev.read_at(rbuf0, 1);
-            let f1 = vdev.read_at(rbuf1, 2);
+            let f0 = vdev.read_at(rbuf0, 1, PriorityClass::Normal);
+            let f1 = vdev.read_at(rbuf1, 2, PriorityClass::Normal);
             f0.join(f1)
         })).expect("test eagain");
+        // EAGAIN should've made the VdevBlock back off
+        assert_eq!(vdev.optimum_queue_depth(), 7);
     }
 
     // Issueing an operation fails with EAGAIN, when the queue depth is 1.  This
@@ -970,10 +1762,162 @@ test_suite! {
         let rbuf = dbs.try_mut().unwrap();
         let vdev = VdevBlock::new(leaf);
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            vdev.read_at(rbuf, 1)
+            vdev.read_at(rbuf, 1, PriorityClass::Normal)
         })).expect("test eagain_queue_depth_1");
     }
 
+    // A read that fails asynchronously should return its error to the caller,
+    // and should not be retried
+    test read_error(mocks) {
+        let mut leaf = mocks.val;
+        leaf.expect_read_at()
+            .with(always(), eq(1))
+            .once()
+            .returning(move |_, _| {
+                let mut seq = Sequence::new();
+                let mut fut = MockVdevFut::new();
+                fut.expect_poll()
+                    .once()
+                    .in_sequence(&mut seq)
+                    .return_const(Ok(Async::NotReady));
+                fut.expect_poll()
+                    .once()
+                    .in_sequence(&mut seq)
+                    .return_const(Err(Error::EIO));
+                Box::new(fut)
+            });
+        let dbs = DivBufShared::from(vec![0u8; 4096]);
+        let rbuf = dbs.try_mut().unwrap();
+        let vdev = VdevBlock::new(leaf);
+        let r = current_thread::Runtime::new().unwrap()
+            .block_on(future::lazy(|| {
+                vdev.read_at(rbuf, 1, PriorityClass::Normal)
+            }));
+        assert_eq!(Err(Error::EIO), r);
+    }
+
+    // A write that fails synchronously should be retried
+    test write_retry(mocks) {
+        let mut leaf = mocks.val;
+        let mut seq = Sequence::new();
+        leaf.expect_write_at()
+            .with(always(), eq(1))
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|_, _| Box::new(future::err::<(), Error>(Error::EIO)));
+        leaf.expect_write_at()
+            .with(always(), eq(1))
+            .once()
+            .in_sequence(&mut seq)
+            .returning(|_, _| Box::new(future::ok::<(), Error>(())));
+
+        let dbs = DivBufShared::from(vec![0u8; 4096]);
+        let wbuf = dbs.try_const().unwrap();
+        let vdev = VdevBlock::new(leaf);
+        current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
+            vdev.write_at(wbuf, 1, PriorityClass::Normal)
+        })).unwrap();
+    }
+
+    // Once its retries are exhausted, a write should return its error
+    test write_retries_exhausted(mocks) {
+        let mut leaf = mocks.val;
+        leaf.expect_write_at()
+            .with(always(), eq(1))
+            .times(2)
+            .returning(|_, _| Box::new(future::err::<(), Error>(Error::EIO)));
+
+        let dbs = DivBufShared::from(vec![0u8; 4096]);
+        let wbuf = dbs.try_const().unwrap();
+        let vdev = VdevBlock::new(leaf);
+        vdev.set_retries(1);
+        let r = current_thread::Runtime::new().unwrap()
+            .block_on(future::lazy(|| {
+                vdev.write_at(wbuf, 1, PriorityClass::Normal)
+            }));
+        assert_eq!(Err(Error::EIO), r);
+    }
+
+    // A failed write to a sequential zone should not be retried, because
+    // later writes to that zone may already be in flight
+    test write_no_retry_sequential(mocks) {
+        let mut leaf = mocks.val;
+        leaf.expect_write_at()
+            .with(always(), eq(1 << 16))
+            .once()
+            .returning(|_, _| Box::new(future::err::<(), Error>(Error::EIO)));
+
+        let dbs = DivBufShared::from(vec![0u8; 4096]);
+        let wbuf = dbs.try_const().unwrap();
+        let vdev = VdevBlock::new(leaf);
+        let r = current_thread::Runtime::new().unwrap()
+            .block_on(future::lazy(|| {
+                vdev.write_at(wbuf, 1 << 16, PriorityClass::Normal)
+            }));
+        assert_eq!(Err(Error::EIO), r);
+    }
+
+    // Completed operations should be counted
+    test iostats(mocks) {
+        let mut leaf = mocks.val;
+        let uuid = Uuid::new_v4();
+        leaf.expect_uuid()
+            .return_const(uuid);
+        leaf.expect_read_at()
+            .with(always(), eq(1))
+            .once()
+            .returning(|_, _| Box::new(future::ok::<(), Error>(())));
+        leaf.expect_write_at()
+            .with(always(), eq(2))
+            .once()
+            .returning(|_, _| Box::new(future::err::<(), Error>(Error::EIO)));
+
+        let rdbs = DivBufShared::from(vec![0u8; 4096]);
+        let rbuf = rdbs.try_mut().unwrap();
+        let wdbs = DivBufShared::from(vec![0u8; 8192]);
+        let wbuf = wdbs.try_const().unwrap();
+        let vdev = VdevBlock::new(leaf);
+        vdev.set_retries(0);
+        let r = current_thread::Runtime::new().unwrap()
+            .block_on(future::lazy(|| {
+                vdev.read_at(rbuf, 1, PriorityClass::Normal)
+                .then(|_| vdev.write_at(wbuf, 2, PriorityClass::Normal))
+            }));
+        assert_eq!(Err(Error::EIO), r);
+        let vs = vdev.iostats();
+        assert_eq!(vs.uuid, uuid);
+        assert!(vs.children.is_empty());
+        assert_eq!(vs.stats.read_ops, 1);
+        assert_eq!(vs.stats.read_bytes, 4096);
+        assert_eq!(vs.stats.read_latency.count(), 1);
+        assert_eq!(vs.stats.write_ops, 0);
+        assert_eq!(vs.stats.errors, 1);
+        assert_eq!(vs.stats.queue_depth, 0);
+    }
+
+    // An operation that never completes should time out
+    test timeout(mocks) {
+        let mut leaf = mocks.val;
+        leaf.expect_read_at()
+            .with(always(), eq(1))
+            .once()
+            .returning(move |_, _| {
+                let mut fut = MockVdevFut::new();
+                fut.expect_poll()
+                    .return_const(Ok(Async::NotReady));
+                Box::new(fut)
+            });
+        let dbs = DivBufShared::from(vec![0u8; 4096]);
+        let rbuf = dbs.try_mut().unwrap();
+        let vdev = VdevBlock::new(leaf);
+        vdev.set_timeout(Some(time::Duration::from_millis(10)));
+        let r = current_thread::Runtime::new().unwrap()
+            .block_on(future::lazy(|| {
+                vdev.read_at(rbuf, 1, PriorityClass::Normal)
+            }));
+        assert_eq!(Err(Error::ETIMEDOUT), r);
+    }
+
     test basic_erase_zone(mocks) {
         let mut leaf = mocks.val;
         leaf.expect_erase_zone()
@@ -1021,7 +1965,7 @@ test_suite! {
         let rbuf0 = dbs0.try_mut().unwrap();
         let vdev = VdevBlock::new(leaf);
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            vdev.read_at(rbuf0, 2)
+            vdev.read_at(rbuf0, 2, PriorityClass::Normal)
         })).unwrap();
     }
 
@@ -1036,7 +1980,7 @@ test_suite! {
         let rbuf0 = vec![dbs0.try_mut().unwrap()];
         let vdev = VdevBlock::new(leaf);
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            vdev.readv_at(rbuf0, 2)
+            vdev.readv_at(rbuf0, 2, PriorityClass::Normal)
         })).unwrap();
     }
 
@@ -1052,201 +1996,304 @@ test_suite! {
         })).unwrap();
     }
 
+    /// Every scheduling policy.  The scheduling tests should pass with each.
+    const POLICIES: [SchedulerPolicy; 3] = [
+        SchedulerPolicy::CLook,
+        SchedulerPolicy::Deadline,
+        SchedulerPolicy::Priority
+    ];
+
+    /// Move the scheduler's position to `lba` by issuing a dummy operation
+    /// there
+    fn seek(inner: &mut Inner, lba: LbaT) {
+        let dummy_dbs = DivBufShared::from(vec![0; 4096]);
+        let op = BlockOp::write_at(dummy_dbs.try_const().unwrap(), lba,
+            oneshot::channel().0);
+        inner.sched(op);
+        assert_eq!(inner.pop_op().unwrap().lba, lba);
+    }
+
     // data operations will be issued in C-LOOK order (from lowest LBA to
     // highest, then start over at lowest)
     test sched_data(mocks) {
         let leaf = mocks.val;
         let vdev = VdevBlock::new(leaf);
-        let mut inner = vdev.inner.borrow_mut();
         let dummy_dbs = DivBufShared::from(vec![0; 4096]);
         let dummy_buffer = dummy_dbs.try_const().unwrap();
 
-        // Start with some intermedia LBA and schedule some ops
-        let same_lba = 1000;
-        let just_after = 1001;
-        let just_after_dup = 1001;
-        let just_before = 999;
-        let min = 1;
-        let max = 16383;
-        let mut lbas = vec![same_lba, just_after, just_after_dup, just_before,
-                            min, max];
-        // Test scheduling the ops in all possible permutations
-        permutohedron::heap_recursive(&mut lbas, |permutation| {
-            inner.last_lba = 1000;
-            for lba in permutation {
-                let op = BlockOp::write_at(dummy_buffer.clone(), *lba,
-                    oneshot::channel::<()>().0);
-                inner.sched(op);
-            }
+        for policy in POLICIES.iter() {
+            vdev.set_scheduler(*policy);
+            let mut inner = vdev.inner.borrow_mut();
+            // Start with some intermedia LBA and schedule some ops
+            let same_lba = 1000;
+            let just_after = 1001;
+            let just_after_dup = 1001;
+            let just_before = 999;
+            let min = 1;
+            let max = 16383;
+            let mut lbas = vec![same_lba, just_after, just_after_dup,
+                                just_before, min, max];
+            // Test scheduling the ops in all possible permutations
+            permutohedron::heap_recursive(&mut lbas, |permutation| {
+                seek(&mut inner, 1000);
+                for lba in permutation {
+                    let op = BlockOp::write_at(dummy_buffer.clone(), *lba,
+                        oneshot::channel().0);
+                    inner.sched(op);
+                }
 
-            // Check that they're scheduled in the correct order
-            assert_eq!(inner.pop_op().unwrap().lba, 1000);
-            assert_eq!(inner.pop_op().unwrap().lba, 1001);
-            assert_eq!(inner.pop_op().unwrap().lba, 1001);
+                // Check that they're scheduled in the correct order
+                assert_eq!(inner.pop_op().unwrap().lba, 1000);
+                assert_eq!(inner.pop_op().unwrap().lba, 1001);
+                assert_eq!(inner.pop_op().unwrap().lba, 1001);
+
+                // Schedule two more operations behind the scheduler, but ahead
+                // of some already-scheduled ops, to make sure they get issued
+                // in the right order
+                let just_before2 = BlockOp::write_at(dummy_buffer.clone(),
+                    1000, oneshot::channel().0);
+                let well_before = BlockOp::write_at(dummy_buffer.clone(), 990,
+                    oneshot::channel().0);
+                inner.sched(just_before2);
+                inner.sched(well_before);
+
+                assert_eq!(inner.pop_op().unwrap().lba, 16383);
+                assert_eq!(inner.pop_op().unwrap().lba, 1);
+                assert_eq!(inner.pop_op().unwrap().lba, 990);
+                assert_eq!(inner.pop_op().unwrap().lba, 999);
+                assert_eq!(inner.pop_op().unwrap().lba, 1000);
+                assert!(inner.pop_op().is_none());
+            });
+        }
+    }
 
-            // Schedule two more operations behind the scheduler, but ahead of
-            // some already-scheduled ops, to make sure they get issued in the
-            // right order
-            let just_before2 = BlockOp::write_at(dummy_buffer.clone(), 1000,
-                oneshot::channel::<()>().0);
-            let well_before = BlockOp::write_at(dummy_buffer.clone(), 990,
-                oneshot::channel::<()>().0);
-            inner.sched(just_before2);
-            inner.sched(well_before);
-
-            assert_eq!(inner.pop_op().unwrap().lba, 16383);
-            assert_eq!(inner.pop_op().unwrap().lba, 1);
-            assert_eq!(inner.pop_op().unwrap().lba, 990);
-            assert_eq!(inner.pop_op().unwrap().lba, 999);
-            assert_eq!(inner.pop_op().unwrap().lba, 1000);
-            assert!(inner.pop_op().is_none());
-        });
+    // The Deadline scheduler should issue expired reads ahead of everything
+    // else, but shouldn't otherwise change the C-LOOK order.
+    test sched_deadline(mocks) {
+        let leaf = mocks.val;
+        let vdev = VdevBlock::new(leaf);
+        let mut inner = vdev.inner.borrow_mut();
+        inner.scheduler = Box::new(Deadline::new(time::Duration::new(0, 0)));
+        let dummy_dbs = DivBufShared::from(vec![0; 8192]);
+        let mut dummy = dummy_dbs.try_mut().unwrap();
+        let wbuf = DivBufShared::from(vec![0; 4096]);
+
+        seek(&mut inner, 1000);
+        for lba in &[1001, 1002, 1] {
+            inner.sched(BlockOp::write_at(wbuf.try_const().unwrap(), *lba,
+                oneshot::channel().0));
+        }
+        inner.sched(BlockOp::read_at(dummy.split_to(4096), 10,
+            oneshot::channel().0));
+        inner.sched(BlockOp::read_at(dummy, 5,
+            oneshot::channel().0));
+
+        // Reads get issued in arrival order, since they're both expired
+        assert_eq!(inner.pop_op().unwrap().lba, 10);
+        assert_eq!(inner.pop_op().unwrap().lba, 5);
+        // Expediting the reads didn't move the scheduler
+        assert_eq!(inner.pop_op().unwrap().lba, 1001);
+        assert_eq!(inner.pop_op().unwrap().lba, 1002);
+        assert_eq!(inner.pop_op().unwrap().lba, 1);
+        assert!(inner.pop_op().is_none());
     }
 
-    // An erase zone command should be scheduled after any reads from that zone
-    test sched_erase_zone(mocks) {
+    // The Priority scheduler should issue reads by priority class.  Writes
+    // should be issued alongside Normal reads, in C-LOOK order.
+    test sched_priority(mocks) {
         let leaf = mocks.val;
         let vdev = VdevBlock::new(leaf);
+        vdev.set_scheduler(SchedulerPolicy::Priority);
         let mut inner = vdev.inner.borrow_mut();
         let dummy_dbs = DivBufShared::from(vec![0; 12288]);
         let mut dummy = dummy_dbs.try_mut().unwrap();
+        let wbuf = DivBufShared::from(vec![0; 4096]);
+
+        seek(&mut inner, 1000);
+        let mut bg = BlockOp::read_at(dummy.split_to(4096), 1001,
+            oneshot::channel().0);
+        bg.priority = PriorityClass::Background;
+        inner.sched(bg);
+        inner.sched(BlockOp::write_at(wbuf.try_const().unwrap(), 1003,
+            oneshot::channel().0));
+        inner.sched(BlockOp::read_at(dummy.split_to(4096), 1002,
+            oneshot::channel().0));
+        let mut interactive = BlockOp::read_at(dummy, 1,
+            oneshot::channel().0);
+        interactive.priority = PriorityClass::Interactive;
+        inner.sched(interactive);
+        // Writes are always Normal priority, even if marked otherwise
+        let mut bgw = BlockOp::write_at(wbuf.try_const().unwrap(), 1004,
+            oneshot::channel().0);
+        bgw.priority = PriorityClass::Background;
+        inner.sched(bgw);
+
+        assert_eq!(inner.pop_op().unwrap().lba, 1);
+        assert_eq!(inner.pop_op().unwrap().lba, 1002);
+        assert_eq!(inner.pop_op().unwrap().lba, 1003);
+        assert_eq!(inner.pop_op().unwrap().lba, 1004);
+        assert_eq!(inner.pop_op().unwrap().lba, 1001);
+        assert!(inner.pop_op().is_none());
+    }
+
+    // An erase zone command should be scheduled after any reads from that zone
+    test sched_erase_zone(mocks) {
+        let leaf = mocks.val;
+        let vdev = VdevBlock::new(leaf);
+        let dummy_dbs = DivBufShared::from(vec![0; 12288]);
 
-        inner.last_lba = 1 << 16;   // In zone 1
-        // Read from zones that lie behind, around, and ahead of the scheduler,
-        // then erase them.  This simulates garbage collection.
-        let ez0 = BlockOp::erase_zone(0, (1 << 16) - 1,
-            oneshot::channel::<()>().0);
-        let ez_discriminant = mem::discriminant(&ez0.cmd);
-        inner.sched(ez0);
-        let r = BlockOp::read_at(dummy.split_to(4096), (1 << 16) - 1,
-            oneshot::channel::<()>().0);
-        let read_at_discriminant = mem::discriminant(&r.cmd);
-        inner.sched(r);
-        inner.sched(BlockOp::erase_zone(1 << 16, (2 << 16) - 1,
-            oneshot::channel::<()>().0));
-        inner.sched(BlockOp::read_at(dummy.split_to(4096), (2 << 16) - 1,
-            oneshot::channel::<()>().0));
-        inner.sched(BlockOp::erase_zone(2 << 16, (3 << 16) - 1,
-            oneshot::channel::<()>().0));
-        inner.sched(BlockOp::read_at(dummy, (3 << 16) - 1,
-            oneshot::channel::<()>().0));
-
-        let first = inner.pop_op().unwrap();
-        assert_eq!(first.lba, (2 << 16) - 1);
-        assert_eq!(mem::discriminant(&first.cmd), read_at_discriminant);
-        let second = inner.pop_op().unwrap();
-        assert_eq!(second.lba, (2 << 16) - 1);
-        assert_eq!(mem::discriminant(&second.cmd), ez_discriminant);
-        let third = inner.pop_op().unwrap();
-        assert_eq!(third.lba, (3 << 16) - 1);
-        assert_eq!(mem::discriminant(&third.cmd), read_at_discriminant);
-        let fourth = inner.pop_op().unwrap();
-        assert_eq!(fourth.lba, (3 << 16) - 1);
-        assert_eq!(mem::discriminant(&fourth.cmd), ez_discriminant);
-        let fifth = inner.pop_op().unwrap();
-        assert_eq!(fifth.lba, (1 << 16) - 1);
-        assert_eq!(mem::discriminant(&fifth.cmd), read_at_discriminant);
-        let sixth = inner.pop_op().unwrap();
-        assert_eq!(sixth.lba, (1 << 16) - 1);
-        assert_eq!(mem::discriminant(&sixth.cmd), ez_discriminant);
+        for policy in POLICIES.iter() {
+            vdev.set_scheduler(*policy);
+            let mut inner = vdev.inner.borrow_mut();
+            let mut dummy = dummy_dbs.try_mut().unwrap();
+
+            seek(&mut inner, 1 << 16);   // In zone 1
+            // Read from zones that lie behind, around, and ahead of the
+            // scheduler, then erase them.  This simulates garbage collection.
+            let ez0 = BlockOp::erase_zone(0, (1 << 16) - 1,
+                oneshot::channel().0);
+            let ez_discriminant = mem::discriminant(&ez0.cmd);
+            inner.sched(ez0);
+            let r = BlockOp::read_at(dummy.split_to(4096), (1 << 16) - 1,
+                oneshot::channel().0);
+            let read_at_discriminant = mem::discriminant(&r.cmd);
+            inner.sched(r);
+            inner.sched(BlockOp::erase_zone(1 << 16, (2 << 16) - 1,
+                oneshot::channel().0));
+            inner.sched(BlockOp::read_at(dummy.split_to(4096), (2 << 16) - 1,
+                oneshot::channel().0));
+            inner.sched(BlockOp::erase_zone(2 << 16, (3 << 16) - 1,
+                oneshot::channel().0));
+            inner.sched(BlockOp::read_at(dummy, (3 << 16) - 1,
+                oneshot::channel().0));
+
+            let first = inner.pop_op().unwrap();
+            assert_eq!(first.lba, (2 << 16) - 1);
+            assert_eq!(mem::discriminant(&first.cmd), read_at_discriminant);
+            let second = inner.pop_op().unwrap();
+            assert_eq!(second.lba, (2 << 16) - 1);
+            assert_eq!(mem::discriminant(&second.cmd), ez_discriminant);
+            let third = inner.pop_op().unwrap();
+            assert_eq!(third.lba, (3 << 16) - 1);
+            assert_eq!(mem::discriminant(&third.cmd), read_at_discriminant);
+            let fourth = inner.pop_op().unwrap();
+            assert_eq!(fourth.lba, (3 << 16) - 1);
+            assert_eq!(mem::discriminant(&fourth.cmd), ez_discriminant);
+            let fifth = inner.pop_op().unwrap();
+            assert_eq!(fifth.lba, (1 << 16) - 1);
+            assert_eq!(mem::discriminant(&fifth.cmd), read_at_discriminant);
+            let sixth = inner.pop_op().unwrap();
+            assert_eq!(sixth.lba, (1 << 16) - 1);
+            assert_eq!(mem::discriminant(&sixth.cmd), ez_discriminant);
+        }
     }
 
     // A finish zone command should be scheduled after any writes to that zone
     test sched_finish_zone(mocks) {
         let leaf = mocks.val;
         let vdev = VdevBlock::new(leaf);
-        let mut inner = vdev.inner.borrow_mut();
         let dummy_dbs = DivBufShared::from(vec![0; 4096]);
         let dummy = dummy_dbs.try_const().unwrap();
 
-        inner.last_lba = 1 << 16;   // In zone 1
-        // Write to zones that lie behind, around, and ahead of the scheduler,
-        // then finish them.
-        let fz0 = BlockOp::finish_zone(0, (1 << 16) - 1,
-            oneshot::channel::<()>().0);
-        let fz_discriminant = mem::discriminant(&fz0.cmd);
-        inner.sched(fz0);
-        let r = BlockOp::write_at(dummy.clone(), (1 << 16) - 1,
-            oneshot::channel::<()>().0);
-        let write_at_discriminant = mem::discriminant(&r.cmd);
-        inner.sched(r);
-        inner.sched(BlockOp::finish_zone(1 << 16, (2 << 16) - 1,
-            oneshot::channel::<()>().0));
-        inner.sched(BlockOp::write_at(dummy.clone(), (2 << 16) - 1,
-            oneshot::channel::<()>().0));
-        inner.sched(BlockOp::finish_zone(2 << 16, (3 << 16) - 1,
-            oneshot::channel::<()>().0));
-        inner.sched(BlockOp::write_at(dummy, (3 << 16) - 1,
-            oneshot::channel::<()>().0));
-
-        let first = inner.pop_op().unwrap();
-        assert_eq!(first.lba, (2 << 16) - 1);
-        assert_eq!(mem::discriminant(&first.cmd), write_at_discriminant);
-        let second = inner.pop_op().unwrap();
-        assert_eq!(second.lba, (2 << 16) - 1);
-        assert_eq!(mem::discriminant(&second.cmd), fz_discriminant);
-        let third = inner.pop_op().unwrap();
-        assert_eq!(third.lba, (3 << 16) - 1);
-        assert_eq!(mem::discriminant(&third.cmd), write_at_discriminant);
-        let fourth = inner.pop_op().unwrap();
-        assert_eq!(fourth.lba, (3 << 16) - 1);
-        assert_eq!(mem::discriminant(&fourth.cmd), fz_discriminant);
-        let fifth = inner.pop_op().unwrap();
-        assert_eq!(fifth.lba, (1 << 16) - 1);
-        assert_eq!(mem::discriminant(&fifth.cmd), write_at_discriminant);
-        let sixth = inner.pop_op().unwrap();
-        assert_eq!(sixth.lba, (1 << 16) - 1);
-        assert_eq!(mem::discriminant(&sixth.cmd), fz_discriminant);
+        for policy in POLICIES.iter() {
+            vdev.set_scheduler(*policy);
+            let mut inner = vdev.inner.borrow_mut();
+
+            seek(&mut inner, 1 << 16);   // In zone 1
+            // Write to zones that lie behind, around, and ahead of the
+            // scheduler, then finish them.
+            let fz0 = BlockOp::finish_zone(0, (1 << 16) - 1,
+                oneshot::channel().0);
+            let fz_discriminant = mem::discriminant(&fz0.cmd);
+            inner.sched(fz0);
+            let r = BlockOp::write_at(dummy.clone(), (1 << 16) - 1,
+                oneshot::channel().0);
+            let write_at_discriminant = mem::discriminant(&r.cmd);
+            inner.sched(r);
+            inner.sched(BlockOp::finish_zone(1 << 16, (2 << 16) - 1,
+                oneshot::channel().0));
+            inner.sched(BlockOp::write_at(dummy.clone(), (2 << 16) - 1,
+                oneshot::channel().0));
+            inner.sched(BlockOp::finish_zone(2 << 16, (3 << 16) - 1,
+                oneshot::channel().0));
+            inner.sched(BlockOp::write_at(dummy.clone(), (3 << 16) - 1,
+                oneshot::channel().0));
+
+            let first = inner.pop_op().unwrap();
+            assert_eq!(first.lba, (2 << 16) - 1);
+            assert_eq!(mem::discriminant(&first.cmd), write_at_discriminant);
+            let second = inner.pop_op().unwrap();
+            assert_eq!(second.lba, (2 << 16) - 1);
+            assert_eq!(mem::discriminant(&second.cmd), fz_discriminant);
+            let third = inner.pop_op().unwrap();
+            assert_eq!(third.lba, (3 << 16) - 1);
+            assert_eq!(mem::discriminant(&third.cmd), write_at_discriminant);
+            let fourth = inner.pop_op().unwrap();
+            assert_eq!(fourth.lba, (3 << 16) - 1);
+            assert_eq!(mem::discriminant(&fourth.cmd), fz_discriminant);
+            let fifth = inner.pop_op().unwrap();
+            assert_eq!(fifth.lba, (1 << 16) - 1);
+            assert_eq!(mem::discriminant(&fifth.cmd), write_at_discriminant);
+            let sixth = inner.pop_op().unwrap();
+            assert_eq!(sixth.lba, (1 << 16) - 1);
+            assert_eq!(mem::discriminant(&sixth.cmd), fz_discriminant);
+        }
     }
 
     // An open zone command should be scheduled before any writes to that zone
     test sched_open_zone(mocks) {
         let leaf = mocks.val;
         let vdev = VdevBlock::new(leaf);
-        let mut inner = vdev.inner.borrow_mut();
         let dummy_dbs = DivBufShared::from(vec![0; 4096]);
         let dummy = dummy_dbs.try_const().unwrap();
 
-        inner.last_lba = 1 << 16;   // In zone 1
-        // Open zones 0 and 2 and write to both.  Note that it is illegal for
-        // the scheduler's last_lba to lie within either of these zones, because
-        // that would imply that it had just performed an operation on an empty
-        // zone.
-        let w = BlockOp::write_at(dummy.clone(), 1, oneshot::channel::<()>().0);
-        let write_at_discriminant = mem::discriminant(&w.cmd);
-        inner.sched(w);
-        inner.sched(BlockOp::write_at(dummy.clone(), (1 << 16) - 1,
-                    oneshot::channel::<()>().0));
-        inner.sched(BlockOp::write_at(dummy.clone(), 2,
-                    oneshot::channel::<()>().0));
-        let oz0 = BlockOp::open_zone(1, oneshot::channel::<()>().0);
-        let oz_discriminant = mem::discriminant(&oz0.cmd);
-        inner.sched(oz0);
-        inner.sched(BlockOp::open_zone(2 << 16, oneshot::channel::<()>().0));
-        inner.sched(BlockOp::write_at(dummy.clone(), (2 << 16) + 1,
-                    oneshot::channel::<()>().0));
-        inner.sched(BlockOp::write_at(dummy.clone(), 2 << 16,
-                    oneshot::channel::<()>().0));
-        inner.sched(BlockOp::write_at(dummy.clone(), (3 << 16) - 1,
-                    oneshot::channel::<()>().0));
-
-        let first = inner.pop_op().unwrap();
-        assert_eq!(first.lba, 2 << 16);
-        assert_eq!(mem::discriminant(&first.cmd), oz_discriminant);
-        let second = inner.pop_op().unwrap();
-        assert_eq!(second.lba, 2 << 16);
-        assert_eq!(mem::discriminant(&second.cmd), write_at_discriminant);
-        assert_eq!(inner.pop_op().unwrap().lba, (2 << 16) + 1);
-        assert_eq!(inner.pop_op().unwrap().lba, (3 << 16) - 1);
-        let fifth = inner.pop_op().unwrap();
-        assert_eq!(fifth.lba, 1);
-        assert_eq!(mem::discriminant(&fifth.cmd), oz_discriminant);
-        let sixth = inner.pop_op().unwrap();
-        assert_eq!(sixth.lba, 1);
-        assert_eq!(mem::discriminant(&sixth.cmd), write_at_discriminant);
-        assert_eq!(inner.pop_op().unwrap().lba, 2);
-        assert_eq!(inner.pop_op().unwrap().lba, (1 << 16) - 1);
-        assert!(inner.pop_op().is_none());
+        for policy in POLICIES.iter() {
+            vdev.set_scheduler(*policy);
+            let mut inner = vdev.inner.borrow_mut();
+
+            seek(&mut inner, 1 << 16);   // In zone 1
+            // Open zones 0 and 2 and write to both.  Note that it is illegal
+            // for the scheduler's position to lie within either of these
+            // zones, because that would imply that it had just performed an
+            // operation on an empty zone.
+            let w = BlockOp::write_at(dummy.clone(), 1,
+                oneshot::channel().0);
+            let write_at_discriminant = mem::discriminant(&w.cmd);
+            inner.sched(w);
+            inner.sched(BlockOp::write_at(dummy.clone(), (1 << 16) - 1,
+                        oneshot::channel().0));
+            inner.sched(BlockOp::write_at(dummy.clone(), 2,
+                        oneshot::channel().0));
+            let oz0 = BlockOp::open_zone(1, oneshot::channel().0);
+            let oz_discriminant = mem::discriminant(&oz0.cmd);
+            inner.sched(oz0);
+            inner.sched(BlockOp::open_zone(2 << 16,
+                        oneshot::channel().0));
+            inner.sched(BlockOp::write_at(dummy.clone(), (2 << 16) + 1,
+                        oneshot::channel().0));
+            inner.sched(BlockOp::write_at(dummy.clone(), 2 << 16,
+                        oneshot::channel().0));
+            inner.sched(BlockOp::write_at(dummy.clone(), (3 << 16) - 1,
+                        oneshot::channel().0));
+
+            let first = inner.pop_op().unwrap();
+            assert_eq!(first.lba, 2 << 16);
+            assert_eq!(mem::discriminant(&first.cmd), oz_discriminant);
+            let second = inner.pop_op().unwrap();
+            assert_eq!(second.lba, 2 << 16);
+            assert_eq!(mem::discriminant(&second.cmd), write_at_discriminant);
+            assert_eq!(inner.pop_op().unwrap().lba, (2 << 16) + 1);
+            assert_eq!(inner.pop_op().unwrap().lba, (3 << 16) - 1);
+            let fifth = inner.pop_op().unwrap();
+            assert_eq!(fifth.lba, 1);
+            assert_eq!(mem::discriminant(&fifth.cmd), oz_discriminant);
+            let sixth = inner.pop_op().unwrap();
+            assert_eq!(sixth.lba, 1);
+            assert_eq!(mem::discriminant(&sixth.cmd), write_at_discriminant);
+            assert_eq!(inner.pop_op().unwrap().lba, 2);
+            assert_eq!(inner.pop_op().unwrap().lba, (1 << 16) - 1);
+            assert!(inner.pop_op().is_none());
+        }
     }
 
     // A sync_all command should be issued in strictly ordered mode; after all
@@ -1254,42 +2301,212 @@ test_suite! {
     test sched_sync_all(mocks) {
         let leaf = mocks.val;
         let vdev = VdevBlock::new(leaf);
-        let mut inner = vdev.inner.borrow_mut();
         let dummy_dbs = DivBufShared::from(vec![0; 4096]);
         let dummy_buffer = dummy_dbs.try_const().unwrap();
 
-        // Start with some intermediate LBA and schedule ops both before and
-        // after
-        inner.last_lba = 1000;
-        inner.sched(BlockOp::write_at(dummy_buffer.clone(), 1001,
-            oneshot::channel::<()>().0));
-        inner.sched(BlockOp::write_at(dummy_buffer.clone(), 999,
-            oneshot::channel::<()>().0));
-        // Now schedule a sync_all, too
-        inner.sched(BlockOp::sync_all(oneshot::channel::<()>().0));
-        // Now schedule some more data ops both before and after the scheudler
-        inner.sched(BlockOp::write_at(dummy_buffer.clone(), 1002,
-            oneshot::channel::<()>().0));
-        inner.sched(BlockOp::write_at(dummy_buffer.clone(), 998,
-            oneshot::channel::<()>().0));
-        // For good measure, schedule a second sync and some more data after
-        // that
-        inner.sched(BlockOp::sync_all(oneshot::channel::<()>().0));
-        inner.sched(BlockOp::write_at(dummy_buffer.clone(), 1003,
-            oneshot::channel::<()>().0));
-        inner.sched(BlockOp::write_at(dummy_buffer.clone(), 997,
-            oneshot::channel::<()>().0));
-
-        // All pre-sync operations should be issued, then the sync, then the
-        // post-sync operations
-        assert_eq!(inner.pop_op().unwrap().lba, 1001);
-        assert_eq!(inner.pop_op().unwrap().lba, 999);
-        assert_eq!(inner.pop_op().unwrap().cmd, Cmd::SyncAll);
-        assert_eq!(inner.pop_op().unwrap().lba, 1002);
-        assert_eq!(inner.pop_op().unwrap().lba, 998);
-        assert_eq!(inner.pop_op().unwrap().cmd, Cmd::SyncAll);
-        assert_eq!(inner.pop_op().unwrap().lba, 1003);
-        assert_eq!(inner.pop_op().unwrap().lba, 997);
+        for policy in POLICIES.iter() {
+            vdev.set_scheduler(*policy);
+            let mut inner = vdev.inner.borrow_mut();
+
+            // Start with some intermediate LBA and schedule ops both before
+            // and after
+            seek(&mut inner, 1000);
+            inner.sched(BlockOp::write_at(dummy_buffer.clone(), 1001,
+                oneshot::channel().0));
+            inner.sched(BlockOp::write_at(dummy_buffer.clone(), 999,
+                oneshot::channel().0));
+            // Now schedule a sync_all, too
+            inner.sched(BlockOp::sync_all(oneshot::channel().0));
+            // Now schedule some more data ops both before and after the
+            // scheduler
+            inner.sched(BlockOp::write_at(dummy_buffer.clone(), 1002,
+                oneshot::channel().0));
+            inner.sched(BlockOp::write_at(dummy_buffer.clone(), 998,
+                oneshot::channel().0));
+            // For good measure, schedule a second sync and some more data
+            // after that
+            inner.sched(BlockOp::sync_all(oneshot::channel().0));
+            inner.sched(BlockOp::write_at(dummy_buffer.clone(), 1003,
+                oneshot::channel().0));
+            inner.sched(BlockOp::write_at(dummy_buffer.clone(), 997,
+                oneshot::channel().0));
+
+            // All pre-sync operations should be issued, then the sync, then
+            // the post-sync operations
+            assert_eq!(inner.pop_op().unwrap().lba, 1001);
+            assert_eq!(inner.pop_op().unwrap().lba, 999);
+            assert_eq!(inner.pop_op().unwrap().cmd, Cmd::SyncAll);
+            assert_eq!(inner.pop_op().unwrap().lba, 1002);
+            assert_eq!(inner.pop_op().unwrap().lba, 998);
+            assert_eq!(inner.pop_op().unwrap().cmd, Cmd::SyncAll);
+            assert_eq!(inner.pop_op().unwrap().lba, 1003);
+            assert_eq!(inner.pop_op().unwrap().lba, 997);
+            assert!(inner.pop_op().is_none());
+        }
+    }
+
+    // Changing the scheduler should preserve pending operations
+    test set_scheduler(mocks) {
+        let leaf = mocks.val;
+        let vdev = VdevBlock::new(leaf);
+        let dummy_dbs = DivBufShared::from(vec![0; 4096]);
+        let dummy_buffer = dummy_dbs.try_const().unwrap();
+        {
+            let mut inner = vdev.inner.borrow_mut();
+            for lba in &[3, 1, 2] {
+                inner.sched(BlockOp::write_at(dummy_buffer.clone(), *lba,
+                    oneshot::channel().0));
+            }
+        }
+        vdev.set_scheduler(SchedulerPolicy::Deadline);
+        let mut inner = vdev.inner.borrow_mut();
+        assert_eq!(inner.scheduler.len(), 3);
+        assert_eq!(inner.pop_op().unwrap().lba, 1);
+        assert_eq!(inner.pop_op().unwrap().lba, 2);
+        assert_eq!(inner.pop_op().unwrap().lba, 3);
+        assert!(inner.pop_op().is_none());
+    }
+
+    // Adjacent reads and writes get merged, but not with each other
+    test merge(mocks) {
+        let leaf = mocks.val;
+        let vdev = VdevBlock::new(leaf);
+        let wdbs = DivBufShared::from(vec![0; 4096]);
+        let wbuf = wdbs.try_const().unwrap();
+        for policy in POLICIES.iter() {
+            vdev.set_scheduler(*policy);
+            let rdbs = DivBufShared::from(vec![0; 4096]);
+            let mut inner = vdev.inner.borrow_mut();
+            seek(&mut inner, 0);
+            inner.sched(BlockOp::write_at(wbuf.clone(), 1,
+                oneshot::channel().0));
+            inner.sched(BlockOp::writev_at(vec![wbuf.clone(), wbuf.clone()], 2,
+                oneshot::channel().0));
+            inner.sched(BlockOp::write_at(wbuf.clone(), 4,
+                oneshot::channel().0));
+            inner.sched(BlockOp::read_at(rdbs.try_mut().unwrap(), 5,
+                oneshot::channel().0));
+            inner.sched(BlockOp::write_at(wbuf.clone(), 7,
+                oneshot::channel().0));
+
+            let op = inner.pop_op().unwrap();
+            let (op, merged) = inner.merge(op);
+            assert_eq!(op.lba, 1);
+            assert_eq!(op.cmd, Cmd::WritevAt(Vec::new()));
+            assert_eq!(op.len(), 4 * BYTES_PER_LBA);
+            assert_eq!(merged.len(), 2);
+
+            let op = inner.pop_op().unwrap();
+            let (op, merged) = inner.merge(op);
+            assert_eq!(op.lba, 5);
+            assert!(op.cmd.is_read());
+            assert!(merged.is_empty());
+
+            let op = inner.pop_op().unwrap();
+            let (op, merged) = inner.merge(op);
+            assert_eq!(op.lba, 7);
+            assert!(merged.is_empty());
+            assert!(inner.pop_op().is_none());
+        }
+    }
+
+    // Merging stops at MAX_MERGE bytes
+    test merge_max(mocks) {
+        let leaf = mocks.val;
+        let vdev = VdevBlock::new(leaf);
+        let dbs = DivBufShared::from(vec![0; 4096]);
+        let wbuf = dbs.try_const().unwrap();
+        let per_op = (MAX_MERGE / BYTES_PER_LBA) as LbaT;
+        let mut inner = vdev.inner.borrow_mut();
+        for lba in 1..=per_op + 1 {
+            inner.sched(BlockOp::write_at(wbuf.clone(), lba,
+                oneshot::channel().0));
+        }
+        let op = inner.pop_op().unwrap();
+        let (op, merged) = inner.merge(op);
+        assert_eq!(op.len(), MAX_MERGE);
+        assert_eq!(merged.len() as LbaT, per_op - 1);
+        assert_eq!(inner.pop_op().unwrap().lba, per_op + 1);
+    }
+
+    // Operations in different zones never get merged
+    test merge_zone_boundary(mocks) {
+        let mut leaf = mocks.val;
+        leaf.expect_lba2zone()
+            .with(ge(1 << 16).and(lt(2 << 16)))
+            .return_const(Some(1));
+        let vdev = VdevBlock::new(leaf);
+        let dbs = DivBufShared::from(vec![0; 4096]);
+        let wbuf = dbs.try_const().unwrap();
+        let mut inner = vdev.inner.borrow_mut();
+        inner.sched(BlockOp::write_at(wbuf.clone(), (1 << 16) - 1,
+            oneshot::channel().0));
+        inner.sched(BlockOp::write_at(wbuf.clone(), 1 << 16,
+            oneshot::channel().0));
+        let op = inner.pop_op().unwrap();
+        let (op, merged) = inner.merge(op);
+        assert_eq!(op.lba, (1 << 16) - 1);
+        assert!(merged.is_empty());
+        assert_eq!(inner.pop_op().unwrap().lba, 1 << 16);
+    }
+
+    // Operations beyond the rate limit get delayed
+    test throttle(mocks) {
+        let mut leaf = mocks.val;
+        leaf.expect_write_at()
+            .times(21)
+            .returning(|_, _| Box::new(future::ok::<(), Error>(())));
+        let dbs = DivBufShared::from(vec![0u8; 4096]);
+        let wbuf = dbs.try_const().unwrap();
+        let vdev = VdevBlock::new(leaf);
+        let mut limits = IoLimits::default();
+        limits.write_iops = Some(20);
+        vdev.set_limits(limits);
+        assert_eq!(vdev.limits(), limits);
+        let start = time::Instant::now();
+        current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
+            // Use nonadjacent LBAs, so nothing gets merged
+            future::join_all((0..21).map(|i| {
+                vdev.write_at(wbuf.clone(), 1 + 2 * i,
+                              PriorityClass::Normal)
+            }).collect::<Vec<_>>())
+        })).unwrap();
+        // The bucket starts out full, so only the last write had to wait
+        assert!(start.elapsed() >= time::Duration::from_millis(45));
+    }
+
+    // A throttled background read doesn't hold up other reads
+    test throttle_background(mocks) {
+        let mut leaf = mocks.val;
+        leaf.expect_read_at()
+            .with(always(), eq(1))
+            .once()
+            .returning(|_, _| Box::new(future::ok::<(), Error>(())));
+        leaf.expect_read_at()
+            .with(always(), eq(5))
+            .once()
+            .returning(|_, _| Box::new(future::ok::<(), Error>(())));
+        let dbs0 = DivBufShared::from(vec![0u8; 4096]);
+        let dbs1 = DivBufShared::from(vec![0u8; 4096]);
+        let dbs2 = DivBufShared::from(vec![0u8; 4096]);
+        let vdev = VdevBlock::new(leaf);
+        let mut limits = IoLimits::default();
+        limits.bg_read_iops = Some(1);
+        vdev.set_limits(limits);
+        let mut rt = current_thread::Runtime::new().unwrap();
+        rt.block_on(future::lazy(|| {
+            vdev.read_at(dbs0.try_mut().unwrap(), 1, PriorityClass::Background)
+        })).unwrap();
+        let mut bg_fut = vdev.read_at(dbs1.try_mut().unwrap(), 3,
+                                      PriorityClass::Background);
+        rt.block_on(future::lazy(|| {
+            // Manually poll so the VdevBlockFut will get scheduled
+            assert!(bg_fut.poll().unwrap().is_not_ready());
+            vdev.read_at(dbs2.try_mut().unwrap(), 5, PriorityClass::Normal)
+        })).unwrap();
+        let inner = vdev.inner.borrow();
+        assert_eq!(inner.throttled.len(), 1);
+        assert_eq!(inner.throttled[0].0.lba, 3);
     }
 
     // Queued operations will both complete
@@ -1297,7 +2514,7 @@ test_suite! {
         let mut leaf = mocks.val;
         let mut seq = Sequence::new();
 
-        let (sender, receiver) = oneshot::channel::<()>();
+        let (sender, receiver) = oneshot::channel();
         let e = Error::EPIPE;
         let fut0 = Box::new(receiver.map_err(move |_| e));
         let fut1 = Box::new(future::ok::<(), Error>(()));
@@ -1320,8 +2537,8 @@ test_suite! {
         let rbuf1 = dbs1.try_mut().unwrap();
         let vdev = VdevBlock::new(leaf);
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            let f0 = vdev.read_at(rbuf0, 1);
-            let f1 = vdev.read_at(rbuf1, 2);
+            let f0 = vdev.read_at(rbuf0, 1, PriorityClass::Normal);
+            let f1 = vdev.read_at(rbuf1, 2, PriorityClass::Normal);
             f0.join(f1)
         })).unwrap();
     }
@@ -1329,13 +2546,13 @@ test_suite! {
     // Operations will be buffered after the max queue depth is reached
     // The first MAX_QUEUE_DEPTH operations will be issued immediately, in the
     // order in which they are requested.  Subsequent operations will be
-    // reordered into LBA order
+    // reordered into LBA order, and merged if they're adjacent.
     test issueing_queue_depth(mocks) {
         let mut leaf = mocks.val;
         let num_ops = leaf.optimum_queue_depth() + 2;
         let mut seq = Sequence::new();
 
-        let channels = (0..num_ops - 2).map(|_| oneshot::channel::<()>());
+        let channels = (0..num_ops - 2).map(|_| oneshot::channel());
         let (futs, senders) : (Vec<_>, Vec<_>) = channels.map(|chan| {
             let e = Error::EPIPE;
             (chan.1.map_err(move |_| e), chan.0)
@@ -1349,19 +2566,14 @@ test_suite! {
                 .return_once_st(|_, _| Box::new(f));
         }
         // Schedule the final two operations in reverse LBA order, but verify
-        // that they get issued in actual LBA order
+        // that they get issued in actual LBA order, as a single operation
         let final_fut = future::ok::<(), Error>(());
-        leaf.expect_write_at()
-            .with(always(), eq(LbaT::from(num_ops) - 1))
-            .once()
+        leaf.expect_writev_at()
+            .withf(move |sglist, lba| {
+                sglist.len() == 2 && *lba == LbaT::from(num_ops) - 1
+            }).once()
             .in_sequence(&mut seq)
             .return_once_st(|_, _| Box::new(final_fut));
-        let penultimate_fut = future::ok::<(), Error>(());
-        leaf.expect_write_at()
-            .with(always(), eq(LbaT::from(num_ops)))
-            .once()
-            .in_sequence(&mut seq)
-            .return_once_st(|_, _| Box::new(penultimate_fut));
         let dbs = DivBufShared::from(vec![0u8; 4096]);
         let wbuf = dbs.try_const().unwrap();
         let vdev = VdevBlock::new(leaf);
@@ -1369,23 +2581,26 @@ test_suite! {
             // First schedule all operations.  There are too many to issue them
             // all immediately
             let unbuf_fut = future::join_all((1..num_ops - 1).rev().map(|i| {
-                let mut fut = vdev.write_at(wbuf.clone(), LbaT::from(i));
+                let mut fut = vdev.write_at(wbuf.clone(), LbaT::from(i),
+                                             PriorityClass::Normal);
                 // Manually poll so the VdevBlockFut will get scheduled
                 fut.poll().unwrap();
                 fut
             }));
             let mut penultimate_fut = vdev.write_at(wbuf.clone(),
-                                                    LbaT::from(num_ops));
+                                                    LbaT::from(num_ops),
+                                                    PriorityClass::Normal);
             // Manually poll so the VdevBlockFut will get scheduled
             penultimate_fut.poll().unwrap();
             let mut final_fut = vdev.write_at(wbuf.clone(),
-                                              LbaT::from(num_ops - 1));
+                                              LbaT::from(num_ops - 1),
+                                              PriorityClass::Normal);
             // Manually poll so the VdevBlockFut will get scheduled
             final_fut.poll().unwrap();
             let fut = unbuf_fut.join3(penultimate_fut, final_fut);
             // Verify that they weren't all issued
             let inner = vdev.inner.borrow_mut();
-            assert_eq!(inner.ahead.len() + inner.behind.len(), 2);
+            assert_eq!(inner.scheduler.len(), 2);
             // Finally, complete them.
             for chan in senders {
                 chan.send(()).unwrap();
@@ -1406,7 +2621,7 @@ test_suite! {
         let wbuf = dbs.try_const().unwrap();
         let vdev = VdevBlock::new(leaf);
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            vdev.write_at(wbuf, 1)
+            vdev.write_at(wbuf, 1, PriorityClass::Normal)
         })).unwrap();
     }
 
@@ -1421,7 +2636,7 @@ test_suite! {
         let wbuf = vec![dbs.try_const().unwrap()];
         let vdev = VdevBlock::new(leaf);
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            vdev.writev_at(wbuf, 1)
+            vdev.writev_at(wbuf, 1, PriorityClass::Normal)
         })).unwrap();
     }
 }
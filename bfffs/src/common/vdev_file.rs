@@ -16,7 +16,8 @@ use std::{
         fs::OpenOptionsExt,
         io::{AsRawFd, RawFd}
     },
-    path::Path
+    path::Path,
+    sync::{Arc, Mutex}
 };
 use tokio_file::{AioFut, File, LioFut};
 
@@ -60,34 +61,233 @@ mod ffi {
     }
 }
 
+/// FFI definitions for Linux's zoned block device interface, from
+/// `<linux/blkzoned.h>`.  All offsets and lengths are in 512-byte sectors.
+#[cfg(target_os = "linux")]
+#[doc(hidden)]
+mod zbd {
+    use nix::{ioctl_readwrite, ioctl_write_ptr};
+
+    pub const BLK_ZONE_TYPE_CONVENTIONAL: u8 = 0x1;
+    pub const BLK_ZONE_TYPE_SEQWRITE_REQ: u8 = 0x2;
+    pub const BLK_ZONE_TYPE_SEQWRITE_PREF: u8 = 0x3;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct blk_zone {
+        pub start: u64,
+        pub len: u64,
+        pub wp: u64,
+        pub type_: u8,
+        pub cond: u8,
+        pub non_seq: u8,
+        pub reset: u8,
+        pub resv: [u8; 4],
+        pub capacity: u64,
+        pub reserved: [u8; 24]
+    }
+
+    /// Header of the `BLKREPORTZONE` argument.  It's followed in memory by
+    /// `nr_zones` `blk_zone` structures.
+    #[repr(C)]
+    pub struct blk_zone_report {
+        pub sector: u64,
+        pub nr_zones: u32,
+        pub flags: u32
+    }
+
+    #[repr(C)]
+    pub struct blk_zone_range {
+        pub sector: u64,
+        pub nr_sectors: u64
+    }
+
+    ioctl_readwrite! {
+        #[doc(hidden)]
+        blkreportzone, 0x12, 130, blk_zone_report
+    }
+
+    ioctl_write_ptr! {
+        #[doc(hidden)]
+        blkresetzone, 0x12, 131, blk_zone_range
+    }
+
+    ioctl_write_ptr! {
+        #[doc(hidden)]
+        blkopenzone, 0x12, 134, blk_zone_range
+    }
+
+    ioctl_write_ptr! {
+        #[doc(hidden)]
+        blkfinishzone, 0x12, 136, blk_zone_range
+    }
+}
+
+/// The kind of a single zone, as reported by the device
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ZoneType {
+    /// Random writes are allowed
+    Conventional,
+    /// Writes must be sequential (host-managed)
+    SequentialRequired,
+    /// Writes should be sequential (host-aware)
+    #[cfg_attr(not(target_os = "linux"), allow(unused))]
+    SequentialPreferred
+}
+
+/// One entry of a device's zone report
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct ZoneInfo {
+    start: LbaT,
+    len: LbaT,
+    zone_type: ZoneType
+}
+
+/// How a `VdevFile` implements zones
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ZoneModel {
+    /// The file or device has no native zones.  They are simulated.
+    Simulated,
+    /// A host-managed or host-aware zoned block device.  The first
+    /// `conventional_zones` zones allow random writes.
+    Native{conventional_zones: ZoneT},
+    /// A regular file that emulates a host-managed zoned block device,
+    /// including its sequential write requirement and its limit on open zones,
+    /// if any.  Used for testing.
+    Emulated{conventional_zones: ZoneT, max_open_zones: Option<ZoneT>}
+}
+
+impl ZoneModel {
+    fn conventional_zones(self) -> ZoneT {
+        match self {
+            ZoneModel::Simulated => ZoneT::max_value(),
+            ZoneModel::Native{conventional_zones} => conventional_zones,
+            ZoneModel::Emulated{conventional_zones, ..} => conventional_zones,
+        }
+    }
+}
+
+/// The sizes of a device's zones.
+///
+/// Zones need not all be the same size, but real devices rarely have more than
+/// a few distinct sizes, so they are stored run-length encoded.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct ZoneLayout {
+    /// Each run is a count of consecutive zones that all have the same number
+    /// of LBAs: `(lbas, count)`
+    runs: Vec<(LbaT, ZoneT)>
+}
+
+impl ZoneLayout {
+    /// Build a layout from the lengths of each zone, in order.
+    fn from_lens(lens: impl IntoIterator<Item=LbaT>) -> Self {
+        let mut runs: Vec<(LbaT, ZoneT)> = Vec::new();
+        for len in lens {
+            match runs.last_mut() {
+                Some((lbas, count)) if *lbas == len => *count += 1,
+                _ => runs.push((len, 1))
+            }
+        }
+        ZoneLayout{runs}
+    }
+
+    /// Return the zone that contains `lba`, or `None` if it lies beyond the
+    /// last zone.
+    fn lba2zone(&self, lba: LbaT) -> Option<ZoneT> {
+        let mut start = 0;
+        let mut zone = 0;
+        for &(lbas, count) in self.runs.iter() {
+            let end = start + lbas * LbaT::from(count);
+            if lba < end {
+                return Some(zone + ((lba - start) / lbas) as ZoneT);
+            }
+            start = end;
+            zone += count;
+        }
+        None
+    }
+
+    /// A layout of `zones` zones, each `lbas` long
+    fn uniform(lbas: LbaT, zones: ZoneT) -> Self {
+        ZoneLayout{runs: vec![(lbas, zones)]}
+    }
+
+    /// The first LBA of `zone`.  If `zone` is one past the last zone, then
+    /// return the end of the last zone instead.
+    fn zone_start(&self, zone: ZoneT) -> LbaT {
+        let mut start = 0;
+        let mut first = 0;
+        for &(lbas, count) in self.runs.iter() {
+            if zone < first + count {
+                return start + LbaT::from(zone - first) * lbas;
+            }
+            start += lbas * LbaT::from(count);
+            first += count;
+        }
+        assert_eq!(zone, first, "zone {} is out of range", zone);
+        start
+    }
+
+    /// Return the first and last LBAs of a zone.  The end is exclusive.
+    fn zone_limits(&self, zone: ZoneT) -> (LbaT, LbaT) {
+        (self.zone_start(zone), self.zone_start(zone + 1))
+    }
+
+    fn zones(&self) -> ZoneT {
+        self.runs.iter().map(|&(_, count)| count).sum()
+    }
+}
+
+/// Write pointer tracking for `ZoneModel::Emulated`
+#[derive(Debug)]
+struct ZoneEmulator {
+    /// The write pointer of every zone.  `None` means unknown.  Write pointers
+    /// aren't persisted, so they're unknown after reopening the file until
+    /// their zones are next erased.  A write moves its zone's write pointer
+    /// when it's issued, so that further writes can be queued behind it, but
+    /// moves it back if it fails.
+    wps: Mutex<Vec<Option<LbaT>>>
+}
+
+impl ZoneEmulator {
+    fn new(zone_starts: impl Iterator<Item=Option<LbaT>>) -> Self {
+        ZoneEmulator{wps: Mutex::new(zone_starts.collect())}
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Label {
     /// Vdev UUID, fixed at format time
     uuid:           Uuid,
-    /// Number of LBAs per simulated zone
-    lbas_per_zone:  LbaT,
-    /// Number of LBAs that were present at format time
+    /// Sizes of the zones, whether simulated or native
+    layout:         ZoneLayout,
+    /// Number of LBAs that were present when the label was written
     lbas:           LbaT,
-    /// LBAs in the first zone reserved for storing each spacemap.
-    spacemap_space:    LbaT
+    /// How zones are implemented
+    zone_model:     ZoneModel
 }
 
 /// `VdevFile`: File-backed implementation of `VdevBlock`
 ///
 /// This is used by the FUSE implementation of BFFFS.  It works with both
-/// regular files and device files
+/// regular files and device files.  On Linux, host-managed and host-aware zoned
+/// block devices are detected, and their native zone layout is used.  The
+/// labels must fit within the device's first zone, which must be conventional.
 ///
 #[derive(Debug)]
 pub struct VdevFile {
     file:           File,
-    /// Number of reserved LBAS in first zone for each spacemap
-    spacemap_space: LbaT,
-    /// Number of LBAs per simulated zone
-    lbas_per_zone:  LbaT,
+    /// Sizes of the zones, whether simulated or native
+    layout:         ZoneLayout,
     size:           LbaT,
     uuid:           Uuid,
     ///// Does the underlying file or device support delete-like operations?
-    candelete:      bool
+    candelete:      bool,
+    zone_model:     ZoneModel,
+    /// Only present for `ZoneModel::Emulated`
+    emulator:       Option<Arc<ZoneEmulator>>,
+    /// The device's limit on simultaneously open zones, if any
+    max_open_zones: Option<ZoneT>
 }
 
 /// Tokio-File requires boxed `DivBufs`, but the upper layers of BFFFS don't.
@@ -115,13 +315,20 @@ impl BorrowMut<[u8]> for IoVecMutContainer {
 
 impl Vdev for VdevFile {
     fn lba2zone(&self, lba: LbaT) -> Option<ZoneT> {
-        if lba >= self.reserved_space() {
-            Some((lba / (self.lbas_per_zone as u64)) as ZoneT)
+        let backup_start = self.backup_start();
+        let in_backup = lba >= backup_start &&
+            lba < backup_start + self.reserved_space();
+        if lba >= self.reserved_space() && !in_backup {
+            self.layout.lba2zone(lba)
         } else {
             None
         }
     }
 
+    fn max_open_zones(&self) -> Option<ZoneT> {
+        self.max_open_zones
+    }
+
     fn optimum_queue_depth(&self) -> u32 {
         // The value `10` is just a total guess.
         10
@@ -143,26 +350,49 @@ impl Vdev for VdevFile {
     }
 
     fn zone_limits(&self, zone: ZoneT) -> (LbaT, LbaT) {
+        let (mut start, mut end) = self.layout.zone_limits(zone);
         if zone == 0 {
-            (self.reserved_space(), self.lbas_per_zone)
-        } else {
-            (u64::from(zone) * self.lbas_per_zone,
-             u64::from(zone + 1) * self.lbas_per_zone)
+            start = self.reserved_space();
         }
+        let backup_start = self.backup_start();
+        if start <= backup_start && backup_start < end {
+            // This zone holds the backup labels
+            end = backup_start;
+        }
+        (start, end)
     }
 
     fn zones(&self) -> ZoneT {
-        div_roundup(self.size, self.lbas_per_zone) as ZoneT
+        if self.zone_model == ZoneModel::Simulated {
+            // The backup labels are the end of the last zone
+            self.layout.lba2zone(self.backup_start() - 1).unwrap() + 1
+        } else {
+            self.layout.zones()
+        }
     }
 }
 
 impl VdevLeafApi for VdevFile {
     fn erase_zone(&self, lba: LbaT) -> Box<VdevFut> {
+        match self.zone_model {
+            ZoneModel::Simulated => (),
+            ZoneModel::Native{..} => {
+                if self.is_conventional(lba) {
+                    return Box::new(future::ok::<(), Error>(()));
+                }
+                return self.zone_mgmt(ZoneOp::Reset, lba);
+            },
+            ZoneModel::Emulated{..} => {
+                self.emulate_zone_op(ZoneOp::Reset, lba);
+                return Box::new(future::ok::<(), Error>(()));
+            }
+        }
         let fut = if self.candelete {
             // There isn't (yet) a way to asynchronously trim, so use a
             // synchronous ioctl.
+            let end = self.zone_limits(self.layout.lba2zone(lba).unwrap()).1;
             let off = lba as off_t * (BYTES_PER_LBA as off_t);
-            let len = self.lbas_per_zone as off_t * BYTES_PER_LBA as off_t;
+            let len = (end - lba) as off_t * BYTES_PER_LBA as off_t;
             let args = [off, len];
             unsafe {
                 ffi::diocgdelete(self.file.as_raw_fd(), &args)
@@ -175,14 +405,12 @@ impl VdevLeafApi for VdevFile {
         Box::new(fut)
     }
 
-    fn finish_zone(&self, _lba: LbaT) -> Box<VdevFut> {
-        // ordinary files don't have Zone operations
-        Box::new(future::ok::<(), Error>(()))
+    fn finish_zone(&self, lba: LbaT) -> Box<VdevFut> {
+        self.zone_op(ZoneOp::Finish, lba)
     }
 
-    fn open_zone(&self, _lba: LbaT) -> Box<VdevFut> {
-        // ordinary files don't have Zone operations
-        Box::new(future::ok::<(), Error>(()))
+    fn open_zone(&self, lba: LbaT) -> Box<VdevFut> {
+        self.zone_op(ZoneOp::Open, lba)
     }
 
     fn read_at(&self, buf: IoVecMut, lba: LbaT) -> Box<VdevFut> {
@@ -192,15 +420,6 @@ impl VdevLeafApi for VdevFile {
         Box::new(fut)
     }
 
-    fn read_spacemap(&self, buf: IoVecMut, idx: u32) -> Box<VdevFut> {
-        assert!(LbaT::from(idx) < LABEL_COUNT);
-        let lba = u64::from(idx) * self.spacemap_space + 2 * LABEL_LBAS;
-        let container = Box::new(IoVecMutContainer(buf));
-        let off = lba * (BYTES_PER_LBA as u64);
-        let fut = VdevFileFut(self.file.read_at(container, off).unwrap());
-        Box::new(fut)
-    }
-
     fn readv_at(&self, buf: SGListMut, lba: LbaT) -> Box<VdevFut> {
         let off = lba * (BYTES_PER_LBA as u64);
         let containers = buf.into_iter().map(|iovec| {
@@ -210,54 +429,48 @@ impl VdevLeafApi for VdevFile {
         Box::new(fut)
     }
 
-    fn spacemap_space(&self) -> LbaT {
-        self.spacemap_space
-    }
-
     fn write_at(&self, buf: IoVec, lba: LbaT) -> Box<VdevFut> {
+        let lbas = (buf.len() / BYTES_PER_LBA) as LbaT;
         assert!(lba >= self.reserved_space(), "Don't overwrite the labels!");
+        assert!(lba + lbas <= self.backup_start() ||
+                lba >= self.backup_start() + self.reserved_space(),
+                "Don't overwrite the backup labels!");
+        if let Err(e) = self.emulate_write(lba, lbas) {
+            return Box::new(future::err(e));
+        }
         let container = Box::new(IoVecContainer(buf));
-        Box::new(self.write_at_unchecked(container, lba))
+        self.emulate_write_completion(lba,
+                                      self.write_at_unchecked(container, lba))
     }
 
     fn write_label(&self, mut label_writer: LabelWriter) -> Box<VdevFut> {
         let label = Label {
             uuid: self.uuid,
-            spacemap_space: self.spacemap_space,
-            lbas_per_zone: self.lbas_per_zone,
-            lbas: self.size
+            layout: self.layout.clone(),
+            lbas: self.size,
+            zone_model: self.zone_model
         };
         label_writer.serialize(&label).unwrap();
+        let idx = label_writer.idx();
         let lba = label_writer.lba();
-        let sglist = label_writer.into_sglist();
-        Box::new(self.writev_at(sglist, lba))
-    }
-
-    fn write_spacemap(&self, buf: SGList, idx: u32, block: LbaT) -> Box<VdevFut>
-    {
-        assert!(LbaT::from(idx) < LABEL_COUNT);
-        let lba = block + u64::from(idx) * self.spacemap_space + 2 * LABEL_LBAS;
-        let bytes: u64 = buf.iter()
-            .map(DivBuf::len)
-            .sum::<usize>() as u64;
-        debug_assert_eq!(bytes % BYTES_PER_LBA as u64, 0);
-        let lbas = bytes / BYTES_PER_LBA as LbaT;
-        assert!(lba + lbas <= self.reserved_space());
-        let containers = buf.into_iter().map(|iovec| {
-            Box::new(IoVecContainer(iovec)) as Box<dyn Borrow<[u8]>>
-        }).collect();
-        let off = lba * (BYTES_PER_LBA as u64);
-        let fut = VdevFileLioFut(self.file.writev_at(containers, off).unwrap());
+        let backup_lba = VdevFile::backup_label_lba(self.backup_end(), idx);
+        let sglist = match label_writer.into_sglist() {
+            Ok(sglist) => sglist,
+            Err(e) => return Box::new(future::err(e))
+        };
+        let fut = self.writev_at_unchecked(sglist.clone(), lba)
+            .join(self.writev_at_unchecked(sglist, backup_lba))
+            .map(drop);
         Box::new(fut)
     }
 
     fn writev_at(&self, buf: SGList, lba: LbaT) -> Box<VdevFut> {
-        let off = lba * (BYTES_PER_LBA as u64);
-        let containers = buf.into_iter().map(|iovec| {
-            Box::new(IoVecContainer(iovec)) as Box<dyn Borrow<[u8]>>
-        }).collect();
-        let fut = VdevFileLioFut(self.file.writev_at(containers, off).unwrap());
-        Box::new(fut)
+        let bytes = buf.iter().map(DivBuf::len).sum::<usize>();
+        let lbas = (bytes / BYTES_PER_LBA) as LbaT;
+        if let Err(e) = self.emulate_write(lba, lbas) {
+            return Box::new(future::err(e));
+        }
+        self.emulate_write_completion(lba, self.writev_at_unchecked(buf, lba))
     }
 }
 
@@ -265,6 +478,35 @@ impl VdevFile {
     /// Size of a simulated zone
     const DEFAULT_LBAS_PER_ZONE: LbaT = 1 << 16;  // 256 MB
 
+    /// Size of each reserved region, primary or backup
+    const RESERVED_LBAS: LbaT = LABEL_COUNT * LABEL_LBAS;
+
+    /// One past the last LBA of the backup reserved region
+    fn backup_end(&self) -> LbaT {
+        VdevFile::backup_end_for(self.zone_model, &self.layout, self.size)
+    }
+
+    /// Locate the backup reserved region.  It must lie in a conventional zone.
+    fn backup_end_for(zone_model: ZoneModel, layout: &ZoneLayout, size: LbaT)
+        -> LbaT
+    {
+        match zone_model {
+            ZoneModel::Simulated => size,
+            _ => layout.zone_start(zone_model.conventional_zones())
+        }
+    }
+
+    /// LBA of the `label`th backup label.
+    fn backup_label_lba(backup_end: LbaT, label: u32) -> LbaT {
+        assert!(LbaT::from(label) < LABEL_COUNT);
+        backup_end - (LABEL_COUNT - LbaT::from(label)) * LABEL_LBAS
+    }
+
+    /// First LBA of the backup reserved region
+    fn backup_start(&self) -> LbaT {
+        self.backup_end() - self.reserved_space()
+    }
+
     fn candelete(fd: RawFd) -> Result<bool, Error> {
         let mut arg = MaybeUninit::<ffi::diocgattr_arg>::uninit();
         let r = unsafe {
@@ -283,9 +525,24 @@ impl VdevFile {
         }
     }
 
+    /// Overwrite both of the vdev's reserved regions with zeros.
+    ///
+    /// Afterwards, the vdev will no longer be recognized as belonging to any
+    /// pool.
+    pub fn clear_labels(&self) -> impl Future<Item=(), Error=Error> {
+        let len = VdevFile::RESERVED_LBAS as usize * BYTES_PER_LBA;
+        let dbs = DivBufShared::from(vec![0u8; len]);
+        let primary = IoVecContainer(dbs.try_const().unwrap());
+        let backup = IoVecContainer(dbs.try_const().unwrap());
+        self.write_at_unchecked(Box::new(primary), 0)
+        .join(self.write_at_unchecked(Box::new(backup), self.backup_start()))
+        .map(move |_| drop(dbs))
+    }
+
     /// Create a new Vdev, backed by a file
     ///
     /// * `path`:           Pathname for the file.  It may be a device node.
+    ///                     It must already exist.
     /// * `lbas_per_zone`:  If specified, this many LBAs will be assigned to
     ///                     simulated zones on devices that don't have native
     ///                     zones.
@@ -293,30 +550,248 @@ impl VdevFile {
         -> io::Result<Self>
         where P: AsRef<Path> + 'static
     {
-        let f = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .custom_flags(libc::O_DIRECT)
-            .open(path)
-            .map(File::new).unwrap();
-        let lpz = match lbas_per_zone {
-            None => VdevFile::DEFAULT_LBAS_PER_ZONE,
-            Some(x) => x.get()
+        let max_open_zones = VdevFile::read_max_open_zones(path.as_ref());
+        let f = VdevFile::open_file(path)?;
+        let (zone_model, layout, size) = match VdevFile::report_zones(
+            f.as_raw_fd())
+        {
+            Ok(None) => {
+                let lpz = match lbas_per_zone {
+                    None => VdevFile::DEFAULT_LBAS_PER_ZONE,
+                    Some(x) => x.get()
+                };
+                let size = f.len()? / BYTES_PER_LBA as u64;
+                if size <= 2 * VdevFile::RESERVED_LBAS {
+                    // Too small to hold even the labels
+                    return Err(io::Error::from_raw_os_error(libc::EINVAL));
+                }
+                let nzones = div_roundup(size, lpz) as ZoneT;
+                let layout = ZoneLayout::uniform(lpz, nzones);
+                (ZoneModel::Simulated, layout, size)
+            },
+            Ok(Some(report)) => {
+                let (layout, conventional_zones, size) =
+                    VdevFile::layout_from_report(&report)
+                    .map_err(|e| io::Error::from_raw_os_error(e.into()))?;
+                (ZoneModel::Native{conventional_zones}, layout, size)
+            },
+            Err(e) => return Err(io::Error::from_raw_os_error(e.into()))
+        };
+        let mut vdev = VdevFile::new(f, zone_model, layout, size);
+        if let ZoneModel::Native{..} = zone_model {
+            vdev.max_open_zones = max_open_zones;
+        }
+        Ok(vdev)
+    }
+
+    /// Create a new Vdev, backed by a regular file that emulates a
+    /// host-managed zoned block device.
+    ///
+    /// * `path`:               Pathname for the file.
+    /// * `lbas_per_zone`:      Size of each emulated zone.
+    /// * `conventional_zones`: Number of leading zones that allow random
+    ///                         writes.  Must be at least one, to hold the
+    ///                         labels.
+    /// * `max_open_zones`:     The device's limit on simultaneously open
+    ///                         zones, if any.
+    pub fn create_emulated<P>(path: P, lbas_per_zone: NonZeroU64,
+                              conventional_zones: ZoneT,
+                              max_open_zones: Option<ZoneT>) -> io::Result<Self>
+        where P: AsRef<Path> + 'static
+    {
+        let lpz = lbas_per_zone.get();
+        let size = path.as_ref().metadata()?.len() / BYTES_PER_LBA as u64;
+        let zone_lbas = (0..div_roundup(size, lpz))
+            .map(|z| lpz.min(size - z * lpz))
+            .collect::<Vec<_>>();
+        VdevFile::create_emulated_zones(path, &zone_lbas, conventional_zones,
+                                        max_open_zones)
+    }
+
+    /// Like [`create_emulated`](#method.create_emulated), but with zones of
+    /// arbitrary sizes.
+    ///
+    /// * `path`:               Pathname for the file.
+    /// * `zone_lbas`:          Size of each emulated zone, in order.  Together
+    ///                         they must not exceed the size of the file.
+    /// * `conventional_zones`: Number of leading zones that allow random
+    ///                         writes.  Must be at least one, to hold the
+    ///                         labels.
+    /// * `max_open_zones`:     The device's limit on simultaneously open
+    ///                         zones, if any.
+    pub fn create_emulated_zones<P>(path: P, zone_lbas: &[LbaT],
+                                    conventional_zones: ZoneT,
+                                    max_open_zones: Option<ZoneT>)
+        -> io::Result<Self>
+        where P: AsRef<Path> + 'static
+    {
+        let f = VdevFile::open_file(path)?;
+        let mut start = 0;
+        let report = zone_lbas.iter().enumerate().map(|(z, &len)| {
+            let zone_type = if (z as ZoneT) < conventional_zones {
+                ZoneType::Conventional
+            } else {
+                ZoneType::SequentialRequired
+            };
+            start += len;
+            ZoneInfo{start: start - len, len, zone_type}
+        }).collect::<Vec<_>>();
+        if start > f.len().unwrap() / BYTES_PER_LBA as u64 {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        let (layout, _, size) = VdevFile::layout_from_report(&report)
+            .map_err(|e| io::Error::from_raw_os_error(e.into()))?;
+        let zone_model = ZoneModel::Emulated{conventional_zones,
+                                             max_open_zones};
+        let mut vdev = VdevFile::new(f, zone_model, layout, size);
+        vdev.max_open_zones = max_open_zones;
+        let emulator = ZoneEmulator::new(report.iter().map(|zi| {
+            if zi.zone_type == ZoneType::Conventional {
+                None
+            } else {
+                Some(zi.start)
+            }
+        }));
+        vdev.emulator = Some(Arc::new(emulator));
+        Ok(vdev)
+    }
+
+    /// Enforce the sequential write requirement of an emulated zoned device.
+    fn emulate_write(&self, lba: LbaT, lbas: LbaT) -> Result<(), Error> {
+        if let Some(emulator) = &self.emulator {
+            if self.is_conventional(lba) {
+                return Ok(());
+            }
+            let zone = self.layout.lba2zone(lba).unwrap();
+            let end = self.zone_limits(zone).1;
+            let mut wps = emulator.wps.lock().unwrap();
+            let wp = &mut wps[zone as usize];
+            if lba + lbas > end || wp.map(|x| x != lba).unwrap_or(false) {
+                // Like a real device, reject unaligned writes
+                return Err(Error::EIO);
+            }
+            *wp = Some(lba + lbas);
+        }
+        Ok(())
+    }
+
+    /// Undo `emulate_write` if the write at `lba` fails.  Like on a real
+    /// device, the zone's write pointer is left where the failed write began,
+    /// so it doesn't leave a gap.
+    fn emulate_write_completion<F>(&self, lba: LbaT, fut: F) -> Box<VdevFut>
+        where F: Future<Item=(), Error=Error> + 'static
+    {
+        let emulator = match &self.emulator {
+            Some(emulator) if !self.is_conventional(lba) => emulator.clone(),
+            _ => return Box::new(fut)
         };
-        let candelete = VdevFile::candelete(f.as_raw_fd()).unwrap();
-        let size = f.len().unwrap() / BYTES_PER_LBA as u64;
-        let nzones = div_roundup(size, lpz);
-        let spacemap_space = spacemap_space(nzones);
+        let zone = self.layout.lba2zone(lba).unwrap();
+        Box::new(fut.map_err(move |e| {
+            let mut wps = emulator.wps.lock().unwrap();
+            let wp = &mut wps[zone as usize];
+            if wp.map(|x| x > lba).unwrap_or(false) {
+                *wp = Some(lba);
+            }
+            e
+        }))
+    }
+
+    /// Adjust an emulated zone's write pointer.
+    fn emulate_zone_op(&self, op: ZoneOp, lba: LbaT) {
+        if self.is_conventional(lba) {
+            return;
+        }
+        let zone = self.layout.lba2zone(lba).unwrap();
+        let limits = self.zone_limits(zone);
+        let mut wps = self.emulator.as_ref().unwrap().wps.lock().unwrap();
+        match op {
+            ZoneOp::Finish => wps[zone as usize] = Some(limits.1),
+            ZoneOp::Open => (),
+            ZoneOp::Reset => wps[zone as usize] = Some(limits.0)
+        }
+    }
+
+    /// Does `lba` lie within a zone that allows random writes?
+    pub fn is_conventional(&self, lba: LbaT) -> bool {
+        self.layout.lba2zone(lba)
+            .map(|zone| zone < self.zone_model.conventional_zones())
+            .unwrap_or(false)
+    }
+
+    /// Derive the zone layout from a device's zone report.
+    ///
+    /// Returns the zone layout, the number of leading conventional zones, and
+    /// the total size of the device in LBAs.  Zones may have any nonzero size,
+    /// but they must be contiguous.  BFFFS also requires the first zone to be
+    /// conventional and large enough for both copies of the labels, and the
+    /// last conventional zone to be large enough for the backup copy.
+    fn layout_from_report(report: &[ZoneInfo])
+        -> Result<(ZoneLayout, ZoneT, LbaT), Error>
+    {
+        match report.first() {
+            Some(zi) if zi.zone_type == ZoneType::Conventional => (),
+            _ => return Err(Error::EINVAL)
+        };
+        let mut size = 0;
+        for zi in report.iter() {
+            if zi.start != size || zi.len == 0 {
+                return Err(Error::EINVAL);
+            }
+            size += zi.len;
+        }
+        let conventional_zones = report.iter()
+            .take_while(|zi| zi.zone_type == ZoneType::Conventional)
+            .count() as ZoneT;
+        let reserved = VdevFile::RESERVED_LBAS;
+        if 2 * reserved > report[0].len ||
+            reserved > report[conventional_zones as usize - 1].len
+        {
+            return Err(Error::EINVAL);
+        }
+        let layout = ZoneLayout::from_lens(report.iter().map(|zi| zi.len));
+        Ok((layout, conventional_zones, size))
+    }
+
+    fn new(file: File, zone_model: ZoneModel, layout: ZoneLayout, size: LbaT)
+        -> Self
+    {
+        let candelete = VdevFile::candelete(file.as_raw_fd()).unwrap();
         let uuid = Uuid::new_v4();
-        Ok(VdevFile{
-            file: f,
-            spacemap_space,
-            lbas_per_zone: lpz,
+        VdevFile{
+            file,
+            layout,
             size,
             uuid,
-            candelete
-        })
+            candelete,
+            zone_model,
+            emulator: None,
+            max_open_zones: None
+        }
+    }
+
+    fn open_file<P: AsRef<Path>>(path: P) -> io::Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(path)
+            .map(File::new)
+    }
+
+    /// Claim the vdev for the pool that's being imported from it.
+    ///
+    /// Takes an exclusive advisory lock, which is released when the `VdevFile`
+    /// is dropped or the process exits.  Fails with `EBUSY` if another import,
+    /// in this process or any other, already holds it.
+    pub fn lock(&self) -> Result<(), Error> {
+        let fd = self.file.as_raw_fd();
+        if unsafe { libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+            return Ok(());
+        }
+        match io::Error::last_os_error().raw_os_error().unwrap() {
+            libc::EWOULDBLOCK => Err(Error::EBUSY),
+            e => Err(Error::from_i32(e).unwrap())
+        }
     }
 
     /// Open an existing `VdevFile`
@@ -328,6 +803,7 @@ impl VdevFile {
     pub fn open<P: AsRef<Path>>(path: P)
         -> impl Future<Item=(Self, LabelReader), Error=Error>
     {
+        let max_open_zones = VdevFile::read_max_open_zones(path.as_ref());
         OpenOptions::new()
         .read(true)
         .write(true)
@@ -336,34 +812,128 @@ impl VdevFile {
         .map(File::new)
         .into_future()
         .map_err(|e| Error::from_i32(e.raw_os_error().unwrap()).unwrap())
-        .and_then(|f| {
-            VdevFile::read_label(f, 0)
-            .or_else(move |(_e, f)| {
-                // Try the second label
-                VdevFile::read_label(f, 1)
-            }).and_then(move |(mut label_reader, f)| {
+        .and_then(move |f| {
+            VdevFile::read_label(f, LabelReader::lba(0))
+            .and_then(|(r0, f)| {
+                VdevFile::read_label(f, LabelReader::lba(1))
+                .map(move |(r1, f)| (vec![r0, r1], f))
+            }).and_then(|(copies, f)| {
+                // Locate the backup labels, from a primary label if possible
+                let backup_end = copies.iter()
+                    .filter_map(|copy| copy.as_ref().ok())
+                    .filter_map(|lr| lr.clone().deserialize::<Label>().ok())
+                    .map(|l| VdevFile::backup_end_for(l.zone_model,
+                                                      &l.layout, l.lbas))
+                    .next()
+                    .unwrap_or_else(|| VdevFile::probe_backup_end(&f));
+                VdevFile::read_backup_labels(f, backup_end)
+                .map(move |(backups, f)| {
+                    let mut copies = copies;
+                    copies.extend(backups);
+                    (copies, f)
+                })
+            }).and_then(move |(copies, f)| {
+                let (_, mut label_reader) = LabelReader::newest(copies)?;
                 let candelete = VdevFile::candelete(f.as_raw_fd()).unwrap();
                 let size = f.len().unwrap() / BYTES_PER_LBA as u64;
                 let label: Label = label_reader.deserialize().unwrap();
-                assert!(size >= label.lbas, "Vdev has shrunk since creation");
+                let (layout, lbas) = if label.zone_model ==
+                    ZoneModel::Simulated
+                {
+                    // Zoned devices' sizes come from the zone report instead.
+                    assert!(size >= label.lbas,
+                            "Vdev has shrunk since creation");
+                    VdevFile::grow(label.layout, label.lbas, size)
+                } else {
+                    (label.layout, label.lbas)
+                };
+                let emulator = if let ZoneModel::Emulated{..} =
+                    label.zone_model
+                {
+                    let nzones = label.layout.zones();
+                    let emulator = ZoneEmulator::new((0..nzones).map(|_| None));
+                    Some(Arc::new(emulator))
+                } else {
+                    None
+                };
+                let max_open_zones = match label.zone_model {
+                    ZoneModel::Native{..} => max_open_zones,
+                    ZoneModel::Emulated{max_open_zones, ..} => max_open_zones,
+                    ZoneModel::Simulated => None
+                };
                 let vdev = VdevFile {
                     file: f,
-                    spacemap_space: label.spacemap_space,
-                    lbas_per_zone: label.lbas_per_zone,
-                    size: label.lbas,
+                    layout,
+                    size: lbas,
                     uuid: label.uuid,
-                    candelete
+                    candelete,
+                    zone_model: label.zone_model,
+                    emulator,
+                    max_open_zones
                 };
                 Ok((vdev, label_reader))
-            }).map_err(|(e, _f)| e)
+            })
         })
     }
 
-    /// Read just one of a vdev's labels
-    fn read_label(f: File, label: u32)
-        -> impl Future<Item=(LabelReader, File), Error=(Error, File)>
+    /// Extend a simulated zone layout over a device that has grown from `lbas`
+    /// to `size` LBAs.
+    ///
+    /// Existing zones keep their starts.  The last one grows to its full size
+    /// and absorbs the old backup labels, and new zones follow it.  The backup
+    /// labels move to the new end of the device the next time they're written.
+    /// Until then, they're still found where the primary labels say.
+    fn grow(layout: ZoneLayout, lbas: LbaT, size: LbaT) -> (ZoneLayout, LbaT) {
+        if size <= lbas {
+            return (layout, lbas);
+        }
+        let lpz = layout.zone_limits(0).1;
+        let nzones = div_roundup(size, lpz) as ZoneT;
+        (ZoneLayout::uniform(lpz, nzones), size)
+    }
+
+    /// Guess where the backup reserved region ends, when no primary label is
+    /// readable.
+    ///
+    /// For emulated zoned devices, the backup can only be found with the help
+    /// of a primary label.
+    fn probe_backup_end(f: &File) -> LbaT {
+        match VdevFile::report_zones(f.as_raw_fd()) {
+            Ok(Some(report)) => {
+                VdevFile::layout_from_report(&report)
+                .map(|(layout, conventional_zones, _)| {
+                    layout.zone_start(conventional_zones)
+                }).unwrap_or(0)
+            },
+            _ => f.len().unwrap_or(0) / BYTES_PER_LBA as u64
+        }
+    }
+
+    /// Read all of the backup labels of a reserved region ending at
+    /// `backup_end`
+    fn read_backup_labels(f: File, backup_end: LbaT)
+        -> impl Future<Item=(Vec<Result<LabelReader, Error>>, File),
+                       Error=Error>
+    {
+        if backup_end < 2 * LABEL_COUNT * LABEL_LBAS {
+            // Too small to hold any backup labels
+            let copies = vec![Err(Error::EINVAL); LABEL_COUNT as usize];
+            return future::Either::A(future::ok((copies, f)));
+        }
+        let lba0 = VdevFile::backup_label_lba(backup_end, 0);
+        let lba1 = VdevFile::backup_label_lba(backup_end, 1);
+        let fut = VdevFile::read_label(f, lba0)
+        .and_then(move |(b0, f)| {
+            VdevFile::read_label(f, lba1)
+            .map(move |(b1, f)| (vec![b0, b1], f))
+        });
+        future::Either::B(fut)
+    }
+
+    /// Read just one copy of a vdev's labels, at `lba`
+    fn read_label(f: File, lba: LbaT)
+        -> impl Future<Item=(Result<LabelReader, Error>, File), Error=Error>
     {
-        let lba = LabelReader::lba(label);
         let offset = lba * BYTES_PER_LBA as u64;
         // TODO: figure out how to use mem::MaybeUninit with divbuf
         let dbs = DivBufShared::uninitialized(LABEL_SIZE);
@@ -371,21 +941,110 @@ impl VdevFile {
         let container = Box::new(IoVecMutContainer(dbm));
         f.read_at(container, offset).unwrap()
         .then(move |r| {
-            match r {
+            let lr = match r {
                 Ok(aio_result) => {
                     drop(aio_result);   // release reference on dbs
-                    match LabelReader::from_dbs(dbs) {
-                        Ok(lr) => Ok((lr, f)),
-                        Err(e) => Err((e, f))
-                    }
+                    LabelReader::from_dbs(dbs)
                 },
-                Err(e) => Err((Error::from(e), f))
-            }
+                Err(e) => Err(Error::from(e))
+            };
+            Ok((lr, f))
         })
     }
 
+    /// Read a zoned block device's zone report.
+    ///
+    /// Returns `None` if the device isn't zoned.
+    #[cfg(target_os = "linux")]
+    fn report_zones(fd: RawFd) -> Result<Option<Vec<ZoneInfo>>, Error> {
+        const BATCH: usize = 128;
+        const SECTORS_PER_LBA: u64 = BYTES_PER_LBA as u64 / 512;
+        let buflen = mem::size_of::<zbd::blk_zone_report>() +
+            BATCH * mem::size_of::<zbd::blk_zone>();
+        // Use a u64 buffer to satisfy the structures' alignment
+        let mut buf = vec![0u64; buflen / mem::size_of::<u64>()];
+        let mut report = Vec::new();
+        let mut sector = 0;
+        loop {
+            let hdr = buf.as_mut_ptr() as *mut zbd::blk_zone_report;
+            let r = unsafe {
+                (*hdr).sector = sector;
+                (*hdr).nr_zones = BATCH as u32;
+                (*hdr).flags = 0;
+                zbd::blkreportzone(fd, hdr)
+            }.map_err(Error::from);
+            match r {
+                // Regular files and conventional block devices
+                Err(Error::ENOTTY) | Err(Error::ENOTSUP) => return Ok(None),
+                Err(e) => return Err(e),
+                Ok(_) => ()
+            }
+            let zones = unsafe {
+                let nr_zones = (*hdr).nr_zones as usize;
+                let first = hdr.add(1) as *const zbd::blk_zone;
+                std::slice::from_raw_parts(first, nr_zones)
+            };
+            if zones.is_empty() {
+                break;
+            }
+            for z in zones {
+                let zone_type = match z.type_ {
+                    zbd::BLK_ZONE_TYPE_CONVENTIONAL => ZoneType::Conventional,
+                    zbd::BLK_ZONE_TYPE_SEQWRITE_REQ =>
+                        ZoneType::SequentialRequired,
+                    zbd::BLK_ZONE_TYPE_SEQWRITE_PREF =>
+                        ZoneType::SequentialPreferred,
+                    _ => return Err(Error::EINVAL)
+                };
+                report.push(ZoneInfo {
+                    start: z.start / SECTORS_PER_LBA,
+                    len: z.len / SECTORS_PER_LBA,
+                    zone_type
+                });
+                sector = z.start + z.len;
+            }
+        }
+        Ok(Some(report))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn report_zones(_fd: RawFd) -> Result<Option<Vec<ZoneInfo>>, Error> {
+        Ok(None)
+    }
+
+    /// Read a zoned block device's limit on open zones from sysfs.
+    ///
+    /// Devices may limit both open and active zones.  Since BFFFS finishes
+    /// every zone that it closes, the two limits are equivalent for it.  A
+    /// value of zero means no limit.
+    #[cfg(target_os = "linux")]
+    fn read_max_open_zones(path: &Path) -> Option<ZoneT> {
+        let devpath = std::fs::canonicalize(path).ok()?;
+        let queue = Path::new("/sys/class/block")
+            .join(devpath.file_name()?)
+            .join("queue");
+        ["max_open_zones", "max_active_zones"].iter()
+            .filter_map(|attr| std::fs::read_to_string(queue.join(attr)).ok())
+            .filter_map(|s| s.trim().parse::<ZoneT>().ok())
+            .filter(|&max| max > 0)
+            .min()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn read_max_open_zones(_path: &Path) -> Option<ZoneT> {
+        None
+    }
+
     fn reserved_space(&self) -> LbaT {
-        LABEL_COUNT * (LABEL_LBAS as u64 + self.spacemap_space)
+        VdevFile::RESERVED_LBAS
+    }
+
+    fn writev_at_unchecked(&self, buf: SGList, lba: LbaT) -> VdevFileLioFut {
+        let off = lba * (BYTES_PER_LBA as u64);
+        let containers = buf.into_iter().map(|iovec| {
+            Box::new(IoVecContainer(iovec)) as Box<dyn Borrow<[u8]>>
+        }).collect();
+        VdevFileLioFut(self.file.writev_at(containers, off).unwrap())
     }
 
     fn write_at_unchecked(&self, buf: Box<dyn Borrow<[u8]>>, lba: LbaT)
@@ -398,6 +1057,58 @@ impl VdevFile {
         let off = lba * (BYTES_PER_LBA as u64);
         VdevFileFut(self.file.write_at(buf, off).unwrap())
     }
+
+    /// Issue an open or finish command for the zone starting at `lba`.
+    fn zone_op(&self, op: ZoneOp, lba: LbaT) -> Box<VdevFut> {
+        match self.zone_model {
+            ZoneModel::Native{..} if !self.is_conventional(lba) => {
+                self.zone_mgmt(op, lba)
+            },
+            ZoneModel::Emulated{..} => {
+                self.emulate_zone_op(op, lba);
+                Box::new(future::ok::<(), Error>(()))
+            },
+            // ordinary files and conventional zones don't have Zone operations
+            _ => Box::new(future::ok::<(), Error>(()))
+        }
+    }
+
+    /// Issue a native zone management command for the zone starting at `lba`.
+    #[cfg(target_os = "linux")]
+    fn zone_mgmt(&self, op: ZoneOp, lba: LbaT) -> Box<VdevFut> {
+        const SECTORS_PER_LBA: u64 = BYTES_PER_LBA as u64 / 512;
+        let zone = self.layout.lba2zone(lba).unwrap();
+        let limits = self.zone_limits(zone);
+        let range = zbd::blk_zone_range {
+            sector: lba * SECTORS_PER_LBA,
+            nr_sectors: (limits.1 - lba) * SECTORS_PER_LBA
+        };
+        let fd = self.file.as_raw_fd();
+        // There isn't (yet) a way to asynchronously manage zones, so use
+        // synchronous ioctls.
+        let r = unsafe {
+            match op {
+                ZoneOp::Finish => zbd::blkfinishzone(fd, &range),
+                ZoneOp::Open => zbd::blkopenzone(fd, &range),
+                ZoneOp::Reset => zbd::blkresetzone(fd, &range),
+            }
+        }.map(drop)
+        .map_err(Error::from);
+        Box::new(r.into_future())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn zone_mgmt(&self, _op: ZoneOp, _lba: LbaT) -> Box<VdevFut> {
+        Box::new(future::err::<(), Error>(Error::ENOTSUP))
+    }
+}
+
+/// Zone management commands
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ZoneOp {
+    Finish,
+    Open,
+    Reset
 }
 
 struct VdevFileFut(AioFut);
@@ -449,12 +1160,15 @@ mock!{
         fn create<P>(path: P, lbas_per_zone: Option<NonZeroU64>)
             -> io::Result<Self>
             where P: AsRef<Path> + 'static;
+        fn is_conventional(&self, lba: LbaT) -> bool;
+        fn lock(&self) -> Result<(), Error>;
         fn open<P>(path: P) -> Box<dyn Future<Item=(Self, LabelReader),
                                               Error=Error>>
             where P: AsRef<Path> + 'static;
     }
     trait Vdev {
         fn lba2zone(&self, lba: LbaT) -> Option<ZoneT>;
+        fn max_open_zones(&self) -> Option<ZoneT>;
         fn optimum_queue_depth(&self) -> u32;
         fn size(&self) -> LbaT;
         fn sync_all(&self) -> Box<dyn futures::Future<Item = (),
@@ -468,13 +1182,9 @@ mock!{
         fn finish_zone(&self, lba: LbaT) -> Box<VdevFut>;
         fn open_zone(&self, lba: LbaT) -> Box<VdevFut>;
         fn read_at(&self, buf: IoVecMut, lba: LbaT) -> Box<VdevFut>;
-        fn read_spacemap(&self, buf: IoVecMut, idx: u32) -> Box<VdevFut>;
         fn readv_at(&self, bufs: SGListMut, lba: LbaT) -> Box<VdevFut>;
-        fn spacemap_space(&self) -> LbaT;
         fn write_at(&self, buf: IoVec, lba: LbaT) -> Box<VdevFut>;
         fn write_label(&self, label_writer: LabelWriter) -> Box<VdevFut>;
-        fn write_spacemap(&self, buf: SGList, idx: u32, block: LbaT)
-            -> Box<VdevFut>;
         fn writev_at(&self, bufs: SGList, lba: LbaT) -> Box<VdevFut>;
     }
 }
@@ -489,13 +1199,153 @@ mod label {
     #[test]
     fn debug() {
         let label = Label{ uuid: Uuid::new_v4(),
-            lbas_per_zone: 0,
+            layout: ZoneLayout::uniform(0, 0),
             lbas: 0,
-            spacemap_space: 0
+            zone_model: ZoneModel::Simulated
         };
         format!("{:?}", label);
     }
 }
 
+mod layout_from_report {
+    use pretty_assertions::assert_eq;
+    use super::super::*;
+
+    fn zi(start: LbaT, len: LbaT, zone_type: ZoneType) -> ZoneInfo {
+        ZoneInfo{start, len, zone_type}
+    }
+
+    #[test]
+    fn conventional_first() {
+        let report = vec![
+            zi(0, 65536, ZoneType::Conventional),
+            zi(65536, 65536, ZoneType::Conventional),
+            zi(131_072, 65536, ZoneType::SequentialRequired),
+            zi(196_608, 65536, ZoneType::SequentialRequired),
+        ];
+        assert_eq!(VdevFile::layout_from_report(&report),
+                   Ok((ZoneLayout::uniform(65536, 4), 2, 262_144)));
+    }
+
+    #[test]
+    fn host_aware() {
+        let report = vec![
+            zi(0, 65536, ZoneType::Conventional),
+            zi(65536, 65536, ZoneType::SequentialPreferred),
+        ];
+        assert_eq!(VdevFile::layout_from_report(&report),
+                   Ok((ZoneLayout::uniform(65536, 2), 1, 131_072)));
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(VdevFile::layout_from_report(&[]), Err(Error::EINVAL));
+    }
+
+    /// The labels must be stored in a conventional zone
+    #[test]
+    fn sequential_first() {
+        let report = vec![
+            zi(0, 65536, ZoneType::SequentialRequired),
+            zi(65536, 65536, ZoneType::SequentialRequired),
+        ];
+        assert_eq!(VdevFile::layout_from_report(&report), Err(Error::EINVAL));
+    }
+
+    /// Both copies of the labels must fit in the first zone
+    #[test]
+    fn first_zone_too_small() {
+        let report = vec![
+            zi(0, 8, ZoneType::Conventional),
+            zi(8, 8, ZoneType::SequentialRequired),
+        ];
+        assert_eq!(VdevFile::layout_from_report(&report), Err(Error::EINVAL));
+    }
+
+    /// Zones must be contiguous
+    #[test]
+    fn gap() {
+        let report = vec![
+            zi(0, 65536, ZoneType::Conventional),
+            zi(65536, 65536, ZoneType::SequentialRequired),
+            zi(196_608, 65536, ZoneType::SequentialRequired),
+        ];
+        assert_eq!(VdevFile::layout_from_report(&report), Err(Error::EINVAL));
+    }
+
+    /// The backup labels must fit in the last conventional zone
+    #[test]
+    fn last_conventional_zone_too_small() {
+        let report = vec![
+            zi(0, 65536, ZoneType::Conventional),
+            zi(65536, 4, ZoneType::Conventional),
+            zi(65540, 65536, ZoneType::SequentialRequired),
+        ];
+        assert_eq!(VdevFile::layout_from_report(&report), Err(Error::EINVAL));
+    }
+
+    #[test]
+    fn nonuniform() {
+        let report = vec![
+            zi(0, 65536, ZoneType::Conventional),
+            zi(65536, 32768, ZoneType::SequentialRequired),
+            zi(98304, 32768, ZoneType::SequentialRequired),
+            zi(131_072, 65536, ZoneType::SequentialRequired),
+        ];
+        let layout = ZoneLayout{
+            runs: vec![(65536, 1), (32768, 2), (65536, 1)]
+        };
+        assert_eq!(VdevFile::layout_from_report(&report),
+                   Ok((layout, 1, 196_608)));
+    }
+
+    /// The last zone may be smaller than the others
+    #[test]
+    fn runt() {
+        let report = vec![
+            zi(0, 65536, ZoneType::Conventional),
+            zi(65536, 65536, ZoneType::SequentialRequired),
+            zi(131_072, 1000, ZoneType::SequentialRequired),
+        ];
+        let layout = ZoneLayout{runs: vec![(65536, 2), (1000, 1)]};
+        assert_eq!(VdevFile::layout_from_report(&report),
+                   Ok((layout, 1, 132_072)));
+    }
+}
+
+mod zone_layout {
+    use pretty_assertions::assert_eq;
+    use super::super::*;
+
+    #[test]
+    fn from_lens() {
+        let layout = ZoneLayout::from_lens(vec![8, 8, 4, 8]);
+        assert_eq!(layout.runs, vec![(8, 2), (4, 1), (8, 1)]);
+        assert_eq!(layout.zones(), 4);
+    }
+
+    #[test]
+    fn lba2zone() {
+        let layout = ZoneLayout{runs: vec![(8, 2), (4, 1), (8, 1)]};
+        assert_eq!(layout.lba2zone(0), Some(0));
+        assert_eq!(layout.lba2zone(15), Some(1));
+        assert_eq!(layout.lba2zone(16), Some(2));
+        assert_eq!(layout.lba2zone(19), Some(2));
+        assert_eq!(layout.lba2zone(20), Some(3));
+        assert_eq!(layout.lba2zone(27), Some(3));
+        assert_eq!(layout.lba2zone(28), None);
+    }
+
+    #[test]
+    fn zone_limits() {
+        let layout = ZoneLayout{runs: vec![(8, 2), (4, 1), (8, 1)]};
+        assert_eq!(layout.zone_limits(0), (0, 8));
+        assert_eq!(layout.zone_limits(1), (8, 16));
+        assert_eq!(layout.zone_limits(2), (16, 20));
+        assert_eq!(layout.zone_limits(3), (20, 28));
+        assert_eq!(layout.zone_start(4), 28);
+    }
+}
+
 }
 // LCOV_EXCL_STOP
@@ -4,16 +4,20 @@ use galvanic_test::test_suite;
 test_suite! {
     name persistence;
 
+    use bfffs::common::{TxgT, WriteClass};
+    use bfffs::common::vdev::PriorityClass;
     use bfffs::common::vdev_block::*;
     use bfffs::common::raid;
     use bfffs::common::cluster::*;
+    use bfffs::common::label::*;
     use bfffs::common::vdev_file::*;
+    use divbuf::DivBufShared;
     use futures::{Future, future};
     use galvanic_test::*;
     use pretty_assertions::assert_eq;
     use std::{
         fs,
-        io::{Read, Seek, SeekFrom, Write},
+        io::Write,
         num::NonZeroU64
     };
     use tempdir::TempDir;
@@ -21,17 +25,22 @@ test_suite! {
 
     // To regenerate this literal, dump the binary label using this command:
     // hexdump -e '8/1 "0x%02x, " " // "' -e '8/1 "%_p" "\n"' /tmp/label.bin
-    const GOLDEN_LABEL: [u8; 132] = [
+    const GOLDEN_LABEL: [u8; 194] = [
         // First the VdevFile label
         0x42, 0x46, 0x46, 0x46, 0x53, 0x20, 0x56, 0x64, // BFFFS Vd
         0x65, 0x76, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // ev......
-        0x43, 0x3a, 0xac, 0x65, 0x9a, 0x24, 0xb5, 0x55,
-        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x64,
+        0x9f, 0x2e, 0xce, 0x67, 0x46, 0x43, 0x71, 0x12,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x82,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
         0x30, 0x55, 0xe2, 0x7d, 0x68, 0xeb, 0x4c, 0x96,
         0xbd, 0x50, 0x88, 0xe4, 0x3f, 0x92, 0xe8, 0x48,
-        0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
-        0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00,
         0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         // Then the raid label
         0x00, 0x00, 0x00, 0x00, 0x86, 0x82, 0x03, 0x1d,
         0x3a, 0x06, 0x4b, 0x4a, 0xb0, 0xb5, 0x5e, 0x85,
@@ -41,17 +50,12 @@ test_suite! {
         0x00, 0x00, 0x00, 0x00, 0x30, 0x55, 0xe2, 0x7d,
         0x68, 0xeb, 0x4c, 0x96, 0xbd, 0x50, 0x88, 0xe4,
         0x3f, 0x92, 0xe8, 0x48,
-        // Cluster does not have a label of its own
-    ];
-    const GOLDEN_SPACEMAP: [u8; 48] = [
-        225, 77, 241, 146, 92, 56, 181, 129,            // Checksum
-        0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // ........
-        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // ........
-        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // ........
-        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // ........
-        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // ........
+        // Then the cluster label: one root, for txg 0, with no spacemap yet
+        // and no gap, and no checkpoint
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
     ];
-
     fixture!( objects() -> (Runtime, Cluster, TempDir, String) {
         setup(&mut self) {
             let len = 1 << 29;  // 512 MB
@@ -62,7 +66,7 @@ test_suite! {
             let rt = Runtime::new().unwrap();
             let lpz = NonZeroU64::new(65536);
             let paths = vec![fname.clone()];
-            let cluster = Cluster::create(None, 1, lpz, 0, paths);
+            let cluster = Cluster::create(None, 1, lpz, 0, paths).unwrap();
             (rt, cluster, tempdir, fname)
         }
     });
@@ -74,43 +78,175 @@ test_suite! {
                 .write(true)
                 .open(objects.val.3.clone()).unwrap();
             f.write_all(&GOLDEN_LABEL).unwrap();
-            f.seek(SeekFrom::Start(32768)).unwrap();
-            f.write_all(&GOLDEN_SPACEMAP).unwrap();
         }
         Runtime::new().unwrap().block_on(future::lazy(|| {
             VdevFile::open(objects.val.3.clone())
             .map(|(leaf, reader)| {
                 (VdevBlock::new(leaf), reader)
             }).and_then(move |combined| {
-                let (vdev_raid, _reader) = raid::open(None, vec![combined]);
-                 Cluster::open(vdev_raid)
-            }).map(|cluster| {
+                let (vdev_raid, reader) = raid::open(None, vec![combined]);
+                Cluster::open(vdev_raid, reader, false)
+            }).map(|(cluster, _reader)| {
                 assert_eq!(cluster.allocated(), 0);
             })
         })).unwrap();
     }
 
+    // Test open-after-write.  The spacemap log should be replayed.
     test flush(objects()) {
         let (mut rt, old_cluster, _tempdir, path) = objects.val;
+        let dbs = DivBufShared::from(vec![42u8; 8192]);
+        let txg = TxgT::from(1);
         rt.block_on(future::lazy(|| {
-            old_cluster.flush(0)
+            let (_, wfut) = old_cluster.write(dbs.try_const().unwrap(),
+                WriteClass::Data, txg, PriorityClass::Normal).unwrap();
+            wfut.and_then(|_| old_cluster.flush(txg))
+            .and_then(|_| old_cluster.sync_all())
+            .and_then(|_| {
+                let label_writer = LabelWriter::new(0, txg);
+                old_cluster.write_label(label_writer)
+            })
+        })).unwrap();
+        let old_allocated = old_cluster.allocated();
+        let old_fsm = old_cluster.dump_fsm();
+        drop(old_cluster);
+
+        let cluster = rt.block_on(future::lazy(|| {
+            VdevFile::open(path)
+            .map(|(leaf, reader)| {
+                (VdevBlock::new(leaf), reader)
+            }).and_then(move |combined| {
+                let (vdev_raid, reader) = raid::open(None, vec![combined]);
+                Cluster::open(vdev_raid, reader, false)
+            }).map(|(cluster, _reader)| cluster)
+        })).unwrap();
+        // Two data LBAs, plus the spacemap record and its stripe padding
+        assert!(old_allocated >= 3);
+        assert_eq!(cluster.allocated(), old_allocated);
+        assert_eq!(cluster.dump_fsm(), old_fsm);
+    }
+
+    // Open a Cluster as of an earlier transaction group
+    test rewind(objects()) {
+        let (mut rt, old_cluster, _tempdir, path) = objects.val;
+        let dbs = DivBufShared::from(vec![42u8; 8192]);
+        let mut old_fsms = Vec::new();
+        for i in 1..3 {
+            let txg = TxgT::from(i);
+            rt.block_on(future::lazy(|| {
+                let (_, wfut) = old_cluster.write(dbs.try_const().unwrap(),
+                    WriteClass::Data, txg, PriorityClass::Normal).unwrap();
+                wfut.and_then(|_| old_cluster.flush(txg))
+                .and_then(|_| old_cluster.sync_all())
+                .and_then(|_| {
+                    let label_writer = LabelWriter::new(0, txg);
+                    old_cluster.write_label(label_writer)
+                })
+            })).unwrap();
+            old_fsms.push(old_cluster.dump_fsm());
+        }
+        drop(old_cluster);
+
+        let cluster = rt.block_on(future::lazy(|| {
+            VdevFile::open(path)
+            .map(|(leaf, reader)| {
+                (VdevBlock::new(leaf), reader)
+            }).and_then(move |combined| {
+                let (vdev_raid, mut reader) = raid::open(None, vec![combined]);
+                reader.set_rewind(Some(TxgT::from(1)));
+                Cluster::open(vdev_raid, reader, false)
+            }).map(|(cluster, _reader)| cluster)
         })).unwrap();
+        assert_eq!(cluster.dump_fsm(), old_fsms[0]);
+    }
+}
+
+test_suite! {
+    name zoned;
 
-        let mut f = fs::File::open(path).unwrap();
-        let mut v = vec![0; 4096];
-        // Skip the whole label and just read the spacemap
-        f.seek(SeekFrom::Start(32768)).unwrap();
-        f.read_exact(&mut v).unwrap();
-        // Uncomment this block to save the binary label for inspection
-        /* {
-            use std::fs::File;
-            use std::io::Write;
-            let mut df = File::create("/tmp/label.bin").unwrap();
-            df.write_all(&v[..]).unwrap();
-        } */
-        // Compare against the golden master
-        assert_eq!(&v[0..48], &GOLDEN_SPACEMAP[..]);
-        // Rest of the buffer should be zero-filled
-        assert!(v[48..].iter().all(|&x| x == 0));
+    use bfffs::common::{TxgT, WriteClass};
+    use bfffs::common::vdev::PriorityClass;
+    use bfffs::common::vdev_block::*;
+    use bfffs::common::raid;
+    use bfffs::common::cluster::*;
+    use bfffs::common::label::*;
+    use bfffs::common::vdev_file::*;
+    use divbuf::DivBufShared;
+    use futures::{Future, future};
+    use galvanic_test::*;
+    use std::{
+        fs,
+        num::NonZeroU64
+    };
+    use tempdir::TempDir;
+    use tokio::runtime::current_thread::Runtime;
+
+    /// How many zones does the `Cluster` have open?
+    fn open_zones(cluster: &Cluster) -> usize {
+        let dump = cluster.dump_fsm();
+        // The first line ends with "<n> Open"
+        let header = dump.lines().next().unwrap();
+        header.rsplit(", ").next().unwrap()
+            .split_whitespace().next().unwrap()
+            .parse().unwrap()
+    }
+
+    // A Cluster on an emulated zoned device with 31 sequential zones of 256
+    // LBAs each, at most 2 of which may be open at once
+    fixture!( objects() -> (Runtime, Cluster, TempDir) {
+        setup(&mut self) {
+            let len = 1 << 25;  // 32 MB
+            let tempdir = t!(TempDir::new("test_cluster_zoned"));
+            let fname = format!("{}/vdev", tempdir.path().display());
+            let file = t!(fs::File::create(&fname));
+            t!(file.set_len(len));
+            let mut rt = Runtime::new().unwrap();
+            // Format the upper layers' labels on a simulated device, then
+            // reopen them on top of the emulated one
+            let cluster = Cluster::create(None, 1, None, 0, vec![fname.clone()])
+                .unwrap();
+            rt.block_on(future::lazy(|| {
+                cluster.write_label(LabelWriter::new(0, TxgT::from(0)))
+            })).unwrap();
+            drop(cluster);
+            let fname2 = fname.clone();
+            let cluster = rt.block_on(future::lazy(move || {
+                VdevFile::open(fname2)
+                .map(move |(leaf, reader)| {
+                    drop(leaf);
+                    let lpz = NonZeroU64::new(256).unwrap();
+                    let leaf = VdevFile::create_emulated(fname, lpz, 1, Some(2))
+                        .unwrap();
+                    (VdevBlock::new(leaf), reader)
+                }).and_then(move |combined| {
+                    let (vdev_raid, reader) = raid::open(None, vec![combined]);
+                    Cluster::open(vdev_raid, reader, false)
+                }).map(|(cluster, _reader)| cluster)
+            })).unwrap();
+            (rt, cluster, tempdir)
+        }
+    });
+
+    // However many classes of records are written, the Cluster must never
+    // have more zones open than the device allows
+    test max_open_zones(objects()) {
+        let (mut rt, cluster, _tempdir) = objects.val;
+        let classes = [WriteClass::Data, WriteClass::Metadata,
+                       WriteClass::Relocation];
+        let dbs = DivBufShared::from(vec![42u8; 32768]);
+        let txg = TxgT::from(1);
+        // Fill about 20 zones
+        for i in 0..640 {
+            let class = classes[i % classes.len()];
+            rt.block_on(future::lazy(|| {
+                let (_, wfut) = cluster.write(dbs.try_const().unwrap(), class,
+                    txg, PriorityClass::Normal).unwrap();
+                wfut
+            })).unwrap();
+            assert!(open_zones(&cluster) <= 2,
+                    "Too many open zones after write {}:\n{}", i,
+                    cluster.dump_fsm());
+        }
+        assert!(cluster.allocated() >= 640 * 8);
     }
 }
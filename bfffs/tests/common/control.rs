@@ -0,0 +1,162 @@
+// vim: tw=80
+use galvanic_test::test_suite;
+
+// Serves a real pool over its control socket
+test_suite! {
+    name control;
+
+    use bfffs::common::{
+        Error,
+        cache::*,
+        control::*,
+        database::*,
+        ddml::*,
+        idml::*,
+        pool::*
+    };
+    use futures::{Future, future};
+    use galvanic_test::*;
+    use std::{
+        fs,
+        sync::{Arc, Mutex}
+    };
+    use tempdir::TempDir;
+    use tokio_io_pool::Runtime;
+
+    fixture!( objects()
+              -> (Runtime, Arc<Database>, Server, Client, TempDir)
+    {
+        setup(&mut self) {
+            let mut rt = Runtime::new();
+            let handle = rt.handle().clone();
+            let len = 1 << 30;  // 1GB
+            let tempdir = t!(TempDir::new("test_control"));
+            let filename = tempdir.path().join("vdev");
+            let file = t!(fs::File::create(&filename));
+            t!(file.set_len(len));
+            drop(file);
+            let db = rt.block_on(future::lazy(move || {
+                Pool::create_cluster(None, 1, None, 0, &[filename])
+                .map_err(|_| unreachable!())
+                .and_then(|cluster| {
+                    Pool::create(String::from("test_control"), vec![cluster])
+                    .map(|pool| {
+                        let cache = Arc::new(
+                            Mutex::new(
+                                Cache::with_capacity(1_000_000)
+                            )
+                        );
+                        let ddml = Arc::new(DDML::new(pool, cache.clone()));
+                        let idml = IDML::create(ddml, cache);
+                        Arc::new(Database::create(Arc::new(idml), handle))
+                    })
+                })
+            })).unwrap();
+            let path = socket_path(tempdir.path(), "test_control");
+            let server = Server::new(&path, Arc::downgrade(&db),
+                                     rt.handle().clone()).unwrap();
+            let client = Client::connect(&path).unwrap();
+            (rt, db, server, client, tempdir)
+        }
+    });
+
+    // A spec that can't be built should fail without formatting anything
+    test add_cluster_einval(objects) {
+        let (_rt, _db, _server, mut client, tempdir) = objects.val;
+        let paths = (0..2).map(|i| {
+            let filename = tempdir.path().join(format!("new{}", i));
+            let file = t!(fs::File::create(&filename));
+            t!(file.set_len(1 << 30));
+            filename
+        }).collect::<Vec<_>>();
+        let spec = ClusterSpec {
+            disks_per_stripe: 3,
+            redundancy: 1,
+            lbas_per_zone: None,
+            storage_class: StorageClass::default(),
+            paths
+        };
+        assert_eq!(client.add_cluster(spec), Err(Error::EINVAL));
+    }
+
+    // A device that already belongs to a pool must not be reformatted
+    test add_cluster_labelled(objects) {
+        let (mut rt, db, _server, mut client, tempdir) = objects.val;
+        rt.block_on(future::lazy(|| db.sync_transaction())).unwrap();
+        let spec = ClusterSpec {
+            disks_per_stripe: 1,
+            redundancy: 0,
+            lbas_per_zone: None,
+            storage_class: StorageClass::default(),
+            paths: vec![tempdir.path().join("vdev")]
+        };
+        assert_eq!(client.add_cluster(spec), Err(Error::EEXIST));
+    }
+
+    // A nonexistent device should be reported, not created
+    test add_cluster_enoent(objects) {
+        let (_rt, _db, _server, mut client, tempdir) = objects.val;
+        let path = tempdir.path().join("nonexistent");
+        let spec = ClusterSpec {
+            disks_per_stripe: 1,
+            redundancy: 0,
+            lbas_per_zone: None,
+            storage_class: StorageClass::default(),
+            paths: vec![path.clone()]
+        };
+        assert_eq!(client.add_cluster(spec), Err(Error::ENOENT));
+        assert!(!path.exists());
+    }
+
+    test checkpoint(objects) {
+        let (_rt, _db, _server, mut client, _tempdir) = objects.val;
+        assert_eq!(client.discard_checkpoint(), Err(Error::ENOENT));
+        client.checkpoint().unwrap();
+        client.discard_checkpoint().unwrap();
+    }
+
+    // Requests that arrive after the Database is gone should fail cleanly
+    test exported(objects) {
+        let (_rt, db, _server, mut client, _tempdir) = objects.val;
+        drop(db);
+        assert_eq!(client.limits(), Err(Error::ENXIO));
+    }
+
+    test iostats(objects) {
+        let (_rt, _db, _server, mut client, _tempdir) = objects.val;
+        let vs = client.iostats().unwrap();
+        assert_eq!(vs.children.len(), 1);
+        assert!(!vs.faulted);
+    }
+
+    // Only one Server may listen on a given socket
+    test in_use(objects) {
+        let (rt, db, _server, _client, tempdir) = objects.val;
+        let path = socket_path(tempdir.path(), "test_control");
+        let r = Server::new(&path, Arc::downgrade(&db), rt.handle().clone());
+        assert_eq!(r.err().unwrap().raw_os_error(), Some(libc::EADDRINUSE));
+    }
+
+    test removing(objects) {
+        let (_rt, _db, _server, mut client, _tempdir) = objects.val;
+        assert_eq!(client.removing(), Ok(None));
+    }
+
+    // Dropping the Server should remove its socket
+    test server_drop(objects) {
+        let (_rt, _db, server, _client, tempdir) = objects.val;
+        let path = socket_path(tempdir.path(), "test_control");
+        drop(server);
+        assert!(!path.exists());
+    }
+
+    test set_limits(objects) {
+        let (_rt, db, _server, mut client, _tempdir) = objects.val;
+        let mut limits = client.limits().unwrap();
+        assert_eq!(limits.read_bw, None);
+        limits.set("read_bw=10M").unwrap();
+        client.set_limits(limits).unwrap();
+        assert_eq!(db.limits().read_bw, Some(10 << 20));
+        assert_eq!(client.limits().unwrap().read_bw, Some(10 << 20));
+    }
+}
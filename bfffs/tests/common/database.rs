@@ -1,4 +1,5 @@
 // vim: tw=80
+use bfffs::common::TxgT;
 use bfffs::common::cache::*;
 use bfffs::common::cluster;
 use bfffs::common::database::*;
@@ -19,17 +20,19 @@ use tokio::{
     runtime::current_thread::Runtime,
 };
 
-fn open_db(rt: &mut Runtime, path: PathBuf) -> Database {
+fn open_db(rt: &mut Runtime, path: PathBuf, rewind: Option<TxgT>)
+    -> Database
+{
     rt.block_on(future::lazy(|| {
         VdevFile::open(path)
-        .and_then(|(leaf, reader)| {
+        .and_then(move |(leaf, reader)| {
                 let block = VdevBlock::new(leaf);
-                let (vr, lr) = raid::open(None, vec![(block, reader)]);
-                cluster::Cluster::open(vr)
-                .map(move |cluster| (cluster, lr))
+                let (vr, mut lr) = raid::open(None, vec![(block, reader)]);
+                lr.set_rewind(rewind);
+                cluster::Cluster::open(vr, lr, false)
         }).and_then(move |(cluster, reader)|{
             let proxy = ClusterProxy::new(cluster);
-            Pool::open(None, vec![(proxy, reader)])
+            Pool::open(None, vec![(proxy, reader)], false)
         }).map(|(pool, reader)| {
             let cache = Cache::with_capacity(1_000_000);
             let arc_cache = Arc::new(Mutex::new(cache));
@@ -56,25 +59,35 @@ test_suite! {
 
     // To regenerate this literal, dump the binary label using this command:
     // hexdump -e '8/1 "0x%02x, " " // "' -e '8/1 "%_p" "\n"' /tmp/label.bin
-    const GOLDEN_DB_LABEL: [u8; 40] = [
-        // The database's label only has one member: the forest
-        // First comes the allocation table
-        // Height as 64 bits
+    const GOLDEN_DB_LABEL: [u8; 53] = [
+        // The database's label only has one member: the forest ring
+        // First the number of roots as 64 bits
         0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // Then the newest root's transaction as 32 bits
+        0x00, 0x00, 0x00, 0x00,
+        // Then the newest root
+        // Height as 64 bits
+                                0x01, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
         // min_int_fanout as 16 bits
-        0x62, 0x00,
+                                0x62, 0x00,
         // max_int_fanout as 16 bits
-                    0x85, 0x01,
+                                            0x85, 0x01,
         // min_leaf_fanout as 16 bits
-                                0x72, 0x00,
+        0x72, 0x00,
         // max_leaf_fanout as 16 bits
-                                            0xc6, 0x01,
+                    0xc6, 0x01,
         // leaf node max size in bytes, as 64-bits
-        0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00,
+                                0x00, 0x00, 0x40, 0x00,
+        0x00, 0x00, 0x00, 0x00,
         // Root node's address as a RID
-        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 
+                                0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
         // Root node's TXG range as a pair of 32-bit numbers
-        0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00
+                                0x00, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x00, 0x00,
+        // Finally, the checkpoint's root as an Option.  There is none.
+                                0x00
     ];
 
     const POOLNAME: &str = &"TestPool";
@@ -125,7 +138,7 @@ test_suite! {
             old_db.sync_transaction()
         ).unwrap();
         drop(old_db);
-        let _db = open_db(&mut rt, path);
+        let _db = open_db(&mut rt, path, None);
     }
 
     test sync_transaction(objects()) {
@@ -136,7 +149,7 @@ test_suite! {
         let mut f = fs::File::open(path).unwrap();
         let mut v = vec![0; 8192];
         // Skip leaf, raid, cluster, pool, and idml labels
-        f.seek(SeekFrom::Start(294)).unwrap();
+        f.seek(SeekFrom::Start(398)).unwrap();
         f.read_exact(&mut v).unwrap();
         // Uncomment this block to save the binary label for inspection
         /* {
@@ -146,9 +159,9 @@ test_suite! {
             df.write_all(&v[..]).unwrap();
         } */
         // Compare against the golden master,
-        assert_eq!(&v[0..40], &GOLDEN_DB_LABEL[0..40]);
+        assert_eq!(&v[0..53], &GOLDEN_DB_LABEL[0..53]);
         // Rest of the buffer should be zero-filled
-        assert!(v[40..].iter().all(|&x| x == 0));
+        assert!(v[53..].iter().all(|&x| x == 0));
     }
 }
 
@@ -156,6 +169,7 @@ test_suite! {
     name t;
 
     use bfffs::common::{
+        Error,
         cache::*,
         pool::*,
         property::*,
@@ -210,6 +224,46 @@ test_suite! {
         }
     });
 
+    test checkpoint_exists(objects()) {
+        let (mut rt, db, _tempdir, _tree_id) = objects.val;
+        rt.block_on(future::lazy(|| db.checkpoint())).unwrap();
+        let r = rt.block_on(future::lazy(|| db.checkpoint()));
+        assert_eq!(r, Err(Error::EEXIST));
+    }
+
+    // Space freed after a checkpoint can't be reused until the checkpoint is
+    // discarded, so it's reported separately
+    test checkpointed(objects()) {
+        let (mut rt, db, _tempdir, tree_id) = objects.val;
+        let checkpointed = |rt: &mut Runtime, db: &Database| {
+            rt.block_on(future::lazy(|| {
+                db.fsread(tree_id, |ds| Ok(ds.checkpointed()).into_future())
+            })).unwrap()
+        };
+        rt.block_on(
+            db.sync_transaction()
+        ).unwrap();
+        rt.block_on(future::lazy(|| db.checkpoint())).unwrap();
+        for i in 0..4 {
+            rt.block_on(future::lazy(|| {
+                db.set_prop(tree_id, Property::RecordSize(13 + i))
+            })).unwrap();
+            rt.block_on(
+                db.sync_transaction()
+            ).unwrap();
+        }
+        assert!(checkpointed(&mut rt, &db) > 0);
+
+        rt.block_on(future::lazy(|| db.discard_checkpoint())).unwrap();
+        assert_eq!(checkpointed(&mut rt, &db), 0);
+    }
+
+    test discard_checkpoint_none(objects()) {
+        let (mut rt, db, _tempdir, _tree_id) = objects.val;
+        let r = rt.block_on(future::lazy(|| db.discard_checkpoint()));
+        assert_eq!(r, Err(Error::ENOENT));
+    }
+
     test get_prop_default(objects()) {
         let (mut rt, db, _tempdir, tree_id) = objects.val;
 
@@ -229,12 +283,63 @@ test_suite! {
         ).unwrap();
         drop(db);
         let filename = tempdir.path().join("vdev");
-        let db = open_db(&mut rt, filename);
+        let db = open_db(&mut rt, filename, None);
         rt.block_on(future::lazy(move || {
             db.fsread(tree_id, |_| Ok(()).into_future())
         })).unwrap();
     }
 
+    // Reopen the database as of an earlier transaction group.  Later changes
+    // should be gone.
+    test rewind(objects()) {
+        let (mut rt, db, tempdir, tree_id) = objects.val;
+        rt.block_on(
+            db.sync_transaction()
+        ).unwrap();
+        rt.block_on(future::lazy(|| {
+            db.set_prop(tree_id, Property::RecordSize(5))
+        })).unwrap();
+        rt.block_on(
+            db.sync_transaction()
+        ).unwrap();
+        drop(db);
+        let filename = tempdir.path().join("vdev");
+        let db = open_db(&mut rt, filename, Some(TxgT::from(0)));
+        let (val, source) = rt.block_on(future::lazy(|| {
+            db.get_prop(tree_id, PropertyName::RecordSize)
+        })).unwrap();
+        assert_eq!(val, Property::default_value(PropertyName::RecordSize));
+        assert_eq!(source, PropertySource::Default);
+    }
+
+    // Reopen the database as of its checkpoint, long after the checkpoint's
+    // transaction group has aged out of the rewind window.  Later changes
+    // should be gone.
+    test rewind_to_checkpoint(objects()) {
+        let (mut rt, db, tempdir, tree_id) = objects.val;
+        rt.block_on(
+            db.sync_transaction()
+        ).unwrap();
+        // Checkpoint in transaction group 1
+        rt.block_on(future::lazy(|| db.checkpoint())).unwrap();
+        for i in 0..8 {
+            rt.block_on(future::lazy(|| {
+                db.set_prop(tree_id, Property::RecordSize(13 + i))
+            })).unwrap();
+            rt.block_on(
+                db.sync_transaction()
+            ).unwrap();
+        }
+        drop(db);
+        let filename = tempdir.path().join("vdev");
+        let db = open_db(&mut rt, filename, Some(TxgT::from(1)));
+        let (val, source) = rt.block_on(future::lazy(|| {
+            db.get_prop(tree_id, PropertyName::RecordSize)
+        })).unwrap();
+        assert_eq!(val, Property::default_value(PropertyName::RecordSize));
+        assert_eq!(source, PropertySource::Default);
+    }
+
     test new_fs_with_props(objects()) {
         let (mut rt, db, _tempdir, _first_tree_id) = objects.val;
         let props = vec![Property::RecordSize(5)];
@@ -297,3 +402,162 @@ test_suite! {
         rt.shutdown_on_idle();
     }
 }
+
+test_suite! {
+    name remove_cluster;
+
+    use bfffs::common::{
+        Error,
+        cache::*,
+        device_manager::DevManager,
+        pool::*,
+        property::*,
+        ddml::*,
+        idml::*,
+    };
+    use futures::Stream;
+    use galvanic_test::*;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use super::*;
+    use tempdir::TempDir;
+
+    const POOLNAME: &str = &"TestPool";
+
+    /// Import the pool from `paths` using a `DevManager`
+    fn import(rt: &mut Runtime, paths: &[String]) -> Database {
+        let dm = DevManager::default();
+        for path in paths {
+            dm.taste(path);
+        }
+        rt.block_on(future::lazy(move || {
+            let te = TaskExecutor::current();
+            dm.import_by_name(POOLNAME, te).unwrap()
+        })).unwrap()
+    }
+
+    // A pool of two single-disk clusters
+    fixture!( objects()
+              -> (Runtime, Database, Arc<IDML>, Vec<String>, TempDir, TreeID)
+    {
+        setup(&mut self) {
+            let len = 1 << 26;  // 64 MB
+            let tempdir = t!(TempDir::new("test_database_remove_cluster"));
+            let paths = (0..2).map(|i| {
+                let fname = format!("{}/vdev.{}", tempdir.path().display(), i);
+                let file = t!(fs::File::create(&fname));
+                t!(file.set_len(len));
+                fname
+            }).collect::<Vec<_>>();
+            let mut rt = Runtime::new().unwrap();
+            let pool = rt.block_on(future::lazy(|| {
+                let clusters = paths.iter().map(|path| {
+                    Pool::create_cluster(None, 1, None, 0, &[path])
+                }).collect::<Vec<_>>();
+                future::join_all(clusters)
+                    .map_err(|_| unreachable!())
+                    .and_then(|clusters|
+                        Pool::create(POOLNAME.to_string(), clusters)
+                    )
+            })).unwrap();
+            let cache = Arc::new(Mutex::new(Cache::with_capacity(1000)));
+            let ddml = Arc::new(DDML::new(pool, cache.clone()));
+            let idml = Arc::new(IDML::create(ddml, cache));
+            let idml2 = idml.clone();
+            let db = rt.block_on(future::lazy(|| {
+                let te = TaskExecutor::current();
+                let db = Database::create(idml2, te);
+                future::ok::<Database, ()>(db)
+            })).unwrap();
+            let tree_id = rt.block_on(future::lazy(|| {
+                db.new_fs(Vec::new())
+                .and_then(|tree_id| {
+                    db.set_prop(tree_id, Property::RecordSize(13))
+                    .map(move |_| tree_id)
+                })
+            })).unwrap();
+            rt.block_on(db.sync_transaction()).unwrap();
+            (rt, db, idml, paths, tempdir, tree_id)
+        }
+    });
+
+    // Remove a cluster, then reimport the pool from the remaining one.  No
+    // data should be lost.
+    test remove(objects()) {
+        let (mut rt, mut db, _idml, paths, _tempdir, tree_id) = objects.val;
+        let vs = rt.block_on(future::lazy(|| db.iostats())).unwrap();
+        let uuid = vs.children[1].uuid;
+        let progress = rt.block_on(future::lazy(|| {
+            db.remove_cluster(uuid).collect()
+        })).unwrap();
+        if let Some(p) = progress.last() {
+            assert_eq!(p.zones_done, p.zones_total);
+        }
+        assert_eq!(db.removing(), None);
+        let vs = rt.block_on(future::lazy(|| db.iostats())).unwrap();
+        assert_eq!(vs.children.len(), 1);
+        assert_ne!(vs.children[0].uuid, uuid);
+        rt.block_on(db.export()).unwrap();
+
+        let db = import(&mut rt, &paths[..1]);
+        let vs = rt.block_on(future::lazy(|| db.iostats())).unwrap();
+        assert_eq!(vs.children.len(), 1);
+        let (val, source) = rt.block_on(future::lazy(|| {
+            db.get_prop(tree_id, PropertyName::RecordSize)
+        })).unwrap();
+        assert_eq!(val, Property::RecordSize(13));
+        assert_eq!(source, PropertySource::Local);
+    }
+
+    // Checkpointed data can't be evacuated, so removal must wait until the
+    // checkpoint is discarded
+    test remove_checkpoint(objects()) {
+        let (mut rt, db, _idml, _paths, _tempdir, _tree_id) = objects.val;
+        let vs = rt.block_on(future::lazy(|| db.iostats())).unwrap();
+        let uuid = vs.children[1].uuid;
+        rt.block_on(future::lazy(|| db.checkpoint())).unwrap();
+        let r = rt.block_on(future::lazy(|| {
+            db.remove_cluster(uuid).collect()
+        }));
+        assert_eq!(r.err(), Some(Error::EBUSY));
+        assert_eq!(db.removing(), None);
+
+        rt.block_on(future::lazy(|| db.discard_checkpoint())).unwrap();
+        rt.block_on(future::lazy(|| {
+            db.remove_cluster(uuid).collect()
+        })).unwrap();
+        let vs = rt.block_on(future::lazy(|| db.iostats())).unwrap();
+        assert_eq!(vs.children.len(), 1);
+    }
+
+    // A removal interrupted by a crash should be resumable after reimport
+    test resume_after_crash(objects()) {
+        let (mut rt, db, idml, paths, _tempdir, tree_id) = objects.val;
+        let vs = rt.block_on(future::lazy(|| db.iostats())).unwrap();
+        let uuid = vs.children[1].uuid;
+        // Start the removal and record it in the labels, but don't evacuate
+        // anything.
+        idml.start_removal(uuid).unwrap();
+        rt.block_on(future::lazy(|| {
+            db.set_prop(tree_id, Property::RecordSize(14))
+        })).unwrap();
+        rt.block_on(db.sync_transaction()).unwrap();
+        // Crash
+        drop(db);
+        drop(idml);
+
+        let db = import(&mut rt, &paths[..]);
+        assert_eq!(db.removing().map(|(u, _)| u), Some(uuid));
+        rt.block_on(future::lazy(|| {
+            db.remove_cluster(uuid).collect()
+        })).unwrap();
+        assert_eq!(db.removing(), None);
+        let vs = rt.block_on(future::lazy(|| db.iostats())).unwrap();
+        assert_eq!(vs.children.len(), 1);
+        assert_ne!(vs.children[0].uuid, uuid);
+        let (val, _source) = rt.block_on(future::lazy(|| {
+            db.get_prop(tree_id, PropertyName::RecordSize)
+        })).unwrap();
+        assert_eq!(val, Property::RecordSize(14));
+    }
+}
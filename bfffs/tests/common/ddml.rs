@@ -53,21 +53,24 @@ test_suite! {
         let dbs = DivBufShared::from(vec![42u8; 4096]);
         let ddml2 = &ddml;
         rt.block_on(future::lazy(|| {
-            ddml.put(dbs, Compression::None, TxgT::from(0))
+            ddml.put(dbs, Compression::None, TxgT::from(0),
+                     PriorityClass::Normal)
             .and_then(move |drp| {
                 let drp2 = &drp;
-                ddml2.get::<DivBufShared, DivBuf>(drp2)
+                ddml2.get::<DivBufShared, DivBuf>(drp2, PriorityClass::Normal)
                 .map(|db: Box<DivBuf>| {
                     assert_eq!(&db[..], &vec![42u8; 4096][..]);
                 }).and_then(move |_| {
-                    ddml2.pop::<DivBufShared, DivBuf>(&drp, TxgT::from(0))
+                    ddml2.pop::<DivBufShared, DivBuf>(&drp, TxgT::from(0),
+                                                     PriorityClass::Normal)
                 }).map(|dbs: Box<DivBufShared>| {
                     assert_eq!(&dbs.try_const().unwrap()[..],
                                &vec![42u8; 4096][..]);
                 }).and_then(move |_| {
                     // Even though the record has been removed from cache, it
                     // should still be on disk
-                    ddml2.get::<DivBufShared, DivBuf>(&drp)
+                    ddml2.get::<DivBufShared, DivBuf>(&drp,
+                                                      PriorityClass::Normal)
                 })
             }).map(|db: Box<DivBuf>| {
                 assert_eq!(&db[..], &vec![42u8; 4096][..]);
@@ -91,21 +94,23 @@ test_suite! {
         file.read_to_end(&mut vdev_raid_contents).unwrap();
         let dbs = DivBufShared::from(vdev_raid_contents.clone());
         rt.block_on(future::lazy(|| {
-            ddml.put(dbs, Compression::Zstd(None), txg)
+            ddml.put(dbs, Compression::Zstd(None), txg, PriorityClass::Normal)
             .and_then(|drp| {
                 let drp2 = &drp;
-                ddml2.get::<DivBufShared, DivBuf>(drp2)
+                ddml2.get::<DivBufShared, DivBuf>(drp2, PriorityClass::Normal)
                 .map(|db: Box<DivBuf>| {
                     assert_eq!(&db[..], &vdev_raid_contents[..]);
                 }).and_then(move |_| {
-                    ddml2.pop::<DivBufShared, DivBuf>(&drp, txg)
+                    ddml2.pop::<DivBufShared, DivBuf>(&drp, txg,
+                                                     PriorityClass::Normal)
                 }).map(|dbs: Box<DivBufShared>| {
                     assert_eq!(&dbs.try_const().unwrap()[..],
                                &vdev_raid_contents[..]);
                 }).and_then(move |_| {
                     // Even though the record has been removed from cache, it
                     // should still be on disk
-                    ddml2.get::<DivBufShared, DivBuf>(&drp)
+                    ddml2.get::<DivBufShared, DivBuf>(&drp,
+                                                      PriorityClass::Normal)
                 }).map(|db: Box<DivBuf>| {
                     assert_eq!(&db[..], &vdev_raid_contents[..]);
                 })
@@ -119,21 +124,24 @@ test_suite! {
         let ddml2 = &ddml;
         let dbs = DivBufShared::from(vec![42u8; 1024]);
         rt.block_on(future::lazy(|| {
-            ddml.put(dbs, Compression::None, TxgT::from(0))
+            ddml.put(dbs, Compression::None, TxgT::from(0),
+                     PriorityClass::Normal)
             .and_then(move |drp| {
                 let drp2 = &drp;
-                ddml2.get::<DivBufShared, DivBuf>(drp2)
+                ddml2.get::<DivBufShared, DivBuf>(drp2, PriorityClass::Normal)
                 .map(|db: Box<DivBuf>| {
                     assert_eq!(&db[..], &vec![42u8; 1024][..]);
                 }).and_then(move |_| {
-                    ddml2.pop::<DivBufShared, DivBuf>(&drp, TxgT::from(0))
+                    ddml2.pop::<DivBufShared, DivBuf>(&drp, TxgT::from(0),
+                                                     PriorityClass::Normal)
                 }).map(|dbs: Box<DivBufShared>| {
                     assert_eq!(&dbs.try_const().unwrap()[..],
                                &vec![42u8; 1024][..]);
                 }).and_then(move |_| {
                     // Even though the record has been removed from cache, it
                     // should still be on disk
-                    ddml2.get::<DivBufShared, DivBuf>(&drp)
+                    ddml2.get::<DivBufShared, DivBuf>(&drp,
+                                                      PriorityClass::Normal)
                 })
             }).map(|db: Box<DivBuf>| {
                 assert_eq!(&db[..], &vec![42u8; 1024][..]);
@@ -6,18 +6,24 @@ test_suite! {
     name device_manager;
 
     use bfffs::{
+        common::{Error, TxgT},
         common::database::*,
         common::device_manager::*,
         common::cache::*,
         common::ddml::*,
         common::idml::*,
+        common::label::*,
         common::pool::*,
+        common::property::*,
+        common::vdev_file::*,
+        common::vdev_leaf::*,
     };
     use futures::{ Future, future, };
     use galvanic_test::*;
     use pretty_assertions::assert_eq;
     use std::{
         fs,
+        os::unix::fs::FileExt,
         sync::{Arc, Mutex}
     };
     use tempdir::TempDir;
@@ -102,6 +108,322 @@ test_suite! {
         })).unwrap();
     }
 
+    // A pool that's already imported read-write can't be imported again, even
+    // from the same host, and even if forced
+    test import_twice(mocks) {
+        let (mut rt, dm, paths, _tempdir) = mocks.val;
+        for path in paths.iter() {
+            dm.taste(path);
+        }
+        let _db = rt.block_on(future::lazy(move || {
+            let te = TaskExecutor::current();
+            dm.import_by_name("test_device_manager", te).unwrap()
+        })).unwrap();
+        let dm2 = DevManager::default();
+        for path in paths.iter() {
+            dm2.taste(path);
+        }
+        dm2.set_force(true);
+        let r = rt.block_on(future::lazy(move || {
+            let te = TaskExecutor::current();
+            dm2.import_by_name("test_device_manager", te).unwrap()
+        }));
+        assert_eq!(r.err(), Some(Error::EBUSY));
+    }
+
+    // Import a pool whose primary labels have been overwritten
+    test import_from_backup_labels(mocks) {
+        let (mut rt, dm, paths, _tempdir) = mocks.val;
+        for path in paths.iter() {
+            let f = fs::OpenOptions::new().write(true).open(path).unwrap();
+            // Clobber the primary labels, which occupy the first 8 LBAs
+            let zeros = vec![0u8; 8 * 4096];
+            f.write_all_at(&zeros, 0).unwrap();
+        }
+        for path in paths.iter() {
+            dm.taste(path);
+        }
+        let _db = rt.block_on(future::lazy(move || {
+            let te = TaskExecutor::current();
+            dm.import_by_name("test_device_manager", te).unwrap()
+        })).unwrap();
+    }
+
+    // A RAID pool can be imported with a disk missing, and its data read back.
+    // Once it's been written that way, the disk stays faulted even after it
+    // returns, because its contents are stale.
+    test import_degraded(mocks((3, 3, 1))) {
+        let (mut rt, dm, paths, _tempdir) = mocks.val;
+        for path in paths.iter() {
+            dm.taste(path);
+        }
+        let mut db = rt.block_on(future::lazy(move || {
+            let te = TaskExecutor::current();
+            dm.import_by_name("test_device_manager", te).unwrap()
+        })).unwrap();
+        let tree_id = rt.block_on(future::lazy(|| db.new_fs(Vec::new())))
+            .unwrap();
+        rt.block_on(future::lazy(|| {
+            db.set_prop(tree_id, Property::RecordSize(13))
+        })).unwrap();
+        rt.block_on(future::lazy(|| db.sync_transaction())).unwrap();
+        rt.block_on(future::lazy(|| db.export())).unwrap();
+
+        // Import without the first disk
+        let dm = DevManager::default();
+        for path in paths[1..].iter() {
+            dm.taste(path);
+        }
+        let mut db = rt.block_on(future::lazy(move || {
+            let te = TaskExecutor::current();
+            dm.import_by_name("test_device_manager", te).unwrap()
+        })).unwrap();
+        let vs = rt.block_on(future::lazy(|| db.iostats())).unwrap();
+        assert!(vs.degraded());
+        let (val, _) = rt.block_on(future::lazy(|| {
+            db.get_prop(tree_id, PropertyName::RecordSize)
+        })).unwrap();
+        assert_eq!(val, Property::RecordSize(13));
+        rt.block_on(future::lazy(|| {
+            db.set_prop(tree_id, Property::RecordSize(14))
+        })).unwrap();
+        rt.block_on(future::lazy(|| db.sync_transaction())).unwrap();
+        rt.block_on(future::lazy(|| db.export())).unwrap();
+
+        // Now bring it back.  Its stale labels must not win, no matter which
+        // disk is tasted first.
+        let dm = DevManager::default();
+        for path in paths.iter() {
+            dm.taste(path);
+        }
+        let db = rt.block_on(future::lazy(move || {
+            let te = TaskExecutor::current();
+            dm.import_by_name("test_device_manager", te).unwrap()
+        })).unwrap();
+        let vs = rt.block_on(future::lazy(|| db.iostats())).unwrap();
+        assert!(vs.degraded());
+        let (val, _) = rt.block_on(future::lazy(|| {
+            db.get_prop(tree_id, PropertyName::RecordSize)
+        })).unwrap();
+        assert_eq!(val, Property::RecordSize(14));
+        assert!(rt.block_on(future::lazy(|| db.check())).unwrap());
+    }
+
+    // Importing should fail if more disks are missing than the RAID level can
+    // cover
+    test import_degraded_too_many(mocks((3, 3, 1))) {
+        let (mut rt, dm, paths, _tempdir) = mocks.val;
+        dm.taste(&paths[0]);
+        let r = rt.block_on(future::lazy(move || {
+            let te = TaskExecutor::current();
+            dm.import_by_name("test_device_manager", te).unwrap()
+        }));
+        assert_eq!(r.err(), Some(Error::ENXIO));
+    }
+
+    // A read-only import must reject modifications and leave the labels alone
+    test import_readonly(mocks) {
+        let (mut rt, dm, paths, _tempdir) = mocks.val;
+        let read_labels = || {
+            paths.iter().map(|path| {
+                let f = fs::File::open(path).unwrap();
+                let mut buf = vec![0u8; 8 * 4096];
+                f.read_exact_at(&mut buf, 0).unwrap();
+                buf
+            }).collect::<Vec<_>>()
+        };
+        let before = read_labels();
+        for path in paths.iter() {
+            dm.taste(path);
+        }
+        dm.set_readonly(true);
+        let mut db = rt.block_on(future::lazy(move || {
+            let te = TaskExecutor::current();
+            dm.import_by_name("test_device_manager", te).unwrap()
+        })).unwrap();
+        assert!(db.readonly());
+        let r = rt.block_on(future::lazy(|| db.new_fs(Vec::new())));
+        assert_eq!(r.unwrap_err(), Error::EROFS);
+        rt.block_on(future::lazy(|| db.export())).unwrap();
+        assert_eq!(before, read_labels());
+    }
+
+    // Rewinding a healthy pool should import its newest transaction group
+    test import_rewind(mocks) {
+        let (mut rt, dm, paths, _tempdir) = mocks.val;
+        for path in paths.iter() {
+            dm.taste(path);
+        }
+        dm.set_rewind(true);
+        let db = rt.block_on(future::lazy(move || {
+            let te = TaskExecutor::current();
+            dm.import_by_name("test_device_manager", te).unwrap()
+        })).unwrap();
+        assert!(!db.readonly());
+        assert!(rt.block_on(future::lazy(|| db.check())).unwrap());
+        rt.block_on(future::lazy(|| db.new_fs(Vec::new()))).unwrap();
+    }
+
+    // Rewinding should skip transaction groups that fail their consistency
+    // check, and import the newest one that passes.  Zones written since must
+    // still be usable.
+    test import_rewind_corrupt(mocks) {
+        let (mut rt, dm, paths, _tempdir) = mocks.val;
+        // Everything so far has been written near the start of each disk
+        let len = 1 << 24;
+        let read_disks = || {
+            paths.iter().map(|path| {
+                let f = fs::File::open(path).unwrap();
+                let mut buf = vec![0u8; len];
+                f.read_exact_at(&mut buf, 0).unwrap();
+                buf
+            }).collect::<Vec<_>>()
+        };
+        for path in paths.iter() {
+            dm.taste(path);
+        }
+        let mut db = rt.block_on(future::lazy(move || {
+            let te = TaskExecutor::current();
+            dm.import_by_name("test_device_manager", te).unwrap()
+        })).unwrap();
+        let tree_id = rt.block_on(future::lazy(|| db.new_fs(Vec::new())))
+            .unwrap();
+        rt.block_on(future::lazy(|| db.sync_transaction())).unwrap();
+        let before = read_disks();
+        rt.block_on(future::lazy(|| {
+            db.set_prop(tree_id, Property::RecordSize(13))
+        })).unwrap();
+        rt.block_on(future::lazy(|| db.sync_transaction())).unwrap();
+        rt.block_on(future::lazy(|| db.export())).unwrap();
+        let after = read_disks();
+
+        // Corrupt everything written by the newer transaction groups, except
+        // for the labels
+        let mut corrupted = 0;
+        for (i, path) in paths.iter().enumerate() {
+            let f = fs::OpenOptions::new().write(true).open(path).unwrap();
+            let reserved = (LABEL_COUNT * LABEL_LBAS) as usize;
+            for lba in reserved..len / 4096 {
+                let block = lba * 4096..(lba + 1) * 4096;
+                if before[i][block.clone()] != after[i][block] {
+                    let garbage = vec![0xa5u8; 4096];
+                    f.write_all_at(&garbage, (lba * 4096) as u64).unwrap();
+                    corrupted += 1;
+                }
+            }
+        }
+        assert!(corrupted > 0);
+
+        let dm = DevManager::default();
+        for path in paths.iter() {
+            dm.taste(path);
+        }
+        dm.set_rewind(true);
+        let db = rt.block_on(future::lazy(move || {
+            let te = TaskExecutor::current();
+            dm.import_by_name("test_device_manager", te).unwrap()
+        })).unwrap();
+        let (val, source) = rt.block_on(future::lazy(|| {
+            db.get_prop(tree_id, PropertyName::RecordSize)
+        })).unwrap();
+        assert_eq!(val, Property::default_value(PropertyName::RecordSize));
+        assert_eq!(source, PropertySource::Default);
+        rt.block_on(future::lazy(|| {
+            db.set_prop(tree_id, Property::RecordSize(14))
+        })).unwrap();
+        rt.block_on(future::lazy(|| db.sync_transaction())).unwrap();
+        assert!(rt.block_on(future::lazy(|| db.check())).unwrap());
+    }
+
+    // Rewinding to a checkpoint should discard everything written since, even
+    // after many more transaction groups
+    test import_rewind_to_checkpoint(mocks) {
+        let (mut rt, dm, paths, _tempdir) = mocks.val;
+        for path in paths.iter() {
+            dm.taste(path);
+        }
+        let mut db = rt.block_on(future::lazy(move || {
+            let te = TaskExecutor::current();
+            dm.import_by_name("test_device_manager", te).unwrap()
+        })).unwrap();
+        let tree_id = rt.block_on(future::lazy(|| db.new_fs(Vec::new())))
+            .unwrap();
+        rt.block_on(future::lazy(|| db.checkpoint())).unwrap();
+        for i in 0..8 {
+            rt.block_on(future::lazy(|| {
+                db.set_prop(tree_id, Property::RecordSize(13 + i))
+            })).unwrap();
+            rt.block_on(future::lazy(|| db.sync_transaction())).unwrap();
+        }
+        rt.block_on(future::lazy(|| db.export())).unwrap();
+
+        let dm = DevManager::default();
+        for path in paths.iter() {
+            dm.taste(path);
+        }
+        dm.set_rewind_to_checkpoint(true);
+        let db = rt.block_on(future::lazy(move || {
+            let te = TaskExecutor::current();
+            dm.import_by_name("test_device_manager", te).unwrap()
+        })).unwrap();
+        assert!(!db.readonly());
+        let (val, source) = rt.block_on(future::lazy(|| {
+            db.get_prop(tree_id, PropertyName::RecordSize)
+        })).unwrap();
+        assert_eq!(val, Property::default_value(PropertyName::RecordSize));
+        assert_eq!(source, PropertySource::Default);
+    }
+
+    // Rewinding to a checkpoint should fail if there isn't one
+    test import_rewind_to_checkpoint_none(mocks) {
+        let (mut rt, dm, paths, _tempdir) = mocks.val;
+        for path in paths.iter() {
+            dm.taste(path);
+        }
+        dm.set_rewind_to_checkpoint(true);
+        let r = rt.block_on(future::lazy(move || {
+            let te = TaskExecutor::current();
+            dm.import_by_name("test_device_manager", te).unwrap()
+        }));
+        assert_eq!(r.err(), Some(Error::ENOENT));
+    }
+
+    // Find a pool's disks by searching for them, even if they've been renamed
+    test scan(mocks) {
+        let (mut rt, dm, paths, tempdir) = mocks.val;
+        for (i, path) in paths.iter().enumerate() {
+            let newpath = format!("{}/renamed.{}", tempdir.path().display(), i);
+            fs::rename(path, newpath).unwrap();
+        }
+        // Files without BFFFS labels should be ignored
+        let junk = format!("{}/junk", tempdir.path().display());
+        fs::File::create(junk).unwrap().set_len(1 << 20).unwrap();
+        dm.add_search_path(tempdir.path());
+        dm.scan();
+        let _db = rt.block_on(future::lazy(move || {
+            let te = TaskExecutor::current();
+            dm.import_by_name("test_device_manager", te).unwrap()
+        })).unwrap();
+    }
+
+    // Devices with a valid VdevFile label but nothing usable above it should be
+    // skipped by scan, too
+    test scan_partial_label(mocks) {
+        let (mut rt, dm, _paths, tempdir) = mocks.val;
+        let partial = format!("{}/partial", tempdir.path().display());
+        fs::File::create(&partial).unwrap().set_len(1 << 30).unwrap();
+        let vdev = VdevFile::create(partial, None).unwrap();
+        rt.block_on(future::lazy(|| {
+            vdev.write_label(LabelWriter::new(0, TxgT::from(0)))
+        })).unwrap();
+        drop(vdev);
+        dm.add_search_path(tempdir.path());
+        dm.scan();
+        let pools = dm.importable_pools();
+        assert_eq!(pools.len(), 1);
+        assert_eq!(pools[0].0, "test_device_manager");
+    }
+
     /// DeviceManager::import_clusters on a single pool
     test import_clusters(mocks) {
         let (mut rt, dm, paths, _tempdir) = mocks.val;
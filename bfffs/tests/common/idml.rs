@@ -28,81 +28,87 @@ test_suite! {
 
     // To regenerate this literal, dump the binary label using this command:
     // hexdump -e '8/1 "0x%02x, " " // "' -e '8/1 "%_p" "\n"' /tmp/label.bin
-    const GOLDEN_IDML_LABEL: [u8; 130] = [
+    const GOLDEN_IDML_LABEL: [u8; 139] = [
         // Past the Pool::Label, we have an IDML::Label
-        // First comes the allocation table
-        // Height as 64 bits
+        // First the number of roots as 64 bits
         0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // Then the newest root's transaction as 32 bits
+        0x2a, 0x00, 0x00, 0x00,
+        // Then the newest root, beginning with the allocation table
+        // Height as 64 bits
+                                0x01, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
         // min_int_fanout as 16 bits
-        0x4e, 0x00,
+                                0x4e, 0x00,
         // max_int_fanout as 16 bits
-                    0x38, 0x01,
+                                            0x38, 0x01,
         // min_leaf_fanout as 16 bits
-                                0xe4, 0x04,
+        0xe4, 0x04,
         // max_leaf_fanout as 16 bits
-                                            0x8d, 0x13,
+                    0x8d, 0x13,
         // leaf node max size in bytes, as 64-bits
-        0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00,
+                                0x00, 0x00, 0x40, 0x00,
+        0x00, 0x00, 0x00, 0x00,
         // Root node's address as a DRP
         // cluster as 16 bits
-        0x00, 0x00,
+                                0x00, 0x00,
         // LBA as 64 bits
-                    0x0a, 0x00, 0x00, 0x00, 0x00, 0x00,
-        0x00, 0x00,
+                                            0x0a, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         // Compression enabled as 8 bits
-                    0x00,
+                                            0x00,
         // lsize as 32 bits
-                          0x0c, 0x00, 0x00, 0x00,
-        // csize as 32 bits
                                                   0x0c,
         0x00, 0x00, 0x00,
+        // csize as 32 bits
+                          0x0c, 0x00, 0x00, 0x00,
         // checksum as 64 bits
-                          0x9f, 0xfa, 0xe3, 0x4c, 0xa2,
-        0x4d, 0xea, 0x69,
+                                                  0x9f,
+        0xfa, 0xe3, 0x4c, 0xa2, 0x4d, 0xea, 0x69,
         // Root node's TXG range as a pair of 32-bit numbers
-                          0x2a, 0x00, 0x00, 0x00, 0x2b,
-        0x00, 0x00, 0x00,
+                                                  0x2a,
+        0x00, 0x00, 0x00, 0x2b, 0x00, 0x00, 0x00,
         // Next is the IDML's next RID as 64 bits
-                          0x00, 0x00, 0x00, 0x00, 0x00,
-        0x00, 0x00, 0x00,
+                                                  0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         // Followed by the RIDT, in the same format as the AllocT
         // Height as 64 bits
-                          0x01, 0x00, 0x00, 0x00, 0x00,
-        0x00, 0x00, 0x00,
+                                                  0x01,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         // min_int_fanout as 16 bits
-                          0x6d, 0x00,
+                                                  0x6d,
+        0x00,
         // max_int_fanout as 16 bits
-                                      0xb1, 0x01,
+              0xb1, 0x01,
         // min_leaf_fanout as 16 bits
-                                                  0x86,
-        0x00,
+                          0x86, 0x00,
         // max_leaf_fanout as 16 bits
-              0x17, 0x02,
+                                      0x17, 0x02,
         // leaf node max size in bytes, as 64-bits
-                          0x00, 0x00, 0x40, 0x00, 0x00,
-        0x00, 0x00, 0x00,
+                                                  0x00,
+        0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00,
         // Root node's address as a DRP
         // cluster as 16 bits
-                          0x00, 0x00,
+                                                  0x00,
+        0x00,
         // LBA as 64 bits
-                                      0x0b, 0x00, 0x00,
-        0x00, 0x00, 0x00, 0x00, 0x00,
+              0x0b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00,
         // Compression enabled as 8 bits
-                                      0x00,
+              0x00,
         // lsize as 32 bits
+                    0x0c, 0x00, 0x00, 0x00,
+        // csize as 32 bits
                                             0x0c, 0x00,
         0x00, 0x00,
-        // csize as 32 bits
-                    0x0c, 0x00, 0x00, 0x00,
         // checksum as 64 bits
-                                            0x9f, 0xfa,
-        0xe3, 0x4c, 0xa2, 0x4d, 0xea, 0x69,
+                    0x9f, 0xfa, 0xe3, 0x4c, 0xa2, 0x4d,
+        0xea, 0x69,
         // Root node's TXG range as a pair of 32-bit numbers
-                                            0x2a, 0x00,
-        0x00, 0x00, 0x2b, 0x00, 0x00, 0x00,
-        // Label's transaction as 32 bits
-                                            0x2a, 0x00,
-        0x00, 0x00
+                    0x2a, 0x00, 0x00, 0x00, 0x2b, 0x00,
+        0x00, 0x00,
+        // Finally, the checkpoint's root as an Option.  There is none.
+                    0x00
     ];
 
     const POOLNAME: &str = &"TestPool";
@@ -144,8 +150,8 @@ test_suite! {
         let old_idml2 = old_idml.clone();
         rt.block_on(
             old_idml.advance_transaction(|_| {
-                let label_writer = LabelWriter::new(0);
-                old_idml2.flush(0, txg)
+                let label_writer = LabelWriter::new(0, TxgT::from(0));
+                old_idml2.flush(txg)
                 .and_then(move |_| {
                     old_idml2.write_label(label_writer, txg)
                 })
@@ -157,11 +163,10 @@ test_suite! {
             .and_then(|(leaf, reader)| {
                     let block = VdevBlock::new(leaf);
                     let (vr, lr) = raid::open(None, vec![(block, reader)]);
-                    cluster::Cluster::open(vr)
-                    .map(move |cluster| (cluster, lr))
+                    cluster::Cluster::open(vr, lr, false)
             }).and_then(move |(cluster, reader)|{
                 let proxy = ClusterProxy::new(cluster);
-                Pool::open(None, vec![(proxy, reader)])
+                Pool::open(None, vec![(proxy, reader)], false)
             }).map(|(pool, reader)| {
                 let cache = cache::Cache::with_capacity(1_000_000);
                 let arc_cache = Arc::new(Mutex::new(cache));
@@ -177,9 +182,9 @@ test_suite! {
         let idml2 = idml.clone();
         rt.block_on(
             idml.advance_transaction(move |_| {
-                idml2.flush(0, txg)
+                idml2.flush(txg)
                 .and_then(move |_| {
-                    let label_writer = LabelWriter::new(0);
+                    let label_writer = LabelWriter::new(0, TxgT::from(0));
                     idml2.write_label(label_writer, txg)
                 })
             })
@@ -187,7 +192,7 @@ test_suite! {
         let mut f = fs::File::open(path).unwrap();
         let mut v = vec![0; 8192];
         // Skip leaf, raid, cluster, and pool labels
-        f.seek(SeekFrom::Start(164)).unwrap();
+        f.seek(SeekFrom::Start(259)).unwrap();
         f.read_exact(&mut v).unwrap();
         // Uncomment this block to save the binary label for inspection
         /* {
@@ -197,9 +202,9 @@ test_suite! {
             df.write_all(&v[..]).unwrap();
         } */
         // Compare against the golden master
-        assert_eq!(&v[0..130], &GOLDEN_IDML_LABEL[0..130]);
+        assert_eq!(&v[0..139], &GOLDEN_IDML_LABEL[0..139]);
         // Rest of the buffer should be zero-filled
-        assert!(v[130..].iter().all(|&x| x == 0));
+        assert!(v[139..].iter().all(|&x| x == 0));
     }
 }
 
@@ -274,7 +279,8 @@ test_suite! {
                 .map_err(|_| Error::EPIPE)
                 .and_then(move |txg| {
                     let dbs = DivBufShared::from(vec![0u8; 4096]);
-                    idml2.put(dbs, Compression::None, *txg)
+                    idml2.put(dbs, Compression::None, *txg,
+                              PriorityClass::Normal)
                 }).map(drop)
             }).and_then(move |_| {
                 idml3.txg()
@@ -1,5 +1,6 @@
 mod clean_zone;
 mod cluster;
+mod control;
 mod database;
 mod device_manager;
 mod ddml;
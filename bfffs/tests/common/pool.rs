@@ -4,6 +4,7 @@ use galvanic_test::test_suite;
 test_suite! {
     name persistence;
 
+    use bfffs::common::TxgT;
     use bfffs::common::vdev_file::*;
     use bfffs::common::vdev_block::*;
     use bfffs::common::raid;
@@ -23,7 +24,7 @@ test_suite! {
 
     // To regenerate this literal, dump the binary label using this command:
     // hexdump -e '8/1 "0x%02x, " " // "' -e '8/1 "%_p" "\n"' /tmp/label.bin
-    const GOLDEN_POOL_LABEL: [u8; 72] = [
+    const GOLDEN_POOL_LABEL: [u8; 115] = [
         // Past the VdevRaid::Label, we have a Pool::Label
         // First is the Pool's name as a String, beginning with a 64-bit length
         0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
@@ -38,6 +39,24 @@ test_suite! {
         0x96, 0x5e, 0x2c, 0x04, 0x3f, 0xab, 0x65, 0x0a,
         0xbe, 0x55, 0x44, 0x83, 0xac, 0x4a, 0x4f, 0x5b,
         0xab, 0x9d, 0xa5, 0x1a, 0x9d, 0x11, 0x5f, 0xfb,
+        // Then the scheduling policy as a 32-bit enum discriminant: CLook
+        0x00, 0x00, 0x00, 0x00,
+        // Then six optional I/O limits, all None
+                                0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00,
+        // Then the UUID of the VdevRaid being removed: None
+                    0x00,
+        // Then a vector of the children's storage classes, as a 64-bit count
+        // followed by 32-bit enum discriminants: Slow and Slow
+                          0x02, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01,
+        0x00, 0x00, 0x00,
+        // Then the pool's state as a 32-bit enum discriminant: Active
+                          0x00, 0x00, 0x00, 0x00,
+        // Then the 32-bit hostid and the 64-bit import time, which vary
+                                                  0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00,
     ];
 
     fixture!( objects() -> (Runtime, Pool, TempDir, Vec<String>) {
@@ -67,14 +86,56 @@ test_suite! {
         }
     });
 
+    // A Cluster added to a live Pool should still be there after reopening
+    test add_cluster(objects()) {
+        let (mut rt, old_pool, tempdir, mut paths) = objects.val;
+        let uuid = old_pool.uuid();
+        let old_size = old_pool.size();
+        let fname = format!("{}/vdev.2", tempdir.path().display());
+        let file = t!(fs::File::create(&fname));
+        t!(file.set_len(1 << 26));
+        paths.push(fname);
+        rt.block_on(future::lazy(|| {
+            let cs = NonZeroU64::new(1);
+            Pool::create_cluster(cs, 1, None, 0, &[&paths[2]][..])
+                .map_err(|_| unreachable!())
+                .and_then(|cluster| old_pool.add_cluster(cluster))
+        })).unwrap();
+        assert_eq!(old_pool.size(), old_size * 3 / 2);
+        rt.block_on(future::lazy(|| {
+            let label_writer = LabelWriter::new(0, TxgT::from(0));
+            old_pool.flush(TxgT::from(0))
+            .join(old_pool.write_label(label_writer))
+        })).unwrap();
+        drop(old_pool);
+        let (pool, _label_reader) = rt.block_on(future::lazy(|| {
+            let cfuts = paths.iter().map(|path| {
+                VdevFile::open(path.clone())
+                .and_then(|(leaf, reader)| {
+                    let block = VdevBlock::new(leaf);
+                    let (vr, lr) = raid::open(None, vec![(block, reader)]);
+                    cluster::Cluster::open(vr, lr, false)
+                })
+            }).collect::<Vec<_>>();
+            future::join_all(cfuts)
+                .and_then(move |clusters| {
+                    let combined = clusters.into_iter()
+                        .map(|(c, cr)| (ClusterProxy::new(c), cr))
+                        .collect::<Vec<_>>();
+                    Pool::open(Some(uuid), combined, false)
+                })
+        })).unwrap();
+        assert_eq!(pool.size(), old_size * 3 / 2);
+    }
+
     // Test open-after-write for Pool
     test open(objects()) {
         let (mut rt, old_pool, _tempdir, paths) = objects.val;
         let name = old_pool.name().to_string();
         let uuid = old_pool.uuid();
         rt.block_on(future::lazy(|| {
-            let label_writer = LabelWriter::new(0);
-            old_pool.flush(0)
+            let label_writer = LabelWriter::new(0, TxgT::from(0));
+            old_pool.flush(TxgT::from(0))
             .join(old_pool.write_label(label_writer))
         })).unwrap();
         drop(old_pool);
@@ -83,21 +144,20 @@ test_suite! {
                 .and_then(|(leaf, reader)| {
                     let block = VdevBlock::new(leaf);
                     let (vr, lr) = raid::open(None, vec![(block, reader)]);
-                    cluster::Cluster::open(vr)
-                    .map(move |cluster| (cluster, lr))
+                    cluster::Cluster::open(vr, lr, false)
             });
             let c1_fut = VdevFile::open(paths[1].clone())
                 .and_then(|(leaf, reader)| {
                     let block = VdevBlock::new(leaf);
                     let (vr, lr) = raid::open(None, vec![(block, reader)]);
-                    cluster::Cluster::open(vr)
-                    .map(move |cluster| (cluster, lr))
+                    cluster::Cluster::open(vr, lr, false)
             });
             c0_fut.join(c1_fut)
                 .and_then(move |((c0, c0r), (c1,c1r))| {
                     let proxy0 = ClusterProxy::new(c0);
                     let proxy1 = ClusterProxy::new(c1);
-                    Pool::open(Some(uuid), vec![(proxy0, c0r), (proxy1,c1r)])
+                    let combined = vec![(proxy0, c0r), (proxy1,c1r)];
+                    Pool::open(Some(uuid), combined, false)
                 })
         })).unwrap();
         assert_eq!(name, pool.name());
@@ -107,14 +167,14 @@ test_suite! {
     test write_label(objects()) {
         let (mut rt, old_pool, _tempdir, paths) = objects.val;
         rt.block_on(future::lazy(|| {
-            let label_writer = LabelWriter::new(0);
+            let label_writer = LabelWriter::new(0, TxgT::from(0));
             old_pool.write_label(label_writer)
         })).unwrap();
         for path in paths {
             let mut f = fs::File::open(path).unwrap();
             let mut v = vec![0; 8192];
             // Skip leaf, raid, and cluster labels
-            f.seek(SeekFrom::Start(108)).unwrap();
+            f.seek(SeekFrom::Start(170)).unwrap();
             f.read_exact(&mut v).unwrap();
             // Uncomment this block to save the binary label for inspection
             /* {
@@ -123,12 +183,13 @@ test_suite! {
                 let mut df = File::create("/tmp/label.bin").unwrap();
                 df.write_all(&v[..]).unwrap();
             } */
-            // Compare against the golden master, skipping the checksum and UUID
-            // fields
+            // Compare against the golden master, skipping the checksum, UUID,
+            // hostid, and import time fields
             assert_eq!(&v[0..16], &GOLDEN_POOL_LABEL[0..16]);
             assert_eq!(&v[32..40], &GOLDEN_POOL_LABEL[32..40]);
+            assert_eq!(&v[72..103], &GOLDEN_POOL_LABEL[72..103]);
             // Rest of the buffer should be zero-filled
-            assert!(v[72..].iter().all(|&x| x == 0));
+            assert!(v[115..].iter().all(|&x| x == 0));
         }
     }
 }
@@ -5,6 +5,7 @@ test_suite! {
     name persistence;
 
     use bfffs::{
+        common::TxgT,
         common::label::*,
         common::vdev_block::*,
         common::vdev::Vdev,
@@ -21,7 +22,7 @@ test_suite! {
     use tempdir::TempDir;
     use tokio::runtime::current_thread;
 
-    const GOLDEN_VDEV_ONEDISK_LABEL: [u8; 36] = [
+    const GOLDEN_VDEV_ONEDISK_LABEL: [u8; 52] = [
         // Past the VdevFile::Label, we have a raid::Label
         // First comes the VdevOneDisk discriminant
         0x00, 0x00, 0x00, 0x00,
@@ -33,6 +34,13 @@ test_suite! {
                                 0xe7, 0x4c, 0xba, 0x28,
         0xbb, 0xf1, 0x4c, 0x1a, 0xad, 0x90, 0xbf, 0x48,
         0xf4, 0x26, 0x1f, 0x7a,
+        // Then the faulted children, with the RAID_FAULTS feature.  A 64-bit
+        // count, and none of them.
+                                0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        // And an empty zone map, also with RAID_FAULTS.
+                                0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
     ];
 
     fixture!( mocks() -> (VdevOneDisk, TempDir, String) {
@@ -42,7 +50,7 @@ test_suite! {
             let path = format!("{}/vdev", tempdir.path().display());
             let file = t!(fs::File::create(&path));
             t!(file.set_len(len));
-            let vdev = VdevOneDisk::create(None, path.clone());
+            let vdev = VdevOneDisk::create(None, path.clone()).unwrap();
             (vdev, tempdir, path)
         }
     });
@@ -51,7 +59,7 @@ test_suite! {
         let (old_vdev, _tempdir, path) = mocks.val;
         let uuid = old_vdev.uuid();
         current_thread::Runtime::new().unwrap().block_on(future::lazy(move || {
-            let label_writer = LabelWriter::new(0);
+            let label_writer = LabelWriter::new(0, TxgT::from(0));
             old_vdev.write_label(label_writer).and_then(move |_| {
                 VdevFile::open(path)
                 .map(|(leaf, reader)| {
@@ -66,12 +74,12 @@ test_suite! {
 
     test write_label(mocks()) {
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            let label_writer = LabelWriter::new(0);
+            let label_writer = LabelWriter::new(0, TxgT::from(0));
             mocks.val.0.write_label(label_writer)
         })).unwrap();
         let mut f = fs::File::open(mocks.val.2).unwrap();
         let mut v = vec![0; 8192];
-        f.seek(SeekFrom::Start(72)).unwrap();   // Skip the VdevLeaf label
+        f.seek(SeekFrom::Start(112)).unwrap();   // Skip the VdevLeaf label
         f.read_exact(&mut v).unwrap();
         // Uncomment this block to save the binary label for inspection
         /* {
@@ -83,7 +91,8 @@ test_suite! {
         } */
         // Compare against the golden master, skipping the UUID fields
         assert_eq!(&v[0..4], &GOLDEN_VDEV_ONEDISK_LABEL[0..4]);
+        assert_eq!(&v[36..52], &GOLDEN_VDEV_ONEDISK_LABEL[36..52]);
         // Rest of the buffer should be zero-filled
-        assert!(v[36..].iter().all(|&x| x == 0));
+        assert!(v[52..].iter().all(|&x| x == 0));
     }
 }
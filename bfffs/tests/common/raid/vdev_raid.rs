@@ -1,14 +1,13 @@
 // vim: tw=80
-use bfffs::common::raid::VdevRaid;
+use bfffs::common::{Error, raid::VdevRaid};
 use galvanic_test::test_suite;
 use std::fs;
 use tempdir::TempDir;
 
 #[test]
-#[should_panic]
 fn create_redundancy_too_big() {
-    // VdevRaid::create should panic if the stripesize is greater than the
-    // number of disks
+    // VdevRaid::create should fail if the redundancy is as great as the
+    // stripesize
     let len = 1 << 30;  // 1 GB
     let num_disks = 5;
     let stripesize = 3;
@@ -20,13 +19,13 @@ fn create_redundancy_too_big() {
         t!(file.set_len(len));
         fname
     }).collect::<Vec<_>>();
-    VdevRaid::create(None, stripesize, None, redundancy, paths);
+    let r = VdevRaid::create(None, stripesize, None, redundancy, paths);
+    assert_eq!(r.err(), Some(Error::EINVAL));
 }
 
 #[test]
-#[should_panic]
 fn create_stripesize_too_big() {
-    // VdevRaid::create should panic if the stripesize is greater than the
+    // VdevRaid::create should fail if the stripesize is greater than the
     // number of disks
     let len = 1 << 30;  // 1 GB
     let num_disks = 3;
@@ -39,7 +38,8 @@ fn create_stripesize_too_big() {
         t!(file.set_len(len));
         fname
     }).collect::<Vec<_>>();
-    VdevRaid::create(None, stripesize, None, redundancy, paths);
+    let r = VdevRaid::create(None, stripesize, None, redundancy, paths);
+    assert_eq!(r.err(), Some(Error::EINVAL));
 }
 
 test_suite! {
@@ -49,7 +49,7 @@ test_suite! {
     use bfffs::{
         common::*,
         common::raid::*,
-        common::vdev::Vdev,
+        common::vdev::{PriorityClass, Vdev},
     };
     use divbuf::DivBufShared;
     use futures::{Future, future};
@@ -88,7 +88,7 @@ test_suite! {
             }).collect::<Vec<_>>();
             let cs = NonZeroU64::new(*self.chunksize);
             let vdev_raid = VdevRaid::create(cs, *self.k, None, *self.f,
-                                             paths.clone());
+                                             paths.clone()).unwrap();
             current_thread::Runtime::new().unwrap().block_on(
                 vdev_raid.open_zone(0)
             ).expect("open_zone");
@@ -121,7 +121,8 @@ test_suite! {
                 wbufs.into_iter()
                 .map(|wb| {
                     let lbas = (wb.len() / BYTES_PER_LBA) as LbaT;
-                    let fut = vr.write_at(wb, zone, write_lba);
+                    let fut = vr.write_at(wb, zone, write_lba,
+                                          PriorityClass::Normal);
                     write_lba += lbas;
                     fut
                 })
@@ -130,7 +131,8 @@ test_suite! {
                     rbufs.into_iter()
                     .map(|rb| {
                         let lbas = (rb.len() / BYTES_PER_LBA) as LbaT;
-                        let fut = vr.read_at(rb, read_lba);
+                        let fut = vr.read_at(rb, read_lba,
+                                             PriorityClass::Normal);
                         read_lba += lbas;
                         fut
                     })
@@ -162,10 +164,11 @@ test_suite! {
         let wbuf_r = wbuf_l.split_off(wbuf.len() / 2);
         let sglist = vec![wbuf_l, wbuf_r];
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            vr.writev_at_one(&sglist, zl.0)
+            vr.writev_at_one(&sglist, zl.0, PriorityClass::Normal)
                 .then(|write_result| {
                     write_result.expect("writev_at_one");
-                    vr.read_at(dbsr.try_mut().unwrap(), zl.0)
+                    vr.read_at(dbsr.try_mut().unwrap(), zl.0,
+                               PriorityClass::Normal)
                 })
         })).expect("read_at");
         assert_eq!(wbuf, dbsr.try_const().unwrap());
@@ -487,11 +490,11 @@ test_suite! {
         let wbuf1 = dbsw.try_const().unwrap();
         let rbuf = dbsr.try_mut().unwrap();
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            raid.val.0.write_at(wbuf0, zone, zl.0)
+            raid.val.0.write_at(wbuf0, zone, zl.0, PriorityClass::Normal)
                 .and_then(|_| {
                     raid.val.0.finish_zone(zone)
                 }).and_then(|_| {
-                    raid.val.0.read_at(rbuf, zl.0)
+                    raid.val.0.read_at(rbuf, zl.0, PriorityClass::Normal)
                 })
         })).expect("current_thread::Runtime::block_on");
         assert_eq!(wbuf1, dbsr.try_const().unwrap());
@@ -509,11 +512,13 @@ test_suite! {
             let mut rbuf = dbsr.try_mut().unwrap();
             let rbuf_short = rbuf.split_to(BYTES_PER_LBA);
             current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-                raid.val.0.write_at(wbuf_short, zone, zl.0)
+                raid.val.0.write_at(wbuf_short, zone, zl.0,
+                                    PriorityClass::Normal)
                     .and_then(|_| {
                         raid.val.0.finish_zone(zone)
                     }).and_then(|_| {
-                        raid.val.0.read_at(rbuf_short, zl.0)
+                        raid.val.0.read_at(rbuf_short, zl.0,
+                                           PriorityClass::Normal)
                     })
             })).expect("current_thread::Runtime::block_on");
         }
@@ -546,7 +551,7 @@ test_suite! {
         let (start, _) = raid.val.0.zone_limits(zone);
         let dbsw = DivBufShared::from(vec![0;4096]);
         let wbuf = dbsw.try_const().unwrap();
-        let _ = raid.val.0.write_at(wbuf, zone, start);
+        let _ = raid.val.0.write_at(wbuf, zone, start, PriorityClass::Normal);
     }
 
     // Opening a closed zone should allow writing
@@ -588,6 +593,7 @@ test_suite! {
     name persistence;
 
     use bfffs::{
+        common::TxgT,
         common::label::*,
         common::vdev_block::*,
         common::vdev::Vdev,
@@ -605,7 +611,7 @@ test_suite! {
     use tempdir::TempDir;
     use tokio::runtime::current_thread;
 
-    const GOLDEN_VDEV_RAID_LABEL: [u8; 124] = [
+    const GOLDEN_VDEV_RAID_LABEL: [u8; 160] = [
         // Past the VdevFile::Label, we have a raid::Label
         // First comes the VdevRaid discriminant
         0x01, 0x00, 0x00, 0x00,
@@ -636,6 +642,16 @@ test_suite! {
         0x6b, 0xfe, 0x9b, 0x7c, 0xb8, 0x79, 0x31, 0x82,
         0x2f, 0xc9, 0x4c, 0x40, 0x84, 0xd3, 0xff, 0xd5,
         0xb8, 0x3b, 0x18, 0x8e,
+        // Then the faulted children, with the RAID_FAULTS feature.  A 64-bit
+        // count, and none of them.
+                                0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        // Then the zone map, also with RAID_FAULTS.  A 64-bit count of runs,
+        // then a single run: 64-bit start, 64-bit length, and 32-bit count.
+                                0x01, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0xf0, 0x3f, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
     ];
 
     fixture!( mocks() -> (VdevRaid, TempDir, Vec<String>) {
@@ -650,7 +666,8 @@ test_suite! {
                 fname
             }).collect::<Vec<_>>();
             let cs = NonZeroU64::new(2);
-            let vdev_raid = VdevRaid::create(cs, 3, None, 1, paths.clone());
+            let vdev_raid = VdevRaid::create(cs, 3, None, 1, paths.clone())
+                .unwrap();
             (vdev_raid, tempdir, paths)
         }
     });
@@ -662,7 +679,7 @@ test_suite! {
         let (old_raid, _tempdir, paths) = mocks.val;
         let uuid = old_raid.uuid();
         current_thread::Runtime::new().unwrap().block_on(future::lazy(move || {
-            let label_writer = LabelWriter::new(0);
+            let label_writer = LabelWriter::new(0, TxgT::from(0));
             old_raid.write_label(label_writer).and_then(move |_| {
                 future::join_all(paths.into_iter().map(|path| {
                     VdevFile::open(path).map(|(leaf, reader)| {
@@ -678,13 +695,13 @@ test_suite! {
 
     test write_label(mocks()) {
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            let label_writer = LabelWriter::new(0);
+            let label_writer = LabelWriter::new(0, TxgT::from(0));
             mocks.val.0.write_label(label_writer)
         })).unwrap();
         for path in mocks.val.2 {
             let mut f = fs::File::open(path).unwrap();
             let mut v = vec![0; 8192];
-            f.seek(SeekFrom::Start(72)).unwrap();   // Skip the VdevLeaf label
+            f.seek(SeekFrom::Start(112)).unwrap();   // Skip the VdevLeaf label
             f.read_exact(&mut v).unwrap();
             // Uncomment this block to save the binary label for inspection
             /* {
@@ -698,8 +715,9 @@ test_suite! {
             // fields
             assert_eq!(&v[0..4], &GOLDEN_VDEV_RAID_LABEL[0..4]);
             assert_eq!(&v[20..44], &GOLDEN_VDEV_RAID_LABEL[20..44]);
+            assert_eq!(&v[124..160], &GOLDEN_VDEV_RAID_LABEL[124..160]);
             // Rest of the buffer should be zero-filled
-            assert!(v[124..].iter().all(|&x| x == 0));
+            assert!(v[160..].iter().all(|&x| x == 0));
         }
     }
 }
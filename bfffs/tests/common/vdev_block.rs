@@ -28,8 +28,8 @@ test_suite! {
 
     test lba2zone(vdev) {
         assert_eq!(vdev.val.0.lba2zone(0), None);
-        assert_eq!(vdev.val.0.lba2zone(9), None);
-        assert_eq!(vdev.val.0.lba2zone(10), Some(0));
+        assert_eq!(vdev.val.0.lba2zone(7), None);
+        assert_eq!(vdev.val.0.lba2zone(8), Some(0));
         assert_eq!(vdev.val.0.lba2zone((1 << 16) - 1), Some(0));
         assert_eq!(vdev.val.0.lba2zone(1 << 16), Some(1));
     }
@@ -39,7 +39,7 @@ test_suite! {
     }
 
     test zone_limits(vdev) {
-        assert_eq!(vdev.val.0.zone_limits(0), (10, 1 << 16));
+        assert_eq!(vdev.val.0.zone_limits(0), (10, 16_374));
         assert_eq!(vdev.val.0.zone_limits(1), (1 << 16, 2 << 16));
     }
 
@@ -48,7 +48,7 @@ test_suite! {
         let dbs = DivBufShared::from(vec![42u8; 4095]);
         let wbuf = dbs.try_const().unwrap();
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            vdev.val.0.write_at(wbuf, 10)
+            vdev.val.0.write_at(wbuf, 10, PriorityClass::Normal)
         })).unwrap();
     }
 
@@ -57,7 +57,7 @@ test_suite! {
         let dbs = DivBufShared::from(vec![42u8; 4097]);
         let wbuf = dbs.try_const().unwrap();
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            vdev.val.0.write_at(wbuf, 10)
+            vdev.val.0.write_at(wbuf, 10, PriorityClass::Normal)
         })).unwrap();
     }
 
@@ -66,7 +66,7 @@ test_suite! {
         let dbs = DivBufShared::from(vec![42u8; 16_385]);
         let wbuf = dbs.try_const().unwrap();
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            vdev.val.0.write_at(wbuf, 10)
+            vdev.val.0.write_at(wbuf, 10, PriorityClass::Normal)
         })).unwrap();
     }
 
@@ -78,7 +78,7 @@ test_suite! {
         let wbuf1 = wbuf.slice_from(1024);
         let wbufs = vec![wbuf0, wbuf1];
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            vdev.val.0.writev_at(wbufs, 10)
+            vdev.val.0.writev_at(wbufs, 10, PriorityClass::Normal)
         })).unwrap();
     }
 
@@ -88,7 +88,7 @@ test_suite! {
         let wbuf = dbs.try_const().unwrap();
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
             let size = vdev.val.0.size();
-            vdev.val.0.write_at(wbuf, size)
+            vdev.val.0.write_at(wbuf, size, PriorityClass::Normal)
         })).unwrap();
     }
 
@@ -98,7 +98,7 @@ test_suite! {
         let wbuf = dbs.try_const().unwrap();
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
             let size = vdev.val.0.size() - 1;
-            vdev.val.0.write_at(wbuf, size)
+            vdev.val.0.write_at(wbuf, size, PriorityClass::Normal)
         })).unwrap();
     }
 
@@ -111,7 +111,7 @@ test_suite! {
         let wbufs = vec![wbuf0.clone(), wbuf1.clone()];
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
             let size = vdev.val.0.size();
-            vdev.val.0.writev_at(wbufs, size)
+            vdev.val.0.writev_at(wbufs, size, PriorityClass::Normal)
         })).unwrap();
     }
 
@@ -124,7 +124,7 @@ test_suite! {
         let wbufs = vec![wbuf0.clone(), wbuf1.clone()];
         current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
             let size = vdev.val.0.size() - 1;
-            vdev.val.0.writev_at(wbufs, size)
+            vdev.val.0.writev_at(wbufs, size, PriorityClass::Normal)
         })).unwrap();
     }
 }
@@ -43,9 +43,12 @@ test_suite! {
 
     test lba2zone(vdev) {
         assert_eq!(vdev.val.0.lba2zone(0), None);
-        assert_eq!(vdev.val.0.lba2zone(9), None);
-        assert_eq!(vdev.val.0.lba2zone(10), Some(0));
+        assert_eq!(vdev.val.0.lba2zone(7), None);
+        assert_eq!(vdev.val.0.lba2zone(8), Some(0));
+        assert_eq!(vdev.val.0.lba2zone(16_375), Some(0));
         assert_eq!(vdev.val.0.lba2zone((1 << 16) - 1), Some(0));
+        assert_eq!(vdev.val.0.lba2zone(16_376), None);
+        assert_eq!(vdev.val.0.lba2zone(16_383), None);
         assert_eq!(vdev.val.0.lba2zone(1 << 16), Some(1));
     }
 
@@ -54,7 +57,8 @@ test_suite! {
     }
 
     test zone_limits(vdev) {
-        assert_eq!(vdev.val.0.zone_limits(0), (10, 1 << 16));
+        // The backup labels are at the end of the last zone
+        assert_eq!(vdev.val.0.zone_limits(0), (8, 16_376));
         assert_eq!(vdev.val.0.zone_limits(1), (1 << 16, 2 << 16));
     }
 
@@ -207,24 +211,36 @@ test_suite! {
     use tempdir::TempDir;
     use tokio::runtime::current_thread;
 
-    const GOLDEN: [u8; 72] = [
+    const GOLDEN: [u8; 112] = [
         // First 16 bytes are file magic
         0x42, 0x46, 0x46, 0x46, 0x53, 0x20, 0x56, 0x64, // BFFFS Vd
         0x65, 0x76, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // ev......
         // Next 8 bytes are a checksum
-        0x2e, 0x43, 0xc2, 0x5d, 0x1f, 0x55, 0x20, 0x3b,
+        0x28, 0x8f, 0x63, 0x44, 0x49, 0x88, 0x21, 0xdd,
         // Next 8 bytes are the contents length, in BE
-        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x28,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x30,
+        // Next 4 bytes are the transaction group, in BE
+        0x00, 0x00, 0x00, 0x00,
+        // Next 4 bytes are the label format version, in BE
+        0x00, 0x00, 0x00, 0x02,
+        // Next 24 bytes are the compat, ro_compat, and incompat features
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07,
         // The rest is a serialized VdevFile::Label object.
         // First comes the VdevFile's UUID.
         0x3f, 0xa1, 0xf6, 0xb9, 0x54, 0xb1, 0x4a, 0x10,
         0xbc, 0x6b, 0x5b, 0x2a, 0x15, 0xe8, 0xa0, 0x3d,
-        // Then the number of LBAS per zone
+        // Then the zone layout: the number of runs as a 64-bit number,
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // followed by the number of LBAs per zone and the number of zones in
+        // each run
         0xbe, 0xba, 0x7e, 0x1a, 0xef, 0xbe, 0xad, 0xde,
+        0x01, 0x00, 0x00, 0x00,
         // Then the number of LBAs as a 64-bit number
         0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-        // Finally the number of LBAs reserved for the spacemap
-        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // Finally the zone model: Simulated
+        0x00, 0x00, 0x00, 0x00,
     ];
 
     fixture!( fixture() -> (PathBuf, TempDir) {
@@ -263,6 +279,104 @@ test_suite! {
         let _ = fixture.val.1;
     }
 
+    /// Open a device whose primary labels have been destroyed
+    test open_backup_label_only(fixture) {
+        let vdev = VdevFile::create(fixture.val.0.clone(), None).unwrap();
+        let uuid = vdev.uuid();
+        let mut rt = current_thread::Runtime::new().unwrap();
+        rt.block_on(future::lazy(|| {
+            vdev.write_label(LabelWriter::new(0, TxgT::from(0)))
+        })).unwrap();
+        drop(vdev);
+        {
+            let f = std::fs::OpenOptions::new()
+                .write(true)
+                .open(fixture.val.0.clone()).unwrap();
+            let zeros = vec![0u8; 2 * LABEL_SIZE];
+            f.write_all_at(&zeros, 0).unwrap();
+        }
+        let (vdev, _label_reader) = rt.block_on(future::lazy(|| {
+            VdevFile::open(fixture.val.0)
+        })).unwrap();
+        assert_eq!(vdev.uuid(), uuid);
+        let _ = fixture.val.1;
+    }
+
+    /// Open a device that has grown since its label was written.  It should
+    /// gain zones, and its labels should follow it to the new end.
+    test open_grown(fixture) {
+        let lpz = NonZeroU64::new(4096);
+        let vdev = VdevFile::create(fixture.val.0.clone(), lpz).unwrap();
+        assert_eq!(vdev.zones(), 4);
+        let mut rt = current_thread::Runtime::new().unwrap();
+        rt.block_on(future::lazy(|| {
+            vdev.write_label(LabelWriter::new(0, TxgT::from(0)))
+        })).unwrap();
+        drop(vdev);
+        {
+            let f = fs::OpenOptions::new()
+                .write(true)
+                .open(fixture.val.0.clone()).unwrap();
+            f.set_len(1 << 27).unwrap();
+        }
+        let (vdev, _label_reader) = rt.block_on(future::lazy(|| {
+            VdevFile::open(fixture.val.0.clone())
+        })).unwrap();
+        assert_eq!(vdev.size(), 32_768);
+        assert_eq!(vdev.zones(), 8);
+        // The old last zone absorbs the old backup labels
+        assert_eq!(vdev.zone_limits(3), (12_288, 16_384));
+        assert_eq!(vdev.zone_limits(7), (28_672, 32_760));
+        rt.block_on(future::lazy(|| {
+            vdev.write_label(LabelWriter::new(0, TxgT::from(1)))
+        })).unwrap();
+        drop(vdev);
+        // Now the backup labels should be at the new end
+        {
+            let f = fs::OpenOptions::new()
+                .write(true)
+                .open(fixture.val.0.clone()).unwrap();
+            let zeros = vec![0u8; 2 * LABEL_SIZE];
+            f.write_all_at(&zeros, 0).unwrap();
+        }
+        let (vdev, label_reader) = rt.block_on(future::lazy(|| {
+            VdevFile::open(fixture.val.0)
+        })).unwrap();
+        assert_eq!(label_reader.txg(), TxgT::from(1));
+        assert_eq!(vdev.size(), 32_768);
+        let _ = fixture.val.1;
+    }
+
+    /// If the copies of a label disagree, the newest one should win
+    test open_newest(fixture) {
+        let vdev = VdevFile::create(fixture.val.0.clone(), None).unwrap();
+        let mut rt = current_thread::Runtime::new().unwrap();
+        rt.block_on(future::lazy(|| {
+            vdev.write_label(LabelWriter::new(0, TxgT::from(5)))
+        })).unwrap();
+        let mut old_label = vec![0u8; LABEL_SIZE];
+        {
+            let f = std::fs::File::open(fixture.val.0.clone()).unwrap();
+            f.read_exact_at(&mut old_label, 0).unwrap();
+        }
+        rt.block_on(future::lazy(|| {
+            vdev.write_label(LabelWriter::new(0, TxgT::from(6)))
+        })).unwrap();
+        drop(vdev);
+        // Roll back the primary copy, as if its write had been lost
+        {
+            let f = std::fs::OpenOptions::new()
+                .write(true)
+                .open(fixture.val.0.clone()).unwrap();
+            f.write_all_at(&old_label, 0).unwrap();
+        }
+        let (_vdev, label_reader) = rt.block_on(future::lazy(|| {
+            VdevFile::open(fixture.val.0)
+        })).unwrap();
+        assert_eq!(label_reader.txg(), TxgT::from(6));
+        let _ = fixture.val.1;
+    }
+
     // Open a device with only corrupted labels
     test open_ecksum(fixture) {
         {
@@ -344,7 +458,7 @@ test_suite! {
         let vdev = VdevFile::create(fixture.val.0.clone(), lbas_per_zone)
             .unwrap();
         t!(current_thread::Runtime::new().unwrap().block_on(future::lazy(|| {
-            let label_writer = LabelWriter::new(0);
+            let label_writer = LabelWriter::new(0, TxgT::from(0));
             vdev.write_label(label_writer)
         })));
 
@@ -362,7 +476,224 @@ test_suite! {
         // Compare against the golden master, skipping the checksum and UUID
         // fields
         assert_eq!(&v[0..16], &GOLDEN[0..16]);
-        assert_eq!(&v[24..32], &GOLDEN[24..32]);
-        assert_eq!(&v[48..GOLDEN.len()], &GOLDEN[48..GOLDEN.len()]);
+        assert_eq!(&v[24..64], &GOLDEN[24..64]);
+        assert_eq!(&v[80..GOLDEN.len()], &GOLDEN[80..GOLDEN.len()]);
+    }
+}
+
+test_suite! {
+    name zoned_emulation;
+
+    use bfffs::common::{
+        *,
+        label::*,
+        vdev::*,
+        vdev_leaf::*,
+        vdev_file::*
+    };
+    use divbuf::DivBufShared;
+    use futures::{future, Future};
+    use galvanic_test::*;
+    use pretty_assertions::assert_eq;
+    use std::{
+        fs,
+        num::NonZeroU64,
+        path::PathBuf
+    };
+    use tempdir::TempDir;
+    use tokio::runtime::current_thread;
+
+    // 16 zones of 1024 LBAs each, the first two conventional
+    fixture!( vdev() -> (VdevFile, PathBuf, TempDir) {
+        setup(&mut self) {
+            let len = 1 << 26;  // 64MB
+            let tempdir = t!(TempDir::new("test_vdev_file_zoned_emulation"));
+            let filename = tempdir.path().join("vdev");
+            let file = t!(fs::File::create(&filename));
+            t!(file.set_len(len));
+            let pb = filename.to_path_buf();
+            let lpz = NonZeroU64::new(1024).unwrap();
+            let vdev = VdevFile::create_emulated(filename, lpz, 2, None)
+                .unwrap();
+            (vdev, pb, tempdir)
+        }
+    });
+
+    test erase_zone(vdev) {
+        let dbs = DivBufShared::from(vec![42u8; 4096]);
+        let wbuf = dbs.try_const().unwrap();
+        let mut rt = current_thread::Runtime::new().unwrap();
+        rt.block_on(future::lazy(|| {
+            vdev.val.0.write_at(wbuf.clone(), 2048)
+            .and_then(|_| vdev.val.0.erase_zone(2048))
+            .and_then(|_| vdev.val.0.write_at(wbuf.clone(), 2048))
+        })).unwrap();
+    }
+
+    test finish_zone(vdev) {
+        let dbs = DivBufShared::from(vec![42u8; 4096]);
+        let wbuf = dbs.try_const().unwrap();
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let e = rt.block_on(future::lazy(|| {
+            vdev.val.0.open_zone(2048)
+            .and_then(|_| vdev.val.0.finish_zone(2048))
+            .and_then(|_| vdev.val.0.write_at(wbuf.clone(), 2048))
+        })).unwrap_err();
+        assert_eq!(e, Error::EIO);
+    }
+
+    test lba2zone(vdev) {
+        assert_eq!(vdev.val.0.lba2zone(0), None);
+        assert_eq!(vdev.val.0.lba2zone(1023), Some(0));
+        assert_eq!(vdev.val.0.lba2zone(1024), Some(1));
+        assert_eq!(vdev.val.0.lba2zone(16_383), Some(15));
+    }
+
+    /// Conventional zones allow random writes
+    test write_at_conventional(vdev) {
+        let dbs = DivBufShared::from(vec![42u8; 4096]);
+        let wbuf = dbs.try_const().unwrap();
+        let mut rt = current_thread::Runtime::new().unwrap();
+        rt.block_on(future::lazy(|| {
+            vdev.val.0.write_at(wbuf.clone(), 1030)
+            .and_then(|_| vdev.val.0.write_at(wbuf.clone(), 1025))
+        })).unwrap();
+    }
+
+    test write_at_sequential(vdev) {
+        let dbs = DivBufShared::from(vec![42u8; 8192]);
+        let wbuf = dbs.try_const().unwrap();
+        let mut rt = current_thread::Runtime::new().unwrap();
+        rt.block_on(future::lazy(|| {
+            vdev.val.0.write_at(wbuf.clone(), 2048)
+            .and_then(|_| vdev.val.0.write_at(wbuf.clone(), 2050))
+        })).unwrap();
+    }
+
+    /// Sequential zones reject writes that aren't at the write pointer
+    test write_at_unaligned(vdev) {
+        let dbs = DivBufShared::from(vec![42u8; 4096]);
+        let wbuf = dbs.try_const().unwrap();
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let e = rt.block_on(future::lazy(|| {
+            vdev.val.0.write_at(wbuf.clone(), 2049)
+        })).unwrap_err();
+        assert_eq!(e, Error::EIO);
+    }
+
+    /// Sequential zones reject writes that cross the zone's end
+    test writev_at_zone_boundary(vdev) {
+        let dbs = DivBufShared::from(vec![42u8; 8192]);
+        let wbuf = dbs.try_const().unwrap();
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let e = rt.block_on(future::lazy(|| {
+            let zbuf = DivBufShared::from(vec![0u8; 1023 * 4096]);
+            vdev.val.0.write_at(zbuf.try_const().unwrap(), 2048)
+            .and_then(|_| vdev.val.0.writev_at(vec![wbuf.clone()], 3071))
+        })).unwrap_err();
+        assert_eq!(e, Error::EIO);
+    }
+
+    test zone_limits(vdev) {
+        let reserved = LABEL_COUNT * LABEL_LBAS;
+        assert_eq!(vdev.val.0.zone_limits(0), (reserved, 1024));
+        // The last conventional zone holds the backup labels
+        assert_eq!(vdev.val.0.zone_limits(1), (1024, 2048 - reserved));
+        assert_eq!(vdev.val.0.zone_limits(2), (2048, 3072));
+        assert_eq!(vdev.val.0.zone_limits(15), (15_360, 16_384));
+    }
+
+    test zones(vdev) {
+        assert_eq!(vdev.val.0.zones(), 16);
+    }
+
+    /// The zone layout should be restored when reopening the device
+    test open(vdev) {
+        let (old_vdev, path, _tempdir) = vdev.val;
+        let uuid = old_vdev.uuid();
+        let mut rt = current_thread::Runtime::new().unwrap();
+        rt.block_on(future::lazy(|| {
+            old_vdev.write_label(LabelWriter::new(0, TxgT::from(0)))
+        })).unwrap();
+        drop(old_vdev);
+        let (new_vdev, _) = rt.block_on(future::lazy(|| {
+            VdevFile::open(path)
+        })).unwrap();
+        assert_eq!(new_vdev.uuid(), uuid);
+        assert_eq!(new_vdev.zones(), 16);
+        assert_eq!(new_vdev.zone_limits(2), (2048, 3072));
+    }
+
+    /// Emulated devices may limit the number of open zones, like real ones
+    test max_open_zones() {
+        let tempdir = t!(TempDir::new("test_vdev_file_zoned_emulation"));
+        let filename = tempdir.path().join("vdev");
+        let file = t!(fs::File::create(&filename));
+        t!(file.set_len(1 << 26));
+        let lpz = NonZeroU64::new(1024).unwrap();
+        let vdev = VdevFile::create_emulated(filename.clone(), lpz, 2, Some(3))
+            .unwrap();
+        assert_eq!(vdev.max_open_zones(), Some(3));
+        let mut rt = current_thread::Runtime::new().unwrap();
+        rt.block_on(future::lazy(|| {
+            vdev.write_label(LabelWriter::new(0, TxgT::from(0)))
+        })).unwrap();
+        drop(vdev);
+        // The limit should persist
+        let (vdev, _) = rt.block_on(future::lazy(|| {
+            VdevFile::open(filename)
+        })).unwrap();
+        assert_eq!(vdev.max_open_zones(), Some(3));
+    }
+
+    /// Zones may have different sizes
+    test variable_zones() {
+        let tempdir = t!(TempDir::new("test_vdev_file_zoned_emulation"));
+        let filename = tempdir.path().join("vdev");
+        let file = t!(fs::File::create(&filename));
+        t!(file.set_len(1 << 26));
+        let zone_lbas = [1024, 1024, 512, 512, 3072];
+        let vdev = VdevFile::create_emulated_zones(filename, &zone_lbas, 2,
+                                                   None).unwrap();
+        assert_eq!(vdev.zones(), 5);
+        assert_eq!(vdev.zone_limits(2), (2048, 2560));
+        assert_eq!(vdev.zone_limits(3), (2560, 3072));
+        assert_eq!(vdev.zone_limits(4), (3072, 6144));
+        assert_eq!(vdev.lba2zone(2559), Some(2));
+        assert_eq!(vdev.lba2zone(2560), Some(3));
+        assert_eq!(vdev.lba2zone(6143), Some(4));
+        assert_eq!(vdev.size(), 6144);
+
+        // Writes may not cross the end of a small zone
+        let dbs = DivBufShared::from(vec![42u8; 8192]);
+        let wbuf = dbs.try_const().unwrap();
+        let mut rt = current_thread::Runtime::new().unwrap();
+        let e = rt.block_on(future::lazy(|| {
+            let zbuf = DivBufShared::from(vec![0u8; 511 * 4096]);
+            vdev.write_at(zbuf.try_const().unwrap(), 2048)
+            .and_then(|_| vdev.write_at(wbuf.clone(), 2559))
+        })).unwrap_err();
+        assert_eq!(e, Error::EIO);
+    }
+
+    /// The zones may not be larger than the file
+    test variable_zones_too_big() {
+        let tempdir = t!(TempDir::new("test_vdev_file_zoned_emulation"));
+        let filename = tempdir.path().join("vdev");
+        let file = t!(fs::File::create(&filename));
+        t!(file.set_len(1 << 22));
+        let zone_lbas = [1024, 1024];
+        assert!(VdevFile::create_emulated_zones(filename, &zone_lbas, 1, None)
+                .is_err());
+    }
+
+    /// The first zone must be conventional, to hold the labels
+    test no_conventional_zones() {
+        let tempdir = t!(TempDir::new("test_vdev_file_zoned_emulation"));
+        let filename = tempdir.path().join("vdev");
+        let file = t!(fs::File::create(&filename));
+        t!(file.set_len(1 << 26));
+        let lpz = NonZeroU64::new(1024).unwrap();
+        assert!(VdevFile::create_emulated(filename, lpz, 0, None).is_err());
     }
 }